@@ -0,0 +1,229 @@
+//! On-disk cache of API response bodies
+//!
+//! [`ResponseCache`] stores one file per request, named after a blake3 hash
+//! of everything that determines the response, so rerunning a tool on the
+//! same inputs doesn't pay for the same transcription or chat completion
+//! twice. Entries older than the TTL are ignored and removed; when the cache
+//! outgrows its size limit the oldest entries go first.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// Entries older than this are stale (7 days)
+pub const DEFAULT_CACHE_TTL: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// Size the cache is swept down to (512 MiB)
+pub const DEFAULT_CACHE_MAX_BYTES: u64 = 512 * 1024 * 1024;
+
+const ENTRY_EXTENSION: &str = "json";
+
+/// Response bodies on disk, keyed by [`ResponseCache::key`]
+#[derive(Debug, Clone)]
+pub struct ResponseCache {
+    dir: PathBuf,
+    ttl: Duration,
+    max_bytes: u64,
+}
+
+impl ResponseCache {
+    /// A cache in `dir`, created on the first write, with the default TTL
+    /// and size limit
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            ttl: DEFAULT_CACHE_TTL,
+            max_bytes: DEFAULT_CACHE_MAX_BYTES,
+        }
+    }
+
+    /// OPENAI_CACHE_DIR, else `swiss-knife/api` under XDG_CACHE_HOME or
+    /// `~/.cache`; `None` without a home directory
+    pub fn default_dir() -> Option<PathBuf> {
+        if let Some(dir) = std::env::var_os("OPENAI_CACHE_DIR").filter(|dir| !dir.is_empty()) {
+            return Some(PathBuf::from(dir));
+        }
+        let root = std::env::var_os("XDG_CACHE_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| {
+                std::env::var_os("HOME")
+                    .filter(|dir| !dir.is_empty())
+                    .map(|home| PathBuf::from(home).join(".cache"))
+            })?;
+        Some(root.join("swiss-knife").join("api"))
+    }
+
+    /// Ignore entries older than `ttl`
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Remove the oldest entries once the cache holds more than `max_bytes`
+    pub fn with_max_bytes(mut self, max_bytes: u64) -> Self {
+        self.max_bytes = max_bytes;
+        self
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Hex blake3 hash of `endpoint`, `model` and the request's `parts`
+    ///
+    /// Every part is length-prefixed, so moving bytes from one part to the
+    /// next changes the key.
+    pub fn key(endpoint: &str, model: &str, parts: &[&[u8]]) -> String {
+        let mut hasher = blake3::Hasher::new();
+        for part in [endpoint.as_bytes(), model.as_bytes()].iter().chain(parts) {
+            hasher.update(&(part.len() as u64).to_le_bytes());
+            hasher.update(part);
+        }
+        hasher.finalize().to_hex().to_string()
+    }
+
+    /// The body stored under `key`, unless missing or expired
+    ///
+    /// Expired entries are removed.
+    pub fn get(&self, key: &str) -> Option<Vec<u8>> {
+        let path = self.entry_path(key);
+        let modified = fs::metadata(&path).and_then(|m| m.modified()).ok()?;
+        if self.is_expired(modified, SystemTime::now()) {
+            let _ = fs::remove_file(&path);
+            return None;
+        }
+        fs::read(&path).ok()
+    }
+
+    /// Store `body` under `key`, then sweep the cache down to its size limit
+    pub fn put(&self, key: &str, body: &[u8]) -> io::Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        // Written aside and renamed, so readers never see half an entry
+        let path = self.entry_path(key);
+        let partial = path.with_extension("partial");
+        fs::write(&partial, body)?;
+        fs::rename(&partial, &path)?;
+        self.sweep()?;
+        Ok(())
+    }
+
+    /// Drop the entry under `key`, e.g. one that no longer parses
+    pub fn remove(&self, key: &str) {
+        let _ = fs::remove_file(self.entry_path(key));
+    }
+
+    /// Remove expired entries, then the oldest ones until the cache fits its
+    /// size limit; returns the number of entries removed
+    pub fn sweep(&self) -> io::Result<usize> {
+        let now = SystemTime::now();
+        let mut entries = Vec::new();
+        let mut removed = 0;
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some(ENTRY_EXTENSION) {
+                continue;
+            }
+            let Ok(metadata) = fs::metadata(&path) else {
+                continue;
+            };
+            let modified = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+            if self.is_expired(modified, now) {
+                if fs::remove_file(&path).is_ok() {
+                    removed += 1;
+                }
+            } else {
+                entries.push((modified, metadata.len(), path));
+            }
+        }
+
+        let mut total: u64 = entries.iter().map(|(_, len, _)| len).sum();
+        entries.sort_by_key(|(modified, _, _)| *modified);
+        for (_, len, path) in entries {
+            if total <= self.max_bytes {
+                break;
+            }
+            if fs::remove_file(&path).is_ok() {
+                total -= len;
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+
+    fn entry_path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{}.{}", key, ENTRY_EXTENSION))
+    }
+
+    fn is_expired(&self, modified: SystemTime, now: SystemTime) -> bool {
+        now.duration_since(modified).is_ok_and(|age| age > self.ttl)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_covers_every_part() {
+        let key = ResponseCache::key("Chat", "gpt-4o", &[b"ab", b"c"]);
+        assert_eq!(key.len(), 64);
+        assert_eq!(key, ResponseCache::key("Chat", "gpt-4o", &[b"ab", b"c"]));
+        assert_ne!(key, ResponseCache::key("Chat", "gpt-4o", &[b"a", b"bc"]));
+        assert_ne!(
+            key,
+            ResponseCache::key("Chat", "gpt-4o-mini", &[b"ab", b"c"])
+        );
+        assert_ne!(
+            key,
+            ResponseCache::key("Transcription", "gpt-4o", &[b"ab", b"c"])
+        );
+    }
+
+    #[test]
+    fn test_put_then_get() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = ResponseCache::new(dir.path().join("api"));
+        assert_eq!(cache.get("k"), None);
+
+        cache.put("k", b"{}").unwrap();
+        assert_eq!(cache.get("k").as_deref(), Some(&b"{}"[..]));
+
+        cache.remove("k");
+        assert_eq!(cache.get("k"), None);
+    }
+
+    #[test]
+    fn test_expired_entry_is_removed() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = ResponseCache::new(dir.path()).with_ttl(Duration::ZERO);
+        cache.put("k", b"{}").unwrap();
+        std::thread::sleep(Duration::from_millis(10));
+
+        assert_eq!(cache.get("k"), None);
+        assert!(!dir.path().join("k.json").exists());
+    }
+
+    #[test]
+    fn test_sweep_removes_oldest_entries_over_the_limit() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = ResponseCache::new(dir.path()).with_max_bytes(10);
+        let old = SystemTime::now() - Duration::from_secs(60);
+        for key in ["a", "b"] {
+            cache.put(key, b"12345").unwrap();
+        }
+        fs::File::options()
+            .write(true)
+            .open(dir.path().join("a.json"))
+            .unwrap()
+            .set_modified(old)
+            .unwrap();
+
+        // A third entry pushes the cache over 10 bytes; the oldest goes
+        cache.put("c", b"12345").unwrap();
+        assert_eq!(cache.get("a"), None);
+        assert!(cache.get("b").is_some());
+        assert!(cache.get("c").is_some());
+    }
+}
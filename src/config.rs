@@ -1,3 +1,5 @@
+use crate::s3::diff::normalize_prefix;
+use crate::s3::provider::Provider;
 use anyhow::{Context, Result};
 use std::env;
 
@@ -6,8 +8,14 @@ use std::env;
 pub struct Config {
     pub region: String,
     pub profile: Option<String>,
+    /// Role assumed with the loaded credentials (s3upload `--role-arn`)
+    pub role_arn: Option<String>,
     pub bucket: String,
     pub target_path: String,
+    /// Endpoint of an S3-compatible service (`S3_ENDPOINT_URL`); AWS when unset
+    pub endpoint_url: Option<String>,
+    /// Provider chosen with s3upload `--provider`; detected from the endpoint when unset
+    pub provider: Option<Provider>,
 }
 
 impl Config {
@@ -35,8 +43,50 @@ impl Config {
         Ok(Self {
             region,
             profile,
+            role_arn: None,
             bucket,
             target_path,
+            endpoint_url: Self::endpoint_url_from_env(),
+            provider: None,
+        })
+    }
+
+    /// `S3_ENDPOINT_URL`, or the SDK's own `AWS_ENDPOINT_URL_S3`/`AWS_ENDPOINT_URL`
+    pub fn endpoint_url_from_env() -> Option<String> {
+        ["S3_ENDPOINT_URL", "AWS_ENDPOINT_URL_S3", "AWS_ENDPOINT_URL"]
+            .iter()
+            .find_map(|name| env::var(name).ok().filter(|v| !v.is_empty()))
+    }
+
+    /// The provider to adapt to: `provider` if set, else detected from the endpoint
+    pub fn provider(&self) -> Provider {
+        self.provider
+            .unwrap_or_else(|| Provider::detect(self.endpoint_url.as_deref()))
+    }
+
+    /// Build a configuration from explicit values, validated like `from_env`
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the region, bucket name or target path is invalid
+    pub fn new(
+        region: &str,
+        profile: Option<String>,
+        bucket: &str,
+        target_path: &str,
+    ) -> Result<Self> {
+        Self::validate_region(region)?;
+        Self::validate_bucket_name(bucket)?;
+        Self::validate_target_path(target_path)?;
+
+        Ok(Self {
+            region: region.to_string(),
+            profile,
+            role_arn: None,
+            bucket: bucket.to_string(),
+            target_path: target_path.to_string(),
+            endpoint_url: None,
+            provider: None,
         })
     }
 
@@ -46,10 +96,11 @@ impl Config {
             anyhow::bail!("AWS_REGION cannot be empty");
         }
 
-        // Basic validation - ensure it looks like a region (contains a dash)
-        if !region.contains('-') {
+        // Basic validation - ensure it looks like a region (contains a dash);
+        // Cloudflare R2 uses "auto"
+        if region != "auto" && !region.contains('-') {
             anyhow::bail!(
-                "AWS_REGION '{}' doesn't look like a valid region (e.g., us-west-2, eu-west-1)",
+                "AWS_REGION '{}' doesn't look like a valid region (e.g., us-west-2, eu-west-1, auto)",
                 region
             );
         }
@@ -162,12 +213,12 @@ impl Config {
     ///
     /// The complete S3 object key including the target path prefix
     pub fn build_s3_key(&self, relative_path: &str) -> String {
-        let path = relative_path.trim_start_matches("./");
-        if self.target_path.is_empty() {
-            path.to_string()
-        } else {
-            format!("{}/{}", self.target_path.trim_end_matches('/'), path)
-        }
+        // Same prefix form as listings (diff mode), so both sides line up
+        format!(
+            "{}{}",
+            normalize_prefix(&self.target_path),
+            relative_path.trim_start_matches("./")
+        )
     }
 }
 
@@ -201,6 +252,7 @@ mod tests {
         assert!(Config::validate_region("us-west-2").is_ok());
         assert!(Config::validate_region("eu-west-1").is_ok());
         assert!(Config::validate_region("ap-southeast-1").is_ok());
+        assert!(Config::validate_region("auto").is_ok()); // Cloudflare R2
 
         // Invalid regions
         assert!(Config::validate_region("").is_err()); // Empty
@@ -225,20 +277,35 @@ mod tests {
         let config = Config {
             region: "us-west-2".to_string(),
             profile: None,
+            role_arn: None,
             bucket: "test-bucket".to_string(),
             target_path: "uploads".to_string(),
+            endpoint_url: None,
+            provider: None,
         };
 
         assert_eq!(config.build_s3_key("file.mp4"), "uploads/file.mp4");
         assert_eq!(config.build_s3_key("./file.mp4"), "uploads/file.mp4");
         assert_eq!(config.build_s3_key("dir/file.mp4"), "uploads/dir/file.mp4");
 
+        // Slashes around the target path don't leak into the key
+        for target_path in ["/uploads", "uploads/", "/uploads/"] {
+            let config = Config {
+                target_path: target_path.to_string(),
+                ..config.clone()
+            };
+            assert_eq!(config.build_s3_key("file.mp4"), "uploads/file.mp4");
+        }
+
         // Test with empty target path
         let config_no_prefix = Config {
             region: "us-west-2".to_string(),
             profile: None,
+            role_arn: None,
             bucket: "test-bucket".to_string(),
             target_path: String::new(),
+            endpoint_url: None,
+            provider: None,
         };
 
         assert_eq!(config_no_prefix.build_s3_key("file.mp4"), "file.mp4");
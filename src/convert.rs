@@ -1,20 +1,84 @@
 use anyhow::{Context, Result};
-use clap::Parser;
-use console::{style, Emoji};
+use clap::{Parser, ValueEnum};
+use console::style;
+use futures::StreamExt;
 use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::OsString;
 use std::fs;
+use std::io::{Read, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
-use std::process::Command;
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
-use swiss_knife::{ContentResponse, OpenAIClient};
-use tokio::sync::mpsc;
+use swiss_knife::cache::ResponseCache;
+use swiss_knife::config::Config;
+use swiss_knife::s3::{
+    compare::compare_file, generate_presigned_url, upload_file, FileComparison, S3Client,
+};
+use swiss_knife::status;
+use swiss_knife::ui::{self, Emoji};
+use swiss_knife::{
+    estimate_tokens, merge_windows, split_into_windows, validate_language_code, with_retry_hook,
+    AiClient, AiClientExt, Chapter, ContentResponse, ContentSection, Keyframe, ModelConfig,
+    OpenAIClient, OpenAIError, RetryConfig, RetryEvent, SpeechFormat, TokenUsage,
+    TranscriptSegment, TranscriptionOptions, WithUsage,
+};
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::sync::{mpsc, Semaphore};
 use tokio::task;
+use tokio_util::sync::CancellationToken;
+
+// Target length of a transcription chunk for long videos
+const CHUNK_SECONDS: u32 = 1300;
+// Upper bound for a chunk cut at a silence, keeps audio within the API size limit
+const MAX_CHUNK_SECONDS: u32 = 1500;
+// silencedetect settings: quieter than -30dB for at least half a second
+const SILENCE_FILTER: &str = "silencedetect=noise=-30dB:d=0.5";
+// Bytes hashed from each end of the video for the cache fingerprint
+const FINGERPRINT_EDGE_BYTES: u64 = 1024 * 1024;
+// Hex characters of the fingerprint used in artifact names
+const FINGERPRINT_LEN: usize = 12;
+// Hex characters of the input path hash that keeps same-named videos apart
+const PATH_TAG_LEN: usize = 8;
+// Estimated tokens of transcript sent per translation request
+const TRANSLATION_WINDOW_TOKENS: usize = 2000;
+// Estimated tokens of transcript sent per polishing request
+const POLISH_WINDOW_TOKENS: usize = 2000;
+// Transcript segments per speaker labeling request
+const SPEAKER_WINDOW_SEGMENTS: usize = 40;
+// Segments each speaker labeling window repeats from the previous one
+const SPEAKER_WINDOW_OVERLAP: usize = 8;
+// Summarization rounds before giving up on fitting a transcript into the content budget
+const MAX_CONDENSE_ROUNDS: usize = 3;
+// ffmpeg chunk extractions allowed at once per video, independent of --max-concurrent
+const MAX_CONCURRENT_EXTRACTIONS: usize = 2;
+// Frames whose scene score exceeds 0.3, printed by showinfo for their timestamps
+const SCENE_FILTER: &str = "select='gt(scene,0.3)',showinfo";
+// Square pixels, then fit within 1280x1280 keeping the aspect ratio and never upscaling
+const KEYFRAME_FILTER: &str = "scale='trunc(iw*sar/2)*2':ih,setsar=1,\
+scale='min(1280,iw)':'min(1280,ih)':force_original_aspect_ratio=decrease";
+// Shortest stretch of video per key frame, so short videos don't repeat frames
+const MIN_KEYFRAME_GAP: f64 = 0.1;
 
 static MOVIE: Emoji<'_, '_> = Emoji("🎬 ", "");
 static SPARKLES: Emoji<'_, '_> = Emoji("✨ ", "");
 static CHECK: Emoji<'_, '_> = Emoji("✅ ", "");
 static PACKAGE: Emoji<'_, '_> = Emoji("📦 ", "");
 static WARNING: Emoji<'_, '_> = Emoji("⚠️  ", "");
+static RECYCLE: Emoji<'_, '_> = Emoji("♻️ ", "");
+static BROOM: Emoji<'_, '_> = Emoji("🧹 ", "");
+static MONEY: Emoji<'_, '_> = Emoji("💰 ", "");
+static CLOUD: Emoji<'_, '_> = Emoji("☁️ ", "");
+static LINK: Emoji<'_, '_> = Emoji("🔗 ", "");
+static CROSS: Emoji<'_, '_> = Emoji("❌ ", "✗ ");
+static LABEL: Emoji<'_, '_> = Emoji("🏷️ ", "");
+static PAGE: Emoji<'_, '_> = Emoji("📄 ", "");
+static SPEECH: Emoji<'_, '_> = Emoji("💬 ", "");
+static MEMO: Emoji<'_, '_> = Emoji("📝 ", "");
+static CLIPBOARD: Emoji<'_, '_> = Emoji("📋 ", "");
+static NOTEBOOK: Emoji<'_, '_> = Emoji("📓 ", "");
 
 #[derive(Parser, Debug)]
 #[command(
@@ -27,550 +91,6907 @@ static WARNING: Emoji<'_, '_> = Emoji("⚠️  ", "");
                   Supports caching to avoid reprocessing.",
     after_help = "Examples:\n  \
                   convert ./lecture.mp4                   # Transcribe and generate content\n  \
-                  convert ~/Videos/presentation.mov       # Process video file\n\n\
+                  convert ~/Videos/presentation.mov       # Process video file\n  \
+                  convert ./lecture.mp4 -o ~/transcripts  # Write all artifacts to a directory\n  \
+                  convert ./talk.mp4 --language en        # Transcribe and write content in English\n  \
+                  convert ./talk.mp4 --transcribe-model whisper-1 --chat-model gpt-5\n  \
+                  convert ./lectures/ --jobs 3            # Batch process a directory of videos\n  \
+                  convert ./long.mp4 --split-on-silence   # Chunk at pauses instead of mid-sentence\n  \
+                  convert ./lecture.mp4 --clear-cache     # Drop artifacts of older exports\n  \
+                  convert ./long.mp4 --allow-gaps         # Finish even if some chunks keep failing\n  \
+                  convert ./long.mp4 --fresh              # Re-plan instead of resuming an earlier run\n  \
+                  convert ./talk.mp4 --translate en       # Also write an English transcript\n  \
+                  convert ./talk.mp4 --polish --remove-fillers  # Punctuate, paragraph and drop fillers\n  \
+                  convert ./talk.mp4 --chapters --transcribe-model whisper-1  # YouTube chapter list\n  \
+                  convert ./interview.mp4 --speakers 2 --transcribe-model whisper-1  # S1:/S2: labeled transcript\n  \
+                  convert ./talk.mp4 --context \"Tokio, Axum, SQLx\"  # Spell product names right\n  \
+                  convert ./talk.mp4 --embed-subtitles soft --srt talk.srt  # Mux subtitles into a copy\n  \
+                  convert ./talk.mp4 --keyframes 6 --scene-detect --caption-keyframes  # Captioned thumbnails\n  \
+                  convert ./3h-talk.mp4 --content-budget-tokens 30000  # Summarize long transcripts sooner\n  \
+                  convert ./talk.mp4 --profiles platforms.yaml  # YouTube/Xiaohongshu/newsletter copy\n  \
+                  convert ./talk.mp4 --no-markdown        # Skip the combined <stem>.md document\n  \
+                  convert ./talk.mp4 --tts --tts-voice nova  # Read titles and status updates aloud\n  \
+                  convert ./talk.mp4 --titles 5 --status-updates 0  # Tune how much content is generated\n  \
+                  convert ./lecture.mp4 --skip-preflight  # Offline rerun from cached artifacts\n  \
+                  convert ./concert.mp4 --audio-bitrate 64 --audio-sample-rate 24000  # Music-heavy audio\n  \
+                  convert ./talk.mp4 --price-config prices.toml  # Cost estimate with custom prices\n  \
+                  convert https://example.com/talk.mp4    # Download over http(s), resuming partial downloads\n  \
+                  convert s3://my-bucket/rec/talk.mp4 --keep-download  # Fetch from S3 and keep the file\n  \
+                  convert ./talk.mp4 --upload-to s3://my-bucket/talks  # Publish artifacts with presigned URLs\n  \
+                  convert a.mp4 b.mov c.mkv               # Batch process several files\n\n\
                   Requirements:\n  \
                   - FFmpeg and FFprobe installed\n  \
-                  - OPENAI_API_KEY environment variable set\n\n\
+                  - OPENAI_API_KEY environment variable set\n  \
+                  - Optional: OPENAI_TRANSCRIBE_MODEL / OPENAI_CHAT_MODEL to change default models\n  \
+                  - Optional: OPENAI_SPEECH_MODEL to change the --tts model (default: tts-1)\n  \
+                  - Optional: OPENAI_TIMEOUT_SECS to change the API request timeout\n  \
+                  - Optional: OPENAI_ORG_ID / OPENAI_PROJECT_ID to bill a specific organization or project\n  \
+                  - Optional: HTTPS_PROXY / NO_PROXY, and OPENAI_CA_BUNDLE (PEM) for a TLS-intercepting proxy\n  \
+                  - Optional: OPENAI_CACHE_DIR for the API response cache (default: ~/.cache/swiss-knife/api)\n  \
+                  - Optional: OPENAI_COMPAT=generic for local OpenAI-compatible servers (with OPENAI_BASE_URL)\n  \
+                  - Optional: LOG_LEVEL=debug to log each API request, LOG_LEVEL=swiss_knife=trace to add (redacted) bodies\n  \
+                  - Optional: NO_COLOR=1 to turn off colors (--no-emoji turns off emoji)\n\n\
+                  Azure OpenAI:\n  \
+                  - OPENAI_PROVIDER=azure and OPENAI_API_KEY set to the resource's key\n  \
+                  - OPENAI_BASE_URL=https://<resource>.openai.azure.com\n  \
+                  - OPENAI_API_VERSION, e.g. 2025-04-01-preview\n  \
+                  - Optional: OPENAI_TRANSCRIBE_DEPLOYMENT / OPENAI_CHAT_DEPLOYMENT (default: named after the model)\n\n\
                   Features:\n  \
                   - Automatic chunking for long videos (>1300s)\n  \
                   - Parallel processing of chunks\n  \
-                  - Smart caching to avoid reprocessing\n  \
+                  - Batch mode over directories with per-video summary\n  \
+                  - Content-based caching to avoid reprocessing (--no-cache to bypass)\n  \
+                  - Identical API requests answered from a 7-day response cache at no cost (--no-api-cache to bypass)\n  \
                   - Audio compression for large files\n  \
-                  - Real-time progress tracking\n\n\
+                  - Real-time progress tracking\n  \
+                  - Ctrl-C cancels outstanding API requests (press again to exit at once)\n\n\
                   For more information: https://github.com/tyrchen/swiss-knife"
 )]
 struct Args {
-    /// Video file to process
-    #[arg(value_name = "VIDEO_FILE")]
-    video_file: PathBuf,
-}
+    /// Video files or directories of videos to process
+    #[arg(value_name = "VIDEO_OR_DIR", required = true)]
+    inputs: Vec<PathBuf>,
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    let args = Args::parse();
+    /// Video extensions picked up from directories (comma-separated)
+    #[arg(short, long, value_delimiter = ',', default_value = "mp4,mov,mkv")]
+    extensions: Vec<String>,
 
-    if !args.video_file.exists() {
-        anyhow::bail!("Video file does not exist: {:?}", args.video_file);
-    }
+    /// Number of videos processed in parallel in batch mode
+    #[arg(short, long, default_value = "1", value_parser = clap::value_parser!(u16).range(1..))]
+    jobs: u16,
 
-    let video_name = args
-        .video_file
-        .file_stem()
-        .context("Invalid video filename")?
-        .to_string_lossy()
-        .to_string();
+    /// Cut long videos at detected silences instead of fixed 1300s boundaries
+    #[arg(long)]
+    split_on_silence: bool,
 
-    println!(
-        "{} {}",
-        MOVIE,
-        style(format!("Processing video: {:?}", args.video_file)).bold()
-    );
-    println!();
-
-    // Get video duration
-    let spinner = ProgressBar::new_spinner();
-    spinner.set_style(
-        ProgressStyle::default_spinner()
-            .template("{spinner:.green} {msg}")
-            .unwrap(),
-    );
-    spinner.set_message("Analyzing video duration...");
-    spinner.enable_steady_tick(Duration::from_millis(100));
+    /// Maximum chunks of one video transcribed at the same time
+    #[arg(long, value_name = "N", default_value = "3", value_parser = clap::value_parser!(u16).range(1..))]
+    max_concurrent: u16,
 
-    let duration = get_video_duration(&args.video_file)?;
-    spinner.finish_with_message(format!(
-        "Video duration: {} seconds",
-        style(duration).cyan()
-    ));
+    /// Number of title options to generate
+    #[arg(long, value_name = "N", default_value = "3", value_parser = clap::value_parser!(u8).range(0..=20))]
+    titles: u8,
 
-    let tmp_dir = PathBuf::from("/tmp");
-    let transcript_file = tmp_dir.join(format!("{}_transcript.txt", video_name));
+    /// Number of video descriptions to generate
+    #[arg(long, value_name = "N", default_value = "2", value_parser = clap::value_parser!(u8).range(0..=20))]
+    descriptions: u8,
 
-    // Process audio extraction and transcription
-    let full_transcript = if duration > 1300 {
-        process_long_video(&args.video_file, &video_name, duration, &tmp_dir).await?
-    } else {
-        process_short_video(&args.video_file, &video_name, &tmp_dir).await?
-    };
+    /// Number of status update posts to generate
+    #[arg(long, value_name = "N", default_value = "3", value_parser = clap::value_parser!(u8).range(0..=20))]
+    status_updates: u8,
 
-    // Save full transcript
-    fs::write(&transcript_file, &full_transcript)?;
-    println!(
-        "{} Transcript saved to: {}",
-        CHECK,
-        style(transcript_file.display()).dim()
-    );
-    println!();
-
-    // Generate content
-    let spinner = ProgressBar::new_spinner();
-    spinner.set_style(
-        ProgressStyle::default_spinner()
-            .template("{spinner:.green} {msg}")
-            .unwrap(),
-    );
-    spinner.set_message("Generating content with GPT-5-mini...");
-    spinner.enable_steady_tick(Duration::from_millis(100));
+    /// Bitrate of the extracted mp3 audio in kbps
+    #[arg(long, value_name = "KBPS", default_value = "32", value_parser = clap::value_parser!(u32).range(8..=320))]
+    audio_bitrate: u32,
 
-    let content = generate_content_from_transcript(&full_transcript).await?;
-    spinner.finish_with_message(format!("{} Content generated successfully!", CHECK));
+    /// Sample rate of the extracted audio in Hz
+    #[arg(long, value_name = "HZ", default_value = "16000", value_parser = clap::value_parser!(u32).range(8000..=48000))]
+    audio_sample_rate: u32,
 
-    // Save all outputs
-    save_outputs(&video_name, &tmp_dir, &content)?;
+    /// Audio larger than this is re-encoded at lower bitrates before upload
+    #[arg(long, value_name = "MB", default_value = "24", value_parser = clap::value_parser!(u64).range(1..))]
+    api_size_limit_mb: u64,
 
-    println!();
-    println!(
-        "{} {}",
-        SPARKLES,
-        style("Processing complete!").green().bold()
-    );
-    println!("{} All files saved in {}", PACKAGE, style("/tmp").yellow());
+    /// Keep videos downloaded from http(s):// or s3:// inputs after processing
+    #[arg(long)]
+    keep_download: bool,
 
-    Ok(())
-}
+    /// Also upload the transcript, content JSON, Markdown and subtitles to
+    /// `s3://bucket/prefix` and print presigned URLs (AWS_REGION/AWS_PROFILE from .env)
+    #[arg(long, value_name = "S3_URI", value_parser = UploadTarget::parse)]
+    upload_to: Option<UploadTarget>,
 
-fn get_video_duration(video_path: &Path) -> Result<u32> {
-    let output = Command::new("ffprobe")
-        .args([
-            "-v",
-            "error",
-            "-show_entries",
-            "format=duration",
-            "-of",
-            "default=noprint_wrappers=1:nokey=1",
-            video_path.to_str().unwrap(),
-        ])
-        .output()
-        .context("Failed to run ffprobe")?;
+    /// Ignore the <stem>_<hash>.state.json manifest and plan the run from scratch
+    #[arg(long)]
+    fresh: bool,
 
-    if !output.status.success() {
-        anyhow::bail!("ffprobe failed");
-    }
+    /// Ignore cached audio and transcripts and process everything again
+    #[arg(long)]
+    no_cache: bool,
 
-    let duration_str = String::from_utf8(output.stdout)?;
-    let duration: f64 = duration_str
-        .trim()
-        .parse()
-        .context("Failed to parse video duration")?;
+    /// Send every API request again instead of answering repeated ones from
+    /// the response cache (OPENAI_CACHE_DIR, default ~/.cache/swiss-knife/api)
+    #[arg(long)]
+    no_api_cache: bool,
 
-    Ok(duration as u32)
-}
+    /// Delete artifacts left over from earlier versions of the video
+    #[arg(long)]
+    clear_cache: bool,
 
-async fn process_short_video(
-    video_path: &Path,
-    video_name: &str,
-    tmp_dir: &Path,
-) -> Result<String> {
-    let audio_file = tmp_dir.join(format!("{}.mp3", video_name));
-    let transcript_file = tmp_dir.join(format!("{}_transcript.txt", video_name));
+    /// Keep going when chunks fail, marking them as [MISSING ...] in the transcript
+    #[arg(long)]
+    allow_gaps: bool,
 
-    // Check cache
-    if transcript_file.exists() {
-        println!("{} Using cached transcript", style("♻️").cyan());
-        return fs::read_to_string(&transcript_file).context("Failed to read cached transcript");
-    }
+    /// Add punctuation and paragraph breaks to the transcript with the chat
+    /// model; the unpolished text is kept as <stem>_transcript.raw.txt
+    #[arg(long)]
+    polish: bool,
 
-    // Extract audio if not exists
-    if !audio_file.exists() {
-        let spinner = ProgressBar::new_spinner();
-        spinner.set_style(
-            ProgressStyle::default_spinner()
-                .template("{spinner:.green} {msg}")
-                .unwrap(),
-        );
-        spinner.set_message("Extracting audio from video...");
-        spinner.enable_steady_tick(Duration::from_millis(100));
+    /// Also drop filler words (um, uh, 嗯, 那个...) while polishing
+    #[arg(long, requires = "polish")]
+    remove_fillers: bool,
 
-        extract_audio(video_path, &audio_file, None, None)?;
-        spinner.finish_with_message(format!("{} Audio extracted", CHECK));
-    } else {
-        println!("{} Using cached audio file", style("♻️").cyan());
-    }
+    /// Warn when the polished transcript's length differs from the original
+    /// by more than this fraction
+    #[arg(long, value_name = "FRACTION", default_value = "0.2", value_parser = parse_tolerance, requires = "polish")]
+    polish_tolerance: f64,
 
-    // Check file size and compress if needed
-    let audio_data = compress_if_needed(&audio_file).await?;
+    /// Read each generated title and status update aloud into
+    /// <stem>_title_<n>.mp3 and <stem>_status_<n>.mp3
+    #[arg(long)]
+    tts: bool,
 
-    // Transcribe
-    let spinner = ProgressBar::new_spinner();
-    spinner.set_style(
-        ProgressStyle::default_spinner()
-            .template("{spinner:.green} {msg}")
-            .unwrap(),
-    );
-    spinner.set_message("Transcribing audio with gpt-4o-transcribe...");
-    spinner.enable_steady_tick(Duration::from_millis(100));
+    /// Voice for --tts, e.g. alloy, echo, fable, nova, onyx or shimmer
+    #[arg(long, value_name = "VOICE", default_value = "alloy", requires = "tts")]
+    tts_voice: String,
 
-    let client = OpenAIClient::new()?;
-    let transcript = client
-        .transcribe(audio_data, &format!("{}.mp3", video_name))
-        .await?;
+    /// Also write the transcript translated to this ISO-639-1 language
+    #[arg(long, value_name = "LANG", value_parser = validate_language_code)]
+    translate: Option<String>,
 
-    spinner.finish_with_message(format!("{} Audio transcribed", CHECK));
+    /// Generate a chapter list from segment timestamps (needs a verbose_json
+    /// capable transcription model such as whisper-1)
+    #[arg(long)]
+    chapters: bool,
 
-    Ok(transcript)
-}
+    /// Label N speakers (S1, S2, ...) from conversational cues with the chat model
+    /// and write <stem>_transcript.labeled.txt (needs segment timestamps like --chapters)
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u8).range(2..=6))]
+    speakers: Option<u8>,
 
-async fn process_long_video(
-    video_path: &Path,
-    video_name: &str,
-    duration: u32,
-    tmp_dir: &Path,
-) -> Result<String> {
-    println!(
-        "{} Video longer than 1300 seconds, processing in chunks...",
-        WARNING
-    );
+    /// Write a combined <stem>.md with content, chapters and transcript (default)
+    #[arg(long, overrides_with = "no_markdown")]
+    markdown: bool,
 
-    let num_chunks = duration.div_ceil(1300);
-    println!("   Will create {} chunks", style(num_chunks).cyan().bold());
-    println!();
+    /// Skip the combined Markdown document
+    #[arg(long, overrides_with = "markdown")]
+    no_markdown: bool,
 
-    let (tx, mut rx) = mpsc::channel(num_chunks as usize);
-    let client = OpenAIClient::new()?;
+    /// Retries for rate-limited (429), 5xx and network API failures
+    #[arg(long, value_name = "N", default_value = "3")]
+    api_retries: u32,
 
-    // Create multi-progress bar
-    let multi_progress = MultiProgress::new();
-    let overall_progress = multi_progress.add(ProgressBar::new(num_chunks as u64));
-    overall_progress.set_style(
-        ProgressStyle::default_bar()
-            .template("{spinner:.green} [{bar:40.cyan/blue}] {pos}/{len} chunks processed")
-            .unwrap()
-            .progress_chars("#>-"),
-    );
-    overall_progress.set_message("Processing chunks");
+    /// Timeout of each API request in seconds (default: $OPENAI_TIMEOUT_SECS,
+    /// else 300 for transcription and 120 for chat)
+    #[arg(long, value_name = "SECS", value_parser = clap::value_parser!(u64).range(1..))]
+    timeout: Option<u64>,
 
-    // Process chunks concurrently
-    let mut handles = Vec::new();
+    /// Directory for transcripts, audio chunks and generated content
+    /// (default: <stem>_output/ next to the video)
+    #[arg(short, long, value_name = "DIR")]
+    output_dir: Option<PathBuf>,
 
-    for i in 0..num_chunks {
-        let tx = tx.clone();
-        let client = client.clone();
-        let video_path = video_path.to_path_buf();
-        let video_name = video_name.to_string();
-        let tmp_dir = tmp_dir.to_path_buf();
-        let chunk_progress = multi_progress.add(ProgressBar::new_spinner());
-        chunk_progress.set_style(
-            ProgressStyle::default_spinner()
-                .template("    {spinner:.green} Chunk {msg}")
-                .unwrap(),
-        );
+    /// Spoken language as an ISO-639-1 code, e.g. en, zh, ja (default: auto-detect)
+    #[arg(short, long, value_name = "CODE", value_parser = validate_language_code)]
+    language: Option<String>,
 
-        let handle = task::spawn(async move {
-            chunk_progress.set_message(format!("{}/{}: Starting...", i + 1, num_chunks));
-            chunk_progress.enable_steady_tick(Duration::from_millis(100));
+    /// Names and jargon to spell correctly; sent as the transcription prompt
+    /// for every chunk and prepended to the content-generation prompt
+    #[arg(long, value_name = "TEXT", conflicts_with = "context_file")]
+    context: Option<String>,
 
-            let result = process_chunk(
-                &video_path,
-                &video_name,
-                i,
-                duration,
-                &tmp_dir,
-                &client,
-                &chunk_progress,
-            )
-            .await;
+    /// Read the --context text from a file
+    #[arg(long, value_name = "PATH")]
+    context_file: Option<PathBuf>,
 
-            chunk_progress.finish_and_clear();
-            tx.send((i, result)).await.unwrap();
-        });
+    /// YAML content profile declaring the output sections (key, description,
+    /// count, max_length, language) to generate instead of the built-in
+    /// titles/descriptions/status updates; see profiles/default.yaml
+    #[arg(long, value_name = "YAML")]
+    profiles: Option<PathBuf>,
 
-        handles.push(handle);
-    }
+    /// Transcripts over this many estimated tokens are summarized window by
+    /// window before content generation (map-reduce) instead of sent whole
+    #[arg(long, value_name = "TOKENS", default_value = "60000", value_parser = clap::value_parser!(u32).range(1000..))]
+    content_budget_tokens: u32,
 
-    // Drop the original sender
-    drop(tx);
+    /// Estimated tokens of transcript per summarized window for long transcripts
+    #[arg(long, value_name = "TOKENS", default_value = "12000", value_parser = clap::value_parser!(u32).range(500..))]
+    content_window_tokens: u32,
 
-    // Collect results
-    let mut chunks = Vec::new();
-    while let Some((index, result)) = rx.recv().await {
-        match result {
-            Ok(transcript) => {
-                chunks.push((index, transcript));
-                overall_progress.inc(1);
-            }
-            Err(e) => anyhow::bail!("Failed to process chunk {}: {}", index, e),
-        }
-    }
+    /// Attach subtitles to a copy of the video: `soft` muxes a subtitle
+    /// stream (fast), `burn` renders them into the picture (re-encodes)
+    #[arg(long, value_enum, value_name = "MODE")]
+    embed_subtitles: Option<EmbedMode>,
 
-    // Wait for all tasks
-    for handle in handles {
-        handle.await?;
-    }
+    /// SRT file for --embed-subtitles (default: <stem>.srt in the output directory)
+    #[arg(long, value_name = "FILE", requires = "embed_subtitles")]
+    srt: Option<PathBuf>,
 
-    overall_progress.finish_with_message("All chunks processed!");
+    /// Extract N evenly spaced frames as <stem>_keyframe_<n>.jpg thumbnail
+    /// candidates
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u8).range(1..=50))]
+    keyframes: Option<u8>,
 
-    // Sort chunks by index and combine
-    chunks.sort_by_key(|c| c.0);
-    let full_transcript = chunks
-        .into_iter()
-        .map(|(_, transcript)| transcript)
-        .collect::<Vec<_>>()
-        .join(" ");
+    /// Pick the --keyframes frames at scene changes instead of evenly spaced
+    #[arg(long, requires = "keyframes")]
+    scene_detect: bool,
 
-    println!("{} All chunks merged into complete transcript", CHECK);
-    Ok(full_transcript)
-}
+    /// Caption each key frame with the chat model (needs image input support)
+    #[arg(long, requires = "keyframes")]
+    caption_keyframes: bool,
 
-async fn process_chunk(
-    video_path: &Path,
-    video_name: &str,
-    chunk_index: u32,
-    total_duration: u32,
-    tmp_dir: &Path,
-    client: &OpenAIClient,
-    progress: &ProgressBar,
-) -> Result<String> {
-    let start_time = chunk_index * 1300;
-    let mut chunk_duration = 1300;
+    /// Transcription model (default: $OPENAI_TRANSCRIBE_MODEL or gpt-4o-transcribe)
+    #[arg(long, value_name = "MODEL")]
+    transcribe_model: Option<String>,
 
-    if start_time + chunk_duration > total_duration {
-        chunk_duration = total_duration - start_time;
-    }
+    /// Chat model for content generation (default: $OPENAI_CHAT_MODEL or gpt-5-mini)
+    #[arg(long, value_name = "MODEL")]
+    chat_model: Option<String>,
 
-    progress.set_message(format!(
-        "{}/{}: Processing ({}-{}s)",
-        chunk_index + 1,
-        (total_duration.div_ceil(1300)),
-        start_time,
-        start_time + chunk_duration
-    ));
+    /// TOML file with per-model prices overriding the built-in table, e.g.
+    /// `[models.gpt-5-mini]` with `input_per_million`/`output_per_million`,
+    /// or `per_minute` for transcription models
+    #[arg(long, value_name = "TOML")]
+    price_config: Option<PathBuf>,
 
-    let chunk_audio_file = tmp_dir.join(format!("{}_chunk_{}.mp3", video_name, chunk_index));
-    let chunk_transcript_file = tmp_dir.join(format!(
-        "{}_chunk_{}_transcript.txt",
-        video_name, chunk_index
-    ));
+    /// Plain output for CI logs: no progress bars, spinners, colors or emoji,
+    /// one line per step (also the default when stdout is not a terminal)
+    #[arg(short, long)]
+    quiet: bool,
 
-    // Check cache
-    if chunk_transcript_file.exists() {
-        progress.set_message(format!(
-            "{}/{}: Using cached transcript",
-            chunk_index + 1,
-            (total_duration.div_ceil(1300))
-        ));
-        return fs::read_to_string(&chunk_transcript_file)
-            .context("Failed to read cached chunk transcript");
+    /// Replace emoji with plain text (also the default with --quiet, when
+    /// stdout is not a terminal or when the locale is not UTF-8)
+    #[arg(long)]
+    no_emoji: bool,
+
+    /// Skip checking for ffmpeg/ffprobe and validating the API key up front;
+    /// without OPENAI_API_KEY, only steps with cached artifacts can run
+    #[arg(long)]
+    skip_preflight: bool,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args = Args::parse();
+    ui::init(args.quiet, args.no_emoji);
+    // Warnings only by default; LOG_LEVEL=debug logs every API request
+    ui::init_tracing("warn");
+
+    let videos = collect_videos(&args.inputs, &args.extensions)?;
+    if videos.is_empty() {
+        anyhow::bail!(
+            "No videos with extensions [{}] found in {:?}",
+            args.extensions.join(", "),
+            args.inputs
+        );
     }
 
-    // Extract audio chunk if not exists
-    if !chunk_audio_file.exists() {
-        progress.set_message(format!(
-            "{}/{}: Extracting audio",
-            chunk_index + 1,
-            (total_duration.div_ceil(1300))
-        ));
-        extract_audio(
-            video_path,
-            &chunk_audio_file,
-            Some(start_time),
-            Some(chunk_duration),
-        )?;
+    if let Some(srt) = &args.srt {
+        if videos.len() > 1 {
+            anyhow::bail!("--srt applies to a single video; batch runs use each video's own SRT");
+        }
+        if !srt.is_file() {
+            anyhow::bail!("SRT file does not exist: {}", srt.display());
+        }
     }
 
-    // Compress if needed and transcribe
-    progress.set_message(format!(
-        "{}/{}: Transcribing",
-        chunk_index + 1,
-        (total_duration.div_ceil(1300))
-    ));
-    let audio_data = compress_if_needed(&chunk_audio_file).await?;
-    let transcript = client
-        .transcribe(
-            audio_data,
-            &format!("{}_chunk_{}.mp3", video_name, chunk_index),
-        )
-        .await?;
+    if !args.skip_preflight {
+        check_tools_installed()?;
+        check_api_key_set()?;
+    }
 
-    // Save chunk transcript
-    fs::write(&chunk_transcript_file, &transcript)?;
-    progress.set_message(format!(
-        "{}/{}: Completed",
-        chunk_index + 1,
-        (total_duration.div_ceil(1300))
-    ));
+    let context = match &args.context_file {
+        Some(path) => Some(
+            fs::read_to_string(path)
+                .with_context(|| format!("Failed to read context file: {}", path.display()))?,
+        ),
+        None => args.context.clone(),
+    }
+    .map(|text| text.trim().to_string())
+    .filter(|text| !text.is_empty());
+    let transcription_prompt = context.as_deref().map(|context| {
+        let (prompt, truncated) = fit_transcription_prompt(context);
+        if truncated {
+            status!(
+                "{}Context exceeds the ~{}-token transcription prompt limit; only its beginning is sent for transcription",
+                style(WARNING).yellow(),
+                TRANSCRIPTION_PROMPT_MAX_TOKENS
+            );
+        }
+        prompt
+    });
 
-    Ok(transcript)
-}
+    let profile = args
+        .profiles
+        .as_deref()
+        .map(ContentProfile::load)
+        .transpose()?
+        .map(Arc::new);
 
-fn extract_audio(
-    video_path: &Path,
-    output_path: &Path,
-    start_time: Option<u32>,
-    duration: Option<u32>,
-) -> Result<()> {
-    let mut cmd = Command::new("ffmpeg");
-    cmd.arg("-i").arg(video_path);
+    let prices = match &args.price_config {
+        Some(path) => PriceTable::load(path)?,
+        None => PriceTable::default(),
+    };
 
-    if let Some(start) = start_time {
-        cmd.arg("-ss").arg(start.to_string());
+    let mut models = ModelConfig::from_env();
+    if let Some(model) = args.transcribe_model {
+        models.transcribe = model;
+    }
+    if let Some(model) = args.chat_model {
+        models.chat = model;
     }
+    // Ctrl-C aborts outstanding requests so the run stops promptly
+    let cancel = ui::cancel_on_ctrl_c();
+    // Reruns from cached artifacts may not need the key at all
+    let client = if args.skip_preflight {
+        OpenAIClient::new_allow_missing_key()?
+    } else {
+        OpenAIClient::new()?
+    };
+    // Calls report their retries on their own spinner via with_retry_hook
+    let mut client = client
+        .with_models(models)
+        .with_retry_config(RetryConfig {
+            max_attempts: args.api_retries.saturating_add(1),
+            base_delay: Duration::from_secs(2),
+            max_delay: Duration::from_secs(60),
+            ..RetryConfig::default()
+        })
+        .with_rate_limit_tracking(true)
+        .with_cancellation(cancel.clone());
+    if !args.no_api_cache {
+        client = client.with_cache(ResponseCache::default_dir().map(ResponseCache::new));
+    }
+    if let Some(secs) = args.timeout {
+        let timeouts = client
+            .timeouts()
+            .with_request_timeout(Duration::from_secs(secs));
+        client = client.with_timeouts(timeouts)?;
+    }
+    let client: Arc<dyn AiClient> = Arc::new(client);
 
-    if let Some(dur) = duration {
-        cmd.arg("-t").arg(dur.to_string());
+    if !args.skip_preflight {
+        verify_api_key(&client).await?;
     }
 
-    cmd.args([
-        "-vn", "-acodec", "mp3", "-ab", "32k", "-ar", "16000", "-ac", "1", "-y",
-    ])
-    .arg(output_path);
+    let upload = match &args.upload_to {
+        Some(target) => Some(S3Client::new(s3_config(&target.bucket, &target.prefix)).await?),
+        None => None,
+    };
+
+    status!(
+        "   Language: {}",
+        style(language_label(args.language.as_deref())).cyan()
+    );
+    status!(
+        "   Models: {} (transcribe), {} (chat)",
+        style(&client.models().transcribe).cyan(),
+        style(&client.models().chat).cyan()
+    );
 
-    let output = cmd.output().context("Failed to run ffmpeg")?;
+    let options = VideoOptions {
+        output_dir: args.output_dir,
+        language: args.language,
+        split_on_silence: args.split_on_silence,
+        max_concurrent: args.max_concurrent as usize,
+        use_cache: !args.no_cache,
+        fresh: args.fresh,
+        keep_download: args.keep_download,
+        upload,
+        clear_cache: args.clear_cache,
+        allow_gaps: args.allow_gaps,
+        translate: args.translate,
+        tts_voice: args.tts.then_some(args.tts_voice),
+        polish: args.polish.then_some(PolishSettings {
+            remove_fillers: args.remove_fillers,
+            tolerance: args.polish_tolerance,
+        }),
+        context,
+        transcription_prompt,
+        chapters: args.chapters,
+        speakers: args.speakers,
+        embed_subtitles: args.embed_subtitles,
+        srt: args.srt,
+        keyframes: args.keyframes.map(|count| KeyframeSettings {
+            count: count as usize,
+            scene_detect: args.scene_detect,
+            caption: args.caption_keyframes,
+        }),
+        markdown: !args.no_markdown,
+        counts: ContentCounts {
+            titles: args.titles as usize,
+            descriptions: args.descriptions as usize,
+            status_updates: args.status_updates as usize,
+        },
+        profile,
+        content_budget: ContentBudget {
+            max_tokens: args.content_budget_tokens as usize,
+            window_tokens: args.content_window_tokens as usize,
+        },
+        audio: AudioSettings {
+            bitrate_kbps: args.audio_bitrate,
+            sample_rate: args.audio_sample_rate,
+            size_limit_mb: args.api_size_limit_mb,
+        },
+        prices: Arc::new(prices),
+        cancel,
+    };
 
-    if !output.status.success() {
-        anyhow::bail!("ffmpeg failed to extract audio");
+    // A single video keeps the original, unprefixed output
+    if videos.len() == 1 {
+        status!();
+        let progress = VideoProgress::new(ui::multi_progress(), None);
+        process_input(&client, &videos[0], &options, &progress).await?;
+        return Ok(());
     }
 
-    Ok(())
-}
+    status!(
+        "{} {}",
+        MOVIE,
+        style(format!(
+            "Batch processing {} videos ({} at a time)",
+            videos.len(),
+            args.jobs
+        ))
+        .bold()
+    );
+    status!();
 
-async fn compress_if_needed(audio_file: &Path) -> Result<Vec<u8>> {
-    let metadata = fs::metadata(audio_file)?;
-    let size_mb = metadata.len() / 1024 / 1024;
+    let multi = ui::multi_progress();
+    let reports: Vec<VideoReport> = futures::stream::iter(&videos)
+        .map(|video| {
+            let progress = VideoProgress::new(multi.clone(), Some(video_label(video)));
+            let client = &client;
+            let options = &options;
 
-    if size_mb > 24 {
-        let spinner = ProgressBar::new_spinner();
-        spinner.set_style(
-            ProgressStyle::default_spinner()
-                .template("{spinner:.green} {msg}")
-                .unwrap(),
-        );
-        spinner.set_message(format!("Compressing large file ({}MB)...", size_mb));
-        spinner.enable_steady_tick(Duration::from_millis(100));
+            async move {
+                // Videos not started before Ctrl-C are skipped
+                let result = if options.cancel.is_cancelled() {
+                    Err(OpenAIError::Cancelled.into())
+                } else {
+                    process_input(client, video, options, &progress).await
+                };
+                if let Err(e) = &result {
+                    progress.println(format!("{} {:#}", style("✗ Failed:").red().bold(), e));
+                }
+                VideoReport {
+                    video: video.clone(),
+                    result,
+                }
+            }
+        })
+        .buffered(args.jobs as usize)
+        .collect()
+        .await;
 
-        let compressed_path = audio_file.with_extension("compressed.mp3");
+    print_batch_summary(&reports);
 
-        let output = Command::new("ffmpeg")
-            .args([
-                "-i",
-                audio_file.to_str().unwrap(),
-                "-acodec",
-                "mp3",
-                "-ab",
-                "24k",
-                "-ar",
-                "16000",
-                "-ac",
-                "1",
-                "-y",
-                compressed_path.to_str().unwrap(),
-            ])
-            .output()?;
+    let failed = reports.iter().filter(|r| r.result.is_err()).count();
+    if failed > 0 {
+        anyhow::bail!("{} of {} videos failed", failed, reports.len());
+    }
 
-        if !output.status.success() {
-            spinner.finish_with_message("Compression failed");
-            anyhow::bail!("Failed to compress audio");
-        }
+    Ok(())
+}
 
-        let data = fs::read(&compressed_path)?;
-        fs::remove_file(&compressed_path)?;
-        spinner.finish_with_message(format!("Compressed to {}MB", data.len() / 1024 / 1024));
-        Ok(data)
-    } else {
-        fs::read(audio_file).context("Failed to read audio file")
+/// Settings shared by every video of a run
+#[derive(Clone)]
+struct VideoOptions {
+    output_dir: Option<PathBuf>,
+    language: Option<String>,
+    split_on_silence: bool,
+    max_concurrent: usize,
+    use_cache: bool,
+    fresh: bool,
+    keep_download: bool,
+    /// --upload-to bucket, with the prefix as its target path
+    upload: Option<S3Client>,
+    clear_cache: bool,
+    allow_gaps: bool,
+    translate: Option<String>,
+    /// --tts voice, when narration is requested
+    tts_voice: Option<String>,
+    polish: Option<PolishSettings>,
+    /// Full --context text, prepended to the content prompt
+    context: Option<String>,
+    /// --context cut down to the transcription prompt limit
+    transcription_prompt: Option<String>,
+    chapters: bool,
+    /// --speakers count, when speaker labels are requested
+    speakers: Option<u8>,
+    embed_subtitles: Option<EmbedMode>,
+    srt: Option<PathBuf>,
+    keyframes: Option<KeyframeSettings>,
+    markdown: bool,
+    counts: ContentCounts,
+    /// Replaces the built-in content items when --profiles is given
+    profile: Option<Arc<ContentProfile>>,
+    content_budget: ContentBudget,
+    audio: AudioSettings,
+    prices: Arc<PriceTable>,
+    /// Cancelled by Ctrl-C; chunks and videos not yet started are skipped
+    cancel: CancellationToken,
+}
+
+impl VideoOptions {
+    /// Whether transcription has to return segment timestamps
+    fn needs_segments(&self) -> bool {
+        self.chapters || self.speakers.is_some()
     }
 }
 
-async fn generate_content_from_transcript(transcript: &str) -> Result<ContentResponse> {
-    let prompt = format!(
-        r#"基于以下视频转录内容，请生成：
-1. 3个吸引人的标题选项（每个不超过16个字）
-2. 2段详细的视频描述（每段300-500字）
-3. 3个bilibili动态更新文案（每个150-250字）
+/// How --polish rewrites the transcript
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct PolishSettings {
+    remove_fillers: bool,
+    /// Allowed relative change in content length before warning
+    tolerance: f64,
+}
 
-请以JSON格式返回，格式如下：
-{{
-  "titles": ["标题1", "标题2", "标题3"],
-  "descriptions": ["描述1", "描述2"],
-  "status_updates": ["动态1", "动态2", "动态3"]
-}}
+/// How --embed-subtitles attaches the SRT
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum EmbedMode {
+    /// Mux as a subtitle stream, copying audio and video
+    Soft,
+    /// Render into the picture with the subtitles filter
+    Burn,
+}
 
-转录内容：
-{}"#,
-        transcript
-    );
+/// Which frames --keyframes extracts and whether they are captioned
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct KeyframeSettings {
+    count: usize,
+    scene_detect: bool,
+    caption: bool,
+}
 
-    let client = OpenAIClient::new()?;
-    client.generate_content(prompt).await
+/// Encoding of the extracted audio and the upload size limit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct AudioSettings {
+    bitrate_kbps: u32,
+    sample_rate: u32,
+    size_limit_mb: u64,
 }
 
-fn save_outputs(video_name: &str, tmp_dir: &Path, content: &ContentResponse) -> Result<()> {
-    let spinner = ProgressBar::new_spinner();
-    spinner.set_style(
-        ProgressStyle::default_spinner()
-            .template("{spinner:.green} {msg}")
-            .unwrap(),
-    );
-    spinner.set_message("Saving output files...");
-    spinner.enable_steady_tick(Duration::from_millis(100));
+/// When and how long transcripts are summarized before content generation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct ContentBudget {
+    /// Transcripts up to this many estimated tokens are sent in a single pass
+    max_tokens: usize,
+    /// Estimated tokens per summarized window
+    window_tokens: usize,
+}
 
-    // Save JSON
-    let content_file = tmp_dir.join(format!("{}_content.json", video_name));
-    let json = serde_json::to_string_pretty(content)?;
-    fs::write(&content_file, json)?;
+/// How many of each content item to ask the model for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct ContentCounts {
+    titles: usize,
+    descriptions: usize,
+    status_updates: usize,
+}
 
-    // Save titles
-    let titles_file = tmp_dir.join(format!("{}_titles.txt", video_name));
-    let titles = content
-        .titles
-        .iter()
-        .enumerate()
-        .map(|(i, title)| format!("{}. {}", i + 1, title))
-        .collect::<Vec<_>>()
-        .join("\n");
-    fs::write(&titles_file, titles)?;
+impl Default for ContentCounts {
+    fn default() -> Self {
+        Self {
+            titles: 3,
+            descriptions: 2,
+            status_updates: 3,
+        }
+    }
+}
 
-    // Save descriptions
-    let descriptions_file = tmp_dir.join(format!("{}_descriptions.txt", video_name));
-    let descriptions = content
-        .descriptions
-        .iter()
-        .enumerate()
-        .map(|(i, desc)| format!("=== 描述 {} ===\n{}\n", i + 1, desc))
-        .collect::<Vec<_>>()
-        .join("\n");
-    fs::write(&descriptions_file, descriptions)?;
+/// Outcome of a successfully processed video
+struct VideoOutput {
+    output_dir: PathBuf,
+    transcript_file: PathBuf,
+    transcript_chars: usize,
+    usage: UsageReport,
+    /// Artifacts that failed to upload; these don't fail the video
+    upload_errors: Vec<String>,
+}
 
-    // Save status updates
-    let status_file = tmp_dir.join(format!("{}_status.txt", video_name));
-    let status_updates = content
-        .status_updates
-        .iter()
-        .enumerate()
-        .map(|(i, status)| format!("=== 动态 {} ===\n{}\n", i + 1, status))
-        .collect::<Vec<_>>()
-        .join("\n");
-    fs::write(&status_file, status_updates)?;
+/// Per-video entry of the batch summary
+struct VideoReport {
+    video: PathBuf,
+    result: Result<VideoOutput>,
+}
 
-    spinner.finish_with_message("All files saved!");
-    println!();
+/// Progress output for one video, grouped under the run's MultiProgress
+///
+/// In batch mode every line and spinner is prefixed with the video label so
+/// concurrently processed videos stay distinguishable.
+#[derive(Clone)]
+struct VideoProgress {
+    multi: MultiProgress,
+    prefix: String,
+}
 
-    println!("{} {}:", style("Generated files").bold(), PACKAGE);
-    println!(
-        "  📝 Transcript: {}",
-        style(
-            tmp_dir
-                .join(format!("{}_transcript.txt", video_name))
-                .display()
-        )
-        .dim()
-    );
-    println!("  📋 Full content: {}", style(content_file.display()).dim());
-    println!("  🏷️ Titles: {}", style(titles_file.display()).dim());
-    println!(
-        "  📄 Descriptions: {}",
-        style(descriptions_file.display()).dim()
-    );
-    println!(
-        "  💬 Status updates: {}",
-        style(status_file.display()).dim()
-    );
-    println!();
+impl VideoProgress {
+    fn new(multi: MultiProgress, label: Option<String>) -> Self {
+        let prefix = label
+            .map(|label| format!("[{}] ", label))
+            .unwrap_or_default();
+        Self { multi, prefix }
+    }
 
-    // Display preview of titles
-    println!("{}", style("Generated titles:").bold().cyan());
-    for (i, title) in content.titles.iter().enumerate() {
-        println!("  {}. {}", style(i + 1).dim(), style(title).green());
+    fn println(&self, line: impl AsRef<str>) {
+        let line = format!("{}{}", self.prefix, line.as_ref());
+        // MultiProgress::println is a no-op when not attached to a terminal
+        if self.multi.is_hidden() {
+            ui::print_line(&line);
+        } else {
+            let _ = self.multi.println(line);
+        }
     }
 
+    /// Print the step's start as a line in plain mode, where bars are hidden
+    fn announce(&self, message: &str) {
+        if ui::is_plain() {
+            self.println(message);
+        }
+    }
+
+    fn spinner(&self, message: impl Into<String>) -> ProgressBar {
+        let message = message.into();
+        self.announce(&message);
+        let spinner = self.multi.add(ProgressBar::new_spinner());
+        spinner.set_style(
+            ProgressStyle::default_spinner()
+                .template("{spinner:.green} {prefix}{msg}")
+                .unwrap(),
+        );
+        spinner.set_prefix(self.prefix.clone());
+        spinner.set_message(message);
+        spinner.enable_steady_tick(Duration::from_millis(100));
+        spinner
+    }
+
+    /// Progress bar over `seconds` of media, advanced in whole seconds
+    fn bar(&self, message: impl Into<String>, seconds: u32) -> ProgressBar {
+        let message = message.into();
+        self.announce(&message);
+        let bar = self.multi.add(ProgressBar::new(seconds as u64));
+        bar.set_style(
+            ProgressStyle::default_bar()
+                .template(
+                    "{spinner:.green} {prefix}{msg} [{bar:30.cyan/blue}] {percent}% (eta {eta})",
+                )
+                .unwrap()
+                .progress_chars("#>-"),
+        );
+        bar.set_prefix(self.prefix.clone());
+        bar.set_message(message);
+        bar.enable_steady_tick(Duration::from_millis(100));
+        bar
+    }
+
+    /// Byte-based progress bar for downloads; `total` is unknown without Content-Length
+    fn download_bar(&self, message: impl Into<String>, total: Option<u64>) -> ProgressBar {
+        let message = message.into();
+        self.announce(&message);
+        let bar = match total {
+            Some(total) => {
+                let bar = self.multi.add(ProgressBar::new(total));
+                bar.set_style(
+                    ProgressStyle::default_bar()
+                        .template("{spinner:.green} {prefix}{msg} [{bar:30.cyan/blue}] {bytes}/{total_bytes} ({bytes_per_sec}, eta {eta})")
+                        .unwrap()
+                        .progress_chars("#>-"),
+                );
+                bar
+            }
+            None => {
+                let bar = self.multi.add(ProgressBar::new_spinner());
+                bar.set_style(
+                    ProgressStyle::default_spinner()
+                        .template("{spinner:.green} {prefix}{msg} {bytes} ({bytes_per_sec})")
+                        .unwrap(),
+                );
+                bar
+            }
+        };
+        bar.set_prefix(self.prefix.clone());
+        bar.set_message(message);
+        bar.enable_steady_tick(Duration::from_millis(100));
+        bar
+    }
+
+    /// Byte-based progress bar for --upload-to, styled like s3upload's
+    fn upload_bar(&self, message: impl Into<String>) -> ProgressBar {
+        let message = message.into();
+        self.announce(&message);
+        let bar = self.multi.add(ProgressBar::new(0));
+        bar.set_style(ui::upload_bar_style());
+        bar.set_prefix(self.prefix.clone());
+        bar.set_message(message);
+        bar
+    }
+
+    /// Finish a spinner and keep its final message in the log
+    fn finish(&self, spinner: ProgressBar, message: impl AsRef<str>) {
+        spinner.finish_and_clear();
+        self.println(message);
+    }
+}
+
+/// Make sure ffmpeg and ffprobe can be run before any work starts
+fn check_tools_installed() -> Result<()> {
+    for tool in ["ffmpeg", "ffprobe"] {
+        let output = Command::new(tool).arg("-version").output();
+        if !matches!(output, Ok(o) if o.status.success()) {
+            anyhow::bail!(
+                "{} not found. Please install FFmpeg (it provides ffmpeg and ffprobe):\n  \
+                 macOS:   brew install ffmpeg\n  \
+                 Ubuntu:  sudo apt-get install ffmpeg\n  \
+                 Windows: choco install ffmpeg",
+                tool
+            );
+        }
+    }
+    Ok(())
+}
+
+fn check_api_key_set() -> Result<()> {
+    if std::env::var("OPENAI_API_KEY").map_or(true, |key| key.trim().is_empty()) {
+        anyhow::bail!(
+            "OPENAI_API_KEY is not set. Export it before running convert:\n  \
+             export OPENAI_API_KEY=\"sk-...\""
+        );
+    }
+    Ok(())
+}
+
+/// Validate the API key with a models-list call so a bad key fails before extraction
+async fn verify_api_key(client: &Arc<dyn AiClient>) -> Result<()> {
+    let spinner = ui::new_spinner();
+    spinner.set_message("Checking OpenAI API key...");
+    spinner.enable_steady_tick(Duration::from_millis(100));
+    let result = client.verify_api_key().await;
+    spinner.finish_and_clear();
+
+    result.map_err(|e| match e {
+        OpenAIError::AuthFailed { message } => {
+            anyhow::anyhow!("OPENAI_API_KEY was rejected by the API: {}", message)
+        }
+        OpenAIError::RateLimited {
+            code: Some(code), ..
+        } if code == "insufficient_quota" => {
+            anyhow::anyhow!(
+                "The OpenAI account is out of quota; check your plan and billing details"
+            )
+        }
+        e => anyhow::Error::new(e)
+            .context("Failed to reach the OpenAI API (use --skip-preflight to skip this check)"),
+    })
+}
+
+/// Expand the positional inputs into a list of videos
+///
+/// Files and http(s):// or s3:// URLs are taken as given; directories contribute every file (non-recursive)
+/// whose extension is in `extensions`, sorted by name.
+fn collect_videos(inputs: &[PathBuf], extensions: &[String]) -> Result<Vec<PathBuf>> {
+    let mut videos = Vec::new();
+
+    for input in inputs {
+        if input.is_file() || RemoteSource::parse(input)?.is_some() {
+            videos.push(input.clone());
+        } else if input.is_dir() {
+            let mut found = Vec::new();
+            for entry in fs::read_dir(input)
+                .with_context(|| format!("Failed to read directory: {}", input.display()))?
+            {
+                let path = entry?.path();
+                let matches = path.is_file()
+                    && path.extension().is_some_and(|ext| {
+                        let ext = ext.to_string_lossy().to_lowercase();
+                        extensions.iter().any(|allowed| {
+                            allowed
+                                .trim()
+                                .trim_start_matches('.')
+                                .eq_ignore_ascii_case(&ext)
+                        })
+                    });
+                if matches {
+                    found.push(path);
+                }
+            }
+            found.sort();
+            videos.extend(found);
+        } else {
+            anyhow::bail!("Video file does not exist: {:?}", input);
+        }
+    }
+
+    Ok(videos)
+}
+
+/// A video given as a URL instead of a local path
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum RemoteSource {
+    Http(String),
+    S3 { bucket: String, key: String },
+}
+
+impl RemoteSource {
+    /// `Ok(None)` for local paths; malformed `s3://` URIs are errors
+    fn parse(input: &Path) -> Result<Option<Self>> {
+        let Some(input) = input.to_str() else {
+            return Ok(None);
+        };
+
+        if input.starts_with("http://") || input.starts_with("https://") {
+            return Ok(Some(Self::Http(input.to_string())));
+        }
+
+        let Some(rest) = input.strip_prefix("s3://") else {
+            return Ok(None);
+        };
+        match rest.split_once('/') {
+            Some((bucket, key)) if !bucket.is_empty() && !key.is_empty() => Ok(Some(Self::S3 {
+                bucket: bucket.to_string(),
+                key: key.to_string(),
+            })),
+            _ => anyhow::bail!("Invalid S3 URI '{}': expected s3://bucket/key", input),
+        }
+    }
+
+    /// Identifies the source in the download metadata
+    fn uri(&self) -> String {
+        match self {
+            Self::Http(url) => url.clone(),
+            Self::S3 { bucket, key } => format!("s3://{}/{}", bucket, key),
+        }
+    }
+
+    /// Local file name: the last path segment, without query or fragment
+    fn file_name(&self) -> Result<String> {
+        let path = match self {
+            Self::Http(url) => {
+                let without_scheme = url.split_once("://").map_or(url.as_str(), |(_, rest)| rest);
+                let without_query = without_scheme.split(['?', '#']).next().unwrap_or_default();
+                without_query.split_once('/').map_or("", |(_, path)| path)
+            }
+            Self::S3 { key, .. } => key.as_str(),
+        };
+
+        path.rsplit('/')
+            .next()
+            .filter(|name| !name.is_empty() && *name != "." && *name != "..")
+            .map(str::to_string)
+            .with_context(|| format!("No file name in {}", self.uri()))
+    }
+}
+
+/// S3 settings for `bucket`; region and profile come from the environment or
+/// .env like s3upload, defaulting to us-east-1
+fn s3_config(bucket: &str, target_path: &str) -> Config {
+    dotenv::dotenv().ok();
+    Config {
+        region: std::env::var("AWS_REGION").unwrap_or_else(|_| "us-east-1".to_string()),
+        profile: std::env::var("AWS_PROFILE").ok(),
+        role_arn: None,
+        bucket: bucket.to_string(),
+        target_path: target_path.to_string(),
+        endpoint_url: None,
+        provider: None,
+    }
+}
+
+/// Destination of --upload-to
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct UploadTarget {
+    bucket: String,
+    /// Key prefix without leading or trailing slashes; empty for the bucket root
+    prefix: String,
+}
+
+impl UploadTarget {
+    fn parse(value: &str) -> Result<Self> {
+        let rest = value.strip_prefix("s3://").with_context(|| {
+            format!(
+                "Invalid upload target '{}': expected s3://bucket/prefix",
+                value
+            )
+        })?;
+        let (bucket, prefix) = rest.split_once('/').unwrap_or((rest, ""));
+        let prefix = prefix.trim_matches('/');
+        if bucket.is_empty() {
+            anyhow::bail!("Invalid upload target '{}': missing bucket", value);
+        }
+        if !prefix.is_empty()
+            && prefix
+                .split('/')
+                .any(|segment| segment.is_empty() || segment == "." || segment == "..")
+        {
+            anyhow::bail!(
+                "Invalid upload target '{}': empty, '.' or '..' segments in the prefix",
+                value
+            );
+        }
+
+        Ok(Self {
+            bucket: bucket.to_string(),
+            prefix: prefix.to_string(),
+        })
+    }
+}
+
+/// What is known about a previous download, saved next to it as `<file>.download.json`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+struct DownloadMeta {
+    source: String,
+    etag: Option<String>,
+    /// HTTP date of the remote object, also applied as the file's mtime so
+    /// re-downloading unchanged content keeps the same cache fingerprint
+    last_modified: Option<String>,
+}
+
+fn download_meta_path(file: &Path) -> PathBuf {
+    let mut name = file.as_os_str().to_owned();
+    name.push(".download.json");
+    PathBuf::from(name)
+}
+
+fn partial_download_path(file: &Path) -> PathBuf {
+    let mut name = file.as_os_str().to_owned();
+    name.push(".part");
+    PathBuf::from(name)
+}
+
+fn load_download_meta(file: &Path) -> Option<DownloadMeta> {
+    let content = fs::read_to_string(download_meta_path(file)).ok()?;
+    serde_json::from_str(&content).ok()
+}
+
+fn save_download_meta(file: &Path, meta: &DownloadMeta) -> Result<()> {
+    fs::write(
+        download_meta_path(file),
+        serde_json::to_string_pretty(meta)?,
+    )?;
+    Ok(())
+}
+
+/// How to ask for a remote video given what is already on disk
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum DownloadPlan {
+    /// The complete file is present: only fetch it if its ETag changed
+    IfNoneMatch(String),
+    /// Continue a partial download if the remote still matches `validator`
+    Resume {
+        offset: u64,
+        validator: String,
+    },
+    Full,
+}
+
+fn plan_download(
+    meta: Option<&DownloadMeta>,
+    source: &str,
+    have_file: bool,
+    partial_len: u64,
+) -> DownloadPlan {
+    let Some(meta) = meta.filter(|meta| meta.source == source) else {
+        return DownloadPlan::Full;
+    };
+
+    if have_file && let Some(etag) = &meta.etag {
+        return DownloadPlan::IfNoneMatch(etag.clone());
+    }
+
+    let validator = meta.etag.clone().or_else(|| meta.last_modified.clone());
+    match validator {
+        Some(validator) if !have_file && partial_len > 0 => DownloadPlan::Resume {
+            offset: partial_len,
+            validator,
+        },
+        _ => DownloadPlan::Full,
+    }
+}
+
+/// Apply the remote Last-Modified date as the file's mtime
+fn set_modified_from_http_date(file: &Path, http_date: Option<&str>) -> Result<()> {
+    let Some(http_date) = http_date else {
+        return Ok(());
+    };
+    let Ok(date) = aws_sdk_s3::primitives::DateTime::from_str(
+        http_date,
+        aws_sdk_s3::primitives::DateTimeFormat::HttpDate,
+    ) else {
+        return Ok(());
+    };
+    if let Ok(modified) = std::time::SystemTime::try_from(date) {
+        fs::File::options()
+            .write(true)
+            .open(file)?
+            .set_modified(modified)?;
+    }
+    Ok(())
+}
+
+/// Download a remote video to `dest`, reusing an unchanged earlier download
+async fn download_source(
+    source: &RemoteSource,
+    dest: &Path,
+    progress: &VideoProgress,
+) -> Result<()> {
+    let partial = partial_download_path(dest);
+    let partial_len = fs::metadata(&partial).map(|m| m.len()).unwrap_or(0);
+    let meta = load_download_meta(dest);
+    let plan = plan_download(meta.as_ref(), &source.uri(), dest.is_file(), partial_len);
+
+    let downloaded = match source {
+        RemoteSource::Http(url) => download_http(url, dest, &partial, plan, progress).await?,
+        RemoteSource::S3 { bucket, key } => {
+            download_s3(bucket, key, &source.uri(), &partial, plan, progress).await?
+        }
+    };
+
+    let Some(meta) = downloaded else {
+        progress.println(format!(
+            "{}Remote video unchanged, using {}",
+            style(RECYCLE).cyan(),
+            style(dest.display()).dim()
+        ));
+        return Ok(());
+    };
+
+    fs::rename(&partial, dest)
+        .with_context(|| format!("Failed to move download to {}", dest.display()))?;
+    set_modified_from_http_date(dest, meta.last_modified.as_deref())?;
+    save_download_meta(dest, &meta)?;
+    progress.println(format!(
+        "{} Downloaded {} to {}",
+        CHECK,
+        source.uri(),
+        style(dest.display()).dim()
+    ));
     Ok(())
 }
+
+/// Fetch over http(s) into `partial`; `None` when the server says the
+/// complete local copy is still current
+async fn download_http(
+    url: &str,
+    dest: &Path,
+    partial: &Path,
+    plan: DownloadPlan,
+    progress: &VideoProgress,
+) -> Result<Option<DownloadMeta>> {
+    let http = reqwest::Client::builder().use_rustls_tls().build()?;
+    let mut request = http.get(url);
+    match &plan {
+        DownloadPlan::IfNoneMatch(etag) => {
+            request = request.header(reqwest::header::IF_NONE_MATCH, etag)
+        }
+        DownloadPlan::Resume { offset, validator } => {
+            request = request
+                .header(reqwest::header::RANGE, format!("bytes={}-", offset))
+                .header(reqwest::header::IF_RANGE, validator);
+        }
+        DownloadPlan::Full => {}
+    }
+
+    let mut response = request
+        .send()
+        .await
+        .with_context(|| format!("Failed to download {}", url))?;
+    let status = response.status();
+    if status == reqwest::StatusCode::NOT_MODIFIED && dest.is_file() {
+        return Ok(None);
+    }
+    if !status.is_success() {
+        anyhow::bail!("Download of {} failed with status {}", url, status);
+    }
+
+    let header = |name| {
+        response
+            .headers()
+            .get(name)
+            .and_then(|v: &reqwest::header::HeaderValue| v.to_str().ok())
+            .map(str::to_string)
+    };
+    let meta = DownloadMeta {
+        source: url.to_string(),
+        etag: header(reqwest::header::ETAG),
+        last_modified: header(reqwest::header::LAST_MODIFIED),
+    };
+    // Remember validators right away so an interrupted transfer can resume
+    save_download_meta(dest, &meta)?;
+
+    // 206 continues the partial file; anything else starts it over
+    let offset = match plan {
+        DownloadPlan::Resume { offset, .. } if status == reqwest::StatusCode::PARTIAL_CONTENT => {
+            offset
+        }
+        _ => 0,
+    };
+    let mut file = open_partial(partial, offset)?;
+    let total = response.content_length().map(|len| len + offset);
+    let bar = progress.download_bar("Downloading video...", total);
+    bar.set_position(offset);
+
+    while let Some(bytes) = response
+        .chunk()
+        .await
+        .with_context(|| format!("Download of {} was interrupted", url))?
+    {
+        file.write_all(&bytes)?;
+        bar.inc(bytes.len() as u64);
+    }
+    bar.finish_and_clear();
+
+    Ok(Some(meta))
+}
+
+/// Fetch an S3 object into `partial` with the crate's S3 client; `None`
+/// when the complete local copy still matches the object's ETag
+async fn download_s3(
+    bucket: &str,
+    key: &str,
+    uri: &str,
+    partial: &Path,
+    plan: DownloadPlan,
+    progress: &VideoProgress,
+) -> Result<Option<DownloadMeta>> {
+    let s3 = S3Client::new(s3_config(bucket, "")).await?;
+
+    let mut request = s3.client().get_object().bucket(s3.bucket()).key(key);
+    match &plan {
+        DownloadPlan::IfNoneMatch(etag) => request = request.if_none_match(etag),
+        DownloadPlan::Resume { offset, validator } => {
+            request = request
+                .range(format!("bytes={}-", offset))
+                .if_match(validator);
+        }
+        DownloadPlan::Full => {}
+    }
+
+    let response = match request.send().await {
+        Ok(response) => response,
+        Err(e) => {
+            let status = e.raw_response().map(|r| r.status().as_u16());
+            match (status, &plan) {
+                (Some(304), DownloadPlan::IfNoneMatch(_)) => return Ok(None),
+                // The object changed since the partial download started
+                (Some(412), DownloadPlan::Resume { .. }) => {
+                    return Box::pin(download_s3(
+                        bucket,
+                        key,
+                        uri,
+                        partial,
+                        DownloadPlan::Full,
+                        progress,
+                    ))
+                    .await;
+                }
+                _ => return Err(e).with_context(|| format!("Failed to download {}", uri)),
+            }
+        }
+    };
+
+    let offset = match plan {
+        DownloadPlan::Resume { offset, .. } if response.content_range().is_some() => offset,
+        _ => 0,
+    };
+    let meta = DownloadMeta {
+        source: uri.to_string(),
+        etag: response.e_tag().map(str::to_string),
+        last_modified: response.last_modified().and_then(|date| {
+            date.fmt(aws_sdk_s3::primitives::DateTimeFormat::HttpDate)
+                .ok()
+        }),
+    };
+
+    let mut file = open_partial(partial, offset)?;
+    let total = response
+        .content_length()
+        .and_then(|len| u64::try_from(len).ok())
+        .map(|len| len + offset);
+    let bar = progress.download_bar("Downloading video from S3...", total);
+    bar.set_position(offset);
+
+    let mut body = response.body;
+    while let Some(bytes) = body
+        .try_next()
+        .await
+        .with_context(|| format!("Download of {} was interrupted", uri))?
+    {
+        file.write_all(&bytes)?;
+        bar.inc(bytes.len() as u64);
+    }
+    bar.finish_and_clear();
+
+    Ok(Some(meta))
+}
+
+/// Open the partial download, appending at `offset` or truncating when 0
+fn open_partial(partial: &Path, offset: u64) -> Result<fs::File> {
+    let file = if offset > 0 {
+        fs::File::options().append(true).open(partial)?
+    } else {
+        fs::File::create(partial)?
+    };
+    Ok(file)
+}
+
+/// Short label for a video in batch output
+fn video_label(video: &Path) -> String {
+    video
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_else(|| video.display().to_string())
+}
+
+/// Process a local video, or download a remote one into its output directory first
+async fn process_input(
+    client: &Arc<dyn AiClient>,
+    input: &Path,
+    options: &VideoOptions,
+    progress: &VideoProgress,
+) -> Result<VideoOutput> {
+    let Some(source) = RemoteSource::parse(input)? else {
+        let output_dir = match &options.output_dir {
+            Some(dir) => dir.clone(),
+            None => default_output_dir(input)?,
+        };
+        let _lock = lock_video(&output_dir, input)?;
+        return process_video(client, input, output_dir, options, progress).await;
+    };
+
+    let file_name = source.file_name()?;
+    let output_dir = match &options.output_dir {
+        Some(dir) => dir.clone(),
+        None => default_output_dir(Path::new(&file_name))?,
+    };
+    fs::create_dir_all(&output_dir).with_context(|| {
+        format!(
+            "Failed to create output directory: {}",
+            output_dir.display()
+        )
+    })?;
+
+    // Downloads of different URLs with the same file name get their own directory
+    let download_dir = output_dir.join(short_hash(source.uri().as_bytes(), PATH_TAG_LEN));
+    fs::create_dir_all(&download_dir)?;
+    let video_file = download_dir.join(&file_name);
+    let _lock = lock_video(&output_dir, &video_file)?;
+
+    download_source(&source, &video_file, progress).await?;
+    let result = process_video(client, &video_file, output_dir, options, progress).await;
+
+    if !options.keep_download {
+        let _ = fs::remove_file(&video_file);
+        let _ = fs::remove_file(download_meta_path(&video_file));
+        let _ = fs::remove_dir(&download_dir);
+    }
+    result
+}
+
+/// Transcribe one video and generate its content
+async fn process_video(
+    client: &Arc<dyn AiClient>,
+    video_file: &Path,
+    output_dir: PathBuf,
+    options: &VideoOptions,
+    progress: &VideoProgress,
+) -> Result<VideoOutput> {
+    let language = options.language.as_deref();
+    let video_name = cache_name(video_file)?;
+    fs::create_dir_all(&output_dir).with_context(|| {
+        format!(
+            "Failed to create output directory: {}",
+            output_dir.display()
+        )
+    })?;
+
+    if options.clear_cache {
+        let removed = clear_stale_artifacts(&output_dir, &video_name)?;
+        if removed > 0 {
+            progress.println(format!(
+                "{}Removed {} stale cache files",
+                style(BROOM).cyan(),
+                removed
+            ));
+        }
+    }
+
+    progress.println(format!(
+        "{} {}",
+        MOVIE,
+        style(format!("Processing video: {:?}", video_file)).bold()
+    ));
+    progress.println(format!(
+        "   Output directory: {}",
+        style(output_dir.display()).yellow()
+    ));
+
+    // Resume from the manifest of an interrupted run, or plan a new one
+    let state_path = state_file_path(&output_dir, video_file)?;
+    let saved = if options.fresh || !options.use_cache {
+        None
+    } else {
+        load_state(&state_path)
+    };
+    let state = match resumable_state(saved, &video_name, options.split_on_silence) {
+        Some(state) => {
+            progress.println(format!("{} {}", style("↻").cyan(), state.resume_summary()));
+            state
+        }
+        None => plan_run(video_file, &video_name, options, progress)?,
+    };
+    let duration = state.duration;
+    let chunks = state.spans();
+    let state = StateFile::create(state_path, state)?;
+
+    let transcript_file = output_dir.join(format!("{}_transcript.txt", video_name));
+
+    // Process audio extraction and transcription
+    let transcript = if !chunks.is_empty() {
+        progress.println(format!(
+            "{} Video longer than {} seconds, processing in chunks...",
+            WARNING, CHUNK_SECONDS
+        ));
+
+        process_long_video(
+            client,
+            video_file,
+            &video_name,
+            &output_dir,
+            options,
+            &state,
+            progress,
+        )
+        .await?
+    } else {
+        process_short_video(
+            client,
+            video_file,
+            &video_name,
+            &output_dir,
+            duration,
+            options,
+            progress,
+        )
+        .await?
+    };
+
+    let Transcript {
+        text: mut full_transcript,
+        segments,
+        audio_seconds,
+    } = transcript;
+    let mut usage = ApiUsage {
+        audio_seconds,
+        ..Default::default()
+    };
+
+    if let Some(polish) = &options.polish {
+        let raw_file = output_dir.join(format!("{}_transcript.raw.txt", video_name));
+        // The previous polished text is reused while the raw transcript is unchanged
+        let cached = (options.use_cache
+            && fs::read_to_string(&raw_file).is_ok_and(|raw| raw == full_transcript))
+        .then(|| fs::read_to_string(&transcript_file).ok())
+        .flatten()
+        .filter(|polished| *polished != full_transcript);
+
+        fs::write(&raw_file, &full_transcript)?;
+        full_transcript = match cached {
+            Some(polished) => {
+                progress.println(format!(
+                    "{}Using cached polished transcript",
+                    style(RECYCLE).cyan()
+                ));
+                polished
+            }
+            None => {
+                let polished =
+                    polish_transcript(client, &full_transcript, polish, progress).await?;
+                usage.chat += polished.usage;
+                polished.value
+            }
+        };
+    }
+
+    // Save full transcript
+    fs::write(&transcript_file, &full_transcript)?;
+    progress.println(format!(
+        "{} Transcript saved to: {}",
+        CHECK,
+        style(transcript_file.display()).dim()
+    ));
+
+    if let Some(target) = &options.translate {
+        let translation_file = output_dir.join(format!("{}_transcript.{}.txt", video_name, target));
+        if options.use_cache && translation_file.exists() {
+            progress.println(format!("{}Using cached translation", style(RECYCLE).cyan()));
+        } else {
+            let translation =
+                translate_transcript(client, &full_transcript, target, progress).await?;
+            usage.chat += translation.usage;
+            fs::write(&translation_file, translation.value)?;
+            progress.println(format!(
+                "{} Translation saved to: {}",
+                CHECK,
+                style(translation_file.display()).dim()
+            ));
+        }
+    }
+
+    if let Some(speakers) = options.speakers {
+        let labeled_file = output_dir.join(format!("{}_transcript.labeled.txt", video_name));
+        if options.use_cache && labeled_file.exists() {
+            progress.println(format!(
+                "{}Using cached speaker labels",
+                style(RECYCLE).cyan()
+            ));
+        } else {
+            let labeled = label_speakers(client, &segments, speakers, progress).await?;
+            usage.chat += labeled.usage;
+            fs::write(&labeled_file, labeled.value)?;
+            progress.println(format!(
+                "{} Labeled transcript saved to: {}",
+                CHECK,
+                style(labeled_file.display()).dim()
+            ));
+        }
+    }
+
+    // Content of a completed earlier run is reused as long as it has what was asked for
+    let content_file = output_dir.join(format!("{}_content.json", video_name));
+    let cached_content = if options.use_cache && state.content_generated() {
+        load_cached_content(&content_file).filter(|c| {
+            (!options.chapters || !c.chapters.is_empty())
+                && content_matches_profile(c, options.profile.as_deref())
+        })
+    } else {
+        None
+    };
+
+    let mut content = match cached_content {
+        Some(content) => {
+            progress.println(format!("{}Using cached content", style(RECYCLE).cyan()));
+            content
+        }
+        None => {
+            let source = condense_transcript(client, &full_transcript, options, progress).await?;
+            usage.chat += source.usage;
+            let source = source.value;
+
+            // Generate content
+            let spinner = progress.spinner(format!(
+                "Generating content with {}...",
+                client.models().chat
+            ));
+            let mut content = match &options.profile {
+                Some(profile) => {
+                    let generated =
+                        generate_profile_content(client, &source, profile, options, &spinner)
+                            .await?;
+                    usage.chat += generated.usage;
+                    ContentResponse {
+                        sections: generated.value,
+                        ..Default::default()
+                    }
+                }
+                None => {
+                    let generated = with_retry_hook(
+                        retry_message(
+                            &spinner,
+                            format!("Generating content with {}", client.models().chat),
+                        ),
+                        generate_content_from_transcript(
+                            client,
+                            &source,
+                            language,
+                            &options.counts,
+                            options.context.as_deref(),
+                        ),
+                    )
+                    .await?;
+                    usage.chat += generated.usage;
+                    generated.value
+                }
+            };
+            progress.finish(
+                spinner,
+                format!("{} Content generated successfully!", CHECK),
+            );
+            if options.profile.is_none() {
+                for warning in count_mismatches(&content, &options.counts) {
+                    progress.println(format!("{}{}", WARNING, warning));
+                }
+            }
+
+            if options.chapters {
+                let spinner = progress.spinner("Generating chapters...");
+                let generated = with_retry_hook(
+                    retry_message(&spinner, "Generating chapters"),
+                    generate_chapters(client, &segments, duration),
+                )
+                .await?;
+                usage.chat += generated.usage;
+                let chapters = generated.value;
+
+                let chapters_file = output_dir.join(format!("{}_chapters.txt", video_name));
+                fs::write(&chapters_file, format_chapters(&chapters))?;
+                progress.finish(
+                    spinner,
+                    format!(
+                        "{} {} chapters saved to: {}",
+                        CHECK,
+                        chapters.len(),
+                        style(chapters_file.display()).dim()
+                    ),
+                );
+                content.chapters = chapters;
+            }
+            content
+        }
+    };
+
+    if let Some(settings) = &options.keyframes {
+        let previous = std::mem::take(&mut content.keyframes);
+        let times = if settings.scene_detect {
+            scene_keyframe_times(video_file, duration, settings.count, progress)?
+        } else {
+            even_keyframe_times(duration, settings.count)
+        };
+        if times.len() < settings.count {
+            progress.println(format!(
+                "{}Only {} of {} key frames fit in the {:.1}s video",
+                WARNING,
+                times.len(),
+                settings.count,
+                duration
+            ));
+        }
+
+        let mut keyframes =
+            extract_keyframes(video_file, &video_name, &output_dir, &times, progress).await?;
+        if settings.caption {
+            if options.use_cache {
+                reuse_captions(&mut keyframes, &previous);
+            }
+            usage.chat +=
+                caption_keyframes(client, &output_dir, &mut keyframes, options, progress).await?;
+        }
+        content.keyframes = keyframes;
+    }
+
+    // Save all outputs
+    save_outputs(
+        &video_name,
+        &output_dir,
+        &content,
+        &full_transcript,
+        options,
+        progress,
+    )?;
+    state.update(|state| state.content_generated = true)?;
+
+    if let Some(voice) = &options.tts_voice {
+        narrate_content(
+            client,
+            &video_name,
+            &output_dir,
+            &content,
+            voice,
+            options,
+            progress,
+        )
+        .await?;
+    }
+
+    if let Some(mode) = options.embed_subtitles {
+        let srt = match &options.srt {
+            Some(srt) => srt.clone(),
+            None => output_dir.join(format!("{}.srt", video_name)),
+        };
+        if !srt.is_file() {
+            anyhow::bail!(
+                "No subtitles to embed: {} does not exist (pass --srt <FILE>)",
+                srt.display()
+            );
+        }
+        let subtitled =
+            embed_subtitles(video_file, &srt, &output_dir, mode, duration, progress).await?;
+        progress.println(format!(
+            "{} Subtitled video saved to: {}",
+            CHECK,
+            style(subtitled.display()).dim()
+        ));
+    }
+
+    let upload_errors = match &options.upload {
+        Some(s3) => {
+            let files = upload_artifacts(&output_dir, &video_name, &content.keyframes, options);
+            upload_outputs(s3, &files, progress).await
+        }
+        None => Vec::new(),
+    };
+
+    let usage = UsageReport::new(usage, client.models(), &options.prices);
+    let usage_file = output_dir.join(format!("{}_usage.json", video_name));
+    fs::write(&usage_file, serde_json::to_string_pretty(&usage)?)?;
+    progress.println(format!("{}{}", style(MONEY).yellow(), usage.summary()));
+
+    progress.println(format!(
+        "{} {}",
+        SPARKLES,
+        style("Processing complete!").green().bold()
+    ));
+    progress.println(format!(
+        "{}All files saved in {}",
+        PACKAGE,
+        style(output_dir.display()).yellow()
+    ));
+    if !upload_errors.is_empty() {
+        progress.println(format!(
+            "{}{}",
+            WARNING,
+            style(format!(
+                "{} artifacts failed to upload:",
+                upload_errors.len()
+            ))
+            .yellow()
+        ));
+        for error in &upload_errors {
+            progress.println(format!("  {} {}", style("✗").red(), error));
+        }
+    }
+
+    Ok(VideoOutput {
+        output_dir,
+        transcript_file,
+        transcript_chars: full_transcript.chars().count(),
+        usage,
+        upload_errors,
+    })
+}
+
+/// Print the per-video status table of a batch run
+fn print_batch_summary(reports: &[VideoReport]) {
+    for line in batch_summary_lines(reports) {
+        status!("{}", line);
+    }
+}
+
+/// Lines of the batch summary table, as printed by [`print_batch_summary`]
+fn batch_summary_lines(reports: &[VideoReport]) -> Vec<String> {
+    let mut lines = Vec::new();
+    let name_width = reports
+        .iter()
+        .map(|r| video_label(&r.video).chars().count())
+        .max()
+        .unwrap_or(5)
+        .max(5);
+
+    lines.push(String::new());
+    lines.push(style("═".repeat(70)).dim().to_string());
+    lines.push(
+        style(format!(
+            "{:<width$}  {:<6}  {:>10}  {}",
+            "Video",
+            "Status",
+            "Transcript",
+            "Output",
+            width = name_width
+        ))
+        .bold()
+        .to_string(),
+    );
+
+    for report in reports {
+        let name = video_label(&report.video);
+        match &report.result {
+            Ok(output) => lines.push(format!(
+                "{:<width$}  {}  {:>10}  {}",
+                name,
+                style(format!("{:<6}", "ok")).green(),
+                format!("{} ch", output.transcript_chars),
+                style(output.transcript_file.display()).dim(),
+                width = name_width
+            )),
+            Err(e) => lines.push(format!(
+                "{:<width$}  {}  {:>10}  {}",
+                name,
+                style(format!("{:<6}", "failed")).red(),
+                "-",
+                style(format!("{:#}", e)).red(),
+                width = name_width
+            )),
+        }
+    }
+
+    let succeeded: Vec<_> = reports
+        .iter()
+        .filter_map(|r| r.result.as_ref().ok())
+        .collect();
+    lines.push(style("═".repeat(70)).dim().to_string());
+    lines.push(
+        style(format!(
+            "Summary: {} succeeded, {} failed",
+            succeeded.len(),
+            reports.len() - succeeded.len()
+        ))
+        .bold()
+        .to_string(),
+    );
+    let costs: Vec<_> = succeeded
+        .iter()
+        .map(|o| o.usage.estimated_cost_usd)
+        .collect();
+    if !costs.is_empty() {
+        let total: f64 = costs.iter().flatten().sum();
+        let unpriced = costs.iter().filter(|c| c.is_none()).count();
+        lines.push(format!(
+            "{}Estimated cost: {}{}",
+            style(MONEY).yellow(),
+            format_cost(total),
+            if unpriced > 0 {
+                format!(" ({} videos without known prices)", unpriced)
+            } else {
+                String::new()
+            }
+        ));
+    }
+    let upload_failures = succeeded
+        .iter()
+        .filter(|o| !o.upload_errors.is_empty())
+        .count();
+    if upload_failures > 0 {
+        lines.push(format!(
+            "{}{}",
+            WARNING,
+            style(format!(
+                "Uploads failed for {} videos (their conversion succeeded)",
+                upload_failures
+            ))
+            .yellow()
+        ));
+    }
+    if let Some(first) = succeeded.first()
+        && succeeded.iter().all(|o| o.output_dir == first.output_dir)
+    {
+        lines.push(format!(
+            "{}All files saved in {}",
+            PACKAGE,
+            style(first.output_dir.display()).yellow()
+        ));
+    }
+    lines
+}
+
+/// Human-readable language for progress messages
+fn language_label(language: Option<&str>) -> &str {
+    language.unwrap_or("auto-detect")
+}
+
+/// Default artifact directory: `<stem>_output/` alongside the input video
+fn default_output_dir(video_path: &Path) -> Result<PathBuf> {
+    let stem = video_path
+        .file_stem()
+        .context("Invalid video filename")?
+        .to_string_lossy();
+    let parent = video_path.parent().unwrap_or_else(|| Path::new(""));
+
+    Ok(parent.join(format!("{}_output", stem)))
+}
+
+/// Leading `len` hex characters of the blake3 hash of `data`
+fn short_hash(data: &[u8], len: usize) -> String {
+    blake3::hash(data).to_hex()[..len].to_string()
+}
+
+/// Per-input key: the video's stem plus a short hash of its absolute path
+///
+/// Same-named videos from different directories sharing an output directory
+/// (or running at the same time) never share a manifest, lock or artifact.
+fn video_key(video_path: &Path) -> Result<String> {
+    let stem = video_path
+        .file_stem()
+        .context("Invalid video filename")?
+        .to_string_lossy();
+    let absolute = fs::canonicalize(video_path).or_else(|_| std::path::absolute(video_path))?;
+
+    Ok(format!(
+        "{}_{}",
+        stem,
+        short_hash(absolute.as_os_str().as_encoded_bytes(), PATH_TAG_LEN)
+    ))
+}
+
+/// Cache name for a video: its [`video_key`] plus a short content fingerprint
+///
+/// Every artifact is prefixed with this name, so re-exporting a video under
+/// the same filename invalidates its cached audio and transcripts.
+fn cache_name(video_path: &Path) -> Result<String> {
+    let fingerprint = video_fingerprint(video_path)?;
+
+    Ok(format!(
+        "{}_{}",
+        video_key(video_path)?,
+        &fingerprint[..FINGERPRINT_LEN]
+    ))
+}
+
+/// Take the advisory `<key>.lock` in the output directory for this video
+///
+/// The lock is released when the returned file is dropped; a second run on the
+/// same video and output directory fails instead of interleaving artifacts.
+fn lock_video(output_dir: &Path, video_path: &Path) -> Result<fs::File> {
+    fs::create_dir_all(output_dir).with_context(|| {
+        format!(
+            "Failed to create output directory: {}",
+            output_dir.display()
+        )
+    })?;
+    let lock_path = output_dir.join(format!("{}.lock", video_key(video_path)?));
+    let file = fs::File::options()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&lock_path)
+        .with_context(|| format!("Failed to open lock file: {}", lock_path.display()))?;
+
+    match file.try_lock() {
+        Ok(()) => Ok(file),
+        Err(fs::TryLockError::WouldBlock) => anyhow::bail!(
+            "{} is already in progress in another run (lock: {})",
+            video_path.display(),
+            lock_path.display()
+        ),
+        Err(fs::TryLockError::Error(e)) => {
+            Err(e).with_context(|| format!("Failed to lock {}", lock_path.display()))
+        }
+    }
+}
+
+/// Hash of size, mtime and the first and last MB of a file
+///
+/// Cheap enough for multi-GB videos while still catching re-exports that keep
+/// the same size.
+fn video_fingerprint(video_path: &Path) -> Result<String> {
+    let mut file = fs::File::open(video_path)
+        .with_context(|| format!("Failed to open video: {}", video_path.display()))?;
+    let metadata = file.metadata()?;
+    let size = metadata.len();
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+        .map(|d| d.as_nanos())
+        .unwrap_or(0);
+
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(&size.to_le_bytes());
+    hasher.update(&modified.to_le_bytes());
+
+    let mut head = Vec::new();
+    (&mut file)
+        .take(FINGERPRINT_EDGE_BYTES)
+        .read_to_end(&mut head)?;
+    hasher.update(&head);
+
+    if size > FINGERPRINT_EDGE_BYTES {
+        let tail_start = size
+            .saturating_sub(FINGERPRINT_EDGE_BYTES)
+            .max(head.len() as u64);
+        file.seek(SeekFrom::Start(tail_start))?;
+        let mut tail = Vec::new();
+        file.read_to_end(&mut tail)?;
+        hasher.update(&tail);
+    }
+
+    Ok(hasher.finalize().to_hex().to_string())
+}
+
+/// Whether a file in the output directory belongs to an older version of the video
+///
+/// Artifacts are named `<video_key>_<fingerprint>...`; anything with the same
+/// video key but a different fingerprint is stale. A video whose name starts
+/// with this key and a fingerprint-like part is another video: its artifacts
+/// carry its own `_<path tag>_<fingerprint>` after that.
+fn is_stale_artifact(file_name: &str, video_name: &str) -> bool {
+    let Some((key, current)) = video_name
+        .len()
+        .checked_sub(FINGERPRINT_LEN + 1)
+        .and_then(|at| video_name.split_at_checked(at))
+        .and_then(|(key, rest)| Some((key, rest.strip_prefix('_')?)))
+    else {
+        return false;
+    };
+    let Some(rest) = file_name
+        .strip_prefix(key)
+        .and_then(|r| r.strip_prefix('_'))
+    else {
+        return false;
+    };
+    let Some((fingerprint, suffix)) = rest.split_at_checked(FINGERPRINT_LEN) else {
+        return false;
+    };
+
+    fingerprint != current
+        && fingerprint.chars().all(|c| c.is_ascii_hexdigit())
+        && (suffix.is_empty() || suffix.starts_with(['.', '_']))
+        && !has_cache_name_part(suffix)
+}
+
+/// Whether an artifact suffix contains `_<path tag>_<fingerprint>`, the end of
+/// another video's cache name
+fn has_cache_name_part(suffix: &str) -> bool {
+    let is_hex =
+        |part: &str, len: usize| part.len() == len && part.chars().all(|c| c.is_ascii_hexdigit());
+    let parts: Vec<&str> = suffix.split('_').collect();
+    parts.windows(2).any(|pair| {
+        let fingerprint = pair[1].split('.').next().unwrap_or_default();
+        is_hex(pair[0], PATH_TAG_LEN) && is_hex(fingerprint, FINGERPRINT_LEN)
+    })
+}
+
+/// Delete stale artifacts of the video from the output directory
+///
+/// Generated content (`*_content.json`, titles, ...) of old versions is removed
+/// too since it was derived from the stale transcript.
+fn clear_stale_artifacts(output_dir: &Path, video_name: &str) -> Result<usize> {
+    let mut removed = 0;
+    for entry in fs::read_dir(output_dir)? {
+        let path = entry?.path();
+        let is_stale = path.is_file()
+            && path
+                .file_name()
+                .is_some_and(|name| is_stale_artifact(&name.to_string_lossy(), video_name));
+        if is_stale {
+            fs::remove_file(&path)
+                .with_context(|| format!("Failed to remove {}", path.display()))?;
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
+/// Subset of `ffprobe -print_format json -show_format -show_streams` output
+#[derive(Debug, Deserialize)]
+struct FfprobeOutput {
+    format: Option<FfprobeEntry>,
+    #[serde(default)]
+    streams: Vec<FfprobeEntry>,
+}
+
+/// A format or stream entry; ffprobe reports durations as decimal strings
+#[derive(Debug, Deserialize)]
+struct FfprobeEntry {
+    duration: Option<String>,
+}
+
+impl FfprobeEntry {
+    fn duration(&self) -> Option<f64> {
+        self.duration
+            .as_deref()
+            .and_then(|d| d.parse::<f64>().ok())
+            .filter(|d| d.is_finite() && *d > 0.0)
+    }
+}
+
+/// Video duration in seconds: the container's duration, else the longest stream
+fn parse_ffprobe_duration(json: &str) -> Result<f64> {
+    let probe: FfprobeOutput =
+        serde_json::from_str(json).context("Failed to parse ffprobe output")?;
+
+    probe
+        .format
+        .as_ref()
+        .and_then(FfprobeEntry::duration)
+        .or_else(|| {
+            probe
+                .streams
+                .iter()
+                .filter_map(FfprobeEntry::duration)
+                .max_by(f64::total_cmp)
+        })
+        .context("ffprobe reported no duration for the format or any stream")
+}
+
+fn get_video_duration(video_path: &Path) -> Result<f64> {
+    let output = Command::new("ffprobe")
+        .args([
+            "-v",
+            "error",
+            "-print_format",
+            "json",
+            "-show_format",
+            "-show_streams",
+        ])
+        .arg(video_path)
+        .output()
+        .context("Failed to run ffprobe")?;
+
+    if !output.status.success() {
+        anyhow::bail!(
+            "ffprobe failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    parse_ffprobe_duration(&String::from_utf8_lossy(&output.stdout))
+}
+
+async fn process_short_video(
+    client: &Arc<dyn AiClient>,
+    video_path: &Path,
+    video_name: &str,
+    output_dir: &Path,
+    duration: f64,
+    options: &VideoOptions,
+    progress: &VideoProgress,
+) -> Result<Transcript> {
+    let language = options.language.as_deref();
+    let use_cache = options.use_cache;
+    let audio_file = output_dir.join(format!("{}.mp3", video_name));
+    // With --polish, _transcript.txt holds the polished text and the raw one moves aside
+    let raw_file = output_dir.join(format!("{}_transcript.raw.txt", video_name));
+    let transcript_file = if raw_file.exists() {
+        raw_file
+    } else {
+        output_dir.join(format!("{}_transcript.txt", video_name))
+    };
+    let segments_file = output_dir.join(format!("{}_segments.json", video_name));
+
+    // Check cache
+    if use_cache
+        && let Some(transcript) =
+            load_cached_transcript(&transcript_file, &segments_file, options.needs_segments())?
+    {
+        progress.println(format!("{}Using cached transcript", style(RECYCLE).cyan()));
+        return Ok(transcript);
+    }
+
+    // Extract audio if not exists
+    if !use_cache || !audio_file.exists() {
+        let bar = progress.bar("Extracting audio from video...", duration.ceil() as u32);
+
+        extract_audio(video_path, &audio_file, None, None, &options.audio, &bar).await?;
+        progress.finish(bar, format!("{} Audio extracted", CHECK));
+    } else {
+        progress.println(format!("{}Using cached audio file", style(RECYCLE).cyan()));
+    }
+
+    // Check file size and compress if needed
+    let audio_data = compress_if_needed(&audio_file, &options.audio, progress).await?;
+
+    // Transcribe
+    let spinner = progress.spinner(format!(
+        "Transcribing audio with {} (language: {})...",
+        client.models().transcribe,
+        language_label(language)
+    ));
+
+    let transcript = with_retry_hook(
+        retry_message(&spinner, "Transcribing audio"),
+        transcribe_audio(
+            client,
+            audio_data,
+            &format!("{}.mp3", video_name),
+            duration,
+            options,
+        ),
+    )
+    .await?;
+    save_segments(&segments_file, &transcript, options.needs_segments())?;
+
+    progress.finish(spinner, format!("{} Audio transcribed", CHECK));
+
+    Ok(transcript)
+}
+
+/// Transcribed text, plus segment timestamps when chapters or speakers are requested
+#[derive(Debug, Clone, Default)]
+struct Transcript {
+    text: String,
+    segments: Vec<TranscriptSegment>,
+    /// Seconds of audio sent for transcription in this run (0 when cached)
+    audio_seconds: f64,
+}
+
+/// Transcribe audio with retries, asking for segments only when needed
+async fn transcribe_audio(
+    client: &Arc<dyn AiClient>,
+    audio_data: Vec<u8>,
+    filename: &str,
+    audio_seconds: f64,
+    options: &VideoOptions,
+) -> Result<Transcript> {
+    let language = options.language.as_deref();
+    let prompt = options.transcription_prompt.as_deref();
+
+    if !options.needs_segments() {
+        let response = client
+            .transcribe(audio_data, filename, language, prompt)
+            .await?;
+        return Ok(Transcript {
+            audio_seconds: response.audio_seconds().unwrap_or(audio_seconds),
+            text: response.text,
+            segments: Vec::new(),
+        });
+    }
+
+    let verbose = TranscriptionOptions {
+        language,
+        prompt,
+        ..TranscriptionOptions::default()
+    };
+    let response = client
+        .transcribe_verbose(audio_data, filename, &verbose)
+        .await?;
+    if response.segments.is_empty() {
+        anyhow::bail!(
+            "{} returned no segment timestamps; --chapters and --speakers need a model with verbose_json support such as whisper-1",
+            client.models().transcribe
+        );
+    }
+
+    Ok(Transcript {
+        audio_seconds: response.audio_seconds().unwrap_or(audio_seconds),
+        text: response.text,
+        segments: response.segments,
+    })
+}
+
+/// Load a cached transcript; with `need_segments` its segments must be cached too
+fn load_cached_transcript(
+    transcript_file: &Path,
+    segments_file: &Path,
+    need_segments: bool,
+) -> Result<Option<Transcript>> {
+    if !transcript_file.exists() || (need_segments && !segments_file.exists()) {
+        return Ok(None);
+    }
+
+    let text = fs::read_to_string(transcript_file).context("Failed to read cached transcript")?;
+    let segments = if need_segments {
+        serde_json::from_str(&fs::read_to_string(segments_file)?)
+            .context("Failed to read cached segments")?
+    } else {
+        Vec::new()
+    };
+
+    Ok(Some(Transcript {
+        text,
+        segments,
+        audio_seconds: 0.0,
+    }))
+}
+
+fn save_segments(segments_file: &Path, transcript: &Transcript, need_segments: bool) -> Result<()> {
+    if need_segments {
+        fs::write(segments_file, serde_json::to_string(&transcript.segments)?)?;
+    }
+    Ok(())
+}
+
+async fn process_long_video(
+    client: &Arc<dyn AiClient>,
+    video_path: &Path,
+    video_name: &str,
+    output_dir: &Path,
+    options: &VideoOptions,
+    state: &StateFile,
+    progress: &VideoProgress,
+) -> Result<Transcript> {
+    let chunks = &state.spans();
+    let num_chunks = chunks.len() as u32;
+    progress.println(format!(
+        "   Will create {} chunks, transcribing up to {} at a time",
+        style(num_chunks).cyan().bold(),
+        style(options.max_concurrent).cyan()
+    ));
+
+    let transcribe_limit = Arc::new(Semaphore::new(options.max_concurrent));
+    let extraction_limit = Arc::new(Semaphore::new(MAX_CONCURRENT_EXTRACTIONS));
+
+    let (tx, mut rx) = mpsc::channel(num_chunks as usize);
+
+    // Chunk bars are grouped under the video's progress
+    let overall_progress = progress.multi.add(ProgressBar::new(num_chunks as u64));
+    overall_progress.set_style(
+        ProgressStyle::default_bar()
+            .template("{spinner:.green} {prefix}[{bar:40.cyan/blue}] {pos}/{len} chunks processed")
+            .unwrap()
+            .progress_chars("#>-"),
+    );
+    overall_progress.set_prefix(progress.prefix.clone());
+    overall_progress.set_message("Processing chunks");
+
+    // Process chunks concurrently
+    let mut handles = Vec::new();
+
+    for &span in chunks {
+        let i = span.index;
+        let tx = tx.clone();
+        let client = client.clone();
+        let video_path = video_path.to_path_buf();
+        let video_name = video_name.to_string();
+        let output_dir = output_dir.to_path_buf();
+        let options = options.clone();
+        let state = state.clone();
+        let progress = progress.clone();
+        let transcribe_limit = Arc::clone(&transcribe_limit);
+        let extraction_limit = Arc::clone(&extraction_limit);
+        let chunk_progress = progress.multi.add(ProgressBar::new_spinner());
+        chunk_progress.set_style(
+            ProgressStyle::default_spinner()
+                .template("    {spinner:.green} {prefix}Chunk {msg}")
+                .unwrap(),
+        );
+        chunk_progress.set_prefix(progress.prefix.clone());
+
+        let handle = task::spawn(async move {
+            chunk_progress.set_message(format!("{}/{}: Waiting...", i + 1, num_chunks));
+            chunk_progress.enable_steady_tick(Duration::from_millis(100));
+
+            let permit = tokio::select! {
+                permit = transcribe_limit.acquire() => permit,
+                _ = options.cancel.cancelled() => {
+                    chunk_progress.finish_and_clear();
+                    let _ = tx.send((i, Err(OpenAIError::Cancelled.into()))).await;
+                    return;
+                }
+            };
+            let Ok(_permit) = permit else {
+                chunk_progress.finish_and_clear();
+                return;
+            };
+
+            let result = process_chunk(
+                &video_path,
+                &video_name,
+                span,
+                num_chunks,
+                &output_dir,
+                &options,
+                &client,
+                &state,
+                &extraction_limit,
+                &chunk_progress,
+                &progress,
+            )
+            .await;
+
+            chunk_progress.finish_and_clear();
+            let _ = tx.send((i, result)).await;
+        });
+
+        handles.push(handle);
+    }
+
+    // Drop the original sender
+    drop(tx);
+
+    // Collect results; a failed chunk doesn't stop the others
+    let mut transcripts = BTreeMap::new();
+    let mut segments = Vec::new();
+    let mut audio_seconds = 0.0;
+    let mut failures = Vec::new();
+    let mut cached = 0;
+    while let Some((index, result)) = rx.recv().await {
+        if ui::is_plain() {
+            let outcome = match &result {
+                Ok(chunk) if chunk.cached => "cached".to_string(),
+                Ok(_) => "transcribed".to_string(),
+                Err(e) => format!("failed: {:#}", e),
+            };
+            progress.println(format!("Chunk {}/{} {}", index + 1, num_chunks, outcome));
+        }
+        match result {
+            Ok(chunk) => {
+                if chunk.cached {
+                    cached += 1;
+                }
+                audio_seconds += chunk.transcript.audio_seconds;
+                // Chunk timestamps start at zero; shift them to the video timeline
+                let offset = chunks[index as usize].start;
+                segments.extend(offset_segments(chunk.transcript.segments, offset));
+                transcripts.insert(index, chunk.transcript.text);
+            }
+            Err(e) => failures.push((index, e)),
+        }
+        overall_progress.inc(1);
+    }
+
+    // Wait for all tasks
+    for handle in handles {
+        handle.await?;
+    }
+
+    overall_progress.finish_and_clear();
+    progress.println(format!(
+        "{} Chunks: {} transcribed, {} cached, {} failed",
+        if failures.is_empty() { CHECK } else { WARNING },
+        transcripts.len() - cached,
+        cached,
+        failures.len()
+    ));
+
+    if !failures.is_empty() {
+        failures.sort_by_key(|(index, _)| *index);
+        for (index, e) in &failures {
+            let span = chunks[*index as usize];
+            progress.println(format!(
+                "   {} chunk {} ({}–{}): {:#}",
+                style("✗").red(),
+                index + 1,
+                format_timestamp(span.start),
+                format_timestamp(span.end()),
+                e
+            ));
+        }
+
+        if !options.allow_gaps {
+            let missing = failures
+                .iter()
+                .map(|(index, _)| {
+                    let stem = chunk_stem(video_name, &chunks[*index as usize]);
+                    format!(
+                        "  {}",
+                        output_dir
+                            .join(format!("{}_transcript.txt", stem))
+                            .display()
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join("\n");
+            anyhow::bail!(
+                "{} of {} chunks failed; rerun to retry only the missing chunks \
+                 or pass --allow-gaps to continue without them:\n{}",
+                failures.len(),
+                num_chunks,
+                missing
+            );
+        }
+    }
+
+    let full_transcript = assemble_transcript(chunks, &transcripts);
+    segments.sort_by(|a, b| a.start.total_cmp(&b.start));
+
+    progress.println(format!(
+        "{} All chunks merged into complete transcript",
+        CHECK
+    ));
+    Ok(Transcript {
+        text: full_transcript,
+        segments,
+        audio_seconds,
+    })
+}
+
+/// Transcript of a processed chunk and whether it came from the cache
+struct ChunkTranscript {
+    transcript: Transcript,
+    cached: bool,
+}
+
+/// Shift segment timestamps by a chunk's start offset
+fn offset_segments(segments: Vec<TranscriptSegment>, offset: u32) -> Vec<TranscriptSegment> {
+    let offset = offset as f64;
+    segments
+        .into_iter()
+        .map(|segment| TranscriptSegment {
+            start: segment.start + offset,
+            end: segment.end + offset,
+            ..segment
+        })
+        .collect()
+}
+
+/// Join chunk transcripts in order, marking failed chunks with their time range
+fn assemble_transcript(chunks: &[ChunkSpan], transcripts: &BTreeMap<u32, String>) -> String {
+    chunks
+        .iter()
+        .map(|span| match transcripts.get(&span.index) {
+            Some(text) => text.clone(),
+            None => format!(
+                "[MISSING {}–{}]",
+                format_timestamp(span.start),
+                format_timestamp(span.end())
+            ),
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Format seconds as m:ss, or h:mm:ss for an hour and more
+fn format_timestamp(seconds: u32) -> String {
+    let (hours, minutes, secs) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+    if hours > 0 {
+        format!("{}:{:02}:{:02}", hours, minutes, secs)
+    } else {
+        format!("{}:{:02}", minutes, secs)
+    }
+}
+
+/// Base name of a chunk's cached audio and transcript files
+///
+/// Boundaries are part of the name so fixed and silence-based chunks never share a cache.
+fn chunk_stem(video_name: &str, span: &ChunkSpan) -> String {
+    format!("{}_chunk_{}_{}s", video_name, span.index, span.start)
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn process_chunk(
+    video_path: &Path,
+    video_name: &str,
+    span: ChunkSpan,
+    num_chunks: u32,
+    output_dir: &Path,
+    options: &VideoOptions,
+    client: &Arc<dyn AiClient>,
+    state: &StateFile,
+    extraction_limit: &Semaphore,
+    chunk_progress: &ProgressBar,
+    progress: &VideoProgress,
+) -> Result<ChunkTranscript> {
+    let chunk_index = span.index;
+    let language = options.language.as_deref();
+    let use_cache = options.use_cache;
+
+    chunk_progress.set_message(format!(
+        "{}/{}: Processing ({}-{}s)",
+        chunk_index + 1,
+        num_chunks,
+        span.start,
+        span.end()
+    ));
+
+    let chunk_stem = chunk_stem(video_name, &span);
+    let chunk_audio_file = output_dir.join(format!("{}.mp3", chunk_stem));
+    let chunk_transcript_file = output_dir.join(format!("{}_transcript.txt", chunk_stem));
+    let chunk_segments_file = output_dir.join(format!("{}_segments.json", chunk_stem));
+
+    // Check cache
+    if use_cache
+        && let Some(transcript) = load_cached_transcript(
+            &chunk_transcript_file,
+            &chunk_segments_file,
+            options.needs_segments(),
+        )?
+    {
+        chunk_progress.set_message(format!(
+            "{}/{}: Using cached transcript",
+            chunk_index + 1,
+            num_chunks
+        ));
+        state.update(|state| {
+            let chunk = &mut state.chunks[chunk_index as usize];
+            chunk.extracted = true;
+            chunk.transcribed = true;
+        })?;
+        return Ok(ChunkTranscript {
+            transcript,
+            cached: true,
+        });
+    }
+
+    // Extract audio chunk if not exists
+    if !use_cache || !chunk_audio_file.exists() {
+        chunk_progress.set_message(format!(
+            "{}/{}: Extracting audio",
+            chunk_index + 1,
+            num_chunks
+        ));
+        let _permit = extraction_limit
+            .acquire()
+            .await
+            .context("Chunk processing was cancelled")?;
+        let bar = progress.bar(
+            format!("Chunk {}/{}: extracting audio", chunk_index + 1, num_chunks),
+            span.duration,
+        );
+        let extracted = extract_audio(
+            video_path,
+            &chunk_audio_file,
+            Some(span.start),
+            Some(span.duration),
+            &options.audio,
+            &bar,
+        )
+        .await;
+        bar.finish_and_clear();
+        extracted?;
+    }
+    state.update(|state| state.chunks[chunk_index as usize].extracted = true)?;
+
+    // Compress if needed and transcribe
+    chunk_progress.set_message(format!(
+        "{}/{}: Transcribing (language: {})",
+        chunk_index + 1,
+        num_chunks,
+        language_label(language)
+    ));
+    let audio_data = compress_if_needed(&chunk_audio_file, &options.audio, progress).await?;
+    // Concurrent chunks wait while the rate limit is nearly used up
+    client.acquire_permit().await;
+    let chunk_progress_on_retry = chunk_progress.clone();
+    let transcript = with_retry_hook(
+        move |event: &RetryEvent| {
+            chunk_progress_on_retry.set_message(format!(
+                "{}/{}: retrying ({}/{}) in {}s",
+                chunk_index + 1,
+                num_chunks,
+                event.attempt,
+                event.max_attempts - 1,
+                event.delay.as_secs()
+            ))
+        },
+        transcribe_audio(
+            client,
+            audio_data,
+            &format!("{}.mp3", chunk_stem),
+            span.duration as f64,
+            options,
+        ),
+    )
+    .await?;
+
+    // Save chunk transcript
+    fs::write(&chunk_transcript_file, &transcript.text)?;
+    save_segments(&chunk_segments_file, &transcript, options.needs_segments())?;
+    state.update(|state| state.chunks[chunk_index as usize].transcribed = true)?;
+    chunk_progress.set_message(format!("{}/{}: Completed", chunk_index + 1, num_chunks));
+
+    Ok(ChunkTranscript {
+        transcript,
+        cached: false,
+    })
+}
+
+/// Progress of one video, saved as `<stem>_<path hash>.state.json` so interrupted runs
+/// can resume with the same chunk plan
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct RunState {
+    /// Artifact prefix, which embeds the video's content fingerprint
+    cache_name: String,
+    duration: f64,
+    split_on_silence: bool,
+    /// Empty for videos short enough to transcribe in one request
+    #[serde(default)]
+    chunks: Vec<ChunkState>,
+    #[serde(default)]
+    content_generated: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+struct ChunkState {
+    start: u32,
+    duration: u32,
+    #[serde(default)]
+    extracted: bool,
+    #[serde(default)]
+    transcribed: bool,
+}
+
+impl RunState {
+    fn new(cache_name: &str, duration: f64, split_on_silence: bool, spans: &[ChunkSpan]) -> Self {
+        Self {
+            cache_name: cache_name.to_string(),
+            duration,
+            split_on_silence,
+            chunks: spans
+                .iter()
+                .map(|span| ChunkState {
+                    start: span.start,
+                    duration: span.duration,
+                    extracted: false,
+                    transcribed: false,
+                })
+                .collect(),
+            content_generated: false,
+        }
+    }
+
+    fn spans(&self) -> Vec<ChunkSpan> {
+        self.chunks
+            .iter()
+            .enumerate()
+            .map(|(index, chunk)| ChunkSpan {
+                index: index as u32,
+                start: chunk.start,
+                duration: chunk.duration,
+            })
+            .collect()
+    }
+
+    fn resume_summary(&self) -> String {
+        if self.content_generated {
+            return "Resuming: content already generated".to_string();
+        }
+        if self.chunks.is_empty() {
+            return "Resuming: reusing the saved duration".to_string();
+        }
+        let done = self.chunks.iter().filter(|c| c.transcribed).count();
+        format!(
+            "Resuming: {}/{} chunks already done",
+            done,
+            self.chunks.len()
+        )
+    }
+}
+
+/// Manifest path: `<stem>_<path hash>.state.json` in the output directory
+fn state_file_path(output_dir: &Path, video_path: &Path) -> Result<PathBuf> {
+    Ok(output_dir.join(format!("{}.state.json", video_key(video_path)?)))
+}
+
+/// Saved run state; a missing or unreadable manifest means starting over
+fn load_state(path: &Path) -> Option<RunState> {
+    let content = fs::read_to_string(path).ok()?;
+    serde_json::from_str(&content).ok()
+}
+
+/// The saved state, if it belongs to this exact video and chunking mode
+fn resumable_state(
+    saved: Option<RunState>,
+    cache_name: &str,
+    split_on_silence: bool,
+) -> Option<RunState> {
+    saved.filter(|state| {
+        state.cache_name == cache_name && state.split_on_silence == split_on_silence
+    })
+}
+
+/// Probe the duration and choose chunk boundaries for a new run
+fn plan_run(
+    video_file: &Path,
+    cache_name: &str,
+    options: &VideoOptions,
+    progress: &VideoProgress,
+) -> Result<RunState> {
+    let spinner = progress.spinner("Analyzing video duration...");
+    let duration = get_video_duration(video_file)?;
+    progress.finish(
+        spinner,
+        format!(
+            "Video duration: {} seconds",
+            style(format!("{:.1}", duration)).cyan()
+        ),
+    );
+
+    let chunks = if duration <= CHUNK_SECONDS as f64 {
+        Vec::new()
+    } else if options.split_on_silence {
+        silence_chunks(video_file, duration, progress)?
+    } else {
+        fixed_chunk_boundaries(duration)
+    };
+
+    Ok(RunState::new(
+        cache_name,
+        duration,
+        options.split_on_silence,
+        &chunks,
+    ))
+}
+
+/// Shared handle to the run state that rewrites the manifest on every update
+#[derive(Clone)]
+struct StateFile {
+    path: PathBuf,
+    state: Arc<Mutex<RunState>>,
+}
+
+impl StateFile {
+    fn create(path: PathBuf, state: RunState) -> Result<Self> {
+        let file = Self {
+            path,
+            state: Arc::new(Mutex::new(state)),
+        };
+        file.update(|_| {})?;
+        Ok(file)
+    }
+
+    fn spans(&self) -> Vec<ChunkSpan> {
+        self.state.lock().unwrap().spans()
+    }
+
+    fn content_generated(&self) -> bool {
+        self.state.lock().unwrap().content_generated
+    }
+
+    fn update(&self, change: impl FnOnce(&mut RunState)) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        change(&mut state);
+        save_state(&self.path, &state)
+    }
+}
+
+/// Write the manifest through a temporary file so a crash never leaves it half-written
+fn save_state(path: &Path, state: &RunState) -> Result<()> {
+    let tmp = path.with_extension("json.tmp");
+    fs::write(&tmp, serde_json::to_string_pretty(state)?)?;
+    fs::rename(&tmp, path).with_context(|| format!("Failed to write run state: {}", path.display()))
+}
+
+/// Content saved by an earlier run, if it is still readable
+fn load_cached_content(content_file: &Path) -> Option<ContentResponse> {
+    let content = fs::read_to_string(content_file).ok()?;
+    serde_json::from_str(&content).ok()
+}
+
+/// Whether cached content has exactly the sections of `profile` (none without one)
+fn content_matches_profile(content: &ContentResponse, profile: Option<&ContentProfile>) -> bool {
+    let keys: Vec<&str> = profile
+        .map(|p| p.sections.iter().map(|s| s.key.as_str()).collect())
+        .unwrap_or_default();
+    content.sections.iter().map(|s| s.key.as_str()).eq(keys)
+}
+
+/// A slice of the video transcribed as one request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct ChunkSpan {
+    index: u32,
+    start: u32,
+    duration: u32,
+}
+
+impl ChunkSpan {
+    fn end(&self) -> u32 {
+        self.start + self.duration
+    }
+}
+
+/// Split a video into fixed CHUNK_SECONDS slices
+fn fixed_chunk_boundaries(total_duration: f64) -> Vec<ChunkSpan> {
+    choose_chunk_boundaries(total_duration, &[])
+}
+
+/// Pick chunk boundaries at the silence nearest to each CHUNK_SECONDS target
+///
+/// Only silences between half a chunk and MAX_CHUNK_SECONDS after the current
+/// start are considered; when none qualifies the fixed boundary is used.
+/// Cuts are whole seconds; the last chunk is rounded up to cover the tail.
+fn choose_chunk_boundaries(total_duration: f64, silences: &[f64]) -> Vec<ChunkSpan> {
+    let mut cuts = Vec::new();
+    let mut start = 0;
+
+    while total_duration - start as f64 > CHUNK_SECONDS as f64 {
+        let target = (start + CHUNK_SECONDS) as f64;
+        let earliest = (start + CHUNK_SECONDS / 2) as f64;
+        let latest = ((start + MAX_CHUNK_SECONDS) as f64).min(total_duration);
+
+        let cut = silences
+            .iter()
+            .copied()
+            .filter(|&s| s > earliest && s < latest)
+            .min_by(|a, b| (a - target).abs().total_cmp(&(b - target).abs()))
+            .map(|s| s.round() as u32)
+            .unwrap_or(start + CHUNK_SECONDS);
+
+        cuts.push(cut);
+        start = cut;
+    }
+
+    let mut spans = Vec::with_capacity(cuts.len() + 1);
+    let mut start = 0;
+    let end = total_duration.ceil() as u32;
+    for (index, end) in cuts.into_iter().chain([end]).enumerate() {
+        spans.push(ChunkSpan {
+            index: index as u32,
+            start,
+            duration: end - start,
+        });
+        start = end;
+    }
+    spans
+}
+
+/// Extract silence midpoints (in seconds) from ffmpeg silencedetect output
+///
+/// silencedetect logs `silence_start: X` followed later by
+/// `silence_end: Y | silence_duration: D`; a start without a matching end
+/// (silence running into the end of the file) is ignored.
+fn parse_silence_midpoints(ffmpeg_stderr: &str) -> Vec<f64> {
+    let value_after = |line: &str, marker: &str| -> Option<f64> {
+        let rest = &line[line.find(marker)? + marker.len()..];
+        rest.split_whitespace().next()?.parse().ok()
+    };
+
+    let mut midpoints = Vec::new();
+    let mut pending_start = None;
+
+    for line in ffmpeg_stderr.lines() {
+        if let Some(start) = value_after(line, "silence_start:") {
+            pending_start = Some(start);
+        } else if let Some(end) = value_after(line, "silence_end:")
+            && let Some(start) = pending_start.take()
+        {
+            midpoints.push((start + end) / 2.0);
+        }
+    }
+
+    midpoints
+}
+
+/// Run ffmpeg's silencedetect filter over the whole video
+fn detect_silences(video_path: &Path) -> Result<Vec<f64>> {
+    let output = Command::new("ffmpeg")
+        .args(["-hide_banner", "-nostats", "-i"])
+        .arg(video_path)
+        .args(["-vn", "-af", SILENCE_FILTER, "-f", "null", "-"])
+        .output()
+        .context("Failed to run ffmpeg")?;
+
+    if !output.status.success() {
+        anyhow::bail!("ffmpeg failed to detect silences");
+    }
+
+    Ok(parse_silence_midpoints(&String::from_utf8_lossy(
+        &output.stderr,
+    )))
+}
+
+/// Chunk boundaries at detected silences, falling back to fixed boundaries
+fn silence_chunks(
+    video_path: &Path,
+    total_duration: f64,
+    progress: &VideoProgress,
+) -> Result<Vec<ChunkSpan>> {
+    let spinner = progress.spinner("Detecting silences...");
+    let silences = detect_silences(video_path)?;
+
+    if silences.is_empty() {
+        progress.finish(
+            spinner,
+            format!(
+                "{} No silences detected, falling back to fixed {}s chunks",
+                WARNING, CHUNK_SECONDS
+            ),
+        );
+        return Ok(fixed_chunk_boundaries(total_duration));
+    }
+
+    progress.finish(
+        spinner,
+        format!(
+            "{} Found {} silences to cut at",
+            CHECK,
+            style(silences.len()).cyan()
+        ),
+    );
+    Ok(choose_chunk_boundaries(total_duration, &silences))
+}
+
+/// Lines of ffmpeg stderr kept in extraction errors
+const FFMPEG_STDERR_TAIL: usize = 20;
+
+/// Extract mono 16kHz mp3 audio, advancing `bar` (in seconds) from ffmpeg's
+/// `-progress` output
+async fn extract_audio(
+    video_path: &Path,
+    output_path: &Path,
+    start_time: Option<u32>,
+    duration: Option<u32>,
+    audio: &AudioSettings,
+    bar: &ProgressBar,
+) -> Result<()> {
+    let mut cmd = tokio::process::Command::new("ffmpeg");
+    cmd.arg("-i").arg(video_path);
+
+    if let Some(start) = start_time {
+        cmd.arg("-ss").arg(start.to_string());
+    }
+
+    if let Some(dur) = duration {
+        cmd.arg("-t").arg(dur.to_string());
+    }
+
+    cmd.args(["-vn", "-acodec", "mp3"])
+        .arg("-ab")
+        .arg(format!("{}k", audio.bitrate_kbps))
+        .arg("-ar")
+        .arg(audio.sample_rate.to_string());
+    cmd.args(["-ac", "1", "-y", "-nostats", "-progress", "pipe:1"])
+        .arg(output_path);
+
+    run_ffmpeg(cmd, bar, "extract audio").await
+}
+
+/// Run an ffmpeg command that has `-progress pipe:1`, advancing `bar` (in
+/// seconds) and failing with the tail of stderr
+async fn run_ffmpeg(
+    mut cmd: tokio::process::Command,
+    bar: &ProgressBar,
+    action: &str,
+) -> Result<()> {
+    cmd.stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+
+    let mut child = cmd.spawn().context("Failed to run ffmpeg")?;
+    let stdout = child
+        .stdout
+        .take()
+        .context("Failed to capture ffmpeg output")?;
+
+    let reader_bar = bar.clone();
+    let reader = task::spawn(async move {
+        let mut lines = BufReader::new(stdout).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            if let Some(seconds) = parse_progress_seconds(&line) {
+                reader_bar.set_position(seconds);
+            }
+        }
+    });
+
+    // Collects stderr while the reader task drains stdout
+    let output = child
+        .wait_with_output()
+        .await
+        .context("Failed to run ffmpeg")?;
+    let _ = reader.await;
+
+    if !output.status.success() {
+        anyhow::bail!(
+            "ffmpeg failed to {} ({}):\n{}",
+            action,
+            output.status,
+            stderr_tail(&String::from_utf8_lossy(&output.stderr), FFMPEG_STDERR_TAIL)
+        );
+    }
+
+    Ok(())
+}
+
+/// Mux or burn `srt` into `<stem>_subtitled.<ext>` in `output_dir`, checking
+/// the result is as long as the source
+async fn embed_subtitles(
+    video_path: &Path,
+    srt: &Path,
+    output_dir: &Path,
+    mode: EmbedMode,
+    duration: f64,
+    progress: &VideoProgress,
+) -> Result<PathBuf> {
+    let output_path = output_dir.join(subtitled_file_name(video_path));
+
+    let mut cmd = tokio::process::Command::new("ffmpeg");
+    cmd.arg("-y").arg("-i").arg(video_path);
+    let bar = match mode {
+        EmbedMode::Soft => {
+            cmd.arg("-i")
+                .arg(srt)
+                .args(["-map", "0:v", "-map", "0:a?", "-map", "1", "-c", "copy"])
+                .arg("-c:s")
+                .arg(soft_subtitle_codec(&output_path));
+            progress.bar("Muxing subtitles...", duration.ceil() as u32)
+        }
+        EmbedMode::Burn => {
+            cmd.arg("-vf")
+                .arg(format!("subtitles={}", escape_filter_path(srt)))
+                .args(["-c:a", "copy"]);
+            progress.bar("Burning in subtitles...", duration.ceil() as u32)
+        }
+    };
+    cmd.args(["-nostats", "-progress", "pipe:1"])
+        .arg(&output_path);
+
+    let result = run_ffmpeg(cmd, &bar, "embed subtitles").await;
+    bar.finish_and_clear();
+    result?;
+
+    let output_duration = get_video_duration(&output_path)?;
+    if (output_duration - duration).abs() > 1.0 {
+        anyhow::bail!(
+            "Subtitled video is {:.1}s long but the source is {:.1}s: {}",
+            output_duration,
+            duration,
+            output_path.display()
+        );
+    }
+
+    Ok(output_path)
+}
+
+/// `<stem>_subtitled.<ext>`, keeping the source container
+fn subtitled_file_name(video_path: &Path) -> String {
+    let stem = video_path
+        .file_stem()
+        .map(|s| s.to_string_lossy())
+        .unwrap_or_default();
+    let ext = video_path
+        .extension()
+        .map(|e| e.to_string_lossy())
+        .unwrap_or("mp4".into());
+    format!("{}_subtitled.{}", stem, ext)
+}
+
+/// Subtitle codec the output container accepts for a muxed SRT
+fn soft_subtitle_codec(output_path: &Path) -> &'static str {
+    let ext = output_path
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    match ext.as_str() {
+        "mp4" | "m4v" | "mov" => "mov_text",
+        "webm" => "webvtt",
+        _ => "srt",
+    }
+}
+
+/// Quote a path for the `subtitles` filter: once as a filter option value,
+/// then escaped again for the filtergraph
+fn escape_filter_path(path: &Path) -> String {
+    let quoted = format!("'{}'", path.to_string_lossy().replace('\'', r"'\''"));
+    let mut escaped = String::with_capacity(quoted.len());
+    for c in quoted.chars() {
+        if matches!(c, '\\' | '\'' | '[' | ']' | ',' | ';') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// `<stem>_keyframe_<n>.jpg`, numbered from 1 like the narration files
+fn keyframe_file_name(video_name: &str, number: usize) -> String {
+    format!("{}_keyframe_{}.jpg", video_name, number)
+}
+
+/// Round seconds to milliseconds, the precision key frame times are kept at
+fn round_millis(seconds: f64) -> f64 {
+    (seconds * 1000.0).round() / 1000.0
+}
+
+/// Middles of `count` equal slices of the video
+///
+/// Slices are at least [`MIN_KEYFRAME_GAP`] long, so a video shorter than the
+/// requested count yields fewer, distinct frames instead of repeats.
+fn even_keyframe_times(duration: f64, count: usize) -> Vec<f64> {
+    let count = count
+        .min((duration / MIN_KEYFRAME_GAP + 1e-9).floor() as usize)
+        .max(1);
+    (0..count)
+        .map(|i| round_millis(duration * (i as f64 + 0.5) / count as f64))
+        .collect()
+}
+
+/// Timestamps of the frames printed by `showinfo` in ffmpeg's stderr
+fn parse_scene_times(ffmpeg_stderr: &str) -> Vec<f64> {
+    ffmpeg_stderr
+        .lines()
+        .filter(|line| line.contains("Parsed_showinfo"))
+        .filter_map(|line| {
+            let rest = &line[line.find("pts_time:")? + "pts_time:".len()..];
+            rest.split_whitespace().next()?.parse().ok()
+        })
+        .collect()
+}
+
+/// Key frame times at scene changes
+///
+/// With more scene changes than frames wanted, an evenly spread selection of
+/// them is kept; with fewer, evenly spaced times that are not right next to a
+/// scene change make up the rest.
+fn choose_scene_times(scenes: &[f64], duration: f64, count: usize) -> Vec<f64> {
+    let even = even_keyframe_times(duration, count);
+    let count = even.len();
+
+    let mut scenes: Vec<f64> = scenes
+        .iter()
+        .filter(|&&time| (0.0..duration).contains(&time))
+        .map(|&time| round_millis(time))
+        .collect();
+    scenes.sort_by(f64::total_cmp);
+    scenes.dedup();
+
+    if scenes.len() >= count {
+        return (0..count)
+            .map(|i| scenes[(2 * i + 1) * scenes.len() / (2 * count)])
+            .collect();
+    }
+
+    let min_distance = duration / count as f64 / 2.0;
+    let mut times = scenes.clone();
+    for time in even {
+        if times.len() == count {
+            break;
+        }
+        if scenes
+            .iter()
+            .all(|scene| (scene - time).abs() >= min_distance)
+        {
+            times.push(time);
+        }
+    }
+    times.sort_by(f64::total_cmp);
+    times
+}
+
+/// Run ffmpeg's scene filter over the whole video
+fn detect_scenes(video_path: &Path) -> Result<Vec<f64>> {
+    let output = Command::new("ffmpeg")
+        .args(["-hide_banner", "-nostats", "-i"])
+        .arg(video_path)
+        .args(["-an", "-vf", SCENE_FILTER, "-f", "null", "-"])
+        .output()
+        .context("Failed to run ffmpeg")?;
+
+    if !output.status.success() {
+        anyhow::bail!(
+            "ffmpeg failed to detect scene changes:\n{}",
+            stderr_tail(&String::from_utf8_lossy(&output.stderr), FFMPEG_STDERR_TAIL)
+        );
+    }
+
+    Ok(parse_scene_times(&String::from_utf8_lossy(&output.stderr)))
+}
+
+/// Key frame times at detected scene changes, see [`choose_scene_times`]
+fn scene_keyframe_times(
+    video_path: &Path,
+    duration: f64,
+    count: usize,
+    progress: &VideoProgress,
+) -> Result<Vec<f64>> {
+    let spinner = progress.spinner("Detecting scene changes...");
+    let scenes = detect_scenes(video_path)?;
+    progress.finish(
+        spinner,
+        format!(
+            "{} Found {} scene changes",
+            CHECK,
+            style(scenes.len()).cyan()
+        ),
+    );
+    Ok(choose_scene_times(&scenes, duration, count))
+}
+
+/// ffmpeg arguments saving the frame at `time` as a JPEG
+///
+/// Seeking before `-i` skips decoding everything up to the previous key frame
+/// of the stream. Rotation metadata is applied by ffmpeg itself, and
+/// [`KEYFRAME_FILTER`] keeps portrait and anamorphic video undistorted.
+fn keyframe_args(video_path: &Path, time: f64, output_path: &Path) -> Vec<OsString> {
+    let mut args: Vec<OsString> = ["-hide_banner", "-loglevel", "error", "-y", "-ss"]
+        .into_iter()
+        .map(OsString::from)
+        .collect();
+    args.push(format!("{:.3}", time).into());
+    args.push("-i".into());
+    args.push(video_path.into());
+    args.extend(
+        [
+            "-frames:v",
+            "1",
+            "-vf",
+            KEYFRAME_FILTER,
+            "-q:v",
+            "2",
+            "-update",
+            "1",
+        ]
+        .map(OsString::from),
+    );
+    args.push(output_path.into());
+    args
+}
+
+/// Save a JPEG per time as `<stem>_keyframe_<n>.jpg` in `output_dir`
+async fn extract_keyframes(
+    video_path: &Path,
+    video_name: &str,
+    output_dir: &Path,
+    times: &[f64],
+    progress: &VideoProgress,
+) -> Result<Vec<Keyframe>> {
+    let spinner = progress.spinner(format!("Extracting {} key frames...", times.len()));
+    let mut keyframes = Vec::with_capacity(times.len());
+
+    for (i, &time) in times.iter().enumerate() {
+        spinner.set_message(format!("Extracting key frames: {}/{}", i + 1, times.len()));
+        let file = keyframe_file_name(video_name, i + 1);
+        let path = output_dir.join(&file);
+        // A frame left by an earlier run must not pass for this one
+        if path.exists() {
+            fs::remove_file(&path)
+                .with_context(|| format!("Failed to remove {}", path.display()))?;
+        }
+
+        let output = tokio::process::Command::new("ffmpeg")
+            .args(keyframe_args(video_path, time, &path))
+            .stdin(Stdio::null())
+            .output()
+            .await
+            .context("Failed to run ffmpeg")?;
+
+        // Seeking past the last decodable frame succeeds without writing anything
+        if !output.status.success() || !path.is_file() {
+            progress.finish(spinner, "Key frame extraction failed");
+            anyhow::bail!(
+                "ffmpeg failed to extract the frame at {:.3}s ({}):\n{}",
+                time,
+                output.status,
+                stderr_tail(&String::from_utf8_lossy(&output.stderr), FFMPEG_STDERR_TAIL)
+            );
+        }
+
+        keyframes.push(Keyframe {
+            file,
+            time,
+            caption: None,
+        });
+    }
+
+    progress.finish(
+        spinner,
+        format!(
+            "{} {} key frames saved to: {}",
+            CHECK,
+            keyframes.len(),
+            style(output_dir.display()).dim()
+        ),
+    );
+    Ok(keyframes)
+}
+
+/// Keep captions of an earlier run for frames taken from the same time
+fn reuse_captions(keyframes: &mut [Keyframe], previous: &[Keyframe]) {
+    for keyframe in keyframes {
+        keyframe.caption = previous
+            .iter()
+            .find(|p| p.file == keyframe.file && p.time == keyframe.time)
+            .and_then(|p| p.caption.clone());
+    }
+}
+
+/// Prompt sent with each key frame, asking for a caption in the output language
+fn keyframe_caption_prompt(language: Option<&str>) -> String {
+    match language {
+        None | Some("zh") => {
+            "用一句简短的中文描述这张视频截图（不超过20个字），适合作为缩略图说明。只输出这句话。"
+                .to_string()
+        }
+        Some(code) => format!(
+            "Describe this video frame in one short line (at most 15 words) suitable as a \
+             thumbnail caption, written in the language with ISO-639-1 code \"{}\". \
+             Reply with the caption only.",
+            code
+        ),
+    }
+}
+
+/// First line of the model's reply without surrounding quotes
+fn clean_caption(reply: &str) -> Option<String> {
+    let line = reply.lines().map(str::trim).find(|line| !line.is_empty())?;
+    let caption = line
+        .trim_matches(|c| matches!(c, '"' | '\'' | '“' | '”' | '「' | '」'))
+        .trim();
+    (!caption.is_empty()).then(|| caption.to_string())
+}
+
+/// Caption key frames without one using the chat model's image input; frames
+/// that still fail after retries are reported and left uncaptioned
+async fn caption_keyframes(
+    client: &Arc<dyn AiClient>,
+    output_dir: &Path,
+    keyframes: &mut [Keyframe],
+    options: &VideoOptions,
+    progress: &VideoProgress,
+) -> Result<TokenUsage> {
+    let prompt = keyframe_caption_prompt(options.language.as_deref());
+    let total = keyframes.len();
+    let spinner = progress.spinner(format!(
+        "Captioning {} key frames with {}...",
+        total,
+        client.models().chat
+    ));
+
+    let mut usage = TokenUsage::default();
+    let mut failed = Vec::new();
+    let mut cached = 0;
+    for (i, keyframe) in keyframes.iter_mut().enumerate() {
+        if keyframe.caption.is_some() {
+            cached += 1;
+            continue;
+        }
+        spinner.set_message(format!("Captioning key frames: {}/{}", i + 1, total));
+
+        let path = output_dir.join(&keyframe.file);
+        let image =
+            fs::read(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+        let result = with_retry_hook(
+            retry_message(
+                &spinner,
+                format!("Captioning key frame {}/{}", i + 1, total),
+            ),
+            client.chat_with_image(&prompt, &image, "image/jpeg"),
+        )
+        .await;
+        match result {
+            Ok(reply) => {
+                usage += reply.usage;
+                match clean_caption(&reply.value) {
+                    Some(caption) => keyframe.caption = Some(caption),
+                    None => failed.push(format!("{}: empty caption", keyframe.file)),
+                }
+            }
+            Err(e) => failed.push(format!("{}: {:#}", keyframe.file, e)),
+        }
+    }
+
+    let captioned = total - failed.len();
+    let cached = if cached > 0 {
+        format!(" ({} cached)", cached)
+    } else {
+        String::new()
+    };
+    let message = if failed.is_empty() {
+        format!("{} {} key frames captioned{}", CHECK, captioned, cached)
+    } else {
+        format!(
+            "{}{} of {} key frames captioned{}",
+            WARNING, captioned, total, cached
+        )
+    };
+    progress.finish(spinner, message);
+    for failure in &failed {
+        progress.println(format!("  {} {}", style("✗").red(), failure));
+    }
+
+    Ok(usage)
+}
+
+/// Seconds of media processed, from an `out_time_ms=`/`out_time_us=` line of
+/// `ffmpeg -progress` (both are microseconds despite the name)
+fn parse_progress_seconds(line: &str) -> Option<u64> {
+    let (key, value) = line.trim().split_once('=')?;
+    if key != "out_time_ms" && key != "out_time_us" {
+        return None;
+    }
+    let micros: i64 = value.parse().ok()?;
+    u64::try_from(micros).ok().map(|us| us / 1_000_000)
+}
+
+/// The last `lines` non-empty lines of ffmpeg's stderr
+fn stderr_tail(stderr: &str, lines: usize) -> String {
+    let kept: Vec<&str> = stderr.lines().filter(|l| !l.trim().is_empty()).collect();
+    kept[kept.len().saturating_sub(lines)..].join("\n")
+}
+
+/// Bitrates (kbps) tried in turn when audio is over the API size limit
+const COMPRESSION_BITRATES: [u32; 3] = [32, 24, 16];
+
+/// Next compression bitrate below `current`, if any is left
+fn next_compression_bitrate(current: u32) -> Option<u32> {
+    COMPRESSION_BITRATES
+        .into_iter()
+        .find(|&kbps| kbps < current)
+}
+
+fn format_mb(bytes: u64) -> String {
+    format!("{:.1}MB", bytes as f64 / 1024.0 / 1024.0)
+}
+
+/// Read the audio for upload, re-encoding at decreasing bitrates while it
+/// exceeds the API size limit
+async fn compress_if_needed(
+    audio_file: &Path,
+    audio: &AudioSettings,
+    progress: &VideoProgress,
+) -> Result<Vec<u8>> {
+    let limit = audio.size_limit_mb * 1024 * 1024;
+    let original_size = fs::metadata(audio_file)?.len();
+
+    if original_size <= limit {
+        return fs::read(audio_file).context("Failed to read audio file");
+    }
+
+    let compressed_path = audio_file.with_extension("compressed.mp3");
+    let mut bitrate = audio.bitrate_kbps;
+    let mut size = original_size;
+
+    while let Some(next) = next_compression_bitrate(bitrate) {
+        bitrate = next;
+        let spinner = progress.spinner(format!(
+            "Compressing large file ({}) at {}k...",
+            format_mb(size),
+            bitrate
+        ));
+
+        // Always re-encode from the original to avoid stacking quality loss
+        let output = Command::new("ffmpeg")
+            .arg("-i")
+            .arg(audio_file)
+            .args(["-acodec", "mp3", "-ab"])
+            .arg(format!("{}k", bitrate))
+            .arg("-ar")
+            .arg(audio.sample_rate.to_string())
+            .args(["-ac", "1", "-y"])
+            .arg(&compressed_path)
+            .output()?;
+
+        if !output.status.success() {
+            progress.finish(spinner, "Compression failed");
+            anyhow::bail!(
+                "Failed to compress audio:\n{}",
+                stderr_tail(&String::from_utf8_lossy(&output.stderr), FFMPEG_STDERR_TAIL)
+            );
+        }
+
+        size = fs::metadata(&compressed_path)?.len();
+        progress.finish(
+            spinner,
+            format!(
+                "Compressed {} → {} at {}k",
+                format_mb(original_size),
+                format_mb(size),
+                bitrate
+            ),
+        );
+
+        if size <= limit {
+            let data = fs::read(&compressed_path)?;
+            fs::remove_file(&compressed_path)?;
+            return Ok(data);
+        }
+    }
+
+    let _ = fs::remove_file(&compressed_path);
+    anyhow::bail!(
+        "Audio is still {} after compressing to {}k, over the {}MB API limit",
+        format_mb(size),
+        bitrate,
+        audio.size_limit_mb
+    )
+}
+
+/// API consumption of one video
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct ApiUsage {
+    audio_seconds: f64,
+    chat: TokenUsage,
+}
+
+/// Price of one model in USD; chat models bill tokens, transcription models minutes
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ModelPrice {
+    #[serde(default)]
+    input_per_million: f64,
+    #[serde(default)]
+    output_per_million: f64,
+    #[serde(default)]
+    per_minute: f64,
+}
+
+/// Per-model prices used for cost estimates
+#[derive(Debug, Clone, PartialEq)]
+struct PriceTable {
+    models: HashMap<String, ModelPrice>,
+}
+
+/// `--price-config` file layout
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PriceConfig {
+    #[serde(default)]
+    models: HashMap<String, ModelPrice>,
+}
+
+impl Default for PriceTable {
+    /// OpenAI list prices (USD) at the time of writing
+    fn default() -> Self {
+        let chat = |input, output| ModelPrice {
+            input_per_million: input,
+            output_per_million: output,
+            per_minute: 0.0,
+        };
+        let audio = |per_minute| ModelPrice {
+            per_minute,
+            ..Default::default()
+        };
+
+        let models = [
+            ("gpt-4o-transcribe", audio(0.006)),
+            ("gpt-4o-mini-transcribe", audio(0.003)),
+            ("whisper-1", audio(0.006)),
+            ("gpt-5", chat(1.25, 10.0)),
+            ("gpt-5-mini", chat(0.25, 2.0)),
+            ("gpt-5-nano", chat(0.05, 0.4)),
+            ("gpt-4.1", chat(2.0, 8.0)),
+            ("gpt-4.1-mini", chat(0.4, 1.6)),
+            ("gpt-4o", chat(2.5, 10.0)),
+            ("gpt-4o-mini", chat(0.15, 0.6)),
+        ];
+        Self {
+            models: models
+                .into_iter()
+                .map(|(name, price)| (name.to_string(), price))
+                .collect(),
+        }
+    }
+}
+
+impl PriceTable {
+    /// Built-in prices with the models of a TOML price config replaced or added
+    fn load(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read price config: {}", path.display()))?;
+        Self::default().with_overrides(&content)
+    }
+
+    fn with_overrides(mut self, toml_config: &str) -> Result<Self> {
+        let config: PriceConfig =
+            toml::from_str(toml_config).context("Failed to parse price config")?;
+        self.models.extend(config.models);
+        Ok(self)
+    }
+
+    /// Price of a model, matching dated snapshots (`gpt-5-mini-2025-08-07`)
+    /// by the longest known prefix
+    fn price(&self, model: &str) -> Option<&ModelPrice> {
+        self.models.get(model).or_else(|| {
+            self.models
+                .iter()
+                .filter(|(name, _)| model.starts_with(&format!("{}-", name)))
+                .max_by_key(|(name, _)| name.len())
+                .map(|(_, price)| price)
+        })
+    }
+
+    /// Estimated cost in USD, or `None` when a used model has no known price
+    fn estimate(&self, models: &ModelConfig, usage: &ApiUsage) -> Option<f64> {
+        let mut cost = 0.0;
+        if usage.audio_seconds > 0.0 {
+            cost += self.price(&models.transcribe)?.per_minute * usage.audio_seconds / 60.0;
+        }
+        if usage.chat != TokenUsage::default() {
+            let price = self.price(&models.chat)?;
+            cost += price.input_per_million * usage.chat.prompt_tokens as f64 / 1e6
+                + price.output_per_million * usage.chat.completion_tokens as f64 / 1e6;
+        }
+        Some(cost)
+    }
+}
+
+/// Usage and estimated cost of one video, saved as `<stem>_usage.json`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+struct UsageReport {
+    transcribe_model: String,
+    chat_model: String,
+    audio_seconds: f64,
+    prompt_tokens: u64,
+    completion_tokens: u64,
+    total_tokens: u64,
+    estimated_cost_usd: Option<f64>,
+}
+
+impl UsageReport {
+    fn new(usage: ApiUsage, models: &ModelConfig, prices: &PriceTable) -> Self {
+        Self {
+            transcribe_model: models.transcribe.clone(),
+            chat_model: models.chat.clone(),
+            audio_seconds: usage.audio_seconds,
+            prompt_tokens: usage.chat.prompt_tokens,
+            completion_tokens: usage.chat.completion_tokens,
+            total_tokens: usage.chat.total_tokens,
+            estimated_cost_usd: prices.estimate(models, &usage),
+        }
+    }
+
+    fn summary(&self) -> String {
+        let cost = match self.estimated_cost_usd {
+            Some(cost) => format!("≈ {}", format_cost(cost)),
+            None => "cost unknown (no price for a model; see --price-config)".to_string(),
+        };
+        format!(
+            "Usage: {:.1} min audio, {} prompt + {} completion tokens, {}",
+            self.audio_seconds / 60.0,
+            self.prompt_tokens,
+            self.completion_tokens,
+            cost
+        )
+    }
+}
+
+fn format_cost(usd: f64) -> String {
+    format!("${:.4}", usd)
+}
+
+/// Retry hook showing `<what>... retrying (n/N) in Ns` on `spinner`
+fn retry_message(
+    spinner: &ProgressBar,
+    what: impl Into<String>,
+) -> impl Fn(&RetryEvent) + Send + Sync + 'static {
+    let spinner = spinner.clone();
+    let what = what.into();
+    move |event| {
+        spinner.set_message(format!(
+            "{}... retrying ({}/{}) in {}s",
+            what,
+            event.attempt,
+            event.max_attempts - 1,
+            event.delay.as_secs()
+        ))
+    }
+}
+
+/// Translate a transcript window by window with the chat model
+///
+/// A window that still fails after retries is kept in the original language
+/// behind a `[TRANSLATION FAILED]` marker so the rest of the translation survives.
+async fn translate_transcript(
+    client: &Arc<dyn AiClient>,
+    transcript: &str,
+    target: &str,
+    progress: &VideoProgress,
+) -> Result<WithUsage<String>> {
+    let windows = split_into_windows(transcript, TRANSLATION_WINDOW_TOKENS);
+    let system = translation_prompt(target);
+    let spinner = progress.spinner(format!(
+        "Translating transcript to {} ({} segments)...",
+        target,
+        windows.len()
+    ));
+
+    let mut outputs = Vec::with_capacity(windows.len());
+    let mut usage = TokenUsage::default();
+    let mut failed = 0;
+    for (i, window) in windows.iter().enumerate() {
+        spinner.set_message(format!(
+            "Translating transcript to {}: segment {}/{}",
+            target,
+            i + 1,
+            windows.len()
+        ));
+
+        let result = with_retry_hook(
+            retry_message(
+                &spinner,
+                format!("Translating segment {}/{}", i + 1, windows.len()),
+            ),
+            client.chat_text(&system, window.text.clone()),
+        )
+        .await;
+
+        match result {
+            Ok(translated) => {
+                usage += translated.usage;
+                outputs.push(translated.value);
+            }
+            Err(e) => {
+                failed += 1;
+                outputs.push(format!("[TRANSLATION FAILED: {:#}]\n{}", e, window.text));
+            }
+        }
+    }
+
+    let message = if failed == 0 {
+        format!("{} Transcript translated to {}", CHECK, target)
+    } else {
+        format!(
+            "{} Transcript translated to {} ({} of {} segments failed and are marked)",
+            WARNING,
+            target,
+            failed,
+            windows.len()
+        )
+    };
+    progress.finish(spinner, message);
+
+    Ok(WithUsage {
+        value: merge_windows(&windows, &outputs),
+        usage,
+    })
+}
+
+/// File names and texts of the titles and status updates read aloud by --tts
+fn narration_items<'a>(video_name: &str, content: &'a ContentResponse) -> Vec<(String, &'a str)> {
+    let titles = content
+        .titles
+        .iter()
+        .enumerate()
+        .map(|(i, title)| (format!("{}_title_{}.mp3", video_name, i + 1), title));
+    let status_updates = content
+        .status_updates
+        .iter()
+        .enumerate()
+        .map(|(i, status)| (format!("{}_status_{}.mp3", video_name, i + 1), status));
+    titles
+        .chain(status_updates)
+        .map(|(name, text)| (name, text.trim()))
+        .filter(|(_, text)| !text.is_empty())
+        .collect()
+}
+
+/// Render titles and status updates to mp3 with the speech model; failed
+/// items are reported and skipped
+async fn narrate_content(
+    client: &Arc<dyn AiClient>,
+    video_name: &str,
+    output_dir: &Path,
+    content: &ContentResponse,
+    voice: &str,
+    options: &VideoOptions,
+    progress: &VideoProgress,
+) -> Result<()> {
+    let items = narration_items(video_name, content);
+    if items.is_empty() {
+        progress.println(format!(
+            "{}No titles or status updates to read aloud (--tts)",
+            WARNING
+        ));
+        return Ok(());
+    }
+
+    let spinner = progress.spinner(format!(
+        "Reading {} items aloud with {}...",
+        items.len(),
+        client.models().speech
+    ));
+    let mut failed = Vec::new();
+    let mut cached = 0;
+    for (i, (name, text)) in items.iter().enumerate() {
+        let file = output_dir.join(name);
+        if options.use_cache && file.is_file() {
+            cached += 1;
+            continue;
+        }
+        spinner.set_message(format!("Reading aloud: {}/{}", i + 1, items.len()));
+
+        let result = with_retry_hook(
+            retry_message(&spinner, format!("Reading aloud {}/{}", i + 1, items.len())),
+            client.synthesize_speech(text, voice, SpeechFormat::Mp3),
+        )
+        .await;
+        match result {
+            Ok(audio) => fs::write(&file, audio)
+                .with_context(|| format!("Failed to write {}", file.display()))?,
+            Err(e) => failed.push(format!("{}: {:#}", name, e)),
+        }
+    }
+
+    let saved = items.len() - failed.len();
+    let cached = if cached > 0 {
+        format!(" ({} cached)", cached)
+    } else {
+        String::new()
+    };
+    let message = if failed.is_empty() {
+        format!("{} {} narrations saved{}", CHECK, saved, cached)
+    } else {
+        format!(
+            "{}{} of {} narrations saved{}",
+            WARNING,
+            saved,
+            items.len(),
+            cached
+        )
+    };
+    progress.finish(spinner, message);
+    for failure in failed {
+        progress.println(format!("  {}{}", style(CROSS).red(), failure));
+    }
+    Ok(())
+}
+
+/// System prompt for summarizing one window of a long transcript
+const SUMMARY_PROMPT: &str = "你是视频内容编辑。用户会发送一段长视频转录的其中一部分。\
+请用转录的原语言，按原有顺序概括这一部分的要点：保留主要观点、关键论据、例子、\
+人名、产品名和术语，不要添加转录中没有的信息，只输出摘要本身。";
+
+/// Shrink a transcript that exceeds the content budget (map step)
+///
+/// Each window is summarized separately and the summaries are joined in order;
+/// the content prompt then runs over them (reduce step). Short transcripts are
+/// returned unchanged.
+async fn condense_transcript(
+    client: &Arc<dyn AiClient>,
+    transcript: &str,
+    options: &VideoOptions,
+    progress: &VideoProgress,
+) -> Result<WithUsage<String>> {
+    let budget = options.content_budget;
+    let mut text = transcript.to_string();
+    let mut usage = TokenUsage::default();
+
+    for round in 1..=MAX_CONDENSE_ROUNDS {
+        if estimate_tokens(&text) <= budget.max_tokens {
+            break;
+        }
+
+        let windows = split_into_windows(&text, budget.window_tokens);
+        let spinner = progress.spinner(format!(
+            "Transcript exceeds ~{} tokens, summarizing {} windows (round {})...",
+            budget.max_tokens,
+            windows.len(),
+            round
+        ));
+
+        let mut summaries = Vec::with_capacity(windows.len());
+        for (i, window) in windows.iter().enumerate() {
+            spinner.set_message(format!(
+                "Summarizing transcript window {}/{} (round {})",
+                i + 1,
+                windows.len(),
+                round
+            ));
+            let summary = with_retry_hook(
+                retry_message(
+                    &spinner,
+                    format!("Summarizing window {}/{}", i + 1, windows.len()),
+                ),
+                client.chat_text(SUMMARY_PROMPT, window.text.clone()),
+            )
+            .await?;
+            usage += summary.usage;
+            summaries.push(summary.value);
+        }
+
+        text = join_summaries(&summaries);
+        progress.finish(
+            spinner,
+            format!(
+                "{} Summarized {} transcript windows into ~{} tokens",
+                CHECK,
+                windows.len(),
+                estimate_tokens(&text)
+            ),
+        );
+
+        // Another round cannot shrink a single window any further
+        if windows.len() == 1 {
+            break;
+        }
+    }
+
+    Ok(WithUsage { value: text, usage })
+}
+
+/// Join window summaries in order, labelled so the model sees they are excerpts
+fn join_summaries<S: AsRef<str>>(summaries: &[S]) -> String {
+    summaries
+        .iter()
+        .enumerate()
+        .map(|(i, summary)| format!("[第{}部分摘要]\n{}", i + 1, summary.as_ref().trim()))
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+/// System prompt for polishing one transcript window
+fn polish_prompt(remove_fillers: bool) -> String {
+    let mut prompt = String::from(
+        "You clean up raw speech-to-text transcripts. Keep the user's text in its original \
+         language and wording: fix and add punctuation, and split it into paragraphs at topic \
+         changes, separated by a blank line. Do not summarize, translate, reorder, add or omit \
+         content.",
+    );
+    if remove_fillers {
+        prompt.push_str(
+            " Remove filler words and false starts such as \"um\", \"uh\", \"you know\", \
+             \"嗯\", \"呃\", \"那个\", \"就是说\" when they carry no meaning.",
+        );
+    }
+    prompt.push_str(" Reply with the cleaned-up transcript only.");
+    prompt
+}
+
+/// Parse the --polish-tolerance fraction
+fn parse_tolerance(value: &str) -> Result<f64> {
+    let tolerance: f64 = value
+        .parse()
+        .with_context(|| format!("Invalid tolerance '{}': expected a number", value))?;
+    if !(0.0..=1.0).contains(&tolerance) {
+        anyhow::bail!(
+            "Invalid tolerance '{}': expected a fraction between 0 and 1",
+            value
+        );
+    }
+    Ok(tolerance)
+}
+
+/// Letters, digits and CJK characters: what polishing should leave in place
+fn content_chars(text: &str) -> usize {
+    text.chars().filter(|c| c.is_alphanumeric()).count()
+}
+
+/// Polished content length relative to the original (1.0 when unchanged)
+fn polished_length_ratio(original: &str, polished: &str) -> f64 {
+    match content_chars(original) {
+        0 => 1.0,
+        original => content_chars(polished) as f64 / original as f64,
+    }
+}
+
+/// Punctuate and paragraph a transcript window by window with the chat model
+///
+/// A window that still fails after retries is kept as it was; a warning is
+/// printed when the result's length strays beyond `settings.tolerance`.
+async fn polish_transcript(
+    client: &Arc<dyn AiClient>,
+    transcript: &str,
+    settings: &PolishSettings,
+    progress: &VideoProgress,
+) -> Result<WithUsage<String>> {
+    let windows = split_into_windows(transcript, POLISH_WINDOW_TOKENS);
+    let system = polish_prompt(settings.remove_fillers);
+    let spinner = progress.spinner(format!(
+        "Polishing transcript ({} segments)...",
+        windows.len()
+    ));
+
+    let mut outputs = Vec::with_capacity(windows.len());
+    let mut usage = TokenUsage::default();
+    let mut failed = 0;
+    for (i, window) in windows.iter().enumerate() {
+        spinner.set_message(format!(
+            "Polishing transcript: segment {}/{}",
+            i + 1,
+            windows.len()
+        ));
+
+        let result = with_retry_hook(
+            retry_message(
+                &spinner,
+                format!("Polishing segment {}/{}", i + 1, windows.len()),
+            ),
+            client.chat_text(&system, window.text.clone()),
+        )
+        .await;
+
+        match result {
+            Ok(polished) => {
+                usage += polished.usage;
+                outputs.push(polished.value);
+            }
+            Err(_) => {
+                failed += 1;
+                outputs.push(window.text.clone());
+            }
+        }
+    }
+
+    let polished = merge_windows(&windows, &outputs);
+    let message = if failed == 0 {
+        format!("{} Transcript polished", CHECK)
+    } else {
+        format!(
+            "{} Transcript polished ({} of {} segments failed and were kept as is)",
+            WARNING,
+            failed,
+            windows.len()
+        )
+    };
+    progress.finish(spinner, message);
+
+    let ratio = polished_length_ratio(transcript, &polished);
+    if (ratio - 1.0).abs() > settings.tolerance {
+        progress.println(format!(
+            "{}Polished transcript is {:.0}% of the original length (tolerance ±{:.0}%); compare with the raw transcript",
+            WARNING,
+            ratio * 100.0,
+            settings.tolerance * 100.0
+        ));
+    }
+
+    Ok(WithUsage {
+        value: polished,
+        usage,
+    })
+}
+
+/// System prompt for translating one transcript window
+fn translation_prompt(target: &str) -> String {
+    format!(
+        "You translate video transcripts. Translate the user's text into the language with \
+         ISO-639-1 code \"{}\". Keep every line break exactly where it is, do not summarize, \
+         add or omit content, and reply with the translation only.",
+        target
+    )
+}
+
+/// Model reply for speaker labeling
+#[derive(Debug, Deserialize)]
+struct SpeakerLabelsResponse {
+    labels: Vec<SpeakerLabel>,
+}
+
+/// The speaker the model assigned to one numbered segment
+#[derive(Debug, Deserialize)]
+struct SpeakerLabel {
+    segment: usize,
+    speaker: String,
+}
+
+/// System prompt for labeling the speakers of one window of segments
+fn speaker_prompt(speakers: u8) -> String {
+    format!(
+        "You label who is speaking in a conversation transcript with {n} speakers, S1 to S{n}. \
+         The user sends numbered transcript segments as \"[<number>] <text>\". Decide the speaker \
+         of each segment from conversational cues such as questions and answers, turn-taking, \
+         names and forms of address. Segments marked \"(earlier: S<n>)\" were labeled with the \
+         previous part of the conversation; keep those speakers consistent. Reply with JSON \
+         {{\"labels\": [{{\"segment\": <number>, \"speaker\": \"S<1-{n}>\"}}]}} covering every segment.",
+        n = speakers
+    )
+}
+
+/// Ranges of segment indexes, `size` long, each repeating the last `overlap`
+/// segments of the previous one so labels can be matched up across windows
+fn speaker_windows(count: usize, size: usize, overlap: usize) -> Vec<std::ops::Range<usize>> {
+    assert!(
+        overlap < size,
+        "window overlap must be smaller than the window"
+    );
+    let mut windows = Vec::new();
+    let mut start = 0;
+    while start < count {
+        let end = (start + size).min(count);
+        windows.push(start..end);
+        if end == count {
+            break;
+        }
+        start = end - overlap;
+    }
+    windows
+}
+
+/// User message for one window; segments already labeled carry their label as a hint
+fn speaker_window_input(
+    segments: &[TranscriptSegment],
+    range: std::ops::Range<usize>,
+    labels: &[Option<u8>],
+) -> String {
+    range
+        .map(|i| match labels[i] {
+            Some(speaker) => format!(
+                "[{}] (earlier: S{}) {}",
+                i,
+                speaker,
+                segments[i].text.trim()
+            ),
+            None => format!("[{}] {}", i, segments[i].text.trim()),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Speaker number of a label such as "S2", if it is one of the `speakers`
+fn parse_speaker(label: &str, speakers: u8) -> Option<u8> {
+    let number: u8 = label.trim().trim_start_matches(['S', 's']).parse().ok()?;
+    (1..=speakers).contains(&number).then_some(number)
+}
+
+/// The model's labels for the segments of `range`; missing or invalid ones are `None`
+fn window_labels(
+    reply: &[SpeakerLabel],
+    range: std::ops::Range<usize>,
+    speakers: u8,
+) -> Vec<Option<u8>> {
+    let mut labels = vec![None; range.len()];
+    for label in reply {
+        if range.contains(&label.segment) {
+            labels[label.segment - range.start] = parse_speaker(&label.speaker, speakers);
+        }
+    }
+    labels
+}
+
+/// Every ordering of the speakers 1..=`speakers`, the identity first
+fn speaker_permutations(speakers: u8) -> Vec<Vec<u8>> {
+    let mut orderings = vec![Vec::new()];
+    for _ in 0..speakers {
+        orderings = orderings
+            .into_iter()
+            .flat_map(|prefix: Vec<u8>| {
+                (1..=speakers)
+                    .filter(|s| !prefix.contains(s))
+                    .map(|s| {
+                        let mut ordering = prefix.clone();
+                        ordering.push(s);
+                        ordering
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+    }
+    orderings
+}
+
+/// Renaming of a window's speakers (`mapping[label - 1]`) that agrees with the
+/// most labels already assigned to the same segments; the identity wins ties
+fn best_speaker_mapping(previous: &[Option<u8>], current: &[Option<u8>], speakers: u8) -> Vec<u8> {
+    let mut best = (1..=speakers).collect::<Vec<_>>();
+    let mut best_score = None;
+    for mapping in speaker_permutations(speakers) {
+        let score = previous
+            .iter()
+            .zip(current)
+            .filter(|(previous, current)| match (previous, current) {
+                (Some(previous), Some(current)) => mapping[*current as usize - 1] == *previous,
+                _ => false,
+            })
+            .count();
+        if best_score.is_none_or(|best_score| score > best_score) {
+            best = mapping;
+            best_score = Some(score);
+        }
+    }
+    best
+}
+
+/// Per-segment speaker labels, merged window by window
+#[derive(Debug)]
+struct SpeakerTrack {
+    speakers: u8,
+    labels: Vec<Option<u8>>,
+    /// Windows whose speakers had to be renamed to match the earlier ones
+    swaps: usize,
+}
+
+impl SpeakerTrack {
+    fn new(segment_count: usize, speakers: u8) -> Self {
+        Self {
+            speakers,
+            labels: vec![None; segment_count],
+            swaps: 0,
+        }
+    }
+
+    /// Merge one window's labels
+    ///
+    /// The window's speakers are first renamed to agree with the overlap that
+    /// earlier windows labeled, since the model may have numbered them the other
+    /// way round. Labels from earlier windows are kept in the overlap.
+    fn merge(&mut self, range: std::ops::Range<usize>, window: &[Option<u8>]) {
+        let mapping = best_speaker_mapping(&self.labels[range.clone()], window, self.speakers);
+        if mapping.iter().zip(1..).any(|(to, from)| *to != from) {
+            self.swaps += 1;
+        }
+        for (label, current) in self.labels[range].iter_mut().zip(window) {
+            if label.is_none() {
+                *label = current.map(|speaker| mapping[speaker as usize - 1]);
+            }
+        }
+    }
+}
+
+/// Labeled transcript: a disclaimer header, then one `S<n>:` paragraph per turn
+fn format_labeled_transcript(
+    segments: &[TranscriptSegment],
+    labels: &[Option<u8>],
+    speakers: u8,
+    model: &str,
+) -> String {
+    let mut text = format!(
+        "# Speakers S1-S{} were assigned by {} from conversational cues, not from the voices.\n\
+         # Labels can be wrong, especially for short turns; S? marks segments left unlabeled.\n",
+        speakers, model
+    );
+
+    let mut turns: Vec<(Option<u8>, String)> = Vec::new();
+    for (segment, label) in segments.iter().zip(labels) {
+        match turns.last_mut() {
+            Some((speaker, turn)) if speaker == label => turn.push_str(&segment.text),
+            _ => turns.push((*label, segment.text.clone())),
+        }
+    }
+    for (speaker, turn) in turns {
+        let speaker = speaker.map_or("S?".to_string(), |n| format!("S{}", n));
+        text.push_str(&format!("\n{}: {}\n", speaker, turn.trim()));
+    }
+    text
+}
+
+/// Label the speakers of a transcript with the chat model, window by window
+///
+/// A window that still fails after retries leaves its new segments unlabeled.
+async fn label_speakers(
+    client: &Arc<dyn AiClient>,
+    segments: &[TranscriptSegment],
+    speakers: u8,
+    progress: &VideoProgress,
+) -> Result<WithUsage<String>> {
+    let windows = speaker_windows(
+        segments.len(),
+        SPEAKER_WINDOW_SEGMENTS,
+        SPEAKER_WINDOW_OVERLAP,
+    );
+    let system = speaker_prompt(speakers);
+    let spinner = progress.spinner(format!(
+        "Labeling {} speakers ({} segments)...",
+        speakers,
+        windows.len()
+    ));
+
+    let mut track = SpeakerTrack::new(segments.len(), speakers);
+    let mut usage = TokenUsage::default();
+    let mut failed = 0;
+    for (i, range) in windows.iter().enumerate() {
+        spinner.set_message(format!(
+            "Labeling speakers: segment {}/{}",
+            i + 1,
+            windows.len()
+        ));
+
+        let input = speaker_window_input(segments, range.clone(), &track.labels);
+        let result = with_retry_hook(
+            retry_message(
+                &spinner,
+                format!("Labeling speakers {}/{}", i + 1, windows.len()),
+            ),
+            client.chat_json::<SpeakerLabelsResponse>(&system, input.clone()),
+        )
+        .await;
+
+        match result {
+            Ok(reply) => {
+                usage += reply.usage;
+                let labels = window_labels(&reply.value.labels, range.clone(), speakers);
+                track.merge(range.clone(), &labels);
+            }
+            Err(_) => failed += 1,
+        }
+    }
+
+    let mut message = format!("{} Speakers labeled", CHECK);
+    if track.swaps > 0 {
+        message.push_str(&format!(
+            " ({} label swap(s) between segments reconciled)",
+            track.swaps
+        ));
+    }
+    if failed > 0 {
+        message = format!(
+            "{} Speakers labeled ({} of {} segments failed and were left unlabeled)",
+            WARNING,
+            failed,
+            windows.len()
+        );
+    }
+    progress.finish(spinner, message);
+
+    Ok(WithUsage {
+        value: format_labeled_transcript(segments, &track.labels, speakers, &client.models().chat),
+        usage,
+    })
+}
+
+/// Model reply for chapter generation
+#[derive(Debug, Deserialize)]
+struct ChaptersResponse {
+    chapters: Vec<Chapter>,
+}
+
+const CHAPTERS_PROMPT: &str = "You create YouTube chapter lists. The user sends transcript \
+segments prefixed with their start time in seconds. Group them into 3 to 15 topics and reply \
+with JSON {\"chapters\": [{\"time\": <start second of the topic>, \"title\": \"<short title>\"}]}. \
+The first chapter starts at 0, times strictly increase, and titles use the transcript's language.";
+
+/// Ask the chat model for chapters and validate its answer
+async fn generate_chapters(
+    client: &Arc<dyn AiClient>,
+    segments: &[TranscriptSegment],
+    duration: f64,
+) -> Result<WithUsage<Vec<Chapter>>> {
+    let input = segments
+        .iter()
+        .map(|s| format!("[{}] {}", s.start.floor() as u32, s.text.trim()))
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    let response: WithUsage<ChaptersResponse> = client.chat_json(CHAPTERS_PROMPT, input).await?;
+    Ok(WithUsage {
+        value: validate_chapters(response.value.chapters, duration)?,
+        usage: response.usage,
+    })
+}
+
+/// Check that chapter times strictly increase and fall within the video
+fn validate_chapters(chapters: Vec<Chapter>, duration: f64) -> Result<Vec<Chapter>> {
+    if chapters.is_empty() {
+        anyhow::bail!("Model returned no chapters");
+    }
+
+    for pair in chapters.windows(2) {
+        if pair[1].time <= pair[0].time {
+            anyhow::bail!(
+                "Chapter times must increase: \"{}\" at {} follows \"{}\" at {}",
+                pair[1].title,
+                format_chapter_time(pair[1].time),
+                pair[0].title,
+                format_chapter_time(pair[0].time)
+            );
+        }
+    }
+
+    if let Some(last) = chapters.last()
+        && last.time as f64 >= duration
+    {
+        anyhow::bail!(
+            "Chapter \"{}\" at {} is past the end of the video ({})",
+            last.title,
+            format_chapter_time(last.time),
+            format_chapter_time(duration as u32)
+        );
+    }
+
+    Ok(chapters)
+}
+
+/// Chapter timestamp as MM:SS, or H:MM:SS for an hour and more
+fn format_chapter_time(seconds: u32) -> String {
+    let (hours, minutes, secs) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+    if hours > 0 {
+        format!("{}:{:02}:{:02}", hours, minutes, secs)
+    } else {
+        format!("{:02}:{:02}", minutes, secs)
+    }
+}
+
+/// One `MM:SS Title` line per chapter, ready to paste into a description
+fn format_chapters(chapters: &[Chapter]) -> String {
+    chapters
+        .iter()
+        .map(|c| format!("{} {}", format_chapter_time(c.time), c.title.trim()))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+async fn generate_content_from_transcript(
+    client: &Arc<dyn AiClient>,
+    transcript: &str,
+    language: Option<&str>,
+    counts: &ContentCounts,
+    context: Option<&str>,
+) -> Result<WithUsage<ContentResponse>> {
+    client
+        .generate_content(
+            content_prompt(transcript, language, counts, context),
+            language,
+        )
+        .await
+        .map_err(Into::into)
+}
+
+/// Content request for the chat model with the requested item counts filled in
+/// and the user's context, if any, ahead of everything else
+fn content_prompt(
+    transcript: &str,
+    language: Option<&str>,
+    counts: &ContentCounts,
+    context: Option<&str>,
+) -> String {
+    let requests = [
+        (counts.titles, "个吸引人的标题选项（每个不超过16个字）"),
+        (counts.descriptions, "段详细的视频描述（每段300-500字）"),
+        (
+            counts.status_updates,
+            "个bilibili动态更新文案（每个150-250字）",
+        ),
+    ]
+    .into_iter()
+    .filter(|(count, _)| *count > 0)
+    .enumerate()
+    .map(|(i, (count, item))| format!("{}. {}{}", i + 1, count, item))
+    .collect::<Vec<_>>()
+    .join("\n");
+
+    let mut prompt = context_preamble(context);
+    prompt.push_str(&format!(
+        r#"基于以下视频转录内容，请生成：
+{}
+
+请以JSON格式返回，格式如下（数量为0的字段返回空数组）：
+{{
+  "titles": {},
+  "descriptions": {},
+  "status_updates": {}
+}}
+
+转录内容：
+{}"#,
+        requests,
+        placeholder_list("标题", counts.titles),
+        placeholder_list("描述", counts.descriptions),
+        placeholder_list("动态", counts.status_updates),
+        transcript
+    ));
+
+    if let Some(code) = language.filter(|code| *code != "zh") {
+        prompt.push_str(&format!(
+            "\n\nWrite all titles, descriptions and status updates in the language with ISO-639-1 code \"{}\".",
+            code
+        ));
+    }
+
+    prompt
+}
+
+/// The --context text ahead of a content prompt, if any
+fn context_preamble(context: Option<&str>) -> String {
+    context
+        .map(|context| {
+            format!(
+                "背景信息（请按此拼写人名、产品名和术语）：\n{}\n\n",
+                context
+            )
+        })
+        .unwrap_or_default()
+}
+
+/// Whisper reads at most 224 prompt tokens
+const TRANSCRIPTION_PROMPT_MAX_TOKENS: usize = 224;
+
+/// Cut `context` to fit the transcription prompt limit (by [`estimate_tokens`]);
+/// `true` when it was cut
+fn fit_transcription_prompt(context: &str) -> (String, bool) {
+    if estimate_tokens(context) <= TRANSCRIPTION_PROMPT_MAX_TOKENS {
+        return (context.to_string(), false);
+    }
+
+    let (mut ascii, mut other, mut end) = (0usize, 0, 0);
+    for (i, c) in context.char_indices() {
+        if c.is_ascii() {
+            ascii += 1;
+        } else {
+            other += 1;
+        }
+        if ascii.div_ceil(4) + other > TRANSCRIPTION_PROMPT_MAX_TOKENS {
+            break;
+        }
+        end = i + c.len_utf8();
+    }
+    (context[..end].trim_end().to_string(), true)
+}
+
+/// Output sections requested from the chat model, loaded from --profiles
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ContentProfile {
+    /// Default ISO-639-1 language for sections without their own
+    #[serde(default)]
+    language: Option<String>,
+    sections: Vec<SectionSpec>,
+}
+
+/// One named output section of a [`ContentProfile`]
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SectionSpec {
+    key: String,
+    description: String,
+    count: usize,
+    /// Upper bound in characters per item
+    #[serde(default)]
+    max_length: Option<usize>,
+    #[serde(default)]
+    language: Option<String>,
+}
+
+/// System prompt for profile-driven content generation
+const PROFILE_SYSTEM_PROMPT: &str = "You are a professional content creation assistant who \
+writes copy about videos for different platforms. Follow each section's instructions, item \
+count, length limit and language exactly, and reply with the requested JSON object only.";
+
+impl ContentProfile {
+    fn load(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read content profile: {}", path.display()))?;
+        Self::parse(&content)
+            .with_context(|| format!("Invalid content profile: {}", path.display()))
+    }
+
+    fn parse(yaml: &str) -> Result<Self> {
+        let profile: Self = serde_yaml::from_str(yaml)?;
+        profile.validate()?;
+        Ok(profile)
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.sections.is_empty() {
+            anyhow::bail!("profile declares no sections");
+        }
+        if let Some(language) = &self.language {
+            validate_language_code(language)?;
+        }
+
+        let mut seen = std::collections::HashSet::new();
+        for section in &self.sections {
+            let key = &section.key;
+            if key.is_empty()
+                || !key
+                    .chars()
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+            {
+                anyhow::bail!(
+                    "section '{}': key must be lowercase letters, digits and _",
+                    key
+                );
+            }
+            if !seen.insert(key) {
+                anyhow::bail!("section '{}': key is declared more than once", key);
+            }
+            if !(1..=20).contains(&section.count) {
+                anyhow::bail!("section '{}': count must be between 1 and 20", key);
+            }
+            if section.max_length == Some(0) {
+                anyhow::bail!("section '{}': max_length must be at least 1", key);
+            }
+            if let Some(language) = &section.language {
+                validate_language_code(language)
+                    .with_context(|| format!("section '{}': invalid language", key))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Language of a section: its own, the profile's, the run's, then Chinese
+    fn section_language<'a>(&'a self, section: &'a SectionSpec, run: Option<&'a str>) -> &'a str {
+        section
+            .language
+            .as_deref()
+            .or(self.language.as_deref())
+            .or(run)
+            .unwrap_or("zh")
+    }
+
+    /// Strict JSON schema: one array of strings per section key
+    fn schema(&self) -> serde_json::Value {
+        let properties: serde_json::Map<String, serde_json::Value> = self
+            .sections
+            .iter()
+            .map(|s| {
+                (
+                    s.key.clone(),
+                    serde_json::json!({ "type": "array", "items": { "type": "string" } }),
+                )
+            })
+            .collect();
+        let required: Vec<&str> = self.sections.iter().map(|s| s.key.as_str()).collect();
+        serde_json::json!({
+            "type": "object",
+            "properties": properties,
+            "required": required,
+            "additionalProperties": false,
+        })
+    }
+}
+
+/// Content request describing every section of `profile`
+fn profile_prompt(
+    transcript: &str,
+    profile: &ContentProfile,
+    language: Option<&str>,
+    context: Option<&str>,
+) -> String {
+    let sections = profile
+        .sections
+        .iter()
+        .map(|s| {
+            let mut line = format!(
+                "- \"{}\": {} item(s). {}.",
+                s.key,
+                s.count,
+                s.description.trim().trim_end_matches(['.', '。'])
+            );
+            if let Some(max) = s.max_length {
+                line.push_str(&format!(" At most {} characters each.", max));
+            }
+            line.push_str(&format!(
+                " Write in the language with ISO-639-1 code \"{}\".",
+                profile.section_language(s, language)
+            ));
+            line
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    format!(
+        "{}Based on the video transcript below, write these sections:\n{}\n\n\
+         Reply with a JSON object that has one array of strings per section key, \
+         each with exactly the requested number of items.\n\nTranscript:\n{}",
+        context_preamble(context),
+        sections,
+        transcript
+    )
+}
+
+/// Check a reply against the profile's sections, counts and lengths
+///
+/// Every violation is reported, each naming its section and constraint.
+fn validate_sections(
+    profile: &ContentProfile,
+    reply: &serde_json::Map<String, serde_json::Value>,
+) -> std::result::Result<Vec<ContentSection>, Vec<String>> {
+    let mut sections = Vec::new();
+    let mut errors = Vec::new();
+
+    for spec in &profile.sections {
+        let key = &spec.key;
+        let Some(value) = reply.get(key) else {
+            errors.push(format!("section '{}': missing from the reply", key));
+            continue;
+        };
+        let Ok(items) = serde_json::from_value::<Vec<String>>(value.clone()) else {
+            errors.push(format!("section '{}': expected an array of strings", key));
+            continue;
+        };
+
+        if items.len() != spec.count {
+            errors.push(format!(
+                "section '{}': count is {}, got {} items",
+                key,
+                spec.count,
+                items.len()
+            ));
+        }
+        if let Some(max) = spec.max_length {
+            for (i, item) in items.iter().enumerate() {
+                let chars = item.trim().chars().count();
+                if chars > max {
+                    errors.push(format!(
+                        "section '{}': item {} has {} characters, max_length is {}",
+                        key,
+                        i + 1,
+                        chars,
+                        max
+                    ));
+                }
+            }
+        }
+        sections.push(ContentSection {
+            key: key.clone(),
+            items,
+        });
+    }
+
+    if errors.is_empty() {
+        Ok(sections)
+    } else {
+        Err(errors)
+    }
+}
+
+/// Generate the sections of a content profile
+///
+/// A reply that breaks a declared count or length is requested once more with
+/// the violations listed; if that one fails too, they become the error.
+async fn generate_profile_content(
+    client: &Arc<dyn AiClient>,
+    transcript: &str,
+    profile: &ContentProfile,
+    options: &VideoOptions,
+    spinner: &ProgressBar,
+) -> Result<WithUsage<Vec<ContentSection>>> {
+    let mut prompt = profile_prompt(
+        transcript,
+        profile,
+        options.language.as_deref(),
+        options.context.as_deref(),
+    );
+    let schema = profile.schema();
+    let mut usage = TokenUsage::default();
+    let mut corrected = false;
+
+    loop {
+        let reply: WithUsage<serde_json::Map<String, serde_json::Value>> = with_retry_hook(
+            retry_message(
+                spinner,
+                format!("Generating content with {}", client.models().chat),
+            ),
+            client.chat_json_schema(
+                PROFILE_SYSTEM_PROMPT,
+                prompt.clone(),
+                "video_content",
+                schema.clone(),
+            ),
+        )
+        .await?;
+        usage += reply.usage;
+
+        let errors = match validate_sections(profile, &reply.value) {
+            Ok(sections) => {
+                return Ok(WithUsage {
+                    value: sections,
+                    usage,
+                });
+            }
+            Err(errors) => errors,
+        };
+        if corrected {
+            anyhow::bail!(
+                "Generated content does not match the profile:\n  {}",
+                errors.join("\n  ")
+            );
+        }
+
+        corrected = true;
+        spinner.set_message("Generated content broke the profile, asking again...");
+        prompt.push_str(&format!(
+            "\n\nAn earlier reply broke these constraints; make sure this one does not:\n- {}",
+            errors.join("\n- ")
+        ));
+    }
+}
+
+/// JSON example array such as `["标题1", "标题2"]`
+fn placeholder_list(label: &str, count: usize) -> String {
+    let items = (1..=count)
+        .map(|i| format!("\"{}{}\"", label, i))
+        .collect::<Vec<_>>()
+        .join(", ");
+    format!("[{}]", items)
+}
+
+/// Warnings for items the model returned more or fewer of than requested
+fn count_mismatches(content: &ContentResponse, counts: &ContentCounts) -> Vec<String> {
+    [
+        ("titles", content.titles.len(), counts.titles),
+        (
+            "descriptions",
+            content.descriptions.len(),
+            counts.descriptions,
+        ),
+        (
+            "status updates",
+            content.status_updates.len(),
+            counts.status_updates,
+        ),
+    ]
+    .into_iter()
+    .filter(|(_, got, wanted)| got != wanted)
+    .map(|(item, got, wanted)| {
+        format!(
+            "Requested {} {} but got {}; keeping them",
+            wanted, item, got
+        )
+    })
+    .collect()
+}
+
+fn save_outputs(
+    video_name: &str,
+    output_dir: &Path,
+    content: &ContentResponse,
+    transcript: &str,
+    options: &VideoOptions,
+    progress: &VideoProgress,
+) -> Result<()> {
+    let spinner = progress.spinner("Saving output files...");
+
+    // Save JSON
+    let content_file = output_dir.join(format!("{}_content.json", video_name));
+    let json = serde_json::to_string_pretty(content)?;
+    fs::write(&content_file, json)?;
+
+    // Profile sections replace the built-in titles/descriptions/status updates
+    let mut files = Vec::new();
+    if content.sections.is_empty() {
+        // Save titles
+        let titles_file = output_dir.join(format!("{}_titles.txt", video_name));
+        let titles = content
+            .titles
+            .iter()
+            .enumerate()
+            .map(|(i, title)| format!("{}. {}", i + 1, title))
+            .collect::<Vec<_>>()
+            .join("\n");
+        fs::write(&titles_file, titles)?;
+
+        // Save descriptions
+        let descriptions_file = output_dir.join(format!("{}_descriptions.txt", video_name));
+        let descriptions = content
+            .descriptions
+            .iter()
+            .enumerate()
+            .map(|(i, desc)| format!("=== 描述 {} ===\n{}\n", i + 1, desc))
+            .collect::<Vec<_>>()
+            .join("\n");
+        fs::write(&descriptions_file, descriptions)?;
+
+        // Save status updates
+        let status_file = output_dir.join(format!("{}_status.txt", video_name));
+        let status_updates = content
+            .status_updates
+            .iter()
+            .enumerate()
+            .map(|(i, status)| format!("=== 动态 {} ===\n{}\n", i + 1, status))
+            .collect::<Vec<_>>()
+            .join("\n");
+        fs::write(&status_file, status_updates)?;
+
+        files.push((format!("{}Titles", LABEL), titles_file));
+        files.push((format!("{}Descriptions", PAGE), descriptions_file));
+        files.push((format!("{}Status updates", SPEECH), status_file));
+    }
+    for section in &content.sections {
+        let section_file = output_dir.join(format!("{}_{}.txt", video_name, section.key));
+        fs::write(&section_file, format_section(section))?;
+        files.push((format!("{}{}", PAGE, section.key), section_file));
+    }
+
+    // Save combined Markdown document
+    let markdown_file = output_dir.join(format!("{}.md", video_name));
+    if options.markdown {
+        let markdown =
+            render_markdown(video_name, content, transcript, options.language.as_deref());
+        fs::write(&markdown_file, markdown)?;
+    }
+
+    progress.finish(
+        spinner,
+        format!("All files saved to {}", output_dir.display()),
+    );
+
+    progress.println(format!("{} {}:", style("Generated files").bold(), PACKAGE));
+    progress.println(format!(
+        "  {}Transcript: {}",
+        MEMO,
+        style(
+            output_dir
+                .join(format!("{}_transcript.txt", video_name))
+                .display()
+        )
+        .dim()
+    ));
+    progress.println(format!(
+        "  {}Full content: {}",
+        CLIPBOARD,
+        style(content_file.display()).dim()
+    ));
+    for (label, file) in &files {
+        progress.println(format!("  {}: {}", label, style(file.display()).dim()));
+    }
+    if options.markdown {
+        progress.println(format!(
+            "  {}Markdown: {}",
+            NOTEBOOK,
+            style(markdown_file.display()).dim()
+        ));
+    }
+
+    // Display preview of titles, or of the first profile section
+    let (label, preview) = match content.sections.first() {
+        Some(section) => (section.key.as_str(), &section.items),
+        None => ("titles", &content.titles),
+    };
+    if !preview.is_empty() {
+        progress.println(format!(
+            "{}",
+            style(format!("Generated {}:", label)).bold().cyan()
+        ));
+    }
+    for (i, title) in preview.iter().enumerate() {
+        progress.println(format!(
+            "  {}. {}",
+            style(i + 1).dim(),
+            style(title).green()
+        ));
+    }
+
+    Ok(())
+}
+
+/// Artifacts --upload-to publishes: the transcript, content JSON, Markdown,
+/// subtitles and key frames, as far as this run produced them
+fn upload_artifacts(
+    output_dir: &Path,
+    video_name: &str,
+    keyframes: &[Keyframe],
+    options: &VideoOptions,
+) -> Vec<PathBuf> {
+    let mut files = vec![
+        output_dir.join(format!("{}_transcript.txt", video_name)),
+        output_dir.join(format!("{}_content.json", video_name)),
+    ];
+    if options.speakers.is_some() {
+        files.push(output_dir.join(format!("{}_transcript.labeled.txt", video_name)));
+    }
+    if options.markdown {
+        files.push(output_dir.join(format!("{}.md", video_name)));
+    }
+    files.push(match &options.srt {
+        Some(srt) => srt.clone(),
+        None => output_dir.join(format!("{}.srt", video_name)),
+    });
+    files.extend(
+        keyframes
+            .iter()
+            .map(|keyframe| output_dir.join(&keyframe.file)),
+    );
+    files.retain(|file| file.is_file());
+    files
+}
+
+/// Upload artifacts under the client's target path and print a presigned URL
+/// for each; failures are returned instead of failing the video
+async fn upload_outputs(s3: &S3Client, files: &[PathBuf], progress: &VideoProgress) -> Vec<String> {
+    progress.println(format!(
+        "{}Uploading to s3://{}/{}",
+        style(CLOUD).cyan(),
+        s3.bucket(),
+        s3.config.build_s3_key("")
+    ));
+
+    let mut errors = Vec::new();
+    for file in files {
+        let name = file
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        match upload_artifact(s3, file, &name, progress).await {
+            Ok(url) => progress.println(format!("  {}{}: {}", LINK, name, style(url).dim())),
+            Err(e) => errors.push(format!("{}: {:#}", name, e)),
+        }
+    }
+    errors
+}
+
+/// Upload one artifact unless S3 already has an identical copy; returns its presigned URL
+async fn upload_artifact(
+    s3: &S3Client,
+    file: &Path,
+    name: &str,
+    progress: &VideoProgress,
+) -> Result<String> {
+    let key = s3.config.build_s3_key(name);
+    if compare_file(s3.client(), s3.bucket(), &key, file).await? == FileComparison::Identical {
+        progress.println(format!("  {} {} (unchanged)", style("✓").green(), name));
+    } else {
+        let bar = progress.upload_bar(format!("Uploading {}", name));
+        let result = upload_file(s3.client(), s3.bucket(), &key, file, Some(&bar)).await;
+        bar.finish_and_clear();
+        result?;
+        progress.println(format!("  {} {} uploaded", style("✓").green(), name));
+    }
+    generate_presigned_url(s3.client(), s3.bucket(), &key).await
+}
+
+/// Items of a profile section as saved in `<stem>_<key>.txt`
+fn format_section(section: &ContentSection) -> String {
+    section
+        .items
+        .iter()
+        .enumerate()
+        .map(|(i, item)| format!("=== {} {} ===\n{}\n", section.key, i + 1, item.trim()))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Section headings of the Markdown document, in the output language
+struct MarkdownHeadings {
+    titles: &'static str,
+    descriptions: &'static str,
+    description: &'static str,
+    status_updates: &'static str,
+    status_update: &'static str,
+    chapters: &'static str,
+    keyframes: &'static str,
+    transcript: &'static str,
+}
+
+impl MarkdownHeadings {
+    /// Chinese headings when content is written in Chinese (the default), English otherwise
+    fn for_language(language: Option<&str>) -> Self {
+        match language {
+            None | Some("zh") => Self {
+                titles: "标题",
+                descriptions: "描述",
+                description: "描述",
+                status_updates: "动态",
+                status_update: "动态",
+                chapters: "章节",
+                keyframes: "关键帧",
+                transcript: "完整文字稿",
+            },
+            Some(_) => Self {
+                titles: "Titles",
+                descriptions: "Descriptions",
+                description: "Description",
+                status_updates: "Status Updates",
+                status_update: "Status Update",
+                chapters: "Chapters",
+                keyframes: "Key Frames",
+                transcript: "Transcript",
+            },
+        }
+    }
+}
+
+/// Render all generated content and the transcript as one Markdown document
+fn render_markdown(
+    video_name: &str,
+    content: &ContentResponse,
+    transcript: &str,
+    language: Option<&str>,
+) -> String {
+    let headings = MarkdownHeadings::for_language(language);
+    let mut doc = format!("# {}\n", video_name);
+
+    if !content.titles.is_empty() {
+        doc.push_str(&format!("\n## {}\n\n", headings.titles));
+    }
+    for (i, title) in content.titles.iter().enumerate() {
+        doc.push_str(&format!("{}. {}\n", i + 1, title));
+    }
+
+    if !content.descriptions.is_empty() {
+        doc.push_str(&format!("\n## {}\n", headings.descriptions));
+    }
+    for (i, desc) in content.descriptions.iter().enumerate() {
+        doc.push_str(&format!(
+            "\n### {} {}\n\n{}\n",
+            headings.description,
+            i + 1,
+            desc.trim()
+        ));
+    }
+
+    if !content.status_updates.is_empty() {
+        doc.push_str(&format!("\n## {}\n", headings.status_updates));
+    }
+    for (i, status) in content.status_updates.iter().enumerate() {
+        doc.push_str(&format!(
+            "\n### {} {}\n\n{}\n",
+            headings.status_update,
+            i + 1,
+            status.trim()
+        ));
+    }
+
+    for section in content.sections.iter().filter(|s| !s.items.is_empty()) {
+        doc.push_str(&format!("\n## {}\n", section.key));
+        // Multi-paragraph items get their own subsection, short ones a list
+        if section.items.iter().any(|item| item.trim().contains('\n')) {
+            for (i, item) in section.items.iter().enumerate() {
+                doc.push_str(&format!(
+                    "\n### {} {}\n\n{}\n",
+                    section.key,
+                    i + 1,
+                    item.trim()
+                ));
+            }
+        } else {
+            doc.push('\n');
+            for (i, item) in section.items.iter().enumerate() {
+                doc.push_str(&format!("{}. {}\n", i + 1, item.trim()));
+            }
+        }
+    }
+
+    if !content.chapters.is_empty() {
+        doc.push_str(&format!(
+            "\n## {}\n\n{}\n",
+            headings.chapters,
+            format_chapters(&content.chapters)
+        ));
+    }
+
+    if !content.keyframes.is_empty() {
+        doc.push_str(&format!("\n## {}\n", headings.keyframes));
+    }
+    for keyframe in &content.keyframes {
+        let caption = keyframe.caption.as_deref().unwrap_or_default();
+        let line = format!("{} {}", format_chapter_time(keyframe.time as u32), caption);
+        doc.push_str(&format!(
+            "\n![{}]({})\n\n{}\n",
+            caption,
+            keyframe.file.replace(' ', "%20"),
+            line.trim_end()
+        ));
+    }
+
+    doc.push_str(&format!(
+        "\n## {}\n\n{}\n",
+        headings.transcript,
+        transcript.trim()
+    ));
+    doc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use indicatif::ProgressDrawTarget;
+    use swiss_knife::mock::{MockAiClient, MockCall};
+
+    #[test]
+    fn test_default_output_dir() {
+        assert_eq!(
+            default_output_dir(Path::new("/videos/lecture.mp4")).unwrap(),
+            PathBuf::from("/videos/lecture_output")
+        );
+        assert_eq!(
+            default_output_dir(Path::new("lecture.mp4")).unwrap(),
+            PathBuf::from("lecture_output")
+        );
+    }
+
+    #[test]
+    fn test_collect_videos_from_directory_and_files() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in ["b.mov", "a.mp4", "c.MKV", "notes.txt"] {
+            fs::write(dir.path().join(name), b"").unwrap();
+        }
+        let extra = tempfile::NamedTempFile::new().unwrap();
+        let extensions = vec!["mp4".to_string(), "mov".to_string(), "mkv".to_string()];
+
+        let videos = collect_videos(
+            &[dir.path().to_path_buf(), extra.path().to_path_buf()],
+            &extensions,
+        )
+        .unwrap();
+
+        let names: Vec<_> = videos.iter().map(|v| video_label(v)).collect();
+        assert_eq!(names[..3], ["a.mp4", "b.mov", "c.MKV"]);
+        assert_eq!(videos[3], extra.path());
+    }
+
+    #[test]
+    fn test_remote_source_parse() {
+        assert_eq!(
+            RemoteSource::parse(Path::new("https://cdn.example.com/v/talk.mp4?sig=1")).unwrap(),
+            Some(RemoteSource::Http(
+                "https://cdn.example.com/v/talk.mp4?sig=1".to_string()
+            ))
+        );
+        assert_eq!(
+            RemoteSource::parse(Path::new("s3://my-bucket/rec/2024/talk.mov")).unwrap(),
+            Some(RemoteSource::S3 {
+                bucket: "my-bucket".to_string(),
+                key: "rec/2024/talk.mov".to_string()
+            })
+        );
+        assert_eq!(RemoteSource::parse(Path::new("./talk.mp4")).unwrap(), None);
+        assert!(RemoteSource::parse(Path::new("s3://my-bucket")).is_err());
+        assert!(RemoteSource::parse(Path::new("s3://my-bucket/")).is_err());
+    }
+
+    #[test]
+    fn test_remote_source_file_name() {
+        let name = |input: &str| {
+            RemoteSource::parse(Path::new(input))
+                .unwrap()
+                .unwrap()
+                .file_name()
+        };
+        assert_eq!(
+            name("https://cdn.example.com/v/talk.mp4?sig=1#t=5").unwrap(),
+            "talk.mp4"
+        );
+        assert_eq!(name("s3://my-bucket/rec/talk.mov").unwrap(), "talk.mov");
+        assert!(name("https://cdn.example.com/").is_err());
+        assert!(name("https://cdn.example.com").is_err());
+    }
+
+    #[test]
+    fn test_upload_target_parse() {
+        let target = UploadTarget::parse("s3://my-bucket/talks/2024/").unwrap();
+        assert_eq!(target.bucket, "my-bucket");
+        assert_eq!(target.prefix, "talks/2024");
+        assert_eq!(
+            s3_config(&target.bucket, &target.prefix).build_s3_key("a_content.json"),
+            "talks/2024/a_content.json"
+        );
+
+        let root = UploadTarget::parse("s3://my-bucket").unwrap();
+        assert_eq!(root.prefix, "");
+        assert_eq!(
+            s3_config(&root.bucket, &root.prefix).build_s3_key("a.md"),
+            "a.md"
+        );
+
+        assert!(UploadTarget::parse("my-bucket/talks").is_err());
+        assert!(UploadTarget::parse("s3:///talks").is_err());
+        assert!(UploadTarget::parse("s3://my-bucket/a//b").is_err());
+        assert!(UploadTarget::parse("s3://my-bucket/../b").is_err());
+    }
+
+    #[test]
+    fn test_collect_videos_keeps_remote_inputs() {
+        let inputs = [
+            PathBuf::from("s3://my-bucket/talk.mp4"),
+            PathBuf::from("https://example.com/a.mp4"),
+        ];
+        assert_eq!(collect_videos(&inputs, &[]).unwrap(), inputs);
+    }
+
+    #[test]
+    fn test_plan_download() {
+        let meta = DownloadMeta {
+            source: "https://example.com/a.mp4".to_string(),
+            etag: Some("\"abc\"".to_string()),
+            last_modified: Some("Wed, 21 Oct 2015 07:28:00 GMT".to_string()),
+        };
+        let source = meta.source.as_str();
+
+        assert_eq!(plan_download(None, source, true, 0), DownloadPlan::Full);
+        assert_eq!(
+            plan_download(Some(&meta), source, true, 0),
+            DownloadPlan::IfNoneMatch("\"abc\"".to_string())
+        );
+        assert_eq!(
+            plan_download(Some(&meta), source, false, 4096),
+            DownloadPlan::Resume {
+                offset: 4096,
+                validator: "\"abc\"".to_string()
+            }
+        );
+        assert_eq!(
+            plan_download(Some(&meta), source, false, 0),
+            DownloadPlan::Full
+        );
+        // Metadata of a different URL is ignored
+        assert_eq!(
+            plan_download(Some(&meta), "https://example.com/b.mp4", true, 0),
+            DownloadPlan::Full
+        );
+
+        // Without an ETag, Last-Modified still validates a resume
+        let no_etag = DownloadMeta {
+            etag: None,
+            ..meta.clone()
+        };
+        assert_eq!(
+            plan_download(Some(&no_etag), source, false, 10),
+            DownloadPlan::Resume {
+                offset: 10,
+                validator: "Wed, 21 Oct 2015 07:28:00 GMT".to_string()
+            }
+        );
+        assert_eq!(
+            plan_download(Some(&no_etag), source, true, 0),
+            DownloadPlan::Full
+        );
+    }
+
+    #[test]
+    fn test_download_meta_and_mtime() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("talk.mp4");
+        fs::write(&file, b"video").unwrap();
+        assert_eq!(
+            download_meta_path(&file),
+            dir.path().join("talk.mp4.download.json")
+        );
+        assert_eq!(load_download_meta(&file), None);
+
+        let meta = DownloadMeta {
+            source: "s3://b/talk.mp4".to_string(),
+            etag: Some("\"e1\"".to_string()),
+            last_modified: Some("Wed, 21 Oct 2015 07:28:00 GMT".to_string()),
+        };
+        save_download_meta(&file, &meta).unwrap();
+        assert_eq!(load_download_meta(&file), Some(meta.clone()));
+
+        set_modified_from_http_date(&file, meta.last_modified.as_deref()).unwrap();
+        let modified = fs::metadata(&file).unwrap().modified().unwrap();
+        let secs = modified
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        assert_eq!(secs, 1445412480);
+    }
+
+    #[test]
+    fn test_collect_videos_missing_input() {
+        let err = collect_videos(&[PathBuf::from("/no/such/video.mp4")], &[]).unwrap_err();
+        assert!(err.to_string().contains("does not exist"));
+    }
+
+    const SILENCEDETECT_OUTPUT: &str = "\
+Input #0, mov,mp4,m4a,3gp,3g2,mj2, from 'lecture.mp4':
+  Duration: 00:50:00.00, start: 0.000000, bitrate: 1200 kb/s
+[silencedetect @ 0x600001c3c000] silence_start: 640.12
+[silencedetect @ 0x600001c3c000] silence_end: 641.88 | silence_duration: 1.76
+[silencedetect @ 0x600001c3c000] silence_start: 1288.5
+[silencedetect @ 0x600001c3c000] silence_end: 1291.5 | silence_duration: 3
+[silencedetect @ 0x600001c3c000] silence_start: 2710
+size=N/A time=00:50:00.00 bitrate=N/A speed= 410x
+";
+
+    #[test]
+    fn test_parse_silence_midpoints() {
+        assert_eq!(
+            parse_silence_midpoints(SILENCEDETECT_OUTPUT),
+            vec![641.0, 1290.0]
+        );
+        assert!(parse_silence_midpoints("no silences here").is_empty());
+    }
+
+    #[test]
+    fn test_fixed_chunk_boundaries() {
+        let spans = fixed_chunk_boundaries(3000.0);
+        let bounds: Vec<_> = spans.iter().map(|s| (s.start, s.end())).collect();
+        assert_eq!(bounds, vec![(0, 1300), (1300, 2600), (2600, 3000)]);
+        assert_eq!(spans[2].index, 2);
+    }
+
+    #[test]
+    fn test_choose_chunk_boundaries_prefers_nearest_silence() {
+        // 1290 is nearest to the 1300 target; 2650 is nearest to 1290 + 1300
+        let spans = choose_chunk_boundaries(3000.0, &[641.0, 1290.0, 1420.0, 2650.0]);
+        let bounds: Vec<_> = spans.iter().map(|s| (s.start, s.end())).collect();
+        assert_eq!(bounds, vec![(0, 1290), (1290, 2650), (2650, 3000)]);
+    }
+
+    #[test]
+    fn test_choose_chunk_boundaries_respects_max_length() {
+        // A silence past MAX_CHUNK_SECONDS is ignored in favor of the fixed cut
+        let spans = choose_chunk_boundaries(3000.0, &[1600.0]);
+        assert_eq!(spans[0].end(), 1300);
+        assert!(spans.iter().all(|s| s.duration <= MAX_CHUNK_SECONDS));
+    }
+
+    #[test]
+    fn test_fixed_chunk_boundaries_keeps_fractional_tail() {
+        let spans = fixed_chunk_boundaries(2600.4);
+        let bounds: Vec<_> = spans.iter().map(|s| (s.start, s.end())).collect();
+        assert_eq!(bounds, vec![(0, 1300), (1300, 2600), (2600, 2601)]);
+
+        // Exactly one chunk's worth stays a single chunk
+        assert_eq!(fixed_chunk_boundaries(1300.0).len(), 1);
+    }
+
+    const FFPROBE_MP4: &str = r#"{
+    "streams": [
+        {"index": 0, "codec_type": "video", "codec_name": "h264", "duration": "2501.433333"},
+        {"index": 1, "codec_type": "audio", "codec_name": "aac", "duration": "2501.461333"}
+    ],
+    "format": {
+        "filename": "lecture.mp4",
+        "nb_streams": 2,
+        "format_name": "mov,mp4,m4a,3gp,3g2,mj2",
+        "duration": "2501.461000",
+        "size": "157286400"
+    }
+}"#;
+
+    // Some MKVs report the duration only on their streams
+    const FFPROBE_MKV_NO_FORMAT_DURATION: &str = r#"{
+    "streams": [
+        {"index": 0, "codec_type": "video", "codec_name": "vp9", "duration": "1805.250000"},
+        {"index": 1, "codec_type": "audio", "codec_name": "opus", "duration": "1805.312000"},
+        {"index": 2, "codec_type": "subtitle", "codec_name": "ass"}
+    ],
+    "format": {
+        "filename": "talk.mkv",
+        "nb_streams": 3,
+        "format_name": "matroska,webm"
+    }
+}"#;
+
+    #[test]
+    fn test_parse_ffprobe_duration_prefers_format() {
+        assert_eq!(parse_ffprobe_duration(FFPROBE_MP4).unwrap(), 2501.461);
+    }
+
+    #[test]
+    fn test_parse_ffprobe_duration_falls_back_to_streams() {
+        assert_eq!(
+            parse_ffprobe_duration(FFPROBE_MKV_NO_FORMAT_DURATION).unwrap(),
+            1805.312
+        );
+    }
+
+    #[test]
+    fn test_parse_ffprobe_duration_missing() {
+        let json = r#"{"streams": [{"codec_type": "video", "duration": "N/A"}], "format": {}}"#;
+        assert!(parse_ffprobe_duration(json).is_err());
+        assert!(parse_ffprobe_duration("not json").is_err());
+    }
+
+    fn sample_state() -> RunState {
+        let spans = fixed_chunk_boundaries(2700.0);
+        let mut state = RunState::new("talk_0123456789ab", 2700.0, false, &spans);
+        state.chunks[0].extracted = true;
+        state.chunks[0].transcribed = true;
+        state.chunks[1].extracted = true;
+        state
+    }
+
+    #[test]
+    fn test_run_state_roundtrip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = state_file_path(dir.path(), Path::new("/videos/talk.mp4")).unwrap();
+        let key = format!("talk_{}", short_hash(b"/videos/talk.mp4", PATH_TAG_LEN));
+        assert_eq!(path, dir.path().join(format!("{}.state.json", key)));
+        assert_eq!(load_state(&path), None);
+
+        let state = StateFile::create(path.clone(), sample_state()).unwrap();
+        state.update(|s| s.chunks[1].transcribed = true).unwrap();
+
+        let loaded = load_state(&path).unwrap();
+        assert!(loaded.chunks[1].transcribed);
+        assert_eq!(loaded.spans(), fixed_chunk_boundaries(2700.0));
+        assert!(!path.with_extension("json.tmp").exists());
+
+        fs::write(&path, "{ not json").unwrap();
+        assert_eq!(load_state(&path), None);
+    }
+
+    #[test]
+    fn test_resumable_state() {
+        let state = sample_state();
+        assert_eq!(
+            resumable_state(Some(state.clone()), "talk_0123456789ab", false),
+            Some(state.clone())
+        );
+        // A re-exported video has a new fingerprint
+        assert_eq!(
+            resumable_state(Some(state.clone()), "talk_ba9876543210", false),
+            None
+        );
+        // Switching chunking mode needs a new plan
+        assert_eq!(
+            resumable_state(Some(state), "talk_0123456789ab", true),
+            None
+        );
+        assert_eq!(resumable_state(None, "talk_0123456789ab", false), None);
+    }
+
+    #[test]
+    fn test_resume_summary() {
+        let mut state = sample_state();
+        assert_eq!(state.resume_summary(), "Resuming: 1/3 chunks already done");
+
+        state.content_generated = true;
+        assert_eq!(
+            state.resume_summary(),
+            "Resuming: content already generated"
+        );
+
+        let short = RunState::new("clip_0123456789ab", 90.0, false, &[]);
+        assert!(short.spans().is_empty());
+        assert_eq!(
+            short.resume_summary(),
+            "Resuming: reusing the saved duration"
+        );
+    }
+
+    fn span(index: u32, start: u32, duration: u32) -> ChunkSpan {
+        ChunkSpan {
+            index,
+            start,
+            duration,
+        }
+    }
+
+    #[test]
+    fn test_format_timestamp() {
+        assert_eq!(format_timestamp(0), "0:00");
+        assert_eq!(format_timestamp(1300), "21:40");
+        assert_eq!(format_timestamp(2600), "43:20");
+        assert_eq!(format_timestamp(3905), "1:05:05");
+    }
+
+    #[test]
+    fn test_assemble_transcript_marks_gaps() {
+        let chunks = [span(0, 0, 1300), span(1, 1300, 1300), span(2, 2600, 400)];
+        let transcripts = BTreeMap::from([(0, "first".to_string()), (2, "last".to_string())]);
+
+        assert_eq!(
+            assemble_transcript(&chunks, &transcripts),
+            "first [MISSING 21:40–43:20] last"
+        );
+    }
+
+    #[test]
+    fn test_assemble_transcript_complete() {
+        let chunks = [span(0, 0, 1300), span(1, 1300, 200)];
+        let transcripts = BTreeMap::from([(1, "b".to_string()), (0, "a".to_string())]);
+
+        assert_eq!(assemble_transcript(&chunks, &transcripts), "a b");
+    }
+
+    const CHAPTERS_REPLY: &str = r#"{"chapters": [
+        {"time": 0, "title": "Intro"},
+        {"time": 192, "title": "Setup"},
+        {"time": 3725, "title": "Q&A"}
+    ]}"#;
+
+    fn parse_chapters(reply: &str) -> Vec<Chapter> {
+        serde_json::from_str::<ChaptersResponse>(reply)
+            .unwrap()
+            .chapters
+    }
+
+    fn sample_content() -> ContentResponse {
+        ContentResponse {
+            titles: vec!["First".to_string(), "Second".to_string()],
+            descriptions: vec!["A description.".to_string()],
+            status_updates: vec!["An update.\n".to_string()],
+            sections: Vec::new(),
+            chapters: vec![
+                Chapter {
+                    time: 0,
+                    title: "Intro".to_string(),
+                },
+                Chapter {
+                    time: 95,
+                    title: "Demo".to_string(),
+                },
+            ],
+            keyframes: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_narration_items() {
+        let mut content = sample_content();
+        content.titles.push("  ".to_string());
+        let items = narration_items("talk_ab12", &content);
+        assert_eq!(
+            items,
+            vec![
+                ("talk_ab12_title_1.mp3".to_string(), "First"),
+                ("talk_ab12_title_2.mp3".to_string(), "Second"),
+                ("talk_ab12_status_1.mp3".to_string(), "An update."),
+            ]
+        );
+        assert!(narration_items("talk", &ContentResponse::default()).is_empty());
+    }
+
+    #[test]
+    fn test_render_markdown() {
+        let markdown = render_markdown("talk", &sample_content(), "Hello world.\n", Some("en"));
+        assert_eq!(
+            markdown,
+            "# talk\n\n\
+             ## Titles\n\n1. First\n2. Second\n\n\
+             ## Descriptions\n\n### Description 1\n\nA description.\n\n\
+             ## Status Updates\n\n### Status Update 1\n\nAn update.\n\n\
+             ## Chapters\n\n00:00 Intro\n01:35 Demo\n\n\
+             ## Transcript\n\nHello world.\n"
+        );
+    }
+
+    #[test]
+    fn test_render_markdown_keyframes() {
+        let mut content = sample_content();
+        content.chapters.clear();
+        content.keyframes = vec![
+            Keyframe {
+                file: "my talk_keyframe_1.jpg".to_string(),
+                time: 12.5,
+                caption: Some("Speaker at the whiteboard".to_string()),
+            },
+            Keyframe {
+                file: "my talk_keyframe_2.jpg".to_string(),
+                time: 95.0,
+                caption: None,
+            },
+        ];
+
+        let markdown = render_markdown("talk", &content, "Hi", Some("en"));
+        assert!(markdown.contains(
+            "## Key Frames\n\n\
+             ![Speaker at the whiteboard](my%20talk_keyframe_1.jpg)\n\n\
+             00:12 Speaker at the whiteboard\n\n\
+             ![](my%20talk_keyframe_2.jpg)\n\n01:35\n\n\
+             ## Transcript"
+        ));
+        assert!(render_markdown("talk", &content, "Hi", None).contains("## 关键帧\n"));
+    }
+
+    #[test]
+    fn test_render_markdown_headings_follow_language() {
+        let mut content = sample_content();
+        content.chapters.clear();
+
+        let markdown = render_markdown("talk", &content, "你好", None);
+        assert!(markdown.contains("## 标题\n"));
+        assert!(markdown.contains("### 描述 1\n"));
+        assert!(markdown.contains("## 完整文字稿\n\n你好\n"));
+        assert!(!markdown.contains("## 章节"));
+
+        assert_eq!(
+            render_markdown("talk", &content, "你好", Some("zh")),
+            markdown
+        );
+    }
+
+    #[test]
+    fn test_render_markdown_skips_empty_sections() {
+        let content = ContentResponse {
+            titles: vec!["Only".to_string()],
+            descriptions: Vec::new(),
+            status_updates: Vec::new(),
+            sections: Vec::new(),
+            chapters: Vec::new(),
+            keyframes: Vec::new(),
+        };
+        assert_eq!(
+            render_markdown("talk", &content, "Hi", Some("en")),
+            "# talk\n\n## Titles\n\n1. Only\n\n## Transcript\n\nHi\n"
+        );
+    }
+
+    const PLATFORMS_PROFILE: &str = r#"
+language: en
+sections:
+  - key: youtube_titles
+    description: Punchy YouTube titles
+    count: 2
+    max_length: 10
+  - key: xhs_posts
+    description: 小红书笔记，轻松活泼
+    count: 1
+    language: zh
+"#;
+
+    #[test]
+    fn test_default_profile_matches_builtin_counts() {
+        let profile = ContentProfile::parse(include_str!("../profiles/default.yaml")).unwrap();
+        let counts = ContentCounts::default();
+        let sections: Vec<(&str, usize)> = profile
+            .sections
+            .iter()
+            .map(|s| (s.key.as_str(), s.count))
+            .collect();
+        assert_eq!(
+            sections,
+            [
+                ("titles", counts.titles),
+                ("descriptions", counts.descriptions),
+                ("status_updates", counts.status_updates)
+            ]
+        );
+        assert_eq!(profile.language, None);
+        assert_eq!(profile.section_language(&profile.sections[0], None), "zh");
+    }
+
+    #[test]
+    fn test_profile_validation_names_section() {
+        let err = |yaml: &str| format!("{:#}", ContentProfile::parse(yaml).unwrap_err());
+
+        assert!(err("sections: []").contains("no sections"));
+        assert!(
+            err("sections:\n  - {key: a, description: x, count: 1}\n  - {key: a, description: y, count: 2}")
+                .contains("section 'a': key is declared more than once")
+        );
+        assert!(
+            err("sections:\n  - {key: Bad Key, description: x, count: 1}")
+                .contains("section 'Bad Key': key must be")
+        );
+        assert!(err("sections:\n  - {key: a, description: x, count: 0}")
+            .contains("section 'a': count must be between 1 and 20"));
+        assert!(
+            err("sections:\n  - {key: a, description: x, count: 1, language: english}")
+                .contains("section 'a': invalid language")
+        );
+        assert!(
+            err("sections:\n  - {key: a, description: x, count: 1, tone: fun}").contains("tone")
+        );
+    }
+
+    #[test]
+    fn test_profile_schema_and_prompt() {
+        let profile = ContentProfile::parse(PLATFORMS_PROFILE).unwrap();
+        assert_eq!(
+            profile.schema()["required"],
+            serde_json::json!(["youtube_titles", "xhs_posts"])
+        );
+        assert_eq!(profile.schema()["additionalProperties"], false);
+
+        let prompt = profile_prompt("text", &profile, Some("ja"), Some("Tokio"));
+        assert!(prompt.starts_with("背景信息"));
+        assert!(prompt.contains(
+            "- \"youtube_titles\": 2 item(s). Punchy YouTube titles. At most 10 characters each. \
+             Write in the language with ISO-639-1 code \"en\"."
+        ));
+        assert!(prompt.contains("- \"xhs_posts\": 1 item(s). 小红书笔记，轻松活泼. Write in the language with ISO-639-1 code \"zh\"."));
+        assert!(prompt.ends_with("Transcript:\ntext"));
+    }
+
+    #[test]
+    fn test_validate_sections() {
+        let profile = ContentProfile::parse(PLATFORMS_PROFILE).unwrap();
+        let reply = |value: serde_json::Value| value.as_object().unwrap().clone();
+
+        let sections = validate_sections(
+            &profile,
+            &reply(serde_json::json!({
+                "youtube_titles": ["Short one", "Another"],
+                "xhs_posts": ["一篇笔记"],
+            })),
+        )
+        .unwrap();
+        assert_eq!(sections[0].key, "youtube_titles");
+        assert_eq!(sections[1].items, ["一篇笔记"]);
+
+        let errors = validate_sections(
+            &profile,
+            &reply(serde_json::json!({ "youtube_titles": ["Much too long title"] })),
+        )
+        .unwrap_err();
+        assert_eq!(
+            errors,
+            [
+                "section 'youtube_titles': count is 2, got 1 items",
+                "section 'youtube_titles': item 1 has 19 characters, max_length is 10",
+                "section 'xhs_posts': missing from the reply",
+            ]
+        );
+
+        let errors = validate_sections(
+            &profile,
+            &reply(serde_json::json!({ "youtube_titles": "x", "xhs_posts": ["a"] })),
+        )
+        .unwrap_err();
+        assert_eq!(
+            errors,
+            ["section 'youtube_titles': expected an array of strings"]
+        );
+    }
+
+    #[test]
+    fn test_profile_sections_in_outputs() {
+        let content = ContentResponse {
+            sections: vec![
+                ContentSection {
+                    key: "youtube_titles".to_string(),
+                    items: vec!["One".to_string(), "Two".to_string()],
+                },
+                ContentSection {
+                    key: "newsletter".to_string(),
+                    items: vec!["Intro.\n\nBody.".to_string()],
+                },
+            ],
+            ..Default::default()
+        };
+        assert_eq!(
+            render_markdown("talk", &content, "Hi", Some("en")),
+            "# talk\n\n\
+             ## youtube_titles\n\n1. One\n2. Two\n\n\
+             ## newsletter\n\n### newsletter 1\n\nIntro.\n\nBody.\n\n\
+             ## Transcript\n\nHi\n"
+        );
+        assert_eq!(
+            format_section(&content.sections[0]),
+            "=== youtube_titles 1 ===\nOne\n\n=== youtube_titles 2 ===\nTwo\n"
+        );
+
+        let profile = ContentProfile::parse(PLATFORMS_PROFILE).unwrap();
+        assert!(!content_matches_profile(&content, Some(&profile)));
+        assert!(!content_matches_profile(&content, None));
+        assert!(content_matches_profile(&sample_content(), None));
+    }
+
+    #[test]
+    fn test_content_prompt_counts() {
+        let default = content_prompt("text", None, &ContentCounts::default(), None);
+        assert!(default.contains("1. 3个吸引人的标题选项"));
+        assert!(default.contains("2. 2段详细的视频描述"));
+        assert!(default.contains("3. 3个bilibili动态更新文案"));
+        assert!(default.contains(r#""titles": ["标题1", "标题2", "标题3"]"#));
+        assert!(default.starts_with("基于以下视频转录内容"));
+        assert!(default.ends_with("转录内容：\ntext"));
+
+        let counts = ContentCounts {
+            titles: 5,
+            descriptions: 0,
+            status_updates: 1,
+        };
+        let prompt = content_prompt("text", Some("en"), &counts, None);
+        assert!(prompt.contains("1. 5个吸引人的标题选项"));
+        assert!(prompt.contains("2. 1个bilibili动态更新文案"));
+        assert!(!prompt.contains("段详细的视频描述"));
+        assert!(prompt.contains(r#""descriptions": []"#));
+        assert!(prompt.contains(r#""status_updates": ["动态1"]"#));
+        assert!(prompt.contains("ISO-639-1 code \"en\""));
+    }
+
+    #[test]
+    fn test_content_prompt_context() {
+        let prompt = content_prompt("text", None, &ContentCounts::default(), Some("Tokio, Axum"));
+        assert!(prompt
+            .starts_with("背景信息（请按此拼写人名、产品名和术语）：\nTokio, Axum\n\n基于以下"));
+        assert!(prompt.ends_with("转录内容：\ntext"));
+    }
+
+    #[test]
+    fn test_fit_transcription_prompt() {
+        assert_eq!(
+            fit_transcription_prompt("Tokio, Axum"),
+            ("Tokio, Axum".to_string(), false)
+        );
+
+        let long = "word ".repeat(400);
+        let (prompt, truncated) = fit_transcription_prompt(&long);
+        assert!(truncated);
+        assert!(estimate_tokens(&prompt) <= TRANSCRIPTION_PROMPT_MAX_TOKENS);
+        assert!(long.starts_with(&prompt));
+
+        let chinese = "字".repeat(300);
+        let (prompt, truncated) = fit_transcription_prompt(&chinese);
+        assert!(truncated);
+        assert_eq!(prompt.chars().count(), TRANSCRIPTION_PROMPT_MAX_TOKENS);
+    }
+
+    #[test]
+    fn test_subtitled_file_name() {
+        assert_eq!(
+            subtitled_file_name(Path::new("/v/talk.final.mkv")),
+            "talk.final_subtitled.mkv"
+        );
+        assert_eq!(subtitled_file_name(Path::new("talk")), "talk_subtitled.mp4");
+    }
+
+    #[test]
+    fn test_soft_subtitle_codec() {
+        assert_eq!(
+            soft_subtitle_codec(Path::new("a_subtitled.MP4")),
+            "mov_text"
+        );
+        assert_eq!(
+            soft_subtitle_codec(Path::new("a_subtitled.mov")),
+            "mov_text"
+        );
+        assert_eq!(soft_subtitle_codec(Path::new("a_subtitled.webm")), "webvtt");
+        assert_eq!(soft_subtitle_codec(Path::new("a_subtitled.mkv")), "srt");
+    }
+
+    #[test]
+    fn test_escape_filter_path() {
+        assert_eq!(
+            escape_filter_path(Path::new("/tmp/a.srt")),
+            r"\'/tmp/a.srt\'"
+        );
+        assert_eq!(
+            escape_filter_path(Path::new("/tmp/it's [v1], ok.srt")),
+            r"\'/tmp/it\'\\\'\'s \[v1\]\, ok.srt\'"
+        );
+    }
+
+    #[test]
+    fn test_join_summaries() {
+        assert_eq!(
+            join_summaries(&["第一段要点 ", "second part"]),
+            "[第1部分摘要]\n第一段要点\n\n[第2部分摘要]\nsecond part"
+        );
+        assert_eq!(join_summaries::<&str>(&[]), "");
+    }
+
+    #[test]
+    fn test_polish_prompt() {
+        assert!(!polish_prompt(false).contains("filler"));
+        assert!(polish_prompt(true).contains("Remove filler words"));
+        assert!(polish_prompt(true).ends_with("cleaned-up transcript only."));
+    }
+
+    #[test]
+    fn test_parse_tolerance() {
+        assert_eq!(parse_tolerance("0.25").unwrap(), 0.25);
+        assert!(parse_tolerance("1.5").is_err());
+        assert!(parse_tolerance("-0.1").is_err());
+        assert!(parse_tolerance("abc").is_err());
+    }
+
+    #[test]
+    fn test_polished_length_ratio() {
+        // Punctuation and line breaks do not count as content
+        assert_eq!(
+            polished_length_ratio("我们今天讲 调度器 然后", "我们今天讲调度器。\n\n然后，"),
+            1.0
+        );
+        assert_eq!(
+            polished_length_ratio("um so we start", "So we start."),
+            9.0 / 11.0
+        );
+        assert_eq!(polished_length_ratio("", "anything"), 1.0);
+    }
+
+    #[test]
+    fn test_count_mismatches() {
+        let content = sample_content();
+        let exact = ContentCounts {
+            titles: 2,
+            descriptions: 1,
+            status_updates: 1,
+        };
+        assert!(count_mismatches(&content, &exact).is_empty());
+
+        let warnings = count_mismatches(&content, &ContentCounts::default());
+        assert_eq!(
+            warnings,
+            vec![
+                "Requested 3 titles but got 2; keeping them",
+                "Requested 2 descriptions but got 1; keeping them",
+                "Requested 3 status updates but got 1; keeping them",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_format_chapters() {
+        let chapters = validate_chapters(parse_chapters(CHAPTERS_REPLY), 4000.0).unwrap();
+        assert_eq!(
+            format_chapters(&chapters),
+            "00:00 Intro\n03:12 Setup\n1:02:05 Q&A"
+        );
+    }
+
+    #[test]
+    fn test_validate_chapters_rejects_non_monotonic_times() {
+        let reply = r#"{"chapters": [
+            {"time": 0, "title": "Intro"},
+            {"time": 300, "title": "Demo"},
+            {"time": 300, "title": "Again"}
+        ]}"#;
+        let err = validate_chapters(parse_chapters(reply), 4000.0).unwrap_err();
+        assert!(err.to_string().contains("must increase"));
+    }
+
+    #[test]
+    fn test_validate_chapters_rejects_times_past_end() {
+        let err = validate_chapters(parse_chapters(CHAPTERS_REPLY), 3600.0).unwrap_err();
+        assert!(err.to_string().contains("past the end"));
+        assert!(validate_chapters(Vec::new(), 3600.0).is_err());
+    }
+
+    #[test]
+    fn test_offset_segments() {
+        let segments = vec![TranscriptSegment {
+            start: 1.5,
+            end: 4.0,
+            text: "hello".to_string(),
+        }];
+        let shifted = offset_segments(segments, 1300);
+
+        assert_eq!(shifted[0].start, 1301.5);
+        assert_eq!(shifted[0].end, 1304.0);
+        assert_eq!(shifted[0].text, "hello");
+    }
+
+    #[test]
+    fn test_parse_progress_seconds() {
+        assert_eq!(parse_progress_seconds("out_time_ms=125500000"), Some(125));
+        assert_eq!(parse_progress_seconds("out_time_us=999999\n"), Some(0));
+        assert_eq!(parse_progress_seconds("out_time_ms=N/A"), None);
+        assert_eq!(parse_progress_seconds("out_time_ms=-23220"), None);
+        assert_eq!(parse_progress_seconds("out_time=00:02:05.500000"), None);
+        assert_eq!(parse_progress_seconds("progress=continue"), None);
+    }
+
+    #[test]
+    fn test_stderr_tail() {
+        let stderr = (1..=30)
+            .map(|i| format!("line {}", i))
+            .collect::<Vec<_>>()
+            .join("\n");
+        let tail = stderr_tail(&stderr, 20);
+        assert!(tail.starts_with("line 11\n"));
+        assert!(tail.ends_with("line 30"));
+        assert_eq!(tail.lines().count(), 20);
+
+        assert_eq!(stderr_tail("only\n\n", 20), "only");
+    }
+
+    fn models(transcribe: &str, chat: &str) -> ModelConfig {
+        ModelConfig {
+            transcribe: transcribe.to_string(),
+            chat: chat.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_price_table_estimate() {
+        let usage = ApiUsage {
+            audio_seconds: 600.0,
+            chat: TokenUsage::new(1_000_000, 500_000),
+        };
+        let cost = PriceTable::default()
+            .estimate(&models("gpt-4o-transcribe", "gpt-5-mini"), &usage)
+            .unwrap();
+        // 10 min * 0.006 + 1M * 0.25/M + 0.5M * 2.0/M
+        assert!((cost - 1.31).abs() < 1e-9);
+
+        // Cached runs cost nothing, even for unknown models
+        assert_eq!(
+            PriceTable::default().estimate(&models("custom", "custom"), &ApiUsage::default()),
+            Some(0.0)
+        );
+        assert_eq!(
+            PriceTable::default().estimate(&models("custom", "gpt-5-mini"), &usage),
+            None
+        );
+    }
+
+    #[test]
+    fn test_price_table_matches_snapshots_by_longest_prefix() {
+        let prices = PriceTable::default();
+        assert_eq!(
+            prices.price("gpt-5-mini-2025-08-07"),
+            prices.price("gpt-5-mini")
+        );
+        assert_eq!(prices.price("gpt-5-2025-08-07"), prices.price("gpt-5"));
+        assert!(prices.price("gpt-5x").is_none());
+    }
+
+    #[test]
+    fn test_price_table_overrides() {
+        let prices = PriceTable::default()
+            .with_overrides(
+                r#"
+                [models.gpt-5-mini]
+                input_per_million = 1.0
+                output_per_million = 4.0
+
+                [models."local-whisper"]
+                per_minute = 0.0
+                "#,
+            )
+            .unwrap();
+        assert_eq!(prices.price("gpt-5-mini").unwrap().input_per_million, 1.0);
+        assert_eq!(prices.price("local-whisper").unwrap().per_minute, 0.0);
+        assert!(prices.price("gpt-4o").is_some());
+
+        let typo = PriceTable::default().with_overrides("[models.gpt-5]\ninput = 1.0\n");
+        assert!(typo.is_err());
+    }
+
+    #[test]
+    fn test_usage_report_json() {
+        let usage = ApiUsage {
+            audio_seconds: 90.0,
+            chat: TokenUsage::new(2000, 500),
+        };
+        let report = UsageReport::new(
+            usage,
+            &models("whisper-1", "gpt-4o-mini"),
+            &PriceTable::default(),
+        );
+        let json: serde_json::Value = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["transcribe_model"], "whisper-1");
+        assert_eq!(json["audio_seconds"], 90.0);
+        assert_eq!(json["prompt_tokens"], 2000);
+        assert_eq!(json["completion_tokens"], 500);
+        assert_eq!(json["total_tokens"], 2500);
+        // 1.5 min * 0.006 + 2000 * 0.15/M + 500 * 0.6/M
+        let cost = json["estimated_cost_usd"].as_f64().unwrap();
+        assert!((cost - 0.0096).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_next_compression_bitrate() {
+        assert_eq!(next_compression_bitrate(32), Some(24));
+        assert_eq!(next_compression_bitrate(24), Some(16));
+        assert_eq!(next_compression_bitrate(16), None);
+        assert_eq!(next_compression_bitrate(8), None);
+        // Higher-quality extractions step down through the whole ladder
+        assert_eq!(next_compression_bitrate(128), Some(32));
+        assert_eq!(next_compression_bitrate(28), Some(24));
+    }
+
+    #[test]
+    fn test_cache_name_changes_when_video_is_modified() {
+        let dir = tempfile::tempdir().unwrap();
+        let video = dir.path().join("lecture.mp4");
+        fs::write(&video, vec![7u8; 3 * 1024 * 1024]).unwrap();
+
+        let original = cache_name(&video).unwrap();
+        assert!(original.starts_with(&format!("{}_", video_key(&video).unwrap())));
+        assert_eq!(
+            original.len(),
+            "lecture_".len() + PATH_TAG_LEN + 1 + FINGERPRINT_LEN
+        );
+        assert_eq!(original, cache_name(&video).unwrap());
+
+        // Same size, different tail: a re-export with a fixed ending
+        let mut content = vec![7u8; 3 * 1024 * 1024];
+        *content.last_mut().unwrap() = 8;
+        fs::write(&video, content).unwrap();
+
+        assert_ne!(original, cache_name(&video).unwrap());
+    }
+
+    #[test]
+    fn test_same_named_videos_never_collide() {
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("shared_output");
+        let mtime = std::time::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let videos: Vec<PathBuf> = ["a", "b"]
+            .iter()
+            .map(|sub| {
+                fs::create_dir(dir.path().join(sub)).unwrap();
+                let video = dir.path().join(sub).join("final.mp4");
+                fs::write(&video, b"identical content").unwrap();
+                fs::File::options()
+                    .write(true)
+                    .open(&video)
+                    .unwrap()
+                    .set_modified(mtime)
+                    .unwrap();
+                video
+            })
+            .collect();
+
+        assert_ne!(
+            video_key(&videos[0]).unwrap(),
+            video_key(&videos[1]).unwrap()
+        );
+        assert_ne!(
+            cache_name(&videos[0]).unwrap(),
+            cache_name(&videos[1]).unwrap()
+        );
+        assert_ne!(
+            state_file_path(&out, &videos[0]).unwrap(),
+            state_file_path(&out, &videos[1]).unwrap()
+        );
+        // Clearing one video's stale artifacts leaves the other's alone
+        assert!(!is_stale_artifact(
+            &format!("{}_transcript.txt", cache_name(&videos[1]).unwrap()),
+            &cache_name(&videos[0]).unwrap()
+        ));
+
+        // Distinct videos lock independently; the same video cannot be locked twice
+        let _first = lock_video(&out, &videos[0]).unwrap();
+        let _second = lock_video(&out, &videos[1]).unwrap();
+        let err = lock_video(&out, &videos[0]).unwrap_err();
+        assert!(err.to_string().contains("already in progress"));
+    }
+
+    #[test]
+    fn test_is_stale_artifact() {
+        let current = "lecture_0123456789ab";
+
+        assert!(is_stale_artifact(
+            "lecture_ffffffffffff_transcript.txt",
+            current
+        ));
+        assert!(is_stale_artifact(
+            "lecture_ffffffffffff_chunk_0_0s.mp3",
+            current
+        ));
+        assert!(!is_stale_artifact(
+            "lecture_0123456789ab_transcript.txt",
+            current
+        ));
+        // Different video whose stem shares a prefix
+        assert!(!is_stale_artifact(
+            "lecture_notes_0123456789ab.mp3",
+            current
+        ));
+        assert!(!is_stale_artifact("other_ffffffffffff.mp3", current));
+        // Cut off too early to hold a fingerprint
+        assert!(!is_stale_artifact("lecture_fff.mp3", current));
+        assert!(!is_stale_artifact("lecture_ffffffffffffabc.mp3", current));
+    }
+
+    #[test]
+    fn test_stale_artifacts_of_videos_sharing_a_prefix() {
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("out");
+        fs::create_dir(&out).unwrap();
+        // The second video's name starts with the first one's key and a
+        // fingerprint-like part
+        let short = dir.path().join("talk.mp4");
+        fs::write(&short, b"short talk").unwrap();
+        let long = dir
+            .path()
+            .join(format!("{}_cafebabecafe.mp4", video_key(&short).unwrap()));
+        fs::write(&long, b"long talk").unwrap();
+
+        let short_name = cache_name(&short).unwrap();
+        let long_name = cache_name(&long).unwrap();
+        let stale_short = format!("{}_ffffffffffff_transcript.txt", video_key(&short).unwrap());
+        for name in [
+            format!("{}_transcript.txt", short_name),
+            stale_short.clone(),
+            format!("{}_transcript.txt", long_name),
+            format!("{}.mp3", long_name),
+            format!("{}_chunk_0_0s.mp3", long_name),
+        ] {
+            fs::write(out.join(name), b"").unwrap();
+        }
+
+        assert!(is_stale_artifact(&stale_short, &short_name));
+        assert!(!is_stale_artifact(
+            &format!("{}_transcript.txt", long_name),
+            &short_name
+        ));
+
+        let removed = clear_stale_artifacts(&out, &short_name).unwrap();
+        assert_eq!(removed, 1);
+        assert!(!out.join(&stale_short).exists());
+        assert!(out.join(format!("{}_transcript.txt", long_name)).exists());
+        assert!(out.join(format!("{}.mp3", long_name)).exists());
+        assert!(out.join(format!("{}_chunk_0_0s.mp3", long_name)).exists());
+    }
+
+    #[test]
+    fn test_clear_stale_artifacts() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in [
+            "talk_aaaaaaaaaaaa_transcript.txt",
+            "talk_aaaaaaaaaaaa.mp3",
+            "talk_bbbbbbbbbbbb_transcript.txt",
+        ] {
+            fs::write(dir.path().join(name), b"").unwrap();
+        }
+
+        let removed = clear_stale_artifacts(dir.path(), "talk_bbbbbbbbbbbb").unwrap();
+
+        assert_eq!(removed, 2);
+        assert!(dir.path().join("talk_bbbbbbbbbbbb_transcript.txt").exists());
+    }
+
+    /// Options of a plain `convert <video>` run
+    fn default_options() -> VideoOptions {
+        let args = Args::parse_from(["convert", "clip.mp4"]);
+        VideoOptions {
+            output_dir: None,
+            language: None,
+            split_on_silence: false,
+            max_concurrent: args.max_concurrent as usize,
+            use_cache: true,
+            fresh: false,
+            keep_download: false,
+            upload: None,
+            clear_cache: false,
+            allow_gaps: false,
+            translate: None,
+            tts_voice: None,
+            polish: None,
+            context: None,
+            transcription_prompt: None,
+            chapters: false,
+            speakers: None,
+            embed_subtitles: None,
+            srt: None,
+            keyframes: None,
+            markdown: true,
+            counts: ContentCounts::default(),
+            profile: None,
+            content_budget: ContentBudget {
+                max_tokens: args.content_budget_tokens as usize,
+                window_tokens: args.content_window_tokens as usize,
+            },
+            audio: AudioSettings {
+                bitrate_kbps: args.audio_bitrate,
+                sample_rate: args.audio_sample_rate,
+                size_limit_mb: args.api_size_limit_mb,
+            },
+            prices: Arc::new(PriceTable::default()),
+            cancel: CancellationToken::new(),
+        }
+    }
+
+    /// `clip.mp4` in `dir` with its output directory, cache name and state
+    /// file; a saved plan and extracted audio stand in for ffprobe and ffmpeg
+    fn short_clip(dir: &Path) -> (PathBuf, PathBuf, String, PathBuf) {
+        let video = dir.join("clip.mp4");
+        fs::write(&video, b"not really a video").unwrap();
+        let output_dir = dir.join("clip_output");
+        fs::create_dir_all(&output_dir).unwrap();
+
+        let video_name = cache_name(&video).unwrap();
+        let state_path = state_file_path(&output_dir, &video).unwrap();
+        StateFile::create(
+            state_path.clone(),
+            RunState::new(&video_name, 42.0, false, &[]),
+        )
+        .unwrap();
+        fs::write(output_dir.join(format!("{}.mp3", video_name)), b"ID3 audio").unwrap();
+        (video, output_dir, video_name, state_path)
+    }
+
+    /// A mock answering the transcription and content calls of [`short_clip`]
+    fn short_clip_mock() -> Arc<MockAiClient> {
+        let mock = Arc::new(MockAiClient::new());
+        mock.push_transcript("大家好，今天聊聊 Rust。")
+            .push_reply(
+                r#"{"titles": ["T1", "T2", "T3"], "descriptions": ["D1", "D2"], "status_updates": ["S1", "S2", "S3"]}"#,
+            );
+        mock
+    }
+
+    fn hidden_progress() -> VideoProgress {
+        VideoProgress::new(
+            MultiProgress::with_draw_target(ProgressDrawTarget::hidden()),
+            None,
+        )
+    }
+
+    #[tokio::test]
+    async fn test_process_short_video_with_mock_client() {
+        let dir = tempfile::tempdir().unwrap();
+        let (video, output_dir, video_name, state_path) = short_clip(dir.path());
+
+        let mock = short_clip_mock();
+        let client: Arc<dyn AiClient> = mock.clone();
+        let progress = hidden_progress();
+
+        let output = process_video(
+            &client,
+            &video,
+            output_dir.clone(),
+            &default_options(),
+            &progress,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            fs::read_to_string(&output.transcript_file).unwrap(),
+            "大家好，今天聊聊 Rust。"
+        );
+        let file = |suffix: &str| output_dir.join(format!("{}_{}", video_name, suffix));
+        assert_eq!(
+            fs::read_to_string(file("titles.txt")).unwrap(),
+            "1. T1\n2. T2\n3. T3"
+        );
+        assert!(file("content.json").exists());
+        assert!(file("usage.json").exists());
+        assert_eq!(output.usage.total_tokens, 15);
+        assert!(load_state(&state_path).unwrap().content_generated);
+
+        let calls = mock.calls();
+        assert_eq!(calls.len(), 2);
+        assert_eq!(
+            calls[0],
+            MockCall::Transcribe {
+                filename: format!("{}.mp3", video_name),
+                language: None,
+                prompt: None,
+                verbose: false,
+            }
+        );
+        let MockCall::Chat { messages, .. } = &calls[1] else {
+            panic!("expected a chat call, got {:?}", calls[1]);
+        };
+        assert!(messages[1].contains("大家好，今天聊聊 Rust。"));
+    }
+
+    #[tokio::test]
+    async fn test_skip_preflight_rerun_from_cached_artifacts_without_api_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let (video, output_dir, video_name, _) = short_clip(dir.path());
+        let mock: Arc<dyn AiClient> = short_clip_mock();
+        process_video(
+            &mock,
+            &video,
+            output_dir.clone(),
+            &default_options(),
+            &hidden_progress(),
+        )
+        .await
+        .unwrap();
+
+        // What main builds under --skip-preflight; any API call would fail
+        unsafe { std::env::remove_var("OPENAI_API_KEY") };
+        let client: Arc<dyn AiClient> = Arc::new(OpenAIClient::new_allow_missing_key().unwrap());
+        let output = process_video(
+            &client,
+            &video,
+            output_dir.clone(),
+            &default_options(),
+            &hidden_progress(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            fs::read_to_string(&output.transcript_file).unwrap(),
+            "大家好，今天聊聊 Rust。"
+        );
+        assert_eq!(
+            fs::read_to_string(output_dir.join(format!("{}_titles.txt", video_name))).unwrap(),
+            "1. T1\n2. T2\n3. T3"
+        );
+    }
+
+    /// Times printed with millisecond precision, as they are passed to ffmpeg
+    fn millis(times: &[f64]) -> Vec<String> {
+        times.iter().map(|t| format!("{:.3}", t)).collect()
+    }
+
+    #[test]
+    fn test_even_keyframe_times() {
+        assert_eq!(
+            millis(&even_keyframe_times(60.0, 4)),
+            ["7.500", "22.500", "37.500", "52.500"]
+        );
+        // Shorter than one second per frame still gets every frame
+        assert_eq!(
+            millis(&even_keyframe_times(3.0, 5)),
+            ["0.300", "0.900", "1.500", "2.100", "2.700"]
+        );
+        // Too short for distinct frames: fewer of them, never past the end
+        assert_eq!(
+            millis(&even_keyframe_times(0.5, 10)),
+            ["0.050", "0.150", "0.250", "0.350", "0.450"]
+        );
+        assert_eq!(millis(&even_keyframe_times(0.04, 3)), ["0.020"]);
+    }
+
+    #[test]
+    fn test_parse_scene_times() {
+        let stderr = "\
+Input #0, mov,mp4,m4a,3gp,3g2,mj2, from 'talk.mp4':
+[Parsed_showinfo_1 @ 0x600003a1c000] config in time_base: 1/12800, frame_rate: 25/1
+[Parsed_showinfo_1 @ 0x600003a1c000] n:   0 pts: 153600 pts_time:12      duration:    512 pos:  1048576 fmt:yuv420p
+[Parsed_showinfo_1 @ 0x600003a1c000] n:   1 pts: 541952 pts_time:42.34   duration:    512 pos:  4194304 fmt:yuv420p
+[out#0/null @ 0x600003a1c0c0] video:0KiB audio:0KiB
+";
+        assert_eq!(parse_scene_times(stderr), vec![12.0, 42.34]);
+        assert!(parse_scene_times("").is_empty());
+    }
+
+    #[test]
+    fn test_choose_scene_times() {
+        // More scene changes than wanted: spread across them
+        let scenes = [3.0, 10.0, 11.0, 30.0, 31.0, 50.0];
+        assert_eq!(choose_scene_times(&scenes, 60.0, 3), vec![10.0, 30.0, 50.0]);
+
+        // Fewer: evenly spaced times away from the scene changes fill in
+        assert_eq!(
+            choose_scene_times(&[16.0, 16.0, 75.0], 60.0, 4),
+            vec![7.5, 16.0, 37.5, 52.5]
+        );
+        assert_eq!(
+            choose_scene_times(&[], 60.0, 4),
+            even_keyframe_times(60.0, 4)
+        );
+    }
+
+    #[test]
+    fn test_keyframe_args() {
+        let args = keyframe_args(
+            Path::new("/videos/talk.mp4"),
+            7.5,
+            Path::new("/out/talk_ab12_keyframe_1.jpg"),
+        );
+        let args: Vec<_> = args.iter().map(|a| a.to_string_lossy()).collect();
+        assert_eq!(
+            args,
+            [
+                "-hide_banner",
+                "-loglevel",
+                "error",
+                "-y",
+                "-ss",
+                "7.500",
+                "-i",
+                "/videos/talk.mp4",
+                "-frames:v",
+                "1",
+                "-vf",
+                KEYFRAME_FILTER,
+                "-q:v",
+                "2",
+                "-update",
+                "1",
+                "/out/talk_ab12_keyframe_1.jpg",
+            ]
+        );
+        assert_eq!(
+            keyframe_file_name("talk_ab12", 3),
+            "talk_ab12_keyframe_3.jpg"
+        );
+    }
+
+    fn ffmpeg_available() -> bool {
+        Command::new("ffmpeg")
+            .arg("-version")
+            .output()
+            .is_ok_and(|output| output.status.success())
+    }
+
+    /// Generate a two-second test pattern video with lavfi
+    fn generate_video(path: &Path, source: &str) {
+        let status = Command::new("ffmpeg")
+            .args([
+                "-hide_banner",
+                "-loglevel",
+                "error",
+                "-y",
+                "-f",
+                "lavfi",
+                "-i",
+            ])
+            .arg(source)
+            .args(["-pix_fmt", "yuv420p"])
+            .arg(path)
+            .status()
+            .unwrap();
+        assert!(status.success());
+    }
+
+    #[tokio::test]
+    async fn test_extract_keyframes_keeps_aspect_ratio() {
+        if !ffmpeg_available() {
+            eprintln!("ffmpeg not found, skipping key frame extraction test");
+            return;
+        }
+        let dir = tempfile::tempdir().unwrap();
+        let progress = VideoProgress::new(
+            MultiProgress::with_draw_target(ProgressDrawTarget::hidden()),
+            None,
+        );
+
+        // Portrait, and anamorphic 320x180 shown at 640x180
+        let cases = [
+            (
+                "portrait",
+                "testsrc=size=720x1600:rate=10:duration=2",
+                (576, 1280),
+            ),
+            (
+                "anamorphic",
+                "testsrc=size=320x180:rate=10:duration=2,setsar=2",
+                (640, 180),
+            ),
+        ];
+        for (name, source, dimensions) in cases {
+            let video = dir.path().join(format!("{}.mp4", name));
+            generate_video(&video, source);
+
+            // Five frames from a video shorter than five seconds
+            let times = even_keyframe_times(2.0, 5);
+            let keyframes = extract_keyframes(&video, name, dir.path(), &times, &progress)
+                .await
+                .unwrap();
+
+            assert_eq!(keyframes.len(), 5);
+            for (i, keyframe) in keyframes.iter().enumerate() {
+                assert_eq!(keyframe.file, keyframe_file_name(name, i + 1));
+                let path = dir.path().join(&keyframe.file);
+                assert_eq!(image::image_dimensions(&path).unwrap(), dimensions);
+            }
+        }
+    }
+
+    #[test]
+    fn test_reuse_captions_and_clean_caption() {
+        let frame = |file: &str, time: f64, caption: Option<&str>| Keyframe {
+            file: file.to_string(),
+            time,
+            caption: caption.map(str::to_string),
+        };
+        let previous = [
+            frame("t_keyframe_1.jpg", 7.5, Some("Title slide")),
+            frame("t_keyframe_2.jpg", 22.5, Some("Live demo")),
+        ];
+        let mut keyframes = [
+            frame("t_keyframe_1.jpg", 7.5, None),
+            frame("t_keyframe_2.jpg", 20.0, None),
+        ];
+        reuse_captions(&mut keyframes, &previous);
+        assert_eq!(keyframes[0].caption.as_deref(), Some("Title slide"));
+        assert_eq!(keyframes[1].caption, None);
+
+        assert_eq!(
+            clean_caption("\n\"A speaker at the whiteboard\"\nExtra").as_deref(),
+            Some("A speaker at the whiteboard")
+        );
+        assert_eq!(
+            clean_caption("“讲者在白板前讲解”").as_deref(),
+            Some("讲者在白板前讲解")
+        );
+        assert_eq!(clean_caption("  \n\"\""), None);
+    }
+
+    #[tokio::test]
+    async fn test_caption_keyframes_with_mock_client() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut keyframes: Vec<Keyframe> = (1..=3)
+            .map(|n| {
+                let file = keyframe_file_name("talk", n);
+                fs::write(dir.path().join(&file), b"\xFF\xD8\xFF\xE0 jpeg").unwrap();
+                Keyframe {
+                    file,
+                    time: n as f64,
+                    caption: None,
+                }
+            })
+            .collect();
+        keyframes[0].caption = Some("Cached caption".to_string());
+
+        // The third frame finds no queued reply and fails
+        let mock = Arc::new(MockAiClient::new());
+        mock.push_reply("\"Live coding in the terminal\"");
+        let client: Arc<dyn AiClient> = mock.clone();
+        let progress = VideoProgress::new(
+            MultiProgress::with_draw_target(ProgressDrawTarget::hidden()),
+            None,
+        );
+        let mut options = default_options();
+        options.language = Some("en".to_string());
+
+        let usage = caption_keyframes(&client, dir.path(), &mut keyframes, &options, &progress)
+            .await
+            .unwrap();
+
+        assert_eq!(keyframes[0].caption.as_deref(), Some("Cached caption"));
+        assert_eq!(
+            keyframes[1].caption.as_deref(),
+            Some("Live coding in the terminal")
+        );
+        assert_eq!(keyframes[2].caption, None);
+        assert_eq!(usage.total_tokens, 15);
+
+        let calls = mock.calls();
+        assert_eq!(calls.len(), 2);
+        let MockCall::Chat { messages, .. } = &calls[0] else {
+            panic!("expected a chat call, got {:?}", calls[0]);
+        };
+        assert!(messages[0].contains("thumbnail caption"));
+        assert!(messages[0].contains("\"en\""));
+    }
+
+    fn numbered_segments(count: usize) -> Vec<TranscriptSegment> {
+        (0..count)
+            .map(|i| TranscriptSegment {
+                start: i as f64,
+                end: i as f64 + 1.0,
+                text: format!(" line {}", i),
+            })
+            .collect()
+    }
+
+    /// Labels as the model would see them, 1 and 2 alternating every `turn` segments
+    fn alternating(range: std::ops::Range<usize>, turn: usize, swapped: bool) -> Vec<Option<u8>> {
+        range
+            .map(|i| {
+                let first = (i / turn).is_multiple_of(2);
+                Some(if first != swapped { 1 } else { 2 })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_speaker_windows() {
+        assert_eq!(
+            speaker_windows(0, 40, 8),
+            Vec::<std::ops::Range<usize>>::new()
+        );
+        assert_eq!(speaker_windows(25, 40, 8), vec![0..25]);
+        assert_eq!(speaker_windows(40, 40, 8), vec![0..40]);
+        assert_eq!(speaker_windows(50, 40, 8), vec![0..40, 32..50]);
+        assert_eq!(speaker_windows(10, 4, 2), vec![0..4, 2..6, 4..8, 6..10]);
+    }
+
+    #[test]
+    fn test_parse_speaker() {
+        assert_eq!(parse_speaker("S1", 2), Some(1));
+        assert_eq!(parse_speaker(" s2 ", 2), Some(2));
+        assert_eq!(parse_speaker("2", 3), Some(2));
+        assert_eq!(parse_speaker("S3", 2), None);
+        assert_eq!(parse_speaker("S0", 2), None);
+        assert_eq!(parse_speaker("Alice", 2), None);
+    }
+
+    #[test]
+    fn test_window_labels_ignores_stray_segments() {
+        let reply = vec![
+            SpeakerLabel {
+                segment: 4,
+                speaker: "S2".to_string(),
+            },
+            SpeakerLabel {
+                segment: 9,
+                speaker: "S1".to_string(),
+            },
+            SpeakerLabel {
+                segment: 5,
+                speaker: "S7".to_string(),
+            },
+        ];
+        assert_eq!(window_labels(&reply, 4..7, 2), vec![Some(2), None, None]);
+    }
+
+    #[test]
+    fn test_best_speaker_mapping() {
+        let previous = [Some(1), Some(2), Some(1), None];
+        // Same numbering
+        assert_eq!(
+            best_speaker_mapping(&previous, &[Some(1), Some(2), Some(1), Some(2)], 2),
+            vec![1, 2]
+        );
+        // Swapped numbering; one disagreement doesn't stop the swap
+        assert_eq!(
+            best_speaker_mapping(&previous, &[Some(2), Some(1), Some(1), Some(1)], 2),
+            vec![2, 1]
+        );
+        // Nothing to compare keeps the window as it is
+        assert_eq!(
+            best_speaker_mapping(&[None, None], &[Some(2), Some(1)], 2),
+            vec![1, 2]
+        );
+        // Three speakers rotated by one
+        assert_eq!(
+            best_speaker_mapping(
+                &[Some(1), Some(2), Some(3)],
+                &[Some(2), Some(3), Some(1)],
+                3
+            ),
+            vec![3, 1, 2]
+        );
+    }
+
+    #[test]
+    fn test_speaker_track_reconciles_swapped_windows() {
+        // The model numbers the speakers the other way round in every second window
+        let windows = speaker_windows(10, 4, 2);
+        let mut track = SpeakerTrack::new(10, 2);
+        for (i, range) in windows.iter().enumerate() {
+            track.merge(range.clone(), &alternating(range.clone(), 3, i % 2 == 1));
+        }
+
+        assert_eq!(track.labels, alternating(0..10, 3, false));
+        assert_eq!(track.swaps, 2);
+    }
+
+    #[test]
+    fn test_speaker_track_keeps_earlier_labels_in_overlap() {
+        let mut track = SpeakerTrack::new(6, 2);
+        track.merge(0..4, &[Some(1), Some(2), Some(1), Some(2)]);
+        // Agrees on segment 2, disagrees on 3: the identity wins the tie and the
+        // earlier label stays
+        track.merge(2..6, &[Some(1), Some(1), Some(2), Some(1)]);
+
+        assert_eq!(
+            track.labels,
+            vec![Some(1), Some(2), Some(1), Some(2), Some(2), Some(1)]
+        );
+        assert_eq!(track.swaps, 0);
+    }
+
+    #[test]
+    fn test_speaker_track_fills_gaps_from_later_windows() {
+        let mut track = SpeakerTrack::new(6, 3);
+        // The first window failed for segments 2 and 3
+        track.merge(0..4, &[Some(1), Some(2), None, None]);
+        // The next window only overlaps on unlabeled segments, so nothing is renamed
+        track.merge(2..6, &[Some(3), Some(1), Some(2), Some(3)]);
+        assert_eq!(
+            track.labels,
+            vec![Some(1), Some(2), Some(3), Some(1), Some(2), Some(3)]
+        );
+        assert_eq!(track.swaps, 0);
+
+        let mut track = SpeakerTrack::new(6, 3);
+        track.merge(0..4, &[Some(1), Some(2), Some(3), Some(1)]);
+        // Speakers rotated in the second window
+        track.merge(2..6, &[Some(1), Some(2), Some(3), None]);
+        assert_eq!(
+            track.labels,
+            vec![Some(1), Some(2), Some(3), Some(1), Some(2), None]
+        );
+        assert_eq!(track.swaps, 1);
+    }
+
+    #[test]
+    fn test_format_labeled_transcript() {
+        let segments = numbered_segments(5);
+        let text = format_labeled_transcript(
+            &segments,
+            &[Some(1), Some(1), Some(2), None, Some(1)],
+            2,
+            "gpt-5-mini",
+        );
+
+        assert_eq!(
+            text,
+            "# Speakers S1-S2 were assigned by gpt-5-mini from conversational cues, not from the voices.\n\
+             # Labels can be wrong, especially for short turns; S? marks segments left unlabeled.\n\
+             \n\
+             S1: line 0 line 1\n\
+             \n\
+             S2: line 2\n\
+             \n\
+             S?: line 3\n\
+             \n\
+             S1: line 4\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_label_speakers_with_mock_client() {
+        let segments = numbered_segments(50);
+        let reply = |range: std::ops::Range<usize>, swapped: bool| {
+            let labels: Vec<_> = range
+                .clone()
+                .zip(alternating(range, 5, swapped))
+                .map(|(i, s)| serde_json::json!({"segment": i, "speaker": format!("S{}", s.unwrap())}))
+                .collect();
+            serde_json::json!({ "labels": labels }).to_string()
+        };
+
+        let mock = Arc::new(MockAiClient::new());
+        mock.push_reply(&reply(0..40, false));
+        mock.push_reply(&reply(32..50, true));
+        let client: Arc<dyn AiClient> = mock.clone();
+        let progress = VideoProgress::new(
+            MultiProgress::with_draw_target(ProgressDrawTarget::hidden()),
+            None,
+        );
+
+        let labeled = label_speakers(&client, &segments, 2, &progress)
+            .await
+            .unwrap();
+
+        assert_eq!(labeled.usage.total_tokens, 30);
+        let turns: Vec<_> = labeled
+            .value
+            .lines()
+            .filter(|line| line.starts_with('S'))
+            .collect();
+        assert_eq!(turns.len(), 10);
+        assert!(turns[0].starts_with("S1: line 0 line 1"));
+        assert!(turns[7].starts_with("S2: line 35 line 36"));
+        assert!(turns[8].starts_with("S1: line 40"));
+
+        let calls = mock.calls();
+        assert_eq!(calls.len(), 2);
+        let MockCall::Chat { messages, .. } = &calls[1] else {
+            panic!("expected a chat call, got {:?}", calls[1]);
+        };
+        assert!(messages[0].contains("S1 to S2"));
+        // The overlap carries the labels of the first window
+        assert!(messages[1].starts_with("[32] (earlier: S1) line 32\n"));
+        assert!(messages[1].contains("[39] (earlier: S2) line 39\n[40] line 40"));
+    }
+
+    #[test]
+    fn test_batch_summary_plain_output() {
+        let usage = UsageReport {
+            transcribe_model: "gpt-4o-transcribe".to_string(),
+            chat_model: "gpt-5-mini".to_string(),
+            audio_seconds: 600.0,
+            prompt_tokens: 1000,
+            completion_tokens: 200,
+            total_tokens: 1200,
+            estimated_cost_usd: Some(0.0612),
+        };
+        let reports = vec![
+            VideoReport {
+                video: PathBuf::from("talks/intro.mp4"),
+                result: Ok(VideoOutput {
+                    output_dir: PathBuf::from("out"),
+                    transcript_file: PathBuf::from("out/intro_ab12_transcript.txt"),
+                    transcript_chars: 5321,
+                    usage,
+                    upload_errors: vec!["intro_ab12.md: access denied".to_string()],
+                }),
+            },
+            VideoReport {
+                video: PathBuf::from("talks/keynote-2026.mov"),
+                result: Err(anyhow::anyhow!("ffprobe failed: Invalid data")),
+            },
+        ];
+
+        // What CI logs get: no emoji and no ANSI styling
+        ui::init(true, true);
+        let plain = batch_summary_lines(&reports).join("\n");
+        let rule = "═".repeat(70);
+        assert_eq!(
+            plain,
+            format!(
+                "\n\
+                 {rule}\n\
+                 Video             Status  Transcript  Output\n\
+                 intro.mp4         ok         5321 ch  out/intro_ab12_transcript.txt\n\
+                 keynote-2026.mov  failed           -  ffprobe failed: Invalid data\n\
+                 {rule}\n\
+                 Summary: 1 succeeded, 1 failed\n\
+                 Estimated cost: $0.0612\n\
+                 Uploads failed for 1 videos (their conversion succeeded)\n\
+                 All files saved in out"
+            )
+        );
+    }
+}
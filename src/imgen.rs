@@ -1,16 +1,68 @@
 use anyhow::{Context, Result};
-use clap::Parser;
+use clap::{Parser, ValueEnum};
 use console::style;
-use indicatif::{ProgressBar, ProgressStyle};
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::webp::WebPEncoder;
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageFormat};
+use indicatif::ProgressStyle;
+use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
 use slug::slugify;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs;
+use std::io::{Cursor, IsTerminal, Write};
 use std::path::{Path, PathBuf};
-use std::sync::Arc;
-use swiss_knife::OpenAIClient;
-use tokio::sync::Semaphore;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use swiss_knife::{
+    status,
+    ui::{self, Emoji},
+    with_retry_hook, AiClient, ImageOptions, ModelConfig, OpenAIClient, OpenAIError, RetryConfig,
+    RetryHook,
+};
+use tokio::sync::{Notify, OwnedSemaphorePermit, Semaphore};
+use walkdir::WalkDir;
 
-const MAX_CONCURRENT_REQUESTS: usize = 32;
+// Outcome markers; text symbols stand in when emoji are off
+static CHECK: Emoji<'_, '_> = Emoji("✅", "✓");
+static CROSS: Emoji<'_, '_> = Emoji("❌", "✗");
+static BLOCKED: Emoji<'_, '_> = Emoji("🚫", "✗");
+static WARNING: Emoji<'_, '_> = Emoji("⚠️", "⚠");
+
+/// Generation record written to the output root
+const MANIFEST_FILE: &str = "manifest.json";
+
+/// Config of the tasks that failed, written to the output root for --retry-file
+const FAILURES_FILE: &str = "imgen-failures.yaml";
+
+/// Task list of the current run, written to the output directory for --resume
+const RUN_FILE: &str = ".imgen-run.json";
+
+/// --gallery page and its thumbnail directory, both in the output root
+const GALLERY_FILE: &str = "gallery.html";
+const THUMBS_DIR: &str = ".thumbs";
+/// Longest side of a gallery thumbnail
+const THUMB_SIZE: u32 = 400;
+
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+/// Zero-length IEND chunk with its CRC: the last 12 bytes of a complete PNG
+const PNG_IEND: [u8; 12] = [0, 0, 0, 0, b'I', b'E', b'N', b'D', 0xAE, 0x42, 0x60, 0x82];
+
+/// How long --adaptive waits without a 429 before allowing one more request in flight
+const THROTTLE_COOLDOWN: Duration = Duration::from_secs(30);
+
+/// Sizes accepted by the image models (gpt-image-1 and DALL-E 2/3)
+const IMAGE_SIZES: [&str; 8] = [
+    "1024x1024",
+    "1536x1024",
+    "1024x1536",
+    "1792x1024",
+    "1024x1792",
+    "512x512",
+    "256x256",
+    "auto",
+];
 
 #[derive(Parser)]
 #[command(
@@ -19,377 +71,3921 @@ const MAX_CONCURRENT_REQUESTS: usize = 32;
     author = "Tyr Chen <tyr.chen@gmail.com>",
     about = "Generate images from YAML configuration using OpenAI's DALL-E API",
     long_about = "Batch generate images using OpenAI's DALL-E based on YAML configuration. \
-                  Supports multiple themes and prompts, parallel processing (up to 32 concurrent requests), \
+                  Supports multiple themes and prompts, parallel processing (8 concurrent requests by default), \
                   and automatic caching to skip previously generated images.",
     after_help = "Examples:\n  \
                   imgen config.yaml                       # Generate images from YAML config\n  \
-                  imgen themes.yaml                       # Process multiple themes and prompts\n\n\
+                  imgen themes.yaml                       # Process multiple themes and prompts\n  \
+                  imgen products/ -o ~/images             # Every YAML in a directory (-r to descend)\n  \
+                  imgen config.yaml --model dall-e-3      # Use a different image model\n  \
+                  imgen config.yaml --quality high --background transparent  # Tune the output\n  \
+                  imgen config.yaml -o ~/images           # Write theme folders under ~/images\n  \
+                  imgen config.yaml --group-by prompt     # One folder per prompt instead of per theme\n  \
+                  imgen config.yaml --gallery             # Also write gallery.html to review the results\n  \
+                  imgen config.yaml --dry-run             # List tasks and estimate the cost offline\n  \
+                  imgen config.yaml --yes                 # Skip the confirmation for runs over 50 images\n  \
+                  imgen config.yaml --concurrency 4 --adaptive  # Back off when rate limited\n  \
+                  imgen --retry-file out/imgen-failures.yaml  # Rerun the tasks that failed\n  \
+                  imgen config.yaml --resume              # Continue an interrupted run in its order\n  \
+                  imgen --inspect out/nature/sunset-1a2b3c.png  # Show the prompt an image came from\n  \
+                  imgen config.yaml --format webp --max-width 1200  # Smaller files for the web\n  \
+                  imgen config.yaml --var brand=Acme      # Override a YAML variable\n  \
+                  imgen config.yaml --dry-run --show-prompts  # Also print the expanded prompts\n\n\
                   YAML Configuration Format:\n  \
                   system_prompt: \"...\"                    # Base instructions for all images\n  \
-                  style: \"minimalist\"                     # Art style to apply\n  \
+                  style: \"minimalist watercolor\"          # Art style appended to every prompt\n  \
+                  aspect: \"landscape\"                     # Optional: square, landscape, portrait\n  \
+                  model: \"gpt-image-1\"                    # Optional; CLI flags win\n  \
+                  quality: \"high\"                         # Optional: low, medium, high, auto\n  \
+                  background: \"transparent\"               # Optional: opaque, transparent\n  \
+                  variables:                              # Optional {name} placeholders ({{ for a brace)\n    \
+                  brand: \"Acme\"\n  \
                   themes:                                 # List of themes\n    \
                   - name: \"Nature\"\n      \
-                  instructions: \"...\"\n  \
+                  instructions: \"...\"\n      \
+                  aspect: \"landscape\"                   # Optional; overrides style\n  \
                   prompts:                                # List of prompts\n    \
                   - name: \"Sunset\"\n      \
-                  prompt: \"...\"\n\n\
+                  prompt: \"...\"\n      \
+                  reference: \"logo.png\"                 # Optional: edit this image (mask: for a mask)\n      \
+                  size: \"1024x1024\"                     # Optional; overrides theme and style\n\n\
                   Requirements:\n  \
-                  - OPENAI_API_KEY environment variable set\n\n\
+                  - OPENAI_API_KEY environment variable set\n  \
+                  - Optional: OPENAI_IMAGE_MODEL to change the default model\n  \
+                  - Optional: OPENAI_TIMEOUT_SECS to change the API request timeout\n  \
+                  - Optional: OPENAI_ORG_ID / OPENAI_PROJECT_ID to bill a specific organization or project\n  \
+                  - Optional: HTTPS_PROXY / NO_PROXY, and OPENAI_CA_BUNDLE (PEM) for a TLS-intercepting proxy\n  \
+                  - Optional: LOG_LEVEL=debug to log each API request, LOG_LEVEL=swiss_knife=trace to add (redacted) bodies\n\n\
+                  Azure OpenAI:\n  \
+                  - OPENAI_PROVIDER=azure and OPENAI_API_KEY set to the resource's key\n  \
+                  - OPENAI_BASE_URL=https://<resource>.openai.azure.com\n  \
+                  - OPENAI_API_VERSION, e.g. 2025-04-01-preview\n  \
+                  - Optional: OPENAI_IMAGE_DEPLOYMENT (default: named after the model)\n\n\
                   Features:\n  \
-                  - Concurrent image generation (32 max)\n  \
+                  - Concurrent image generation (--concurrency, optionally adaptive)\n  \
                   - Smart caching (skips existing images)\n  \
+                  - Retries rate limits and server errors with backoff\n  \
+                  - Waits for the reset when the rate limit headers show the budget nearly used up\n  \
                   - Progress tracking with status\n  \
-                  - Organized output by theme and prompt\n\n\
+                  - Organized output by theme, by prompt or flat (--group-by)\n  \
+                  - manifest.json mapping each file to its prompt and settings\n  \
+                  - Ctrl-C cancels outstanding requests, reported as failures for --retry-file\n  \
+                  - .imgen-run.json tracks the run's tasks so --resume can finish it after a crash\n\n\
                   For more information: https://github.com/tyrchen/swiss-knife"
 )]
 struct Args {
-    /// Path to the YAML configuration file
-    #[arg(value_name = "YAML_FILE")]
-    yaml_file: PathBuf,
-}
+    /// YAML configuration files, or directories of them (*.yaml, *.yml)
+    #[arg(value_name = "YAML_FILE", required_unless_present_any = ["retry_file", "inspect"])]
+    yaml_files: Vec<PathBuf>,
 
-#[derive(Serialize, Deserialize, Debug)]
-struct Config {
-    system_prompt: String,
-    style: String,
-    themes: Vec<Theme>,
-    prompts: Vec<Prompt>,
-}
+    /// Rerun the failed tasks saved in an imgen-failures.yaml; output goes
+    /// next to it unless --output-dir is given
+    #[arg(long, value_name = "PATH", conflicts_with = "yaml_files")]
+    retry_file: Option<PathBuf>,
 
-#[derive(Serialize, Deserialize, Debug)]
-struct Theme {
-    name: String,
-    instructions: String,
-}
+    /// Continue the run recorded in .imgen-run.json in the output directory:
+    /// only its unfinished tasks are generated, in their original order
+    #[arg(long, conflicts_with_all = ["dry_run", "inspect"])]
+    resume: bool,
 
-#[derive(Serialize, Deserialize, Debug)]
-struct Prompt {
-    name: String,
-    prompt: String,
-}
+    /// Print the metadata embedded in a generated PNG and exit
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["yaml_files", "retry_file"])]
+    inspect: Option<PathBuf>,
 
-#[derive(Debug, Clone)]
-struct ImageTask {
-    theme_name: String,
-    prompt_name: String,
-    full_prompt: String,
-    output_path: PathBuf,
-    _hash: String,
-    size: String,
-}
+    /// Also read config files in subdirectories of the given directories
+    #[arg(short, long)]
+    recursive: bool,
 
-impl Config {
-    fn get_image_size(&self) -> &str {
-        match self.style.as_str() {
-            "square" => "1024x1024",
-            "landscape" => "1536x1024",
-            "portrait" => "1024x1536",
-            _ => "1024x1024", // default to square
-        }
-    }
-}
+    /// Directory under which theme subdirectories are created; with several
+    /// config files, each gets its own subdirectory named after the file
+    /// (default: .)
+    #[arg(short, long, value_name = "DIR")]
+    output_dir: Option<PathBuf>,
 
-fn calculate_hash(system_prompt: &str, theme_instruction: &str, prompt: &str) -> String {
-    let combined = format!("{}{}{}", system_prompt, theme_instruction, prompt);
-    let hash = blake3::hash(combined.as_bytes());
-    format!("{:.6}", hash.to_hex())
-}
+    /// Image model (default: `model` in the YAML, $OPENAI_IMAGE_MODEL or gpt-image-1)
+    #[arg(long, alias = "image-model", value_name = "MODEL")]
+    model: Option<String>,
 
-fn create_output_filename(prompt_name: &str, hash: &str) -> String {
-    let slug = slugify(prompt_name);
-    format!("{}-{}.png", slug, hash)
-}
+    /// Rendering quality (default: `quality` in the YAML, else the model's default)
+    #[arg(long, value_enum)]
+    quality: Option<Quality>,
 
-async fn process_config(config_path: &Path) -> Result<()> {
-    // Read and parse YAML config
-    let config_content = fs::read_to_string(config_path)
-        .with_context(|| format!("Failed to read config file: {}", config_path.display()))?;
+    /// Background of the generated images (default: `background` in the YAML,
+    /// else the model's default)
+    #[arg(long, value_enum)]
+    background: Option<Background>,
 
-    let config: Config =
-        serde_yaml::from_str(&config_content).context("Failed to parse YAML configuration")?;
+    /// Print the resolved tasks and an estimated cost without calling the API
+    #[arg(long)]
+    dry_run: bool,
 
-    println!(
-        "{}",
-        style(format!(
-            "📝 Loaded config with {} themes and {} prompts",
-            config.themes.len(),
-            config.prompts.len()
-        ))
-        .green()
-        .bold()
-    );
+    /// With --dry-run, also print each task's full prompt
+    #[arg(long, requires = "dry_run")]
+    show_prompts: bool,
 
-    // Create OpenAI client
-    let client = OpenAIClient::new().context("Failed to create OpenAI client")?;
+    /// Price in USD per image for the --dry-run estimate (default: built-in
+    /// per-size prices)
+    #[arg(long, value_name = "USD")]
+    price_per_image: Option<f64>,
 
-    // Generate tasks for all theme-prompt combinations
-    let mut tasks_by_theme: Vec<Vec<ImageTask>> = Vec::new();
-    let image_size = config.get_image_size();
+    /// Ask for confirmation when more than N new images would be generated
+    #[arg(long, value_name = "N", default_value = "50")]
+    confirm_over: usize,
 
-    for theme in &config.themes {
-        // Create theme directory
-        let theme_dir = Path::new(&theme.name);
-        if !theme_dir.exists() {
-            fs::create_dir_all(theme_dir)
-                .with_context(|| format!("Failed to create directory: {}", theme_dir.display()))?;
-        }
+    /// Don't ask for confirmation before large runs
+    #[arg(short, long)]
+    yes: bool,
 
-        let mut theme_tasks = Vec::new();
+    /// Attempts per image; rate limits (429), server errors and network
+    /// failures are retried with exponential backoff
+    #[arg(long, value_name = "N", default_value = "3", value_parser = clap::value_parser!(u32).range(1..=10))]
+    max_attempts: u32,
 
-        for prompt in &config.prompts {
-            // Calculate hash for this combination
-            let hash = calculate_hash(&config.system_prompt, &theme.instructions, &prompt.prompt);
+    /// Timeout of each image request in seconds; timed-out requests are
+    /// retried (default: $OPENAI_TIMEOUT_SECS or 180)
+    #[arg(long, value_name = "SECS", value_parser = clap::value_parser!(u64).range(1..))]
+    timeout: Option<u64>,
 
-            // Create full prompt combining system prompt, theme instructions, and specific prompt
-            let full_prompt = format!(
-                "{}\n\n{}\n\n{}",
-                config.system_prompt, theme.instructions, prompt.prompt
-            );
+    /// Maximum image requests in flight
+    #[arg(long, value_name = "N", default_value = "8", value_parser = clap::value_parser!(u16).range(1..=64))]
+    concurrency: u16,
 
-            // Generate output filename and path
-            let filename = create_output_filename(&prompt.name, &hash);
-            let output_path = theme_dir.join(&filename);
+    /// Maximum image requests in flight per theme (default: unlimited), so a
+    /// slow theme can't take every --concurrency slot
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u16).range(1..))]
+    per_theme_concurrency: Option<u16>,
 
-            // Check if image already exists
-            if output_path.exists() {
-                println!(
-                    "{}",
-                    style(format!(
-                        "⏭️  Skipping existing image: {}",
-                        output_path.display()
-                    ))
-                    .yellow()
-                );
-                continue;
-            }
+    /// Halve the requests in flight whenever a 429 is seen, then ramp back up
+    /// by one every 30s without rate limiting
+    #[arg(long)]
+    adaptive: bool,
 
-            theme_tasks.push(ImageTask {
-                theme_name: theme.name.clone(),
-                prompt_name: prompt.name.clone(),
-                full_prompt,
-                output_path,
-                _hash: hash,
-                size: image_size.to_string(),
-            });
-        }
+    /// Directory layout: theme/prompt-hash, prompt/theme-hash or a flat
+    /// theme-prompt-hash; images saved under another layout are reused
+    #[arg(long, value_enum, default_value = "theme")]
+    group_by: GroupBy,
 
-        if !theme_tasks.is_empty() {
-            tasks_by_theme.push(theme_tasks);
+    /// Write gallery.html to the output directory: thumbnails grouped by
+    /// theme, with placeholders for failed images
+    #[arg(long)]
+    gallery: bool,
+
+    /// Don't write manifest.json to the output directory
+    #[arg(long)]
+    no_manifest: bool,
+
+    /// File format the returned PNGs are converted to (WebP is encoded lossless)
+    #[arg(long, value_enum, default_value = "png")]
+    format: OutputFormat,
+
+    /// Scale images wider than this down to this width, keeping the aspect ratio
+    #[arg(long, value_name = "PX", value_parser = clap::value_parser!(u32).range(16..))]
+    max_width: Option<u32>,
+
+    /// JPEG quality for --format jpeg
+    #[arg(long, value_name = "1-100", default_value = "85", value_parser = clap::value_parser!(u8).range(1..=100))]
+    jpeg_quality: u8,
+
+    /// Also keep the PNG returned by the API when converting or resizing
+    #[arg(long)]
+    keep_original: bool,
+
+    /// Store the prompt, theme, model, size and creation time in PNG text
+    /// chunks (read them back with --inspect)
+    #[arg(long, value_name = "BOOL", default_value_t = true, action = clap::ArgAction::Set)]
+    embed_metadata: bool,
+
+    /// Set a template variable, overriding `variables` in the YAML (repeatable)
+    #[arg(long = "var", value_name = "KEY=VALUE", value_parser = parse_variable)]
+    variables: Vec<(String, String)>,
+
+    /// Replace emoji with plain text (also the default when stdout is
+    /// not a terminal or when the locale is not UTF-8)
+    #[arg(long)]
+    no_emoji: bool,
+}
+
+impl Args {
+    /// Config files and directories to process
+    fn inputs(&self) -> Vec<PathBuf> {
+        match &self.retry_file {
+            Some(retry_file) => vec![retry_file.clone()],
+            None => self.yaml_files.clone(),
         }
     }
 
-    // Interleave tasks from different themes for better distribution
-    let mut tasks = Vec::new();
-    let max_prompts = tasks_by_theme.iter().map(|t| t.len()).max().unwrap_or(0);
+    /// --output-dir, defaulting to the retry file's directory
+    fn output_dir(&self) -> PathBuf {
+        if let Some(output_dir) = &self.output_dir {
+            return output_dir.clone();
+        }
+        match self.retry_file.as_deref().and_then(Path::parent) {
+            Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+            _ => PathBuf::from("."),
+        }
+    }
 
-    for i in 0..max_prompts {
-        for theme_tasks in &tasks_by_theme {
-            if i < theme_tasks.len() {
-                tasks.push(theme_tasks[i].clone());
-            }
+    /// Flags a rerun needs to find the images that were already saved
+    fn rerun_flags(&self) -> String {
+        let mut flags = String::new();
+        if self.format != OutputFormat::Png {
+            flags.push_str(&format!(" --format {}", self.format.extension()));
+        }
+        if let Some(max_width) = self.max_width {
+            flags.push_str(&format!(" --max-width {}", max_width));
+        }
+        if self.format == OutputFormat::Jpeg && self.jpeg_quality != 85 {
+            flags.push_str(&format!(" --jpeg-quality {}", self.jpeg_quality));
+        }
+        if self.keep_original {
+            flags.push_str(" --keep-original");
         }
+        if self.group_by != GroupBy::Theme {
+            flags.push_str(&format!(" --group-by {}", self.group_by.as_str()));
+        }
+        if self.no_manifest {
+            flags.push_str(" --no-manifest");
+        }
+        flags
     }
+}
 
-    if tasks.is_empty() {
-        println!("{}", style("✅ All images already exist!").green().bold());
-        return Ok(());
+fn parse_variable(value: &str) -> Result<(String, String)> {
+    let (key, value) = value
+        .split_once('=')
+        .with_context(|| format!("Invalid variable '{}': expected KEY=VALUE", value))?;
+    let key = key.trim();
+    if key.is_empty() {
+        anyhow::bail!("Invalid variable '{}={}': empty name", key, value);
     }
+    Ok((key.to_string(), value.to_string()))
+}
 
-    println!(
-        "{}",
-        style(format!("🎨 Generating {} new images...", tasks.len()))
-            .cyan()
-            .bold()
-    );
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum GroupBy {
+    Theme,
+    Prompt,
+    Flat,
+}
 
-    // Create progress bar
-    let pb = Arc::new(ProgressBar::new(tasks.len() as u64));
-    pb.set_style(
-        ProgressStyle::with_template(
-            "{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} {msg}",
-        )?
-        .progress_chars("#>-"),
-    );
-    pb.set_message("Generating images...");
-    pb.enable_steady_tick(std::time::Duration::from_millis(100));
+impl GroupBy {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Theme => "theme",
+            Self::Prompt => "prompt",
+            Self::Flat => "flat",
+        }
+    }
 
-    // Use semaphore to limit concurrent requests (OpenAI has rate limits)
-    let semaphore = Arc::new(Semaphore::new(MAX_CONCURRENT_REQUESTS));
-    let client = Arc::new(client);
+    /// Path of an image relative to the output root
+    fn relative_path(
+        self,
+        theme_name: &str,
+        prompt_name: &str,
+        hash: &str,
+        output: &OutputSettings,
+    ) -> PathBuf {
+        match self {
+            Self::Theme => Path::new(&theme_dir_name(theme_name)).join(create_output_filename(
+                prompt_name,
+                hash,
+                output,
+            )),
+            Self::Prompt => Path::new(&slug_or(prompt_name, "prompt"))
+                .join(create_output_filename(theme_name, hash, output)),
+            Self::Flat => PathBuf::from(create_output_filename(
+                &format!("{}-{}", theme_name, prompt_name),
+                hash,
+                output,
+            )),
+        }
+    }
+}
 
-    // Create concurrent tasks
-    let mut handles = Vec::new();
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+    Png,
+    Webp,
+    Jpeg,
+}
 
-    for task in tasks {
-        let client = Arc::clone(&client);
-        let semaphore = Arc::clone(&semaphore);
-        let pb_clone = Arc::clone(&pb);
-        let theme_name = task.theme_name.clone();
-        let prompt_name = task.prompt_name.clone();
+impl OutputFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            Self::Png => "png",
+            Self::Webp => "webp",
+            Self::Jpeg => "jpg",
+        }
+    }
+}
 
-        let handle = tokio::spawn(async move {
-            // Acquire semaphore permit
-            let _permit = semaphore.acquire().await.unwrap();
+/// How returned images are post-processed before saving
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct OutputSettings {
+    format: OutputFormat,
+    max_width: Option<u32>,
+    jpeg_quality: u8,
+    keep_original: bool,
+    /// Add text chunks describing the image to PNG output
+    embed_metadata: bool,
+}
 
-            // Update progress bar message
-            pb_clone.set_message(format!("Processing {}/{}", theme_name, prompt_name));
+impl Default for OutputSettings {
+    fn default() -> Self {
+        Self {
+            format: OutputFormat::Png,
+            max_width: None,
+            jpeg_quality: 85,
+            keep_original: false,
+            embed_metadata: true,
+        }
+    }
+}
 
-            let result = generate_and_save_image(&client, &task).await;
+impl OutputSettings {
+    /// Whether the saved file differs from the PNG returned by the API
+    fn converts(&self) -> bool {
+        self.format != OutputFormat::Png || self.max_width.is_some()
+    }
+}
 
-            // Update progress
-            pb_clone.inc(1);
+/// Concurrency limit shared by the image requests
+///
+/// A fixed throttle behaves like a semaphore. An adaptive one halves its limit
+/// on every rate limit and, after a cooldown without one, raises it by one per
+/// cooldown until it is back at the maximum.
+struct Throttle {
+    state: Mutex<ThrottleState>,
+    released: Notify,
+}
 
-            (prompt_name, theme_name, result)
-        });
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct ThrottleState {
+    max: usize,
+    limit: usize,
+    in_flight: usize,
+    adaptive: bool,
+    cooldown: Duration,
+    /// Last rate limit or ramp-up step
+    calm_since: Instant,
+}
 
-        handles.push(handle);
+impl ThrottleState {
+    fn new(max: usize, adaptive: bool, cooldown: Duration, now: Instant) -> Self {
+        Self {
+            max,
+            limit: max,
+            in_flight: 0,
+            adaptive,
+            cooldown,
+            calm_since: now,
+        }
     }
 
-    // Wait for all tasks to complete
-    let results = futures::future::join_all(handles).await;
+    fn on_rate_limited(&mut self, now: Instant) {
+        if self.adaptive {
+            self.limit = (self.limit / 2).max(1);
+            self.calm_since = now;
+        }
+    }
 
-    pb.finish_and_clear();
+    fn ramp_up(&mut self, now: Instant) {
+        if self.limit < self.max && now.duration_since(self.calm_since) >= self.cooldown {
+            self.limit += 1;
+            self.calm_since = now;
+        }
+    }
 
-    // Count successes and failures, and collect errors
-    let mut success_count = 0;
-    let mut failures = Vec::new();
+    fn try_acquire(&mut self, now: Instant) -> bool {
+        self.ramp_up(now);
+        if self.in_flight < self.limit {
+            self.in_flight += 1;
+            true
+        } else {
+            false
+        }
+    }
+}
 
-    for result in results {
-        match result {
-            Ok((prompt_name, theme_name, Ok(_))) => {
-                success_count += 1;
-                println!("{}  {}/{}", style("✅").green(), theme_name, prompt_name);
-            }
-            Ok((prompt_name, theme_name, Err(e))) => {
-                failures.push((prompt_name, theme_name, e.to_string()));
-            }
-            Err(e) => {
-                failures.push(("Unknown".to_string(), "Unknown".to_string(), e.to_string()));
+impl Throttle {
+    fn new(max: usize, adaptive: bool) -> Self {
+        Self {
+            state: Mutex::new(ThrottleState::new(
+                max,
+                adaptive,
+                THROTTLE_COOLDOWN,
+                Instant::now(),
+            )),
+            released: Notify::new(),
+        }
+    }
+
+    async fn acquire(&self) -> ThrottlePermit<'_> {
+        loop {
+            if self.state.lock().unwrap().try_acquire(Instant::now()) {
+                return ThrottlePermit(self);
             }
+            // Also wake up periodically: the limit may ramp up without a release
+            let _ = tokio::time::timeout(Duration::from_secs(1), self.released.notified()).await;
         }
     }
 
-    // Print failures if any
-    for (prompt_name, theme_name, error) in &failures {
-        eprintln!(
-            "{}  {}/{}: {}",
-            style("❌").red(),
-            theme_name,
-            prompt_name,
-            error
-        );
+    fn on_rate_limited(&self) {
+        self.state.lock().unwrap().on_rate_limited(Instant::now());
     }
+}
 
-    // Print summary
-    println!();
-    if failures.is_empty() {
-        println!(
-            "{}",
-            style(format!(
-                "🎉 All {} images generated successfully!",
-                success_count
-            ))
-            .green()
-            .bold()
-        );
-    } else {
-        println!(
-            "{}",
-            style(format!(
-                "🎉 Image generation completed! Success: {}, Failed: {}",
-                success_count,
-                failures.len()
-            ))
-            .yellow()
-            .bold()
-        );
+/// Returns its slot to the throttle when dropped
+struct ThrottlePermit<'a>(&'a Throttle);
+
+impl Drop for ThrottlePermit<'_> {
+    fn drop(&mut self) {
+        self.0.state.lock().unwrap().in_flight -= 1;
+        self.0.released.notify_one();
     }
+}
 
-    Ok(())
+/// Per-theme caps layered under the global throttle
+///
+/// A task takes its theme's slot before a global one, so tasks of a theme at
+/// its cap wait without holding slots other themes could use.
+struct ThemeLimits {
+    limit: Option<usize>,
+    /// Keyed by config index and theme name
+    semaphores: Mutex<HashMap<(usize, String), Arc<Semaphore>>>,
 }
 
-async fn generate_and_save_image(client: &Arc<OpenAIClient>, task: &ImageTask) -> Result<()> {
-    // Generate image (returns bytes directly now)
-    let image_data = client
-        .generate_image(&task.full_prompt, &task.size)
-        .await
-        .context("Failed to generate image")?;
+impl ThemeLimits {
+    fn new(limit: Option<usize>) -> Self {
+        Self {
+            limit,
+            semaphores: Mutex::new(HashMap::new()),
+        }
+    }
 
-    // Save image to file
-    fs::write(&task.output_path, image_data)
-        .with_context(|| format!("Failed to save image to {}", task.output_path.display()))?;
+    /// Wait for a slot of the theme; `None` without a limit
+    async fn acquire(&self, config: usize, theme: &str) -> Option<OwnedSemaphorePermit> {
+        let limit = self.limit?;
+        let semaphore = Arc::clone(
+            self.semaphores
+                .lock()
+                .unwrap()
+                .entry((config, theme.to_string()))
+                .or_insert_with(|| Arc::new(Semaphore::new(limit))),
+        );
+        semaphore.acquire_owned().await.ok()
+    }
+}
 
-    Ok(())
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+enum Quality {
+    Low,
+    Medium,
+    High,
+    Auto,
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    let args = Args::parse();
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+enum Background {
+    Opaque,
+    Transparent,
+}
 
-    if !args.yaml_file.exists() {
-        anyhow::bail!(
-            "Configuration file does not exist: {}",
-            args.yaml_file.display()
-        );
+impl Quality {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Low => "low",
+            Self::Medium => "medium",
+            Self::High => "high",
+            Self::Auto => "auto",
+        }
     }
+}
 
-    if let Err(e) = process_config(&args.yaml_file).await {
-        eprintln!("{}", style(format!("Error: {}", e)).red().bold());
-        std::process::exit(1);
+impl Background {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Opaque => "opaque",
+            Self::Transparent => "transparent",
+        }
     }
+}
 
-    Ok(())
+// Missing fields default to empty so `validate` can report them all at once
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+struct Config {
+    #[serde(default)]
+    system_prompt: String,
+    /// Art style appended to every prompt
+    #[serde(default)]
+    style: String,
+    /// Global image size; `size` wins over `aspect`, square by default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    size: Option<String>,
+    /// `square`, `landscape` or `portrait`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    aspect: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    model: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    quality: Option<Quality>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    background: Option<Background>,
+    /// Values for `{name}` placeholders in the system prompt, theme
+    /// instructions and prompts
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    variables: BTreeMap<String, String>,
+    #[serde(default)]
+    themes: Vec<Theme>,
+    #[serde(default)]
+    prompts: Vec<Prompt>,
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+struct Theme {
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    instructions: String,
+    /// Overrides the global style for every prompt of this theme
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    size: Option<String>,
+    /// `square`, `landscape` or `portrait`; alternative to `size`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    aspect: Option<String>,
+}
 
-    #[test]
-    fn test_calculate_hash() {
-        let system_prompt = "test system";
-        let theme_instruction = "test theme";
-        let prompt = "test prompt";
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+struct Prompt {
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    prompt: String,
+    /// Overrides the theme and global size for this prompt
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    size: Option<String>,
+    /// `square`, `landscape` or `portrait`; alternative to `size`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    aspect: Option<String>,
+    /// PNG to edit through the images/edits endpoint instead of generating
+    /// from scratch; relative to the config file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    reference: Option<PathBuf>,
+    /// PNG whose transparent areas mark where the reference may change
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    mask: Option<PathBuf>,
+}
 
-        let hash1 = calculate_hash(system_prompt, theme_instruction, prompt);
-        let hash2 = calculate_hash(system_prompt, theme_instruction, prompt);
+/// Source images of an edit task
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct EditInputs {
+    image: PathBuf,
+    mask: Option<PathBuf>,
+}
 
-        // Same inputs should produce same hash
-        assert_eq!(hash1, hash2);
-        assert_eq!(hash1.len(), 6); // Should be 6 characters
+#[derive(Debug, Clone)]
+struct ImageTask {
+    theme_name: String,
+    prompt_name: String,
+    full_prompt: String,
+    output_path: PathBuf,
+    hash: String,
+    model: String,
+    size: String,
+    options: ImageOptions,
+    output: OutputSettings,
+    /// Set for prompts with a reference image
+    edit: Option<EditInputs>,
+    /// The image already exists and is skipped
+    cached: bool,
+}
 
-        // Different inputs should produce different hash
-        let hash3 = calculate_hash("different", theme_instruction, prompt);
-        assert_ne!(hash1, hash3);
+impl Config {
+    fn get_image_size(&self) -> &str {
+        self.size
+            .as_deref()
+            .or_else(|| self.aspect.as_deref().and_then(aspect_size))
+            .unwrap_or("1024x1024") // default to square
     }
 
-    #[test]
-    fn test_create_output_filename() {
-        let filename = create_output_filename("Memory Safety", "abc123");
-        assert_eq!(filename, "memory-safety-abc123.png");
-
-        let filename2 = create_output_filename("Concurrency-Safety", "def456");
-        assert_eq!(filename2, "concurrency-safety-def456.png");
+    /// Older configs chose the aspect ratio through `style`; move such a value
+    /// to `aspect` and return it so the caller can warn
+    fn migrate_legacy_style(&mut self) -> Option<String> {
+        aspect_size(self.style.trim())?;
+        let legacy = std::mem::take(&mut self.style).trim().to_string();
+        if self.size.is_none() && self.aspect.is_none() {
+            self.aspect = Some(legacy.clone());
+        }
+        Some(legacy)
     }
 
-    #[test]
-    fn test_config_image_size() {
-        let mut config = Config {
+    /// Expand `{name}` placeholders in place; `overrides` (from --var) win over
+    /// the YAML's variables
+    fn apply_variables(&mut self, overrides: &[(String, String)]) -> Result<()> {
+        let mut variables = std::mem::take(&mut self.variables);
+        variables.extend(overrides.iter().cloned());
+        check_variables(&variables)?;
+
+        self.system_prompt = expand_template(&self.system_prompt, &variables)
+            .map_err(|e| anyhow::anyhow!("system_prompt: {}", e))?;
+        for theme in &mut self.themes {
+            theme.instructions = expand_template(&theme.instructions, &variables)
+                .map_err(|e| anyhow::anyhow!("theme '{}': {}", theme.name, e))?;
+        }
+        for prompt in &mut self.prompts {
+            prompt.prompt = expand_template(&prompt.prompt, &variables)
+                .map_err(|e| anyhow::anyhow!("prompt '{}': {}", prompt.name, e))?;
+        }
+
+        self.variables = variables;
+        Ok(())
+    }
+
+    /// Resolve reference and mask paths against the config file's directory
+    /// and make sure they exist
+    fn resolve_references(&mut self, base_dir: &Path) -> Result<()> {
+        for prompt in &mut self.prompts {
+            if prompt.mask.is_some() && prompt.reference.is_none() {
+                anyhow::bail!("prompt '{}': mask requires a reference image", prompt.name);
+            }
+            for (kind, path) in [
+                ("reference", &mut prompt.reference),
+                ("mask", &mut prompt.mask),
+            ] {
+                let Some(path) = path else {
+                    continue;
+                };
+                *path = base_dir.join(&*path);
+                if !path.is_file() {
+                    anyhow::bail!(
+                        "prompt '{}': {} image not found: {}",
+                        prompt.name,
+                        kind,
+                        path.display()
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Config with only the themes and prompts of the failed (theme, prompt)
+    /// pairs, for a rerun through the normal loading path
+    ///
+    /// The schema can only describe every theme with every prompt, so pairs
+    /// that succeeded come along too; their images exist and are skipped.
+    /// Prompts are written expanded (variables applied, braces escaped) and
+    /// reference paths absolute, so the file works from any directory.
+    fn retry_config(&self, failed: &[(String, String)]) -> Result<Config> {
+        let mut config = self.clone();
+        config.variables.clear();
+        config.system_prompt = escape_template(&config.system_prompt);
+        config
+            .themes
+            .retain(|theme| failed.iter().any(|(name, _)| *name == theme.name));
+        for theme in &mut config.themes {
+            theme.instructions = escape_template(&theme.instructions);
+        }
+        config
+            .prompts
+            .retain(|prompt| failed.iter().any(|(_, name)| *name == prompt.name));
+        for prompt in &mut config.prompts {
+            prompt.prompt = escape_template(&prompt.prompt);
+            for path in [&mut prompt.reference, &mut prompt.mask]
+                .into_iter()
+                .flatten()
+            {
+                *path = std::path::absolute(&*path)
+                    .with_context(|| format!("Failed to resolve path: {}", path.display()))?;
+            }
+        }
+        Ok(config)
+    }
+
+    /// Check the config after parsing, reporting every problem at once with
+    /// its YAML path
+    fn validate(&self) -> Result<()> {
+        let problems = self.problems();
+        if problems.is_empty() {
+            return Ok(());
+        }
+        anyhow::bail!(
+            "Invalid configuration ({} problems):\n{}",
+            problems.len(),
+            problems
+                .iter()
+                .map(|problem| format!("  - {}", problem))
+                .collect::<Vec<_>>()
+                .join("\n")
+        )
+    }
+
+    fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if let Err(e) = entry_size(
+            "config",
+            "top level",
+            self.size.as_deref(),
+            self.aspect.as_deref(),
+        ) {
+            problems.push(e.to_string());
+        }
+
+        if self.themes.is_empty() {
+            problems.push("themes: no themes listed".to_string());
+        }
+        // Themes sharing a directory would overwrite each other's images
+        let mut theme_dirs: BTreeMap<String, usize> = BTreeMap::new();
+        for (i, theme) in self.themes.iter().enumerate() {
+            if theme.name.trim().is_empty() {
+                problems.push(format!("themes[{}].name: missing or empty", i));
+            } else if let Some(first) = theme_dirs.insert(theme_dir_name(&theme.name), i) {
+                problems.push(format!(
+                    "themes[{}].name: '{}' uses the same directory as themes[{}]",
+                    i, theme.name, first
+                ));
+            }
+            if theme.instructions.trim().is_empty() {
+                problems.push(format!("themes[{}].instructions: missing or empty", i));
+            }
+            if let Err(e) = entry_size(
+                "theme",
+                &theme.name,
+                theme.size.as_deref(),
+                theme.aspect.as_deref(),
+            ) {
+                problems.push(format!("themes[{}]: {}", i, e));
+            }
+        }
+
+        if self.prompts.is_empty() {
+            problems.push("prompts: no prompts listed".to_string());
+        }
+        let mut prompt_names: BTreeMap<&str, usize> = BTreeMap::new();
+        for (i, prompt) in self.prompts.iter().enumerate() {
+            if prompt.name.trim().is_empty() {
+                problems.push(format!("prompts[{}].name: missing or empty", i));
+            } else if let Some(first) = prompt_names.insert(&prompt.name, i) {
+                problems.push(format!(
+                    "prompts[{}].name: duplicate of prompts[{}] '{}'",
+                    i, first, prompt.name
+                ));
+            }
+            if prompt.prompt.trim().is_empty() {
+                problems.push(format!("prompts[{}].prompt: missing or empty", i));
+            }
+            if let Err(e) = entry_size(
+                "prompt",
+                &prompt.name,
+                prompt.size.as_deref(),
+                prompt.aspect.as_deref(),
+            ) {
+                problems.push(format!("prompts[{}]: {}", i, e));
+            }
+        }
+        problems
+    }
+
+    /// Size of one image: the prompt's override, else the theme's, else the global style
+    fn resolve_size(&self, theme: &Theme, prompt: &Prompt) -> Result<String> {
+        let prompt_size = entry_size(
+            "prompt",
+            &prompt.name,
+            prompt.size.as_deref(),
+            prompt.aspect.as_deref(),
+        )?;
+        let theme_size = entry_size(
+            "theme",
+            &theme.name,
+            theme.size.as_deref(),
+            theme.aspect.as_deref(),
+        )?;
+        Ok(prompt_size
+            .or(theme_size)
+            .unwrap_or_else(|| self.get_image_size().to_string()))
+    }
+}
+
+/// Parse the YAML config; errors carry the file, line and column
+fn parse_config(path: &Path, content: &str) -> Result<Config> {
+    serde_yaml::from_str(content).map_err(|e| {
+        let message = e.to_string();
+        match e.location() {
+            Some(location) => {
+                let suffix = format!(" at line {} column {}", location.line(), location.column());
+                anyhow::anyhow!(
+                    "{}:{}:{}: {}",
+                    path.display(),
+                    location.line(),
+                    location.column(),
+                    message.strip_suffix(&suffix).unwrap_or(&message)
+                )
+            }
+            None => anyhow::anyhow!("{}: {}", path.display(), message),
+        }
+    })
+}
+
+/// Part of a template: literal text or a `{name}` placeholder
+#[derive(Debug, PartialEq, Eq)]
+enum TemplatePart<'a> {
+    Text(String),
+    Variable(&'a str),
+}
+
+/// Split a template into text and placeholders; `{{` and `}}` are literal braces
+fn parse_template(text: &str) -> Result<Vec<TemplatePart<'_>>> {
+    let mut parts = Vec::new();
+    let mut literal = String::new();
+    let mut rest = text;
+    while let Some(pos) = rest.find(['{', '}']) {
+        literal.push_str(&rest[..pos]);
+        let brace = &rest[pos..pos + 1];
+        let after = &rest[pos + 1..];
+        if after.starts_with(brace) {
+            literal.push_str(brace);
+            rest = &after[1..];
+        } else if brace == "}" {
+            literal.push('}');
+            rest = after;
+        } else {
+            let end = after.find('}').with_context(|| {
+                format!(
+                    "unclosed '{{' in \"{}\" (write {{{{ for a literal brace)",
+                    text
+                )
+            })?;
+            let name = &after[..end];
+            if name.is_empty() || name.contains('{') {
+                anyhow::bail!(
+                    "invalid placeholder '{{{}}}' (write {{{{ for a literal brace)",
+                    name
+                );
+            }
+            parts.push(TemplatePart::Text(std::mem::take(&mut literal)));
+            parts.push(TemplatePart::Variable(name));
+            rest = &after[end + 1..];
+        }
+    }
+    literal.push_str(rest);
+    parts.push(TemplatePart::Text(literal));
+    parts.retain(|part| *part != TemplatePart::Text(String::new()));
+    Ok(parts)
+}
+
+/// Replace `{name}` placeholders with their variable values
+fn expand_template(text: &str, variables: &BTreeMap<String, String>) -> Result<String> {
+    let mut expanded = String::with_capacity(text.len());
+    for part in parse_template(text)? {
+        match part {
+            TemplatePart::Text(text) => expanded.push_str(&text),
+            TemplatePart::Variable(name) => {
+                let value = variables
+                    .get(name)
+                    .with_context(|| format!("unknown variable '{{{}}}'", name))?;
+                expanded.push_str(value);
+            }
+        }
+    }
+    Ok(expanded)
+}
+
+/// Inverse of the literal-brace handling of [`expand_template`]
+fn escape_template(text: &str) -> String {
+    text.replace('{', "{{").replace('}', "}}")
+}
+
+/// Variable values are inserted verbatim, so one referencing another is an error
+fn check_variables(variables: &BTreeMap<String, String>) -> Result<()> {
+    for (name, value) in variables {
+        let parts =
+            parse_template(value).map_err(|e| anyhow::anyhow!("variable '{}': {}", name, e))?;
+        if let Some(TemplatePart::Variable(other)) = parts
+            .iter()
+            .find(|part| matches!(part, TemplatePart::Variable(_)))
+        {
+            anyhow::bail!(
+                "variable '{}' references '{{{}}}': variables can't reference other variables",
+                name,
+                other
+            );
+        }
+    }
+    Ok(())
+}
+
+fn aspect_size(aspect: &str) -> Option<&'static str> {
+    match aspect {
+        "square" => Some("1024x1024"),
+        "landscape" => Some("1536x1024"),
+        "portrait" => Some("1024x1536"),
+        _ => None,
+    }
+}
+
+/// Size override of a theme or prompt, from `size` or `aspect`
+fn entry_size(
+    kind: &str,
+    name: &str,
+    size: Option<&str>,
+    aspect: Option<&str>,
+) -> Result<Option<String>> {
+    match (size, aspect) {
+        (Some(_), Some(_)) => {
+            anyhow::bail!("{} '{}': set either size or aspect, not both", kind, name)
+        }
+        (Some(size), None) => {
+            if !IMAGE_SIZES.contains(&size) {
+                anyhow::bail!(
+                    "{} '{}': invalid size '{}', expected one of {}",
+                    kind,
+                    name,
+                    size,
+                    IMAGE_SIZES.join(", ")
+                );
+            }
+            Ok(Some(size.to_string()))
+        }
+        (None, Some(aspect)) => aspect_size(aspect)
+            .map(|size| Some(size.to_string()))
+            .with_context(|| {
+                format!(
+                    "{} '{}': invalid aspect '{}', expected square, landscape or portrait",
+                    kind, name, aspect
+                )
+            }),
+        (None, None) => Ok(None),
+    }
+}
+
+/// Everything besides the system, theme and prompt text that changes the rendered image
+struct RenderSettings<'a> {
+    style: &'a str,
+    model: &'a str,
+    size: &'a str,
+    options: &'a ImageOptions,
+    /// Content hash of the reference and mask images; empty for generations
+    inputs: &'a str,
+}
+
+fn calculate_hash(
+    system_prompt: &str,
+    theme_instruction: &str,
+    prompt: &str,
+    render: &RenderSettings,
+) -> String {
+    let combined = format!(
+        "{}{}{}\n{}|{}|{}|{}|{}|{}",
+        system_prompt,
+        theme_instruction,
+        prompt,
+        render.style,
+        render.model,
+        render.size,
+        render.options.quality.as_deref().unwrap_or_default(),
+        render.options.background.as_deref().unwrap_or_default(),
+        render.inputs
+    );
+    let hash = blake3::hash(combined.as_bytes());
+    format!("{:.6}", hash.to_hex())
+}
+
+/// Content hash of an edit task's source images, so changing them regenerates
+fn edit_inputs_hash(edit: &EditInputs) -> Result<String> {
+    let mut hasher = blake3::Hasher::new();
+    for path in std::iter::once(&edit.image).chain(&edit.mask) {
+        let data = fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        hasher.update(blake3::hash(&data).as_bytes());
+    }
+    Ok(hasher.finalize().to_hex().to_string())
+}
+
+/// Prompt sent to the model: system prompt, theme instructions, the prompt and the art style
+fn build_full_prompt(
+    system_prompt: &str,
+    theme_instruction: &str,
+    prompt: &str,
+    style: &str,
+) -> String {
+    let mut full_prompt = format!("{}\n\n{}\n\n{}", system_prompt, theme_instruction, prompt);
+    if !style.trim().is_empty() {
+        full_prompt.push_str(&format!("\n\nStyle: {}", style.trim()));
+    }
+    full_prompt
+}
+
+/// Approximate USD price of one image of `size` (medium quality)
+fn image_price(size: &str) -> f64 {
+    match size {
+        "1024x1024" => 0.042,
+        "1536x1024" | "1024x1536" | "auto" => 0.063,
+        "1792x1024" | "1024x1792" => 0.08,
+        "512x512" => 0.018,
+        "256x256" => 0.016,
+        _ => 0.063,
+    }
+}
+
+/// Estimated cost of the images that still need to be generated
+fn estimate_cost(tasks: &[ImageTask], price_per_image: Option<f64>) -> f64 {
+    tasks
+        .iter()
+        .filter(|task| !task.cached)
+        .map(|task| price_per_image.unwrap_or_else(|| image_price(&task.size)))
+        .sum()
+}
+
+/// "estimated cost $X" for the new images, naming the price source
+fn describe_cost(tasks: &[ImageTask], price_per_image: Option<f64>) -> String {
+    format!(
+        "estimated cost ${:.2}{}",
+        estimate_cost(tasks, price_per_image),
+        if price_per_image.is_some() {
+            ""
+        } else {
+            " (built-in per-size prices)"
+        }
+    )
+}
+
+/// Ask before generating more than --confirm-over images; without a terminal
+/// to ask on, the run is refused unless --yes is given
+fn confirm_run(tasks: &[ImageTask], args: &Args) -> Result<()> {
+    if args.yes || tasks.len() <= args.confirm_over {
+        return Ok(());
+    }
+
+    let summary = format!(
+        "{} new images, {}",
+        tasks.len(),
+        describe_cost(tasks, args.price_per_image)
+    );
+    if !std::io::stdin().is_terminal() {
+        anyhow::bail!(
+            "{} is over --confirm-over {}; rerun with --yes to generate them",
+            summary,
+            args.confirm_over
+        );
+    }
+
+    print!(
+        "{} ",
+        style(format!(
+            "⚠️  About to generate {}. Continue? [y/N]",
+            summary
+        ))
+        .yellow()
+    );
+    std::io::stdout().flush()?;
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    if !is_yes(&answer) {
+        anyhow::bail!("Cancelled; no images were generated");
+    }
+    Ok(())
+}
+
+fn is_yes(answer: &str) -> bool {
+    matches!(answer.trim().to_lowercase().as_str(), "y" | "yes")
+}
+
+/// Print the --dry-run task list and cost estimate
+fn print_dry_run(tasks: &[ImageTask], show_prompts: bool, price_per_image: Option<f64>) {
+    for task in tasks {
+        let status_label = if task.cached {
+            style("cached").yellow()
+        } else {
+            style("new   ").green()
+        };
+        status!(
+            "  {}  {}/{}  {}  {}",
+            status_label,
+            task.theme_name,
+            task.prompt_name,
+            task.size,
+            style(task.output_path.display()).dim()
+        );
+        if show_prompts {
+            for line in task.full_prompt.lines() {
+                status!("          {}", style(line).dim());
+            }
+        }
+    }
+
+    let new_images = tasks.iter().filter(|task| !task.cached).count();
+    status!();
+    status!(
+        "{}",
+        style(format!(
+            "🧮 {} tasks, {} new images, {}",
+            tasks.len(),
+            new_images,
+            describe_cost(tasks, price_per_image)
+        ))
+        .cyan()
+        .bold()
+    );
+}
+
+/// What produced each image under an output root, saved as `manifest.json`
+///
+/// Reruns merge into the existing manifest: entries are keyed by the image path
+/// relative to the output root and a new result replaces the previous one.
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+struct Manifest {
+    #[serde(default)]
+    images: BTreeMap<String, ManifestEntry>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct ManifestEntry {
+    theme: String,
+    prompt: String,
+    full_prompt: String,
+    model: String,
+    size: String,
+    hash: String,
+    /// Unix seconds of the generation attempt
+    timestamp: u64,
+    /// Size of the saved image; unset for failed generations
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    bytes: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl Manifest {
+    /// Load an existing manifest; a missing file is an empty manifest
+    fn load(path: &Path) -> Result<Self> {
+        match fs::read_to_string(path) {
+            Ok(content) => serde_json::from_str(&content)
+                .with_context(|| format!("Failed to parse manifest: {}", path.display())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => {
+                Err(e).with_context(|| format!("Failed to read manifest: {}", path.display()))
+            }
+        }
+    }
+
+    fn save(&self, path: &Path) -> Result<()> {
+        fs::write(path, serde_json::to_string_pretty(self)?)
+            .with_context(|| format!("Failed to write manifest: {}", path.display()))
+    }
+
+    /// A valid image saved for `hash` with the same format and width, possibly
+    /// under another --group-by layout; returns its manifest key
+    fn find_image(&self, output_root: &Path, hash: &str, output: &OutputSettings) -> Option<&str> {
+        let suffix = format!("-{}", output_file_suffix(hash, output));
+        self.images
+            .iter()
+            .find(|(key, entry)| {
+                entry.hash == hash
+                    && entry.error.is_none()
+                    && key.ends_with(&suffix)
+                    && check_cached_image(&output_root.join(key)) == CachedImage::Valid
+            })
+            .map(|(key, _)| key.as_str())
+    }
+
+    /// Record the outcome of one task, replacing an earlier entry for the same file
+    fn record(&mut self, output_root: &Path, task: &ImageTask, error: Option<String>) {
+        let key = task
+            .output_path
+            .strip_prefix(output_root)
+            .unwrap_or(&task.output_path)
+            .to_string_lossy()
+            .into_owned();
+        let bytes = match error {
+            None => fs::metadata(&task.output_path).ok().map(|m| m.len()),
+            Some(_) => None,
+        };
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+
+        self.images.insert(
+            key,
+            ManifestEntry {
+                theme: task.theme_name.clone(),
+                prompt: task.prompt_name.clone(),
+                full_prompt: task.full_prompt.clone(),
+                model: task.model.clone(),
+                size: task.size.clone(),
+                hash: task.hash.clone(),
+                timestamp,
+                bytes,
+                error,
+            },
+        );
+    }
+}
+
+/// State of a previously generated image on disk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CachedImage {
+    Missing,
+    Valid,
+    /// Empty or truncated, e.g. by a crash during an older, non-atomic write
+    Corrupt,
+}
+
+fn check_cached_image(path: &Path) -> CachedImage {
+    match fs::read(path) {
+        Ok(data) if is_complete_image(&data) => CachedImage::Valid,
+        Ok(_) => CachedImage::Corrupt,
+        Err(_) => CachedImage::Missing,
+    }
+}
+
+/// Cheap completeness check of the formats imgen writes: PNG signature and
+/// IEND chunk, JPEG start and end markers, or a RIFF size matching the WebP file
+fn is_complete_image(data: &[u8]) -> bool {
+    if data.starts_with(&PNG_SIGNATURE) {
+        return data.len() >= PNG_SIGNATURE.len() + PNG_IEND.len() && data.ends_with(&PNG_IEND);
+    }
+    if data.starts_with(&[0xFF, 0xD8]) {
+        return data.len() > 4 && data.ends_with(&[0xFF, 0xD9]);
+    }
+    if data.len() >= 12 && data.starts_with(b"RIFF") && &data[8..12] == b"WEBP" {
+        let riff_size = u32::from_le_bytes([data[4], data[5], data[6], data[7]]) as usize;
+        return riff_size + 8 == data.len();
+    }
+    false
+}
+
+/// Text chunks --embed-metadata adds to a task's PNG
+fn png_metadata(task: &ImageTask, created: SystemTime) -> Vec<(&'static str, String)> {
+    let seconds = created
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default();
+    vec![
+        ("Prompt", task.full_prompt.clone()),
+        ("Theme", task.theme_name.clone()),
+        ("Prompt Name", task.prompt_name.clone()),
+        ("Model", task.model.clone()),
+        ("Size", task.size.clone()),
+        ("Creation Time", format_utc(seconds)),
+        ("Software", format!("imgen {}", env!("CARGO_PKG_VERSION"))),
+    ]
+}
+
+/// RFC 3339 UTC time of a Unix timestamp
+fn format_utc(seconds: u64) -> String {
+    let days = (seconds / 86_400) as i64;
+    let secs = seconds % 86_400;
+
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm)
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        secs / 3_600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+/// Insert an uncompressed iTXt chunk per field just before a PNG's IEND chunk
+fn embed_png_text(png: &[u8], fields: &[(&str, String)]) -> Result<Vec<u8>> {
+    let iend = png_chunks(png)?
+        .into_iter()
+        .find(|chunk| chunk.kind == *b"IEND")
+        .context("PNG has no IEND chunk")?;
+
+    let mut out =
+        Vec::with_capacity(png.len() + fields.iter().map(|(_, v)| v.len() + 64).sum::<usize>());
+    out.extend_from_slice(&png[..iend.offset]);
+    for (keyword, text) in fields {
+        // keyword, null, compression flag and method, empty language and
+        // translated keyword, then the UTF-8 text
+        let mut data = Vec::with_capacity(keyword.len() + text.len() + 5);
+        data.extend_from_slice(keyword.as_bytes());
+        data.extend_from_slice(&[0, 0, 0, 0, 0]);
+        data.extend_from_slice(text.as_bytes());
+        write_png_chunk(&mut out, b"iTXt", &data);
+    }
+    out.extend_from_slice(&png[iend.offset..]);
+    Ok(out)
+}
+
+/// Keyword and text of a PNG's tEXt and uncompressed iTXt chunks
+fn read_png_text(png: &[u8]) -> Result<Vec<(String, String)>> {
+    let mut fields = Vec::new();
+    for chunk in png_chunks(png)? {
+        let data = &png[chunk.data.clone()];
+        let Some(null) = data.iter().position(|&b| b == 0) else {
+            continue;
+        };
+        let keyword = String::from_utf8_lossy(&data[..null]).into_owned();
+        let rest = &data[null + 1..];
+        match &chunk.kind {
+            // tEXt is Latin-1
+            b"tEXt" => fields.push((keyword, rest.iter().map(|&b| b as char).collect())),
+            b"iTXt" if rest.len() >= 2 && rest[0] == 0 => {
+                // Skip the language tag and translated keyword
+                let mut text = &rest[2..];
+                for _ in 0..2 {
+                    let Some(null) = text.iter().position(|&b| b == 0) else {
+                        break;
+                    };
+                    text = &text[null + 1..];
+                }
+                fields.push((keyword, String::from_utf8_lossy(text).into_owned()));
+            }
+            _ => {}
+        }
+    }
+    Ok(fields)
+}
+
+/// Position of a chunk inside a PNG
+struct PngChunk {
+    kind: [u8; 4],
+    /// Start of the chunk's length field
+    offset: usize,
+    data: std::ops::Range<usize>,
+}
+
+fn png_chunks(png: &[u8]) -> Result<Vec<PngChunk>> {
+    if !png.starts_with(&PNG_SIGNATURE) {
+        anyhow::bail!("Not a PNG file");
+    }
+    let mut chunks = Vec::new();
+    let mut offset = PNG_SIGNATURE.len();
+    while offset < png.len() {
+        let header = png
+            .get(offset..offset + 8)
+            .context("Truncated PNG chunk header")?;
+        let length = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+        let data = offset + 8..offset + 8 + length;
+        if data.end + 4 > png.len() {
+            anyhow::bail!("Truncated PNG chunk");
+        }
+        chunks.push(PngChunk {
+            kind: [header[4], header[5], header[6], header[7]],
+            offset,
+            data: data.clone(),
+        });
+        offset = data.end + 4;
+    }
+    Ok(chunks)
+}
+
+fn write_png_chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = out.len();
+    out.extend_from_slice(kind);
+    out.extend_from_slice(data);
+    let crc = crc32(&out[start..]);
+    out.extend_from_slice(&crc.to_be_bytes());
+}
+
+/// CRC-32 (ISO-HDLC) as used by PNG chunks
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+/// Print the text chunks of a PNG for --inspect
+fn inspect_image(path: &Path) -> Result<()> {
+    let data = fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let fields = read_png_text(&data).map_err(|e| anyhow::anyhow!("{}: {}", path.display(), e))?;
+    if fields.is_empty() {
+        status!(
+            "{}",
+            style(format!("No embedded metadata in {}", path.display())).yellow()
+        );
+        return Ok(());
+    }
+
+    status!("{}", style(format!("🔎 {}", path.display())).bold());
+    for (keyword, text) in fields {
+        let mut lines = text.lines();
+        status!(
+            "  {}: {}",
+            style(&keyword).cyan(),
+            lines.next().unwrap_or_default()
+        );
+        for line in lines {
+            status!("    {}", line);
+        }
+    }
+    Ok(())
+}
+
+/// Convert and/or downscale a PNG returned by the API; unchanged when there is
+/// nothing to do
+fn convert_image(png: &[u8], output: &OutputSettings) -> Result<Vec<u8>> {
+    if !output.converts() {
+        return Ok(png.to_vec());
+    }
+
+    let mut image = image::load_from_memory_with_format(png, ImageFormat::Png)
+        .context("Failed to decode the generated PNG")?;
+    if let Some(max_width) = output.max_width
+        && image.width() > max_width
+    {
+        let height = (image.height() as u64 * max_width as u64 / image.width() as u64).max(1);
+        image = image.resize_exact(max_width, height as u32, FilterType::Lanczos3);
+    }
+
+    let mut data = Vec::new();
+    match output.format {
+        OutputFormat::Png => image.write_to(&mut Cursor::new(&mut data), ImageFormat::Png)?,
+        OutputFormat::Webp => DynamicImage::ImageRgba8(image.to_rgba8())
+            .write_with_encoder(WebPEncoder::new_lossless(&mut data))?,
+        // JPEG has no alpha channel
+        OutputFormat::Jpeg => DynamicImage::ImageRgb8(image.to_rgb8()).write_with_encoder(
+            JpegEncoder::new_with_quality(&mut data, output.jpeg_quality),
+        )?,
+    }
+    Ok(data)
+}
+
+/// Write `data` next to `path` first and rename it into place, so a crash
+/// never leaves a partial image under the final name
+fn write_atomically(path: &Path, data: &[u8]) -> Result<()> {
+    let mut tmp_name = path.as_os_str().to_owned();
+    tmp_name.push(".tmp");
+    let tmp_path = PathBuf::from(tmp_name);
+
+    fs::write(&tmp_path, data)
+        .with_context(|| format!("Failed to write {}", tmp_path.display()))?;
+    if let Err(e) = fs::rename(&tmp_path, path) {
+        let _ = fs::remove_file(&tmp_path);
+        return Err(e).with_context(|| format!("Failed to move {} into place", tmp_path.display()));
+    }
+    Ok(())
+}
+
+fn create_output_filename(name: &str, hash: &str, output: &OutputSettings) -> String {
+    format!("{}-{}", slugify(name), output_file_suffix(hash, output))
+}
+
+/// End of every image filename, whatever the layout: `hash[-wN].ext`
+fn output_file_suffix(hash: &str, output: &OutputSettings) -> String {
+    // The width is part of the name so resized and full-size copies don't mix
+    let width = output
+        .max_width
+        .map(|width| format!("-w{}", width))
+        .unwrap_or_default();
+    format!("{}{}.{}", hash, width, output.format.extension())
+}
+
+/// Directory name for a theme, slugified so names like "AI / ML" stay one level
+fn theme_dir_name(theme_name: &str) -> String {
+    slug_or(theme_name, "theme")
+}
+
+fn slug_or(name: &str, fallback: &str) -> String {
+    let slug = slugify(name);
+    if slug.is_empty() {
+        fallback.to_string()
+    } else {
+        slug
+    }
+}
+
+/// The tasks of a run in execution order, saved as `.imgen-run.json` in the
+/// output directory before any image is requested
+///
+/// Tasks are marked done as their images land, so `--resume` can run the
+/// rest in the original order after a crash. `config_hash` covers every
+/// task's prompt hash and path: editing a config, a --var or an output flag
+/// changes it.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct RunFile {
+    config_hash: String,
+    tasks: Vec<RunTask>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct RunTask {
+    /// The config file as given on the command line
+    config: String,
+    theme: String,
+    prompt: String,
+    hash: String,
+    path: PathBuf,
+    done: bool,
+}
+
+impl RunFile {
+    /// Every task of the plans, in the order they run; existing images are done
+    fn new(plans: &[ConfigPlan]) -> Self {
+        let tasks: Vec<RunTask> = plans
+            .iter()
+            .flat_map(|plan| {
+                plan.tasks.iter().map(|task| RunTask {
+                    config: plan.label.clone(),
+                    theme: task.theme_name.clone(),
+                    prompt: task.prompt_name.clone(),
+                    hash: task.hash.clone(),
+                    path: task.output_path.clone(),
+                    done: task.cached,
+                })
+            })
+            .collect();
+
+        let mut hasher = blake3::Hasher::new();
+        for task in &tasks {
+            for field in [&task.config, &task.theme, &task.prompt, &task.hash] {
+                hasher.update(field.as_bytes());
+                hasher.update(b"\0");
+            }
+            hasher.update(task.path.as_os_str().as_encoded_bytes());
+            hasher.update(b"\n");
+        }
+        Self {
+            config_hash: hasher.finalize().to_hex().to_string(),
+            tasks,
+        }
+    }
+
+    /// Load a saved run; a missing file is `None`
+    fn load(path: &Path) -> Result<Option<Self>> {
+        match fs::read_to_string(path) {
+            Ok(content) => serde_json::from_str(&content)
+                .map(Some)
+                .with_context(|| format!("Failed to parse run file: {}", path.display())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => {
+                Err(e).with_context(|| format!("Failed to read run file: {}", path.display()))
+            }
+        }
+    }
+
+    fn save(&self, path: &Path) -> Result<()> {
+        write_atomically(path, serde_json::to_string_pretty(self)?.as_bytes())
+    }
+
+    fn pending(&self) -> usize {
+        self.tasks.iter().filter(|task| !task.done).count()
+    }
+}
+
+/// The saved run at `path`, checked against the run the configs compute now
+///
+/// Images that landed after the last save are marked done.
+fn resume_run(path: &Path, current: &RunFile) -> Result<RunFile> {
+    let Some(mut saved) = RunFile::load(path)? else {
+        anyhow::bail!(
+            "No run to resume: {} not found (it is removed once every task is done); run without --resume",
+            path.display()
+        );
+    };
+    if saved.config_hash != current.config_hash || saved.tasks.len() != current.tasks.len() {
+        anyhow::bail!(
+            "{} was written for a different configuration: the YAML files, --var values, \
+             model or output flags changed since the run started. Restore them to resume, \
+             or start a new run without --resume (saved images are kept)",
+            path.display()
+        );
+    }
+    for (task, now) in saved.tasks.iter_mut().zip(&current.tasks) {
+        task.done |= now.done;
+    }
+    Ok(saved)
+}
+
+/// Round-robin over the groups: the first of each, then the second of each, ...
+fn interleave<T>(groups: Vec<Vec<T>>) -> Vec<T> {
+    let mut iters: Vec<_> = groups.into_iter().map(Vec::into_iter).collect();
+    let mut items = Vec::new();
+    loop {
+        let before = items.len();
+        items.extend(iters.iter_mut().filter_map(Iterator::next));
+        if items.len() == before {
+            return items;
+        }
+    }
+}
+
+/// Tasks resolved from one config file
+struct ConfigPlan {
+    /// The config file as given on the command line
+    label: String,
+    /// Variables applied, with the effective model, quality and background
+    config: Config,
+    output_root: PathBuf,
+    models: ModelConfig,
+    tasks: Vec<ImageTask>,
+}
+
+/// Outcome of one config's tasks, for the summary
+#[derive(Debug, Default)]
+struct ConfigStats {
+    generated: usize,
+    cached: usize,
+    /// (theme, prompt, error)
+    failures: Vec<(String, String, String)>,
+    rejections: Vec<(String, String, String)>,
+}
+
+/// Expand the positional arguments into config files
+///
+/// Directories contribute their `*.yaml`/`*.yml` files sorted by path, and
+/// only descend into subdirectories with `recursive`. Other paths are kept as
+/// given, so a missing file fails when it is loaded.
+fn collect_config_files(inputs: &[PathBuf], recursive: bool) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for input in inputs {
+        if !input.is_dir() {
+            files.push(input.clone());
+            continue;
+        }
+
+        let max_depth = if recursive { usize::MAX } else { 1 };
+        let mut found = Vec::new();
+        for entry in WalkDir::new(input).min_depth(1).max_depth(max_depth) {
+            let entry =
+                entry.with_context(|| format!("Failed to read directory: {}", input.display()))?;
+            if entry.file_type().is_file() && is_yaml_file(entry.path()) {
+                found.push(entry.into_path());
+            }
+        }
+        if found.is_empty() {
+            status!(
+                "{}",
+                style(format!(
+                    "⚠️  No *.yaml or *.yml files in {}",
+                    input.display()
+                ))
+                .yellow()
+            );
+        }
+        found.sort();
+        files.extend(found);
+    }
+
+    // A file named directly and through its directory is processed once
+    let mut seen = HashSet::new();
+    files.retain(|file| seen.insert(file.clone()));
+    Ok(files)
+}
+
+fn is_yaml_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("yaml") || ext.eq_ignore_ascii_case("yml"))
+}
+
+/// Output subdirectory for each config file, named after its file stem
+///
+/// Stems that slugify to the same name (`a/logo.yaml`, `b/logo.yml`) get a
+/// numeric suffix in order.
+fn config_dir_names(files: &[PathBuf]) -> Vec<String> {
+    let mut used = HashSet::new();
+    files
+        .iter()
+        .map(|file| {
+            let stem = file
+                .file_stem()
+                .map(|stem| stem.to_string_lossy())
+                .unwrap_or_default();
+            let base = theme_dir_name(&stem);
+            let mut name = base.clone();
+            let mut suffix = 2;
+            while !used.insert(name.clone()) {
+                name = format!("{}-{}", base, suffix);
+                suffix += 1;
+            }
+            name
+        })
+        .collect()
+}
+
+/// Load one config file and resolve its tasks, creating its output directories
+fn load_config(config_path: &Path, output_dir: &Path, args: &Args) -> Result<ConfigPlan> {
+    if !config_path.exists() {
+        anyhow::bail!(
+            "Configuration file does not exist: {}",
+            config_path.display()
+        );
+    }
+
+    // Read and parse YAML config
+    let config_content = fs::read_to_string(config_path)
+        .with_context(|| format!("Failed to read config file: {}", config_path.display()))?;
+
+    let mut config = parse_config(config_path, &config_content)?;
+    if let Some(legacy) = config.migrate_legacy_style() {
+        status!(
+            "{}",
+            style(format!(
+                "⚠️  `style: {}` selecting the image size is deprecated; use `aspect: {}` (style is now an art style added to every prompt)",
+                legacy, legacy
+            ))
+            .yellow()
+        );
+    }
+    config.validate()?;
+    config.apply_variables(&args.variables)?;
+    config.resolve_references(config_path.parent().unwrap_or_else(|| Path::new("")))?;
+
+    status!(
+        "{}",
+        style(format!(
+            "📝 Loaded config with {} themes and {} prompts",
+            config.themes.len(),
+            config.prompts.len()
+        ))
+        .green()
+        .bold()
+    );
+
+    // CLI flags win over the YAML
+    let mut models = ModelConfig::from_env();
+    if let Some(model) = args.model.clone().or_else(|| config.model.clone()) {
+        models.image = model;
+    }
+    let image_options = ImageOptions {
+        quality: args
+            .quality
+            .or(config.quality)
+            .map(|quality| quality.as_str().to_string()),
+        background: args
+            .background
+            .or(config.background)
+            .map(|background| background.as_str().to_string()),
+    };
+    status!(
+        "{}",
+        style(format!("🤖 Using image model: {}", models.image)).dim()
+    );
+
+    // A dry run doesn't create anything
+    let output_root = if args.dry_run {
+        std::path::absolute(output_dir)
+            .with_context(|| format!("Failed to resolve directory: {}", output_dir.display()))?
+    } else {
+        fs::create_dir_all(output_dir)
+            .with_context(|| format!("Failed to create directory: {}", output_dir.display()))?;
+        output_dir
+            .canonicalize()
+            .with_context(|| format!("Failed to resolve directory: {}", output_dir.display()))?
+    };
+
+    let output = OutputSettings {
+        format: args.format,
+        max_width: args.max_width,
+        jpeg_quality: args.jpeg_quality,
+        keep_original: args.keep_original,
+        embed_metadata: args.embed_metadata,
+    };
+
+    // Generate tasks for all theme-prompt combinations
+    let mut tasks_by_theme: Vec<Vec<ImageTask>> = Vec::new();
+
+    // Images generated under another layout are found through the manifest
+    let manifest_path = output_root.join(MANIFEST_FILE);
+    let mut manifest = Manifest::load(&manifest_path).unwrap_or_default();
+    let mut manifest_changed = false;
+
+    for theme in &config.themes {
+        let mut theme_tasks = Vec::new();
+
+        for prompt in &config.prompts {
+            let size = config.resolve_size(theme, prompt)?;
+            let edit = prompt.reference.clone().map(|image| EditInputs {
+                image,
+                mask: prompt.mask.clone(),
+            });
+            let inputs = edit.as_ref().map(edit_inputs_hash).transpose()?;
+            let render = RenderSettings {
+                style: &config.style,
+                model: &models.image,
+                size: &size,
+                options: &image_options,
+                inputs: inputs.as_deref().unwrap_or_default(),
+            };
+
+            // Calculate hash for this combination
+            let hash = calculate_hash(
+                &config.system_prompt,
+                &theme.instructions,
+                &prompt.prompt,
+                &render,
+            );
+
+            // Create full prompt combining system prompt, theme instructions, specific prompt and style
+            let full_prompt = build_full_prompt(
+                &config.system_prompt,
+                &theme.instructions,
+                &prompt.prompt,
+                &config.style,
+            );
+
+            // Generate output filename and path
+            let relative_path =
+                args.group_by
+                    .relative_path(&theme.name, &prompt.name, &hash, &output);
+            let output_path = output_root.join(&relative_path);
+            if !args.dry_run
+                && let Some(dir) = output_path.parent()
+            {
+                fs::create_dir_all(dir)
+                    .with_context(|| format!("Failed to create directory: {}", dir.display()))?;
+            }
+
+            // Check if image already exists; partial files are generated again
+            let mut cached = match check_cached_image(&output_path) {
+                CachedImage::Valid => true,
+                CachedImage::Missing => false,
+                CachedImage::Corrupt => {
+                    status!(
+                        "{}",
+                        style(format!(
+                            "⚠️  Corrupt image will be regenerated: {}",
+                            output_path.display()
+                        ))
+                        .yellow()
+                    );
+                    false
+                }
+            };
+            if !cached && let Some(existing) = manifest.find_image(&output_root, &hash, &output) {
+                let existing = existing.to_string();
+                if !args.dry_run {
+                    let data = fs::read(output_root.join(&existing))
+                        .with_context(|| format!("Failed to read {}", existing))?;
+                    write_atomically(&output_path, &data)?;
+                    let entry = manifest.images[&existing].clone();
+                    manifest
+                        .images
+                        .insert(relative_path.to_string_lossy().into_owned(), entry);
+                    manifest_changed = true;
+                    status!(
+                        "{}",
+                        style(format!(
+                            "♻️  Reusing {} for {}",
+                            existing,
+                            relative_path.display()
+                        ))
+                        .yellow()
+                    );
+                }
+                cached = true;
+            }
+
+            theme_tasks.push(ImageTask {
+                theme_name: theme.name.clone(),
+                prompt_name: prompt.name.clone(),
+                full_prompt,
+                hash,
+                model: models.image.clone(),
+                size,
+                options: image_options.clone(),
+                output,
+                edit,
+                cached,
+                output_path,
+            });
+        }
+
+        if !theme_tasks.is_empty() {
+            tasks_by_theme.push(theme_tasks);
+        }
+    }
+
+    if manifest_changed && !args.no_manifest {
+        manifest.save(&manifest_path)?;
+    }
+
+    // Interleave tasks from different themes for better distribution; the
+    // order is part of the run file, so it must not depend on anything but
+    // the config
+    let tasks = interleave(tasks_by_theme);
+
+    // A retry must produce the same hashes without the original flags
+    config.model = Some(models.image.clone());
+    config.quality = args.quality.or(config.quality);
+    config.background = args.background.or(config.background);
+
+    Ok(ConfigPlan {
+        label: config_path.display().to_string(),
+        config,
+        output_root,
+        models,
+        tasks,
+    })
+}
+
+/// Load every config, then generate all their images with one client, one
+/// throttle and one progress bar
+async fn process_configs(args: &Args) -> Result<()> {
+    let inputs = args.inputs();
+    let files = collect_config_files(&inputs, args.recursive)?;
+    if files.is_empty() {
+        anyhow::bail!("No config files to process");
+    }
+
+    // A single config file writes straight into --output-dir; with several,
+    // each gets a subdirectory named after it
+    let shared_root = files.len() == 1 && !inputs.iter().any(|input| input.is_dir());
+    let dir_names = config_dir_names(&files);
+    let output_dir = args.output_dir();
+
+    let mut plans = Vec::new();
+    let mut load_errors = Vec::new();
+    for (file, dir_name) in files.iter().zip(&dir_names) {
+        if shared_root {
+            plans.push(load_config(file, &output_dir, args)?);
+            continue;
+        }
+
+        status!("{}", style(format!("📄 {}", file.display())).bold());
+        match load_config(file, &output_dir.join(dir_name), args) {
+            Ok(plan) => plans.push(plan),
+            Err(e) => {
+                eprintln!("{}  {}: {}", style(CROSS).red(), file.display(), e);
+                load_errors.push(file.display().to_string());
+            }
+        }
+    }
+
+    if args.dry_run {
+        let tasks: Vec<ImageTask> = plans
+            .iter()
+            .flat_map(|plan| plan.tasks.iter().cloned())
+            .collect();
+        print_dry_run(&tasks, args.show_prompts, args.price_per_image);
+        return check_load_errors(&load_errors, files.len());
+    }
+
+    let mut stats: Vec<ConfigStats> = Vec::new();
+    for plan in &plans {
+        for task in plan.tasks.iter().filter(|task| task.cached) {
+            status!(
+                "{}",
+                style(format!(
+                    "⏭️  Skipping existing image: {}",
+                    task.output_path.display()
+                ))
+                .yellow()
+            );
+        }
+        stats.push(ConfigStats {
+            cached: plan.tasks.iter().filter(|task| task.cached).count(),
+            ..Default::default()
+        });
+    }
+
+    // A resumed run keeps its recorded order and skips the tasks it finished
+    let run_path = output_dir.join(RUN_FILE);
+    let current_run = RunFile::new(&plans);
+    let run = if args.resume {
+        resume_run(&run_path, &current_run)?
+    } else {
+        current_run
+    };
+    // (position in the run, config index, task)
+    let tasks: Vec<(usize, usize, ImageTask)> = plans
+        .iter()
+        .enumerate()
+        .flat_map(|(index, plan)| plan.tasks.iter().map(move |task| (index, task)))
+        .zip(&run.tasks)
+        .enumerate()
+        .filter(|(_, (_, run_task))| !run_task.done)
+        .map(|(position, ((index, task), _))| (position, index, task.clone()))
+        .collect();
+
+    if tasks.is_empty() {
+        finish_run(&run, &run_path);
+        if !plans.is_empty() {
+            status!("{}", style("✅ All images already exist!").green().bold());
+        }
+        print_config_summary(&plans, &stats, &load_errors, shared_root);
+        status!(
+            "📁 Output: {}",
+            output_location(&plans, &output_dir, shared_root)
+        );
+        save_failures(&plans, &stats, args);
+        if args.gallery {
+            save_galleries(&plans, &stats);
+        }
+        return check_load_errors(&load_errors, files.len());
+    }
+
+    let pending: Vec<ImageTask> = tasks.iter().map(|(_, _, task)| task.clone()).collect();
+    confirm_run(&pending, args)?;
+
+    if args.resume {
+        status!(
+            "{}",
+            style(format!(
+                "⏯️  Resuming run: {} of {} tasks left",
+                tasks.len(),
+                run.tasks.len()
+            ))
+            .cyan()
+        );
+    }
+    run.save(&run_path)?;
+    let run = Arc::new(Mutex::new(run));
+
+    // Limit concurrent requests (OpenAI has rate limits)
+    let throttle = Arc::new(Throttle::new(args.concurrency as usize, args.adaptive));
+
+    // One connection pool and rate limit budget for every config; each keeps
+    // its own models. A 429 retry slows the throttle down; each task reports
+    // its retries on the progress bar. Ctrl-C aborts outstanding requests.
+    let cancel = ui::cancel_on_ctrl_c();
+    let rate_limited = Arc::clone(&throttle);
+    let mut client = OpenAIClient::new()?
+        .with_retry_config(RetryConfig {
+            max_attempts: args.max_attempts,
+            base_delay: Duration::from_secs(2),
+            max_delay: Duration::from_secs(60),
+            on_retry: Some(RetryHook::new(move |event| {
+                if event.error.status() == Some(429) {
+                    rate_limited.on_rate_limited();
+                }
+            })),
+            ..RetryConfig::default()
+        })
+        .with_rate_limit_tracking(true)
+        .with_cancellation(cancel.clone());
+    if let Some(secs) = args.timeout {
+        let timeouts = client
+            .timeouts()
+            .with_request_timeout(Duration::from_secs(secs));
+        client = client.with_timeouts(timeouts)?;
+    }
+    let clients: Vec<Arc<dyn AiClient>> = plans
+        .iter()
+        .map(|plan| Arc::new(client.clone().with_models(plan.models.clone())) as Arc<dyn AiClient>)
+        .collect();
+
+    status!(
+        "{}",
+        style(format!("🎨 Generating {} new images...", tasks.len()))
+            .cyan()
+            .bold()
+    );
+
+    // Create progress bar
+    let pb = Arc::new(ui::new_bar(tasks.len() as u64));
+    pb.set_style(
+        ProgressStyle::with_template(
+            "{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} {msg}",
+        )?
+        .progress_chars("#>-"),
+    );
+    pb.set_message("Generating images...");
+    pb.enable_steady_tick(std::time::Duration::from_millis(100));
+
+    // Results are merged into each config's manifest as they come in
+    let mut manifests = Vec::new();
+    for plan in &plans {
+        let manifest_path = plan.output_root.join(MANIFEST_FILE);
+        let manifest = if args.no_manifest {
+            None
+        } else {
+            Some(Arc::new(Mutex::new(Manifest::load(&manifest_path)?)))
+        };
+        manifests.push((manifest, manifest_path));
+    }
+
+    let theme_limits = Arc::new(ThemeLimits::new(
+        args.per_theme_concurrency.map(usize::from),
+    ));
+
+    // Create concurrent tasks
+    let mut indices = Vec::new();
+    let mut handles = Vec::new();
+
+    for (position, index, task) in tasks {
+        let client = Arc::clone(&clients[index]);
+        let run = Arc::clone(&run);
+        let run_path = run_path.clone();
+        let throttle = Arc::clone(&throttle);
+        let theme_limits = Arc::clone(&theme_limits);
+        let (manifest, manifest_path) = manifests[index].clone();
+        let output_root = plans[index].output_root.clone();
+        let pb_clone = Arc::clone(&pb);
+        let theme_name = task.theme_name.clone();
+        let prompt_name = task.prompt_name.clone();
+        let cancel = cancel.clone();
+
+        let handle = tokio::spawn(async move {
+            // Wait for a free slot, the theme's first
+            let _theme_permit = theme_limits.acquire(index, &theme_name).await;
+            let _permit = throttle.acquire().await;
+
+            // Update progress bar message
+            pb_clone.set_message(format!("Processing {}/{}", theme_name, prompt_name));
+
+            let pb_retry = Arc::clone(&pb_clone);
+            let retry_name = format!("{}/{}", theme_name, prompt_name);
+            let generate = with_retry_hook(
+                move |event| {
+                    pb_retry.set_message(format!(
+                        "Retrying {} ({}/{}) in {}s",
+                        retry_name,
+                        event.attempt + 1,
+                        event.max_attempts,
+                        event.delay.as_secs()
+                    ))
+                },
+                generate_and_save_image(&client, &task),
+            );
+            // Images are saved without awaiting, so this only drops a request
+            // or a retry delay
+            let result = tokio::select! {
+                biased;
+                _ = cancel.cancelled() => Err(OpenAIError::Cancelled.into()),
+                result = generate => result,
+            };
+
+            if result.is_ok() {
+                let mut run = run.lock().unwrap();
+                run.tasks[position].done = true;
+                if let Err(e) = run.save(&run_path) {
+                    eprintln!("{} {:#}", style(WARNING).yellow(), e);
+                }
+            }
+
+            // A cancelled task keeps its earlier manifest entry, if any
+            if let Some(manifest) = manifest
+                && !is_cancelled(&result)
+            {
+                let mut manifest = manifest.lock().unwrap();
+                let error = result.as_ref().err().map(|e| format!("{:#}", e));
+                manifest.record(&output_root, &task, error);
+                if let Err(e) = manifest.save(&manifest_path) {
+                    eprintln!("{} {:#}", style(WARNING).yellow(), e);
+                }
+            }
+
+            // Update progress
+            pb_clone.inc(1);
+
+            (prompt_name, theme_name, result)
+        });
+
+        indices.push(index);
+        handles.push(handle);
+    }
+
+    // Wait for all tasks to complete
+    let results = futures::future::join_all(handles).await;
+
+    pb.finish_and_clear();
+
+    // Count successes and failures per config, and collect errors
+    for (index, result) in indices.into_iter().zip(results) {
+        let stats = &mut stats[index];
+        match result {
+            Ok((prompt_name, theme_name, Ok(_))) => {
+                stats.generated += 1;
+                status!(
+                    "{}  {}{}/{}",
+                    style(CHECK).green(),
+                    config_prefix(&plans[index], shared_root),
+                    theme_name,
+                    prompt_name
+                );
+            }
+            Ok((prompt_name, theme_name, Err(e))) if is_policy_rejection(&e) => {
+                stats
+                    .rejections
+                    .push((theme_name, prompt_name, format!("{:#}", e)));
+            }
+            Ok((prompt_name, theme_name, Err(e))) => {
+                stats
+                    .failures
+                    .push((theme_name, prompt_name, format!("{:#}", e)));
+            }
+            Err(e) => {
+                stats
+                    .failures
+                    .push(("Unknown".to_string(), "Unknown".to_string(), e.to_string()));
+            }
+        }
+    }
+
+    // Print failures if any
+    for (plan, stats) in plans.iter().zip(&stats) {
+        let prefix = config_prefix(plan, shared_root);
+        for (theme_name, prompt_name, error) in &stats.failures {
+            eprintln!(
+                "{}  {}{}/{}: {}",
+                style(CROSS).red(),
+                prefix,
+                theme_name,
+                prompt_name,
+                error
+            );
+        }
+        for (theme_name, prompt_name, error) in &stats.rejections {
+            eprintln!(
+                "{}  {}{}/{} rejected by content policy: {}",
+                style(BLOCKED).red(),
+                prefix,
+                theme_name,
+                prompt_name,
+                error
+            );
+        }
+    }
+
+    // Print summary
+    let success_count: usize = stats.iter().map(|stats| stats.generated).sum();
+    let failure_count: usize = stats.iter().map(|stats| stats.failures.len()).sum();
+    let rejection_count: usize = stats.iter().map(|stats| stats.rejections.len()).sum();
+    status!();
+    if failure_count == 0 && rejection_count == 0 {
+        status!(
+            "{}",
+            style(format!(
+                "🎉 All {} images generated successfully!",
+                success_count
+            ))
+            .green()
+            .bold()
+        );
+    } else {
+        status!(
+            "{}",
+            style(format!(
+                "🎉 Image generation completed! Success: {}, Failed after retries: {}, Rejected by policy: {}",
+                success_count, failure_count, rejection_count
+            ))
+            .yellow()
+            .bold()
+        );
+    }
+    print_config_summary(&plans, &stats, &load_errors, shared_root);
+    status!(
+        "📁 Output: {}",
+        output_location(&plans, &output_dir, shared_root)
+    );
+    finish_run(&run.lock().unwrap(), &run_path);
+    save_failures(&plans, &stats, args);
+    if args.gallery {
+        save_galleries(&plans, &stats);
+    }
+
+    check_load_errors(&load_errors, files.len())
+}
+
+/// Remove the run file once every task is done, else say how to continue
+fn finish_run(run: &RunFile, run_path: &Path) {
+    let pending = run.pending();
+    if pending > 0 {
+        status!(
+            "{}",
+            style(format!(
+                "⏸️  {} of {} tasks unfinished; rerun the same command with --resume to continue them in order",
+                pending,
+                run.tasks.len()
+            ))
+            .yellow()
+        );
+    } else if run_path.exists()
+        && let Err(e) = fs::remove_file(run_path)
+    {
+        eprintln!(
+            "{} Failed to remove {}: {}",
+            style(WARNING).yellow(),
+            run_path.display(),
+            e
+        );
+    }
+}
+
+/// `config: ` before task names when several configs are processed
+fn config_prefix(plan: &ConfigPlan, shared_root: bool) -> String {
+    if shared_root {
+        String::new()
+    } else {
+        format!("{}: ", plan.label)
+    }
+}
+
+/// Per-config breakdown, printed when several configs are processed
+fn print_config_summary(
+    plans: &[ConfigPlan],
+    stats: &[ConfigStats],
+    load_errors: &[String],
+    shared_root: bool,
+) {
+    if shared_root {
+        return;
+    }
+    for (plan, stats) in plans.iter().zip(stats) {
+        status!(
+            "  {}: {} generated, {} cached, {} failed, {} rejected → {}",
+            plan.label,
+            stats.generated,
+            stats.cached,
+            stats.failures.len(),
+            stats.rejections.len(),
+            style(plan.output_root.display()).dim()
+        );
+    }
+    for label in load_errors {
+        status!("  {}: {}", label, style("failed to load").red());
+    }
+}
+
+fn output_location(plans: &[ConfigPlan], output_dir: &Path, shared_root: bool) -> String {
+    match plans.first() {
+        Some(plan) if shared_root => plan.output_root.display().to_string(),
+        _ => std::path::absolute(output_dir)
+            .unwrap_or_else(|_| output_dir.to_path_buf())
+            .display()
+            .to_string(),
+    }
+}
+
+/// Write each config's failures file and print how to rerun them
+fn save_failures(plans: &[ConfigPlan], stats: &[ConfigStats], args: &Args) {
+    for (plan, stats) in plans.iter().zip(stats) {
+        match write_failures(&plan.output_root, &plan.config, &stats.failures) {
+            Ok(Some(path)) => {
+                status!(
+                    "{}",
+                    style(format!(
+                        "🔁 {} failed tasks saved to {}; rerun them with:",
+                        stats.failures.len(),
+                        path.display()
+                    ))
+                    .yellow()
+                );
+                status!(
+                    "   imgen --retry-file {}{}",
+                    path.display(),
+                    args.rerun_flags()
+                );
+            }
+            Ok(None) => {}
+            Err(e) => eprintln!("{} {:#}", style(WARNING).yellow(), e),
+        }
+    }
+}
+
+/// Save the failed tasks' config as `imgen-failures.yaml` in the output root
+///
+/// A run without failures removes the file left by an earlier run. Policy
+/// rejections are not included: rerunning them unchanged fails again.
+fn write_failures(
+    output_root: &Path,
+    config: &Config,
+    failures: &[(String, String, String)],
+) -> Result<Option<PathBuf>> {
+    let path = output_root.join(FAILURES_FILE);
+    if failures.is_empty() {
+        if path.exists() {
+            fs::remove_file(&path)
+                .with_context(|| format!("Failed to remove {}", path.display()))?;
+        }
+        return Ok(None);
+    }
+
+    let failed: Vec<(String, String)> = failures
+        .iter()
+        .map(|(theme, prompt, _)| (theme.clone(), prompt.clone()))
+        .collect();
+    let yaml = serde_yaml::to_string(&config.retry_config(&failed)?)
+        .context("Failed to serialize the failed tasks")?;
+    let content = format!(
+        "# Tasks that failed; rerun with: imgen --retry-file {}\n{}",
+        path.display(),
+        yaml
+    );
+    write_atomically(&path, content.as_bytes())?;
+    Ok(Some(path))
+}
+
+/// Write each config's gallery and print where it is
+fn save_galleries(plans: &[ConfigPlan], stats: &[ConfigStats]) {
+    for (plan, stats) in plans.iter().zip(stats) {
+        match write_gallery(plan, stats) {
+            Ok(path) => status!("🖼️  Gallery: {}", path.display()),
+            Err(e) => eprintln!("{} {:#}", style(WARNING).yellow(), e),
+        }
+    }
+}
+
+/// One tile of the gallery
+struct GalleryItem<'a> {
+    task: &'a ImageTask,
+    /// Image and thumbnail paths relative to the output root; unset when the
+    /// image is missing
+    image: Option<(String, String)>,
+    bytes: u64,
+    error: Option<String>,
+}
+
+/// Write `gallery.html` for a config's tasks, making missing thumbnails first
+///
+/// Links are relative to the output root, so the directory can be zipped and
+/// shared as is.
+fn write_gallery(plan: &ConfigPlan, stats: &ConfigStats) -> Result<PathBuf> {
+    let errors: BTreeMap<(&str, &str), String> = stats
+        .failures
+        .iter()
+        .map(|(theme, prompt, error)| ((theme.as_str(), prompt.as_str()), error.clone()))
+        .chain(stats.rejections.iter().map(|(theme, prompt, error)| {
+            (
+                (theme.as_str(), prompt.as_str()),
+                format!("Rejected by content policy: {}", error),
+            )
+        }))
+        .collect();
+
+    // Themes in config order, prompts in config order within each
+    let tasks: Vec<&ImageTask> = plan
+        .config
+        .themes
+        .iter()
+        .flat_map(|theme| {
+            plan.tasks
+                .iter()
+                .filter(move |task| task.theme_name == theme.name)
+        })
+        .collect();
+
+    let items: Vec<GalleryItem> = tasks
+        .par_iter()
+        .map(|task| {
+            let error = errors
+                .get(&(task.theme_name.as_str(), task.prompt_name.as_str()))
+                .cloned();
+            if check_cached_image(&task.output_path) != CachedImage::Valid {
+                return GalleryItem {
+                    task,
+                    image: None,
+                    bytes: 0,
+                    error: error.or_else(|| Some("Not generated".to_string())),
+                };
+            }
+
+            let relative = task
+                .output_path
+                .strip_prefix(&plan.output_root)
+                .unwrap_or(&task.output_path);
+            let thumb = Path::new(THUMBS_DIR).join(relative.with_extension("jpg"));
+            // Without a thumbnail the page shows the full image
+            let thumb = match write_thumbnail(&task.output_path, &plan.output_root.join(&thumb)) {
+                Ok(()) => url_path(&thumb),
+                Err(e) => {
+                    eprintln!("{} {:#}", style(WARNING).yellow(), e);
+                    url_path(relative)
+                }
+            };
+            GalleryItem {
+                task,
+                image: Some((url_path(relative), thumb)),
+                bytes: fs::metadata(&task.output_path)
+                    .map(|m| m.len())
+                    .unwrap_or_default(),
+                error: None,
+            }
+        })
+        .collect();
+
+    let path = plan.output_root.join(GALLERY_FILE);
+    write_atomically(&path, render_gallery(&plan.label, &items).as_bytes())?;
+    Ok(path)
+}
+
+/// Downscale an image to at most THUMB_SIZE pixels as a JPEG; a thumbnail
+/// newer than its image is kept
+fn write_thumbnail(image_path: &Path, thumb_path: &Path) -> Result<()> {
+    let modified = |path: &Path| fs::metadata(path).and_then(|m| m.modified()).ok();
+    if let (Some(image), Some(thumb)) = (modified(image_path), modified(thumb_path))
+        && thumb >= image
+    {
+        return Ok(());
+    }
+
+    let image = image::open(image_path)
+        .with_context(|| format!("Failed to decode {}", image_path.display()))?;
+    let thumb = image.thumbnail(THUMB_SIZE, THUMB_SIZE).to_rgba8();
+
+    // JPEG has no alpha: put transparent backgrounds on white
+    let flattened = image::RgbImage::from_fn(thumb.width(), thumb.height(), |x, y| {
+        let [r, g, b, a] = thumb.get_pixel(x, y).0;
+        let blend =
+            |c: u8| ((u16::from(c) * u16::from(a) + 255 * (255 - u16::from(a))) / 255) as u8;
+        image::Rgb([blend(r), blend(g), blend(b)])
+    });
+
+    let mut data = Vec::new();
+    JpegEncoder::new_with_quality(&mut data, 80).encode_image(&flattened)?;
+    if let Some(dir) = thumb_path.parent() {
+        fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create directory: {}", dir.display()))?;
+    }
+    write_atomically(thumb_path, &data)
+}
+
+/// Relative path with `/` separators, for use in links
+fn url_path(path: &Path) -> String {
+    path.components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn render_gallery(title: &str, items: &[GalleryItem]) -> String {
+    let failed = items.iter().filter(|item| item.image.is_none()).count();
+    let mut html = format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>{title}</title>
+<style>
+body {{ font-family: system-ui, sans-serif; margin: 1.5rem; background: #fafafa; color: #222; }}
+.grid {{ display: grid; grid-template-columns: repeat(auto-fill, minmax(220px, 1fr)); gap: 1rem; }}
+figure {{ margin: 0; background: #fff; border-radius: 6px; box-shadow: 0 1px 3px #0003; overflow: hidden; }}
+figure img, .placeholder {{ display: block; width: 100%; aspect-ratio: 1; object-fit: contain; background: #eee; }}
+.placeholder {{ display: flex; align-items: center; justify-content: center; box-sizing: border-box;
+  padding: 1rem; background: #fdecea; color: #b71c1c; font-size: 0.85rem; text-align: center; overflow: hidden; }}
+figcaption {{ display: flex; justify-content: space-between; gap: 0.5rem; padding: 0.5rem; font-size: 0.85rem; }}
+figcaption span {{ color: #777; white-space: nowrap; }}
+</style>
+</head>
+<body>
+<h1>{title}</h1>
+<p>{total} images, {failed} missing</p>
+"#,
+        title = html_escape(title),
+        total = items.len(),
+        failed = failed,
+    );
+
+    let mut current_theme = None;
+    for item in items {
+        let task = item.task;
+        if current_theme != Some(&task.theme_name) {
+            if current_theme.is_some() {
+                html.push_str("</div>\n");
+            }
+            html.push_str(&format!(
+                "<h2>{}</h2>\n<div class=\"grid\">\n",
+                html_escape(&task.theme_name)
+            ));
+            current_theme = Some(&task.theme_name);
+        }
+
+        let tile = match (&item.image, &item.error) {
+            (Some((image, thumb)), _) => format!(
+                "<a href=\"{}\"><img src=\"{}\" loading=\"lazy\" alt=\"{}\"></a>",
+                html_escape(image),
+                html_escape(thumb),
+                html_escape(&task.prompt_name)
+            ),
+            (None, error) => format!(
+                "<div class=\"placeholder\">{}</div>",
+                html_escape(error.as_deref().unwrap_or("Failed"))
+            ),
+        };
+        let size = if item.image.is_some() {
+            format_size(item.bytes)
+        } else {
+            "failed".to_string()
+        };
+        html.push_str(&format!(
+            "<figure title=\"{}\">{}<figcaption>{}<span>{}</span></figcaption></figure>\n",
+            html_escape(&task.full_prompt),
+            tile,
+            html_escape(&task.prompt_name),
+            size
+        ));
+    }
+    if current_theme.is_some() {
+        html.push_str("</div>\n");
+    }
+    html.push_str("</body>\n</html>\n");
+    html
+}
+
+fn html_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Format file size in human-readable format
+fn format_size(bytes: u64) -> String {
+    const KB: u64 = 1024;
+    const MB: u64 = KB * 1024;
+
+    if bytes >= MB {
+        format!("{:.1} MB", bytes as f64 / MB as f64)
+    } else if bytes >= KB {
+        format!("{:.0} KB", bytes as f64 / KB as f64)
+    } else {
+        format!("{} B", bytes)
+    }
+}
+
+/// Skipped configs make the run fail once the others are done
+fn check_load_errors(load_errors: &[String], total: usize) -> Result<()> {
+    if load_errors.is_empty() {
+        Ok(())
+    } else {
+        Err(anyhow::anyhow!(
+            "{} of {} config files failed to load",
+            load_errors.len(),
+            total
+        ))
+    }
+}
+
+fn is_cancelled(result: &Result<()>) -> bool {
+    result
+        .as_ref()
+        .err()
+        .and_then(|e| e.downcast_ref::<OpenAIError>())
+        .is_some_and(|e| matches!(e, OpenAIError::Cancelled))
+}
+
+fn is_policy_rejection(error: &anyhow::Error) -> bool {
+    error
+        .downcast_ref::<OpenAIError>()
+        .is_some_and(OpenAIError::is_policy_rejection)
+}
+
+async fn generate_and_save_image(client: &Arc<dyn AiClient>, task: &ImageTask) -> Result<()> {
+    // Hold back while the last response said the rate limit is nearly used up
+    client.acquire_permit().await;
+
+    // Generate image (returns bytes directly now); reference images are edited instead
+    let image_data = match &task.edit {
+        Some(edit) => {
+            let image = fs::read(&edit.image)
+                .with_context(|| format!("Failed to read {}", edit.image.display()))?;
+            let mask = edit
+                .mask
+                .as_ref()
+                .map(|mask| {
+                    fs::read(mask).with_context(|| format!("Failed to read {}", mask.display()))
+                })
+                .transpose()?;
+            client
+                .edit_image(&task.full_prompt, image, mask, &task.size, &task.options)
+                .await
+                .context("Failed to edit image")?
+        }
+        None => client
+            .generate_image(&task.full_prompt, &task.size, &task.options)
+            .await
+            .context("Failed to generate image")?,
+    };
+
+    let output = task.output;
+    let metadata = output
+        .embed_metadata
+        .then(|| png_metadata(task, SystemTime::now()));
+    if output.keep_original && output.converts() {
+        let original = task.output_path.with_extension("original.png");
+        let original_data = match &metadata {
+            Some(metadata) => embed_png_text(&image_data, metadata)?,
+            None => image_data.clone(),
+        };
+        write_atomically(&original, &original_data)
+            .with_context(|| format!("Failed to save image to {}", original.display()))?;
+    }
+    let mut image_data =
+        tokio::task::spawn_blocking(move || convert_image(&image_data, &output)).await??;
+    if let Some(metadata) = &metadata
+        && output.format == OutputFormat::Png
+    {
+        image_data = embed_png_text(&image_data, metadata)?;
+    }
+
+    // Save image to file
+    write_atomically(&task.output_path, &image_data)
+        .with_context(|| format!("Failed to save image to {}", task.output_path.display()))?;
+
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args = Args::parse();
+    ui::init(false, args.no_emoji);
+    // Warnings only by default; LOG_LEVEL=debug logs every API request
+    ui::init_tracing("warn");
+
+    let result = match &args.inspect {
+        Some(path) => inspect_image(path),
+        None => process_configs(&args).await,
+    };
+    if let Err(e) = result {
+        eprintln!("{}", style(format!("Error: {}", e)).red().bold());
+        std::process::exit(1);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use swiss_knife::mock::{MockAiClient, MockCall};
+
+    #[test]
+    fn test_calculate_hash() {
+        let system_prompt = "test system";
+        let theme_instruction = "test theme";
+        let prompt = "test prompt";
+
+        let options = ImageOptions::default();
+        let render = RenderSettings {
+            style: "",
+            model: "gpt-image-1",
+            size: "1024x1024",
+            options: &options,
+            inputs: "",
+        };
+
+        let hash1 = calculate_hash(system_prompt, theme_instruction, prompt, &render);
+        let hash2 = calculate_hash(system_prompt, theme_instruction, prompt, &render);
+
+        // Same inputs should produce same hash
+        assert_eq!(hash1, hash2);
+        assert_eq!(hash1.len(), 6); // Should be 6 characters
+
+        // Different inputs should produce different hash
+        let hash3 = calculate_hash("different", theme_instruction, prompt, &render);
+        assert_ne!(hash1, hash3);
+    }
+
+    #[test]
+    fn test_calculate_hash_tracks_render_settings() {
+        let hash = |model: &str, size: &str, quality: Option<&str>| {
+            let options = ImageOptions {
+                quality: quality.map(str::to_string),
+                background: None,
+            };
+            let render = RenderSettings {
+                style: "",
+                model,
+                size,
+                options: &options,
+                inputs: "",
+            };
+            calculate_hash("system", "theme", "prompt", &render)
+        };
+
+        let base = hash("gpt-image-1", "1024x1024", None);
+        assert_ne!(base, hash("dall-e-3", "1024x1024", None));
+        assert_ne!(base, hash("gpt-image-1", "1536x1024", None));
+        assert_ne!(base, hash("gpt-image-1", "1024x1024", Some("high")));
+
+        let options = ImageOptions::default();
+        let styled = RenderSettings {
+            style: "watercolor",
+            model: "gpt-image-1",
+            size: "1024x1024",
+            options: &options,
+            inputs: "",
+        };
+        assert_ne!(base, calculate_hash("system", "theme", "prompt", &styled));
+    }
+
+    #[test]
+    fn test_config_optional_render_keys() {
+        let config: Config = serde_yaml::from_str(
+            "system_prompt: s\nstyle: square\nquality: high\nbackground: transparent\nthemes: []\nprompts: []\n",
+        )
+        .unwrap();
+        assert_eq!(config.model, None);
+        assert_eq!(config.quality, Some(Quality::High));
+        assert_eq!(config.background, Some(Background::Transparent));
+    }
+
+    #[test]
+    fn test_create_output_filename() {
+        let png = OutputSettings::default();
+        let filename = create_output_filename("Memory Safety", "abc123", &png);
+        assert_eq!(filename, "memory-safety-abc123.png");
+
+        let filename2 = create_output_filename("Concurrency-Safety", "def456", &png);
+        assert_eq!(filename2, "concurrency-safety-def456.png");
+
+        let webp = OutputSettings {
+            format: OutputFormat::Webp,
+            max_width: Some(1200),
+            ..OutputSettings::default()
+        };
+        assert_eq!(
+            create_output_filename("Sunset", "abc123", &webp),
+            "sunset-abc123-w1200.webp"
+        );
+    }
+
+    fn encoded_png(width: u32, height: u32) -> Vec<u8> {
+        let image = image::RgbaImage::from_fn(width, height, |x, y| {
+            image::Rgba([(x * 4) as u8, (y * 4) as u8, 128, 255])
+        });
+        let mut data = Vec::new();
+        DynamicImage::ImageRgba8(image)
+            .write_to(&mut Cursor::new(&mut data), ImageFormat::Png)
+            .unwrap();
+        data
+    }
+
+    #[test]
+    fn test_convert_image_formats_and_width() {
+        let png = encoded_png(40, 20);
+        assert_eq!(
+            convert_image(&png, &OutputSettings::default()).unwrap(),
+            png
+        );
+
+        for (format, expected) in [
+            (OutputFormat::Webp, ImageFormat::WebP),
+            (OutputFormat::Jpeg, ImageFormat::Jpeg),
+            (OutputFormat::Png, ImageFormat::Png),
+        ] {
+            let output = OutputSettings {
+                format,
+                max_width: Some(10),
+                ..OutputSettings::default()
+            };
+            let data = convert_image(&png, &output).unwrap();
+            assert_eq!(image::guess_format(&data).unwrap(), expected);
+            assert!(is_complete_image(&data), "{:?}", format);
+            let decoded = image::load_from_memory(&data).unwrap();
+            assert_eq!((decoded.width(), decoded.height()), (10, 5));
+        }
+
+        // Narrow images are never scaled up
+        let output = OutputSettings {
+            format: OutputFormat::Jpeg,
+            max_width: Some(100),
+            ..OutputSettings::default()
+        };
+        let decoded = image::load_from_memory(&convert_image(&png, &output).unwrap()).unwrap();
+        assert_eq!((decoded.width(), decoded.height()), (40, 20));
+    }
+
+    #[test]
+    fn test_truncated_converted_images_are_incomplete() {
+        let png = encoded_png(8, 8);
+        for format in [OutputFormat::Webp, OutputFormat::Jpeg] {
+            let output = OutputSettings {
+                format,
+                ..OutputSettings::default()
+            };
+            let data = convert_image(&png, &output).unwrap();
+            assert!(is_complete_image(&data));
+            assert!(!is_complete_image(&data[..data.len() - 3]));
+        }
+    }
+
+    fn task(size: &str, cached: bool) -> ImageTask {
+        ImageTask {
+            theme_name: "Nature".to_string(),
+            prompt_name: "Sunset".to_string(),
+            full_prompt: "prompt".to_string(),
+            output_path: PathBuf::from("nature/sunset-abc123.png"),
+            hash: "abc123".to_string(),
+            model: "gpt-image-1".to_string(),
+            size: size.to_string(),
+            options: ImageOptions::default(),
+            output: OutputSettings::default(),
+            edit: None,
+            cached,
+        }
+    }
+
+    #[test]
+    fn test_manifest_merges_with_existing() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(MANIFEST_FILE);
+        assert_eq!(Manifest::load(&path).unwrap(), Manifest::default());
+
+        let mut first = task("1024x1024", false);
+        first.output_path = dir.path().join("nature/sunset-abc123.png");
+        fs::create_dir_all(dir.path().join("nature")).unwrap();
+        fs::write(&first.output_path, b"png").unwrap();
+        let mut manifest = Manifest::load(&path).unwrap();
+        manifest.record(dir.path(), &first, None);
+        manifest.save(&path).unwrap();
+
+        // A rerun appends its results to the existing entries
+        let mut second = task("1536x1024", false);
+        second.prompt_name = "Dawn".to_string();
+        second.output_path = dir.path().join("nature/dawn-def456.png");
+        let mut manifest = Manifest::load(&path).unwrap();
+        manifest.record(dir.path(), &second, Some("rate limited".to_string()));
+        manifest.save(&path).unwrap();
+
+        let manifest = Manifest::load(&path).unwrap();
+        assert_eq!(manifest.images.len(), 2);
+        let sunset = &manifest.images["nature/sunset-abc123.png"];
+        assert_eq!(sunset.bytes, Some(3));
+        assert_eq!(sunset.error, None);
+        assert_eq!(sunset.model, "gpt-image-1");
+        let dawn = &manifest.images["nature/dawn-def456.png"];
+        assert_eq!(dawn.bytes, None);
+        assert_eq!(dawn.error.as_deref(), Some("rate limited"));
+
+        // Regenerating a file replaces its entry
+        let mut manifest = manifest;
+        fs::write(&second.output_path, b"fixed").unwrap();
+        manifest.record(dir.path(), &second, None);
+        assert_eq!(manifest.images.len(), 2);
+        assert_eq!(manifest.images["nature/dawn-def456.png"].bytes, Some(5));
+        assert_eq!(manifest.images["nature/dawn-def456.png"].error, None);
+    }
+
+    fn png_bytes() -> Vec<u8> {
+        let mut data = PNG_SIGNATURE.to_vec();
+        data.extend_from_slice(b"\0\0\0\rIHDR-and-some-pixel-data");
+        data.extend_from_slice(&PNG_IEND);
+        data
+    }
+
+    #[test]
+    fn test_check_cached_image_detects_partial_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sunset-abc123.png");
+        assert_eq!(check_cached_image(&path), CachedImage::Missing);
+
+        let png = png_bytes();
+        fs::write(&path, &png).unwrap();
+        assert_eq!(check_cached_image(&path), CachedImage::Valid);
+
+        // Truncated mid-write
+        fs::write(&path, &png[..png.len() - 5]).unwrap();
+        assert_eq!(check_cached_image(&path), CachedImage::Corrupt);
+
+        fs::write(&path, b"").unwrap();
+        assert_eq!(check_cached_image(&path), CachedImage::Corrupt);
+
+        fs::write(&path, b"<html>error</html>").unwrap();
+        assert_eq!(check_cached_image(&path), CachedImage::Corrupt);
+    }
+
+    #[test]
+    fn test_write_atomically_leaves_no_tmp_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sunset-abc123.png");
+        fs::write(&path, b"old partial").unwrap();
+
+        write_atomically(&path, &png_bytes()).unwrap();
+        assert_eq!(check_cached_image(&path), CachedImage::Valid);
+        assert!(!dir.path().join("sunset-abc123.png.tmp").exists());
+    }
+
+    #[test]
+    fn test_estimate_cost_counts_new_images() {
+        let tasks = [
+            task("1024x1024", false),
+            task("1536x1024", false),
+            task("1536x1024", true),
+        ];
+        assert!((estimate_cost(&tasks, None) - 0.105).abs() < 1e-9);
+        assert!((estimate_cost(&tasks, Some(0.5)) - 1.0).abs() < 1e-9);
+        assert_eq!(estimate_cost(&[task("1024x1024", true)], None), 0.0);
+    }
+
+    #[test]
+    fn test_fixed_throttle_limits_in_flight() {
+        let start = Instant::now();
+        let mut state = ThrottleState::new(2, false, Duration::from_secs(30), start);
+        assert!(state.try_acquire(start));
+        assert!(state.try_acquire(start));
+        assert!(!state.try_acquire(start));
+
+        // A fixed throttle ignores rate limits
+        state.on_rate_limited(start);
+        assert_eq!(state.limit, 2);
+        state.in_flight -= 1;
+        assert!(state.try_acquire(start));
+    }
+
+    #[test]
+    fn test_adaptive_throttle_halves_on_rate_limits() {
+        let start = Instant::now();
+        let mut state = ThrottleState::new(8, true, Duration::from_secs(30), start);
+        state.on_rate_limited(start);
+        assert_eq!(state.limit, 4);
+        state.on_rate_limited(start);
+        state.on_rate_limited(start);
+        state.on_rate_limited(start);
+        assert_eq!(state.limit, 1);
+
+        // Requests already in flight keep running, new ones wait
+        state.in_flight = 3;
+        assert!(!state.try_acquire(start + Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn test_adaptive_throttle_ramps_back_up() {
+        let start = Instant::now();
+        let mut state = ThrottleState::new(4, true, Duration::from_secs(30), start);
+        state.on_rate_limited(start);
+        assert_eq!(state.limit, 2);
+
+        // No ramp-up before the cooldown
+        state.ramp_up(start + Duration::from_secs(29));
+        assert_eq!(state.limit, 2);
+
+        // One step per cooldown
+        state.ramp_up(start + Duration::from_secs(30));
+        assert_eq!(state.limit, 3);
+        state.ramp_up(start + Duration::from_secs(45));
+        assert_eq!(state.limit, 3);
+        state.ramp_up(start + Duration::from_secs(60));
+        assert_eq!(state.limit, 4);
+
+        // Never above the maximum
+        state.ramp_up(start + Duration::from_secs(600));
+        assert_eq!(state.limit, 4);
+
+        // A 429 mid-ramp restarts the cooldown
+        state.on_rate_limited(start + Duration::from_secs(600));
+        assert_eq!(state.limit, 2);
+        state.ramp_up(start + Duration::from_secs(620));
+        assert_eq!(state.limit, 2);
+    }
+
+    #[test]
+    fn test_policy_rejection_survives_context() {
+        let rejected = anyhow::Error::from(OpenAIError::from_response(
+            400,
+            r#"{"error":{"code":"content_policy_violation","message":"rejected"}}"#,
+            None,
+        ))
+        .context("Failed to generate image");
+        assert!(is_policy_rejection(&rejected));
+        assert!(!is_policy_rejection(&anyhow::anyhow!("disk full")));
+    }
+
+    #[test]
+    fn test_theme_dir_name() {
+        assert_eq!(theme_dir_name("Nature"), "nature");
+        assert_eq!(theme_dir_name("AI / ML"), "ai-ml");
+        assert_eq!(theme_dir_name("../etc"), "etc");
+        assert_eq!(theme_dir_name("///"), "theme");
+    }
+
+    #[test]
+    fn test_config_image_size() {
+        let mut config = Config {
             system_prompt: "test".to_string(),
-            style: "square".to_string(),
+            style: "minimalist watercolor".to_string(),
+            size: None,
+            aspect: Some("square".to_string()),
+            model: None,
+            quality: None,
+            background: None,
+            variables: BTreeMap::new(),
             themes: vec![],
             prompts: vec![],
         };
 
         assert_eq!(config.get_image_size(), "1024x1024");
 
-        config.style = "landscape".to_string();
+        config.aspect = Some("landscape".to_string());
         assert_eq!(config.get_image_size(), "1536x1024");
 
-        config.style = "portrait".to_string();
+        config.aspect = Some("portrait".to_string());
         assert_eq!(config.get_image_size(), "1024x1536");
 
-        config.style = "unknown".to_string();
+        config.size = Some("1792x1024".to_string());
+        assert_eq!(config.get_image_size(), "1792x1024");
+
+        config.size = None;
+        config.aspect = None;
         assert_eq!(config.get_image_size(), "1024x1024"); // default
+
+        // An art style never picks the size
+        assert_eq!(config.migrate_legacy_style(), None);
+        assert_eq!(config.style, "minimalist watercolor");
+    }
+
+    fn vars(pairs: &[(&str, &str)]) -> BTreeMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_expand_template() {
+        let variables = vars(&[("brand", "Acme"), ("color", "teal")]);
+        assert_eq!(
+            expand_template("{brand} logo in {color}, {color} only", &variables).unwrap(),
+            "Acme logo in teal, teal only"
+        );
+        assert_eq!(
+            expand_template("no placeholders", &variables).unwrap(),
+            "no placeholders"
+        );
+
+        // Doubled braces are literal
+        assert_eq!(
+            expand_template("JSON like {{\"a\": 1}} for {brand}", &variables).unwrap(),
+            "JSON like {\"a\": 1} for Acme"
+        );
+        assert_eq!(expand_template("{{brand}}", &variables).unwrap(), "{brand}");
+
+        let err = expand_template("{product} shot", &variables).unwrap_err();
+        assert_eq!(err.to_string(), "unknown variable '{product}'");
+        assert!(expand_template("open { brace", &variables).is_err());
+        assert!(expand_template("empty {}", &variables).is_err());
+    }
+
+    #[test]
+    fn test_nested_variables_are_rejected() {
+        assert!(check_variables(&vars(&[("brand", "Acme"), ("tagline", "{{not a var}}")])).is_ok());
+        let err = check_variables(&vars(&[("brand", "Acme"), ("title", "{brand} Pro")]))
+            .unwrap_err()
+            .to_string();
+        assert_eq!(
+            err,
+            "variable 'title' references '{brand}': variables can't reference other variables"
+        );
+    }
+
+    #[test]
+    fn test_apply_variables_precedence_and_errors() {
+        let yaml = "system_prompt: \"Brand {brand}\"\nvariables:\n  brand: Acme\n  color: teal\nthemes:\n  - name: Dark\n    instructions: \"{color} tones\"\nprompts:\n  - name: Logo\n    prompt: \"{brand} logo\"\n";
+
+        let mut config: Config = serde_yaml::from_str(yaml).unwrap();
+        config.apply_variables(&[]).unwrap();
+        assert_eq!(config.system_prompt, "Brand Acme");
+        assert_eq!(config.themes[0].instructions, "teal tones");
+        assert_eq!(config.prompts[0].prompt, "Acme logo");
+
+        // --var wins over the YAML
+        let mut config: Config = serde_yaml::from_str(yaml).unwrap();
+        config
+            .apply_variables(&[("brand".to_string(), "Globex".to_string())])
+            .unwrap();
+        assert_eq!(config.system_prompt, "Brand Globex");
+        assert_eq!(config.prompts[0].prompt, "Globex logo");
+        assert_eq!(config.themes[0].instructions, "teal tones");
+
+        // Unknown placeholders name the prompt and the variable
+        let mut config: Config =
+            serde_yaml::from_str(&yaml.replace("\"{brand} logo\"", "\"{product} logo\"")).unwrap();
+        let err = config.apply_variables(&[]).unwrap_err().to_string();
+        assert_eq!(err, "prompt 'Logo': unknown variable '{product}'");
+    }
+
+    #[test]
+    fn test_parse_variable() {
+        assert_eq!(
+            parse_variable("brand=Acme Corp").unwrap(),
+            ("brand".to_string(), "Acme Corp".to_string())
+        );
+        assert_eq!(parse_variable("eq=a=b").unwrap().1, "a=b");
+        assert!(parse_variable("brand").is_err());
+        assert!(parse_variable("=x").is_err());
+    }
+
+    fn reference_config(dir: &Path) -> Config {
+        let yaml = "system_prompt: s\nthemes:\n  - name: Teal\n    instructions: t\nprompts:\n  - name: Logo\n    prompt: restyle\n    reference: logo.png\n";
+        let mut config: Config = serde_yaml::from_str(yaml).unwrap();
+        config.resolve_references(dir).unwrap();
+        config
+    }
+
+    #[test]
+    fn test_references_resolve_against_config_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("logo.png"), b"v1").unwrap();
+        let config = reference_config(dir.path());
+        assert_eq!(
+            config.prompts[0].reference.as_deref(),
+            Some(dir.path().join("logo.png").as_path())
+        );
+
+        // Missing files fail with the prompt name
+        let yaml = "system_prompt: s\nthemes: []\nprompts:\n  - name: Logo\n    prompt: p\n    reference: gone.png\n";
+        let mut config: Config = serde_yaml::from_str(yaml).unwrap();
+        let err = config
+            .resolve_references(dir.path())
+            .unwrap_err()
+            .to_string();
+        assert!(
+            err.starts_with("prompt 'Logo': reference image not found"),
+            "{}",
+            err
+        );
+
+        let yaml = "system_prompt: s\nthemes: []\nprompts:\n  - name: Logo\n    prompt: p\n    mask: m.png\n";
+        let mut config: Config = serde_yaml::from_str(yaml).unwrap();
+        let err = config
+            .resolve_references(dir.path())
+            .unwrap_err()
+            .to_string();
+        assert_eq!(err, "prompt 'Logo': mask requires a reference image");
+    }
+
+    #[test]
+    fn test_edit_inputs_hash_tracks_content() {
+        let dir = tempfile::tempdir().unwrap();
+        let image = dir.path().join("logo.png");
+        fs::write(&image, b"v1").unwrap();
+        let edit = EditInputs {
+            image: image.clone(),
+            mask: None,
+        };
+        let first = edit_inputs_hash(&edit).unwrap();
+        assert_eq!(first, edit_inputs_hash(&edit).unwrap());
+
+        fs::write(&image, b"v2").unwrap();
+        let changed = edit_inputs_hash(&edit).unwrap();
+        assert_ne!(first, changed);
+
+        let mask = dir.path().join("mask.png");
+        fs::write(&mask, b"m").unwrap();
+        let masked = EditInputs {
+            image,
+            mask: Some(mask),
+        };
+        assert_ne!(changed, edit_inputs_hash(&masked).unwrap());
+
+        // The hash feeds the cache file name
+        let options = ImageOptions::default();
+        let render = |inputs| RenderSettings {
+            style: "",
+            model: "gpt-image-1",
+            size: "1024x1024",
+            options: &options,
+            inputs,
+        };
+        assert_ne!(
+            calculate_hash("s", "t", "p", &render(&first)),
+            calculate_hash("s", "t", "p", &render(&changed))
+        );
+    }
+
+    #[test]
+    fn test_legacy_style_config_still_loads() {
+        let mut config: Config = serde_yaml::from_str(
+            "system_prompt: s\nstyle: landscape\nthemes:\n  - name: A\n    instructions: t\nprompts:\n  - name: B\n    prompt: p\n",
+        )
+        .unwrap();
+        assert_eq!(config.migrate_legacy_style().as_deref(), Some("landscape"));
+        assert_eq!(config.style, "");
+        assert_eq!(config.aspect.as_deref(), Some("landscape"));
+        assert_eq!(config.get_image_size(), "1536x1024");
+        assert!(config.validate().is_ok());
+
+        // An explicit aspect wins over the legacy style
+        let mut config: Config = serde_yaml::from_str(
+            "system_prompt: s\nstyle: portrait\naspect: square\nthemes: []\nprompts: []\n",
+        )
+        .unwrap();
+        assert!(config.migrate_legacy_style().is_some());
+        assert_eq!(config.get_image_size(), "1024x1024");
+    }
+
+    #[test]
+    fn test_build_full_prompt_appends_style() {
+        assert_eq!(
+            build_full_prompt("sys", "theme", "cat", "minimalist watercolor"),
+            "sys\n\ntheme\n\ncat\n\nStyle: minimalist watercolor"
+        );
+        assert_eq!(
+            build_full_prompt("sys", "theme", "cat", " "),
+            "sys\n\ntheme\n\ncat"
+        );
+    }
+
+    fn size_config(theme_override: &str, prompt_override: &str) -> Config {
+        let yaml = format!(
+            "system_prompt: s\naspect: portrait\nthemes:\n  - name: Hero\n    instructions: t\n{}prompts:\n  - name: Icon\n    prompt: p\n{}",
+            theme_override, prompt_override
+        );
+        serde_yaml::from_str(&yaml).unwrap()
+    }
+
+    fn resolved(config: &Config) -> String {
+        config
+            .resolve_size(&config.themes[0], &config.prompts[0])
+            .unwrap()
+    }
+
+    #[test]
+    fn test_size_precedence() {
+        // Global style
+        let config = size_config("", "");
+        assert_eq!(resolved(&config), "1024x1536");
+
+        // Theme over global
+        let config = size_config("    aspect: landscape\n", "");
+        assert_eq!(resolved(&config), "1536x1024");
+
+        // Prompt over theme
+        let config = size_config("    aspect: landscape\n", "    size: 1024x1024\n");
+        assert!(config.validate().is_ok());
+        assert_eq!(resolved(&config), "1024x1024");
+    }
+
+    #[test]
+    fn test_parse_errors_carry_location() {
+        let path = Path::new("themes.yaml");
+        let err = parse_config(
+            path,
+            "system_prompt: s\nthemes:\n  - name: A\n    instructions: t\npromts:\n  - name: B\n",
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "themes.yaml:5:1: unknown field `promts`, expected one of `system_prompt`, `style`, `size`, `aspect`, `model`, `quality`, `background`, `variables`, `themes`, `prompts`"
+        );
+
+        let err = parse_config(
+            path,
+            "system_prompt: s\nthemes:\n  - name: A\n    instrutions: t\n",
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "themes.yaml:4:5: themes[0]: unknown field `instrutions`, expected one of `name`, `instructions`, `size`, `aspect`"
+        );
+
+        let err = parse_config(path, "themes: [unclosed\n").unwrap_err();
+        assert!(err.to_string().starts_with("themes.yaml:"), "{}", err);
+    }
+
+    #[test]
+    fn test_validate_reports_every_problem() {
+        let config = parse_config(
+            Path::new("themes.yaml"),
+            "system_prompt: s\nthemes:\n  - name: AI / ML\n  - name: ai-ml\n    instructions: t\nprompts:\n  - name: Logo\n    prompt: p\n  - name: Logo\n    prompt: \"  \"\n  - prompt: p\n    size: 800x600\n",
+        )
+        .unwrap();
+        assert_eq!(
+            config.validate().unwrap_err().to_string(),
+            "Invalid configuration (6 problems):\n  \
+             - themes[0].instructions: missing or empty\n  \
+             - themes[1].name: 'ai-ml' uses the same directory as themes[0]\n  \
+             - prompts[1].name: duplicate of prompts[0] 'Logo'\n  \
+             - prompts[1].prompt: missing or empty\n  \
+             - prompts[2].name: missing or empty\n  \
+             - prompts[2]: prompt '': invalid size '800x600', expected one of 1024x1024, 1536x1024, 1024x1536, 1792x1024, 1024x1792, 512x512, 256x256, auto"
+        );
+    }
+
+    #[test]
+    fn test_validate_rejects_empty_lists() {
+        let config =
+            parse_config(Path::new("themes.yaml"), "system_prompt: s\nthemes: []\n").unwrap();
+        assert_eq!(
+            config.validate().unwrap_err().to_string(),
+            "Invalid configuration (2 problems):\n  - themes: no themes listed\n  - prompts: no prompts listed"
+        );
+    }
+
+    #[test]
+    fn test_invalid_size_names_entry() {
+        let config = size_config("", "    size: 800x600\n");
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("prompt 'Icon'"), "{}", err);
+        assert!(err.contains("800x600"), "{}", err);
+
+        let config = size_config("    aspect: wide\n", "");
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("theme 'Hero'"), "{}", err);
+
+        let config = size_config("    size: 1024x1024\n    aspect: square\n", "");
+        assert!(config.validate().is_err());
+    }
+
+    const ORDER_YAML: &str = "system_prompt: s\nthemes:\n  - name: Day\n    instructions: bright\n  - name: Night\n    instructions: dark\nprompts:\n  - name: City\n    prompt: a city\n  - name: Lake\n    prompt: a lake\n  - name: Hill\n    prompt: a hill\n";
+
+    fn order_plans(dir: &Path, yaml: &str) -> Vec<ConfigPlan> {
+        let config = dir.join("order.yaml");
+        fs::write(&config, yaml).unwrap();
+        let out = dir.join("out");
+        let args = Args::parse_from([
+            "imgen".as_ref(),
+            config.as_os_str(),
+            "--dry-run".as_ref(),
+            "-o".as_ref(),
+            out.as_os_str(),
+        ]);
+        vec![load_config(&config, &out, &args).unwrap()]
+    }
+
+    #[test]
+    fn test_interleave() {
+        let groups = vec![vec!["a1", "a2", "a3"], vec![], vec!["b1"], vec!["c1", "c2"]];
+        assert_eq!(interleave(groups), vec!["a1", "b1", "c1", "a2", "c2", "a3"]);
+        assert_eq!(interleave(Vec::<Vec<u8>>::new()), Vec::<u8>::new());
+    }
+
+    #[test]
+    fn test_task_order_is_deterministic() {
+        let dir = tempfile::tempdir().unwrap();
+        let names = |plans: &[ConfigPlan]| -> Vec<String> {
+            plans[0]
+                .tasks
+                .iter()
+                .map(|task| format!("{}/{}", task.theme_name, task.prompt_name))
+                .collect()
+        };
+
+        let first = order_plans(dir.path(), ORDER_YAML);
+        assert_eq!(
+            names(&first),
+            vec![
+                "Day/City",
+                "Night/City",
+                "Day/Lake",
+                "Night/Lake",
+                "Day/Hill",
+                "Night/Hill"
+            ]
+        );
+        // The run file of a resumed run must match the one computed again
+        let second = order_plans(dir.path(), ORDER_YAML);
+        assert_eq!(RunFile::new(&first), RunFile::new(&second));
+    }
+
+    #[test]
+    fn test_resume_run() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(RUN_FILE);
+        let plans = order_plans(dir.path(), ORDER_YAML);
+
+        let err = resume_run(&path, &RunFile::new(&plans)).unwrap_err();
+        assert!(err.to_string().contains("No run to resume"));
+
+        // The run crashed after its first two images
+        let mut run = RunFile::new(&plans);
+        assert_eq!(run.pending(), 6);
+        run.tasks[0].done = true;
+        run.tasks[2].done = true;
+        run.save(&path).unwrap();
+
+        // Image 4 landed before the crash but was not recorded yet
+        let mut current = RunFile::new(&plans);
+        current.tasks[3].done = true;
+        let resumed = resume_run(&path, &current).unwrap();
+        let pending: Vec<_> = resumed
+            .tasks
+            .iter()
+            .filter(|task| !task.done)
+            .map(|task| format!("{}/{}", task.theme, task.prompt))
+            .collect();
+        assert_eq!(pending, vec!["Night/City", "Day/Hill", "Night/Hill"]);
+
+        // A changed prompt means a different run
+        let changed = order_plans(dir.path(), &ORDER_YAML.replace("a lake", "a frozen lake"));
+        let err = resume_run(&path, &RunFile::new(&changed)).unwrap_err();
+        assert!(err.to_string().contains("different configuration"));
+        assert!(err.to_string().contains("without --resume"));
+    }
+
+    #[test]
+    fn test_collect_config_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let nested = dir.path().join("nested");
+        fs::create_dir(&nested).unwrap();
+        for file in ["b.yml", "a.yaml", "notes.txt", "nested/c.YAML"] {
+            fs::write(dir.path().join(file), "").unwrap();
+        }
+        let missing = dir.path().join("missing.yaml");
+
+        let files =
+            collect_config_files(&[dir.path().to_path_buf(), missing.clone()], false).unwrap();
+        assert_eq!(
+            files,
+            vec![dir.path().join("a.yaml"), dir.path().join("b.yml"), missing]
+        );
+
+        // Named twice: processed once
+        let inputs = [dir.path().join("a.yaml"), dir.path().to_path_buf()];
+        let files = collect_config_files(&inputs, true).unwrap();
+        assert_eq!(
+            files,
+            vec![
+                dir.path().join("a.yaml"),
+                dir.path().join("b.yml"),
+                nested.join("c.YAML"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_config_dir_names() {
+        let files = [
+            PathBuf::from("a/Shoes.yaml"),
+            PathBuf::from("b/shoes.yml"),
+            PathBuf::from("hats.yaml"),
+            PathBuf::from("c/shoes.yaml"),
+        ];
+        assert_eq!(
+            config_dir_names(&files),
+            vec!["shoes", "shoes-2", "hats", "shoes-3"]
+        );
+    }
+
+    #[test]
+    fn test_failures_file_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let config_path = dir.path().join("config.yaml");
+        let content = r#"
+system_prompt: "Brand {brand}, braces {{kept}}"
+aspect: landscape
+variables:
+  brand: Acme
+themes:
+  - name: Nature
+    instructions: "Green {brand}"
+  - name: City
+    instructions: "Grey"
+    size: 1024x1536
+prompts:
+  - name: Logo
+    prompt: "Logo for {brand}"
+  - name: Banner
+    prompt: "Banner"
+"#;
+        let mut config = parse_config(&config_path, content).unwrap();
+        config.validate().unwrap();
+        config
+            .apply_variables(&[("brand".to_string(), "Globex".to_string())])
+            .unwrap();
+        config.model = Some("gpt-image-1".to_string());
+
+        let failures = vec![(
+            "City".to_string(),
+            "Logo".to_string(),
+            "HTTP 500".to_string(),
+        )];
+        let path = write_failures(dir.path(), &config, &failures)
+            .unwrap()
+            .unwrap();
+        assert_eq!(path, dir.path().join(FAILURES_FILE));
+
+        // Reloads through the normal path with the same expanded text
+        let content = fs::read_to_string(&path).unwrap();
+        let mut retry = parse_config(&path, &content).unwrap();
+        retry.validate().unwrap();
+        retry.apply_variables(&[]).unwrap();
+        assert_eq!(retry.system_prompt, "Brand Globex, braces {kept}");
+        assert_eq!(retry.aspect.as_deref(), Some("landscape"));
+        assert_eq!(retry.model.as_deref(), Some("gpt-image-1"));
+        assert_eq!(retry.themes.len(), 1);
+        assert_eq!(retry.themes[0].name, "City");
+        assert_eq!(retry.themes[0].size.as_deref(), Some("1024x1536"));
+        assert_eq!(retry.prompts.len(), 1);
+        assert_eq!(retry.prompts[0].prompt, "Logo for Globex");
+
+        // The rerun targets the same files
+        let render = |config: &Config| {
+            let options = ImageOptions::default();
+            let size = config
+                .resolve_size(&config.themes[0], &config.prompts[0])
+                .unwrap();
+            let render = RenderSettings {
+                style: &config.style,
+                model: config.model.as_deref().unwrap(),
+                size: &size,
+                options: &options,
+                inputs: "",
+            };
+            calculate_hash(
+                &config.system_prompt,
+                &config.themes[0].instructions,
+                &config.prompts[0].prompt,
+                &render,
+            )
+        };
+        let original = Config {
+            themes: vec![config.themes[1].clone()],
+            prompts: vec![config.prompts[0].clone()],
+            ..config.clone()
+        };
+        assert_eq!(render(&retry), render(&original));
+
+        // A run without failures removes the stale file
+        assert_eq!(write_failures(dir.path(), &config, &[]).unwrap(), None);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_retry_file_output_dir() {
+        let args = Args::parse_from(["imgen", "--retry-file", "out/imgen-failures.yaml"]);
+        assert_eq!(
+            args.inputs(),
+            vec![PathBuf::from("out/imgen-failures.yaml")]
+        );
+        assert_eq!(args.output_dir(), PathBuf::from("out"));
+
+        let args = Args::parse_from(["imgen", "--retry-file", "imgen-failures.yaml", "-o", "x"]);
+        assert_eq!(args.output_dir(), PathBuf::from("x"));
+
+        let args = Args::parse_from(["imgen", "a.yaml", "--format", "webp"]);
+        assert_eq!(args.output_dir(), PathBuf::from("."));
+        assert_eq!(args.rerun_flags(), " --format webp");
+
+        assert!(Args::try_parse_from(["imgen"]).is_err());
+        assert!(Args::try_parse_from(["imgen", "a.yaml", "--retry-file", "b.yaml"]).is_err());
+    }
+
+    #[test]
+    fn test_png_metadata_round_trip() {
+        let png = encoded_png(8, 4);
+        let task = ImageTask {
+            theme_name: "Nature".to_string(),
+            prompt_name: "Sunset".to_string(),
+            full_prompt: "A sunset\n\nStyle: 水彩".to_string(),
+            output_path: PathBuf::from("nature/sunset-abc123.png"),
+            hash: "abc123".to_string(),
+            model: "gpt-image-1".to_string(),
+            size: "1024x1024".to_string(),
+            options: ImageOptions::default(),
+            output: OutputSettings::default(),
+            edit: None,
+            cached: false,
+        };
+        let created = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let metadata = png_metadata(&task, created);
+
+        let embedded = embed_png_text(&png, &metadata).unwrap();
+        assert!(is_complete_image(&embedded));
+        let decoded = image::load_from_memory_with_format(&embedded, ImageFormat::Png).unwrap();
+        assert_eq!((decoded.width(), decoded.height()), (8, 4));
+
+        let fields = read_png_text(&embedded).unwrap();
+        let field = |name: &str| {
+            fields
+                .iter()
+                .find(|(keyword, _)| keyword == name)
+                .map(|(_, text)| text.as_str())
+        };
+        assert_eq!(field("Prompt"), Some("A sunset\n\nStyle: 水彩"));
+        assert_eq!(field("Theme"), Some("Nature"));
+        assert_eq!(field("Model"), Some("gpt-image-1"));
+        assert_eq!(field("Size"), Some("1024x1024"));
+        assert_eq!(field("Creation Time"), Some("2023-11-14T22:13:20Z"));
+
+        // Plain PNGs have none; other formats are rejected
+        assert!(read_png_text(&png).unwrap().is_empty());
+        assert!(read_png_text(b"GIF89a").is_err());
+    }
+
+    #[test]
+    fn test_crc32_and_format_utc() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc32(b"IEND"), 0xAE42_6082);
+        assert_eq!(format_utc(0), "1970-01-01T00:00:00Z");
+        assert_eq!(format_utc(951_782_400), "2000-02-29T00:00:00Z");
+        assert_eq!(format_utc(1_700_000_000), "2023-11-14T22:13:20Z");
+    }
+
+    #[test]
+    fn test_group_by_layouts() {
+        let png = OutputSettings::default();
+        let webp = OutputSettings {
+            format: OutputFormat::Webp,
+            max_width: Some(800),
+            ..Default::default()
+        };
+        assert_eq!(
+            GroupBy::Theme.relative_path("AI / ML", "Sunset", "abc123", &png),
+            PathBuf::from("ai-ml/sunset-abc123.png")
+        );
+        assert_eq!(
+            GroupBy::Prompt.relative_path("AI / ML", "Sunset", "abc123", &png),
+            PathBuf::from("sunset/ai-ml-abc123.png")
+        );
+        assert_eq!(
+            GroupBy::Flat.relative_path("AI / ML", "Sunset", "abc123", &webp),
+            PathBuf::from("ai-ml-sunset-abc123-w800.webp")
+        );
+    }
+
+    #[test]
+    fn test_manifest_finds_images_across_layouts() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut sunset = task("1024x1024", false);
+        sunset.output_path = dir.path().join("nature/sunset-abc123.png");
+        fs::create_dir_all(dir.path().join("nature")).unwrap();
+        fs::write(&sunset.output_path, png_bytes()).unwrap();
+
+        let mut manifest = Manifest::default();
+        manifest.record(dir.path(), &sunset, None);
+        let png = OutputSettings::default();
+        assert_eq!(
+            manifest.find_image(dir.path(), "abc123", &png),
+            Some("nature/sunset-abc123.png")
+        );
+
+        // Another format or width is a different file
+        let webp = OutputSettings {
+            format: OutputFormat::Webp,
+            ..Default::default()
+        };
+        assert_eq!(manifest.find_image(dir.path(), "abc123", &webp), None);
+        assert_eq!(manifest.find_image(dir.path(), "def456", &png), None);
+
+        // Failed or missing files don't count
+        manifest.record(dir.path(), &sunset, Some("HTTP 500".to_string()));
+        assert_eq!(manifest.find_image(dir.path(), "abc123", &png), None);
+        manifest.record(dir.path(), &sunset, None);
+        fs::remove_file(&sunset.output_path).unwrap();
+        assert_eq!(manifest.find_image(dir.path(), "abc123", &png), None);
+    }
+
+    #[test]
+    fn test_confirmation() {
+        assert!(is_yes("y\n"));
+        assert!(is_yes(" YES "));
+        assert!(!is_yes("\n"));
+        assert!(!is_yes("no"));
+
+        let tasks = vec![task("1024x1024", false), task("1024x1024", false)];
+        assert_eq!(
+            describe_cost(&tasks, None),
+            "estimated cost $0.08 (built-in per-size prices)"
+        );
+        assert_eq!(describe_cost(&tasks, Some(0.5)), "estimated cost $1.00");
+
+        // Small runs and --yes never ask
+        let args = Args::parse_from(["imgen", "a.yaml", "--confirm-over", "2"]);
+        assert!(confirm_run(&tasks, &args).is_ok());
+        let args = Args::parse_from(["imgen", "a.yaml", "--confirm-over", "1", "--yes"]);
+        assert!(confirm_run(&tasks, &args).is_ok());
+    }
+
+    #[test]
+    fn test_write_gallery() {
+        let dir = tempfile::tempdir().unwrap();
+        let content = "themes:\n  - name: Nature\n    instructions: i\nprompts:\n  - name: Sunset\n    prompt: p\n  - name: Dawn\n    prompt: p\n";
+        let config = parse_config(Path::new("config.yaml"), content).unwrap();
+
+        let mut sunset = task("1024x1024", false);
+        sunset.full_prompt = "A \"warm\" <sunset>".to_string();
+        sunset.output_path = dir.path().join("nature/sunset-abc123.png");
+        fs::create_dir_all(dir.path().join("nature")).unwrap();
+        fs::write(&sunset.output_path, encoded_png(800, 400)).unwrap();
+        let mut dawn = task("1024x1024", false);
+        dawn.prompt_name = "Dawn".to_string();
+        dawn.output_path = dir.path().join("nature/dawn-def456.png");
+
+        let plan = ConfigPlan {
+            label: "config.yaml".to_string(),
+            config,
+            output_root: dir.path().to_path_buf(),
+            models: ModelConfig::from_env(),
+            tasks: vec![sunset, dawn],
+        };
+        let stats = ConfigStats {
+            generated: 1,
+            failures: vec![(
+                "Nature".to_string(),
+                "Dawn".to_string(),
+                "HTTP 500".to_string(),
+            )],
+            ..Default::default()
+        };
+
+        let path = write_gallery(&plan, &stats).unwrap();
+        assert_eq!(path, dir.path().join(GALLERY_FILE));
+        let html = fs::read_to_string(&path).unwrap();
+        assert!(html.contains("<h2>Nature</h2>"));
+        assert!(html.contains(
+            r#"<a href="nature/sunset-abc123.png"><img src=".thumbs/nature/sunset-abc123.jpg""#
+        ));
+        assert!(html.contains(r#"title="A &quot;warm&quot; &lt;sunset&gt;""#));
+        assert!(html.contains(r#"<div class="placeholder">HTTP 500</div>"#));
+        assert!(html.contains("2 images, 1 missing"));
+
+        let thumb = image::open(dir.path().join(".thumbs/nature/sunset-abc123.jpg")).unwrap();
+        assert_eq!((thumb.width(), thumb.height()), (400, 200));
+    }
+
+    /// Finish times in seconds of the "fast" theme's tasks when 8 slow tasks
+    /// of another theme are queued first
+    async fn fast_theme_finish_times(per_theme: Option<usize>) -> Vec<u64> {
+        let throttle = Arc::new(Throttle::new(4, false));
+        let theme_limits = Arc::new(ThemeLimits::new(per_theme));
+        let start = tokio::time::Instant::now();
+
+        let mut handles = Vec::new();
+        let themes =
+            std::iter::repeat_n(("slow", 60), 8).chain(std::iter::repeat_n(("fast", 1), 3));
+        for (theme, seconds) in themes {
+            let throttle = Arc::clone(&throttle);
+            let theme_limits = Arc::clone(&theme_limits);
+            handles.push(tokio::spawn(async move {
+                let _theme_permit = theme_limits.acquire(0, theme).await;
+                let _permit = throttle.acquire().await;
+                tokio::time::sleep(Duration::from_secs(seconds)).await;
+                (theme, start.elapsed().as_secs())
+            }));
+        }
+
+        futures::future::join_all(handles)
+            .await
+            .into_iter()
+            .map(|result| result.unwrap())
+            .filter(|(theme, _)| *theme == "fast")
+            .map(|(_, elapsed)| elapsed)
+            .collect()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_per_theme_concurrency_lets_other_themes_progress() {
+        // Unlimited: the slow theme holds every global slot for a minute
+        let times = fast_theme_finish_times(None).await;
+        assert!(times.iter().all(|&t| t >= 60), "{:?}", times);
+
+        // Capped at 2: the other 2 slots serve the fast theme right away
+        let times = fast_theme_finish_times(Some(2)).await;
+        assert_eq!(times.len(), 3);
+        assert!(times.iter().all(|&t| t <= 2), "{:?}", times);
+    }
+
+    #[tokio::test]
+    async fn test_generate_and_save_image_writes_image() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut task = task("1024x1024", false);
+        task.output_path = dir.path().join("nature/sunset-abc123.png");
+        fs::create_dir_all(dir.path().join("nature")).unwrap();
+
+        let mock = Arc::new(MockAiClient::new());
+        mock.push_image(Ok(encoded_png(8, 8)));
+        let client: Arc<dyn AiClient> = mock.clone();
+
+        generate_and_save_image(&client, &task).await.unwrap();
+
+        let expected = MockCall::GenerateImage {
+            prompt: "prompt".to_string(),
+            size: "1024x1024".to_string(),
+        };
+        assert_eq!(mock.calls(), [expected]);
+        let saved = fs::read(&task.output_path).unwrap();
+        let saved = image::load_from_memory(&saved).unwrap();
+        assert_eq!((saved.width(), saved.height()), (8, 8));
+    }
+
+    #[tokio::test]
+    async fn test_generate_and_save_image_reports_policy_rejection() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut task = task("1024x1024", false);
+        task.output_path = dir.path().join("sunset-abc123.png");
+
+        let mock = Arc::new(MockAiClient::new());
+        mock.push_image(Err(OpenAIError::InvalidRequest {
+            status: 400,
+            code: Some("content_policy_violation".to_string()),
+            message: "rejected".to_string(),
+        }));
+        let client: Arc<dyn AiClient> = mock.clone();
+
+        let err = generate_and_save_image(&client, &task).await.unwrap_err();
+        assert!(is_policy_rejection(&err));
+        assert_eq!(mock.calls().len(), 1);
+        assert!(!task.output_path.exists());
     }
 }
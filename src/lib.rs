@@ -1,2 +1,11 @@
+pub mod cache;
+pub mod config;
+#[cfg(any(test, feature = "test-support"))]
+pub mod mock;
 mod openai;
+pub mod pdf;
+pub mod s3;
+mod text;
+pub mod ui;
 pub use openai::*;
+pub use text::*;
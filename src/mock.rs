@@ -0,0 +1,264 @@
+//! Test double for [`AiClient`]
+//!
+//! [`MockAiClient`] answers every call from queues of canned responses and
+//! records what it was asked, so the CLI tools can be exercised end to end
+//! without network access or an API key.
+
+use crate::{
+    AiClient, ChatMessage, ChatRequest, ChatResponse, Choice, ImageOptions, ModelConfig,
+    OpenAIError, SpeechFormat, TokenUsage, TranscriptionOptions, TranscriptionResponse,
+};
+use async_trait::async_trait;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+/// A call received by [`MockAiClient`], with the arguments worth asserting on
+#[derive(Debug, Clone, PartialEq)]
+pub enum MockCall {
+    Transcribe {
+        filename: String,
+        language: Option<String>,
+        prompt: Option<String>,
+        /// Whether segment timestamps were asked for (`transcribe_verbose`)
+        verbose: bool,
+    },
+    /// The chat request's messages, as text
+    Chat {
+        model: String,
+        messages: Vec<String>,
+    },
+    VerifyApiKey,
+    SynthesizeSpeech {
+        text: String,
+        voice: String,
+    },
+    GenerateImage {
+        prompt: String,
+        size: String,
+    },
+    EditImage {
+        prompt: String,
+        size: String,
+    },
+}
+
+/// [`AiClient`] returning queued responses in order
+///
+/// Each kind of call has its own queue; a call whose queue is empty fails
+/// with [`OpenAIError::Decode`] naming the missing response.
+/// `verify_api_key` always succeeds.
+#[derive(Default)]
+pub struct MockAiClient {
+    models: ModelConfig,
+    transcriptions: Mutex<VecDeque<Result<TranscriptionResponse, OpenAIError>>>,
+    chats: Mutex<VecDeque<Result<ChatResponse, OpenAIError>>>,
+    speech: Mutex<VecDeque<Result<Vec<u8>, OpenAIError>>>,
+    images: Mutex<VecDeque<Result<Vec<u8>, OpenAIError>>>,
+    calls: Mutex<Vec<MockCall>>,
+}
+
+impl MockAiClient {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Report these models from [`AiClient::models`]
+    pub fn with_models(mut self, models: ModelConfig) -> Self {
+        self.models = models;
+        self
+    }
+
+    /// Queue a transcription returning `text`
+    pub fn push_transcript(&self, text: &str) -> &Self {
+        self.push_transcription(Ok(TranscriptionResponse {
+            text: text.to_string(),
+            language: None,
+            segments: Vec::new(),
+            words: Vec::new(),
+            duration: None,
+            usage: None,
+        }))
+    }
+
+    /// Queue the result of the next `transcribe` or `transcribe_verbose`
+    pub fn push_transcription(&self, result: Result<TranscriptionResponse, OpenAIError>) -> &Self {
+        self.transcriptions.lock().unwrap().push_back(result);
+        self
+    }
+
+    /// Queue a chat reply of `content`, billed as 10 prompt and 5 completion tokens
+    pub fn push_reply(&self, content: &str) -> &Self {
+        self.push_chat(Ok(ChatResponse {
+            choices: vec![Choice {
+                message: ChatMessage::new("assistant", content),
+            }],
+            usage: Some(TokenUsage::new(10, 5)),
+        }))
+    }
+
+    /// Queue the result of the next `chat`, which every chat helper goes through
+    pub fn push_chat(&self, result: Result<ChatResponse, OpenAIError>) -> &Self {
+        self.chats.lock().unwrap().push_back(result);
+        self
+    }
+
+    /// Queue the result of the next `synthesize_speech`
+    pub fn push_speech(&self, result: Result<Vec<u8>, OpenAIError>) -> &Self {
+        self.speech.lock().unwrap().push_back(result);
+        self
+    }
+
+    /// Queue the result of the next `generate_image` or `edit_image`
+    pub fn push_image(&self, result: Result<Vec<u8>, OpenAIError>) -> &Self {
+        self.images.lock().unwrap().push_back(result);
+        self
+    }
+
+    /// Calls received so far, oldest first
+    pub fn calls(&self) -> Vec<MockCall> {
+        self.calls.lock().unwrap().clone()
+    }
+
+    fn record(&self, call: MockCall) {
+        self.calls.lock().unwrap().push(call);
+    }
+}
+
+/// Next queued response, or an error saying which kind ran out
+fn next<T>(queue: &Mutex<VecDeque<Result<T, OpenAIError>>>, kind: &str) -> Result<T, OpenAIError> {
+    queue.lock().unwrap().pop_front().unwrap_or_else(|| {
+        Err(OpenAIError::Decode(format!(
+            "No mock {} response queued",
+            kind
+        )))
+    })
+}
+
+#[async_trait]
+impl AiClient for MockAiClient {
+    fn models(&self) -> &ModelConfig {
+        &self.models
+    }
+
+    async fn transcribe(
+        &self,
+        _audio_data: Vec<u8>,
+        filename: &str,
+        language: Option<&str>,
+        prompt: Option<&str>,
+    ) -> Result<TranscriptionResponse, OpenAIError> {
+        self.record(MockCall::Transcribe {
+            filename: filename.to_string(),
+            language: language.map(str::to_string),
+            prompt: prompt.map(str::to_string),
+            verbose: false,
+        });
+        next(&self.transcriptions, "transcription")
+    }
+
+    async fn transcribe_verbose(
+        &self,
+        _audio_data: Vec<u8>,
+        filename: &str,
+        options: &TranscriptionOptions<'_>,
+    ) -> Result<TranscriptionResponse, OpenAIError> {
+        self.record(MockCall::Transcribe {
+            filename: filename.to_string(),
+            language: options.language.map(str::to_string),
+            prompt: options.prompt.map(str::to_string),
+            verbose: true,
+        });
+        next(&self.transcriptions, "transcription")
+    }
+
+    async fn chat(&self, request: ChatRequest) -> Result<ChatResponse, OpenAIError> {
+        self.record(MockCall::Chat {
+            model: request.model.clone(),
+            messages: request
+                .messages
+                .iter()
+                .map(|message| message.content.text())
+                .collect(),
+        });
+        next(&self.chats, "chat")
+    }
+
+    async fn verify_api_key(&self) -> Result<(), OpenAIError> {
+        self.record(MockCall::VerifyApiKey);
+        Ok(())
+    }
+
+    async fn synthesize_speech(
+        &self,
+        text: &str,
+        voice: &str,
+        _format: SpeechFormat,
+    ) -> Result<Vec<u8>, OpenAIError> {
+        self.record(MockCall::SynthesizeSpeech {
+            text: text.to_string(),
+            voice: voice.to_string(),
+        });
+        next(&self.speech, "speech")
+    }
+
+    async fn generate_image(
+        &self,
+        prompt: &str,
+        size: &str,
+        _options: &ImageOptions,
+    ) -> Result<Vec<u8>, OpenAIError> {
+        self.record(MockCall::GenerateImage {
+            prompt: prompt.to_string(),
+            size: size.to_string(),
+        });
+        next(&self.images, "image")
+    }
+
+    async fn edit_image(
+        &self,
+        prompt: &str,
+        _image: Vec<u8>,
+        _mask: Option<Vec<u8>>,
+        size: &str,
+        _options: &ImageOptions,
+    ) -> Result<Vec<u8>, OpenAIError> {
+        self.record(MockCall::EditImage {
+            prompt: prompt.to_string(),
+            size: size.to_string(),
+        });
+        next(&self.images, "image")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{AiClientExt, ContentResponse};
+
+    #[tokio::test]
+    async fn test_mock_replays_queued_responses() {
+        let client = MockAiClient::new();
+        client
+            .push_reply("not json")
+            .push_reply(r#"{"titles": ["T"], "descriptions": [], "status_updates": []}"#);
+
+        // The JSON helpers' repair follow-up goes through the mock as well
+        let content: ContentResponse = client
+            .chat_json("system", "user".to_string())
+            .await
+            .unwrap()
+            .value;
+        assert_eq!(content.titles, ["T"]);
+
+        let calls = client.calls();
+        assert_eq!(calls.len(), 2);
+        let MockCall::Chat { model, messages } = &calls[1] else {
+            panic!("expected a chat call, got {:?}", calls[1]);
+        };
+        assert_eq!(model, &ModelConfig::default().chat);
+        assert_eq!(messages[..3], ["system", "user", "not json"]);
+
+        let err = client.transcribe(Vec::new(), "a.mp3", None, None).await;
+        assert!(matches!(err, Err(OpenAIError::Decode(_))));
+    }
+}
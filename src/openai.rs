@@ -1,214 +1,4839 @@
-use anyhow::{Context, Result};
+use crate::cache::ResponseCache;
+use anyhow::Result;
+use async_trait::async_trait;
+use bytes::Bytes;
 use reqwest::multipart;
-use serde::{Deserialize, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::collections::hash_map::RandomState;
 use std::env;
+use std::future::Future;
+use std::hash::BuildHasher;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+use tracing::Instrument;
 
-#[derive(Clone)]
-pub struct OpenAIClient {
-    client: reqwest::Client,
-    api_key: String,
-    base_url: String,
+/// Default model for audio transcription
+pub const DEFAULT_TRANSCRIBE_MODEL: &str = "gpt-4o-transcribe";
+/// Default model for chat-based content generation
+pub const DEFAULT_CHAT_MODEL: &str = "gpt-5-mini";
+/// Default model for image generation
+pub const DEFAULT_IMAGE_MODEL: &str = "gpt-image-1";
+/// Default model for text-to-speech
+pub const DEFAULT_SPEECH_MODEL: &str = "tts-1";
+
+/// Model ids used by the client. Ids are passed through unchanged and
+/// validated by the API.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModelConfig {
+    pub transcribe: String,
+    pub chat: String,
+    pub image: String,
+    pub speech: String,
 }
 
-#[derive(Deserialize)]
-pub struct TranscriptionResponse {
-    pub text: String,
+impl Default for ModelConfig {
+    fn default() -> Self {
+        Self {
+            transcribe: DEFAULT_TRANSCRIBE_MODEL.to_string(),
+            chat: DEFAULT_CHAT_MODEL.to_string(),
+            image: DEFAULT_IMAGE_MODEL.to_string(),
+            speech: DEFAULT_SPEECH_MODEL.to_string(),
+        }
+    }
 }
 
-#[derive(Serialize, Deserialize)]
-pub struct ChatMessage {
-    pub role: String,
-    pub content: String,
+impl ModelConfig {
+    /// Defaults overridden by OPENAI_TRANSCRIBE_MODEL, OPENAI_CHAT_MODEL,
+    /// OPENAI_IMAGE_MODEL and OPENAI_SPEECH_MODEL when set
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let var = |name: &str, default: String| {
+            env::var(name)
+                .ok()
+                .filter(|v| !v.trim().is_empty())
+                .unwrap_or(default)
+        };
+
+        Self {
+            transcribe: var("OPENAI_TRANSCRIBE_MODEL", defaults.transcribe),
+            chat: var("OPENAI_CHAT_MODEL", defaults.chat),
+            image: var("OPENAI_IMAGE_MODEL", defaults.image),
+            speech: var("OPENAI_SPEECH_MODEL", defaults.speech),
+        }
+    }
 }
 
-#[derive(Serialize)]
-pub struct ChatRequest {
-    pub model: String,
-    pub messages: Vec<ChatMessage>,
-    pub temperature: f32,
-    pub max_completion_tokens: u32,
-    pub response_format: ResponseFormat,
+/// Deployment names of an Azure OpenAI resource; a capability without one
+/// uses a deployment named after its model
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AzureDeployments {
+    pub transcribe: Option<String>,
+    pub chat: Option<String>,
+    pub image: Option<String>,
+    pub speech: Option<String>,
 }
 
-#[derive(Serialize)]
-pub struct ResponseFormat {
-    #[serde(rename = "type")]
-    pub format_type: String,
+/// The service requests are sent to
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Provider {
+    /// The OpenAI API, or a server compatible with it, at `base_url`:
+    /// `Authorization: Bearer` auth and the model in each request
+    #[default]
+    OpenAI,
+
+    /// Azure OpenAI at `base_url` (`https://<resource>.openai.azure.com`):
+    /// `api-key` auth, a deployment per capability and an `api-version`
+    Azure {
+        api_version: String,
+        deployments: AzureDeployments,
+    },
 }
 
-#[derive(Deserialize)]
-pub struct ChatResponse {
-    pub choices: Vec<Choice>,
+impl Provider {
+    /// OpenAI unless OPENAI_PROVIDER is `azure`, which takes OPENAI_API_VERSION
+    /// and, optionally, OPENAI_TRANSCRIBE_DEPLOYMENT, OPENAI_CHAT_DEPLOYMENT,
+    /// OPENAI_IMAGE_DEPLOYMENT and OPENAI_SPEECH_DEPLOYMENT
+    pub fn from_env() -> Result<Self, OpenAIError> {
+        let var = |name: &str| {
+            env::var(name)
+                .ok()
+                .map(|v| v.trim().to_string())
+                .filter(|v| !v.is_empty())
+        };
+
+        match var("OPENAI_PROVIDER").map(|v| v.to_lowercase()).as_deref() {
+            None | Some("openai") => Ok(Self::OpenAI),
+            Some("azure") => {
+                let api_version = var("OPENAI_API_VERSION").ok_or_else(|| {
+                    OpenAIError::Config(
+                        "OPENAI_PROVIDER=azure requires OPENAI_API_VERSION, e.g. 2025-04-01-preview"
+                            .to_string(),
+                    )
+                })?;
+                Ok(Self::Azure {
+                    api_version,
+                    deployments: AzureDeployments {
+                        transcribe: var("OPENAI_TRANSCRIBE_DEPLOYMENT"),
+                        chat: var("OPENAI_CHAT_DEPLOYMENT"),
+                        image: var("OPENAI_IMAGE_DEPLOYMENT"),
+                        speech: var("OPENAI_SPEECH_DEPLOYMENT"),
+                    },
+                })
+            }
+            Some(other) => Err(OpenAIError::Config(format!(
+                "Unknown OPENAI_PROVIDER '{}' (expected openai or azure)",
+                other
+            ))),
+        }
+    }
 }
 
-#[derive(Deserialize)]
-pub struct Choice {
-    pub message: ChatMessage,
+/// How closely the server follows the OpenAI API
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Compatibility {
+    /// Requests and responses exactly as the OpenAI API defines them
+    #[default]
+    OpenAI,
+
+    /// Lenient mode for OpenAI-compatible servers such as LM Studio or
+    /// faster-whisper: chat requests send `max_tokens` instead of
+    /// `max_completion_tokens` and are resent once without `response_format`
+    /// when the server rejects it, and a transcription response that isn't
+    /// JSON is taken as the transcript itself
+    Generic,
 }
 
-#[derive(Serialize, Deserialize)]
-pub struct ContentResponse {
-    pub titles: Vec<String>,
-    pub descriptions: Vec<String>,
-    pub status_updates: Vec<String>,
+impl Compatibility {
+    /// OpenAI unless OPENAI_COMPAT is `generic`
+    pub fn from_env() -> Result<Self, OpenAIError> {
+        let value = env::var("OPENAI_COMPAT").unwrap_or_default();
+        match value.trim().to_lowercase().as_str() {
+            "" | "openai" => Ok(Self::OpenAI),
+            "generic" => Ok(Self::Generic),
+            other => Err(OpenAIError::Config(format!(
+                "Unknown OPENAI_COMPAT '{}' (expected openai or generic)",
+                other
+            ))),
+        }
+    }
 }
 
-#[derive(Serialize)]
-pub struct ImageGenerationRequest {
-    pub model: String,
-    pub prompt: String,
-    pub n: u32,
-    pub size: String,
+/// An API operation of the client
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Endpoint {
+    Transcription,
+    Chat,
+    ImageGeneration,
+    ImageEdit,
+    Speech,
+    Models,
 }
 
-#[derive(Deserialize)]
-pub struct ImageGenerationResponse {
-    pub data: Vec<ImageData>,
+/// URL of `endpoint` for `provider`; `model` is the model of the request,
+/// which picks the Azure deployment when none is configured
+pub fn endpoint_url(
+    base_url: &str,
+    provider: &Provider,
+    endpoint: Endpoint,
+    model: &str,
+) -> String {
+    let base_url = base_url.trim_end_matches('/');
+    let path = match endpoint {
+        Endpoint::Transcription => "audio/transcriptions",
+        Endpoint::Chat => "chat/completions",
+        Endpoint::ImageGeneration => "images/generations",
+        Endpoint::ImageEdit => "images/edits",
+        Endpoint::Speech => "audio/speech",
+        Endpoint::Models => "models",
+    };
+
+    match provider {
+        Provider::OpenAI => format!("{}/{}", base_url, path),
+        Provider::Azure {
+            api_version,
+            deployments,
+        } => {
+            let deployment = match endpoint {
+                Endpoint::Transcription => &deployments.transcribe,
+                Endpoint::Chat => &deployments.chat,
+                Endpoint::ImageGeneration | Endpoint::ImageEdit => &deployments.image,
+                Endpoint::Speech => &deployments.speech,
+                Endpoint::Models => {
+                    return format!("{}/openai/models?api-version={}", base_url, api_version);
+                }
+            };
+            format!(
+                "{}/openai/deployments/{}/{}?api-version={}",
+                base_url,
+                deployment.as_deref().unwrap_or(model),
+                path,
+                api_version
+            )
+        }
+    }
 }
 
-#[derive(Deserialize)]
-pub struct ImageData {
-    pub b64_json: String,
+/// A failed OpenAI API request
+///
+/// Error responses are classified by status and the `error.code` and
+/// `error.message` of the standard `{"error": {...}}` body.
+#[derive(Debug, thiserror::Error)]
+pub enum OpenAIError {
+    /// 429: too many requests, or the quota is used up (`insufficient_quota`)
+    #[error("Rate limited by the OpenAI API: {message}")]
+    RateLimited {
+        retry_after: Option<Duration>,
+        code: Option<String>,
+        message: String,
+    },
+
+    /// A 4xx the request itself is to blame for; sending it again can't help
+    #[error("Invalid request (status {status}): {message}")]
+    InvalidRequest {
+        status: u16,
+        code: Option<String>,
+        message: String,
+    },
+
+    /// 401 or 403, or no API key to send
+    #[error("Authentication failed: {message}")]
+    AuthFailed { message: String },
+
+    /// 5xx, or 408 when the server gave up waiting for the request
+    #[error("OpenAI server error (status {status}): {message}")]
+    ServerError {
+        status: u16,
+        retry_after: Option<Duration>,
+        message: String,
+    },
+
+    /// No response within the client's [timeout](TimeoutConfig)
+    #[error("Request timed out: {0}")]
+    Timeout(reqwest::Error),
+
+    /// The request never got a complete response
+    #[error("Request failed: {}", describe_transport_error(.0))]
+    Transport(reqwest::Error),
+
+    /// A successful response that doesn't hold what was asked for
+    #[error("Failed to decode the API response: {0}")]
+    Decode(String),
+
+    /// The client's environment or settings are incomplete
+    #[error("Invalid OpenAI client configuration: {0}")]
+    Config(String),
+
+    /// Audio in a format the transcription endpoint doesn't take
+    #[error(
+        "Unsupported audio format for {filename} (supported: {})",
+        supported_audio_extensions()
+    )]
+    UnsupportedAudio { filename: String },
+
+    /// The client's [cancellation token](OpenAIClient::with_cancellation)
+    /// fired before the request finished
+    #[error("Request cancelled")]
+    Cancelled,
 }
 
-impl OpenAIClient {
-    pub fn new() -> Result<Self> {
-        let api_key =
-            env::var("OPENAI_API_KEY").context("OPENAI_API_KEY environment variable not set")?;
-        let base_url =
-            env::var("OPENAI_BASE_URL").unwrap_or_else(|_| "https://api.openai.com/v1".to_string());
+impl From<reqwest::Error> for OpenAIError {
+    fn from(e: reqwest::Error) -> Self {
+        if e.is_timeout() {
+            Self::Timeout(e)
+        } else if e.is_decode() {
+            Self::Decode(e.to_string())
+        } else {
+            Self::Transport(e)
+        }
+    }
+}
 
-        let client = reqwest::Client::builder().use_rustls_tls().build()?;
+impl OpenAIError {
+    /// Classify an error response from its status, body and Retry-After delay
+    pub fn from_response(status: u16, body: &str, retry_after: Option<Duration>) -> Self {
+        let (code, message) = parse_error_body(body);
+        match status {
+            429 => Self::RateLimited {
+                retry_after,
+                code,
+                message,
+            },
+            401 | 403 => Self::AuthFailed { message },
+            408 | 500..=599 => Self::ServerError {
+                status,
+                retry_after,
+                message,
+            },
+            _ => Self::InvalidRequest {
+                status,
+                code,
+                message,
+            },
+        }
+    }
 
-        Ok(Self {
-            client,
-            api_key,
-            base_url,
-        })
+    /// Whether the request may succeed when sent again: rate limits (but not
+    /// an exhausted quota), server errors and network failures
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Self::RateLimited { code, .. } => code.as_deref() != Some("insufficient_quota"),
+            Self::ServerError { .. } | Self::Timeout(_) => true,
+            Self::Transport(e) => e.is_connect() || e.is_timeout() || e.is_request() || e.is_body(),
+            Self::InvalidRequest { .. }
+            | Self::AuthFailed { .. }
+            | Self::Decode(_)
+            | Self::Config(_)
+            | Self::UnsupportedAudio { .. }
+            | Self::Cancelled => false,
+        }
+    }
+
+    /// Delay requested by the server through the Retry-After header
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            Self::RateLimited { retry_after, .. } | Self::ServerError { retry_after, .. } => {
+                *retry_after
+            }
+            _ => None,
+        }
+    }
+
+    /// HTTP status of the failed response, where it matters to callers
+    pub fn status(&self) -> Option<u16> {
+        match self {
+            Self::RateLimited { .. } => Some(429),
+            Self::InvalidRequest { status, .. } | Self::ServerError { status, .. } => Some(*status),
+            _ => None,
+        }
     }
 
-    pub async fn transcribe(&self, audio_data: Vec<u8>, filename: &str) -> Result<String> {
-        let url = format!("{}/audio/transcriptions", self.base_url);
+    /// The `error.code` of the response body
+    pub fn code(&self) -> Option<&str> {
+        match self {
+            Self::RateLimited { code, .. } | Self::InvalidRequest { code, .. } => code.as_deref(),
+            _ => None,
+        }
+    }
 
-        let part = multipart::Part::bytes(audio_data)
-            .file_name(filename.to_string())
-            .mime_str("audio/mpeg")?;
+    /// Whether the prompt was refused by the content policy; resending it
+    /// cannot succeed
+    pub fn is_policy_rejection(&self) -> bool {
+        matches!(
+            self,
+            Self::InvalidRequest {
+                status: 400,
+                code: Some(code),
+                ..
+            } if code == "content_policy_violation" || code == "moderation_blocked"
+        )
+    }
+}
 
-        let form = multipart::Form::new()
-            .part("file", part)
-            .text("model", "gpt-4o-transcribe")
-            .text("response_format", "json")
-            .text("language", "zh");
+/// `error.code` and `error.message` of an error body; bodies that aren't
+/// OpenAI error JSON (proxies, load balancers) become the message as is
+fn parse_error_body(body: &str) -> (Option<String>, String) {
+    let json: Option<serde_json::Value> = serde_json::from_str(body).ok();
+    let error = json.as_ref().map(|json| &json["error"]);
+    let code = error
+        .and_then(|error| error["code"].as_str())
+        .map(str::to_string);
+    let message = error
+        .and_then(|error| error["message"].as_str())
+        .filter(|message| !message.is_empty())
+        .unwrap_or_else(|| body.trim());
+    let message = if message.is_empty() {
+        "no error details".to_string()
+    } else {
+        message.to_string()
+    };
+    (code, message)
+}
 
-        let response = self
-            .client
-            .post(&url)
-            .header("Authorization", format!("Bearer {}", self.api_key))
-            .multipart(form)
-            .send()
-            .await?;
+/// A failed attempt the client is about to retry
+#[derive(Debug)]
+pub struct RetryEvent<'a> {
+    /// The attempt that failed, 1-based
+    pub attempt: u32,
+    /// Attempts per request, including the first
+    pub max_attempts: u32,
+    /// How long the client waits before the next attempt
+    pub delay: Duration,
+    /// Why the attempt failed
+    pub error: &'a OpenAIError,
+}
 
-        if !response.status().is_success() {
-            let status = response.status();
-            let text = response.text().await?;
-            anyhow::bail!("API call failed with status {}: {}", status, text);
+/// Callback told about every retry, e.g. to show it on a progress bar
+#[derive(Clone)]
+pub struct RetryHook(Arc<dyn Fn(&RetryEvent) + Send + Sync>);
+
+impl RetryHook {
+    pub fn new(hook: impl Fn(&RetryEvent) + Send + Sync + 'static) -> Self {
+        Self(Arc::new(hook))
+    }
+
+    fn call(&self, event: &RetryEvent) {
+        (self.0)(event)
+    }
+}
+
+impl std::fmt::Debug for RetryHook {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("RetryHook")
+    }
+}
+
+tokio::task_local! {
+    static SCOPED_RETRY_HOOK: RetryHook;
+}
+
+/// Run `future`, calling `hook` for every retry of the requests it sends
+///
+/// Unlike [`RetryConfig::on_retry`], which sees the retries of every request
+/// of a client, this reports those of one call, so concurrent calls on a
+/// shared client can each update their own progress bar.
+pub async fn with_retry_hook<T>(
+    hook: impl Fn(&RetryEvent) + Send + Sync + 'static,
+    future: impl Future<Output = T>,
+) -> T {
+    SCOPED_RETRY_HOOK.scope(RetryHook::new(hook), future).await
+}
+
+/// How the client retries requests that failed with an
+/// [retryable](OpenAIError::is_retryable) error
+#[derive(Debug, Clone)]
+pub struct RetryConfig {
+    /// Attempts per request, including the first; 1 disables retries
+    pub max_attempts: u32,
+    /// Delay before the first retry, doubled for each further one
+    pub base_delay: Duration,
+    /// Upper bound of the backoff delay
+    pub max_delay: Duration,
+    /// Wait as long as a Retry-After header asks instead of backing off
+    pub honor_retry_after: bool,
+    /// Called before every retry of every request
+    pub on_retry: Option<RetryHook>,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            base_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(30),
+            honor_retry_after: true,
+            on_retry: None,
         }
+    }
+}
+
+impl RetryConfig {
+    /// Send every request once
+    pub fn disabled() -> Self {
+        Self {
+            max_attempts: 1,
+            ..Self::default()
+        }
+    }
 
-        let result: TranscriptionResponse = response.json().await?;
-        Ok(result.text)
+    /// Delay before retrying after `attempt` (1-based) failed
+    fn delay(&self, attempt: u32, retry_after: Option<Duration>) -> Duration {
+        if self.honor_retry_after
+            && let Some(retry_after) = retry_after
+        {
+            return retry_after;
+        }
+        let backoff = self
+            .base_delay
+            .saturating_mul(1 << (attempt - 1).min(16))
+            .min(self.max_delay);
+        // Up to a quarter more, so concurrent requests don't retry in lockstep
+        let random = RandomState::new().hash_one(attempt);
+        backoff + backoff.mul_f64((random % 1000) as f64 / 4000.0)
     }
+}
 
-    pub async fn generate_content(&self, prompt: String) -> Result<ContentResponse> {
-        let url = format!("{}/chat/completions", self.base_url);
+/// The `x-ratelimit-*` headers of a response
+///
+/// Reset values are how long until the budget is full again, e.g. `6m0s`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RateLimitHeaders {
+    pub limit_requests: Option<u64>,
+    pub remaining_requests: Option<u64>,
+    pub reset_requests: Option<Duration>,
+    pub limit_tokens: Option<u64>,
+    pub remaining_tokens: Option<u64>,
+    pub reset_tokens: Option<Duration>,
+}
 
-        let system_message = ChatMessage {
-            role: "system".to_string(),
-            content: "你是一个专业的内容创作助手，擅长为视频内容生成吸引人的标题和描述。请用中文回复，并严格按照JSON格式输出。".to_string(),
+impl RateLimitHeaders {
+    /// Parse the headers; `None` when the response carries none of them
+    pub fn from_headers(headers: &reqwest::header::HeaderMap) -> Option<Self> {
+        let value = |name: &str| {
+            headers
+                .get(format!("x-ratelimit-{}", name))
+                .and_then(|v| v.to_str().ok())
         };
+        let count = |name: &str| value(name).and_then(|v| v.trim().parse().ok());
+        let reset = |name: &str| value(name).and_then(parse_reset_duration);
 
-        let user_message = ChatMessage {
-            role: "user".to_string(),
-            content: prompt,
+        let parsed = Self {
+            limit_requests: count("limit-requests"),
+            remaining_requests: count("remaining-requests"),
+            reset_requests: reset("reset-requests"),
+            limit_tokens: count("limit-tokens"),
+            remaining_tokens: count("remaining-tokens"),
+            reset_tokens: reset("reset-tokens"),
         };
+        (parsed != Self::default()).then_some(parsed)
+    }
+}
 
-        let request = ChatRequest {
-            model: "gpt-5-mini".to_string(),
-            messages: vec![system_message, user_message],
-            temperature: 1.0,
-            max_completion_tokens: 10000,
-            response_format: ResponseFormat {
-                format_type: "json_object".to_string(),
-            },
+/// Parse a rate limit reset duration such as `1s`, `20ms`, `6m0s` or `1h2m3.5s`
+pub fn parse_reset_duration(value: &str) -> Option<Duration> {
+    let mut rest = value.trim();
+    if rest.is_empty() {
+        return None;
+    }
+
+    let mut total = 0.0;
+    while !rest.is_empty() {
+        let digits = rest
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .filter(|&end| end > 0)?;
+        let amount: f64 = rest[..digits].parse().ok()?;
+        rest = &rest[digits..];
+
+        let (seconds, unit_len) = if rest.starts_with("ms") {
+            (0.001, 2)
+        } else if rest.starts_with('h') {
+            (3600.0, 1)
+        } else if rest.starts_with('m') {
+            (60.0, 1)
+        } else if rest.starts_with('s') {
+            (1.0, 1)
+        } else {
+            return None;
         };
+        total += amount * seconds;
+        rest = &rest[unit_len..];
+    }
+    Some(Duration::from_secs_f64(total))
+}
 
-        let response = self
-            .client
-            .post(&url)
-            .header("Authorization", format!("Bearer {}", self.api_key))
-            .json(&request)
-            .send()
-            .await?;
+/// One budget (requests or tokens) as last reported, until it resets
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct RateLimitWindow {
+    limit: Option<u64>,
+    remaining: u64,
+    resets_at: Instant,
+}
+
+impl RateLimitWindow {
+    fn new(
+        limit: Option<u64>,
+        remaining: Option<u64>,
+        reset: Option<Duration>,
+        now: Instant,
+    ) -> Option<Self> {
+        Some(Self {
+            limit,
+            remaining: remaining?,
+            resets_at: now + reset?,
+        })
+    }
+
+    /// Wait until the reset once no more than 2% of the limit (or nothing,
+    /// when the limit is unknown) is left, keeping a margin for requests in flight
+    fn delay(&self, now: Instant) -> Option<Duration> {
+        let reserve = self.limit.map_or(0, |limit| limit / 50);
+        (self.remaining <= reserve && self.resets_at > now).then(|| self.resets_at - now)
+    }
+}
+
+/// Request and token budgets from the latest `x-ratelimit-*` headers, shared
+/// by an [`OpenAIClient`] and its clones (see
+/// [`with_rate_limit_tracking`](OpenAIClientBuilder::with_rate_limit_tracking))
+#[derive(Debug, Default)]
+pub struct RateLimitState {
+    windows: Mutex<(Option<RateLimitWindow>, Option<RateLimitWindow>)>,
+}
+
+impl RateLimitState {
+    /// Take in the headers of a response received at `now`
+    pub fn update(&self, headers: &RateLimitHeaders, now: Instant) {
+        let requests = RateLimitWindow::new(
+            headers.limit_requests,
+            headers.remaining_requests,
+            headers.reset_requests,
+            now,
+        );
+        let tokens = RateLimitWindow::new(
+            headers.limit_tokens,
+            headers.remaining_tokens,
+            headers.reset_tokens,
+            now,
+        );
+
+        let mut windows = self.windows.lock().unwrap();
+        if requests.is_some() {
+            windows.0 = requests;
+        }
+        if tokens.is_some() {
+            windows.1 = tokens;
+        }
+    }
 
-        if !response.status().is_success() {
-            let status = response.status();
-            let text = response.text().await?;
-            anyhow::bail!("GPT API call failed with status {}: {}", status, text);
+    /// How long a request starting at `now` has to wait; when it doesn't,
+    /// it is counted against the remaining requests
+    pub fn reserve(&self, now: Instant) -> Option<Duration> {
+        let mut windows = self.windows.lock().unwrap();
+        let (requests, tokens) = &mut *windows;
+        let delay = [requests.as_ref(), tokens.as_ref()]
+            .into_iter()
+            .flatten()
+            .filter_map(|window| window.delay(now))
+            .max();
+        if delay.is_none()
+            && let Some(requests) = requests
+            && requests.resets_at > now
+        {
+            requests.remaining = requests.remaining.saturating_sub(1);
         }
+        delay
+    }
+}
 
-        let chat_response: ChatResponse = response.json().await?;
+/// Request timeouts and connection settings of the client
+///
+/// A request that gets no complete response within its endpoint's timeout
+/// fails with [`OpenAIError::Timeout`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeoutConfig {
+    /// Overall timeout of a transcription request, upload included
+    pub transcription: Duration,
+    /// Overall timeout of a chat completion (and API key check)
+    pub chat: Duration,
+    /// Overall timeout of an image generation or edit
+    pub image: Duration,
+    /// Overall timeout of a text-to-speech request, download included
+    pub speech: Duration,
+    /// Timeout for establishing a connection
+    pub connect: Duration,
+    /// How long an unused pooled connection is kept open; `None` keeps it
+    /// until the server closes it
+    pub pool_idle_timeout: Option<Duration>,
+    /// Unused connections kept open per host
+    pub pool_max_idle_per_host: usize,
+}
 
-        if chat_response.choices.is_empty() {
-            anyhow::bail!("No response from GPT API");
+impl Default for TimeoutConfig {
+    fn default() -> Self {
+        Self {
+            transcription: Duration::from_secs(300),
+            chat: Duration::from_secs(120),
+            image: Duration::from_secs(180),
+            speech: Duration::from_secs(120),
+            connect: Duration::from_secs(10),
+            pool_idle_timeout: Some(Duration::from_secs(90)),
+            pool_max_idle_per_host: 64,
         }
+    }
+}
 
-        let content = &chat_response.choices[0].message.content;
-        let content_response: ContentResponse =
-            serde_json::from_str(content).context("Failed to parse GPT response as JSON")?;
+impl TimeoutConfig {
+    /// Defaults, with every request timeout set to OPENAI_TIMEOUT_SECS when it
+    /// holds a positive number of seconds
+    pub fn from_env() -> Self {
+        let timeout = env::var("OPENAI_TIMEOUT_SECS")
+            .ok()
+            .and_then(|v| v.trim().parse::<u64>().ok())
+            .filter(|&secs| secs > 0);
+        match timeout {
+            Some(secs) => Self::default().with_request_timeout(Duration::from_secs(secs)),
+            None => Self::default(),
+        }
+    }
 
-        Ok(content_response)
+    /// Use `timeout` for requests to every endpoint
+    pub fn with_request_timeout(self, timeout: Duration) -> Self {
+        Self {
+            transcription: timeout,
+            chat: timeout,
+            image: timeout,
+            speech: timeout,
+            ..self
+        }
     }
+}
 
-    pub async fn generate_image(&self, prompt: &str, size: &str) -> Result<Vec<u8>> {
-        let url = format!("{}/images/generations", self.base_url);
+/// Proxy and certificate settings of the client's connections
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NetworkConfig {
+    /// Proxy for `https` URLs
+    pub https_proxy: Option<String>,
+    /// Proxy for plain `http` URLs
+    pub http_proxy: Option<String>,
+    /// Comma-separated hosts, domains and CIDR ranges reached without a proxy
+    pub no_proxy: Option<String>,
+    /// PEM file with root certificates to trust on top of the built-in ones,
+    /// e.g. the CA of a TLS-intercepting proxy
+    pub ca_bundle: Option<PathBuf>,
+}
 
-        let request = ImageGenerationRequest {
-            model: "gpt-image-1".to_string(),
-            prompt: prompt.to_string(),
-            n: 1,
-            size: size.to_string(),
+impl NetworkConfig {
+    /// HTTPS_PROXY, HTTP_PROXY, NO_PROXY (or their lower-case forms) and
+    /// OPENAI_CA_BUNDLE
+    pub fn from_env() -> Self {
+        Self::from_vars(|name| env::var(name).ok())
+    }
+
+    fn from_vars(var: impl Fn(&str) -> Option<String>) -> Self {
+        let var = |name: &str| {
+            [name.to_string(), name.to_lowercase()]
+                .iter()
+                .filter_map(|name| var(name))
+                .map(|value| value.trim().to_string())
+                .find(|value| !value.is_empty())
         };
+        Self {
+            https_proxy: var("HTTPS_PROXY"),
+            http_proxy: var("HTTP_PROXY"),
+            no_proxy: var("NO_PROXY"),
+            ca_bundle: var("OPENAI_CA_BUNDLE").map(PathBuf::from),
+        }
+    }
 
-        let response = self
-            .client
-            .post(&url)
-            .header("Authorization", format!("Bearer {}", self.api_key))
-            .header("Content-Type", "application/json")
-            .json(&request)
-            .send()
-            .await?;
+    /// Proxies to route through, each honoring `no_proxy`
+    fn proxies(&self) -> Result<Vec<reqwest::Proxy>, OpenAIError> {
+        let no_proxy = self
+            .no_proxy
+            .as_deref()
+            .and_then(reqwest::NoProxy::from_string);
+        let invalid = |url: &str, e: reqwest::Error| {
+            OpenAIError::Config(format!("Invalid proxy URL {}: {}", url, e))
+        };
 
-        if !response.status().is_success() {
-            let status = response.status();
-            let text = response.text().await?;
-            anyhow::bail!(
-                "Image generation API call failed with status {}: {}",
-                status,
-                text
-            );
+        let mut proxies = Vec::new();
+        if let Some(url) = &self.https_proxy {
+            let proxy = reqwest::Proxy::https(url).map_err(|e| invalid(url, e))?;
+            proxies.push(proxy.no_proxy(no_proxy.clone()));
+        }
+        if let Some(url) = &self.http_proxy {
+            let proxy = reqwest::Proxy::http(url).map_err(|e| invalid(url, e))?;
+            proxies.push(proxy.no_proxy(no_proxy.clone()));
         }
+        Ok(proxies)
+    }
 
-        let result: ImageGenerationResponse = response.json().await?;
+    /// Certificates of the CA bundle, if one is configured
+    fn root_certificates(&self) -> Result<Vec<reqwest::Certificate>, OpenAIError> {
+        let Some(path) = &self.ca_bundle else {
+            return Ok(Vec::new());
+        };
+        let pem = std::fs::read(path).map_err(|e| {
+            OpenAIError::Config(format!(
+                "Failed to read CA bundle {}: {}",
+                path.display(),
+                e
+            ))
+        })?;
+        let certificates = reqwest::Certificate::from_pem_bundle(&pem).map_err(|e| {
+            OpenAIError::Config(format!("Invalid CA bundle {}: {}", path.display(), e))
+        })?;
+        if certificates.is_empty() {
+            return Err(OpenAIError::Config(format!(
+                "CA bundle {} holds no PEM certificates",
+                path.display()
+            )));
+        }
+        Ok(certificates)
+    }
+}
 
-        if result.data.is_empty() {
-            anyhow::bail!("No images returned from API");
+/// Connection pool with the given timeouts, proxies and extra root
+/// certificates; proxies come from `network` only, not reqwest's own lookup
+fn build_http_client(
+    timeouts: &TimeoutConfig,
+    network: &NetworkConfig,
+) -> Result<reqwest::Client, OpenAIError> {
+    let mut builder = reqwest::Client::builder()
+        .use_rustls_tls()
+        .connect_timeout(timeouts.connect)
+        .pool_idle_timeout(timeouts.pool_idle_timeout)
+        .pool_max_idle_per_host(timeouts.pool_max_idle_per_host)
+        .no_proxy();
+    for proxy in network.proxies()? {
+        builder = builder.proxy(proxy);
+    }
+    for certificate in network.root_certificates()? {
+        builder = builder.add_root_certificate(certificate);
+    }
+    Ok(builder.build()?)
+}
+
+/// A transport error with its root cause, and a pointer to the proxy and CA
+/// settings when no connection could be made
+fn describe_transport_error(error: &reqwest::Error) -> String {
+    let mut message = error.to_string();
+    let mut source = std::error::Error::source(error);
+    let mut cause = None;
+    while let Some(error) = source {
+        cause = Some(error.to_string());
+        source = error.source();
+    }
+    if let Some(cause) = cause {
+        message = format!("{}: {}", message, cause);
+    }
+    if error.is_connect() {
+        message.push_str(
+            " (behind a proxy? check HTTPS_PROXY/NO_PROXY, and OPENAI_CA_BUNDLE if it intercepts TLS)",
+        );
+    }
+    message
+}
+
+/// Parse a Retry-After header given in seconds (HTTP dates are ignored)
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    value.trim().parse::<u64>().ok().map(Duration::from_secs)
+}
+
+/// Turn a non-success response into an OpenAIError
+async fn check_status(response: reqwest::Response) -> Result<reqwest::Response, OpenAIError> {
+    if response.status().is_success() {
+        return Ok(response);
+    }
+
+    let status = response.status().as_u16();
+    let retry_after = response
+        .headers()
+        .get(reqwest::header::RETRY_AFTER)
+        .and_then(|v| v.to_str().ok())
+        .and_then(parse_retry_after);
+    let body = response.text().await?;
+    tracing::trace!(body = %loggable_body(body.as_bytes(), ""), "error body");
+
+    Err(OpenAIError::from_response(status, &body, retry_after))
+}
+
+/// Logged JSON bodies keep string values (prompts, transcripts) up to this
+/// many characters; longer ones, such as base64 images, are cut
+const MAX_LOGGED_STRING: usize = 500;
+
+/// Longest non-JSON body logged, in characters
+const MAX_LOGGED_BODY: usize = 2000;
+
+/// Characters after `sk-` from which a token is taken for an API key
+const MIN_KEY_SUFFIX: usize = 16;
+
+const REDACTED: &str = "[REDACTED]";
+
+/// Headers holding credentials, masked in logs
+const SECRET_HEADERS: [&str; 3] = ["authorization", "api-key", "proxy-authorization"];
+
+/// A 400 that blames the request's `response_format`, as servers without
+/// JSON mode or structured outputs send
+fn rejects_response_format(error: &OpenAIError) -> bool {
+    match error {
+        OpenAIError::InvalidRequest {
+            status: 400,
+            code,
+            message,
+        } => {
+            message.contains("response_format")
+                || code
+                    .as_deref()
+                    .is_some_and(|code| code.contains("response_format"))
         }
+        _ => false,
+    }
+}
 
-        // Decode base64 to bytes
-        use base64::{engine::general_purpose::STANDARD, Engine as _};
-        let image_bytes = STANDARD
-            .decode(&result.data[0].b64_json)
-            .context("Failed to decode base64 image data")?;
+/// A body for trace logs: secrets are masked, then long JSON strings and
+/// non-JSON bodies are cut
+fn loggable_body(body: &[u8], api_key: &str) -> String {
+    let text = redact_secrets(&String::from_utf8_lossy(body), api_key);
+    match serde_json::from_str::<serde_json::Value>(&text) {
+        Ok(mut value) => {
+            truncate_strings(&mut value);
+            value.to_string()
+        }
+        Err(_) => truncate_chars(&text, MAX_LOGGED_BODY),
+    }
+}
+
+fn truncate_strings(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::String(text) => *text = truncate_chars(text, MAX_LOGGED_STRING),
+        serde_json::Value::Array(items) => items.iter_mut().for_each(truncate_strings),
+        serde_json::Value::Object(map) => map.values_mut().for_each(truncate_strings),
+        _ => {}
+    }
+}
+
+/// The first `max` characters of `text`, noting the full size when cut
+fn truncate_chars(text: &str, max: usize) -> String {
+    match text.char_indices().nth(max) {
+        Some((end, _)) => format!("{}…({} bytes)", &text[..end], text.len()),
+        None => text.to_string(),
+    }
+}
+
+/// Mask `api_key`, bearer tokens and anything shaped like an OpenAI key
+/// (`sk-` followed by at least 16 key characters, e.g. `sk-proj-…`)
+fn redact_secrets(text: &str, api_key: &str) -> String {
+    let text = if api_key.is_empty() {
+        text.to_string()
+    } else {
+        text.replace(api_key, REDACTED)
+    };
+    let is_key_char = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '_';
+
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text.as_str();
+    while let Some(start) = rest.find(is_key_char) {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let end = rest.find(|c| !is_key_char(c)).unwrap_or(rest.len());
+        let token = &rest[..end];
+        rest = &rest[end..];
+
+        match token
+            .match_indices("sk-")
+            .find(|(i, _)| token.len() - i - 3 >= MIN_KEY_SUFFIX)
+        {
+            Some((i, _)) => {
+                out.push_str(&token[..i]);
+                out.push_str(REDACTED);
+            }
+            None => out.push_str(token),
+        }
+
+        // Whatever follows "Bearer " up to the end of the value is a credential
+        if token.eq_ignore_ascii_case("bearer") && rest.starts_with(' ') {
+            let secret = rest[1..]
+                .find(|c: char| c.is_whitespace() || c == '"' || c == '\'' || c == ',')
+                .map_or(rest.len(), |i| i + 1);
+            if secret > 1 {
+                out.push(' ');
+                out.push_str(REDACTED);
+                rest = &rest[secret..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Request headers for trace logs, with credentials masked
+fn loggable_headers(headers: &reqwest::header::HeaderMap) -> Vec<(String, String)> {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            let value = if SECRET_HEADERS.contains(&name.as_str()) {
+                REDACTED.to_string()
+            } else {
+                redact_secrets(&String::from_utf8_lossy(value.as_bytes()), "")
+            };
+            (name.to_string(), value)
+        })
+        .collect()
+}
+
+/// A URL for logs: presigned download links carry credentials in the query
+fn loggable_url(url: &reqwest::Url) -> String {
+    let mut url = url.clone();
+    url.set_query(None);
+    url.to_string()
+}
+
+/// Settings of an [`OpenAIClient`], for building one without the environment
+#[derive(Clone, Default)]
+pub struct OpenAIClientBuilder {
+    api_key: Option<String>,
+    allow_missing_key: bool,
+    base_url: Option<String>,
+    organization: Option<String>,
+    project: Option<String>,
+    provider: Provider,
+    models: ModelConfig,
+    retry: RetryConfig,
+    timeouts: TimeoutConfig,
+    network: NetworkConfig,
+    http_client: Option<reqwest::Client>,
+    rate_limit_tracking: bool,
+    compatibility: Compatibility,
+    cancel: Option<CancellationToken>,
+    cache: Option<ResponseCache>,
+}
+
+impl OpenAIClientBuilder {
+    /// Key sent with every request (required unless
+    /// [`allow_missing_api_key`](Self::allow_missing_api_key))
+    pub fn with_api_key(mut self, api_key: impl Into<String>) -> Self {
+        self.api_key = Some(api_key.into());
+        self
+    }
+
+    /// Build without a key: cached responses are still served, and the first
+    /// request that has to reach the API fails with
+    /// [`OpenAIError::AuthFailed`]
+    pub fn allow_missing_api_key(mut self) -> Self {
+        self.allow_missing_key = true;
+        self
+    }
+
+    /// API root, e.g. a compatible server or an Azure resource (default:
+    /// [`DEFAULT_BASE_URL`]; required for Azure)
+    pub fn with_base_url(mut self, base_url: impl Into<String>) -> Self {
+        self.base_url = Some(base_url.into());
+        self
+    }
+
+    /// Organization billed for requests (`OpenAI-Organization` header)
+    pub fn with_organization(mut self, organization: impl Into<String>) -> Self {
+        self.organization = Some(organization.into());
+        self
+    }
+
+    /// Project requests are attributed to (`OpenAI-Project` header)
+    pub fn with_project(mut self, project: impl Into<String>) -> Self {
+        self.project = Some(project.into());
+        self
+    }
+
+    /// Service requests are sent to (default: OpenAI)
+    pub fn with_provider(mut self, provider: Provider) -> Self {
+        self.provider = provider;
+        self
+    }
+
+    /// Models used for requests (default: [`ModelConfig::default`])
+    pub fn with_models(mut self, models: ModelConfig) -> Self {
+        self.models = models;
+        self
+    }
+
+    /// Use `timeout` for requests to every endpoint
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeouts = self.timeouts.with_request_timeout(timeout);
+        self
+    }
+
+    /// Request timeouts and connection settings (default: [`TimeoutConfig::default`])
+    pub fn with_timeouts(mut self, timeouts: TimeoutConfig) -> Self {
+        self.timeouts = timeouts;
+        self
+    }
+
+    /// How failed requests are retried (default: [`RetryConfig::default`])
+    pub fn with_retry_config(mut self, retry: RetryConfig) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Proxies and extra root certificates (default: none, connecting directly)
+    pub fn with_network(mut self, network: NetworkConfig) -> Self {
+        self.network = network;
+        self
+    }
+
+    /// Send requests through the proxy at `url` (`https` and plain `http` alike)
+    pub fn with_proxy(mut self, url: impl Into<String>) -> Self {
+        let url = url.into();
+        self.network.http_proxy = Some(url.clone());
+        self.network.https_proxy = Some(url);
+        self
+    }
+
+    /// Trust the root certificates of the PEM file at `path` as well
+    pub fn with_ca_bundle(mut self, path: impl Into<PathBuf>) -> Self {
+        self.network.ca_bundle = Some(path.into());
+        self
+    }
+
+    /// Quirks of the server to accommodate (default: [`Compatibility::OpenAI`])
+    pub fn with_compatibility(mut self, compatibility: Compatibility) -> Self {
+        self.compatibility = compatibility;
+        self
+    }
+
+    /// Share an existing connection pool; its own connection settings are
+    /// used instead of the builder's
+    pub fn with_http_client(mut self, client: reqwest::Client) -> Self {
+        self.http_client = Some(client);
+        self
+    }
+
+    /// Track the `x-ratelimit-*` response headers so
+    /// [`acquire_permit`](AiClient::acquire_permit) can hold new requests back
+    /// while a budget is nearly used up (off by default)
+    pub fn with_rate_limit_tracking(mut self, enabled: bool) -> Self {
+        self.rate_limit_tracking = enabled;
+        self
+    }
+
+    /// Abort requests, retry delays and permit waits with
+    /// [`OpenAIError::Cancelled`] once `token` is cancelled
+    pub fn with_cancellation(mut self, token: CancellationToken) -> Self {
+        self.cancel = Some(token);
+        self
+    }
+
+    /// Answer repeated chat and transcription requests from a
+    /// [`ResponseCache`] in `dir`, with its default TTL and size limit
+    pub fn with_cache_dir(self, dir: impl Into<PathBuf>) -> Self {
+        self.with_cache(ResponseCache::new(dir))
+    }
+
+    /// Answer repeated chat and transcription requests from `cache` (off by
+    /// default); hits report zero usage
+    pub fn with_cache(mut self, cache: ResponseCache) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    pub fn build(self) -> Result<OpenAIClient, OpenAIError> {
+        if self.api_key.is_none() && !self.allow_missing_key {
+            return Err(missing_api_key());
+        }
+        let base_url = match (self.base_url, &self.provider) {
+            (Some(base_url), _) => base_url,
+            (None, Provider::OpenAI) => DEFAULT_BASE_URL.to_string(),
+            (None, Provider::Azure { .. }) => {
+                return Err(OpenAIError::Config(
+                    "Azure OpenAI requires a base URL (OPENAI_BASE_URL), e.g. https://<resource>.openai.azure.com"
+                        .to_string(),
+                ));
+            }
+        };
+        let client = match self.http_client {
+            Some(client) => client,
+            None => build_http_client(&self.timeouts, &self.network)?,
+        };
+
+        Ok(OpenAIClient {
+            client,
+            api_key: self.api_key,
+            base_url,
+            organization: self.organization,
+            project: self.project,
+            provider: self.provider,
+            models: self.models,
+            retry: self.retry,
+            timeouts: self.timeouts,
+            network: self.network,
+            rate_limits: self
+                .rate_limit_tracking
+                .then(|| Arc::new(RateLimitState::default())),
+            compatibility: self.compatibility,
+            cancel: self.cancel,
+            cache: self.cache,
+        })
+    }
+}
+
+fn missing_api_key() -> OpenAIError {
+    OpenAIError::AuthFailed {
+        message: "no API key given (set OPENAI_API_KEY)".to_string(),
+    }
+}
+
+/// Base URL of the OpenAI API
+pub const DEFAULT_BASE_URL: &str = "https://api.openai.com/v1";
+
+/// Client for the OpenAI API; clones share one connection pool
+#[derive(Clone)]
+pub struct OpenAIClient {
+    client: reqwest::Client,
+    /// Unset only when built with [`OpenAIClientBuilder::allow_missing_api_key`]
+    api_key: Option<String>,
+    base_url: String,
+    organization: Option<String>,
+    project: Option<String>,
+    provider: Provider,
+    models: ModelConfig,
+    retry: RetryConfig,
+    timeouts: TimeoutConfig,
+    network: NetworkConfig,
+    /// Set when rate limit tracking is on
+    rate_limits: Option<Arc<RateLimitState>>,
+    compatibility: Compatibility,
+    cancel: Option<CancellationToken>,
+    cache: Option<ResponseCache>,
+}
+
+#[derive(Default, Deserialize)]
+pub struct TranscriptionResponse {
+    pub text: String,
+    /// Detected or given language as an English name (e.g. `english`),
+    /// reported by `verbose_json` responses
+    #[serde(default)]
+    pub language: Option<String>,
+    /// Only present for `verbose_json` responses
+    #[serde(default)]
+    pub segments: Vec<TranscriptSegment>,
+    /// Only present for `verbose_json` responses with word timestamps requested
+    #[serde(default)]
+    pub words: Vec<TranscriptWord>,
+    /// Audio length in seconds, reported by `verbose_json` responses
+    #[serde(default)]
+    pub duration: Option<f64>,
+    #[serde(default)]
+    pub usage: Option<TranscriptionUsage>,
+}
+
+/// Billing info of a transcription; duration-billed models report `seconds`
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct TranscriptionUsage {
+    #[serde(default)]
+    pub seconds: Option<f64>,
+}
+
+impl TranscriptionResponse {
+    /// This response billed as nothing, for one answered from the cache
+    fn without_usage(self) -> Self {
+        Self {
+            usage: Some(TranscriptionUsage { seconds: Some(0.0) }),
+            ..self
+        }
+    }
+
+    /// Seconds of audio the API reports having processed, if it says so
+    pub fn audio_seconds(&self) -> Option<f64> {
+        self.usage
+            .as_ref()
+            .and_then(|usage| usage.seconds)
+            .or(self.duration)
+    }
+}
+
+/// Token counts reported by a chat completion
+///
+/// Missing counts are read as zero, and a missing `total_tokens` as the sum
+/// of the other two.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(from = "RawTokenUsage")]
+pub struct TokenUsage {
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub total_tokens: u64,
+}
+
+#[derive(Deserialize)]
+struct RawTokenUsage {
+    #[serde(default)]
+    prompt_tokens: u64,
+    #[serde(default)]
+    completion_tokens: u64,
+    #[serde(default)]
+    total_tokens: Option<u64>,
+}
+
+impl From<RawTokenUsage> for TokenUsage {
+    fn from(raw: RawTokenUsage) -> Self {
+        Self::new(raw.prompt_tokens, raw.completion_tokens).with_total(raw.total_tokens)
+    }
+}
+
+impl TokenUsage {
+    /// Usage whose total is the prompt and completion tokens
+    pub fn new(prompt_tokens: u64, completion_tokens: u64) -> Self {
+        Self {
+            prompt_tokens,
+            completion_tokens,
+            total_tokens: prompt_tokens + completion_tokens,
+        }
+    }
+
+    fn with_total(self, total_tokens: Option<u64>) -> Self {
+        Self {
+            total_tokens: total_tokens.unwrap_or(self.total_tokens),
+            ..self
+        }
+    }
+}
+
+impl std::ops::AddAssign for TokenUsage {
+    fn add_assign(&mut self, other: Self) {
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+        self.total_tokens += other.total_tokens;
+    }
+}
+
+/// A chat result together with the tokens it cost
+#[derive(Debug)]
+pub struct WithUsage<T> {
+    pub value: T,
+    pub usage: TokenUsage,
+}
+
+/// A timestamped piece of a transcription, in seconds from the start of the audio
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TranscriptSegment {
+    pub start: f64,
+    pub end: f64,
+    pub text: String,
+}
+
+/// An audio format accepted by the transcription endpoint
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AudioFormat {
+    pub extension: &'static str,
+    pub mime_type: &'static str,
+}
+
+const fn audio_format(extension: &'static str, mime_type: &'static str) -> AudioFormat {
+    AudioFormat {
+        extension,
+        mime_type,
+    }
+}
+
+/// Formats the transcription endpoint takes, by file extension
+const AUDIO_FORMATS: &[AudioFormat] = &[
+    audio_format("flac", "audio/flac"),
+    audio_format("m4a", "audio/mp4"),
+    audio_format("mp3", "audio/mpeg"),
+    audio_format("mp4", "audio/mp4"),
+    audio_format("mpeg", "audio/mpeg"),
+    audio_format("mpga", "audio/mpeg"),
+    audio_format("oga", "audio/ogg"),
+    audio_format("ogg", "audio/ogg"),
+    audio_format("wav", "audio/wav"),
+    audio_format("webm", "audio/webm"),
+];
+
+fn supported_audio_extensions() -> String {
+    AUDIO_FORMATS
+        .iter()
+        .map(|format| format.extension)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Format of audio to transcribe, from the extension of `filename` or, for
+/// other names, the magic bytes at the start of `data`
+pub fn detect_audio_format(filename: &str, data: &[u8]) -> Result<AudioFormat, OpenAIError> {
+    let extension = std::path::Path::new(filename)
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(str::to_ascii_lowercase);
+    let by_extension =
+        extension.and_then(|extension| AUDIO_FORMATS.iter().find(|f| f.extension == extension));
+    if let Some(format) = by_extension {
+        return Ok(*format);
+    }
+
+    let sniffed = match data {
+        [b'I', b'D', b'3', ..] => "mp3",
+        [0xFF, second, ..] if second & 0xE0 == 0xE0 => "mp3",
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'A', b'V', b'E', ..] => "wav",
+        [b'f', b'L', b'a', b'C', ..] => "flac",
+        [b'O', b'g', b'g', b'S', ..] => "ogg",
+        [_, _, _, _, b'f', b't', b'y', b'p', ..] => "m4a",
+        [0x1A, 0x45, 0xDF, 0xA3, ..] => "webm",
+        _ => {
+            return Err(OpenAIError::UnsupportedAudio {
+                filename: filename.to_string(),
+            });
+        }
+    };
+    Ok(*AUDIO_FORMATS
+        .iter()
+        .find(|format| format.extension == sniffed)
+        .expect("sniffed formats are listed"))
+}
+
+/// Encoding of synthesized speech
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SpeechFormat {
+    #[default]
+    Mp3,
+    Opus,
+    Aac,
+    Flac,
+    Wav,
+    /// Raw 24kHz 16-bit little-endian samples
+    Pcm,
+}
+
+impl SpeechFormat {
+    /// File extension for audio in this format
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Mp3 => "mp3",
+            Self::Opus => "opus",
+            Self::Aac => "aac",
+            Self::Flac => "flac",
+            Self::Wav => "wav",
+            Self::Pcm => "pcm",
+        }
+    }
+}
+
+/// Largest speech response accepted; the API caps input at 4096 characters,
+/// which stays far below this in every format
+pub const MAX_SPEECH_BYTES: usize = 64 * 1024 * 1024;
+
+#[derive(Serialize)]
+struct SpeechRequest<'a> {
+    model: &'a str,
+    input: &'a str,
+    voice: &'a str,
+    response_format: SpeechFormat,
+}
+
+/// A single word of a transcription with its timing, in seconds
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TranscriptWord {
+    pub word: String,
+    pub start: f64,
+    pub end: f64,
+}
+
+/// Optional settings of a transcription request
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TranscriptionOptions<'a> {
+    /// Spoken language (ISO-639-1); auto-detected when `None`
+    pub language: Option<&'a str>,
+    /// Biases spelling of names and jargon; sent as given, so callers keep it
+    /// within the model's prompt limit
+    pub prompt: Option<&'a str>,
+    /// Also return per-word timestamps (`verbose_json` only)
+    pub word_timestamps: bool,
+}
+
+/// A chapter marker for video descriptions, `time` in seconds
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Chapter {
+    pub time: u32,
+    pub title: String,
+}
+
+/// A frame extracted as a thumbnail candidate, `time` in seconds
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Keyframe {
+    /// JPEG file name in the output directory
+    pub file: String,
+    pub time: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub caption: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChatMessage {
+    pub role: String,
+    pub content: MessageContent,
+}
+
+impl ChatMessage {
+    pub fn new(role: &str, content: &str) -> Self {
+        Self {
+            role: role.to_string(),
+            content: MessageContent::Text(content.to_string()),
+        }
+    }
+
+    /// A message made of several parts, e.g. text and images
+    pub fn with_parts(role: &str, parts: Vec<ContentPart>) -> Self {
+        Self {
+            role: role.to_string(),
+            content: MessageContent::Parts(parts),
+        }
+    }
+}
+
+/// Content of a chat message: plain text, or an array of parts for
+/// multimodal input
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum MessageContent {
+    Text(String),
+    Parts(Vec<ContentPart>),
+}
+
+impl MessageContent {
+    /// The text, with the text parts of multimodal content joined by newlines
+    pub fn text(&self) -> String {
+        match self {
+            Self::Text(text) => text.clone(),
+            Self::Parts(parts) => parts
+                .iter()
+                .filter_map(|part| match part {
+                    ContentPart::Text { text } => Some(text.as_str()),
+                    ContentPart::ImageUrl { .. } => None,
+                })
+                .collect::<Vec<_>>()
+                .join("\n"),
+        }
+    }
+}
+
+/// A part of multimodal message content
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ContentPart {
+    Text { text: String },
+    ImageUrl { image_url: ImageUrl },
+}
+
+impl ContentPart {
+    pub fn text(text: &str) -> Self {
+        Self::Text {
+            text: text.to_string(),
+        }
+    }
+
+    /// An image sent inline as a base64 data URL, e.g. `image/jpeg` bytes
+    pub fn image(data: &[u8], mime_type: &str) -> Self {
+        use base64::{engine::general_purpose::STANDARD, Engine as _};
+        Self::ImageUrl {
+            image_url: ImageUrl {
+                url: format!("data:{};base64,{}", mime_type, STANDARD.encode(data)),
+                detail: None,
+            },
+        }
+    }
+}
+
+/// Image of a [`ContentPart::ImageUrl`]: an `https` or `data:` URL
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ImageUrl {
+    pub url: String,
+    /// `low`, `high` or `auto` (the default) input resolution
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+/// Body of a chat completion request; see [`ChatRequest::builder`]
+#[derive(Debug, Clone, Serialize)]
+pub struct ChatRequest {
+    pub model: String,
+    pub messages: Vec<ChatMessage>,
+    pub temperature: f32,
+    pub max_completion_tokens: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response_format: Option<ResponseFormat>,
+}
+
+impl ChatRequest {
+    /// Builder starting from the defaults the client uses: the default chat
+    /// model, temperature 1.0, up to 10000 completion tokens and a free-form
+    /// reply
+    pub fn builder() -> ChatRequestBuilder {
+        ChatRequestBuilder::default()
+    }
+}
+
+/// Builds a [`ChatRequest`] message by message
+#[derive(Debug, Clone)]
+pub struct ChatRequestBuilder {
+    request: ChatRequest,
+}
+
+impl Default for ChatRequestBuilder {
+    fn default() -> Self {
+        Self {
+            request: ChatRequest {
+                model: DEFAULT_CHAT_MODEL.to_string(),
+                messages: Vec::new(),
+                temperature: 1.0,
+                max_completion_tokens: 10000,
+                response_format: None,
+            },
+        }
+    }
+}
+
+impl ChatRequestBuilder {
+    pub fn model(mut self, model: impl Into<String>) -> Self {
+        self.request.model = model.into();
+        self
+    }
+
+    /// Append a message with any role
+    pub fn message(mut self, role: &str, content: &str) -> Self {
+        self.request.messages.push(ChatMessage::new(role, content));
+        self
+    }
+
+    /// Append all of `messages`
+    pub fn messages(mut self, messages: impl IntoIterator<Item = ChatMessage>) -> Self {
+        self.request.messages.extend(messages);
+        self
+    }
+
+    pub fn system(self, content: &str) -> Self {
+        self.message("system", content)
+    }
+
+    pub fn user(self, content: &str) -> Self {
+        self.message("user", content)
+    }
+
+    pub fn assistant(self, content: &str) -> Self {
+        self.message("assistant", content)
+    }
+
+    pub fn temperature(mut self, temperature: f32) -> Self {
+        self.request.temperature = temperature;
+        self
+    }
+
+    pub fn max_completion_tokens(mut self, max_completion_tokens: u32) -> Self {
+        self.request.max_completion_tokens = max_completion_tokens;
+        self
+    }
+
+    pub fn response_format(mut self, response_format: ResponseFormat) -> Self {
+        self.request.response_format = Some(response_format);
+        self
+    }
+
+    pub fn build(self) -> ChatRequest {
+        self.request
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ResponseFormat {
+    #[serde(rename = "type")]
+    pub format_type: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub json_schema: Option<serde_json::Value>,
+}
+
+impl ResponseFormat {
+    /// Any JSON object
+    pub fn json_object() -> Self {
+        Self {
+            format_type: "json_object".to_string(),
+            json_schema: None,
+        }
+    }
+
+    /// Structured output constrained to a strict JSON schema
+    pub fn json_schema(name: &str, schema: serde_json::Value) -> Self {
+        Self {
+            format_type: "json_schema".to_string(),
+            json_schema: Some(serde_json::json!({
+                "name": name,
+                "strict": true,
+                "schema": schema,
+            })),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ChatResponse {
+    pub choices: Vec<Choice>,
+    #[serde(default)]
+    pub usage: Option<TokenUsage>,
+}
+
+impl ChatResponse {
+    /// Text of the first choice, if there is one
+    pub fn content(&self) -> Option<String> {
+        self.choices
+            .first()
+            .map(|choice| choice.message.content.text())
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Choice {
+    pub message: ChatMessage,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct ContentResponse {
+    #[serde(default)]
+    pub titles: Vec<String>,
+    #[serde(default)]
+    pub descriptions: Vec<String>,
+    #[serde(default)]
+    pub status_updates: Vec<String>,
+    /// Sections declared by a content profile, in profile order
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sections: Vec<ContentSection>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub chapters: Vec<Chapter>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub keyframes: Vec<Keyframe>,
+}
+
+/// Generated items of one named output section
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ContentSection {
+    pub key: String,
+    pub items: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct ImageGenerationRequest {
+    pub model: String,
+    pub prompt: String,
+    pub n: u32,
+    pub size: String,
+    /// Omitted when unset: older image models reject unknown parameters
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quality: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub background: Option<String>,
+}
+
+/// Optional image generation settings; unset ones are left to the model's default
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ImageOptions {
+    /// `low`, `medium`, `high` or `auto`
+    pub quality: Option<String>,
+    /// `opaque` or `transparent`
+    pub background: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct ImageGenerationResponse {
+    pub data: Vec<ImageData>,
+}
+
+/// One generated image: inline as base64 (gpt-image-1) or as a temporary
+/// URL (the dall-e models' default)
+#[derive(Debug, Deserialize)]
+pub struct ImageData {
+    #[serde(default)]
+    pub b64_json: Option<String>,
+    #[serde(default)]
+    pub url: Option<String>,
+    /// The prompt the model actually used, when it rewrote the given one
+    #[serde(default)]
+    pub revised_prompt: Option<String>,
+}
+
+/// A generated image, decoded or downloaded
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GeneratedImage {
+    pub data: Vec<u8>,
+    pub revised_prompt: Option<String>,
+}
+
+/// Images of one request; images that couldn't be decoded or downloaded are
+/// left out and described in `warnings`
+#[derive(Debug, Default)]
+pub struct GeneratedImages {
+    pub images: Vec<GeneratedImage>,
+    pub warnings: Vec<String>,
+}
+
+/// Check that a language code looks like ISO-639-1 (two ASCII letters, e.g. "en")
+pub fn validate_language_code(code: &str) -> Result<String> {
+    let code = code.trim().to_lowercase();
+    if code.len() != 2 || !code.chars().all(|c| c.is_ascii_lowercase()) {
+        anyhow::bail!(
+            "Invalid language code '{}': expected a two-letter ISO-639-1 code such as en, zh or ja",
+            code
+        );
+    }
+    Ok(code)
+}
+
+fn content_system_prompt(language: Option<&str>) -> String {
+    match language {
+        None | Some("zh") => "你是一个专业的内容创作助手，擅长为视频内容生成吸引人的标题和描述。请用中文回复，并严格按照JSON格式输出。".to_string(),
+        Some(code) => format!(
+            "You are a professional content creation assistant who writes engaging titles and descriptions for videos. \
+             Write every title, description and status update in the language with ISO-639-1 code \"{}\", \
+             and strictly follow the requested JSON format.",
+            code
+        ),
+    }
+}
+
+/// Follow-up sent once when a reply can't be parsed as JSON
+const JSON_REPAIR_PROMPT: &str = "Your previous reply could not be parsed as JSON. \
+Reply again with only a valid JSON object in the requested format, \
+without code fences or any other text.";
+
+/// Whether the chat model accepts `json_schema` structured outputs
+fn supports_structured_outputs(model: &str) -> bool {
+    ["gpt-4o", "gpt-4.1", "gpt-5", "o1", "o3", "o4"]
+        .iter()
+        .any(|prefix| model.starts_with(prefix))
+        && model != "gpt-4o-2024-05-13"
+}
+
+/// Strict schema for [`ContentResponse`]; chapters are added locally, not by the model
+fn content_response_schema() -> serde_json::Value {
+    let strings = serde_json::json!({ "type": "array", "items": { "type": "string" } });
+    serde_json::json!({
+        "type": "object",
+        "properties": {
+            "titles": strings,
+            "descriptions": strings,
+            "status_updates": strings,
+        },
+        "required": ["titles", "descriptions", "status_updates"],
+        "additionalProperties": false,
+    })
+}
+
+/// Drop a surrounding markdown code fence (```json ... ```), if any
+fn strip_code_fences(reply: &str) -> &str {
+    let trimmed = reply.trim();
+    let Some(rest) = trimmed.strip_prefix("```") else {
+        return trimmed;
+    };
+    // Skip the info string, e.g. `json`
+    let body = rest.split_once('\n').map_or("", |(_, body)| body);
+    body.trim_end().strip_suffix("```").unwrap_or(body).trim()
+}
+
+/// First balanced top-level `{...}` in `text`, ignoring braces inside strings
+fn extract_json_object(text: &str) -> Option<&str> {
+    let start = text.find('{')?;
+    let mut depth = 0usize;
+    let mut in_string = false;
+    let mut escaped = false;
+
+    for (offset, c) in text[start..].char_indices() {
+        if in_string {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match c {
+            '"' => in_string = true,
+            '{' => depth += 1,
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(&text[start..=start + offset]);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    None
+}
+
+/// Parse a model reply as JSON, tolerating code fences and surrounding commentary
+fn parse_json_reply<T: DeserializeOwned>(reply: &str) -> Result<T, OpenAIError> {
+    let first_error = match serde_json::from_str(reply) {
+        Ok(value) => return Ok(value),
+        Err(e) => e,
+    };
+
+    let unfenced = strip_code_fences(reply);
+    let candidate = extract_json_object(unfenced).unwrap_or(unfenced);
+    serde_json::from_str(candidate).map_err(|_| {
+        OpenAIError::Decode(format!(
+            "Failed to parse GPT response as JSON: {}",
+            first_error
+        ))
+    })
+}
+
+/// The API calls the CLI tools make, implemented by [`OpenAIClient`]
+///
+/// Tools hold a `dyn AiClient` so tests can run them against `MockAiClient`
+/// (`mock` module, `test-support` feature) instead of the network. The
+/// chat helpers are built on [`chat`](Self::chat); the JSON ones are in
+/// [`AiClientExt`].
+#[async_trait]
+pub trait AiClient: Send + Sync {
+    /// Models used for requests
+    fn models(&self) -> &ModelConfig;
+
+    /// Transcribe audio, optionally hinting the spoken language (ISO-639-1).
+    /// When `language` is `None` the field is omitted and the API auto-detects.
+    /// `prompt` biases spelling of names and jargon; it is sent as given, so
+    /// callers keep it within the model's prompt limit.
+    /// See [`TranscriptionResponse::audio_seconds`] for the audio length billed.
+    async fn transcribe(
+        &self,
+        audio_data: Vec<u8>,
+        filename: &str,
+        language: Option<&str>,
+        prompt: Option<&str>,
+    ) -> Result<TranscriptionResponse, OpenAIError>;
+
+    /// Transcribe audio with language, duration and segment timestamps
+    /// (`verbose_json`), plus word timestamps when `options` asks for them.
+    /// Requires a model that supports it, such as whisper-1.
+    async fn transcribe_verbose(
+        &self,
+        audio_data: Vec<u8>,
+        filename: &str,
+        options: &TranscriptionOptions<'_>,
+    ) -> Result<TranscriptionResponse, OpenAIError>;
+
+    /// Send a chat completion request as is and return the raw response
+    ///
+    /// The request's model is used, not the client's; start from
+    /// `ChatRequest::builder().model(&client.models().chat)` to keep it.
+    async fn chat(&self, request: ChatRequest) -> Result<ChatResponse, OpenAIError>;
+
+    /// Cheap authenticated request (list models) to check that the API key works
+    async fn verify_api_key(&self) -> Result<(), OpenAIError>;
+
+    /// Read `text` aloud with `voice` (e.g. alloy, nova) using the speech
+    /// model, returning the audio in `format`
+    async fn synthesize_speech(
+        &self,
+        text: &str,
+        voice: &str,
+        format: SpeechFormat,
+    ) -> Result<Vec<u8>, OpenAIError>;
+
+    /// Generate one image for `prompt`
+    async fn generate_image(
+        &self,
+        prompt: &str,
+        size: &str,
+        options: &ImageOptions,
+    ) -> Result<Vec<u8>, OpenAIError>;
+
+    /// Edit a PNG reference image according to `prompt` (`/images/edits`)
+    ///
+    /// Transparent areas of the optional `mask` mark where the image may change.
+    async fn edit_image(
+        &self,
+        prompt: &str,
+        image: Vec<u8>,
+        mask: Option<Vec<u8>>,
+        size: &str,
+        options: &ImageOptions,
+    ) -> Result<Vec<u8>, OpenAIError>;
+
+    /// Wait until the rate limits leave room for another request; returns
+    /// right away unless the client tracks them
+    async fn acquire_permit(&self) {}
+
+    /// Send a system and user message to the chat model and return the plain-text reply
+    async fn chat_text(
+        &self,
+        system: &str,
+        user: String,
+    ) -> Result<WithUsage<String>, OpenAIError> {
+        let messages = [
+            ChatMessage::new("system", system),
+            ChatMessage::new("user", &user),
+        ];
+        send_chat(self, &messages, None).await
+    }
+
+    /// Ask the chat model about an image (`mime_type` such as `image/png`),
+    /// e.g. to caption a video frame; the model must accept image input
+    async fn chat_with_image(
+        &self,
+        prompt: &str,
+        image: &[u8],
+        mime_type: &str,
+    ) -> Result<WithUsage<String>, OpenAIError> {
+        let message = ChatMessage::with_parts(
+            "user",
+            vec![
+                ContentPart::text(prompt),
+                ContentPart::image(image, mime_type),
+            ],
+        );
+        send_chat(self, &[message], None).await
+    }
+
+    /// Generate titles, descriptions and status updates from a prompt.
+    /// Output is in Chinese unless an ISO-639-1 `language` is given.
+    async fn generate_content(
+        &self,
+        prompt: String,
+        language: Option<&str>,
+    ) -> Result<WithUsage<ContentResponse>, OpenAIError> {
+        self.chat_json_schema(
+            &content_system_prompt(language),
+            prompt,
+            "video_content",
+            content_response_schema(),
+        )
+        .await
+    }
+}
+
+/// Chat replies parsed as JSON, for every [`AiClient`]
+///
+/// Kept apart from [`AiClient`] because generic methods can't be called
+/// through `dyn AiClient`; the blanket impl makes them available there too.
+#[async_trait]
+pub trait AiClientExt: AiClient {
+    /// Like [`chat_text`](AiClient::chat_text), but forces a JSON object reply and parses it
+    async fn chat_json<T: DeserializeOwned + Send>(
+        &self,
+        system: &str,
+        user: String,
+    ) -> Result<WithUsage<T>, OpenAIError>;
+
+    /// Like [`chat_json`](Self::chat_json), but constrains the reply to `schema`
+    /// on models with structured outputs (a plain JSON object otherwise)
+    async fn chat_json_schema<T: DeserializeOwned + Send>(
+        &self,
+        system: &str,
+        user: String,
+        name: &str,
+        schema: serde_json::Value,
+    ) -> Result<WithUsage<T>, OpenAIError>;
+}
+
+#[async_trait]
+impl<C: AiClient + ?Sized> AiClientExt for C {
+    async fn chat_json<T: DeserializeOwned + Send>(
+        &self,
+        system: &str,
+        user: String,
+    ) -> Result<WithUsage<T>, OpenAIError> {
+        request_json(self, system, user, ResponseFormat::json_object()).await
+    }
+
+    async fn chat_json_schema<T: DeserializeOwned + Send>(
+        &self,
+        system: &str,
+        user: String,
+        name: &str,
+        schema: serde_json::Value,
+    ) -> Result<WithUsage<T>, OpenAIError> {
+        let format = if supports_structured_outputs(&self.models().chat) {
+            ResponseFormat::json_schema(name, schema)
+        } else {
+            ResponseFormat::json_object()
+        };
+        request_json(self, system, user, format).await
+    }
+}
+
+/// Ask for JSON and parse the reply; an unparseable reply gets one
+/// follow-up asking the model to resend valid JSON
+async fn request_json<C: AiClient + ?Sized, T: DeserializeOwned>(
+    client: &C,
+    system: &str,
+    user: String,
+    format: ResponseFormat,
+) -> Result<WithUsage<T>, OpenAIError> {
+    let mut messages = vec![
+        ChatMessage::new("system", system),
+        ChatMessage::new("user", &user),
+    ];
+    let reply = send_chat(client, &messages, Some(format)).await?;
+    let mut usage = reply.usage;
+    if let Ok(value) = parse_json_reply(&reply.value) {
+        return Ok(WithUsage { value, usage });
+    }
+
+    // The follow-up is free-form so a refused schema can't fail it again
+    messages.push(ChatMessage::new("assistant", &reply.value));
+    messages.push(ChatMessage::new("user", JSON_REPAIR_PROMPT));
+    let reply = send_chat(client, &messages, Some(ResponseFormat::json_object())).await?;
+    usage += reply.usage;
+    Ok(WithUsage {
+        value: parse_json_reply(&reply.value)?,
+        usage,
+    })
+}
+
+/// Send `messages` to the client's chat model and return the first choice's text
+async fn send_chat<C: AiClient + ?Sized>(
+    client: &C,
+    messages: &[ChatMessage],
+    response_format: Option<ResponseFormat>,
+) -> Result<WithUsage<String>, OpenAIError> {
+    let mut request = ChatRequest::builder()
+        .model(&client.models().chat)
+        .messages(messages.iter().cloned());
+    if let Some(response_format) = response_format {
+        request = request.response_format(response_format);
+    }
+
+    let chat_response = client.chat(request.build()).await?;
+    let usage = chat_response.usage.unwrap_or_default();
+    let choice = chat_response
+        .choices
+        .into_iter()
+        .next()
+        .ok_or_else(|| OpenAIError::Decode("No response from GPT API".to_string()))?;
+
+    Ok(WithUsage {
+        value: choice.message.content.text(),
+        usage,
+    })
+}
+
+impl OpenAIClient {
+    /// Client configured from the environment: OPENAI_API_KEY, and optionally
+    /// OPENAI_BASE_URL, OPENAI_ORG_ID, OPENAI_PROJECT_ID, the provider (see
+    /// [`Provider::from_env`]), models, OPENAI_TIMEOUT_SECS and the proxy and
+    /// CA settings of [`NetworkConfig::from_env`]
+    pub fn new() -> Result<Self, OpenAIError> {
+        if env::var("OPENAI_API_KEY").is_err() {
+            return Err(OpenAIError::AuthFailed {
+                message: "OPENAI_API_KEY environment variable not set".to_string(),
+            });
+        }
+        Self::from_env()
+    }
+
+    /// Like [`new`](Self::new), but OPENAI_API_KEY may be unset: requests
+    /// answered from the cache still work, the others fail with
+    /// [`OpenAIError::AuthFailed`]
+    pub fn new_allow_missing_key() -> Result<Self, OpenAIError> {
+        Self::from_env()
+    }
+
+    fn from_env() -> Result<Self, OpenAIError> {
+        let mut builder = Self::builder()
+            .allow_missing_api_key()
+            .with_provider(Provider::from_env()?)
+            .with_models(ModelConfig::from_env())
+            .with_timeouts(TimeoutConfig::from_env())
+            .with_network(NetworkConfig::from_env())
+            .with_compatibility(Compatibility::from_env()?);
+        if let Ok(api_key) = env::var("OPENAI_API_KEY") {
+            builder = builder.with_api_key(api_key);
+        }
+        if let Ok(base_url) = env::var("OPENAI_BASE_URL") {
+            builder = builder.with_base_url(base_url);
+        }
+        if let Ok(organization) = env::var("OPENAI_ORG_ID") {
+            builder = builder.with_organization(organization);
+        }
+        if let Ok(project) = env::var("OPENAI_PROJECT_ID") {
+            builder = builder.with_project(project);
+        }
+        builder.build()
+    }
+
+    /// Client configured in code only; the environment is not read
+    pub fn builder() -> OpenAIClientBuilder {
+        OpenAIClientBuilder::default()
+    }
+
+    /// Use the given models instead of the environment/default ones
+    pub fn with_models(mut self, models: ModelConfig) -> Self {
+        self.models = models;
+        self
+    }
+
+    /// URL of `endpoint` for a request to `model`
+    fn url(&self, endpoint: Endpoint, model: &str) -> String {
+        endpoint_url(&self.base_url, &self.provider, endpoint, model)
+    }
+
+    /// Fail requests that would go out without a key
+    fn ensure_api_key(&self) -> Result<(), OpenAIError> {
+        match self.api_key {
+            Some(_) => Ok(()),
+            None => Err(missing_api_key()),
+        }
+    }
+
+    /// The key sent along and masked in logs
+    fn secret(&self) -> &str {
+        self.api_key.as_deref().unwrap_or_default()
+    }
+
+    /// A request to `url` carrying the provider's auth headers
+    fn request(&self, method: reqwest::Method, url: &str) -> reqwest::RequestBuilder {
+        let request = self.client.request(method, url);
+        match self.provider {
+            Provider::OpenAI => {
+                let mut request =
+                    request.header("Authorization", format!("Bearer {}", self.secret()));
+                if let Some(organization) = &self.organization {
+                    request = request.header("OpenAI-Organization", organization);
+                }
+                if let Some(project) = &self.project {
+                    request = request.header("OpenAI-Project", project);
+                }
+                request
+            }
+            Provider::Azure { .. } => request.header("api-key", self.secret()),
+        }
+    }
+
+    /// Retry transcription, chat and image requests according to `retry`
+    /// instead of the default 3 attempts
+    pub fn with_retry_config(mut self, retry: RetryConfig) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Turn rate limit tracking on or off; see
+    /// [`OpenAIClientBuilder::with_rate_limit_tracking`]. Clones made
+    /// afterwards share the tracked budgets.
+    pub fn with_rate_limit_tracking(mut self, enabled: bool) -> Self {
+        self.rate_limits = enabled.then(|| Arc::new(RateLimitState::default()));
+        self
+    }
+
+    /// Abort in-flight requests once `token` is cancelled; see
+    /// [`OpenAIClientBuilder::with_cancellation`]. Clones share the token.
+    pub fn with_cancellation(mut self, token: CancellationToken) -> Self {
+        self.cancel = Some(token);
+        self
+    }
+
+    /// Answer repeated requests from `cache`, or stop caching with `None`;
+    /// see [`OpenAIClientBuilder::with_cache`]
+    pub fn with_cache(mut self, cache: Option<ResponseCache>) -> Self {
+        self.cache = cache;
+        self
+    }
+
+    /// Run `future` unless the cancellation token fires first
+    async fn cancellable<T>(
+        &self,
+        future: impl Future<Output = Result<T, OpenAIError>>,
+    ) -> Result<T, OpenAIError> {
+        let Some(token) = &self.cancel else {
+            return future.await;
+        };
+        tokio::select! {
+            biased;
+            _ = token.cancelled() => Err(OpenAIError::Cancelled),
+            result = future => result,
+        }
+    }
+
+    /// Use `timeouts` instead of the defaults (or OPENAI_TIMEOUT_SECS); the
+    /// connection pool is rebuilt with its connection settings
+    pub fn with_timeouts(mut self, timeouts: TimeoutConfig) -> Result<Self, OpenAIError> {
+        self.client = build_http_client(&timeouts, &self.network)?;
+        self.timeouts = timeouts;
+        Ok(self)
+    }
+
+    /// Request timeouts and connection settings
+    pub fn timeouts(&self) -> &TimeoutConfig {
+        &self.timeouts
+    }
+
+    /// Send the request built by `build`, building and sending it again after
+    /// retryable failures
+    ///
+    /// Attempts are logged at debug level in a span naming `endpoint` and
+    /// `model`, with redacted headers and bodies at trace level.
+    async fn send_with_retry(
+        &self,
+        endpoint: Endpoint,
+        model: &str,
+        build: impl Fn() -> reqwest::Result<reqwest::RequestBuilder>,
+    ) -> Result<reqwest::Response, OpenAIError> {
+        self.ensure_api_key()?;
+        let span = tracing::debug_span!("openai_request", ?endpoint, model);
+        async {
+            let mut attempt = 1;
+            loop {
+                match self.send_logged(build()?, attempt).await {
+                    Err(e) if attempt < self.retry.max_attempts && e.is_retryable() => {
+                        let delay = self.retry.delay(attempt, e.retry_after());
+                        tracing::debug!(attempt, delay_ms = delay.as_millis() as u64, "retrying");
+                        let event = RetryEvent {
+                            attempt,
+                            max_attempts: self.retry.max_attempts,
+                            delay,
+                            error: &e,
+                        };
+                        if let Some(hook) = &self.retry.on_retry {
+                            hook.call(&event);
+                        }
+                        let _ = SCOPED_RETRY_HOOK.try_with(|hook| hook.call(&event));
+                        self.cancellable(async {
+                            tokio::time::sleep(delay).await;
+                            Ok(())
+                        })
+                        .await?;
+                        attempt += 1;
+                    }
+                    result => return result,
+                }
+            }
+        }
+        .instrument(span)
+        .await
+    }
+
+    /// Send one attempt, logging its size, outcome and duration
+    async fn send_logged(
+        &self,
+        builder: reqwest::RequestBuilder,
+        attempt: u32,
+    ) -> Result<reqwest::Response, OpenAIError> {
+        let (client, request) = builder.build_split();
+        let request = request?;
+        let body = request.body().and_then(|body| body.as_bytes());
+        let payload_bytes = body.map(|body| body.len() as u64).or_else(|| {
+            request
+                .headers()
+                .get(reqwest::header::CONTENT_LENGTH)
+                .and_then(|v| v.to_str().ok()?.parse().ok())
+        });
+        tracing::debug!(
+            attempt,
+            payload_bytes,
+            "{} {}",
+            request.method(),
+            loggable_url(request.url())
+        );
+        if tracing::enabled!(tracing::Level::TRACE) {
+            tracing::trace!(headers = ?loggable_headers(request.headers()), "request headers");
+            if let Some(body) = body {
+                tracing::trace!(body = %loggable_body(body, self.secret()), "request body");
+            }
+        }
+
+        let start = Instant::now();
+        let result = self
+            .cancellable(async {
+                let response = client.execute(request).await?;
+                self.track_rate_limits(&response);
+                tracing::debug!(
+                    status = response.status().as_u16(),
+                    elapsed_ms = start.elapsed().as_millis() as u64,
+                    "response"
+                );
+                check_status(response).await
+            })
+            .await;
+        if let Err(e) = &result {
+            tracing::debug!(
+                elapsed_ms = start.elapsed().as_millis() as u64,
+                "request failed: {}",
+                redact_secrets(&e.to_string(), self.secret())
+            );
+        }
+        result
+    }
+
+    /// POST a chat completion body for `model`
+    async fn send_chat_body(
+        &self,
+        model: &str,
+        body: &impl Serialize,
+    ) -> Result<reqwest::Response, OpenAIError> {
+        let url = self.url(Endpoint::Chat, model);
+        self.send_with_retry(Endpoint::Chat, model, || {
+            Ok(self
+                .request(reqwest::Method::POST, &url)
+                .timeout(self.timeouts.chat)
+                .json(body))
+        })
+        .await
+    }
+
+    /// Send a chat request the way [`Compatibility::Generic`] servers take it
+    async fn send_generic_chat(
+        &self,
+        request: &ChatRequest,
+    ) -> Result<reqwest::Response, OpenAIError> {
+        let mut body = serde_json::to_value(request).expect("chat requests serialize to JSON");
+        let fields = body
+            .as_object_mut()
+            .expect("chat requests are JSON objects");
+        if let Some(max_tokens) = fields.remove("max_completion_tokens") {
+            fields.insert("max_tokens".to_string(), max_tokens);
+        }
+
+        match self.send_chat_body(&request.model, &body).await {
+            Err(e) if body.get("response_format").is_some() && rejects_response_format(&e) => {
+                tracing::debug!("server rejected response_format, resending without it");
+                if let Some(fields) = body.as_object_mut() {
+                    fields.remove("response_format");
+                }
+                self.send_chat_body(&request.model, &body).await
+            }
+            result => result,
+        }
+    }
+
+    /// Read a JSON response body, logging it (redacted) at trace level
+    async fn read_json<T: DeserializeOwned>(
+        &self,
+        response: reqwest::Response,
+    ) -> Result<T, OpenAIError> {
+        self.read_json_cached(response, None).await
+    }
+
+    /// Cache key of a request to `endpoint`, when caching is on
+    fn cache_key(&self, endpoint: Endpoint, model: &str, parts: &[&[u8]]) -> Option<String> {
+        self.cache.as_ref()?;
+        let mut keyed = vec![self.base_url.as_bytes()];
+        keyed.extend_from_slice(parts);
+        Some(ResponseCache::key(
+            &format!("{:?}", endpoint),
+            model,
+            &keyed,
+        ))
+    }
+
+    /// The cached response under `key`; entries that no longer parse are
+    /// removed and count as a miss
+    fn cached<T: DeserializeOwned>(&self, key: Option<&str>) -> Option<T> {
+        let (cache, key) = (self.cache.as_ref()?, key?);
+        let body = cache.get(key)?;
+        match serde_json::from_slice(&body) {
+            Ok(value) => {
+                tracing::debug!(key, "response cache hit");
+                Some(value)
+            }
+            Err(e) => {
+                tracing::warn!(key, "discarding corrupted response cache entry: {}", e);
+                cache.remove(key);
+                None
+            }
+        }
+    }
+
+    /// [`read_json`](Self::read_json), storing the body under `key` once it parses
+    async fn read_json_cached<T: DeserializeOwned>(
+        &self,
+        response: reqwest::Response,
+        key: Option<&str>,
+    ) -> Result<T, OpenAIError> {
+        let body = self
+            .cancellable(async { Ok(response.bytes().await?) })
+            .await?;
+        tracing::trace!(body = %loggable_body(&body, self.secret()), "response body");
+        let value =
+            serde_json::from_slice(&body).map_err(|e| OpenAIError::Decode(e.to_string()))?;
+        self.store_cached(key, &body);
+        Ok(value)
+    }
+
+    /// Store a response body under `key`; a full disk only costs the caching
+    fn store_cached(&self, key: Option<&str>, body: &[u8]) {
+        if let (Some(cache), Some(key)) = (&self.cache, key)
+            && let Err(e) = cache.put(key, body)
+        {
+            tracing::warn!("failed to write response cache entry: {}", e);
+        }
+    }
+
+    /// Record the response's rate limit headers when tracking is on
+    fn track_rate_limits(&self, response: &reqwest::Response) {
+        if let Some(state) = &self.rate_limits
+            && let Some(headers) = RateLimitHeaders::from_headers(response.headers())
+        {
+            state.update(&headers, Instant::now());
+        }
+    }
+
+    async fn request_transcription(
+        &self,
+        audio_data: Vec<u8>,
+        filename: &str,
+        options: &TranscriptionOptions<'_>,
+        response_format: &'static str,
+    ) -> Result<TranscriptionResponse, OpenAIError> {
+        let url = self.url(Endpoint::Transcription, &self.models.transcribe);
+        let key = self.cache_key(
+            Endpoint::Transcription,
+            &self.models.transcribe,
+            &[
+                &audio_data,
+                filename.as_bytes(),
+                response_format.as_bytes(),
+                &[options.word_timestamps as u8],
+                options.language.unwrap_or_default().as_bytes(),
+                options.prompt.unwrap_or_default().as_bytes(),
+            ],
+        );
+        if let Some(response) = self.cached::<TranscriptionResponse>(key.as_deref()) {
+            return Ok(response.without_usage());
+        }
+
+        // The API goes by the file name, so sniffed audio gets a matching one
+        let format = detect_audio_format(filename, &audio_data)?;
+        let filename = if filename
+            .to_ascii_lowercase()
+            .ends_with(&format!(".{}", format.extension))
+        {
+            filename.to_string()
+        } else {
+            format!("{}.{}", filename, format.extension)
+        };
+
+        // Forms can't be reused, so every attempt builds its own around a
+        // shared copy of the audio
+        let audio_data = Bytes::from(audio_data);
+        let build = || {
+            let part = bytes_part(&audio_data)
+                .file_name(filename.clone())
+                .mime_str(format.mime_type)?;
+
+            let mut form = multipart::Form::new()
+                .part("file", part)
+                .text("model", self.models.transcribe.clone())
+                .text("response_format", response_format);
+
+            if response_format == "verbose_json" {
+                form = form.text("timestamp_granularities[]", "segment");
+                if options.word_timestamps {
+                    form = form.text("timestamp_granularities[]", "word");
+                }
+            }
+            if let Some(language) = options.language {
+                form = form.text("language", language.to_string());
+            }
+            if let Some(prompt) = options.prompt {
+                form = form.text("prompt", prompt.to_string());
+            }
+
+            Ok(self
+                .request(reqwest::Method::POST, &url)
+                .timeout(self.timeouts.transcription)
+                .multipart(form))
+        };
+        let response = self
+            .send_with_retry(Endpoint::Transcription, &self.models.transcribe, build)
+            .await?;
+
+        if self.compatibility == Compatibility::OpenAI {
+            return self.read_json_cached(response, key.as_deref()).await;
+        }
+        // Some servers answer with the transcript as plain text, which isn't cached
+        let body = self
+            .cancellable(async { Ok(response.bytes().await?) })
+            .await?;
+        tracing::trace!(body = %loggable_body(&body, self.secret()), "response body");
+        if let Ok(result) = serde_json::from_slice(&body) {
+            self.store_cached(key.as_deref(), &body);
+            return Ok(result);
+        }
+        let text = String::from_utf8_lossy(&body).trim().to_string();
+        if text.is_empty() {
+            return Err(OpenAIError::Decode(
+                "Empty transcription response".to_string(),
+            ));
+        }
+        Ok(TranscriptionResponse {
+            text,
+            ..TranscriptionResponse::default()
+        })
+    }
+
+    /// Generate `n` images for `prompt`
+    ///
+    /// Fails only when no image could be returned; otherwise the images that
+    /// succeeded come with a warning for each one that didn't.
+    pub async fn generate_images(
+        &self,
+        prompt: &str,
+        size: &str,
+        n: u32,
+        options: &ImageOptions,
+    ) -> Result<GeneratedImages, OpenAIError> {
+        let url = self.url(Endpoint::ImageGeneration, &self.models.image);
+
+        let request = ImageGenerationRequest {
+            model: self.models.image.clone(),
+            prompt: prompt.to_string(),
+            n,
+            size: size.to_string(),
+            quality: options.quality.clone(),
+            background: options.background.clone(),
+        };
+
+        let response = self
+            .send_with_retry(Endpoint::ImageGeneration, &self.models.image, || {
+                Ok(self
+                    .request(reqwest::Method::POST, &url)
+                    .header("Content-Type", "application/json")
+                    .timeout(self.timeouts.image)
+                    .json(&request))
+            })
+            .await?;
+
+        self.decode_images(response).await
+    }
+
+    /// Decode inline images and download URL ones; fails when none succeed
+    async fn decode_images(
+        &self,
+        response: reqwest::Response,
+    ) -> Result<GeneratedImages, OpenAIError> {
+        let result: ImageGenerationResponse = self.read_json(response).await?;
+        if result.data.is_empty() {
+            return Err(OpenAIError::Decode(
+                "No images returned from API".to_string(),
+            ));
+        }
+
+        let mut generated = GeneratedImages::default();
+        for (i, image) in result.data.into_iter().enumerate() {
+            match self.image_bytes(&image).await {
+                Ok(data) => generated.images.push(GeneratedImage {
+                    data,
+                    revised_prompt: image.revised_prompt,
+                }),
+                Err(e) => generated.warnings.push(format!("Image {}: {}", i + 1, e)),
+            }
+        }
+        if generated.images.is_empty() {
+            return Err(OpenAIError::Decode(generated.warnings.join("; ")));
+        }
+        Ok(generated)
+    }
+
+    async fn image_bytes(&self, image: &ImageData) -> Result<Vec<u8>, OpenAIError> {
+        if let Some(b64_json) = &image.b64_json {
+            use base64::{engine::general_purpose::STANDARD, Engine as _};
+            return STANDARD.decode(b64_json).map_err(|e| {
+                OpenAIError::Decode(format!("Failed to decode base64 image data: {}", e))
+            });
+        }
+        let Some(url) = &image.url else {
+            return Err(OpenAIError::Decode(
+                "neither b64_json nor url in the response".to_string(),
+            ));
+        };
+
+        // Image URLs are presigned storage links: no API key is sent along
+        let response = self
+            .send_logged(self.client.get(url).timeout(self.timeouts.image), 1)
+            .instrument(tracing::debug_span!("image_download"))
+            .await?;
+        let body = self
+            .cancellable(async { Ok(response.bytes().await?) })
+            .await?;
+        Ok(body.to_vec())
+    }
+}
+
+#[async_trait]
+impl AiClient for OpenAIClient {
+    fn models(&self) -> &ModelConfig {
+        &self.models
+    }
+
+    async fn acquire_permit(&self) {
+        let Some(state) = &self.rate_limits else {
+            return;
+        };
+        while let Some(delay) = state.reserve(Instant::now()) {
+            let waited = self
+                .cancellable(async {
+                    tokio::time::sleep(delay).await;
+                    Ok(())
+                })
+                .await;
+            if waited.is_err() {
+                return;
+            }
+        }
+    }
+
+    async fn transcribe(
+        &self,
+        audio_data: Vec<u8>,
+        filename: &str,
+        language: Option<&str>,
+        prompt: Option<&str>,
+    ) -> Result<TranscriptionResponse, OpenAIError> {
+        let options = TranscriptionOptions {
+            language,
+            prompt,
+            ..TranscriptionOptions::default()
+        };
+        self.request_transcription(audio_data, filename, &options, "json")
+            .await
+    }
+
+    async fn transcribe_verbose(
+        &self,
+        audio_data: Vec<u8>,
+        filename: &str,
+        options: &TranscriptionOptions<'_>,
+    ) -> Result<TranscriptionResponse, OpenAIError> {
+        self.request_transcription(audio_data, filename, options, "verbose_json")
+            .await
+    }
+
+    async fn chat(&self, request: ChatRequest) -> Result<ChatResponse, OpenAIError> {
+        let key = self
+            .cache
+            .is_some()
+            .then(|| serde_json::to_vec(&request).expect("chat requests serialize to JSON"));
+        let key = key.and_then(|body| self.cache_key(Endpoint::Chat, &request.model, &[&body]));
+        if let Some(mut response) = self.cached::<ChatResponse>(key.as_deref()) {
+            response.usage = Some(TokenUsage::default());
+            return Ok(response);
+        }
+
+        let response = match self.compatibility {
+            Compatibility::OpenAI => self.send_chat_body(&request.model, &request).await?,
+            Compatibility::Generic => self.send_generic_chat(&request).await?,
+        };
+        self.read_json_cached(response, key.as_deref()).await
+    }
+
+    async fn verify_api_key(&self) -> Result<(), OpenAIError> {
+        self.ensure_api_key()?;
+        let url = self.url(Endpoint::Models, "");
+
+        let request = self
+            .request(reqwest::Method::GET, &url)
+            .timeout(self.timeouts.chat);
+        let span = tracing::debug_span!("openai_request", endpoint = ?Endpoint::Models);
+        self.send_logged(request, 1).instrument(span).await?;
+
+        Ok(())
+    }
+
+    async fn synthesize_speech(
+        &self,
+        text: &str,
+        voice: &str,
+        format: SpeechFormat,
+    ) -> Result<Vec<u8>, OpenAIError> {
+        let url = self.url(Endpoint::Speech, &self.models.speech);
+
+        let request = SpeechRequest {
+            model: &self.models.speech,
+            input: text,
+            voice,
+            response_format: format,
+        };
+
+        let mut response = self
+            .send_with_retry(Endpoint::Speech, &self.models.speech, || {
+                Ok(self
+                    .request(reqwest::Method::POST, &url)
+                    .timeout(self.timeouts.speech)
+                    .json(&request))
+            })
+            .await?;
+
+        let too_large = || {
+            OpenAIError::Decode(format!(
+                "Speech response exceeds {} MiB",
+                MAX_SPEECH_BYTES / (1024 * 1024)
+            ))
+        };
+        let expected = response.content_length().unwrap_or(0) as usize;
+        if expected > MAX_SPEECH_BYTES {
+            return Err(too_large());
+        }
+        let mut audio = Vec::with_capacity(expected);
+        while let Some(chunk) = self
+            .cancellable(async { Ok(response.chunk().await?) })
+            .await?
+        {
+            if audio.len() + chunk.len() > MAX_SPEECH_BYTES {
+                return Err(too_large());
+            }
+            audio.extend_from_slice(&chunk);
+        }
+        if audio.is_empty() {
+            return Err(OpenAIError::Decode(
+                "No audio returned from API".to_string(),
+            ));
+        }
+
+        Ok(audio)
+    }
+
+    async fn generate_image(
+        &self,
+        prompt: &str,
+        size: &str,
+        options: &ImageOptions,
+    ) -> Result<Vec<u8>, OpenAIError> {
+        let generated = self.generate_images(prompt, size, 1, options).await?;
+        Ok(first_image(generated))
+    }
+
+    async fn edit_image(
+        &self,
+        prompt: &str,
+        image: Vec<u8>,
+        mask: Option<Vec<u8>>,
+        size: &str,
+        options: &ImageOptions,
+    ) -> Result<Vec<u8>, OpenAIError> {
+        let url = self.url(Endpoint::ImageEdit, &self.models.image);
+
+        let image = Bytes::from(image);
+        let mask = mask.map(Bytes::from);
+        let build = || {
+            let image = bytes_part(&image)
+                .file_name("image.png")
+                .mime_str("image/png")?;
+            let mut form = multipart::Form::new()
+                .part("image", image)
+                .text("model", self.models.image.clone())
+                .text("prompt", prompt.to_string())
+                .text("n", "1")
+                .text("size", size.to_string());
+            if let Some(mask) = &mask {
+                let mask = bytes_part(mask)
+                    .file_name("mask.png")
+                    .mime_str("image/png")?;
+                form = form.part("mask", mask);
+            }
+            if let Some(quality) = &options.quality {
+                form = form.text("quality", quality.clone());
+            }
+            if let Some(background) = &options.background {
+                form = form.text("background", background.clone());
+            }
+
+            Ok(self
+                .request(reqwest::Method::POST, &url)
+                .timeout(self.timeouts.image)
+                .multipart(form))
+        };
+        let response = self
+            .send_with_retry(Endpoint::ImageEdit, &self.models.image, build)
+            .await?;
+
+        Ok(first_image(self.decode_images(response).await?))
+    }
+}
+
+/// Multipart part sharing `data` instead of copying it, so every attempt
+/// can build one
+fn bytes_part(data: &Bytes) -> multipart::Part {
+    multipart::Part::stream_with_length(data.clone(), data.len() as u64)
+}
+
+/// The single image of a request for one
+fn first_image(generated: GeneratedImages) -> Vec<u8> {
+    generated
+        .images
+        .into_iter()
+        .next()
+        .map(|image| image.data)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    /// Serve one request on a local port, replying with `reply` as JSON;
+    /// the returned handle yields the raw request
+    async fn one_shot_server(reply: &'static str) -> (String, tokio::task::JoinHandle<String>) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        let handle = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0u8; 8192];
+            while !request_complete(&request) {
+                let n = socket.read(&mut buf).await.unwrap();
+                if n == 0 {
+                    break;
+                }
+                request.extend_from_slice(&buf[..n]);
+            }
+            let response = format!(
+                "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                reply.len(),
+                reply
+            );
+            socket.write_all(response.as_bytes()).await.unwrap();
+            String::from_utf8_lossy(&request).into_owned()
+        });
+        (base_url, handle)
+    }
+
+    /// Whether the whole body announced by Content-Length has arrived; bodies
+    /// without one are multipart, ending with the closing `--<boundary>--\r\n`
+    fn request_complete(request: &[u8]) -> bool {
+        let text = String::from_utf8_lossy(request);
+        let Some((head, body)) = text.split_once("\r\n\r\n") else {
+            return false;
+        };
+        let content_length = head.lines().find_map(|line| {
+            let (name, value) = line.split_once(':')?;
+            name.eq_ignore_ascii_case("content-length")
+                .then(|| value.trim().parse::<usize>().ok())?
+        });
+        match content_length {
+            Some(length) => body.len() >= length,
+            None => request.ends_with(b"--\r\n"),
+        }
+    }
+
+    fn test_client(base_url: String) -> OpenAIClient {
+        OpenAIClient::builder()
+            .with_api_key("test-key")
+            .with_base_url(base_url)
+            .with_retry_config(RetryConfig::disabled())
+            .build()
+            .unwrap()
+    }
+
+    const CONTENT_JSON: &str =
+        r#"{"titles": ["A {braced} title"], "descriptions": ["d"], "status_updates": ["s"]}"#;
+
+    #[tokio::test]
+    async fn test_transcribe_sends_prompt() {
+        let (base_url, server) = one_shot_server(r#"{"text": "hello"}"#).await;
+        let response = test_client(base_url)
+            .transcribe(b"audio".to_vec(), "a.mp3", Some("en"), Some("Tokio, Axum"))
+            .await
+            .unwrap();
+        assert_eq!(response.text, "hello");
+
+        let request = server.await.unwrap();
+        assert!(request.contains("name=\"prompt\"\r\n\r\nTokio, Axum\r\n"));
+        assert!(request.contains("name=\"language\"\r\n\r\nen\r\n"));
+    }
+
+    // verbose_json reply of whisper-1 with segment and word timestamps
+    const VERBOSE_JSON: &str = r#"{
+  "task": "transcribe",
+  "language": "english",
+  "duration": 7.519999980926514,
+  "text": "Welcome to the talk. Let's get started.",
+  "words": [
+    {"word": "Welcome", "start": 0.0, "end": 0.47999998927116394},
+    {"word": "to", "start": 0.47999998927116394, "end": 0.6399999856948853},
+    {"word": "the", "start": 0.6399999856948853, "end": 0.7799999713897705},
+    {"word": "talk", "start": 0.7799999713897705, "end": 1.340000033378601}
+  ],
+  "segments": [
+    {"id": 0, "seek": 0, "start": 0.0, "end": 3.4800000190734863, "text": " Welcome to the talk.", "tokens": [50364, 4027, 281, 264, 751, 13, 50538], "temperature": 0.0, "avg_logprob": -0.2860786020755768, "compression_ratio": 1.0, "no_speech_prob": 0.006271342374384403},
+    {"id": 1, "seek": 0, "start": 3.4800000190734863, "end": 7.519999980926514, "text": " Let's get started.", "tokens": [50538, 961, 311, 483, 1409, 13, 50738], "temperature": 0.0, "avg_logprob": -0.2860786020755768, "compression_ratio": 1.0, "no_speech_prob": 0.006271342374384403}
+  ],
+  "usage": {"type": "duration", "seconds": 8}
+}"#;
+
+    #[test]
+    fn test_verbose_transcription_response() {
+        let response: TranscriptionResponse = serde_json::from_str(VERBOSE_JSON).unwrap();
+        assert_eq!(response.language.as_deref(), Some("english"));
+        assert_eq!(response.duration, Some(7.519999980926514));
+        assert_eq!(response.audio_seconds(), Some(8.0));
+
+        assert_eq!(response.segments.len(), 2);
+        assert_eq!(response.segments[1].text, " Let's get started.");
+        // Timestamps keep the reply's full f64 precision
+        assert_eq!(response.segments[0].end, 3.4800000190734863);
+        assert_eq!(response.segments[1].start, response.segments[0].end);
+
+        assert_eq!(response.words.len(), 4);
+        assert_eq!(
+            response.words[3],
+            TranscriptWord {
+                word: "talk".to_string(),
+                start: 0.7799999713897705,
+                end: 1.340000033378601,
+            }
+        );
+
+        // Plain json replies have neither
+        let response: TranscriptionResponse = serde_json::from_str(r#"{"text": "hi"}"#).unwrap();
+        assert!(response.language.is_none());
+        assert!(response.words.is_empty());
+    }
+
+    #[test]
+    fn test_detect_audio_format() {
+        let mime = |filename: &str, data: &[u8]| {
+            detect_audio_format(filename, data).map(|format| format.mime_type)
+        };
+        assert_eq!(mime("talk.mp3", b"").unwrap(), "audio/mpeg");
+        assert_eq!(mime("talk.WAV", b"").unwrap(), "audio/wav");
+        assert_eq!(mime("talk.m4a", b"").unwrap(), "audio/mp4");
+        assert_eq!(mime("talk.flac", b"").unwrap(), "audio/flac");
+
+        // Unknown extensions fall back to the magic bytes
+        assert_eq!(mime("chunk", b"ID3\x04\x00").unwrap(), "audio/mpeg");
+        assert_eq!(
+            mime("chunk.bin", &[0xFF, 0xFB, 0x90, 0x64]).unwrap(),
+            "audio/mpeg"
+        );
+        assert_eq!(
+            mime("chunk.bin", b"RIFF\x24\x08\x00\x00WAVEfmt ").unwrap(),
+            "audio/wav"
+        );
+        assert_eq!(
+            mime("chunk.bin", b"fLaC\x00\x00\x00\x22").unwrap(),
+            "audio/flac"
+        );
+        assert_eq!(
+            mime("chunk.bin", b"\x00\x00\x00\x20ftypM4A ").unwrap(),
+            "audio/mp4"
+        );
+        assert_eq!(
+            detect_audio_format("chunk.bin", b"fLaC").unwrap().extension,
+            "flac"
+        );
+
+        let err = detect_audio_format("notes.txt", b"hello").unwrap_err();
+        assert!(matches!(err, OpenAIError::UnsupportedAudio { .. }));
+        assert!(!err.is_retryable());
+        assert_eq!(
+            err.to_string(),
+            "Unsupported audio format for notes.txt (supported: flac, m4a, mp3, mp4, mpeg, mpga, oga, ogg, wav, webm)"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_transcribe_sends_detected_content_type() {
+        let (base_url, server) = one_shot_server(r#"{"text": "hello"}"#).await;
+        test_client(base_url)
+            .transcribe(b"RIFF\x24\x08\x00\x00WAVE".to_vec(), "chunk_0", None, None)
+            .await
+            .unwrap();
+
+        let request = server.await.unwrap();
+        assert!(request.contains("filename=\"chunk_0.wav\"\r\nContent-Type: audio/wav\r\n"));
+    }
+
+    #[tokio::test]
+    async fn test_transcribe_verbose_requests_word_timestamps() {
+        let (base_url, server) = one_shot_server(VERBOSE_JSON).await;
+        let options = TranscriptionOptions {
+            language: Some("en"),
+            word_timestamps: true,
+            ..TranscriptionOptions::default()
+        };
+        let response = test_client(base_url)
+            .transcribe_verbose(b"audio".to_vec(), "a.mp3", &options)
+            .await
+            .unwrap();
+        assert_eq!(response.words.len(), 4);
+
+        let request = server.await.unwrap();
+        assert!(request.contains("name=\"response_format\"\r\n\r\nverbose_json\r\n"));
+        assert!(request.contains("name=\"timestamp_granularities[]\"\r\n\r\nsegment\r\n"));
+        assert!(request.contains("name=\"timestamp_granularities[]\"\r\n\r\nword\r\n"));
+        assert!(!request.contains("name=\"prompt\""));
+    }
+
+    #[tokio::test]
+    async fn test_synthesize_speech() {
+        use wiremock::matchers::{body_json, header, method, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let audio = vec![0xFF, 0xFB, 0x90, 0x64, 0x00, 0x0F, 0xF0];
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/audio/speech"))
+            .and(header("authorization", "Bearer test-key"))
+            .and(body_json(serde_json::json!({
+                "model": "tts-1",
+                "input": "Hello there",
+                "voice": "nova",
+                "response_format": "mp3",
+            })))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("content-type", "audio/mpeg")
+                    .set_body_bytes(audio.clone()),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let speech = test_client(server.uri())
+            .synthesize_speech("Hello there", "nova", SpeechFormat::Mp3)
+            .await
+            .unwrap();
+        assert_eq!(speech, audio);
+    }
+
+    #[tokio::test]
+    async fn test_synthesize_speech_maps_error_body() {
+        use wiremock::matchers::method;
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(400).set_body_string(
+                r#"{"error": {"message": "Input should be a valid string", "type": "invalid_request_error", "param": "voice", "code": null}}"#,
+            ))
+            .mount(&server)
+            .await;
+
+        let err = test_client(server.uri())
+            .synthesize_speech("Hello", "nobody", SpeechFormat::Wav)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid request (status 400): Input should be a valid string"
+        );
+        assert_eq!(SpeechFormat::Wav.extension(), "wav");
+    }
+
+    #[test]
+    fn test_image_response_shapes() {
+        // gpt-image-1: inline base64
+        let response: ImageGenerationResponse = serde_json::from_str(
+            r#"{"created": 1713833628, "data": [{"b64_json": "aGk="}],
+                "usage": {"total_tokens": 100, "input_tokens": 50, "output_tokens": 50}}"#,
+        )
+        .unwrap();
+        assert_eq!(response.data[0].b64_json.as_deref(), Some("aGk="));
+        assert!(response.data[0].url.is_none());
+
+        // dall-e-3: URL with the rewritten prompt
+        let response: ImageGenerationResponse = serde_json::from_str(
+            r#"{"created": 1713833628, "data": [{
+                "url": "https://oaidalleapiprodscus.blob.core.windows.net/private/img-abc.png?st=2024",
+                "revised_prompt": "A photorealistic cat sitting on a windowsill at sunset"}]}"#,
+        )
+        .unwrap();
+        let image = &response.data[0];
+        assert!(image.b64_json.is_none());
+        assert!(image.url.as_deref().unwrap().contains("img-abc.png"));
+        assert_eq!(
+            image.revised_prompt.as_deref(),
+            Some("A photorealistic cat sitting on a windowsill at sunset")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_generate_images_downloads_urls_and_reports_failures() {
+        use wiremock::matchers::{method, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        let body = serde_json::json!({"data": [
+            {"url": format!("{}/files/one.png", server.uri()), "revised_prompt": "a tabby cat"},
+            {"b64_json": "aGk="},
+            {"url": format!("{}/files/gone.png", server.uri())},
+            {},
+        ]});
+        Mock::given(method("POST"))
+            .and(path("/images/generations"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(body))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/files/one.png"))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(b"png-one".to_vec()))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/files/gone.png"))
+            .respond_with(ResponseTemplate::new(404))
+            .mount(&server)
+            .await;
+
+        let generated = test_client(server.uri())
+            .generate_images("a cat", "1024x1024", 4, &ImageOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(
+            generated.images,
+            vec![
+                GeneratedImage {
+                    data: b"png-one".to_vec(),
+                    revised_prompt: Some("a tabby cat".to_string()),
+                },
+                GeneratedImage {
+                    data: b"hi".to_vec(),
+                    revised_prompt: None,
+                },
+            ]
+        );
+        assert_eq!(generated.warnings.len(), 2);
+        assert!(generated.warnings[0].starts_with("Image 3: "));
+        assert!(generated.warnings[1].contains("neither b64_json nor url"));
+
+        let requests = server.received_requests().await.unwrap();
+        assert!(requests[0].body.windows(6).any(|w| w == br#""n":4,"#));
+        // The API key stays with the API
+        assert!(requests[1..]
+            .iter()
+            .all(|request| !request.headers.contains_key("authorization")));
+    }
+
+    #[tokio::test]
+    async fn test_generate_image_fails_without_usable_images() {
+        let (base_url, _server) = one_shot_server(r#"{"data": [{}]}"#).await;
+        let err = test_client(base_url)
+            .generate_image("a cat", "1024x1024", &ImageOptions::default())
+            .await
+            .unwrap_err();
+        assert!(matches!(err, OpenAIError::Decode(_)));
+        assert!(err.to_string().contains("Image 1: "));
+    }
+
+    #[tokio::test]
+    async fn test_edit_image_posts_multipart() {
+        let (base_url, server) = one_shot_server(r#"{"data": [{"b64_json": "aGk="}]}"#).await;
+        let image = test_client(base_url)
+            .edit_image(
+                "make it teal",
+                b"png-bytes".to_vec(),
+                Some(b"mask-bytes".to_vec()),
+                "1024x1024",
+                &ImageOptions::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(image, b"hi");
+
+        let request = server.await.unwrap();
+        assert!(request.starts_with("POST /images/edits "));
+        assert!(request.contains("name=\"image\"; filename=\"image.png\""));
+        assert!(request.contains("name=\"mask\"; filename=\"mask.png\""));
+        assert!(request.contains("name=\"prompt\"\r\n\r\nmake it teal\r\n"));
+        assert!(request.contains("name=\"size\"\r\n\r\n1024x1024\r\n"));
+        assert!(!request.contains("name=\"quality\""));
+    }
+
+    #[tokio::test]
+    async fn test_transcribe_omits_missing_prompt() {
+        let (base_url, server) = one_shot_server(r#"{"text": "hello"}"#).await;
+        test_client(base_url)
+            .transcribe(b"audio".to_vec(), "a.mp3", None, None)
+            .await
+            .unwrap();
+
+        let request = server.await.unwrap();
+        assert!(!request.contains("name=\"prompt\""));
+        assert!(request.contains("name=\"model\""));
+    }
+
+    #[test]
+    fn test_image_request_skips_unset_options() {
+        let mut request = ImageGenerationRequest {
+            model: "dall-e-3".to_string(),
+            prompt: "a cat".to_string(),
+            n: 1,
+            size: "1024x1024".to_string(),
+            quality: None,
+            background: None,
+        };
+        let json = serde_json::to_value(&request).unwrap();
+        assert!(json.get("quality").is_none());
+        assert!(json.get("background").is_none());
+
+        request.quality = Some("high".to_string());
+        request.background = Some("transparent".to_string());
+        let json = serde_json::to_value(&request).unwrap();
+        assert_eq!(json["quality"], "high");
+        assert_eq!(json["background"], "transparent");
+    }
+
+    #[test]
+    fn test_parse_json_reply_plain() {
+        let content: ContentResponse = parse_json_reply(CONTENT_JSON).unwrap();
+        assert_eq!(content.titles, vec!["A {braced} title"]);
+        assert!(content.chapters.is_empty());
+    }
+
+    #[test]
+    fn test_parse_json_reply_fenced() {
+        let reply = format!("```json\n{}\n```", CONTENT_JSON);
+        let content: ContentResponse = parse_json_reply(&reply).unwrap();
+        assert_eq!(content.descriptions, vec!["d"]);
+
+        let reply = format!("```\n{}\n```\n", CONTENT_JSON);
+        assert!(parse_json_reply::<ContentResponse>(&reply).is_ok());
+    }
+
+    #[test]
+    fn test_parse_json_reply_prefixed_and_trailing_text() {
+        let reply = format!(
+            "Here is the JSON you asked for:\n{}\nLet me know if you need changes {{}}.",
+            CONTENT_JSON
+        );
+        let content: ContentResponse = parse_json_reply(&reply).unwrap();
+        assert_eq!(content.status_updates, vec!["s"]);
+    }
+
+    #[test]
+    fn test_parse_json_reply_truncated() {
+        let reply = r#"{"titles": ["one", "two"], "descriptions": ["cut off mid"#;
+        let err = parse_json_reply::<ContentResponse>(reply).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("Failed to parse GPT response as JSON"));
+        assert_eq!(extract_json_object(reply), None);
+    }
+
+    #[test]
+    fn test_extract_json_object_ignores_braces_in_strings() {
+        let text = r#"note {"a": "}\"{", "b": {"c": 1}} tail }"#;
+        assert_eq!(
+            extract_json_object(text),
+            Some(r#"{"a": "}\"{", "b": {"c": 1}}"#)
+        );
+    }
+
+    #[test]
+    fn test_transcription_audio_seconds() {
+        let verbose: TranscriptionResponse =
+            serde_json::from_str(r#"{"text": "hi", "duration": 12.5, "segments": []}"#).unwrap();
+        assert_eq!(verbose.audio_seconds(), Some(12.5));
+
+        let billed: TranscriptionResponse =
+            serde_json::from_str(r#"{"text": "hi", "usage": {"type": "duration", "seconds": 13}}"#)
+                .unwrap();
+        assert_eq!(billed.audio_seconds(), Some(13.0));
+
+        let tokens: TranscriptionResponse = serde_json::from_str(
+            r#"{"text": "hi", "usage": {"type": "tokens", "input_tokens": 40, "output_tokens": 5}}"#,
+        )
+        .unwrap();
+        assert_eq!(tokens.audio_seconds(), None);
+    }
+
+    #[test]
+    fn test_chat_response_usage() {
+        let response: ChatResponse = serde_json::from_str(
+            r#"{"choices": [{"message": {"role": "assistant", "content": "ok"}}],
+                "usage": {"prompt_tokens": 120, "completion_tokens": 30, "total_tokens": 150}}"#,
+        )
+        .unwrap();
+        let mut usage = response.usage.unwrap();
+        assert_eq!(usage, TokenUsage::new(120, 30));
+
+        usage += usage;
+        assert_eq!(usage.prompt_tokens, 240);
+        assert_eq!(usage.completion_tokens, 60);
+        assert_eq!(usage.total_tokens, 300);
+    }
+
+    #[test]
+    fn test_chat_response_partial_or_missing_usage() {
+        let choices = r#""choices": [{"message": {"role": "assistant", "content": "ok"}}]"#;
+        let parse = |json: String| serde_json::from_str::<ChatResponse>(&json).unwrap().usage;
+
+        assert_eq!(parse(format!("{{{}}}", choices)), None);
+        assert_eq!(parse(format!(r#"{{{}, "usage": null}}"#, choices)), None);
+        // Total derived when absent, kept as reported otherwise (e.g. with
+        // reasoning tokens counted separately by a provider)
+        assert_eq!(
+            parse(format!(
+                r#"{{{}, "usage": {{"prompt_tokens": 10, "completion_tokens": 5}}}}"#,
+                choices
+            )),
+            Some(TokenUsage::new(10, 5))
+        );
+        assert_eq!(
+            parse(format!(
+                r#"{{{}, "usage": {{"prompt_tokens": 10, "completion_tokens": 5, "total_tokens": 20}}}}"#,
+                choices
+            ))
+            .unwrap()
+            .total_tokens,
+            20
+        );
+        assert_eq!(
+            parse(format!(r#"{{{}, "usage": {{}}}}"#, choices)),
+            Some(TokenUsage::default())
+        );
+    }
+
+    #[test]
+    fn test_chat_request_builder_defaults() {
+        let request = ChatRequest::builder().user("hi").build();
+        assert_eq!(request.model, DEFAULT_CHAT_MODEL);
+        assert_eq!(request.temperature, 1.0);
+        assert_eq!(request.max_completion_tokens, 10000);
+        assert_eq!(request.messages, vec![ChatMessage::new("user", "hi")]);
+        let json = serde_json::to_value(&request).unwrap();
+        assert!(json.get("response_format").is_none());
+
+        let request = ChatRequest::builder()
+            .model("gpt-4o")
+            .system("be brief")
+            .user("hi")
+            .assistant("hello")
+            .temperature(0.2)
+            .max_completion_tokens(50)
+            .response_format(ResponseFormat::json_object())
+            .build();
+        let roles: Vec<&str> = request.messages.iter().map(|m| m.role.as_str()).collect();
+        assert_eq!(roles, ["system", "user", "assistant"]);
+        let json = serde_json::to_value(&request).unwrap();
+        assert_eq!(json["model"], "gpt-4o");
+        assert_eq!(json["max_completion_tokens"], 50);
+        assert_eq!(json["response_format"]["type"], "json_object");
+    }
+
+    #[test]
+    fn test_chat_message_serialization() {
+        // Plain text stays a string
+        let message = ChatMessage::new("user", "hi");
+        assert_eq!(
+            serde_json::to_string(&message).unwrap(),
+            r#"{"role":"user","content":"hi"}"#
+        );
+
+        let message = ChatMessage::with_parts(
+            "user",
+            vec![
+                ContentPart::text("Describe this frame"),
+                ContentPart::image(b"jpeg", "image/jpeg"),
+            ],
+        );
+        assert_eq!(
+            serde_json::to_string(&message).unwrap(),
+            r#"{"role":"user","content":[{"type":"text","text":"Describe this frame"},{"type":"image_url","image_url":{"url":"data:image/jpeg;base64,anBlZw=="}}]}"#
+        );
+        assert_eq!(message.content.text(), "Describe this frame");
+
+        // Both forms read back
+        let parsed: ChatMessage =
+            serde_json::from_str(&serde_json::to_string(&message).unwrap()).unwrap();
+        assert_eq!(parsed, message);
+        let parsed: ChatMessage =
+            serde_json::from_str(r#"{"role": "assistant", "content": "A cat."}"#).unwrap();
+        assert_eq!(parsed, ChatMessage::new("assistant", "A cat."));
+    }
+
+    #[tokio::test]
+    async fn test_chat_with_image() {
+        let (base_url, server) = one_shot_server(
+            r#"{"choices": [{"message": {"role": "assistant", "content": "A cat on a sofa."}}]}"#,
+        )
+        .await;
+        let reply = test_client(base_url)
+            .chat_with_image("Caption this frame", b"png", "image/png")
+            .await
+            .unwrap();
+        assert_eq!(reply.value, "A cat on a sofa.");
+
+        let request = server.await.unwrap();
+        assert!(request.contains(
+            r#""messages":[{"role":"user","content":[{"type":"text","text":"Caption this frame"},{"type":"image_url","image_url":{"url":"data:image/png;base64,cG5n"}}]}]"#
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_chat_sends_request_as_is() {
+        let (base_url, server) = one_shot_server(
+            r#"{"choices": [{"message": {"role": "assistant", "content": "pong"}}]}"#,
+        )
+        .await;
+        let request = ChatRequest::builder()
+            .model("custom-model")
+            .user("ping")
+            .temperature(0.5)
+            .build();
+        let response = test_client(base_url).chat(request).await.unwrap();
+        assert_eq!(response.content().as_deref(), Some("pong"));
+        assert!(response.usage.is_none());
+
+        let request = server.await.unwrap();
+        assert!(request.starts_with("POST /chat/completions "));
+        assert!(request.contains(r#""model":"custom-model""#));
+        assert!(request.contains(r#""temperature":0.5"#));
+    }
+
+    #[test]
+    fn test_supports_structured_outputs() {
+        assert!(supports_structured_outputs("gpt-5-mini"));
+        assert!(supports_structured_outputs("gpt-4o-mini"));
+        assert!(!supports_structured_outputs("gpt-4o-2024-05-13"));
+        assert!(!supports_structured_outputs("gpt-3.5-turbo"));
+    }
+
+    fn status_error(status: u16) -> OpenAIError {
+        OpenAIError::from_response(status, "{}", None)
+    }
+
+    #[test]
+    fn test_openai_error_is_retryable() {
+        assert!(status_error(429).is_retryable());
+        assert!(status_error(408).is_retryable());
+        assert!(status_error(500).is_retryable());
+        assert!(status_error(503).is_retryable());
+        assert!(!status_error(400).is_retryable());
+        assert!(!status_error(401).is_retryable());
+        assert!(!status_error(413).is_retryable());
+        assert!(!OpenAIError::from_response(429, INSUFFICIENT_QUOTA, None).is_retryable());
+    }
+
+    // Error bodies as returned by the API
+    const INVALID_API_KEY: &str = r#"{
+    "error": {
+        "message": "Incorrect API key provided: sk-abc123. You can find your API key at https://platform.openai.com/account/api-keys.",
+        "type": "invalid_request_error",
+        "param": null,
+        "code": "invalid_api_key"
+    }
+}"#;
+    const RATE_LIMIT: &str = r#"{
+    "error": {
+        "message": "Rate limit reached for gpt-4o in organization org-abc on tokens per min (TPM): Limit 30000, Used 29000, Requested 2000. Please try again in 2s.",
+        "type": "tokens",
+        "param": null,
+        "code": "rate_limit_exceeded"
+    }
+}"#;
+    const INSUFFICIENT_QUOTA: &str = r#"{
+    "error": {
+        "message": "You exceeded your current quota, please check your plan and billing details.",
+        "type": "insufficient_quota",
+        "param": null,
+        "code": "insufficient_quota"
+    }
+}"#;
+    const INVALID_VALUE: &str = r#"{
+    "error": {
+        "message": "Invalid value: 'ultra'. Supported values are: 'low', 'medium', 'high', and 'auto'.",
+        "type": "invalid_request_error",
+        "param": "quality",
+        "code": "invalid_value"
+    }
+}"#;
+    const SERVER_ERROR: &str = r#"{
+    "error": {
+        "message": "The server had an error while processing your request. Sorry about that!",
+        "type": "server_error",
+        "param": null,
+        "code": null
+    }
+}"#;
+
+    #[test]
+    fn test_openai_error_from_response() {
+        let err = OpenAIError::from_response(401, INVALID_API_KEY, None);
+        assert!(matches!(err, OpenAIError::AuthFailed { .. }));
+        assert!(err
+            .to_string()
+            .starts_with("Authentication failed: Incorrect API key provided"));
+
+        let err = OpenAIError::from_response(429, RATE_LIMIT, Some(Duration::from_secs(2)));
+        assert!(matches!(
+            err,
+            OpenAIError::RateLimited {
+                retry_after: Some(_),
+                ..
+            }
+        ));
+        assert_eq!(err.code(), Some("rate_limit_exceeded"));
+        assert!(err.is_retryable());
+
+        let err = OpenAIError::from_response(400, INVALID_VALUE, None);
+        assert_eq!(err.code(), Some("invalid_value"));
+        assert_eq!(err.status(), Some(400));
+        assert_eq!(
+            err.to_string(),
+            "Invalid request (status 400): Invalid value: 'ultra'. Supported values are: 'low', 'medium', 'high', and 'auto'."
+        );
+
+        let err = OpenAIError::from_response(500, SERVER_ERROR, None);
+        assert!(matches!(err, OpenAIError::ServerError { status: 500, .. }));
+        assert_eq!(err.code(), None);
+
+        // Proxies answer with HTML or plain text
+        let err = OpenAIError::from_response(502, "<html><body>Bad Gateway</body></html>\n", None);
+        assert_eq!(
+            err.to_string(),
+            "OpenAI server error (status 502): <html><body>Bad Gateway</body></html>"
+        );
+        let err = OpenAIError::from_response(
+            413,
+            "Maximum content size limit (26214400) exceeded (26228340 bytes read)",
+            None,
+        );
+        assert!(matches!(
+            err,
+            OpenAIError::InvalidRequest {
+                status: 413,
+                code: None,
+                ..
+            }
+        ));
+        assert_eq!(
+            OpenAIError::from_response(503, "", None).to_string(),
+            "OpenAI server error (status 503): no error details"
+        );
+    }
+
+    #[test]
+    fn test_retry_config_delay() {
+        let retry = RetryConfig {
+            max_attempts: 5,
+            base_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(5),
+            ..RetryConfig::default()
+        };
+        for (attempt, backoff) in [(1, 1), (2, 2), (3, 4), (4, 5), (10, 5)] {
+            let delay = retry.delay(attempt, None);
+            let backoff = Duration::from_secs(backoff);
+            assert!(
+                delay >= backoff && delay <= backoff.mul_f64(1.25),
+                "{:?}",
+                delay
+            );
+        }
+        assert_eq!(
+            retry.delay(1, Some(Duration::from_secs(42))),
+            Duration::from_secs(42)
+        );
+
+        let retry = RetryConfig {
+            honor_retry_after: false,
+            ..retry
+        };
+        assert!(retry.delay(1, Some(Duration::from_secs(42))) < Duration::from_secs(2));
+    }
+
+    #[test]
+    fn test_redact_secrets_never_leaks_key_shaped_strings() {
+        // Deterministic pseudo-random keys of every OpenAI key flavor
+        const KEY_CHARS: &[u8] =
+            b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+        let mut seed: u64 = 0x5eed;
+        let mut next = move || {
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (seed >> 33) as usize
+        };
+
+        let prefixes = ["sk-", "sk-proj-", "sk-svcacct-", "sk-admin-"];
+        let contexts = [
+            "{}",
+            "Authorization: Bearer {}",
+            r#"{"api_key": "{}", "model": "gpt-5-mini"}"#,
+            "OPENAI_API_KEY={}\n",
+            "key ({}), again: {}.",
+            "Incorrect API key provided: {}. You can find your API key at https://platform.openai.com",
+            "x_{}",
+        ];
+        for _ in 0..500 {
+            let prefix = prefixes[next() % prefixes.len()];
+            let len = MIN_KEY_SUFFIX + next() % 150;
+            let suffix: String = (0..len)
+                .map(|_| KEY_CHARS[next() % KEY_CHARS.len()] as char)
+                .collect();
+            let key = format!("{}{}", prefix, suffix);
+
+            for context in contexts {
+                let text = context.replace("{}", &key);
+                for api_key in ["", key.as_str(), "some-other-key"] {
+                    let redacted = redact_secrets(&text, api_key);
+                    assert!(
+                        !redacted.contains(&suffix[suffix.len() - MIN_KEY_SUFFIX..]),
+                        "{} leaked in {}",
+                        key,
+                        redacted
+                    );
+                }
+            }
+        }
+
+        // Keys of other shapes are masked when they are the client's own
+        let azure_key = "0123456789abcdef0123456789abcdef";
+        assert_eq!(
+            redact_secrets(&format!("api-key: {}", azure_key), azure_key),
+            "api-key: [REDACTED]"
+        );
+        assert_eq!(
+            redact_secrets(r#"{"auth": "Bearer abc.def-ghi"}"#, ""),
+            r#"{"auth": "Bearer [REDACTED]"}"#
+        );
+        // Ordinary text is left alone
+        let text = "task-queue sk-short model gpt-5-mini";
+        assert_eq!(redact_secrets(text, ""), text);
+    }
+
+    #[test]
+    fn test_loggable_body_truncates_long_values() {
+        let image = "A".repeat(100_000);
+        let body = format!(
+            r#"{{"data": [{{"b64_json": "{}", "revised_prompt": "a cat"}}], "key": "sk-{}"}}"#,
+            image,
+            "k".repeat(40)
+        );
+        let logged = loggable_body(body.as_bytes(), "");
+        assert!(logged.len() < 1000, "{}", logged);
+        assert!(logged.contains(&format!("{}…(100000 bytes)", "A".repeat(MAX_LOGGED_STRING))));
+        assert!(logged.contains(r#""revised_prompt":"a cat""#));
+        assert!(logged.contains(r#""key":"[REDACTED]""#));
+
+        let logged = loggable_body("x".repeat(5000).as_bytes(), "");
+        assert_eq!(
+            logged,
+            format!("{}…(5000 bytes)", "x".repeat(MAX_LOGGED_BODY))
+        );
+    }
+
+    #[test]
+    fn test_loggable_headers_and_url() {
+        use reqwest::header::{HeaderMap, HeaderValue};
+
+        let mut headers = HeaderMap::new();
+        headers.insert("authorization", HeaderValue::from_static("Bearer secret"));
+        headers.insert("api-key", HeaderValue::from_static("0123abcd"));
+        headers.insert("openai-project", HeaderValue::from_static("proj_123"));
+        assert_eq!(
+            loggable_headers(&headers),
+            [
+                ("authorization".to_string(), "[REDACTED]".to_string()),
+                ("api-key".to_string(), "[REDACTED]".to_string()),
+                ("openai-project".to_string(), "proj_123".to_string()),
+            ]
+        );
+
+        let url = reqwest::Url::parse("https://files.example.com/img.png?sig=abc&se=1").unwrap();
+        assert_eq!(loggable_url(&url), "https://files.example.com/img.png");
+    }
+
+    /// Client in generic compatibility mode against `base_url`
+    fn generic_client(base_url: String) -> OpenAIClient {
+        OpenAIClient::builder()
+            .with_api_key("test-key")
+            .with_base_url(base_url)
+            .with_retry_config(RetryConfig::disabled())
+            .with_compatibility(Compatibility::Generic)
+            .build()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_generic_chat_uses_max_tokens_and_drops_rejected_response_format() {
+        use wiremock::matchers::{method, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        // An LM Studio-like server without JSON mode
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .respond_with(ResponseTemplate::new(400).set_body_string(
+                r#"{"error": {"message": "'response_format.type' must be 'json_schema' or 'text'"}}"#,
+            ))
+            .up_to_n_times(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"{"choices": [{"message": {"role": "assistant", "content": "{\"answer\": 42}"}}]}"#,
+            ))
+            .mount(&server)
+            .await;
+
+        let reply: WithUsage<serde_json::Value> = generic_client(server.uri())
+            .chat_json("system", "hi".to_string())
+            .await
+            .unwrap();
+        assert_eq!(reply.value["answer"], 42);
+
+        let bodies: Vec<serde_json::Value> = server
+            .received_requests()
+            .await
+            .unwrap()
+            .iter()
+            .map(|request| serde_json::from_slice(&request.body).unwrap())
+            .collect();
+        assert_eq!(bodies.len(), 2);
+        assert!(bodies[0].get("response_format").is_some());
+        assert!(bodies[1].get("response_format").is_none());
+        for body in &bodies {
+            assert!(body.get("max_tokens").is_some());
+            assert!(body.get("max_completion_tokens").is_none());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_openai_chat_keeps_request_as_is() {
+        use wiremock::matchers::{method, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .respond_with(ResponseTemplate::new(400).set_body_string(
+                r#"{"error": {"message": "Invalid value for 'response_format'"}}"#,
+            ))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let err = test_client(server.uri())
+            .chat_json::<serde_json::Value>("system", "hi".to_string())
+            .await
+            .unwrap_err();
+        assert!(rejects_response_format(&err));
+        let requests = server.received_requests().await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&requests[0].body).unwrap();
+        assert!(body.get("max_completion_tokens").is_some());
+        assert!(body.get("max_tokens").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_generic_transcription_accepts_plain_text() {
+        use wiremock::matchers::{method, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        // A faster-whisper-like server answering with text/plain
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/audio/transcriptions"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("content-type", "text/plain")
+                    .set_body_string(" 大家好，欢迎收看。\n"),
+            )
+            .mount(&server)
+            .await;
+
+        let response = generic_client(server.uri())
+            .transcribe(b"ID3audio".to_vec(), "a.mp3", None, None)
+            .await
+            .unwrap();
+        assert_eq!(response.text, "大家好，欢迎收看。");
+        assert!(response.segments.is_empty());
+
+        // JSON is still parsed as such
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/audio/transcriptions"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(VERBOSE_JSON))
+            .mount(&server)
+            .await;
+        let response = generic_client(server.uri())
+            .transcribe(b"ID3audio".to_vec(), "a.mp3", None, None)
+            .await
+            .unwrap();
+        assert_eq!(response.segments.len(), 2);
+
+        // Strict clients insist on JSON
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/audio/transcriptions"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("plain"))
+            .mount(&server)
+            .await;
+        let err = test_client(server.uri())
+            .transcribe(b"ID3audio".to_vec(), "a.mp3", None, None)
+            .await;
+        assert!(matches!(err, Err(OpenAIError::Decode(_))));
+    }
+
+    #[test]
+    fn test_parse_reset_duration() {
+        let ms = Duration::from_millis;
+        assert_eq!(parse_reset_duration("1s"), Some(ms(1000)));
+        assert_eq!(parse_reset_duration("6m0s"), Some(ms(360_000)));
+        assert_eq!(parse_reset_duration("20ms"), Some(ms(20)));
+        assert_eq!(parse_reset_duration("0.5s"), Some(ms(500)));
+        assert_eq!(parse_reset_duration("1h2m3.5s"), Some(ms(3_723_500)));
+        assert_eq!(parse_reset_duration("2m30ms"), Some(ms(120_030)));
+        for invalid in ["", "5", "s", "1d", "1.2.3s", "-1s"] {
+            assert_eq!(parse_reset_duration(invalid), None, "{:?}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_rate_limit_headers_from_headers() {
+        use reqwest::header::{HeaderMap, HeaderValue};
+
+        let mut headers = HeaderMap::new();
+        assert_eq!(RateLimitHeaders::from_headers(&headers), None);
+
+        for (name, value) in [
+            ("x-ratelimit-limit-requests", "500"),
+            ("x-ratelimit-remaining-requests", "499"),
+            ("x-ratelimit-reset-requests", "120ms"),
+            ("x-ratelimit-limit-tokens", "30000"),
+            ("x-ratelimit-remaining-tokens", "not a number"),
+            ("x-ratelimit-reset-tokens", "6m0s"),
+        ] {
+            headers.insert(name, HeaderValue::from_static(value));
+        }
+        assert_eq!(
+            RateLimitHeaders::from_headers(&headers),
+            Some(RateLimitHeaders {
+                limit_requests: Some(500),
+                remaining_requests: Some(499),
+                reset_requests: Some(Duration::from_millis(120)),
+                limit_tokens: Some(30000),
+                remaining_tokens: None,
+                reset_tokens: Some(Duration::from_secs(360)),
+            })
+        );
+    }
+
+    #[test]
+    fn test_rate_limit_state_waits_for_reset_near_zero() {
+        let now = Instant::now();
+        let state = RateLimitState::default();
+        assert_eq!(state.reserve(now), None);
+
+        // 2% of 100 requests are kept in reserve
+        state.update(
+            &RateLimitHeaders {
+                limit_requests: Some(100),
+                remaining_requests: Some(4),
+                reset_requests: Some(Duration::from_secs(10)),
+                ..Default::default()
+            },
+            now,
+        );
+        assert_eq!(state.reserve(now), None);
+        assert_eq!(state.reserve(now), None);
+        assert_eq!(state.reserve(now), Some(Duration::from_secs(10)));
+        let later = now + Duration::from_secs(4);
+        assert_eq!(state.reserve(later), Some(Duration::from_secs(6)));
+        // Past the reset the old numbers no longer apply
+        assert_eq!(state.reserve(now + Duration::from_secs(10)), None);
+
+        // Tokens aren't counted locally, but an exhausted budget waits too;
+        // the longer of the two waits wins
+        state.update(
+            &RateLimitHeaders {
+                remaining_requests: Some(50),
+                reset_requests: Some(Duration::from_secs(1)),
+                remaining_tokens: Some(0),
+                reset_tokens: Some(Duration::from_secs(30)),
+                ..Default::default()
+            },
+            now,
+        );
+        assert_eq!(state.reserve(now), Some(Duration::from_secs(30)));
+        state.update(
+            &RateLimitHeaders {
+                limit_requests: Some(100),
+                remaining_requests: Some(0),
+                reset_requests: Some(Duration::from_secs(60)),
+                ..Default::default()
+            },
+            now,
+        );
+        assert_eq!(state.reserve(now), Some(Duration::from_secs(60)));
+    }
+
+    #[tokio::test]
+    async fn test_acquire_permit_waits_for_exhausted_rate_limit() {
+        use wiremock::matchers::{method, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("x-ratelimit-remaining-requests", "0")
+                    .insert_header("x-ratelimit-reset-requests", "300ms")
+                    .set_body_string(
+                        r#"{"choices": [{"message": {"role": "assistant", "content": "ok"}}]}"#,
+                    ),
+            )
+            .mount(&server)
+            .await;
+
+        // Tracking is off unless asked for
+        let client = test_client(server.uri());
+        client.chat_text("system", "hi".to_string()).await.unwrap();
+        let start = std::time::Instant::now();
+        client.acquire_permit().await;
+        assert!(start.elapsed() < Duration::from_millis(100));
+
+        let client = client.with_rate_limit_tracking(true);
+        client.acquire_permit().await;
+        client.chat_text("system", "hi".to_string()).await.unwrap();
+        let start = std::time::Instant::now();
+        client.clone().acquire_permit().await;
+        assert!(start.elapsed() >= Duration::from_millis(250));
+    }
+
+    /// Client against a wiremock server, retrying quickly
+    fn retrying_client(server: &wiremock::MockServer) -> OpenAIClient {
+        test_client(server.uri()).with_retry_config(RetryConfig {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(10),
+            max_delay: Duration::from_millis(50),
+            ..RetryConfig::default()
+        })
+    }
+
+    #[tokio::test]
+    async fn test_retries_rate_limit_honoring_retry_after() {
+        use wiremock::matchers::{method, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .respond_with(ResponseTemplate::new(429).insert_header("retry-after", "1"))
+            .up_to_n_times(1)
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"{"choices": [{"message": {"role": "assistant", "content": "ok"}}]}"#,
+            ))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let start = std::time::Instant::now();
+        let reply = retrying_client(&server)
+            .chat_text("system", "hi".to_string())
+            .await
+            .unwrap();
+        assert_eq!(reply.value, "ok");
+        assert!(start.elapsed() >= Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn test_retries_server_errors_and_rebuilds_multipart() {
+        use wiremock::matchers::{method, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/audio/transcriptions"))
+            .respond_with(ResponseTemplate::new(503))
+            .up_to_n_times(2)
+            .expect(2)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/audio/transcriptions"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(r#"{"text": "hello"}"#))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let response = retrying_client(&server)
+            .transcribe(b"audio".to_vec(), "a.mp3", None, None)
+            .await
+            .unwrap();
+        assert_eq!(response.text, "hello");
+        let requests = server.received_requests().await.unwrap();
+        assert_eq!(requests.len(), 3);
+        for request in &requests {
+            assert!(String::from_utf8_lossy(&request.body).contains("audio"));
+            let length = request.headers.get("content-length").unwrap();
+            assert_eq!(length.to_str().unwrap(), request.body.len().to_string());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_retry_hooks_report_each_retry() {
+        use wiremock::matchers::{method, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .respond_with(ResponseTemplate::new(429))
+            .up_to_n_times(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"{"choices": [{"message": {"role": "assistant", "content": "ok"}}]}"#,
+            ))
+            .mount(&server)
+            .await;
+
+        let client_events = Arc::new(Mutex::new(Vec::new()));
+        let events = client_events.clone();
+        let client = test_client(server.uri()).with_retry_config(RetryConfig {
+            base_delay: Duration::from_millis(10),
+            on_retry: Some(RetryHook::new(move |event| {
+                events.lock().unwrap().push((
+                    event.attempt,
+                    event.max_attempts,
+                    event.error.status(),
+                ))
+            })),
+            ..RetryConfig::default()
+        });
+
+        let scoped_events = Arc::new(Mutex::new(Vec::new()));
+        let events = scoped_events.clone();
+        let reply = with_retry_hook(
+            move |event| events.lock().unwrap().push(event.attempt),
+            client.chat_text("system", "hi".to_string()),
+        )
+        .await
+        .unwrap();
+        assert_eq!(reply.value, "ok");
+        assert_eq!(*client_events.lock().unwrap(), [(1, 3, Some(429))]);
+        assert_eq!(*scoped_events.lock().unwrap(), [1]);
+
+        // Outside the scope only the client-wide hook hears about retries
+        client.chat_text("system", "hi".to_string()).await.unwrap();
+        assert_eq!(client_events.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_policy_rejection_is_not_retried() {
+        use wiremock::matchers::{method, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/images/generations"))
+            .respond_with(ResponseTemplate::new(400).set_body_string(
+                r#"{"error": {"code": "content_policy_violation", "message": "rejected"}}"#,
+            ))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = retrying_client(&server);
+        let err = with_retry_hook(
+            |_| panic!("retried"),
+            client.generate_images("prompt", "1024x1024", 1, &ImageOptions::default()),
+        )
+        .await
+        .unwrap_err();
+        assert!(
+            matches!(err, OpenAIError::InvalidRequest { status: 400, .. }),
+            "{:?}",
+            err
+        );
+    }
+
+    #[tokio::test]
+    async fn test_request_timeout_is_retryable_error() {
+        use wiremock::matchers::{method, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_string(r#"{"choices": []}"#)
+                    .set_delay(Duration::from_secs(5)),
+            )
+            .mount(&server)
+            .await;
+
+        let timeouts = TimeoutConfig {
+            chat: Duration::from_millis(200),
+            ..TimeoutConfig::default()
+        };
+        let client = test_client(server.uri()).with_timeouts(timeouts).unwrap();
+        let start = std::time::Instant::now();
+        let err = client
+            .chat_text("system", "hi".to_string())
+            .await
+            .unwrap_err();
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert!(matches!(err, OpenAIError::Timeout(_)), "{:?}", err);
+        assert!(err.is_retryable());
+        assert!(err.to_string().starts_with("Request timed out"));
+    }
+
+    #[tokio::test]
+    async fn test_timeouts_are_per_endpoint() {
+        use wiremock::matchers::{method, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/audio/transcriptions"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_string(r#"{"text": "hello"}"#)
+                    .set_delay(Duration::from_millis(500)),
+            )
+            .mount(&server)
+            .await;
+
+        // A short chat timeout doesn't cut a slower transcription short
+        let timeouts = TimeoutConfig {
+            chat: Duration::from_millis(100),
+            ..TimeoutConfig::default()
+        };
+        let response = test_client(server.uri())
+            .with_timeouts(timeouts)
+            .unwrap()
+            .transcribe(b"audio".to_vec(), "a.mp3", None, None)
+            .await
+            .unwrap();
+        assert_eq!(response.text, "hello");
+    }
+
+    #[tokio::test]
+    async fn test_cancellation_aborts_in_flight_request() {
+        use wiremock::matchers::{method, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/audio/transcriptions"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_string(r#"{"text": "hello"}"#)
+                    .set_delay(Duration::from_secs(10)),
+            )
+            .mount(&server)
+            .await;
+
+        let token = CancellationToken::new();
+        let client = test_client(server.uri()).with_cancellation(token.clone());
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            token.cancel();
+        });
+
+        let result = tokio::time::timeout(
+            Duration::from_secs(2),
+            client.transcribe(b"audio".to_vec(), "a.mp3", None, None),
+        )
+        .await
+        .expect("cancelled request should return promptly");
+        let Err(err) = result else {
+            panic!("cancelled request succeeded");
+        };
+        assert!(matches!(err, OpenAIError::Cancelled), "{:?}", err);
+        assert!(!err.is_retryable());
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_token_fails_before_sending() {
+        let token = CancellationToken::new();
+        token.cancel();
+        let client = OpenAIClient::builder()
+            .with_api_key("test-key")
+            .with_base_url("http://127.0.0.1:9")
+            .with_cancellation(token)
+            .build()
+            .unwrap();
+
+        // Already cancelled: nothing is sent
+        let err = client.verify_api_key().await.unwrap_err();
+        assert!(matches!(err, OpenAIError::Cancelled), "{:?}", err);
+    }
+
+    fn cached_client(server: &wiremock::MockServer, cache: ResponseCache) -> OpenAIClient {
+        OpenAIClient::builder()
+            .with_api_key("test-key")
+            .with_base_url(server.uri())
+            .with_retry_config(RetryConfig::disabled())
+            .with_cache(cache)
+            .build()
+            .unwrap()
+    }
+
+    async fn mount_chat_reply(server: &wiremock::MockServer, expected_calls: u64) {
+        use wiremock::matchers::{method, path};
+        use wiremock::{Mock, ResponseTemplate};
+
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"{"choices": [{"message": {"role": "assistant", "content": "hi"}}],
+                    "usage": {"prompt_tokens": 10, "completion_tokens": 5}}"#,
+            ))
+            .expect(expected_calls)
+            .mount(server)
+            .await;
+    }
+
+    #[tokio::test]
+    async fn test_cache_hit_skips_request_and_reports_no_usage() {
+        use wiremock::matchers::{method, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        mount_chat_reply(&server, 1).await;
+        Mock::given(method("POST"))
+            .and(path("/audio/transcriptions"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"{"text": "hello", "usage": {"type": "duration", "seconds": 8}}"#,
+            ))
+            .expect(1)
+            .mount(&server)
+            .await;
+        let dir = tempfile::tempdir().unwrap();
+        let client = cached_client(&server, ResponseCache::new(dir.path()));
+
+        let first = client.chat_text("system", "hi".to_string()).await.unwrap();
+        assert_eq!(first.usage, TokenUsage::new(10, 5));
+        let second = client.chat_text("system", "hi".to_string()).await.unwrap();
+        assert_eq!(second.value, "hi");
+        assert_eq!(second.usage, TokenUsage::default());
+
+        let first = client
+            .transcribe(b"audio".to_vec(), "a.mp3", None, None)
+            .await
+            .unwrap();
+        assert_eq!(first.audio_seconds(), Some(8.0));
+        let second = client
+            .transcribe(b"audio".to_vec(), "a.mp3", None, None)
+            .await
+            .unwrap();
+        assert_eq!(second.text, "hello");
+        assert_eq!(second.audio_seconds(), Some(0.0));
+    }
+
+    #[tokio::test]
+    async fn test_missing_api_key_serves_cache_and_fails_before_sending() {
+        let server = wiremock::MockServer::start().await;
+        mount_chat_reply(&server, 1).await;
+        let dir = tempfile::tempdir().unwrap();
+        cached_client(&server, ResponseCache::new(dir.path()))
+            .chat_text("system", "hi".to_string())
+            .await
+            .unwrap();
+
+        let client = OpenAIClient::builder()
+            .allow_missing_api_key()
+            .with_base_url(server.uri())
+            .with_cache(ResponseCache::new(dir.path()))
+            .build()
+            .unwrap();
+        let cached = client.chat_text("system", "hi".to_string()).await.unwrap();
+        assert_eq!(cached.value, "hi");
+        let err = client
+            .chat_text("system", "bye".to_string())
+            .await
+            .unwrap_err();
+        assert!(matches!(err, OpenAIError::AuthFailed { .. }), "{:?}", err);
+        let err = client.verify_api_key().await.unwrap_err();
+        assert!(matches!(err, OpenAIError::AuthFailed { .. }), "{:?}", err);
+    }
+
+    #[tokio::test]
+    async fn test_cache_miss_on_different_request() {
+        let server = wiremock::MockServer::start().await;
+        mount_chat_reply(&server, 2).await;
+        let dir = tempfile::tempdir().unwrap();
+        let client = cached_client(&server, ResponseCache::new(dir.path()));
+
+        client.chat_text("system", "hi".to_string()).await.unwrap();
+        let other = client.chat_text("system", "bye".to_string()).await.unwrap();
+        assert_eq!(other.usage, TokenUsage::new(10, 5));
+    }
+
+    #[tokio::test]
+    async fn test_cache_entry_expires_after_ttl() {
+        let server = wiremock::MockServer::start().await;
+        mount_chat_reply(&server, 2).await;
+        let dir = tempfile::tempdir().unwrap();
+        let cache = ResponseCache::new(dir.path()).with_ttl(Duration::ZERO);
+        let client = cached_client(&server, cache);
+
+        client.chat_text("system", "hi".to_string()).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        let second = client.chat_text("system", "hi".to_string()).await.unwrap();
+        assert_eq!(second.usage, TokenUsage::new(10, 5));
+    }
+
+    #[tokio::test]
+    async fn test_corrupted_cache_entry_is_replaced() {
+        let server = wiremock::MockServer::start().await;
+        mount_chat_reply(&server, 2).await;
+        let dir = tempfile::tempdir().unwrap();
+        let client = cached_client(&server, ResponseCache::new(dir.path()));
+
+        client.chat_text("system", "hi".to_string()).await.unwrap();
+        let entries: Vec<_> = std::fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        assert_eq!(entries.len(), 1);
+        std::fs::write(&entries[0], b"{\"choices\": [tru").unwrap();
+
+        // The broken entry is a miss; the fresh response replaces it
+        let second = client.chat_text("system", "hi".to_string()).await.unwrap();
+        assert_eq!(second.usage, TokenUsage::new(10, 5));
+        let third = client.chat_text("system", "hi".to_string()).await.unwrap();
+        assert_eq!(third.usage, TokenUsage::default());
+    }
+
+    #[test]
+    fn test_with_request_timeout_sets_every_endpoint() {
+        let timeouts = TimeoutConfig::default().with_request_timeout(Duration::from_secs(30));
+        assert_eq!(timeouts.transcription, Duration::from_secs(30));
+        assert_eq!(timeouts.chat, Duration::from_secs(30));
+        assert_eq!(timeouts.image, Duration::from_secs(30));
+        assert_eq!(timeouts.speech, Duration::from_secs(30));
+        assert_eq!(timeouts.connect, TimeoutConfig::default().connect);
+    }
+
+    fn azure(deployments: AzureDeployments) -> Provider {
+        Provider::Azure {
+            api_version: "2025-04-01-preview".to_string(),
+            deployments,
+        }
+    }
+
+    #[test]
+    fn test_endpoint_url_openai() {
+        let base = "https://api.openai.com/v1";
+        let url = |endpoint| endpoint_url(base, &Provider::OpenAI, endpoint, "gpt-5-mini");
+        assert_eq!(
+            url(Endpoint::Chat),
+            "https://api.openai.com/v1/chat/completions"
+        );
+        assert_eq!(
+            url(Endpoint::Transcription),
+            "https://api.openai.com/v1/audio/transcriptions"
+        );
+        assert_eq!(
+            url(Endpoint::ImageGeneration),
+            "https://api.openai.com/v1/images/generations"
+        );
+        assert_eq!(
+            url(Endpoint::ImageEdit),
+            "https://api.openai.com/v1/images/edits"
+        );
+        assert_eq!(
+            endpoint_url(
+                "http://localhost:8080/v1/",
+                &Provider::OpenAI,
+                Endpoint::Models,
+                ""
+            ),
+            "http://localhost:8080/v1/models"
+        );
+    }
+
+    #[test]
+    fn test_endpoint_url_azure() {
+        let base = "https://acme.openai.azure.com/";
+        let provider = azure(AzureDeployments {
+            chat: Some("chat-prod".to_string()),
+            ..AzureDeployments::default()
+        });
+        let url = |endpoint, model| endpoint_url(base, &provider, endpoint, model);
+        assert_eq!(
+            url(Endpoint::Chat, "gpt-5-mini"),
+            "https://acme.openai.azure.com/openai/deployments/chat-prod/chat/completions?api-version=2025-04-01-preview"
+        );
+        // Without a deployment name, the deployment is named after the model
+        assert_eq!(
+            url(Endpoint::Transcription, "whisper-1"),
+            "https://acme.openai.azure.com/openai/deployments/whisper-1/audio/transcriptions?api-version=2025-04-01-preview"
+        );
+        assert_eq!(
+            url(Endpoint::ImageEdit, "gpt-image-1"),
+            "https://acme.openai.azure.com/openai/deployments/gpt-image-1/images/edits?api-version=2025-04-01-preview"
+        );
+        assert_eq!(
+            url(Endpoint::Models, ""),
+            "https://acme.openai.azure.com/openai/models?api-version=2025-04-01-preview"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_azure_requests_use_api_key_header() {
+        use wiremock::matchers::{header, method, path, query_param};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/openai/deployments/images/images/generations"))
+            .and(query_param("api-version", "2025-04-01-preview"))
+            .and(header("api-key", "test-key"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_string(r#"{"data": [{"b64_json": "aGk="}]}"#),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let provider = azure(AzureDeployments {
+            image: Some("images".to_string()),
+            ..AzureDeployments::default()
+        });
+        let image = OpenAIClient::builder()
+            .with_api_key("test-key")
+            .with_base_url(server.uri())
+            .with_provider(provider)
+            .build()
+            .unwrap()
+            .generate_image("a cat", "1024x1024", &ImageOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(image, b"hi");
+        let requests = server.received_requests().await.unwrap();
+        assert!(!requests[0].headers.contains_key("authorization"));
+    }
+
+    #[tokio::test]
+    async fn test_builder_clients_keep_their_own_keys() {
+        use wiremock::matchers::{header, method, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        for key in ["key-a", "key-b"] {
+            Mock::given(method("GET"))
+                .and(path("/v1/models"))
+                .and(header("authorization", format!("Bearer {}", key).as_str()))
+                .respond_with(ResponseTemplate::new(200).set_body_string(r#"{"data": []}"#))
+                .expect(1)
+                .mount(&server)
+                .await;
+        }
+
+        let base_url = format!("{}/v1", server.uri());
+        let shared = reqwest::Client::new();
+        let a = OpenAIClient::builder()
+            .with_api_key("key-a")
+            .with_base_url(&base_url)
+            .with_http_client(shared.clone())
+            .build()
+            .unwrap();
+        let b = OpenAIClient::builder()
+            .with_api_key("key-b")
+            .with_base_url(&base_url)
+            .with_organization("org-b")
+            .with_http_client(shared)
+            .build()
+            .unwrap();
+        a.verify_api_key().await.unwrap();
+        b.verify_api_key().await.unwrap();
+
+        let requests = server.received_requests().await.unwrap();
+        assert!(!requests[0].headers.contains_key("openai-organization"));
+        assert_eq!(requests[1].headers["openai-organization"], "org-b");
+    }
+
+    #[tokio::test]
+    async fn test_organization_and_project_headers() {
+        use wiremock::matchers::method;
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        async fn headers_sent(
+            client: OpenAIClient,
+            server: &MockServer,
+        ) -> Vec<[Option<String>; 2]> {
+            client
+                .transcribe(b"audio".to_vec(), "a.mp3", None, None)
+                .await
+                .unwrap();
+            client.chat_text("system", "hi".to_string()).await.unwrap();
+            client
+                .generate_image("a cat", "1024x1024", &ImageOptions::default())
+                .await
+                .unwrap();
+
+            let requests = server.received_requests().await.unwrap();
+            server.reset().await;
+            requests
+                .iter()
+                .map(|request| {
+                    ["openai-organization", "openai-project"].map(|name| {
+                        request
+                            .headers
+                            .get(name)
+                            .map(|value| value.to_str().unwrap().to_string())
+                    })
+                })
+                .collect()
+        }
+
+        let server = MockServer::start().await;
+        let mount = || async {
+            // One reply that every endpoint accepts
+            Mock::given(method("POST"))
+                .respond_with(ResponseTemplate::new(200).set_body_string(
+                    r#"{"text": "hello", "choices": [{"message": {"role": "assistant", "content": "ok"}}], "data": [{"b64_json": "aGk="}]}"#,
+                ))
+                .mount(&server)
+                .await
+        };
+
+        mount().await;
+        let client = OpenAIClient::builder()
+            .with_api_key("test-key")
+            .with_base_url(server.uri())
+            .with_organization("org-123")
+            .with_project("proj_456")
+            .build()
+            .unwrap();
+        let sent = headers_sent(client, &server).await;
+        assert_eq!(sent.len(), 3);
+        assert!(
+            sent.iter()
+                .all(|headers| headers
+                    == &[Some("org-123".to_string()), Some("proj_456".to_string())])
+        );
+
+        mount().await;
+        let sent = headers_sent(test_client(server.uri()), &server).await;
+        assert_eq!(sent.len(), 3);
+        assert!(sent.iter().all(|headers| headers == &[None, None]));
+    }
+
+    #[test]
+    fn test_network_config_from_vars() {
+        let vars = [
+            ("https_proxy", "http://proxy.corp:3128"),
+            ("HTTP_PROXY", " "),
+            ("http_proxy", "http://proxy.corp:8080"),
+            ("NO_PROXY", "localhost,.internal"),
+            ("OPENAI_CA_BUNDLE", "/etc/ssl/corp.pem"),
+        ];
+        let var = |name: &str| {
+            vars.iter()
+                .find(|(key, _)| *key == name)
+                .map(|(_, value)| value.to_string())
+        };
+        assert_eq!(
+            NetworkConfig::from_vars(var),
+            NetworkConfig {
+                https_proxy: Some("http://proxy.corp:3128".to_string()),
+                http_proxy: Some("http://proxy.corp:8080".to_string()),
+                no_proxy: Some("localhost,.internal".to_string()),
+                ca_bundle: Some(PathBuf::from("/etc/ssl/corp.pem")),
+            }
+        );
+        assert_eq!(NetworkConfig::from_vars(|_| None), NetworkConfig::default());
+    }
+
+    // Self-signed root of a TLS-intercepting proxy
+    const PROXY_CA_PEM: &str = "-----BEGIN CERTIFICATE-----\n\
+MIIBhjCCAS2gAwIBAgIUeLIAPMT1tS5vUgG610sEpxwEUdkwCgYIKoZIzj0EAwIw\n\
+GDEWMBQGA1UEAwwNVGVzdCBQcm94eSBDQTAgFw0yNjEwMTUxMTQ4NTFaGA8yMTI2\n\
+MDkyMTExNDg1MVowGDEWMBQGA1UEAwwNVGVzdCBQcm94eSBDQTBZMBMGByqGSM49\n\
+AgEGCCqGSM49AwEHA0IABF+LLjLzLpMWls4IR8tVU0bM1EULGOUtiXqPABrA+eXT\n\
+LQt+FKHX7P+wFyHAtK4g2x1e9xn8L7dRRo6SrTfNIrejUzBRMB0GA1UdDgQWBBSf\n\
+cRR9kpL48YTji9XD/z+4Wnu84TAfBgNVHSMEGDAWgBSfcRR9kpL48YTji9XD/z+4\n\
+Wnu84TAPBgNVHRMBAf8EBTADAQH/MAoGCCqGSM49BAMCA0cAMEQCIFEdcWwEEG6A\n\
+I4JQ44TI1bjAyNWErtdSN++xa2mooG4JAiAEHPagBvUqIsuyyjvERtXjJp41BEmL\n\
+uHkMfvSL6Fn+/g==\n\
+-----END CERTIFICATE-----";
+
+    #[test]
+    fn test_network_config_builder_wiring() {
+        let dir = tempfile::tempdir().unwrap();
+        let build = |network: NetworkConfig| {
+            OpenAIClient::builder()
+                .with_api_key("key")
+                .with_network(network)
+                .build()
+        };
+
+        let bundle = dir.path().join("corp.pem");
+        std::fs::write(&bundle, PROXY_CA_PEM).unwrap();
+        let network = NetworkConfig {
+            https_proxy: Some("http://proxy.corp:3128".to_string()),
+            no_proxy: Some("localhost".to_string()),
+            ca_bundle: Some(bundle),
+            ..NetworkConfig::default()
+        };
+        assert_eq!(network.proxies().unwrap().len(), 1);
+        assert_eq!(network.root_certificates().unwrap().len(), 1);
+        build(network).unwrap();
+
+        let err = build(NetworkConfig {
+            http_proxy: Some("http://[::1".to_string()),
+            ..NetworkConfig::default()
+        })
+        .err()
+        .unwrap();
+        assert!(err.to_string().contains("Invalid proxy URL http://[::1"));
+
+        let empty = dir.path().join("empty.pem");
+        std::fs::write(&empty, "not a certificate").unwrap();
+        for path in [empty, dir.path().join("missing.pem")] {
+            let err = build(NetworkConfig {
+                ca_bundle: Some(path),
+                ..NetworkConfig::default()
+            })
+            .err()
+            .unwrap();
+            assert!(matches!(err, OpenAIError::Config(_)), "{:?}", err);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_requests_go_through_configured_proxy() {
+        use wiremock::matchers::{method, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        // A plain-http proxy receives the absolute URL and answers for the host
+        let proxy = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/v1/models"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(r#"{"data": []}"#))
+            .expect(1)
+            .mount(&proxy)
+            .await;
+
+        let client = |no_proxy: Option<&str>| {
+            OpenAIClient::builder()
+                .with_api_key("key")
+                .with_base_url("http://api.openai.test/v1")
+                .with_network(NetworkConfig {
+                    http_proxy: Some(proxy.uri()),
+                    no_proxy: no_proxy.map(str::to_string),
+                    ..NetworkConfig::default()
+                })
+                .with_retry_config(RetryConfig::disabled())
+                .build()
+                .unwrap()
+        };
+        client(None).verify_api_key().await.unwrap();
+        let requests = proxy.received_requests().await.unwrap();
+        assert_eq!(requests[0].url.host_str(), Some("api.openai.test"));
+
+        // NO_PROXY hosts are connected to directly, which fails here
+        let err = client(Some("api.openai.test"))
+            .verify_api_key()
+            .await
+            .unwrap_err();
+        assert!(matches!(err, OpenAIError::Transport(_)), "{:?}", err);
+    }
+
+    #[tokio::test]
+    async fn test_connect_errors_point_at_proxy_settings() {
+        // Nothing listens on port 1
+        let err = test_client("http://127.0.0.1:1/v1".to_string())
+            .verify_api_key()
+            .await
+            .unwrap_err();
+        let message = err.to_string();
+        assert!(
+            message.starts_with("Request failed: error sending request"),
+            "{}",
+            message
+        );
+        assert!(message.contains("HTTPS_PROXY/NO_PROXY"), "{}", message);
+        assert!(err.is_retryable());
+    }
+
+    #[test]
+    fn test_builder_settings() {
+        let client = OpenAIClient::builder()
+            .with_api_key("key")
+            .with_timeout(Duration::from_secs(30))
+            .build()
+            .unwrap();
+        assert_eq!(client.base_url, DEFAULT_BASE_URL);
+        assert_eq!(client.timeouts().chat, Duration::from_secs(30));
+        assert_eq!(client.models(), &ModelConfig::default());
+
+        let err = OpenAIClient::builder().build().err().unwrap();
+        assert!(matches!(err, OpenAIError::AuthFailed { .. }));
+
+        let err = OpenAIClient::builder()
+            .with_api_key("key")
+            .with_provider(azure(AzureDeployments::default()))
+            .build()
+            .err()
+            .unwrap();
+        assert!(matches!(err, OpenAIError::Config(_)));
+    }
+
+    #[tokio::test]
+    async fn test_does_not_retry_validation_errors() {
+        use wiremock::matchers::method;
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(400).set_body_string(r#"{"error": {}}"#))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let err = retrying_client(&server)
+            .generate_image("a cat", "1024x1024", &ImageOptions::default())
+            .await
+            .unwrap_err();
+        assert_eq!(err.status(), Some(400));
+    }
+
+    #[test]
+    fn test_openai_error_is_policy_rejection() {
+        let error = |status: u16, body: &str| OpenAIError::from_response(status, body, None);
+        let policy = r#"{"error":{"message":"rejected","code":"content_policy_violation"}}"#;
+        assert!(error(400, policy).is_policy_rejection());
+        assert!(error(400, r#"{"error":{"code":"moderation_blocked"}}"#).is_policy_rejection());
+        assert!(!error(400, r#"{"error":{"code":"invalid_size"}}"#).is_policy_rejection());
+        assert!(!error(429, policy).is_policy_rejection());
+        assert!(!error(400, "not json").is_policy_rejection());
+        assert_eq!(error(400, policy).code(), Some("content_policy_violation"));
+    }
+
+    #[test]
+    fn test_parse_retry_after() {
+        assert_eq!(parse_retry_after("7"), Some(Duration::from_secs(7)));
+        assert_eq!(parse_retry_after(" 0 "), Some(Duration::ZERO));
+        assert_eq!(parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"), None);
+    }
+
+    #[test]
+    fn test_model_config_default() {
+        let models = ModelConfig::default();
+        assert_eq!(models.transcribe, DEFAULT_TRANSCRIBE_MODEL);
+        assert_eq!(models.chat, DEFAULT_CHAT_MODEL);
+        assert_eq!(models.image, DEFAULT_IMAGE_MODEL);
+    }
+
+    #[test]
+    fn test_validate_language_code() {
+        assert_eq!(validate_language_code("en").unwrap(), "en");
+        assert_eq!(validate_language_code("JA").unwrap(), "ja");
+        assert!(validate_language_code("eng").is_err());
+        assert!(validate_language_code("e1").is_err());
+        assert!(validate_language_code("").is_err());
+    }
+
+    #[test]
+    fn test_content_system_prompt_language() {
+        assert!(content_system_prompt(None).contains("中文"));
+        assert!(content_system_prompt(Some("zh")).contains("中文"));
 
-        Ok(image_bytes)
+        let prompt = content_system_prompt(Some("ja"));
+        assert!(prompt.contains("\"ja\""));
+        assert!(!prompt.contains("中文"));
     }
 }
@@ -0,0 +1,476 @@
+//! What pdfinfo (or pdfium) says about a PDF: page count, sizes and boxes
+
+use super::{Backend, ConversionOptions, PageBox, DEFAULT_DPI, POINTS_PER_INCH};
+use anyhow::{Context, Result};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::process::Command;
+
+/// Check that poppler's `tool` (pdftoppm, pdftotext) is installed
+pub fn check_poppler_tool(tool: &str) -> Result<()> {
+    let output = Command::new(tool).arg("-v").output();
+
+    match output {
+        Ok(o) if o.status.success() || !o.stderr.is_empty() => Ok(()),
+        _ => {
+            anyhow::bail!(
+                "{} not found. Please install poppler:\n  \
+                 macOS:   brew install poppler\n  \
+                 Ubuntu:  sudo apt-get install poppler-utils\n  \
+                 Windows: choco install poppler",
+                tool
+            );
+        }
+    }
+}
+
+/// Get the number of pages in a PDF using pdfinfo
+pub fn get_page_count(pdf_path: &Path) -> Result<u32> {
+    let output = Command::new("pdfinfo")
+        .arg(pdf_path)
+        .output()
+        .context("Failed to run pdfinfo")?;
+
+    if !output.status.success() {
+        anyhow::bail!("Failed to get PDF info");
+    }
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    for line in stdout.lines() {
+        if line.starts_with("Pages:") {
+            let pages_str = line.trim_start_matches("Pages:").trim();
+            return pages_str
+                .parse()
+                .with_context(|| format!("Failed to parse page count: {}", pages_str));
+        }
+    }
+
+    anyhow::bail!("Could not find page count in PDF info")
+}
+
+/// Get the number of pages in a PDF with `backend`
+pub fn count_pages(pdf_path: &Path, backend: Backend) -> Result<u32> {
+    match backend {
+        Backend::Poppler => get_page_count(pdf_path),
+        #[cfg(feature = "pdfium")]
+        Backend::Pdfium => super::pdfium::page_count(pdf_path),
+        #[cfg(not(feature = "pdfium"))]
+        Backend::Pdfium => Err(super::pdfium_missing()),
+    }
+}
+
+/// DPI to render `pdf_path` at: `options.dpi`, or the one meeting the size
+/// target
+pub(crate) fn resolve_dpi(
+    pdf_path: &Path,
+    page_count: u32,
+    options: &ConversionOptions,
+) -> Result<u16> {
+    if !options.is_size_targeted() {
+        return Ok(options.dpi.unwrap_or(DEFAULT_DPI));
+    }
+    let sizes = output_page_sizes(
+        pdf_path,
+        page_count,
+        options.crop_box,
+        options.rotate,
+        options.backend,
+    )?;
+    Ok(target_dpi(
+        &sizes,
+        options.max_width,
+        options.max_height,
+        options.dpi,
+    ))
+}
+
+/// Highest whole DPI that keeps the widest page within `max_width` pixels
+/// and the tallest within `max_height`, capped by `max_dpi`
+///
+/// Page sizes are in points (1/72 inch) as displayed, i.e. after rotation.
+pub(crate) fn target_dpi(
+    sizes: &[(f64, f64)],
+    max_width: Option<u32>,
+    max_height: Option<u32>,
+    max_dpi: Option<u16>,
+) -> u16 {
+    let widest = sizes.iter().map(|&(width, _)| width).fold(0.0, f64::max);
+    let tallest = sizes.iter().map(|&(_, height)| height).fold(0.0, f64::max);
+
+    let mut dpi = f64::INFINITY;
+    if let Some(max_width) = max_width
+        && widest > 0.0
+    {
+        dpi = dpi.min(max_width as f64 * POINTS_PER_INCH / widest);
+    }
+    if let Some(max_height) = max_height
+        && tallest > 0.0
+    {
+        dpi = dpi.min(max_height as f64 * POINTS_PER_INCH / tallest);
+    }
+    if !dpi.is_finite() {
+        return max_dpi.unwrap_or(DEFAULT_DPI);
+    }
+
+    // Rounding down keeps pages within the target; `as` saturates at u16::MAX
+    let dpi = (dpi.floor() as u16).max(1);
+    max_dpi.map_or(dpi, |max_dpi| dpi.min(max_dpi))
+}
+
+/// Size in points of pages 1 to `page_count` as they leave the pipeline,
+/// before any resizing: the `page_box` region, turned by the page's own
+/// rotation and then by `rotate` degrees
+pub fn output_page_sizes(
+    pdf_path: &Path,
+    page_count: u32,
+    page_box: PageBox,
+    rotate: u16,
+    backend: Backend,
+) -> Result<Vec<(f64, f64)>> {
+    let sizes: Vec<(f64, f64)> = match (page_box, backend) {
+        (PageBox::Media, Backend::Poppler) => get_page_sizes(pdf_path, page_count)?,
+        (page_box, _) => page_boxes(pdf_path, page_count, backend)?
+            .iter()
+            .map(|boxes| boxes.rendered_size(page_box))
+            .collect(),
+    };
+    Ok(match rotate {
+        90 | 270 => sizes.into_iter().map(|(w, h)| (h, w)).collect(),
+        _ => sizes,
+    })
+}
+
+/// A PDF box in points, from `(x0, y0)` bottom left to `(x1, y1)` top right
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct PdfRect {
+    pub(crate) x0: f64,
+    pub(crate) y0: f64,
+    pub(crate) x1: f64,
+    pub(crate) y1: f64,
+}
+
+impl PdfRect {
+    fn width(&self) -> f64 {
+        (self.x1 - self.x0).abs()
+    }
+
+    fn height(&self) -> f64 {
+        (self.y1 - self.y0).abs()
+    }
+}
+
+/// A region of a rendered page in pixels, from its top left corner
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct PixelRect {
+    pub(crate) x: u32,
+    pub(crate) y: u32,
+    pub(crate) width: u32,
+    pub(crate) height: u32,
+}
+
+/// One page's boxes and rotation, from `pdfinfo -box`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct PageBoxes {
+    pub(crate) media: PdfRect,
+    pub(crate) crop: PdfRect,
+    pub(crate) trim: PdfRect,
+    /// The page's own /Rotate, clockwise degrees
+    pub(crate) rotation: u16,
+}
+
+impl PageBoxes {
+    fn get(&self, page_box: PageBox) -> PdfRect {
+        match page_box {
+            PageBox::Media => self.media,
+            PageBox::Crop => self.crop,
+            PageBox::Trim => self.trim,
+        }
+    }
+
+    /// Size of `page_box` in points as pdftoppm renders it, i.e. rotated
+    pub(crate) fn rendered_size(&self, page_box: PageBox) -> (f64, f64) {
+        let rect = self.get(page_box);
+        match self.rotation % 180 {
+            90 => (rect.height(), rect.width()),
+            _ => (rect.width(), rect.height()),
+        }
+    }
+
+    /// Where the trim box lies in the media box rendered at `dpi`
+    pub(crate) fn trim_pixels(&self, dpi: u16) -> PixelRect {
+        let (media, trim) = (self.media, self.trim);
+        let (page_width, page_height) = (media.width(), media.height());
+        // Unrotated, from the top left: PDF y grows upwards
+        let (left, top) = (trim.x0 - media.x0, media.y1 - trim.y1);
+        let (width, height) = (trim.width(), trim.height());
+        let (x, y, width, height) = match self.rotation % 360 {
+            90 => (page_height - top - height, left, height, width),
+            180 => (
+                page_width - left - width,
+                page_height - top - height,
+                width,
+                height,
+            ),
+            270 => (top, page_width - left - width, height, width),
+            _ => (left, top, width, height),
+        };
+        let pixels = |points: f64| (points.max(0.0) * dpi as f64 / POINTS_PER_INCH).round() as u32;
+        PixelRect {
+            x: pixels(x),
+            y: pixels(y),
+            width: pixels(width).max(1),
+            height: pixels(height).max(1),
+        }
+    }
+}
+
+/// Boxes of pages 1 to `page_count`, from `backend`
+pub(crate) fn page_boxes(
+    pdf_path: &Path,
+    page_count: u32,
+    backend: Backend,
+) -> Result<Vec<PageBoxes>> {
+    match backend {
+        Backend::Poppler => get_page_boxes(pdf_path, page_count),
+        #[cfg(feature = "pdfium")]
+        Backend::Pdfium => super::pdfium::page_boxes(pdf_path, page_count),
+        #[cfg(not(feature = "pdfium"))]
+        Backend::Pdfium => Err(super::pdfium_missing()),
+    }
+}
+
+/// Boxes of pages 1 to `page_count`, from pdfinfo
+pub(crate) fn get_page_boxes(pdf_path: &Path, page_count: u32) -> Result<Vec<PageBoxes>> {
+    let output = Command::new("pdfinfo")
+        .args(["-box", "-f", "1", "-l", &page_count.to_string()])
+        .arg(pdf_path)
+        .output()
+        .context("Failed to run pdfinfo")?;
+
+    if !output.status.success() {
+        anyhow::bail!("Failed to get PDF page boxes");
+    }
+
+    let boxes = parse_page_boxes(&String::from_utf8_lossy(&output.stdout));
+    if boxes.is_empty() {
+        anyhow::bail!("Could not find page boxes in PDF info");
+    }
+    Ok(boxes)
+}
+
+/// Per-page boxes from `pdfinfo -box -f/-l` lines such as
+/// `Page    1 TrimBox:  9.00  9.00  603.00  783.00`
+///
+/// A missing CropBox is the MediaBox and a missing TrimBox the CropBox, as
+/// in the PDF spec; pages without a MediaBox are left out.
+fn parse_page_boxes(pdfinfo: &str) -> Vec<PageBoxes> {
+    type Found = (Option<PdfRect>, Option<PdfRect>, Option<PdfRect>, u16);
+    let mut pages: BTreeMap<u32, Found> = BTreeMap::new();
+    for line in pdfinfo.lines() {
+        let mut words = line.split_whitespace();
+        if words.next() != Some("Page") {
+            continue;
+        }
+        let (Some(Ok(page)), Some(key)) = (words.next().map(str::parse::<u32>), words.next())
+        else {
+            continue;
+        };
+        let values: Vec<f64> = words.filter_map(|word| word.parse().ok()).collect();
+        let rect = match values[..] {
+            [x0, y0, x1, y1] => Some(PdfRect { x0, y0, x1, y1 }),
+            _ => None,
+        };
+        let found = pages.entry(page).or_default();
+        match key {
+            "MediaBox:" => found.0 = rect,
+            "CropBox:" => found.1 = rect,
+            "TrimBox:" => found.2 = rect,
+            "rot:" => found.3 = values.first().map_or(0, |&rot| rot as u16 % 360),
+            _ => {}
+        }
+    }
+    pages
+        .into_values()
+        .filter_map(|(media, crop, trim, rotation)| {
+            let media = media?;
+            let crop = crop.unwrap_or(media);
+            Some(PageBoxes {
+                media,
+                crop,
+                trim: trim.unwrap_or(crop),
+                rotation,
+            })
+        })
+        .collect()
+}
+
+/// Displayed size in points of every page, from pdfinfo
+fn get_page_sizes(pdf_path: &Path, page_count: u32) -> Result<Vec<(f64, f64)>> {
+    let output = Command::new("pdfinfo")
+        .args(["-f", "1", "-l", &page_count.to_string()])
+        .arg(pdf_path)
+        .output()
+        .context("Failed to run pdfinfo")?;
+
+    if !output.status.success() {
+        anyhow::bail!("Failed to get PDF page sizes");
+    }
+
+    let sizes = parse_page_sizes(&String::from_utf8_lossy(&output.stdout));
+    if sizes.is_empty() {
+        anyhow::bail!("Could not find page sizes in PDF info");
+    }
+    Ok(sizes)
+}
+
+/// Page sizes from pdfinfo output, as `(width, height)` in points
+///
+/// Takes both `Page size: 612 x 792 pts` and, with -f/-l, per-page
+/// `Page    3 size: ...` lines; a following `Page    3 rot:  90` line swaps
+/// that page's width and height, as pdftoppm renders it rotated.
+fn parse_page_sizes(pdfinfo: &str) -> Vec<(f64, f64)> {
+    let mut sizes = Vec::new();
+    for line in pdfinfo.lines() {
+        let Some(rest) = line.strip_prefix("Page") else {
+            continue;
+        };
+        if let Some((_, size)) = rest.split_once("size:") {
+            if let Some(size) = parse_page_size(size) {
+                sizes.push(size);
+            }
+        } else if let Some((_, rotation)) = rest.split_once("rot:")
+            && matches!(rotation.trim().parse::<i32>(), Ok(90 | 270 | -90 | -270))
+            && let Some((width, height)) = sizes.last_mut()
+        {
+            std::mem::swap(width, height);
+        }
+    }
+    sizes
+}
+
+/// `595.28 x 841.89 pts (A4)` as `(595.28, 841.89)`
+fn parse_page_size(size: &str) -> Option<(f64, f64)> {
+    let mut parts = size.split_whitespace();
+    let width = parts.next()?.parse().ok()?;
+    if parts.next()? != "x" {
+        return None;
+    }
+    let height = parts.next()?.parse().ok()?;
+    Some((width, height))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_page_sizes() {
+        assert_eq!(
+            parse_page_sizes("Pages:          1\nPage size:      595.28 x 841.89 pts (A4)\n"),
+            [(595.28, 841.89)]
+        );
+        // Per-page lines from pdfinfo -f/-l; landscape by box or by rotation
+        let info = "Page    1 size: 612 x 792 pts (letter)\n\
+                    Page    1 rot:  0\n\
+                    Page    2 size: 792 x 612 pts (letter)\n\
+                    Page    2 rot:  0\n\
+                    Page    3 size: 612 x 792 pts (letter)\n\
+                    Page    3 rot:  90\n";
+        assert_eq!(
+            parse_page_sizes(info),
+            [(612.0, 792.0), (792.0, 612.0), (792.0, 612.0)]
+        );
+        assert!(parse_page_sizes("Page size: unknown").is_empty());
+    }
+
+    /// Pixels a page `points` long spans at `dpi`
+    fn points_to_pixels(points: f64, dpi: u16) -> u32 {
+        (points * dpi as f64 / POINTS_PER_INCH).round() as u32
+    }
+
+    #[test]
+    fn test_target_dpi() {
+        let letter = [(612.0, 792.0)];
+        // 1600px across 8.5in
+        assert_eq!(target_dpi(&letter, Some(1600), None, None), 188);
+        assert!(points_to_pixels(612.0, 188) <= 1600);
+        assert_eq!(target_dpi(&letter, None, Some(1100), None), 100);
+        // Both targets: the tighter one wins
+        assert_eq!(target_dpi(&letter, Some(1600), Some(1100), None), 100);
+        // Capped by an explicit --dpi
+        assert_eq!(target_dpi(&letter, Some(1600), None, Some(150)), 150);
+
+        // Landscape pages are wider; mixed sizes fit the largest page
+        let a4 = (595.28, 841.89);
+        assert_eq!(target_dpi(&[(841.89, 595.28)], Some(1600), None, None), 136);
+        assert_eq!(
+            target_dpi(&[a4, (841.89, 595.28)], Some(1600), Some(1600), None),
+            136
+        );
+        assert_eq!(points_to_pixels(841.89, 136), 1590);
+
+        // No usable sizes: fall back to the plain DPI
+        assert_eq!(target_dpi(&[], Some(1600), None, None), DEFAULT_DPI);
+    }
+
+    #[test]
+    fn test_parse_page_boxes() {
+        let info = "Page    1 size: 612 x 792 pts (letter)\n\
+                    Page    1 rot:  0\n\
+                    Page    1 MediaBox:     0.00     0.00   612.00   792.00\n\
+                    Page    1 CropBox:      0.00     0.00   612.00   792.00\n\
+                    Page    1 TrimBox:     10.00    20.00   600.00   780.00\n\
+                    Page    2 rot:  90\n\
+                    Page    2 MediaBox:     0.00     0.00   612.00   792.00\n\
+                    Page    2 CropBox:     18.00    18.00   594.00   774.00\n";
+        let boxes = parse_page_boxes(info);
+        assert_eq!(boxes.len(), 2);
+        assert_eq!(boxes[0].rendered_size(PageBox::Trim), (590.0, 760.0));
+        // No TrimBox: the CropBox; rotated a quarter turn
+        assert_eq!(boxes[1].trim, boxes[1].crop);
+        assert_eq!(boxes[1].rendered_size(PageBox::Crop), (756.0, 576.0));
+        assert_eq!(boxes[1].rendered_size(PageBox::Media), (792.0, 612.0));
+        assert!(parse_page_boxes("Pages: 2").is_empty());
+    }
+
+    #[test]
+    fn test_trim_pixels_follow_page_rotation() {
+        let mut boxes = PageBoxes {
+            media: PdfRect {
+                x0: 0.0,
+                y0: 0.0,
+                x1: 612.0,
+                y1: 792.0,
+            },
+            crop: PdfRect {
+                x0: 0.0,
+                y0: 0.0,
+                x1: 612.0,
+                y1: 792.0,
+            },
+            // 10pt from the left, 12pt from the top
+            trim: PdfRect {
+                x0: 10.0,
+                y0: 20.0,
+                x1: 600.0,
+                y1: 780.0,
+            },
+            rotation: 0,
+        };
+        let rect = |x, y, width, height| PixelRect {
+            x,
+            y,
+            width,
+            height,
+        };
+        assert_eq!(boxes.trim_pixels(72), rect(10, 12, 590, 760));
+        assert_eq!(boxes.trim_pixels(144), rect(20, 24, 1180, 1520));
+        boxes.rotation = 90;
+        assert_eq!(boxes.trim_pixels(72), rect(20, 10, 760, 590));
+        boxes.rotation = 180;
+        assert_eq!(boxes.trim_pixels(72), rect(12, 20, 590, 760));
+        boxes.rotation = 270;
+        assert_eq!(boxes.trim_pixels(72), rect(12, 12, 760, 590));
+    }
+}
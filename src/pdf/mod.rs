@@ -0,0 +1,629 @@
+//! Converting PDF pages to images with poppler or pdfium
+//!
+//! [`convert_pdf`] renders the pages of a PDF into a directory as
+//! `[prefix_]001.jpg`, `002.jpg`, ... and reports the files it wrote; the
+//! `pdf2jpg` binary is a front end to it. With [`Backend::Poppler`], poppler's
+//! `pdftoppm` and `pdfinfo` must be on the `PATH` ([`check_poppler_tool`]);
+//! [`Backend::Pdfium`] needs the `pdfium` feature and the pdfium library.
+//!
+//! ```no_run
+//! use std::path::Path;
+//! use swiss_knife::pdf::{convert_pdf, ConversionOptions, PageEvent};
+//!
+//! let options = ConversionOptions {
+//!     dpi: Some(200),
+//!     pages: Some(vec![1, 2, 3]),
+//!     prefix: Some("doc".to_string()),
+//!     ..Default::default()
+//! };
+//! let report = convert_pdf(
+//!     Path::new("document.pdf"),
+//!     Path::new("images"),
+//!     &options,
+//!     |event| {
+//!         if let PageEvent::Rendered { done, total } = event {
+//!             eprintln!("{}/{} pages", done, total);
+//!         }
+//!     },
+//! )?;
+//! for page in &report.pages {
+//!     // images/doc_001.jpg: 1700x2200, 412345 bytes
+//!     println!(
+//!         "{}: {}x{}, {} bytes",
+//!         page.path.display(),
+//!         page.width,
+//!         page.height,
+//!         page.bytes
+//!     );
+//! }
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+
+mod info;
+#[cfg(feature = "pdfium")]
+mod pdfium;
+mod render;
+mod transform;
+
+pub use info::{check_poppler_tool, count_pages, get_page_count, output_page_sizes};
+pub use render::{output_digits, output_name};
+pub use transform::save_image;
+
+use anyhow::{Context, Result};
+use render::RenderPlan;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// DPI used when neither a DPI nor a size target is given
+pub const DEFAULT_DPI: u16 = 150;
+
+/// PDF user space units per inch
+pub const POINTS_PER_INCH: f64 = 72.0;
+
+/// File format of the rendered pages
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ImageFormat {
+    #[default]
+    Jpeg,
+    /// Lossless; the quality setting doesn't apply
+    Png,
+}
+
+impl ImageFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Jpeg => "jpg",
+            Self::Png => "png",
+        }
+    }
+}
+
+/// What renders the pages
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Backend {
+    /// poppler's pdftoppm and pdfinfo
+    #[default]
+    Poppler,
+    /// pdfium, loaded in-process (needs the `pdfium` feature)
+    Pdfium,
+}
+
+impl Backend {
+    /// Whether this build can render with the backend
+    pub fn is_compiled_in(self) -> bool {
+        match self {
+            Self::Poppler => true,
+            Self::Pdfium => cfg!(feature = "pdfium"),
+        }
+    }
+
+    /// Whether the backend's tools or library are installed
+    pub fn is_installed(self) -> bool {
+        match self {
+            Self::Poppler => {
+                check_poppler_tool("pdftoppm").is_ok() && check_poppler_tool("pdfinfo").is_ok()
+            }
+            #[cfg(feature = "pdfium")]
+            Self::Pdfium => pdfium::is_available(),
+            #[cfg(not(feature = "pdfium"))]
+            Self::Pdfium => false,
+        }
+    }
+
+    /// Fails unless this build can render with the backend
+    fn check_compiled_in(self) -> Result<()> {
+        if !self.is_compiled_in() {
+            return Err(pdfium_missing());
+        }
+        Ok(())
+    }
+}
+
+/// The error for asking a build without the `pdfium` feature for pdfium
+fn pdfium_missing() -> anyhow::Error {
+    anyhow::anyhow!("pdf2jpg was built without pdfium; rebuild it with `--features pdfium`")
+}
+
+/// Which PDF box is rendered
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum PageBox {
+    /// The whole sheet, including any printer marks
+    #[default]
+    Media,
+    /// The region viewers display
+    Crop,
+    /// The finished page, after trimming
+    Trim,
+}
+
+/// How [`convert_pdf`] renders and names pages
+///
+/// Each page is rendered from its `crop_box` region, rotated by `rotate`,
+/// and written as `[prefix_]<page>.<ext>`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConversionOptions {
+    /// Rendering DPI; with `max_width`/`max_height`, the highest they may
+    /// pick (default: [`DEFAULT_DPI`])
+    pub dpi: Option<u16>,
+    /// Render at the DPI that makes the widest page this many pixels wide
+    pub max_width: Option<u32>,
+    /// Render at the DPI that makes the tallest page this many pixels high
+    pub max_height: Option<u32>,
+    /// JPEG quality, 1-100
+    pub quality: u8,
+    pub format: ImageFormat,
+    /// Pages to render, counting from 1 (default: all of them)
+    pub pages: Option<Vec<u32>>,
+    /// File name prefix: `doc` gives `doc_001.jpg`, ...
+    pub prefix: Option<String>,
+    /// Zero-padded width of page numbers in file names (default: 3, or
+    /// wider when the page count needs it)
+    pub digits: Option<u8>,
+    /// Added to page numbers in file names, to carry on a sequence: with
+    /// 45, page 1 is written as `046.jpg`
+    pub number_offset: u32,
+    /// pdftoppm processes rendering contiguous page ranges at once
+    pub jobs: u32,
+    pub crop_box: PageBox,
+    /// Clockwise degrees: 0, 90, 180 or 270
+    pub rotate: u16,
+    /// After a failure, leave the pages rendered so far instead of
+    /// removing them
+    pub keep_partial: bool,
+    pub backend: Backend,
+}
+
+impl Default for ConversionOptions {
+    fn default() -> Self {
+        Self {
+            dpi: None,
+            max_width: None,
+            max_height: None,
+            quality: 85,
+            format: ImageFormat::Jpeg,
+            pages: None,
+            prefix: None,
+            digits: None,
+            number_offset: 0,
+            jobs: 1,
+            crop_box: PageBox::Media,
+            rotate: 0,
+            keep_partial: false,
+            backend: Backend::Poppler,
+        }
+    }
+}
+
+impl ConversionOptions {
+    /// Whether `max_width` or `max_height` picks the DPI
+    pub fn is_size_targeted(&self) -> bool {
+        self.max_width.is_some() || self.max_height.is_some()
+    }
+
+    fn validate(&self) -> Result<()> {
+        if !(1..=100).contains(&self.quality) {
+            anyhow::bail!("JPEG quality must be 1-100, not {}", self.quality);
+        }
+        if self.dpi == Some(0) || self.max_width == Some(0) || self.max_height == Some(0) {
+            anyhow::bail!("DPI and size targets must be at least 1");
+        }
+        if !matches!(self.rotate, 0 | 90 | 180 | 270) {
+            anyhow::bail!(
+                "Rotation must be 0, 90, 180 or 270 degrees, not {}",
+                self.rotate
+            );
+        }
+        self.backend.check_compiled_in()
+    }
+
+    /// `pages` of a `page_count` page PDF, ascending and without repeats
+    fn pages_of(&self, page_count: u32) -> Result<Vec<u32>> {
+        let Some(pages) = &self.pages else {
+            return Ok((1..=page_count).collect());
+        };
+        let mut pages = pages.clone();
+        pages.sort_unstable();
+        pages.dedup();
+        if let Some(&page) = pages.iter().find(|&&page| page == 0 || page > page_count) {
+            anyhow::bail!(
+                "Page {} is out of range: the PDF has {} page{}",
+                page,
+                page_count,
+                if page_count == 1 { "" } else { "s" }
+            );
+        }
+        Ok(pages)
+    }
+}
+
+/// Progress of a [`convert_pdf`] call, in the order they happen
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PageEvent {
+    /// Rendering is about to start: `pages` of the PDF's `page_count`, at
+    /// `dpi`
+    Started {
+        page_count: u32,
+        pages: usize,
+        dpi: u16,
+    },
+    /// `done` of the `total` pages are rendered
+    Rendered { done: usize, total: usize },
+    /// A page's file is complete, after every page is rendered
+    Written(ConvertedPage),
+}
+
+/// A page image [`convert_pdf`] wrote
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConvertedPage {
+    /// Page number in the PDF, counting from 1
+    pub page: u32,
+    pub path: PathBuf,
+    /// Size in pixels
+    pub width: u32,
+    pub height: u32,
+    /// File size
+    pub bytes: u64,
+}
+
+/// What [`convert_pdf`] did
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConversionReport {
+    /// Pages in the PDF, rendered or not
+    pub page_count: u32,
+    /// DPI the pages were rendered at
+    pub dpi: u16,
+    /// Pages written, in page order
+    pub pages: Vec<ConvertedPage>,
+}
+
+/// Render pages of `input` into `output_dir`, created if needed, as
+/// described by `options`; `progress` hears about each step
+///
+/// Existing files with the output names are replaced. On failure the
+/// error names the pages that had been rendered, and those are removed
+/// unless `options.keep_partial`.
+pub fn convert_pdf(
+    input: &Path,
+    output_dir: &Path,
+    options: &ConversionOptions,
+    progress: impl Fn(PageEvent),
+) -> Result<ConversionReport> {
+    options.validate()?;
+    let page_count = count_pages(input, options.backend)?;
+    let digits = output_digits(page_count + options.number_offset, options.digits)?;
+    let pages = options.pages_of(page_count)?;
+    if pages.is_empty() {
+        return Ok(ConversionReport {
+            page_count,
+            dpi: options.dpi.unwrap_or(DEFAULT_DPI),
+            pages: Vec::new(),
+        });
+    }
+
+    fs::create_dir_all(output_dir).with_context(|| {
+        format!(
+            "Failed to create output directory: {}",
+            output_dir.display()
+        )
+    })?;
+    let dpi = info::resolve_dpi(input, page_count, options)?;
+    let boxes = match options.crop_box {
+        PageBox::Trim => info::page_boxes(input, page_count, options.backend)?,
+        _ => Vec::new(),
+    };
+    let plan = RenderPlan {
+        page_count,
+        pages,
+        dpi,
+        digits,
+        offset: options.number_offset,
+        format: options.format,
+        boxes,
+    };
+
+    let total = plan.pages.len();
+    progress(PageEvent::Started {
+        page_count,
+        pages: total,
+        dpi,
+    });
+    let rendered = |done| progress(PageEvent::Rendered { done, total });
+    let pages = match options.backend {
+        Backend::Poppler => render::render_pages(input, output_dir, &plan, options, &rendered)?,
+        #[cfg(feature = "pdfium")]
+        Backend::Pdfium => pdfium::render_pages(input, output_dir, &plan, options, &rendered)?,
+        #[cfg(not(feature = "pdfium"))]
+        Backend::Pdfium => return Err(pdfium_missing()),
+    };
+    for page in &pages {
+        progress(PageEvent::Written(page.clone()));
+    }
+    Ok(ConversionReport {
+        page_count,
+        dpi,
+        pages,
+    })
+}
+
+/// Render page `page` of `input` at `options.dpi` (ignoring any size
+/// target), cropped and rotated like [`convert_pdf`] pages, as an image;
+/// pdftoppm's file is written to `scratch_dir` and removed (pdfium renders
+/// in memory)
+pub fn render_page(
+    input: &Path,
+    page: u32,
+    options: &ConversionOptions,
+    scratch_dir: &Path,
+) -> Result<image::DynamicImage> {
+    // Keeps pages rendered at the same time, e.g. by other threads, apart
+    static RENDERED: AtomicUsize = AtomicUsize::new(0);
+
+    options.validate()?;
+    #[cfg(feature = "pdfium")]
+    if options.backend == Backend::Pdfium {
+        return pdfium::render_page(input, page, options);
+    }
+    let dpi = options.dpi.unwrap_or(DEFAULT_DPI);
+    let trim = match options.crop_box {
+        PageBox::Trim => info::get_page_boxes(input, page)?
+            .get(page as usize - 1)
+            .map(|boxes| boxes.trim_pixels(dpi)),
+        _ => None,
+    };
+    let root = scratch_dir.join(format!(
+        ".pdf2jpg-{}-page-{}",
+        std::process::id(),
+        RENDERED.fetch_add(1, Ordering::Relaxed)
+    ));
+    let rendered = root.with_extension(options.format.extension());
+    let output = Command::new("pdftoppm")
+        .args(render::pdftoppm_format_args(
+            options.format,
+            options.quality,
+        ))
+        .args([
+            "-r",
+            &dpi.to_string(),
+            "-f",
+            &page.to_string(),
+            "-l",
+            &page.to_string(),
+            "-singlefile",
+        ])
+        .args(render::pdftoppm_box_args(options.crop_box))
+        .arg(input)
+        .arg(&root)
+        .output()
+        .context("Failed to run pdftoppm. Make sure poppler is installed (brew install poppler)")?;
+    if !output.status.success() {
+        let _ = fs::remove_file(&rendered);
+        anyhow::bail!(
+            "pdftoppm failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    let image = image::open(&rendered);
+    let _ = fs::remove_file(&rendered);
+    let image =
+        image.with_context(|| format!("Failed to read rendered page {}", rendered.display()))?;
+    Ok(transform::transform_page(image, trim, options.rotate))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    /// Two pages, 200x100 points each
+    fn fixture() -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/two-pages.pdf")
+    }
+
+    /// Whether the poppler tools convert_pdf runs are installed
+    fn has_poppler() -> bool {
+        let found = check_poppler_tool("pdftoppm").is_ok() && check_poppler_tool("pdfinfo").is_ok();
+        if !found {
+            eprintln!("poppler not installed; skipping");
+        }
+        found
+    }
+
+    #[test]
+    fn test_default_options() {
+        let options = ConversionOptions::default();
+        assert_eq!(options.quality, 85);
+        assert_eq!(options.jobs, 1);
+        assert_eq!(options.format, ImageFormat::Jpeg);
+        assert!(!options.is_size_targeted());
+        assert!(options.validate().is_ok());
+    }
+
+    #[test]
+    fn test_invalid_options_fail_before_rendering() {
+        let dir = tempfile::tempdir().unwrap();
+        let output_dir = dir.path().join("pages");
+        for options in [
+            ConversionOptions {
+                quality: 0,
+                ..Default::default()
+            },
+            ConversionOptions {
+                dpi: Some(0),
+                ..Default::default()
+            },
+            ConversionOptions {
+                rotate: 45,
+                ..Default::default()
+            },
+        ] {
+            assert!(convert_pdf(&fixture(), &output_dir, &options, |_| {}).is_err());
+        }
+        assert!(!output_dir.exists());
+    }
+
+    #[test]
+    fn test_pages_of() {
+        let all = ConversionOptions::default();
+        assert_eq!(all.pages_of(3).unwrap(), [1, 2, 3]);
+        let some = ConversionOptions {
+            pages: Some(vec![3, 1, 3]),
+            ..Default::default()
+        };
+        assert_eq!(some.pages_of(3).unwrap(), [1, 3]);
+        let message = some.pages_of(2).unwrap_err().to_string();
+        assert!(message.contains("Page 3 is out of range"), "{}", message);
+        let zero = ConversionOptions {
+            pages: Some(vec![0]),
+            ..Default::default()
+        };
+        assert!(zero.pages_of(2).is_err());
+    }
+
+    #[test]
+    fn test_convert_pdf() {
+        if !has_poppler() {
+            return;
+        }
+        let dir = tempfile::tempdir().unwrap();
+        let output_dir = dir.path().join("pages");
+        let options = ConversionOptions {
+            dpi: Some(72),
+            ..Default::default()
+        };
+        let events = RefCell::new(Vec::new());
+        let report = convert_pdf(&fixture(), &output_dir, &options, |event| {
+            events.borrow_mut().push(event)
+        })
+        .unwrap();
+
+        assert_eq!((report.page_count, report.dpi), (2, 72));
+        assert_eq!(report.pages.len(), 2);
+        for (page, converted) in (1..).zip(&report.pages) {
+            assert_eq!(converted.page, page);
+            assert_eq!(converted.path, output_dir.join(format!("00{}.jpg", page)));
+            assert_eq!((converted.width, converted.height), (200, 100));
+            assert_eq!(
+                converted.bytes,
+                fs::metadata(&converted.path).unwrap().len()
+            );
+        }
+
+        let events = events.into_inner();
+        assert_eq!(
+            events[0],
+            PageEvent::Started {
+                page_count: 2,
+                pages: 2,
+                dpi: 72
+            }
+        );
+        assert!(events.contains(&PageEvent::Rendered { done: 2, total: 2 }));
+        assert_eq!(
+            events[events.len() - 1],
+            PageEvent::Written(report.pages[1].clone())
+        );
+    }
+
+    #[test]
+    fn test_convert_pdf_pages_as_png() {
+        if !has_poppler() {
+            return;
+        }
+        let dir = tempfile::tempdir().unwrap();
+        let options = ConversionOptions {
+            max_width: Some(50),
+            format: ImageFormat::Png,
+            pages: Some(vec![2]),
+            prefix: Some("doc".to_string()),
+            number_offset: 10,
+            rotate: 90,
+            ..Default::default()
+        };
+        let report = convert_pdf(&fixture(), dir.path(), &options, |_| {}).unwrap();
+
+        // Rotated, the 100pt edge is the width: 50px at 36 DPI
+        assert_eq!(report.dpi, 36);
+        assert_eq!(report.pages.len(), 1);
+        let page = &report.pages[0];
+        assert_eq!(page.page, 2);
+        assert_eq!(page.path, dir.path().join("doc_012.png"));
+        assert_eq!((page.width, page.height), (50, 100));
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[cfg(not(feature = "pdfium"))]
+    #[test]
+    fn test_pdfium_needs_the_feature() {
+        let dir = tempfile::tempdir().unwrap();
+        let options = ConversionOptions {
+            backend: Backend::Pdfium,
+            ..Default::default()
+        };
+        let err = convert_pdf(&fixture(), dir.path(), &options, |_| {}).unwrap_err();
+        assert!(err.to_string().contains("--features pdfium"), "{}", err);
+        assert!(count_pages(&fixture(), Backend::Pdfium).is_err());
+    }
+
+    #[cfg(feature = "pdfium")]
+    #[test]
+    fn test_pdfium_matches_poppler() {
+        if !has_poppler() {
+            return;
+        }
+        if !Backend::Pdfium.is_installed() {
+            eprintln!("pdfium library not found; skipping");
+            return;
+        }
+        let dir = tempfile::tempdir().unwrap();
+        let convert = |backend, crop_box, rotate| {
+            let options = ConversionOptions {
+                dpi: Some(72),
+                crop_box,
+                rotate,
+                backend,
+                ..Default::default()
+            };
+            let output_dir = dir
+                .path()
+                .join(format!("{:?}-{:?}-{}", backend, crop_box, rotate));
+            convert_pdf(&fixture(), &output_dir, &options, |_| {}).unwrap()
+        };
+
+        for (crop_box, rotate) in [(PageBox::Media, 0), (PageBox::Trim, 90)] {
+            let poppler = convert(Backend::Poppler, crop_box, rotate);
+            let pdfium = convert(Backend::Pdfium, crop_box, rotate);
+            assert_eq!(pdfium.page_count, poppler.page_count);
+            assert_eq!(pdfium.pages.len(), poppler.pages.len());
+            for (pdfium, poppler) in pdfium.pages.iter().zip(&poppler.pages) {
+                assert_eq!(pdfium.page, poppler.page);
+                assert_eq!(pdfium.path.file_name(), poppler.path.file_name());
+                // The rasterizers round page boxes differently
+                assert!(pdfium.width.abs_diff(poppler.width) <= 2, "{:?}", pdfium);
+                assert!(pdfium.height.abs_diff(poppler.height) <= 2, "{:?}", pdfium);
+            }
+        }
+        assert_eq!(
+            output_page_sizes(&fixture(), 2, PageBox::Crop, 0, Backend::Pdfium).unwrap(),
+            output_page_sizes(&fixture(), 2, PageBox::Crop, 0, Backend::Poppler).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_render_page() {
+        if !has_poppler() {
+            return;
+        }
+        let dir = tempfile::tempdir().unwrap();
+        let options = ConversionOptions {
+            dpi: Some(144),
+            ..Default::default()
+        };
+        let image = render_page(&fixture(), 1, &options, dir.path()).unwrap();
+        assert_eq!((image.width(), image.height()), (400, 200));
+        // The scratch file is gone
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+}
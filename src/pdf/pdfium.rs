@@ -0,0 +1,209 @@
+//! Rendering with pdfium, loaded in-process, for when poppler isn't installed
+//!
+//! The pdfium shared library is bound at runtime: `PDFIUM_LIB_PATH` names
+//! the library file or the directory holding it, else the system's library
+//! search path is used.
+
+use super::info::{PageBoxes, PdfRect};
+use super::render::{abandon_render, converted_pages, output_name, RenderPlan};
+use super::transform::{save_image, transform_page};
+use super::{ConversionOptions, ConvertedPage, PageBox, DEFAULT_DPI, POINTS_PER_INCH};
+use anyhow::{Context, Result};
+use pdfium_render::prelude::*;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+/// The bound library, or why it couldn't be loaded; bindings can only be
+/// made once per process
+fn library() -> Result<&'static Pdfium> {
+    static PDFIUM: OnceLock<Result<Pdfium, String>> = OnceLock::new();
+    PDFIUM
+        .get_or_init(|| {
+            let bindings = match env::var_os("PDFIUM_LIB_PATH").map(PathBuf::from) {
+                Some(path) if path.is_dir() => {
+                    Pdfium::bind_to_library(Pdfium::pdfium_platform_library_name_at_path(&path))
+                }
+                Some(path) => Pdfium::bind_to_library(path),
+                None => Pdfium::bind_to_system_library(),
+            };
+            bindings.map(Pdfium::new).map_err(|e| {
+                format!(
+                    "Failed to load the pdfium library ({:?}); install it on the library \
+                     path or point PDFIUM_LIB_PATH at it",
+                    e
+                )
+            })
+        })
+        .as_ref()
+        .map_err(|e| anyhow::anyhow!("{}", e))
+}
+
+/// Whether the pdfium library can be loaded
+pub(crate) fn is_available() -> bool {
+    library().is_ok()
+}
+
+fn open(pdf_path: &Path) -> Result<PdfDocument<'static>> {
+    library()?
+        .load_pdf_from_file(pdf_path, None)
+        .map_err(|e| anyhow::anyhow!("{:?}", e))
+        .with_context(|| format!("Failed to open {}", pdf_path.display()))
+}
+
+fn page_of<'a>(document: &PdfDocument<'a>, page: u32) -> Result<PdfPage<'a>> {
+    document
+        .pages()
+        .get((page - 1) as PdfPageIndex)
+        .map_err(|e| anyhow::anyhow!("Failed to load page {}: {:?}", page, e))
+}
+
+/// Get the number of pages in a PDF
+pub(crate) fn page_count(pdf_path: &Path) -> Result<u32> {
+    Ok(open(pdf_path)?.pages().len() as u32)
+}
+
+/// Boxes of pages 1 to `page_count`; a missing CropBox is the MediaBox and a
+/// missing TrimBox the CropBox, as with pdfinfo
+pub(crate) fn page_boxes(pdf_path: &Path, page_count: u32) -> Result<Vec<PageBoxes>> {
+    let document = open(pdf_path)?;
+    (1..=page_count)
+        .map(|page| {
+            let pdf_page = page_of(&document, page)?;
+            let boundaries = pdf_page.boundaries();
+            let media = boundaries
+                .media()
+                .map(|media| pdf_rect(media.bounds))
+                .map_err(|e| anyhow::anyhow!("Page {} has no media box: {:?}", page, e))?;
+            let crop = boundaries
+                .crop()
+                .map_or(media, |crop| pdf_rect(crop.bounds));
+            let trim = boundaries.trim().map_or(crop, |trim| pdf_rect(trim.bounds));
+            let rotation = match pdf_page.rotation() {
+                Ok(PdfPageRenderRotation::Degrees90) => 90,
+                Ok(PdfPageRenderRotation::Degrees180) => 180,
+                Ok(PdfPageRenderRotation::Degrees270) => 270,
+                _ => 0,
+            };
+            Ok(PageBoxes {
+                media,
+                crop,
+                trim,
+                rotation,
+            })
+        })
+        .collect()
+}
+
+fn pdf_rect(rect: pdfium_render::prelude::PdfRect) -> PdfRect {
+    PdfRect {
+        x0: rect.left().value as f64,
+        y0: rect.bottom().value as f64,
+        x1: rect.right().value as f64,
+        y1: rect.top().value as f64,
+    }
+}
+
+/// Page `page` of `document` at `dpi`, from the `page_box` region as
+/// pdftoppm renders it: trim renders the media box, to be cropped afterwards
+fn render(
+    document: &PdfDocument,
+    page: u32,
+    dpi: u16,
+    page_box: PageBox,
+) -> Result<image::DynamicImage> {
+    let mut pdf_page = page_of(document, page)?;
+    // pdfium draws the crop box; widening it only changes the loaded copy
+    if page_box != PageBox::Crop
+        && let Ok(media) = pdf_page.boundaries().media()
+    {
+        pdf_page
+            .boundaries_mut()
+            .set_crop(media.bounds)
+            .map_err(|e| anyhow::anyhow!("Failed to select the media box: {:?}", e))?;
+    }
+    // Rounded up like pdftoppm does
+    let pixels = |points: PdfPoints| {
+        ((points.value as f64 * dpi as f64 / POINTS_PER_INCH).ceil() as i32).max(1)
+    };
+    let bitmap = pdf_page
+        .render(pixels(pdf_page.width()), pixels(pdf_page.height()), None)
+        .map_err(|e| anyhow::anyhow!("Failed to render page {}: {:?}", page, e))?;
+    bitmap
+        .as_image()
+        .map_err(|e| anyhow::anyhow!("Failed to read rendered page {}: {:?}", page, e))
+}
+
+/// Render the planned pages of `pdf_file` into `output_dir` as
+/// `[prefix_]001.jpg`, ..., one after the other; returns them in page order
+///
+/// pdfium is driven from one thread, so `options.jobs` doesn't apply. When
+/// a page fails, the ones written are dealt with as by pdftoppm runs.
+pub(crate) fn render_pages(
+    pdf_file: &Path,
+    output_dir: &Path,
+    plan: &RenderPlan,
+    options: &ConversionOptions,
+    progress: &dyn Fn(usize),
+) -> Result<Vec<ConvertedPage>> {
+    let run_prefix = format!(".pdf2jpg-{}", std::process::id());
+    let mut written = Vec::with_capacity(plan.pages.len());
+    let mut files = Vec::with_capacity(plan.pages.len());
+    let result = (|| {
+        let document = open(pdf_file)?;
+        for &page in &plan.pages {
+            let trim = plan
+                .boxes
+                .get(page as usize - 1)
+                .map(|boxes| boxes.trim_pixels(plan.dpi));
+            let image = render(&document, page, plan.dpi, options.crop_box)?;
+            let name = output_name(
+                options.prefix.as_deref(),
+                page + plan.offset,
+                plan.digits,
+                plan.format,
+            );
+            let path = output_dir.join(&name);
+            save_image(
+                &path,
+                &transform_page(image, trim, options.rotate).into_rgb8(),
+                plan.format,
+                options.quality,
+            )?;
+            written.push((page, path.clone()));
+            files.push((name, fs::metadata(&path).map(|m| m.len()).unwrap_or(0)));
+            progress(written.len());
+        }
+        Ok(())
+    })();
+    if let Err(e) = result {
+        return Err(abandon_render(
+            e,
+            output_dir,
+            &run_prefix,
+            &written,
+            options.keep_partial,
+        ));
+    }
+    converted_pages(output_dir, plan, files)
+        .map_err(|e| abandon_render(e, output_dir, &run_prefix, &written, options.keep_partial))
+}
+
+/// Page `page` of `input` rendered like [`super::render_page`] does with
+/// pdftoppm
+pub(crate) fn render_page(
+    input: &Path,
+    page: u32,
+    options: &ConversionOptions,
+) -> Result<image::DynamicImage> {
+    let dpi = options.dpi.unwrap_or(DEFAULT_DPI);
+    let trim = match options.crop_box {
+        PageBox::Trim => page_boxes(input, page)?
+            .get(page as usize - 1)
+            .map(|boxes| boxes.trim_pixels(dpi)),
+        _ => None,
+    };
+    let image = render(&open(input)?, page, dpi, options.crop_box)?;
+    Ok(transform_page(image, trim, options.rotate))
+}
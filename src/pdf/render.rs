@@ -0,0 +1,719 @@
+//! Running pdftoppm over page ranges and naming what it writes
+
+use super::info::PageBoxes;
+use super::transform::{save_image, transform_page};
+use super::{ConversionOptions, ConvertedPage, ImageFormat, PageBox};
+use anyhow::{Context, Result};
+use rayon::prelude::*;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::time::Duration;
+
+/// Pages of one PDF to render, and how
+pub(crate) struct RenderPlan {
+    pub(crate) page_count: u32,
+    /// Ascending; possibly only some of the pages
+    pub(crate) pages: Vec<u32>,
+    pub(crate) dpi: u16,
+    /// Width of output page numbers
+    pub(crate) digits: usize,
+    /// Added to page numbers in output names
+    pub(crate) offset: u32,
+    pub(crate) format: ImageFormat,
+    /// Every page's boxes, when cropping to the trim box; otherwise empty
+    pub(crate) boxes: Vec<PageBoxes>,
+}
+
+/// Render the planned pages of `pdf_file` into `output_dir` with up to
+/// `options.jobs` concurrent pdftoppm runs, one per contiguous page range,
+/// and rename the pages to `[prefix_]001.jpg`, ...; returns them in page
+/// order
+///
+/// `progress` is told how many pages are rendered so far. When a run fails,
+/// the others are stopped and the pages written are dealt with by
+/// [`abandon_render`].
+pub(crate) fn render_pages(
+    pdf_file: &Path,
+    output_dir: &Path,
+    plan: &RenderPlan,
+    options: &ConversionOptions,
+    progress: &dyn Fn(usize),
+) -> Result<Vec<ConvertedPage>> {
+    let prefix = options.prefix.as_deref();
+    let jobs = options.jobs;
+    // Intermediate names are unique to this process and range, so neither a
+    // concurrent run into the same directory nor another range can clash
+    let run_prefix = format!(".pdf2jpg-{}", std::process::id());
+    let ranges = plan_ranges(&plan.pages, jobs);
+
+    let spawn = |chunk: usize, (first, last): (u32, u32)| {
+        Command::new("pdftoppm")
+            .args(pdftoppm_format_args(plan.format, options.quality))
+            .args([
+                "-r",
+                &plan.dpi.to_string(),
+                "-f",
+                &first.to_string(),
+                "-l",
+                &last.to_string(),
+            ])
+            .args(pdftoppm_box_args(options.crop_box))
+            .arg(pdf_file)
+            .arg(output_dir.join(format!("{}-{}", run_prefix, chunk)))
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .context(
+                "Failed to run pdftoppm. Make sure poppler is installed (brew install poppler)",
+            )
+    };
+    if let Err(e) = run_chunks(
+        &ranges,
+        jobs,
+        spawn,
+        output_dir,
+        &run_prefix,
+        plan.pages.len(),
+        progress,
+    ) {
+        return Err(abandon_render(
+            e,
+            output_dir,
+            &run_prefix,
+            &[],
+            options.keep_partial,
+        ));
+    }
+
+    let mut renamed = Vec::with_capacity(plan.pages.len());
+    let abandon = |e, renamed: &[(u32, PathBuf)]| {
+        abandon_render(e, output_dir, &run_prefix, renamed, options.keep_partial)
+    };
+    let mut files = rename_pages(output_dir, plan, &ranges, &run_prefix, prefix, &mut renamed)
+        .map_err(|e| abandon(e, &renamed))?;
+    if needs_transform(options) {
+        transform_pages(output_dir, plan, &mut files, options).map_err(|e| abandon(e, &renamed))?;
+    }
+    converted_pages(output_dir, plan, files).map_err(|e| abandon(e, &renamed))
+}
+
+/// Pages in `files`, the names and sizes [`rename_pages`] returned for
+/// `plan`, with their pixel sizes
+pub(crate) fn converted_pages(
+    output_dir: &Path,
+    plan: &RenderPlan,
+    files: Vec<(String, u64)>,
+) -> Result<Vec<ConvertedPage>> {
+    plan.pages
+        .iter()
+        .zip(files)
+        .map(|(&page, (name, bytes))| {
+            let path = output_dir.join(name);
+            let (width, height) = image::image_dimensions(&path)
+                .with_context(|| format!("Failed to read image size: {}", path.display()))?;
+            Ok(ConvertedPage {
+                page,
+                path,
+                width,
+                height,
+                bytes,
+            })
+        })
+        .collect()
+}
+
+/// pdftoppm flags writing `format`
+pub(crate) fn pdftoppm_format_args(format: ImageFormat, quality: u8) -> Vec<String> {
+    match format {
+        ImageFormat::Jpeg => vec![
+            "-jpeg".to_string(),
+            "-jpegopt".to_string(),
+            format!("quality={}", quality),
+        ],
+        ImageFormat::Png => vec!["-png".to_string()],
+    }
+}
+
+/// pdftoppm flags selecting `page_box`; trim renders the media box and is
+/// cropped afterwards, as pdftoppm has no trim box option
+pub(crate) fn pdftoppm_box_args(page_box: PageBox) -> &'static [&'static str] {
+    match page_box {
+        PageBox::Crop => &["-cropbox"],
+        PageBox::Media | PageBox::Trim => &[],
+    }
+}
+
+/// Whether rendered pages need decoding again, to crop them to the trim box
+/// or to rotate them
+fn needs_transform(options: &ConversionOptions) -> bool {
+    options.crop_box == PageBox::Trim || options.rotate != 0
+}
+
+/// Apply [`transform_page`] to every page file in `files`, in place, updating
+/// their sizes
+fn transform_pages(
+    output_dir: &Path,
+    plan: &RenderPlan,
+    files: &mut [(String, u64)],
+    options: &ConversionOptions,
+) -> Result<()> {
+    plan.pages
+        .par_iter()
+        .zip(files.par_iter_mut())
+        .try_for_each(|(&page, (name, size))| {
+            let path = output_dir.join(&*name);
+            let trim = plan
+                .boxes
+                .get(page as usize - 1)
+                .map(|boxes| boxes.trim_pixels(plan.dpi));
+            let image =
+                image::open(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+            save_image(
+                &path,
+                &transform_page(image, trim, options.rotate).into_rgb8(),
+                plan.format,
+                options.quality,
+            )?;
+            *size = fs::metadata(&path).map(|m| m.len()).unwrap_or(0);
+            Ok(())
+        })
+}
+
+/// Rename the pages pdftoppm wrote for `plan` to `[prefix_]001.jpg`, ...,
+/// recording each in `renamed` as it goes; returns the file names and sizes
+fn rename_pages(
+    output_dir: &Path,
+    plan: &RenderPlan,
+    ranges: &[(u32, u32)],
+    run_prefix: &str,
+    prefix: Option<&str>,
+    renamed: &mut Vec<(u32, PathBuf)>,
+) -> Result<Vec<(String, u64)>> {
+    let mut converted_files: Vec<(String, u64)> = Vec::new();
+
+    for &page in &plan.pages {
+        let chunk = ranges
+            .iter()
+            .position(|&(first, last)| (first..=last).contains(&page))
+            .unwrap_or_default();
+        let source_path = output_dir.join(pdftoppm_name(
+            &format!("{}-{}", run_prefix, chunk),
+            page,
+            plan.page_count,
+            plan.format,
+        ));
+        if !source_path.exists() {
+            anyhow::bail!(
+                "pdftoppm did not write page {} ({})",
+                page,
+                source_path.display()
+            );
+        }
+
+        // Rename to our preferred format: prefix_001.jpg or just 001.jpg
+        let target_name = output_name(prefix, page + plan.offset, plan.digits, plan.format);
+        let target_path = output_dir.join(&target_name);
+
+        fs::rename(&source_path, &target_path).with_context(|| {
+            format!(
+                "Failed to rename {} to {}",
+                source_path.display(),
+                target_path.display()
+            )
+        })?;
+        renamed.push((page, target_path.clone()));
+
+        let file_size = fs::metadata(&target_path).map(|m| m.len()).unwrap_or(0);
+
+        converted_files.push((target_name, file_size));
+    }
+
+    Ok(converted_files)
+}
+
+/// Deal with what a failed render left behind: the page files of the runs
+/// named `run_prefix` and the pages already `renamed` are removed, unless
+/// `keep_partial`; returns `err` with the pages that had been completed
+pub(crate) fn abandon_render(
+    err: anyhow::Error,
+    output_dir: &Path,
+    run_prefix: &str,
+    renamed: &[(u32, PathBuf)],
+    keep_partial: bool,
+) -> anyhow::Error {
+    let mut completed = intermediate_pages(output_dir, run_prefix);
+    completed.extend(renamed.iter().map(|&(page, _)| page));
+    completed.sort_unstable();
+    completed.dedup();
+
+    if completed.is_empty() {
+        remove_intermediates(output_dir, run_prefix);
+        return err.context("Conversion failed before any page was rendered");
+    }
+    let fate = if keep_partial {
+        format!(
+            "kept in {} (unrenamed ones as {}-*)",
+            output_dir.display(),
+            run_prefix
+        )
+    } else {
+        remove_intermediates(output_dir, run_prefix);
+        for (_, path) in renamed {
+            let _ = fs::remove_file(path);
+        }
+        "removed".to_string()
+    };
+    err.context(format!(
+        "Conversion stopped after rendering page{} {}, {}",
+        if completed.len() == 1 { "" } else { "s" },
+        page_list(&completed),
+        fate
+    ))
+}
+
+/// Pages written so far by the runs named `run_prefix`, ascending
+fn intermediate_pages(output_dir: &Path, run_prefix: &str) -> Vec<u32> {
+    let mut pages: Vec<u32> = intermediates(output_dir, run_prefix)
+        .filter_map(|path| {
+            let stem = path.file_stem()?.to_str()?.to_string();
+            stem.rsplit('-').next()?.parse().ok()
+        })
+        .collect();
+    pages.sort_unstable();
+    pages
+}
+
+/// Ascending pages as ranges: `1-4, 7, 9-10`
+fn page_list(pages: &[u32]) -> String {
+    pages
+        .chunk_by(|a, b| a + 1 == *b)
+        .map(|run| match run {
+            [page] => page.to_string(),
+            _ => format!("{}-{}", run[0], run[run.len() - 1]),
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Width pdftoppm zero-pads page numbers to: that of the document's last
+/// page number, whatever range a run renders
+fn pdftoppm_digits(page_count: u32) -> usize {
+    page_count.max(1).to_string().len()
+}
+
+/// File pdftoppm writes page `page` to, given its `-<n>` root prefix
+fn pdftoppm_name(root: &str, page: u32, page_count: u32, format: ImageFormat) -> String {
+    format!(
+        "{}-{:0width$}.{}",
+        root,
+        page,
+        format.extension(),
+        width = pdftoppm_digits(page_count)
+    )
+}
+
+/// Width of output page numbers: `digits` when given, else at least 3;
+/// fails when `digits` is too narrow for the last page number
+pub fn output_digits(page_count: u32, digits: Option<u8>) -> Result<usize> {
+    let needed = pdftoppm_digits(page_count);
+    match digits {
+        Some(digits) if (digits as usize) < needed => anyhow::bail!(
+            "{} digits are too narrow for {} pages (at least {} needed)",
+            digits,
+            page_count,
+            needed
+        ),
+        Some(digits) => Ok(digits as usize),
+        None => Ok(needed.max(3)),
+    }
+}
+
+/// Final name of page `page`: `[prefix_]<page padded to digits>.<ext>`
+pub fn output_name(prefix: Option<&str>, page: u32, digits: usize, format: ImageFormat) -> String {
+    let ext = format.extension();
+    match prefix {
+        Some(p) => format!("{}_{:0digits$}.{}", p, page, ext),
+        None => format!("{:0digits$}.{}", page, ext),
+    }
+}
+
+/// Split pages `1..=page_count` into at most `jobs` contiguous, inclusive
+/// `(first, last)` ranges whose sizes differ by at most one page
+fn page_ranges(page_count: u32, jobs: u32) -> Vec<(u32, u32)> {
+    let chunks = jobs.clamp(1, page_count.max(1));
+    let (base, extra) = (page_count / chunks, page_count % chunks);
+    let mut ranges = Vec::with_capacity(chunks as usize);
+    let mut first = 1;
+    for chunk in 0..chunks {
+        let len = base + u32::from(chunk < extra);
+        if len == 0 {
+            continue;
+        }
+        ranges.push((first, first + len - 1));
+        first += len;
+    }
+    ranges
+}
+
+/// Split `pages` (ascending) into contiguous, inclusive `(first, last)`
+/// ranges: each run of consecutive pages is cut into pieces of at most
+/// `pages.len() / jobs` pages, rounded up
+fn plan_ranges(pages: &[u32], jobs: u32) -> Vec<(u32, u32)> {
+    let chunk_len = pages.len().div_ceil(jobs.max(1) as usize).max(1) as u32;
+    let mut ranges = Vec::new();
+    for run in pages.chunk_by(|a, b| a + 1 == *b) {
+        let (start, len) = (run[0], run.len() as u32);
+        for (first, last) in page_ranges(len, len.div_ceil(chunk_len)) {
+            ranges.push((start + first - 1, start + last - 1));
+        }
+    }
+    ranges
+}
+
+/// Run pdftoppm on every range, `jobs` at a time, telling `progress` the
+/// page files written whenever more appear; on the first run that exits
+/// unsuccessfully the others are stopped and its error returned
+fn run_chunks(
+    ranges: &[(u32, u32)],
+    jobs: u32,
+    spawn: impl Fn(usize, (u32, u32)) -> Result<Child>,
+    output_dir: &Path,
+    run_prefix: &str,
+    page_total: usize,
+    progress: &dyn Fn(usize),
+) -> Result<()> {
+    let mut reported = 0;
+    let mut pending = ranges.iter().copied().enumerate().peekable();
+    let mut running: Vec<((u32, u32), Child)> = Vec::new();
+    let result = (|| loop {
+        while running.len() < jobs.max(1) as usize
+            && let Some((chunk, range)) = pending.next()
+        {
+            running.push((range, spawn(chunk, range)?));
+        }
+
+        let mut i = 0;
+        while i < running.len() {
+            let Some(status) = running[i]
+                .1
+                .try_wait()
+                .context("Failed to wait for pdftoppm")?
+            else {
+                i += 1;
+                continue;
+            };
+            let ((first, last), mut child) = running.swap_remove(i);
+            if !status.success() {
+                let mut stderr = String::new();
+                if let Some(mut pipe) = child.stderr.take() {
+                    let _ = pipe.read_to_string(&mut stderr);
+                }
+                anyhow::bail!(
+                    "pdftoppm failed on pages {}-{}: {}",
+                    first,
+                    last,
+                    stderr.trim()
+                );
+            }
+        }
+
+        let rendered = count_intermediates(output_dir, run_prefix).min(page_total);
+        if rendered > reported {
+            reported = rendered;
+            progress(rendered);
+        }
+        if running.is_empty() && pending.peek().is_none() {
+            return Ok(());
+        }
+        std::thread::sleep(Duration::from_millis(200));
+    })();
+
+    if result.is_err() {
+        stop_chunks(&mut running);
+    }
+    result
+}
+
+/// Kill the pdftoppm runs still going
+fn stop_chunks(running: &mut Vec<((u32, u32), Child)>) {
+    for (_, mut child) in running.drain(..) {
+        let _ = child.kill();
+        let _ = child.wait();
+    }
+}
+
+/// Page files written so far by the runs named `run_prefix`
+fn count_intermediates(output_dir: &Path, run_prefix: &str) -> usize {
+    intermediates(output_dir, run_prefix).count()
+}
+
+/// Delete the page files written by the runs named `run_prefix`
+fn remove_intermediates(output_dir: &Path, run_prefix: &str) {
+    for path in intermediates(output_dir, run_prefix) {
+        let _ = fs::remove_file(path);
+    }
+}
+
+fn intermediates(output_dir: &Path, run_prefix: &str) -> impl Iterator<Item = PathBuf> {
+    let prefix = format!("{}-", run_prefix);
+    fs::read_dir(output_dir)
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok())
+        .filter(move |entry| {
+            entry
+                .file_name()
+                .to_str()
+                .is_some_and(|name| name.starts_with(&prefix))
+        })
+        .map(|entry| entry.path())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pdf::DEFAULT_DPI;
+
+    #[test]
+    fn test_pdftoppm_digits() {
+        assert_eq!(pdftoppm_digits(9), 1);
+        assert_eq!(pdftoppm_digits(10), 2);
+        assert_eq!(pdftoppm_digits(99), 2);
+        assert_eq!(pdftoppm_digits(100), 3);
+        assert_eq!(pdftoppm_digits(999), 3);
+        assert_eq!(pdftoppm_digits(1000), 4);
+        // Padded to the last page's width, even for a range of early pages
+        assert_eq!(
+            pdftoppm_name(".r-0", 7, 250, ImageFormat::Jpeg),
+            ".r-0-007.jpg"
+        );
+        assert_eq!(pdftoppm_name(".r-0", 7, 9, ImageFormat::Png), ".r-0-7.png");
+    }
+
+    #[test]
+    fn test_output_digits() {
+        for (pages, digits) in [(9, 3), (10, 3), (99, 3), (100, 3), (999, 3), (1000, 4)] {
+            assert_eq!(
+                output_digits(pages, None).unwrap(),
+                digits,
+                "{} pages",
+                pages
+            );
+        }
+        assert_eq!(output_digits(9, Some(1)).unwrap(), 1);
+        assert_eq!(output_digits(1000, Some(5)).unwrap(), 5);
+        assert!(output_digits(1000, Some(3)).is_err());
+
+        assert_eq!(output_name(None, 42, 3, ImageFormat::Jpeg), "042.jpg");
+        assert_eq!(
+            output_name(Some("doc"), 1000, 4, ImageFormat::Jpeg),
+            "doc_1000.jpg"
+        );
+        assert_eq!(output_name(None, 7, 3, ImageFormat::Png), "007.png");
+    }
+
+    #[test]
+    fn test_page_ranges() {
+        assert_eq!(page_ranges(10, 1), [(1, 10)]);
+        assert_eq!(page_ranges(10, 3), [(1, 4), (5, 7), (8, 10)]);
+        assert_eq!(page_ranges(600, 4)[3], (451, 600));
+        // Never more ranges than pages
+        assert_eq!(page_ranges(2, 8), [(1, 1), (2, 2)]);
+        assert_eq!(page_ranges(5, 0), [(1, 5)]);
+    }
+
+    #[test]
+    fn test_plan_ranges() {
+        let all: Vec<u32> = (1..=10).collect();
+        assert_eq!(plan_ranges(&all, 1), [(1, 10)]);
+        assert_eq!(plan_ranges(&all, 3), page_ranges(10, 3));
+        // Gaps left by existing pages split the ranges
+        assert_eq!(plan_ranges(&[2, 3, 4, 8, 9], 1), [(2, 4), (8, 9)]);
+        assert_eq!(
+            plan_ranges(&[1, 2, 3, 4, 9, 10], 3),
+            [(1, 2), (3, 4), (9, 10)]
+        );
+        assert!(plan_ranges(&[], 2).is_empty());
+    }
+
+    /// Plan of a 3 page PDF, with page files for `written` rendered into
+    /// `dir` by a single run named `.pdf2jpg-9`
+    fn rendered_pages(dir: &Path, written: &[u32]) -> RenderPlan {
+        for &page in written {
+            let name = pdftoppm_name(".pdf2jpg-9-0", page, 3, ImageFormat::Jpeg);
+            fs::write(dir.join(name), b"jpg").unwrap();
+        }
+        RenderPlan {
+            page_count: 3,
+            pages: vec![1, 2, 3],
+            dpi: DEFAULT_DPI,
+            digits: 3,
+            offset: 0,
+            format: ImageFormat::Jpeg,
+            boxes: Vec::new(),
+        }
+    }
+
+    fn file_names(dir: &Path) -> Vec<String> {
+        let mut names: Vec<_> = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn test_rename_pages() {
+        let dir = tempfile::tempdir().unwrap();
+        let plan = rendered_pages(dir.path(), &[1, 2, 3]);
+        let mut renamed = Vec::new();
+        let files = rename_pages(
+            dir.path(),
+            &plan,
+            &[(1, 3)],
+            ".pdf2jpg-9",
+            Some("doc"),
+            &mut renamed,
+        )
+        .unwrap();
+        assert_eq!(files[2], ("doc_003.jpg".to_string(), 3));
+        assert_eq!(renamed.len(), 3);
+        assert_eq!(
+            file_names(dir.path()),
+            ["doc_001.jpg", "doc_002.jpg", "doc_003.jpg"]
+        );
+    }
+
+    #[test]
+    fn test_converted_pages_read_image_sizes() {
+        let dir = tempfile::tempdir().unwrap();
+        let plan = rendered_pages(dir.path(), &[]);
+        let mut files = Vec::new();
+        for page in &plan.pages {
+            let name = output_name(None, *page, 3, ImageFormat::Jpeg);
+            image::RgbImage::new(40, 30 + page)
+                .save(dir.path().join(&name))
+                .unwrap();
+            files.push((name, 99));
+        }
+        let pages = converted_pages(dir.path(), &plan, files.clone()).unwrap();
+        assert_eq!(pages[2].page, 3);
+        assert_eq!(pages[2].path, dir.path().join("003.jpg"));
+        assert_eq!(
+            (pages[2].width, pages[2].height, pages[2].bytes),
+            (40, 33, 99)
+        );
+
+        fs::write(dir.path().join("002.jpg"), b"not a jpeg").unwrap();
+        assert!(converted_pages(dir.path(), &plan, files).is_err());
+    }
+
+    #[test]
+    fn test_rename_pages_with_offset() {
+        let dir = tempfile::tempdir().unwrap();
+        let plan = RenderPlan {
+            offset: 45,
+            ..rendered_pages(dir.path(), &[1, 2, 3])
+        };
+        let files = rename_pages(
+            dir.path(),
+            &plan,
+            &[(1, 3)],
+            ".pdf2jpg-9",
+            None,
+            &mut Vec::new(),
+        )
+        .unwrap();
+        assert_eq!(files[0].0, "046.jpg");
+        assert_eq!(file_names(dir.path()), ["046.jpg", "047.jpg", "048.jpg"]);
+    }
+
+    #[test]
+    fn test_failed_render_removes_its_pages() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("notes.txt"), b"mine").unwrap();
+        let plan = rendered_pages(dir.path(), &[1, 2]);
+        let mut renamed = Vec::new();
+        let err = rename_pages(
+            dir.path(),
+            &plan,
+            &[(1, 3)],
+            ".pdf2jpg-9",
+            None,
+            &mut renamed,
+        )
+        .unwrap_err();
+        assert_eq!(renamed.len(), 2);
+
+        let err = abandon_render(err, dir.path(), ".pdf2jpg-9", &renamed, false);
+        let message = format!("{:#}", err);
+        assert!(
+            message.contains("after rendering pages 1-2, removed:"),
+            "{}",
+            message
+        );
+        assert!(message.contains("did not write page 3"), "{}", message);
+        assert_eq!(file_names(dir.path()), ["notes.txt"]);
+    }
+
+    #[test]
+    fn test_failed_render_keeps_partial_pages() {
+        let dir = tempfile::tempdir().unwrap();
+        rendered_pages(dir.path(), &[1, 3]);
+        let err = abandon_render(
+            anyhow::anyhow!("pdftoppm failed on pages 1-3: corrupt"),
+            dir.path(),
+            ".pdf2jpg-9",
+            &[],
+            true,
+        );
+        assert!(format!("{:#}", err).contains("pages 1, 3, kept in"));
+        assert_eq!(
+            file_names(dir.path()),
+            [".pdf2jpg-9-0-1.jpg", ".pdf2jpg-9-0-3.jpg"]
+        );
+
+        // Nothing rendered yet
+        let empty = tempfile::tempdir().unwrap();
+        let err = abandon_render(
+            anyhow::anyhow!("boom"),
+            empty.path(),
+            ".pdf2jpg-9",
+            &[],
+            false,
+        );
+        assert!(err.to_string().contains("before any page was rendered"));
+    }
+
+    #[test]
+    fn test_page_list() {
+        assert_eq!(page_list(&[1, 2, 3, 4, 7, 9, 10]), "1-4, 7, 9-10");
+        assert_eq!(page_list(&[5]), "5");
+        assert_eq!(page_list(&[]), "");
+    }
+
+    #[test]
+    fn test_remove_intermediates_keeps_other_files() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in [
+            ".pdf2jpg-7-0-1.jpg",
+            ".pdf2jpg-7-1-2.jpg",
+            ".pdf2jpg-70-0-1.jpg",
+            "001.jpg",
+        ] {
+            fs::write(dir.path().join(name), b"").unwrap();
+        }
+        assert_eq!(count_intermediates(dir.path(), ".pdf2jpg-7"), 2);
+
+        remove_intermediates(dir.path(), ".pdf2jpg-7");
+        let mut left: Vec<_> = fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        left.sort();
+        assert_eq!(left, [".pdf2jpg-70-0-1.jpg", "001.jpg"]);
+    }
+}
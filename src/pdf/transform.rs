@@ -0,0 +1,123 @@
+//! Work done on rendered pages that pdftoppm can't do itself
+
+use super::info::PixelRect;
+use super::ImageFormat;
+use anyhow::{Context, Result};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+/// A rendered page cropped to `trim`, then rotated clockwise by `rotate`
+/// degrees; resizing, when wanted, comes after
+pub(crate) fn transform_page(
+    image: image::DynamicImage,
+    trim: Option<PixelRect>,
+    rotate: u16,
+) -> image::DynamicImage {
+    let image = match trim {
+        // Clamped to the image; a box outside it leaves the page whole
+        Some(rect) if rect.x < image.width() && rect.y < image.height() => {
+            image.crop_imm(rect.x, rect.y, rect.width, rect.height)
+        }
+        _ => image,
+    };
+    match rotate {
+        90 => image.rotate90(),
+        180 => image.rotate180(),
+        270 => image.rotate270(),
+        _ => image,
+    }
+}
+
+/// Write `image` to `path` as `format`; `quality` applies to JPEG only
+pub fn save_image(
+    path: &Path,
+    image: &image::RgbImage,
+    format: ImageFormat,
+    quality: u8,
+) -> Result<()> {
+    let mut file = BufWriter::new(
+        File::create(path).with_context(|| format!("Failed to create {}", path.display()))?,
+    );
+    match format {
+        ImageFormat::Jpeg => image::codecs::jpeg::JpegEncoder::new_with_quality(&mut file, quality)
+            .encode_image(image),
+        ImageFormat::Png => {
+            image.write_with_encoder(image::codecs::png::PngEncoder::new(&mut file))
+        }
+    }
+    .with_context(|| format!("Failed to encode {}", path.display()))?;
+    file.flush()
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 40x30 image, white but for a red top left corner pixel
+    fn marked_page() -> image::DynamicImage {
+        let mut page = image::RgbImage::from_pixel(40, 30, image::Rgb([255, 255, 255]));
+        page.put_pixel(0, 0, image::Rgb([255, 0, 0]));
+        image::DynamicImage::ImageRgb8(page)
+    }
+
+    #[test]
+    fn test_transform_page_rotates() {
+        for (rotate, size) in [
+            (0, (40, 30)),
+            (90, (30, 40)),
+            (180, (40, 30)),
+            (270, (30, 40)),
+        ] {
+            let page = transform_page(marked_page(), None, rotate);
+            assert_eq!((page.width(), page.height()), size, "{} degrees", rotate);
+        }
+        // Clockwise: the top left corner goes to the top right
+        let page = transform_page(marked_page(), None, 90).into_rgb8();
+        assert_eq!(page.get_pixel(29, 0).0, [255, 0, 0]);
+    }
+
+    #[test]
+    fn test_transform_page_crops_before_rotating() {
+        let trim = PixelRect {
+            x: 0,
+            y: 0,
+            width: 20,
+            height: 10,
+        };
+        let page = transform_page(marked_page(), Some(trim), 90).into_rgb8();
+        assert_eq!(page.dimensions(), (10, 20));
+        assert_eq!(page.get_pixel(9, 0).0, [255, 0, 0]);
+
+        // Clamped to the page; entirely outside it, ignored
+        let wide = PixelRect {
+            x: 30,
+            y: 0,
+            width: 100,
+            height: 100,
+        };
+        assert_eq!(transform_page(marked_page(), Some(wide), 0).width(), 10);
+        let outside = PixelRect { x: 50, ..wide };
+        assert_eq!(transform_page(marked_page(), Some(outside), 0).width(), 40);
+    }
+
+    #[test]
+    fn test_save_image_formats() {
+        let dir = tempfile::tempdir().unwrap();
+        let page = marked_page().into_rgb8();
+        for (format, name) in [
+            (ImageFormat::Jpeg, "page.jpg"),
+            (ImageFormat::Png, "page.png"),
+        ] {
+            let path = dir.path().join(name);
+            save_image(&path, &page, format, 85).unwrap();
+            assert_eq!(image::image_dimensions(&path).unwrap(), (40, 30));
+        }
+        // PNG is lossless
+        let png = image::open(dir.path().join("page.png"))
+            .unwrap()
+            .into_rgb8();
+        assert_eq!(png, page);
+    }
+}
@@ -1,11 +1,20 @@
 use anyhow::{Context, Result};
-use clap::Parser;
-use console::{style, Emoji};
-use indicatif::{ProgressBar, ProgressStyle};
-use std::fs;
-use std::path::PathBuf;
+use clap::{CommandFactory, Parser, ValueEnum};
+use console::style;
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
 use std::process::Command;
 use std::time::Duration;
+use swiss_knife::pdf::{
+    self, Backend, ConversionOptions, ConvertedPage, ImageFormat, PageBox, PageEvent, DEFAULT_DPI,
+    POINTS_PER_INCH,
+};
+use swiss_knife::ui::{self, Emoji};
 
 static DOCUMENT: Emoji<'_, '_> = Emoji("📄 ", "");
 static FOLDER: Emoji<'_, '_> = Emoji("📁 ", "");
@@ -13,6 +22,18 @@ static CHECK: Emoji<'_, '_> = Emoji("✅ ", "");
 static GEAR: Emoji<'_, '_> = Emoji("⚙️  ", "");
 static SPARKLES: Emoji<'_, '_> = Emoji("✨ ", "");
 
+/// White space around and between the pages of a combined image
+const SHEET_MARGIN: u32 = 16;
+
+/// Largest width or height a JPEG can have
+const JPEG_MAX_DIMENSION: u32 = 65_535;
+
+/// JPEG quality when neither --quality nor --preset gives one
+const DEFAULT_QUALITY: u8 = 85;
+
+/// Longest edge of a --thumbnail given without a size
+const DEFAULT_THUMBNAIL_SIZE: &str = "400";
+
 #[derive(Parser)]
 #[command(
     name = "pdf2jpg",
@@ -20,101 +41,709 @@ static SPARKLES: Emoji<'_, '_> = Emoji("✨ ", "");
     author = "Tyr Chen <tyr.chen@gmail.com>",
     about = "Convert PDF files to JPG images",
     long_about = "Convert each page of a PDF file to a separate JPG image. \
-                  Supports custom output directory, JPEG quality, and DPI settings.",
+                  Supports custom output directory, JPEG quality, and DPI settings. \
+                  Several PDFs, or directories of them, are converted in one batch. \
+                  Each page goes through the same steps, in order: render the \
+                  --crop-box region (trim renders the media box, then crops to the \
+                  TrimBox), rotate by --rotate, then resize (--thumbnail). \
+                  --max-width/--max-height pick the DPI from the cropped, rotated size.",
     after_help = "Examples:\n  \
                   pdf2jpg document.pdf                    # Output: 001.jpg, 002.jpg, ...\n  \
                   pdf2jpg document.pdf -o ./images        # Convert to ./images directory\n  \
                   pdf2jpg document.pdf -q 90 -d 200       # High quality, 200 DPI\n  \
-                  pdf2jpg document.pdf --prefix doc       # Output: doc_001.jpg, doc_002.jpg, ...\n\n\
+                  pdf2jpg document.pdf --preset print     # 300 DPI, quality 92\n  \
+                  pdf2jpg document.pdf --preset print -d 240  # The print preset at 240 DPI\n  \
+                  pdf2jpg document.pdf --format png       # Lossless: 001.png, 002.png, ...\n  \
+                  pdf2jpg document.pdf --max-width 1600   # DPI picked for 1600px wide pages\n  \
+                  pdf2jpg document.pdf --prefix doc       # Output: doc_001.jpg, doc_002.jpg, ...\n  \
+                  pdf2jpg document.pdf --digits 4         # Output: 0001.jpg, 0002.jpg, ...\n  \
+                  pdf2jpg scan.pdf --rotate 90            # Turn landscape scans upright\n  \
+                  pdf2jpg print.pdf --crop-box trim       # Without printer marks and bleed\n  \
+                  pdf2jpg book.pdf -j 8                   # Render 8 page ranges in parallel\n  \
+                  pdf2jpg ./invoices -o ./images -j 4     # Batch: 4 PDFs at a time\n  \
+                  pdf2jpg ch1.pdf ch2.pdf --continue-numbering  # One sequence: 001.jpg, ...\n  \
+                  pdf2jpg document.pdf --skip-existing    # Only pages not converted yet\n  \
+                  pdf2jpg document.pdf --overwrite        # Replace pages already there\n  \
+                  pdf2jpg document.pdf --manifest pages.json --with-text  # Sizes and text as JSON\n  \
+                  pdf2jpg document.pdf --with-text --text-files           # Also 001.txt, 002.txt, ...\n  \
+                  pdf2jpg document.pdf --combine grid --labels           # Plus a contact sheet\n  \
+                  pdf2jpg book.pdf --combine vertical --combine-format png --combine-only\n  \
+                  pdf2jpg ./papers -o ./covers --thumbnail # Cover thumbnails: <name>_thumb.jpg\n  \
+                  pdf2jpg document.pdf --backend pdfium   # Render in-process, without poppler\n\n\
                   Output:\n  \
                   For a file named 'test.pdf' with 3 pages (no prefix):\n    \
                   001.jpg\n    \
                   002.jpg\n    \
-                  003.jpg\n\n\
+                  003.jpg\n  \
+                  In batch mode (a directory or several PDFs) each PDF gets a\n  \
+                  subdirectory named after it: test/001.jpg, test/002.jpg, ...\n  \
+                  --combine adds combined.jpg (or [prefix_]combined.png) beside the\n  \
+                  pages. PNG is written a row of pages at a time and suits long\n  \
+                  documents; a JPEG is limited to 65535 pixels each way.\n  \
+                  --thumbnail writes only <name>_thumb.jpg per PDF, straight into\n  \
+                  the output directory, keeping thumbnails that already exist.\n  \
+                  --continue-numbering numbers all PDFs' pages as one sequence in the\n  \
+                  output directory, in argument order: with a 45 page ch1.pdf,\n  \
+                  ch2.pdf starts at 046.jpg. A failed PDF stops the sequence; with\n  \
+                  --keep-going the rest are converted and its numbers left unused\n  \
+                  (rerun with --skip-existing to fill them in). A PDF whose page\n  \
+                  count can't be read takes no numbers.\n  \
+                  Presets (--dpi, --quality and --format override their values):\n    \
+                  screen   96 DPI, quality 75, jpg\n    \
+                  ebook   150 DPI, quality 85, jpg\n    \
+                  print   300 DPI, quality 92, jpg\n    \
+                  archive 300 DPI, png\n  \
+                  With --max-width/--max-height a preset's DPI is the most they pick.\n  \
+                  Existing page files stop the conversion before anything is\n  \
+                  rendered, unless --overwrite or --skip-existing is given.\n\n\
+                  A failed conversion removes the pages it had rendered (see\n  \
+                  --keep-partial) and names them in its error.\n\n\
+                  Exit status:\n  \
+                  0  every PDF converted\n  \
+                  1  a conversion failed\n  \
+                  2  invalid arguments\n\n\
                   Requirements:\n  \
-                  - poppler (install via: brew install poppler)\n\n\
+                  - poppler (install via: brew install poppler); --with-text also\n    \
+                  uses its pdftotext\n  \
+                  - or, for --backend pdfium, a build with --features pdfium and the\n    \
+                  pdfium library on the library path (or PDFIUM_LIB_PATH naming it).\n    \
+                  Without --backend, poppler is used when installed, else pdfium.\n    \
+                  pdfium renders one page at a time: -j only spreads batch PDFs\n\n\
                   For more information: https://github.com/tyrchen/swiss-knife"
 )]
 struct Args {
-    /// PDF file to convert
-    #[arg(value_name = "PDF_FILE")]
-    pdf_file: PathBuf,
+    /// PDF files, or directories of PDFs, to convert
+    #[arg(value_name = "PDF_OR_DIR", required = true)]
+    inputs: Vec<PathBuf>,
 
     /// Output directory (default: current directory)
     #[arg(short, long, value_name = "DIR")]
     output: Option<PathBuf>,
 
-    /// JPEG quality (1-100)
-    #[arg(short, long, default_value = "85", value_parser = clap::value_parser!(u8).range(1..=100))]
-    quality: u8,
+    /// DPI, quality and format for a use case; --dpi, --quality and
+    /// --format override its values
+    #[arg(long, value_enum)]
+    preset: Option<Preset>,
 
-    /// DPI for rendering
-    #[arg(short, long, default_value = "150")]
-    dpi: u16,
+    /// JPEG quality (1-100, default: 85)
+    #[arg(short, long, value_parser = clap::value_parser!(u8).range(1..=100))]
+    quality: Option<u8>,
+
+    /// DPI for rendering (default: 150); with --max-width/--max-height, the
+    /// highest DPI they may pick
+    #[arg(short, long)]
+    dpi: Option<u16>,
+
+    /// Page image format (default: jpg)
+    #[arg(long, value_enum, conflicts_with = "thumbnail")]
+    format: Option<PageFormat>,
+
+    /// Render at the DPI that makes the widest page this many pixels wide
+    #[arg(long, value_name = "PX", value_parser = clap::value_parser!(u32).range(1..))]
+    max_width: Option<u32>,
+
+    /// Render at the DPI that makes the tallest page this many pixels high
+    #[arg(long, value_name = "PX", value_parser = clap::value_parser!(u32).range(1..))]
+    max_height: Option<u32>,
 
     /// Filename prefix (optional, e.g., --prefix doc produces doc_001.jpg)
     #[arg(short, long)]
     prefix: Option<String>,
+
+    /// Zero-padded width of output page numbers (default: 3, or wider when
+    /// the page count needs it)
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u8).range(1..=9))]
+    digits: Option<u8>,
+
+    /// Parallel pdftoppm processes: PDFs at a time in batch mode, otherwise
+    /// contiguous page ranges of the one PDF
+    #[arg(short, long, default_value = "1", value_parser = clap::value_parser!(u16).range(1..))]
+    jobs: u16,
+
+    /// Replace page files left by an earlier conversion
+    #[arg(long, conflicts_with = "skip_existing")]
+    overwrite: bool,
+
+    /// Convert only the pages whose files don't exist yet
+    #[arg(long)]
+    skip_existing: bool,
+
+    /// Write a JSON manifest of the converted pages: file, pixel size, bytes
+    #[arg(long, value_name = "PATH")]
+    manifest: Option<PathBuf>,
+
+    /// Extract each converted page's text with pdftotext into the manifest
+    #[arg(long)]
+    with_text: bool,
+
+    /// Write extracted text beside each page (001.txt, ...) instead of into
+    /// the manifest
+    #[arg(long, requires = "with_text")]
+    text_files: bool,
+
+    /// Also stitch every page into one image: one tall strip or a grid
+    /// contact sheet
+    #[arg(long, value_enum, value_name = "LAYOUT")]
+    combine: Option<CombineLayout>,
+
+    /// Pages per row of a --combine grid
+    #[arg(long, default_value = "4", value_parser = clap::value_parser!(u32).range(1..))]
+    columns: u32,
+
+    /// Number each page of the combined image
+    #[arg(long, requires = "combine")]
+    labels: bool,
+
+    /// Format of the combined image
+    #[arg(long, value_enum, default_value = "jpg", requires = "combine")]
+    combine_format: CombineFormat,
+
+    /// Delete the page images once combined
+    #[arg(long, requires = "combine", conflicts_with = "manifest")]
+    combine_only: bool,
+
+    /// Only render each PDF's first page, scaled to SIZE pixels on its
+    /// longest edge (default: 400), as <name>_thumb.jpg
+    #[arg(
+        long,
+        value_name = "SIZE",
+        num_args = 0..=1,
+        default_missing_value = DEFAULT_THUMBNAIL_SIZE,
+        value_parser = clap::value_parser!(u32).range(1..),
+        conflicts_with_all = [
+            "dpi", "max_width", "max_height", "prefix", "digits", "skip_existing",
+            "manifest", "with_text", "combine",
+        ]
+    )]
+    thumbnail: Option<u32>,
+
+    /// After a failure, leave the pages rendered so far in the output
+    /// directory instead of removing them
+    #[arg(long)]
+    keep_partial: bool,
+
+    /// Number the pages of all PDFs as one sequence, in argument order,
+    /// straight into the output directory: the second PDF carries on where
+    /// the first stopped
+    #[arg(long, conflicts_with = "thumbnail")]
+    continue_numbering: bool,
+
+    /// With --continue-numbering, carry on past a PDF that fails, leaving
+    /// its numbers unused, instead of stopping there
+    #[arg(long, requires = "continue_numbering")]
+    keep_going: bool,
+
+    /// Page region to render
+    #[arg(long, value_enum, default_value = "media", value_name = "BOX")]
+    crop_box: PageBox,
+
+    /// Rotate every page clockwise by 0, 90, 180 or 270 degrees
+    #[arg(long, default_value = "0", value_name = "DEGREES", value_parser = parse_rotation)]
+    rotate: u16,
+
+    /// Renderer (default: poppler when installed, else pdfium when built
+    /// with the pdfium feature)
+    #[arg(long, value_enum)]
+    backend: Option<Backend>,
+
+    /// Replace emoji with plain text (also the default when stdout is
+    /// not a terminal or when the locale is not UTF-8)
+    #[arg(long)]
+    no_emoji: bool,
+}
+
+/// --rotate: a quarter turn multiple
+fn parse_rotation(value: &str) -> Result<u16, String> {
+    match value.parse() {
+        Ok(degrees @ (0 | 90 | 180 | 270)) => Ok(degrees),
+        _ => Err(format!("{} is not 0, 90, 180 or 270", value)),
+    }
+}
+
+/// Rendering settings for a use case
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum Preset {
+    /// 96 DPI, quality 75, jpg: viewing on screen or the web
+    Screen,
+    /// 150 DPI, quality 85, jpg: e-readers and tablets
+    Ebook,
+    /// 300 DPI, quality 92, jpg: printing
+    Print,
+    /// 300 DPI, lossless png: keeping a faithful copy
+    Archive,
+}
+
+impl Preset {
+    fn settings(self) -> Settings {
+        let (dpi, quality, format) = match self {
+            Self::Screen => (96, 75, PageFormat::Jpg),
+            Self::Ebook => (150, 85, PageFormat::Jpg),
+            Self::Print => (300, 92, PageFormat::Jpg),
+            // Quality still applies to a --combine or --thumbnail JPEG
+            Self::Archive => (300, 95, PageFormat::Png),
+        };
+        Settings {
+            dpi: Some(dpi),
+            quality,
+            format,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum PageFormat {
+    /// Lossy, at --quality
+    Jpg,
+    /// Lossless and larger; --quality doesn't apply
+    Png,
+}
+
+impl PageFormat {
+    fn image_format(self) -> ImageFormat {
+        match self {
+            Self::Jpg => ImageFormat::Jpeg,
+            Self::Png => ImageFormat::Png,
+        }
+    }
+}
+
+/// DPI, quality and format the pages are rendered with
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Settings {
+    /// `None` for the default DPI, or whichever meets the size target
+    dpi: Option<u16>,
+    quality: u8,
+    format: PageFormat,
+}
+
+/// Settings from the flags given: each of `dpi`, `quality` and `format`
+/// when given, else the preset's value, else the default
+fn resolve_settings(
+    preset: Option<Preset>,
+    dpi: Option<u16>,
+    quality: Option<u8>,
+    format: Option<PageFormat>,
+) -> Settings {
+    let base = preset.map_or(
+        Settings {
+            dpi: None,
+            quality: DEFAULT_QUALITY,
+            format: PageFormat::Jpg,
+        },
+        Preset::settings,
+    );
+    Settings {
+        dpi: dpi.or(base.dpi),
+        quality: quality.unwrap_or(base.quality),
+        format: format.unwrap_or(base.format),
+    }
+}
+
+impl Args {
+    fn settings(&self) -> Settings {
+        resolve_settings(self.preset, self.dpi, self.quality, self.format)
+    }
+
+    /// The renderer; `main` resolves --backend before anything is converted
+    fn backend(&self) -> Backend {
+        self.backend.unwrap_or_default()
+    }
+}
+
+/// The renderer for --backend `requested`: poppler when it's installed,
+/// falling back to pdfium when this build has it
+fn resolve_backend(requested: Option<Backend>, poppler_installed: bool) -> Result<Backend> {
+    let pdfium = Backend::Pdfium.is_compiled_in();
+    match requested {
+        Some(Backend::Pdfium) if !pdfium => {
+            anyhow::bail!("--backend pdfium needs a build with `--features pdfium`")
+        }
+        Some(backend @ Backend::Pdfium) => Ok(backend),
+        Some(Backend::Poppler) | None if poppler_installed => Ok(Backend::Poppler),
+        None if pdfium => Ok(Backend::Pdfium),
+        _ => anyhow::bail!(
+            "poppler not found. Please install poppler{}:\n  \
+             macOS:   brew install poppler\n  \
+             Ubuntu:  sudo apt-get install poppler-utils\n  \
+             Windows: choco install poppler",
+            if pdfium {
+                ", or pass --backend pdfium"
+            } else {
+                ""
+            }
+        ),
+    }
+}
+
+/// How --combine arranges the pages
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum CombineLayout {
+    /// One page under another
+    Vertical,
+    /// --columns pages per row
+    Grid,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum CombineFormat {
+    /// Built in memory; at most 65535 pixels each way
+    Jpg,
+    /// Streamed a row of pages at a time
+    Png,
+}
+
+impl CombineFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            Self::Jpg => "jpg",
+            Self::Png => "png",
+        }
+    }
+}
+
+/// What to do about page files already in the output directory
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ExistingPages {
+    /// Refuse to convert, listing them
+    Abort,
+    Overwrite,
+    Skip,
+}
+
+impl ExistingPages {
+    fn from_args(args: &Args) -> Self {
+        if args.overwrite {
+            Self::Overwrite
+        } else if args.skip_existing {
+            Self::Skip
+        } else {
+            Self::Abort
+        }
+    }
 }
 
 fn main() -> Result<()> {
-    let args = Args::parse();
+    let mut args = Args::parse();
+    ui::init(false, args.no_emoji);
+    if args.with_text && args.manifest.is_none() && !args.text_files {
+        Args::command()
+            .error(
+                clap::error::ErrorKind::MissingRequiredArgument,
+                "--with-text needs --manifest or --text-files to put the text in",
+            )
+            .exit();
+    }
 
-    // Check if pdftoppm is available
-    check_pdftoppm_installed()?;
+    // poppler when installed, else pdfium; --with-text needs poppler either way
+    let poppler_installed =
+        args.backend != Some(Backend::Pdfium) && Backend::Poppler.is_installed();
+    args.backend = Some(resolve_backend(args.backend, poppler_installed)?);
+    if args.with_text {
+        pdf::check_poppler_tool("pdftotext")?;
+    }
 
-    // Validate input file
-    if !args.pdf_file.exists() {
-        anyhow::bail!(
-            "PDF file not found: {}",
-            style(args.pdf_file.display()).red()
-        );
+    let pdfs = collect_pdfs(&args.inputs)?;
+    if pdfs.is_empty() {
+        anyhow::bail!("No PDF files found in {:?}", args.inputs);
+    }
+
+    // Determine output directory
+    let output_dir = args.output.clone().unwrap_or_else(|| PathBuf::from("."));
+
+    let result = if let Some(size) = args.thumbnail {
+        convert_thumbnails(&pdfs, &output_dir, &args, size)
+    } else if args.continue_numbering {
+        convert_sequence(&pdfs, &output_dir, &args)
+    } else if args.inputs.len() == 1 && args.inputs[0].is_file() {
+        // A single PDF keeps the original, flat output
+        convert_single(&pdfs[0], &output_dir, &args)
+    } else {
+        convert_batch(&pdfs, &output_dir, &args)
+    };
+    result.map_err(|e| explain_disk_full(e, &output_dir))
+}
+
+/// PDFs named on the command line, with directories expanded (not
+/// recursively) to the PDFs they hold, sorted by name
+fn collect_pdfs(inputs: &[PathBuf]) -> Result<Vec<PathBuf>> {
+    let mut pdfs = Vec::new();
+
+    for input in inputs {
+        if input.is_dir() {
+            let mut found = Vec::new();
+            for entry in fs::read_dir(input)
+                .with_context(|| format!("Failed to read directory: {}", input.display()))?
+            {
+                let path = entry?.path();
+                if path.is_file() && is_pdf(&path) {
+                    found.push(path);
+                }
+            }
+            found.sort();
+            pdfs.extend(found);
+        } else if !input.exists() {
+            anyhow::bail!("PDF file not found: {}", style(input.display()).red());
+        } else if !is_pdf(input) {
+            anyhow::bail!(
+                "File does not appear to be a PDF: {}",
+                style(input.display()).yellow()
+            );
+        } else {
+            pdfs.push(input.clone());
+        }
     }
 
-    if !args
-        .pdf_file
-        .extension()
+    Ok(pdfs)
+}
+
+fn is_pdf(path: &Path) -> bool {
+    path.extension()
         .map(|e| e.eq_ignore_ascii_case("pdf"))
         .unwrap_or(false)
-    {
-        anyhow::bail!(
-            "File does not appear to be a PDF: {}",
-            style(args.pdf_file.display()).yellow()
-        );
+}
+
+/// Output subdirectory of each PDF in a batch: `output_dir/<file stem>`
+///
+/// Fails when two PDFs (from different directories) share a stem, rather
+/// than letting one overwrite the other's pages.
+fn batch_output_dirs(pdfs: &[PathBuf], output_dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut seen = HashMap::new();
+    let mut dirs = Vec::with_capacity(pdfs.len());
+    for pdf in pdfs {
+        let stem = pdf
+            .file_stem()
+            .with_context(|| format!("Invalid PDF filename: {}", pdf.display()))?;
+        if let Some(other) = seen.insert(stem.to_os_string(), pdf) {
+            anyhow::bail!(
+                "{} and {} would both be converted into {}; convert them separately",
+                other.display(),
+                pdf.display(),
+                output_dir.join(stem).display()
+            );
+        }
+        dirs.push(output_dir.join(stem));
     }
+    Ok(dirs)
+}
 
-    // Determine output directory
-    let output_dir = args.output.unwrap_or_else(|| PathBuf::from("."));
+/// What happened to one PDF's thumbnail
+enum Thumbnail {
+    /// Written, this many pixels wide and high
+    Written(u32, u32),
+    /// Already there, and not --overwrite
+    Kept,
+}
+
+/// Write a first-page thumbnail of every PDF into `output_dir`, `--jobs` at
+/// a time; a PDF that fails is reported without stopping the others
+fn convert_thumbnails(pdfs: &[PathBuf], output_dir: &Path, args: &Args, size: u32) -> Result<()> {
+    let targets = thumbnail_paths(pdfs, output_dir)?;
+    create_output_dir(output_dir)?;
+
+    println!();
+    println!("{} {}", GEAR, style("PDF to JPG Converter").bold().cyan());
+    println!();
+    println!(
+        "{}Inputs:  {} PDF{} ({} at a time)",
+        DOCUMENT,
+        style(pdfs.len()).green(),
+        plural(pdfs.len()),
+        args.jobs
+    );
+    println!("{}Output:  {}", FOLDER, style(output_dir.display()).green());
+    print_preset(args);
+    println!(
+        "  Quality: {}, Thumbnail: {}px",
+        style(args.settings().quality).cyan(),
+        style(size).cyan()
+    );
+    println!();
+
+    let progress = ProgressBar::new(pdfs.len() as u64);
+    progress.set_style(
+        ProgressStyle::with_template("  [{bar:40.cyan/blue}] {pos}/{len} PDFs")?
+            .progress_chars("━━─"),
+    );
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(args.jobs as usize)
+        .build()
+        .context("Failed to start worker threads")?;
+    let results: Vec<Result<Thumbnail>> = pool.install(|| {
+        pdfs.par_iter()
+            .zip(&targets)
+            .map(|(pdf, target)| {
+                let result = make_thumbnail(pdf, target, size, args)
+                    .map_err(|e| explain_disk_full(e, output_dir));
+                let name = pdf_label(pdf);
+                let line = match &result {
+                    Ok(Thumbnail::Written(width, height)) => format!(
+                        "  {} {} → {} ({}x{})",
+                        style("✓").green(),
+                        name,
+                        target.display(),
+                        width,
+                        height
+                    ),
+                    Ok(Thumbnail::Kept) => format!(
+                        "  {} {} ({} exists)",
+                        style("-").dim(),
+                        name,
+                        target.display()
+                    ),
+                    Err(e) => format!("  {} {}: {:#}", style("✗").red(), name, e),
+                };
+                // A hidden bar (no terminal) drops its printed lines
+                if progress.is_hidden() {
+                    println!("{}", line);
+                } else {
+                    progress.println(line);
+                }
+                progress.inc(1);
+                result
+            })
+            .collect()
+    });
+    progress.finish_and_clear();
 
-    // Create output directory if it doesn't exist
+    let written = results
+        .iter()
+        .filter(|r| matches!(r, Ok(Thumbnail::Written(..))))
+        .count();
+    let kept = results
+        .iter()
+        .filter(|r| matches!(r, Ok(Thumbnail::Kept)))
+        .count();
+    let failed = results.iter().filter(|r| r.is_err()).count();
+    println!();
+    println!(
+        "{} {} thumbnail{} written, {} kept, {} failed",
+        SPARKLES,
+        style(written).cyan().bold(),
+        plural(written),
+        kept,
+        failed
+    );
+    println!();
+
+    if failed > 0 {
+        anyhow::bail!("{} of {} PDFs failed", failed, pdfs.len());
+    }
+    Ok(())
+}
+
+/// Thumbnail path of each PDF: `<output_dir>/<stem>_thumb.jpg`
+///
+/// Fails when two PDFs share a stem, as for [`batch_output_dirs`].
+fn thumbnail_paths(pdfs: &[PathBuf], output_dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut seen = HashMap::new();
+    let mut paths = Vec::with_capacity(pdfs.len());
+    for pdf in pdfs {
+        let path = output_dir.join(thumbnail_name(pdf)?);
+        if let Some(other) = seen.insert(path.clone(), pdf) {
+            anyhow::bail!(
+                "{} and {} would both be thumbnailed into {}; convert them separately",
+                other.display(),
+                pdf.display(),
+                path.display()
+            );
+        }
+        paths.push(path);
+    }
+    Ok(paths)
+}
+
+/// `<stem>_thumb.jpg`
+fn thumbnail_name(pdf: &Path) -> Result<String> {
+    let stem = pdf
+        .file_stem()
+        .with_context(|| format!("Invalid PDF filename: {}", pdf.display()))?;
+    Ok(format!("{}_thumb.jpg", stem.to_string_lossy()))
+}
+
+/// Render page 1 of `pdf_file` just above `size` pixels on its longest edge,
+/// then scale it down to exactly that into `target`
+fn make_thumbnail(pdf_file: &Path, target: &Path, size: u32, args: &Args) -> Result<Thumbnail> {
+    if target.exists() && !args.overwrite {
+        return Ok(Thumbnail::Kept);
+    }
+
+    let sizes = pdf::output_page_sizes(pdf_file, 1, args.crop_box, args.rotate, args.backend())?;
+    let dpi = match sizes.first() {
+        Some(&(width, height)) => thumbnail_dpi(width, height, size),
+        None => DEFAULT_DPI,
+    };
+    let options = ConversionOptions {
+        dpi: Some(dpi),
+        ..conversion_options(args, 1)
+    };
+    let scratch_dir = target.parent().unwrap_or(Path::new("."));
+    let page = pdf::render_page(pdf_file, 1, &options, scratch_dir)?.into_rgb8();
+    let (width, height) = thumbnail_dimensions(page.width(), page.height(), size);
+    let thumbnail =
+        image::imageops::resize(&page, width, height, image::imageops::FilterType::Lanczos3);
+
+    pdf::save_image(
+        target,
+        &thumbnail,
+        ImageFormat::Jpeg,
+        args.settings().quality,
+    )?;
+    Ok(Thumbnail::Written(width, height))
+}
+
+/// DPI at which a `width` x `height` points page is at least `size` pixels
+/// on its longest edge, so the thumbnail is only ever scaled down
+fn thumbnail_dpi(width: f64, height: f64, size: u32) -> u16 {
+    let longest = width.max(height);
+    if longest <= 0.0 {
+        return DEFAULT_DPI;
+    }
+    (size as f64 * POINTS_PER_INCH / longest)
+        .ceil()
+        .clamp(1.0, u16::MAX as f64) as u16
+}
+
+/// `width` x `height` scaled so the longest edge is `size`, keeping the
+/// aspect ratio; neither edge drops below one pixel
+fn thumbnail_dimensions(width: u32, height: u32, size: u32) -> (u32, u32) {
+    let scale = |edge: u32, longest: u32| {
+        ((edge as f64 * size as f64 / longest as f64).round() as u32).max(1)
+    };
+    if width >= height {
+        (size, scale(height, width.max(1)))
+    } else {
+        (scale(width, height), size)
+    }
+}
+
+/// Create `output_dir` if it doesn't exist
+fn create_output_dir(output_dir: &Path) -> Result<()> {
     if !output_dir.exists() {
-        fs::create_dir_all(&output_dir).with_context(|| {
+        fs::create_dir_all(output_dir).with_context(|| {
             format!(
                 "Failed to create output directory: {}",
                 output_dir.display()
             )
         })?;
     }
+    Ok(())
+}
 
-    // Use user-provided prefix or None
-    let prefix = args.prefix.as_deref();
-    // Internal prefix for pdftoppm (it requires one)
-    let internal_prefix = "page";
+/// Convert one PDF straight into `output_dir`
+fn convert_single(pdf_file: &Path, output_dir: &Path, args: &Args) -> Result<()> {
+    create_output_dir(output_dir)?;
 
     // Print header
     println!();
     println!("{} {}", GEAR, style("PDF to JPG Converter").bold().cyan());
     println!();
-    println!(
-        "{}Input:   {}",
-        DOCUMENT,
-        style(args.pdf_file.display()).green()
-    );
+    println!("{}Input:   {}", DOCUMENT, style(pdf_file.display()).green());
     println!("{}Output:  {}", FOLDER, style(output_dir.display()).green());
+    print_preset(args);
     println!(
-        "  Quality: {}, DPI: {}",
-        style(args.quality).cyan(),
-        style(args.dpi).cyan()
+        "  {}, Jobs: {}",
+        settings_label(args),
+        style(args.jobs).cyan()
     );
     println!();
 
@@ -128,11 +757,11 @@ fn main() -> Result<()> {
     spinner.set_message("Analyzing PDF...");
     spinner.enable_steady_tick(Duration::from_millis(100));
 
-    let page_count = get_page_count(&args.pdf_file)?;
+    let page_count = pdf::count_pages(pdf_file, args.backend())?;
     spinner.finish_with_message(format!(
         "PDF has {} page{}",
         style(page_count).cyan().bold(),
-        if page_count == 1 { "" } else { "s" }
+        plural(page_count as usize)
     ));
     println!();
 
@@ -141,105 +770,92 @@ fn main() -> Result<()> {
         return Ok(());
     }
 
+    let plan = plan_render(output_dir, page_count, args)?;
+    if plan.skipped() > 0 {
+        println!(
+            "  Skipping {} page{} already converted",
+            style(plan.skipped()).cyan().bold(),
+            plural(plan.skipped())
+        );
+        println!();
+    }
+    if plan.pages.is_empty() {
+        println!("{} Every page is already converted", CHECK);
+        if let Some(path) = &args.manifest {
+            write_manifest(path, Vec::new())?;
+        }
+        if let Some((path, size)) = combine_pages(output_dir, &plan, args)? {
+            print_combined(&path, size);
+        }
+        return Ok(());
+    }
+
     // Create progress bar for conversion
-    let progress = ProgressBar::new(page_count as u64);
+    let progress = ProgressBar::new(plan.pages.len() as u64);
     progress.set_style(
         ProgressStyle::with_template(
-            "  {spinner:.green} [{bar:40.cyan/blue}] {pos}/{len} pages {msg}",
+            "  {spinner:.green} [{bar:40.cyan/blue}] {pos}/{len} pages ({elapsed}, {eta} left) {msg}",
         )?
         .progress_chars("━━─"),
     );
     progress.set_message("Converting...");
-    progress.enable_steady_tick(Duration::from_millis(100));
-
-    // Build output path prefix for pdftoppm
-    let output_prefix = output_dir.join(internal_prefix);
-
-    // Run pdftoppm to convert all pages
-    let output = Command::new("pdftoppm")
-        .args([
-            "-jpeg",
-            "-jpegopt",
-            &format!("quality={}", args.quality),
-            "-r",
-            &args.dpi.to_string(),
-        ])
-        .arg(&args.pdf_file)
-        .arg(&output_prefix)
-        .output()
-        .context("Failed to run pdftoppm. Make sure poppler is installed (brew install poppler)")?;
 
-    if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        anyhow::bail!("pdftoppm failed: {}", stderr);
-    }
+    let options = ConversionOptions {
+        pages: Some(plan.pages.clone()),
+        ..conversion_options(args, args.jobs as u32)
+    };
+    let report = pdf::convert_pdf(pdf_file, output_dir, &options, |event| match event {
+        PageEvent::Started { dpi, .. } => {
+            if options.is_size_targeted() {
+                progress.suspend(|| {
+                    println!("  Rendering at {} DPI", style(dpi).cyan().bold());
+                    println!();
+                });
+            }
+            progress.reset_elapsed();
+            progress.enable_steady_tick(Duration::from_millis(100));
+        }
+        PageEvent::Rendered { done, .. } => progress.set_position(done as u64),
+        PageEvent::Written(_) => {}
+    })?;
 
+    let elapsed = progress.elapsed();
     progress.finish_and_clear();
+    println!("  Rendered {}", render_rate(plan.pages.len(), elapsed));
+    println!();
 
-    // Collect and rename output files
-    let mut converted_files: Vec<(String, u64)> = Vec::new();
-
-    for page in 1..=page_count {
-        // pdftoppm outputs files with format: prefix-01.jpg, prefix-02.jpg, etc.
-        let pdftoppm_name = if page_count >= 10 {
-            format!("{}-{:02}.jpg", internal_prefix, page)
-        } else {
-            format!("{}-{}.jpg", internal_prefix, page)
-        };
-        let pdftoppm_path = output_dir.join(&pdftoppm_name);
-
-        // Also try the format with more digits for larger documents
-        let pdftoppm_name_3digit = format!("{}-{:03}.jpg", internal_prefix, page);
-        let pdftoppm_path_3digit = output_dir.join(&pdftoppm_name_3digit);
-
-        let source_path = if pdftoppm_path.exists() {
-            pdftoppm_path
-        } else if pdftoppm_path_3digit.exists() {
-            pdftoppm_path_3digit
+    if wants_page_details(args) {
+        let spinner = ProgressBar::new_spinner();
+        spinner.set_message(if args.with_text {
+            "Extracting text..."
         } else {
-            // Try other common patterns
-            let patterns = [
-                format!("{}-{}.jpg", internal_prefix, page),
-                format!("{}-{:04}.jpg", internal_prefix, page),
-                format!("{}-{:05}.jpg", internal_prefix, page),
-                format!("{}-{:06}.jpg", internal_prefix, page),
-            ];
-
-            let mut found = None;
-            for pattern in patterns {
-                let path = output_dir.join(&pattern);
-                if path.exists() {
-                    found = Some(path);
-                    break;
-                }
-            }
-
-            match found {
-                Some(p) => p,
-                None => continue, // Skip if file not found
-            }
-        };
-
-        // Rename to our preferred format: prefix_001.jpg or just 001.jpg
-        let target_name = match prefix {
-            Some(p) => format!("{}_{:03}.jpg", p, page),
-            None => format!("{:03}.jpg", page),
-        };
-        let target_path = output_dir.join(&target_name);
-
-        if source_path != target_path {
-            fs::rename(&source_path, &target_path).with_context(|| {
-                format!(
-                    "Failed to rename {} to {}",
-                    source_path.display(),
-                    target_path.display()
-                )
-            })?;
+            "Reading page sizes..."
+        });
+        spinner.enable_steady_tick(Duration::from_millis(100));
+        let pages = describe_pages(pdf_file, &report.pages, args)?;
+        spinner.finish_and_clear();
+        if let Some(path) = &args.manifest {
+            write_manifest(path, pages)?;
+            println!("{}Manifest: {}", DOCUMENT, style(path.display()).green());
+            println!();
         }
+    }
 
-        let file_size = fs::metadata(&target_path).map(|m| m.len()).unwrap_or(0);
-
-        converted_files.push((target_name, file_size));
+    let mut converted_files = file_sizes(&report.pages);
+    let mut combined = None;
+    if args.combine.is_some() {
+        let spinner = ProgressBar::new_spinner();
+        spinner.set_message("Combining pages...");
+        spinner.enable_steady_tick(Duration::from_millis(100));
+        combined = combine_pages(output_dir, &plan, args)?;
+        spinner.finish_and_clear();
+    }
+    if args.combine_only
+        && let Some((path, size)) = combined.take()
+    {
+        // The page images are gone; the combined image is what was created
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        converted_files = vec![(name.into_owned(), size)];
     }
 
     // Print summary
@@ -294,76 +910,1821 @@ fn main() -> Result<()> {
         style(format_size(total_size)).cyan()
     );
     println!();
+    if let Some((path, size)) = combined {
+        print_combined(&path, size);
+    }
 
     Ok(())
 }
 
-/// Check if pdftoppm is installed
-fn check_pdftoppm_installed() -> Result<()> {
-    let output = Command::new("pdftoppm").arg("-v").output();
+fn print_combined(path: &Path, size: u64) {
+    println!(
+        "{}Combined: {} {}",
+        DOCUMENT,
+        style(path.display()).green(),
+        style(format_size(size)).dim()
+    );
+    println!();
+}
 
-    match output {
-        Ok(o) if o.status.success() || !o.stderr.is_empty() => Ok(()),
-        _ => {
-            anyhow::bail!(
-                "pdftoppm not found. Please install poppler:\n  \
-                 macOS:   brew install poppler\n  \
-                 Ubuntu:  sudo apt-get install poppler-utils\n  \
-                 Windows: choco install poppler"
-            );
-        }
-    }
+/// Outcome of one PDF of a batch
+struct PdfReport {
+    pdf: PathBuf,
+    /// Where its pages went
+    output: String,
+    /// Converted pages' file names and sizes
+    result: Result<Vec<(String, u64)>>,
+    /// Manifest entries of the converted pages
+    pages: Vec<ManifestPage>,
 }
 
-/// Get the number of pages in a PDF using pdfinfo
-fn get_page_count(pdf_path: &PathBuf) -> Result<u32> {
-    let output = Command::new("pdfinfo")
-        .arg(pdf_path)
-        .output()
-        .context("Failed to run pdfinfo")?;
+/// One PDF of a batch, converted
+struct Converted {
+    plan: RenderPlan,
+    /// DPI the pages were rendered at
+    dpi: u16,
+    files: Vec<(String, u64)>,
+    /// Set with --manifest or --with-text
+    pages: Vec<ManifestPage>,
+    /// Path and size of the --combine image
+    combined: Option<(PathBuf, u64)>,
+}
 
-    if !output.status.success() {
-        anyhow::bail!("Failed to get PDF info");
-    }
+/// Convert every PDF into its own subdirectory of `output_dir`, `--jobs` at
+/// a time; a PDF that fails doesn't stop the others
+fn convert_batch(pdfs: &[PathBuf], output_dir: &Path, args: &Args) -> Result<()> {
+    let output_dirs = batch_output_dirs(pdfs, output_dir)?;
+    create_output_dir(output_dir)?;
 
-    let stdout = String::from_utf8_lossy(&output.stdout);
+    println!();
+    println!("{} {}", GEAR, style("PDF to JPG Converter").bold().cyan());
+    println!();
+    println!(
+        "{}Inputs:  {} PDF{} ({} at a time)",
+        DOCUMENT,
+        style(pdfs.len()).green(),
+        plural(pdfs.len()),
+        args.jobs
+    );
+    println!("{}Output:  {}", FOLDER, style(output_dir.display()).green());
+    print_preset(args);
+    println!("  {}", settings_label(args));
+    println!();
 
-    for line in stdout.lines() {
-        if line.starts_with("Pages:") {
-            let pages_str = line.trim_start_matches("Pages:").trim();
-            return pages_str
-                .parse()
-                .with_context(|| format!("Failed to parse page count: {}", pages_str));
-        }
+    let multi = MultiProgress::new();
+    let overall = multi.add(ProgressBar::new(pdfs.len() as u64));
+    overall.set_style(
+        ProgressStyle::with_template("  [{bar:40.cyan/blue}] {pos}/{len} PDFs")?
+            .progress_chars("━━─"),
+    );
+    let file_style = ProgressStyle::with_template("    {spinner:.green} {prefix:.bold} {msg}")?;
+
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(args.jobs as usize)
+        .build()
+        .context("Failed to start worker threads")?;
+    let reports: Vec<PdfReport> = pool.install(|| {
+        pdfs.par_iter()
+            .zip(&output_dirs)
+            .map(|(pdf, pdf_output_dir)| {
+                let name = pdf_label(pdf);
+                let bar = multi.insert_before(&overall, ProgressBar::new_spinner());
+                bar.set_style(file_style.clone());
+                bar.set_prefix(name.clone());
+                bar.enable_steady_tick(Duration::from_millis(100));
+
+                let result = convert_in_batch(pdf, pdf_output_dir, args, &bar)
+                    .map_err(|e| explain_disk_full(e, pdf_output_dir));
+                bar.finish_and_clear();
+                multi.remove(&bar);
+                let _ = multi.println(status_line(&name, &result));
+                overall.inc(1);
+
+                let (result, pages) = match result {
+                    Ok(converted) => (Ok(converted.files), converted.pages),
+                    Err(e) => (Err(e), Vec::new()),
+                };
+                PdfReport {
+                    pdf: pdf.clone(),
+                    output: pdf_output_dir.display().to_string(),
+                    result,
+                    pages,
+                }
+            })
+            .collect()
+    });
+    overall.finish_and_clear();
+
+    print_batch_summary(&reports);
+    let failed = reports.iter().filter(|r| r.result.is_err()).count();
+    if let Some(path) = &args.manifest {
+        // Failed PDFs contribute no pages; the manifest still covers the rest
+        let pages = reports.into_iter().flat_map(|r| r.pages).collect();
+        write_manifest(path, pages)?;
+        println!("{}Manifest: {}", DOCUMENT, style(path.display()).green());
+        println!();
     }
 
-    anyhow::bail!("Could not find page count in PDF info")
+    if failed > 0 {
+        anyhow::bail!("{} of {} PDFs failed", failed, pdfs.len());
+    }
+    Ok(())
 }
 
-/// Format file size in human-readable format
-fn format_size(bytes: u64) -> String {
-    const KB: u64 = 1024;
-    const MB: u64 = KB * 1024;
+/// Line reporting how one PDF of a batch went
+fn status_line(name: &str, result: &Result<Converted>) -> String {
+    match result {
+        Ok(Converted {
+            plan,
+            dpi,
+            files,
+            combined,
+            ..
+        }) => format!(
+            "  {} {} ({} page{}, {} DPI{}{})",
+            style("✓").green(),
+            name,
+            files.len(),
+            plural(files.len()),
+            dpi,
+            if plan.skipped() > 0 {
+                format!(", {} skipped", plan.skipped())
+            } else {
+                String::new()
+            },
+            if combined.is_some() { ", combined" } else { "" }
+        ),
+        Err(e) => format!("  {} {}: {:#}", style("✗").red(), name, e),
+    }
+}
 
-    if bytes >= MB {
-        format!("{:.1} MB", bytes as f64 / MB as f64)
-    } else if bytes >= KB {
-        format!("{:.0} KB", bytes as f64 / KB as f64)
-    } else {
-        format!("{} B", bytes)
+/// Convert one PDF of a batch, reporting its steps on `bar`
+fn convert_in_batch(
+    pdf_file: &Path,
+    output_dir: &Path,
+    args: &Args,
+    bar: &ProgressBar,
+) -> Result<Converted> {
+    bar.set_message("analyzing...");
+    let page_count = pdf::count_pages(pdf_file, args.backend())?;
+    let plan = plan_render(output_dir, page_count, args)?;
+    if plan.pages.is_empty() {
+        let combined = combine_pages(output_dir, &plan, args)?;
+        return Ok(Converted {
+            plan,
+            dpi: args.settings().dpi.unwrap_or(DEFAULT_DPI),
+            files: Vec::new(),
+            pages: Vec::new(),
+            combined,
+        });
+    }
+    // --jobs already spreads PDFs across processes
+    let options = ConversionOptions {
+        pages: Some(plan.pages.clone()),
+        ..conversion_options(args, 1)
+    };
+    let mut converted = convert_with_bar(pdf_file, output_dir, plan, &options, args, bar)?;
+    if args.combine.is_some() {
+        bar.set_message("combining...");
     }
+    converted.combined = combine_pages(output_dir, &converted.plan, args)?;
+    Ok(converted)
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+/// Convert the pages of `plan` as `options` say, following them on `bar`;
+/// nothing is combined
+fn convert_with_bar(
+    pdf_file: &Path,
+    output_dir: &Path,
+    plan: RenderPlan,
+    options: &ConversionOptions,
+    args: &Args,
+    bar: &ProgressBar,
+) -> Result<Converted> {
+    let page_style = ProgressStyle::with_template(
+        "    {spinner:.green} {prefix:.bold} [{bar:20.cyan/blue}] {pos}/{len} pages {msg}",
+    )?
+    .progress_chars("━━─");
+    let report = pdf::convert_pdf(pdf_file, output_dir, options, |event| match event {
+        PageEvent::Started { pages, dpi, .. } => {
+            bar.set_length(pages as u64);
+            bar.set_style(page_style.clone());
+            bar.set_message(format!("at {} DPI...", dpi));
+        }
+        PageEvent::Rendered { done, .. } => bar.set_position(done as u64),
+        PageEvent::Written(_) => {}
+    })?;
+    let files = file_sizes(&report.pages);
+    let pages = if wants_page_details(args) {
+        bar.set_message(if args.with_text {
+            "extracting text..."
+        } else {
+            "reading page sizes..."
+        });
+        describe_pages(pdf_file, &report.pages, args)?
+    } else {
+        Vec::new()
+    };
+    Ok(Converted {
+        plan,
+        dpi: report.dpi,
+        files,
+        pages,
+        combined: None,
+    })
+}
 
-    #[test]
-    fn test_format_size() {
-        assert_eq!(format_size(500), "500 B");
-        assert_eq!(format_size(1024), "1 KB");
-        assert_eq!(format_size(2048), "2 KB");
+/// Convert every PDF into `output_dir` itself, one after another, numbering
+/// their pages as one sequence; with --keep-going a PDF that fails leaves a
+/// gap in the numbers, otherwise the PDFs after it aren't converted
+fn convert_sequence(pdfs: &[PathBuf], output_dir: &Path, args: &Args) -> Result<()> {
+    // Every page count is needed up front to number the pages
+    let mut page_counts = Vec::with_capacity(pdfs.len());
+    for pdf in pdfs {
+        let count = pdf::count_pages(pdf, args.backend())
+            .with_context(|| format!("Failed to read {}", pdf.display()));
+        if count.is_err() && !args.keep_going {
+            return count.map(|_| ());
+        }
+        page_counts.push(count);
+    }
+    let numbered: Vec<u32> = page_counts
+        .iter()
+        .map(|count| *count.as_ref().unwrap_or(&0))
+        .collect();
+    let plan = plan_render(output_dir, numbered.iter().sum(), args)?;
+    let parts = split_sequence(&plan.pages, &numbered);
+    create_output_dir(output_dir)?;
+
+    println!();
+    println!("{} {}", GEAR, style("PDF to JPG Converter").bold().cyan());
+    println!();
+    println!(
+        "{}Inputs:  {} PDF{}, numbered {} in order",
+        DOCUMENT,
+        style(pdfs.len()).green(),
+        plural(pdfs.len()),
+        sequence_label(args, &plan, 0, plan.page_count)
+    );
+    println!("{}Output:  {}", FOLDER, style(output_dir.display()).green());
+    print_preset(args);
+    println!(
+        "  {}, Jobs: {}",
+        settings_label(args),
+        style(args.jobs).cyan()
+    );
+    println!();
+
+    let multi = MultiProgress::new();
+    let overall = multi.add(ProgressBar::new(pdfs.len() as u64));
+    overall.set_style(
+        ProgressStyle::with_template("  [{bar:40.cyan/blue}] {pos}/{len} PDFs")?
+            .progress_chars("━━─"),
+    );
+    let file_style = ProgressStyle::with_template("    {spinner:.green} {prefix:.bold} {msg}")?;
+
+    let inputs = pdfs.iter().zip(page_counts).zip(&parts);
+    let results = run_in_order(inputs, args.keep_going, |((pdf, count), part)| {
+        let name = pdf_label(pdf);
+        let result = count.and_then(|page_count| {
+            let bar = multi.insert_before(&overall, ProgressBar::new_spinner());
+            bar.set_style(file_style.clone());
+            bar.set_prefix(name.clone());
+            bar.enable_steady_tick(Duration::from_millis(100));
+            let part_plan = RenderPlan {
+                page_count,
+                pages: part.pages.clone(),
+                digits: plan.digits,
+            };
+            let result = convert_part(pdf, output_dir, part_plan, part.offset, args, &bar)
+                .map_err(|e| explain_disk_full(e, output_dir));
+            bar.finish_and_clear();
+            multi.remove(&bar);
+            result
+        });
+        let line = status_line(&name, &result);
+        // A hidden bar (no terminal) drops its printed lines
+        if overall.is_hidden() {
+            println!("{}", line);
+        } else {
+            let _ = multi.println(line);
+        }
+        overall.inc(1);
+        result
+    });
+    overall.finish_and_clear();
+
+    let reports: Vec<PdfReport> = pdfs
+        .iter()
+        .zip(results)
+        .zip(parts.iter().zip(&numbered))
+        .map(|((pdf, result), (part, &span))| {
+            let (result, pages) = match result {
+                Ok(converted) => (Ok(converted.files), converted.pages),
+                Err(e) => (Err(e), Vec::new()),
+            };
+            PdfReport {
+                pdf: pdf.clone(),
+                output: sequence_label(args, &plan, part.offset, span),
+                result,
+                pages,
+            }
+        })
+        .collect();
+
+    print_batch_summary(&reports);
+    let failed = reports.iter().filter(|r| r.result.is_err()).count();
+    if failed == 0
+        && let Some((path, size)) = combine_pages(output_dir, &plan, args)?
+    {
+        print_combined(&path, size);
+    }
+    if let Some(path) = &args.manifest {
+        let pages = reports.into_iter().flat_map(|r| r.pages).collect();
+        write_manifest(path, pages)?;
+        println!("{}Manifest: {}", DOCUMENT, style(path.display()).green());
+        println!();
+    }
+
+    if failed > 0 {
+        anyhow::bail!(
+            "{} of {} PDFs not converted{}",
+            failed,
+            pdfs.len(),
+            if args.keep_going {
+                ""
+            } else {
+                "; --keep-going carries on past a failed PDF"
+            }
+        );
+    }
+    Ok(())
+}
+
+/// Run `convert` on each of `items` in order; once one fails the rest are
+/// left unconverted, unless `keep_going`
+fn run_in_order<I, T>(
+    items: impl IntoIterator<Item = I>,
+    keep_going: bool,
+    mut convert: impl FnMut(I) -> Result<T>,
+) -> Vec<Result<T>> {
+    let mut results: Vec<Result<T>> = Vec::new();
+    for item in items {
+        let stopped = !keep_going && results.iter().any(|result| result.is_err());
+        results.push(if stopped {
+            Err(anyhow::anyhow!("not converted: an earlier PDF failed"))
+        } else {
+            convert(item)
+        });
+    }
+    results
+}
+
+/// One PDF's share of a --continue-numbering sequence
+#[derive(Debug, PartialEq, Eq)]
+struct SequencePart {
+    /// Numbers used by the PDFs before it
+    offset: u32,
+    /// Its own page numbers to render
+    pages: Vec<u32>,
+}
+
+/// Share out the sequence `numbers` to render (ascending) among PDFs of
+/// `page_counts` pages, numbered one after another
+fn split_sequence(numbers: &[u32], page_counts: &[u32]) -> Vec<SequencePart> {
+    let mut offset = 0;
+    page_counts
+        .iter()
+        .map(|&count| {
+            let pages = numbers
+                .iter()
+                .filter(|&&number| number > offset && number <= offset + count)
+                .map(|&number| number - offset)
+                .collect();
+            let part = SequencePart { offset, pages };
+            offset += count;
+            part
+        })
+        .collect()
+}
+
+/// Convert one PDF of a --continue-numbering sequence, its pages numbered
+/// from `offset + 1`
+fn convert_part(
+    pdf_file: &Path,
+    output_dir: &Path,
+    plan: RenderPlan,
+    offset: u32,
+    args: &Args,
+    bar: &ProgressBar,
+) -> Result<Converted> {
+    if plan.pages.is_empty() {
+        return Ok(Converted {
+            plan,
+            dpi: args.settings().dpi.unwrap_or(DEFAULT_DPI),
+            files: Vec::new(),
+            pages: Vec::new(),
+            combined: None,
+        });
+    }
+    let options = ConversionOptions {
+        pages: Some(plan.pages.clone()),
+        // Already checked against the whole sequence
+        digits: Some(plan.digits as u8),
+        number_offset: offset,
+        ..conversion_options(args, args.jobs as u32)
+    };
+    convert_with_bar(pdf_file, output_dir, plan, &options, args, bar)
+}
+
+/// First and last file of the `count` pages numbered after `offset`:
+/// `046.jpg … 090.jpg`
+fn sequence_label(args: &Args, plan: &RenderPlan, offset: u32, count: u32) -> String {
+    let name = |number| {
+        page_name(
+            args.prefix.as_deref(),
+            number,
+            plan.digits,
+            args.settings().format,
+        )
+    };
+    match count {
+        0 => "-".to_string(),
+        1 => name(offset + 1),
+        _ => format!("{} … {}", name(offset + 1), name(offset + count)),
+    }
+}
+
+/// Pages of one PDF to render, and how their files are named
+struct RenderPlan {
+    page_count: u32,
+    /// Ascending; fewer than `page_count` with --skip-existing
+    pages: Vec<u32>,
+    /// Width of output page numbers
+    digits: usize,
+}
+
+impl RenderPlan {
+    /// Pages left alone because their files already exist
+    fn skipped(&self) -> usize {
+        self.page_count as usize - self.pages.len()
+    }
+}
+
+/// Decide which pages to render into `output_dir`, checking the final page
+/// file names for ones already there
+fn plan_render(output_dir: &Path, page_count: u32, args: &Args) -> Result<RenderPlan> {
+    let digits = pdf::output_digits(page_count, args.digits)?;
+    let pages = pages_to_render(
+        output_dir,
+        page_count,
+        args.prefix.as_deref(),
+        digits,
+        args.settings().format,
+        ExistingPages::from_args(args),
+    )?;
+    if let Some(format) = args.combine.map(|_| args.combine_format) {
+        let combined = output_dir.join(combined_name(args.prefix.as_deref(), format));
+        if ExistingPages::from_args(args) == ExistingPages::Abort && combined.exists() {
+            anyhow::bail!(
+                "{} already exists; use --overwrite to replace it",
+                combined.display()
+            );
+        }
+    }
+    Ok(RenderPlan {
+        page_count,
+        pages,
+        digits,
+    })
+}
+
+/// Library options for the pages' rendering flags, rendering all pages
+/// with up to `jobs` pdftoppm runs
+fn conversion_options(args: &Args, jobs: u32) -> ConversionOptions {
+    let settings = args.settings();
+    ConversionOptions {
+        dpi: settings.dpi,
+        max_width: args.max_width,
+        max_height: args.max_height,
+        quality: settings.quality,
+        format: settings.format.image_format(),
+        pages: None,
+        prefix: args.prefix.clone(),
+        digits: args.digits,
+        number_offset: 0,
+        jobs,
+        crop_box: args.crop_box,
+        rotate: args.rotate,
+        keep_partial: args.keep_partial,
+        backend: args.backend(),
+    }
+}
+
+/// File names and sizes of converted `pages`
+fn file_sizes(pages: &[ConvertedPage]) -> Vec<(String, u64)> {
+    pages
+        .iter()
+        .map(|page| {
+            let name = page.path.file_name().unwrap_or_default().to_string_lossy();
+            (name.into_owned(), page.bytes)
+        })
+        .collect()
+}
+
+/// Pages whose output files may be written, given those already in
+/// `output_dir`; fails listing them under [`ExistingPages::Abort`]
+fn pages_to_render(
+    output_dir: &Path,
+    page_count: u32,
+    prefix: Option<&str>,
+    digits: usize,
+    format: PageFormat,
+    existing: ExistingPages,
+) -> Result<Vec<u32>> {
+    let (present, missing): (Vec<u32>, Vec<u32>) = (1..=page_count).partition(|&page| {
+        output_dir
+            .join(page_name(prefix, page, digits, format))
+            .exists()
+    });
+    match existing {
+        ExistingPages::Overwrite => Ok((1..=page_count).collect()),
+        ExistingPages::Skip => Ok(missing),
+        ExistingPages::Abort if present.is_empty() => Ok(missing),
+        ExistingPages::Abort => {
+            const SHOWN: usize = 10;
+            let mut names: Vec<String> = present
+                .iter()
+                .take(SHOWN)
+                .map(|&page| page_name(prefix, page, digits, format))
+                .collect();
+            if present.len() > SHOWN {
+                names.push(format!("and {} more", present.len() - SHOWN));
+            }
+            anyhow::bail!(
+                "{} page file{} already exist{} in {}: {}; use --overwrite to replace them or --skip-existing to convert only the missing pages",
+                present.len(),
+                plural(present.len()),
+                if present.len() == 1 { "s" } else { "" },
+                output_dir.display(),
+                names.join(", ")
+            )
+        }
+    }
+}
+
+/// File name of a PDF for status lines
+fn pdf_label(pdf: &Path) -> String {
+    pdf.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| pdf.display().to_string())
+}
+
+fn print_batch_summary(reports: &[PdfReport]) {
+    let name_width = reports
+        .iter()
+        .map(|r| pdf_label(&r.pdf).chars().count())
+        .max()
+        .unwrap_or(3)
+        .max(3);
+
+    println!();
+    println!("{}", style("═".repeat(70)).dim());
+    println!(
+        "{}",
+        style(format!(
+            "{:<width$}  {:<6}  {:>5}  {:>9}  {}",
+            "PDF",
+            "Status",
+            "Pages",
+            "Size",
+            "Output",
+            width = name_width
+        ))
+        .bold()
+    );
+
+    let mut total_pages = 0;
+    let mut total_size = 0;
+    for report in reports {
+        let name = pdf_label(&report.pdf);
+        match &report.result {
+            Ok(files) => {
+                let size: u64 = files.iter().map(|(_, size)| size).sum();
+                total_pages += files.len();
+                total_size += size;
+                println!(
+                    "{:<width$}  {}  {:>5}  {:>9}  {}",
+                    name,
+                    style(format!("{:<6}", "ok")).green(),
+                    files.len(),
+                    format_size(size),
+                    style(&report.output).dim(),
+                    width = name_width
+                );
+            }
+            Err(e) => println!(
+                "{:<width$}  {}  {:>5}  {:>9}  {}",
+                name,
+                style(format!("{:<6}", "failed")).red(),
+                "-",
+                "-",
+                style(format!("{:#}", e)).red(),
+                width = name_width
+            ),
+        }
+    }
+    println!("{}", style("═".repeat(70)).dim());
+
+    let converted = reports.iter().filter(|r| r.result.is_ok()).count();
+    println!(
+        "{} {} of {} PDFs converted, {} pages, total size: {}",
+        SPARKLES,
+        style(converted).cyan().bold(),
+        reports.len(),
+        style(total_pages).cyan().bold(),
+        style(format_size(total_size)).cyan()
+    );
+    println!();
+}
+
+/// `err` with a plain "disk is full" explanation when that's what caused it,
+/// as reported by an io::Error or in pdftoppm's output
+fn explain_disk_full(err: anyhow::Error, output_dir: &Path) -> anyhow::Error {
+    if is_disk_full(&err) {
+        err.context(format!(
+            "The disk holding {} is full; free some space and rerun",
+            output_dir.display()
+        ))
+    } else {
+        err
+    }
+}
+
+fn is_disk_full(err: &anyhow::Error) -> bool {
+    err.chain().any(|cause| {
+        cause
+            .downcast_ref::<io::Error>()
+            .is_some_and(|e| e.kind() == io::ErrorKind::StorageFull)
+            || cause.to_string().contains("No space left on device")
+    })
+}
+
+/// Converted pages, saved by --manifest
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+struct Manifest {
+    pages: Vec<ManifestPage>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct ManifestPage {
+    /// The PDF the page comes from, as given on the command line
+    pdf: String,
+    page: u32,
+    /// Image path, under the output directory
+    file: String,
+    /// Pixel size of the image
+    width: u32,
+    height: u32,
+    bytes: u64,
+    /// Extracted text, with --with-text
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    text: Option<String>,
+    /// Where the extracted text went, with --text-files
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    text_file: Option<String>,
+}
+
+fn write_manifest(path: &Path, pages: Vec<ManifestPage>) -> Result<()> {
+    let manifest = Manifest { pages };
+    fs::write(path, serde_json::to_string_pretty(&manifest)?)
+        .with_context(|| format!("Failed to write manifest: {}", path.display()))
+}
+
+/// Whether converted pages need describing, for the manifest or text files
+fn wants_page_details(args: &Args) -> bool {
+    args.manifest.is_some() || args.with_text
+}
+
+/// Manifest entries of the pages just converted; extracts their text with
+/// --with-text, writing it beside the images with --text-files
+fn describe_pages(
+    pdf_file: &Path,
+    pages: &[ConvertedPage],
+    args: &Args,
+) -> Result<Vec<ManifestPage>> {
+    pages
+        .par_iter()
+        .map(|page| {
+            let mut entry = describe_page(pdf_file, page);
+            if args.with_text {
+                let text = extract_text(pdf_file, page.page)?;
+                if args.text_files {
+                    let text_path = text_file_path(&page.path);
+                    fs::write(&text_path, &text).with_context(|| {
+                        format!("Failed to write text: {}", text_path.display())
+                    })?;
+                    entry.text_file = Some(text_path.display().to_string());
+                } else {
+                    entry.text = Some(text);
+                }
+            }
+            Ok(entry)
+        })
+        .collect()
+}
+
+/// Manifest entry of a converted page, without its text
+fn describe_page(pdf_file: &Path, page: &ConvertedPage) -> ManifestPage {
+    ManifestPage {
+        pdf: pdf_file.display().to_string(),
+        page: page.page,
+        file: page.path.display().to_string(),
+        width: page.width,
+        height: page.height,
+        bytes: page.bytes,
+        text: None,
+        text_file: None,
+    }
+}
+
+/// Sidecar text file of a page image: `001.jpg` -> `001.txt`
+fn text_file_path(image: &Path) -> PathBuf {
+    image.with_extension("txt")
+}
+
+/// Text of one page, from pdftotext
+fn extract_text(pdf_file: &Path, page: u32) -> Result<String> {
+    let output = Command::new("pdftotext")
+        .args([
+            "-f",
+            &page.to_string(),
+            "-l",
+            &page.to_string(),
+            "-enc",
+            "UTF-8",
+        ])
+        .arg(pdf_file)
+        .arg("-")
+        .output()
+        .context(
+            "Failed to run pdftotext. Make sure poppler is installed (brew install poppler)",
+        )?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "pdftotext failed on page {}: {}",
+            page,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(clean_page_text(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// pdftotext output without the form feed it ends each page with, or
+/// surrounding blank lines
+fn clean_page_text(raw: &str) -> String {
+    raw.trim_end_matches(['\u{c}', '\n', '\r', ' '])
+        .trim_start_matches(['\n', '\r'])
+        .to_string()
+}
+
+/// Stitch every page of `plan` in `output_dir` into one image per
+/// --combine, deleting the page images with --combine-only; returns the
+/// image's path and size, or `None` without --combine
+fn combine_pages(
+    output_dir: &Path,
+    plan: &RenderPlan,
+    args: &Args,
+) -> Result<Option<(PathBuf, u64)>> {
+    let Some(layout) = args.combine else {
+        return Ok(None);
+    };
+    let prefix = args.prefix.as_deref();
+    let format = args.settings().format;
+    let pages: Vec<(u32, PathBuf)> = (1..=plan.page_count)
+        .map(|page| {
+            (
+                page,
+                output_dir.join(page_name(prefix, page, plan.digits, format)),
+            )
+        })
+        .collect();
+    let sizes = pages
+        .iter()
+        .map(|(_, path)| {
+            image::image_dimensions(path)
+                .with_context(|| format!("Failed to read image size: {}", path.display()))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let columns = match layout {
+        CombineLayout::Vertical => 1,
+        CombineLayout::Grid => args.columns,
+    };
+    let sheet = SheetLayout::new(&sizes, columns, args.labels);
+
+    let path = output_dir.join(combined_name(prefix, args.combine_format));
+    let quality = args.settings().quality;
+    if let Err(e) = write_sheet(&path, &sheet, &pages, args.combine_format, quality) {
+        let _ = fs::remove_file(&path);
+        return Err(e);
+    }
+    if args.combine_only {
+        for (_, page) in &pages {
+            fs::remove_file(page)
+                .with_context(|| format!("Failed to remove {}", page.display()))?;
+        }
+    }
+    let size = fs::metadata(&path).map(|m| m.len()).unwrap_or(0);
+    Ok(Some((path, size)))
+}
+
+/// `[prefix_]combined.<ext>`
+fn combined_name(prefix: Option<&str>, format: CombineFormat) -> String {
+    match prefix {
+        Some(p) => format!("{}_combined.{}", p, format.extension()),
+        None => format!("combined.{}", format.extension()),
+    }
+}
+
+/// Where the pages of a combined image go: rows of `columns` cells as wide
+/// as the widest page, each row as tall as its tallest page plus the label,
+/// with [`SHEET_MARGIN`] around every cell
+#[derive(Debug, PartialEq)]
+struct SheetLayout {
+    columns: u32,
+    cell_width: u32,
+    row_heights: Vec<u32>,
+    /// Band above each page for its number; 0 without labels
+    label_height: u32,
+}
+
+impl SheetLayout {
+    /// Layout of pages sized `sizes` (width, height), in page order
+    fn new(sizes: &[(u32, u32)], columns: u32, labels: bool) -> Self {
+        let columns = columns.clamp(1, sizes.len().max(1) as u32);
+        let cell_width = sizes.iter().map(|&(width, _)| width).max().unwrap_or(0);
+        let label_height = if labels {
+            (GLYPH_HEIGHT + 2) * label_scale(cell_width)
+        } else {
+            0
+        };
+        let row_heights = sizes
+            .chunks(columns as usize)
+            .map(|row| row.iter().map(|&(_, height)| height).max().unwrap_or(0) + label_height)
+            .collect();
+        Self {
+            columns,
+            cell_width,
+            row_heights,
+            label_height,
+        }
+    }
+
+    fn width(&self) -> u32 {
+        self.columns * self.cell_width + (self.columns + 1) * SHEET_MARGIN
+    }
+
+    fn height(&self) -> u32 {
+        self.row_heights.iter().sum::<u32>() + (self.row_heights.len() as u32 + 1) * SHEET_MARGIN
+    }
+}
+
+/// Draw `pages` (number, image path) into a `sheet` sized image at `path`
+///
+/// Only one row of pages is decoded at a time; PNG rows go straight to the
+/// encoder, while JPEG needs the whole image in memory before encoding.
+fn write_sheet(
+    path: &Path,
+    sheet: &SheetLayout,
+    pages: &[(u32, PathBuf)],
+    format: CombineFormat,
+    quality: u8,
+) -> Result<()> {
+    let (width, height) = (sheet.width(), sheet.height());
+    let mut writer = SheetWriter::create(path, format, width, height, quality)?;
+    let white = image::Rgb([255, 255, 255]);
+
+    for (row, &row_height) in pages.chunks(sheet.columns as usize).zip(&sheet.row_heights) {
+        // The margin above the row, then the row
+        let mut strip = image::RgbImage::from_pixel(width, SHEET_MARGIN + row_height, white);
+        for (column, (page, page_path)) in row.iter().enumerate() {
+            let image = image::open(page_path)
+                .with_context(|| format!("Failed to read {}", page_path.display()))?
+                .into_rgb8();
+            let cell_x = SHEET_MARGIN + column as u32 * (sheet.cell_width + SHEET_MARGIN);
+            let x = cell_x + sheet.cell_width.saturating_sub(image.width()) / 2;
+            let y = SHEET_MARGIN + sheet.label_height;
+            image::imageops::replace(&mut strip, &image, x as i64, y as i64);
+            if sheet.label_height > 0 {
+                let scale = sheet.label_height / (GLYPH_HEIGHT + 2);
+                draw_label(
+                    &mut strip,
+                    &page.to_string(),
+                    cell_x + sheet.cell_width / 2,
+                    SHEET_MARGIN + scale,
+                    scale,
+                );
+            }
+        }
+        writer.write_rows(&strip)?;
+    }
+    writer.write_rows(&image::RgbImage::from_pixel(width, SHEET_MARGIN, white))?;
+    writer.finish()
+}
+
+/// Encoder of a combined image, fed top to bottom
+enum SheetWriter {
+    Jpeg {
+        file: BufWriter<File>,
+        canvas: image::RgbImage,
+        /// Rows filled so far
+        filled: u32,
+        quality: u8,
+    },
+    Png(Box<png::StreamWriter<'static, BufWriter<File>>>),
+}
+
+impl SheetWriter {
+    fn create(
+        path: &Path,
+        format: CombineFormat,
+        width: u32,
+        height: u32,
+        quality: u8,
+    ) -> Result<Self> {
+        if format == CombineFormat::Jpg && width.max(height) > JPEG_MAX_DIMENSION {
+            anyhow::bail!(
+                "The combined image would be {}x{} pixels, too large for a JPEG \
+                 (at most {} each way); use --combine-format png",
+                width,
+                height,
+                JPEG_MAX_DIMENSION
+            );
+        }
+        let file = BufWriter::new(
+            File::create(path).with_context(|| format!("Failed to create {}", path.display()))?,
+        );
+        Ok(match format {
+            CombineFormat::Jpg => Self::Jpeg {
+                file,
+                canvas: image::RgbImage::new(width, height),
+                filled: 0,
+                quality,
+            },
+            CombineFormat::Png => {
+                let mut encoder = png::Encoder::new(file, width, height);
+                encoder.set_color(png::ColorType::Rgb);
+                encoder.set_depth(png::BitDepth::Eight);
+                let writer = encoder
+                    .write_header()
+                    .context("Failed to write PNG header")?;
+                Self::Png(Box::new(
+                    writer
+                        .into_stream_writer()
+                        .context("Failed to start PNG data")?,
+                ))
+            }
+        })
+    }
+
+    /// Append `rows`, as wide as the image, below those written so far
+    fn write_rows(&mut self, rows: &image::RgbImage) -> Result<()> {
+        match self {
+            Self::Jpeg { canvas, filled, .. } => {
+                image::imageops::replace(canvas, rows, 0, *filled as i64);
+                *filled += rows.height();
+            }
+            Self::Png(stream) => stream
+                .write_all(rows.as_raw())
+                .context("Failed to write PNG data")?,
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Result<()> {
+        match self {
+            Self::Jpeg {
+                mut file,
+                canvas,
+                quality,
+                ..
+            } => {
+                image::codecs::jpeg::JpegEncoder::new_with_quality(&mut file, quality)
+                    .encode_image(&canvas)
+                    .context("Failed to encode JPEG")?;
+                file.flush().context("Failed to write JPEG")
+            }
+            Self::Png(stream) => stream.finish().context("Failed to finish PNG"),
+        }
+    }
+}
+
+/// Size of a label digit, in font pixels
+const GLYPH_WIDTH: u32 = 3;
+const GLYPH_HEIGHT: u32 = 5;
+
+/// 3x5 digits 0-9, one row per byte, most significant of the low 3 bits leftmost
+const DIGITS: [[u8; 5]; 10] = [
+    [0b111, 0b101, 0b101, 0b101, 0b111],
+    [0b010, 0b110, 0b010, 0b010, 0b111],
+    [0b111, 0b001, 0b111, 0b100, 0b111],
+    [0b111, 0b001, 0b111, 0b001, 0b111],
+    [0b101, 0b101, 0b111, 0b001, 0b001],
+    [0b111, 0b100, 0b111, 0b001, 0b111],
+    [0b111, 0b100, 0b111, 0b101, 0b111],
+    [0b111, 0b001, 0b001, 0b001, 0b001],
+    [0b111, 0b101, 0b111, 0b101, 0b111],
+    [0b111, 0b101, 0b111, 0b001, 0b111],
+];
+
+/// Screen pixels per font pixel, growing with the page width
+fn label_scale(cell_width: u32) -> u32 {
+    (cell_width / 150).clamp(2, 12)
+}
+
+/// Draw the digits of `text` centered on `center_x`, top at `top`
+fn draw_label(image: &mut image::RgbImage, text: &str, center_x: u32, top: u32, scale: u32) {
+    let advance = (GLYPH_WIDTH + 1) * scale;
+    let width = (text.len() as u32 * advance).saturating_sub(scale);
+    let mut x = center_x.saturating_sub(width / 2);
+    for digit in text.chars().filter_map(|c| c.to_digit(10)) {
+        for (row, bits) in DIGITS[digit as usize].iter().enumerate() {
+            for column in 0..GLYPH_WIDTH {
+                if bits & (1 << (GLYPH_WIDTH - 1 - column)) == 0 {
+                    continue;
+                }
+                for dy in 0..scale {
+                    for dx in 0..scale {
+                        let (px, py) = (x + column * scale + dx, top + row as u32 * scale + dy);
+                        if px < image.width() && py < image.height() {
+                            image.put_pixel(px, py, image::Rgb([64, 64, 64]));
+                        }
+                    }
+                }
+            }
+        }
+        x += advance;
+    }
+}
+
+/// "s" unless `count` is one
+fn plural(count: usize) -> &'static str {
+    if count == 1 {
+        ""
+    } else {
+        "s"
+    }
+}
+
+/// `Preset: print` line of the header, with --preset
+fn print_preset(args: &Args) {
+    if let Some(preset) = args.preset {
+        let name = preset
+            .to_possible_value()
+            .map(|value| value.get_name().to_string());
+        println!("  Preset:  {}", style(name.unwrap_or_default()).cyan());
+    }
+}
+
+/// Effective settings for the header: `Quality: 92, DPI: 300, Format: jpg`
+fn settings_label(args: &Args) -> String {
+    let settings = args.settings();
+    let quality = match settings.format {
+        PageFormat::Jpg => settings.quality.to_string(),
+        PageFormat::Png => "lossless".to_string(),
+    };
+    format!(
+        "Quality: {}, DPI: {}, Format: {}",
+        style(quality).cyan(),
+        style(dpi_label(args)).cyan(),
+        style(settings.format.image_format().extension()).cyan()
+    )
+}
+
+/// DPI setting for the header: a number, or the size target it comes from
+fn dpi_label(args: &Args) -> String {
+    let dpi = args.settings().dpi;
+    let target = match (args.max_width, args.max_height) {
+        (None, None) => return dpi.unwrap_or(DEFAULT_DPI).to_string(),
+        (Some(width), None) => format!("{}px wide", width),
+        (None, Some(height)) => format!("{}px high", height),
+        (Some(width), Some(height)) => format!("{}x{}px", width, height),
+    };
+    match dpi {
+        Some(dpi) => format!("fit {} (max {})", target, dpi),
+        None => format!("fit {}", target),
+    }
+}
+
+/// Final name of page `page` of a conversion: `[prefix_]<page>.<format>`
+fn page_name(prefix: Option<&str>, page: u32, digits: usize, format: PageFormat) -> String {
+    pdf::output_name(prefix, page, digits, format.image_format())
+}
+
+/// `12 pages in 3.0s (4.0 pages/s)`
+fn render_rate(pages: usize, elapsed: Duration) -> String {
+    let seconds = elapsed.as_secs_f64();
+    let rate = if seconds > 0.0 {
+        format!("{:.1}", pages as f64 / seconds)
+    } else {
+        "-".to_string()
+    };
+    format!(
+        "{} page{} in {} ({} pages/s)",
+        pages,
+        plural(pages),
+        format_elapsed(elapsed),
+        rate
+    )
+}
+
+/// `3.2s` under a minute, else `12m05s` or `1h02m05s`
+fn format_elapsed(elapsed: Duration) -> String {
+    let total = elapsed.as_secs();
+    match total {
+        0..60 => format!("{:.1}s", elapsed.as_secs_f64()),
+        60..3600 => format!("{}m{:02}s", total / 60, total % 60),
+        _ => format!("{}h{:02}m{:02}s", total / 3600, total / 60 % 60, total % 60),
+    }
+}
+
+/// Format file size in human-readable format
+fn format_size(bytes: u64) -> String {
+    const KB: u64 = 1024;
+    const MB: u64 = KB * 1024;
+
+    if bytes >= MB {
+        format!("{:.1} MB", bytes as f64 / MB as f64)
+    } else if bytes >= KB {
+        format!("{:.0} KB", bytes as f64 / KB as f64)
+    } else {
+        format!("{} B", bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_size() {
+        assert_eq!(format_size(500), "500 B");
+        assert_eq!(format_size(1024), "1 KB");
+        assert_eq!(format_size(2048), "2 KB");
         assert_eq!(format_size(1024 * 1024), "1.0 MB");
         assert_eq!(format_size(1024 * 1024 * 2 + 512 * 1024), "2.5 MB");
     }
+
+    #[test]
+    fn test_render_rate() {
+        assert_eq!(format_elapsed(Duration::from_millis(3250)), "3.2s");
+        assert_eq!(format_elapsed(Duration::from_secs(725)), "12m05s");
+        assert_eq!(format_elapsed(Duration::from_secs(3725)), "1h02m05s");
+
+        assert_eq!(
+            render_rate(12, Duration::from_secs(3)),
+            "12 pages in 3.0s (4.0 pages/s)"
+        );
+        assert_eq!(
+            render_rate(1, Duration::from_secs(1200)),
+            "1 page in 20m00s (0.0 pages/s)"
+        );
+        assert_eq!(
+            render_rate(0, Duration::ZERO),
+            "0 pages in 0.0s (- pages/s)"
+        );
+    }
+
+    /// Output directory holding the page files `existing` of a 5 page PDF
+    fn output_with_pages(existing: &[u32]) -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        for &page in existing {
+            fs::write(
+                dir.path()
+                    .join(page_name(Some("doc"), page, 3, PageFormat::Jpg)),
+                b"",
+            )
+            .unwrap();
+        }
+        // Files of another prefix or width are no conflict
+        fs::write(dir.path().join("001.jpg"), b"").unwrap();
+        fs::write(dir.path().join("doc_0001.jpg"), b"").unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_existing_pages_abort_by_default() {
+        let dir = output_with_pages(&[2, 4]);
+        let err = pages_to_render(
+            dir.path(),
+            5,
+            Some("doc"),
+            3,
+            PageFormat::Jpg,
+            ExistingPages::Abort,
+        )
+        .unwrap_err()
+        .to_string();
+        assert!(err.contains("2 page files already exist"), "{}", err);
+        assert!(err.contains("doc_002.jpg, doc_004.jpg"), "{}", err);
+        assert!(err.contains("--overwrite"), "{}", err);
+
+        // Nothing in the way: every page
+        let dir = output_with_pages(&[]);
+        assert_eq!(
+            pages_to_render(
+                dir.path(),
+                5,
+                Some("doc"),
+                3,
+                PageFormat::Jpg,
+                ExistingPages::Abort
+            )
+            .unwrap(),
+            [1, 2, 3, 4, 5]
+        );
+    }
+
+    #[test]
+    fn test_existing_pages_abort_lists_the_first_few() {
+        let pages: Vec<u32> = (1..=12).collect();
+        let dir = output_with_pages(&pages);
+        let err = pages_to_render(
+            dir.path(),
+            12,
+            Some("doc"),
+            3,
+            PageFormat::Jpg,
+            ExistingPages::Abort,
+        )
+        .unwrap_err()
+        .to_string();
+        assert!(err.contains("doc_010.jpg, and 2 more"), "{}", err);
+        assert!(!err.contains("doc_011.jpg"), "{}", err);
+    }
+
+    #[test]
+    fn test_existing_pages_overwrite() {
+        let dir = output_with_pages(&[2, 4]);
+        assert_eq!(
+            pages_to_render(
+                dir.path(),
+                5,
+                Some("doc"),
+                3,
+                PageFormat::Jpg,
+                ExistingPages::Overwrite
+            )
+            .unwrap(),
+            [1, 2, 3, 4, 5]
+        );
+    }
+
+    #[test]
+    fn test_existing_pages_skip() {
+        let dir = output_with_pages(&[2, 4]);
+        assert_eq!(
+            pages_to_render(
+                dir.path(),
+                5,
+                Some("doc"),
+                3,
+                PageFormat::Jpg,
+                ExistingPages::Skip
+            )
+            .unwrap(),
+            [1, 3, 5]
+        );
+        let dir = output_with_pages(&[1, 2, 3, 4, 5]);
+        assert!(pages_to_render(
+            dir.path(),
+            5,
+            Some("doc"),
+            3,
+            PageFormat::Jpg,
+            ExistingPages::Skip
+        )
+        .unwrap()
+        .is_empty());
+    }
+
+    fn manifest_page(page: u32) -> ManifestPage {
+        ManifestPage {
+            pdf: "doc.pdf".to_string(),
+            page,
+            file: format!("out/{:03}.jpg", page),
+            width: 1275,
+            height: 1650,
+            bytes: 20480,
+            text: None,
+            text_file: None,
+        }
+    }
+
+    #[test]
+    fn test_manifest_shape() {
+        let mut with_text = manifest_page(2);
+        with_text.text = Some("Chapter 1".to_string());
+        let mut with_file = manifest_page(3);
+        with_file.text_file = Some("out/003.txt".to_string());
+        let manifest = Manifest {
+            pages: vec![manifest_page(1), with_text, with_file],
+        };
+
+        let json = serde_json::to_value(&manifest).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "pages": [
+                    {
+                        "pdf": "doc.pdf",
+                        "page": 1,
+                        "file": "out/001.jpg",
+                        "width": 1275,
+                        "height": 1650,
+                        "bytes": 20480
+                    },
+                    {
+                        "pdf": "doc.pdf",
+                        "page": 2,
+                        "file": "out/002.jpg",
+                        "width": 1275,
+                        "height": 1650,
+                        "bytes": 20480,
+                        "text": "Chapter 1"
+                    },
+                    {
+                        "pdf": "doc.pdf",
+                        "page": 3,
+                        "file": "out/003.jpg",
+                        "width": 1275,
+                        "height": 1650,
+                        "bytes": 20480,
+                        "text_file": "out/003.txt"
+                    }
+                ]
+            })
+        );
+        assert_eq!(serde_json::from_value::<Manifest>(json).unwrap(), manifest);
+    }
+
+    #[test]
+    fn test_write_manifest() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("pages.json");
+        write_manifest(&path, vec![manifest_page(1)]).unwrap();
+        let manifest: Manifest = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(manifest.pages, [manifest_page(1)]);
+    }
+
+    #[test]
+    fn test_resolve_backend() {
+        let pdfium = Backend::Pdfium.is_compiled_in();
+        assert_eq!(resolve_backend(None, true).unwrap(), Backend::Poppler);
+        assert_eq!(
+            resolve_backend(Some(Backend::Poppler), true).unwrap(),
+            Backend::Poppler
+        );
+        let message = resolve_backend(Some(Backend::Poppler), false)
+            .unwrap_err()
+            .to_string();
+        assert!(message.starts_with("poppler not found"), "{}", message);
+
+        // Without poppler, pdfium is the fallback when compiled in
+        match resolve_backend(None, false) {
+            Ok(backend) => assert!(pdfium && backend == Backend::Pdfium),
+            Err(e) => assert!(!pdfium && e.to_string().starts_with("poppler not found")),
+        }
+        for poppler_installed in [true, false] {
+            match resolve_backend(Some(Backend::Pdfium), poppler_installed) {
+                Ok(backend) => assert!(pdfium && backend == Backend::Pdfium),
+                Err(e) => assert!(!pdfium && e.to_string().contains("--features pdfium")),
+            }
+        }
+    }
+
+    #[test]
+    fn test_resolve_settings() {
+        let defaults = Settings {
+            dpi: None,
+            quality: DEFAULT_QUALITY,
+            format: PageFormat::Jpg,
+        };
+        assert_eq!(resolve_settings(None, None, None, None), defaults);
+        assert_eq!(
+            resolve_settings(None, Some(200), None, Some(PageFormat::Png)),
+            Settings {
+                dpi: Some(200),
+                format: PageFormat::Png,
+                ..defaults
+            }
+        );
+
+        let print = Preset::Print.settings();
+        assert_eq!((print.dpi, print.quality), (Some(300), 92));
+        assert_eq!(
+            resolve_settings(Some(Preset::Print), None, None, None),
+            print
+        );
+        // Each explicit flag replaces only its own value
+        assert_eq!(
+            resolve_settings(Some(Preset::Print), Some(240), None, None),
+            Settings {
+                dpi: Some(240),
+                ..print
+            }
+        );
+        assert_eq!(
+            resolve_settings(Some(Preset::Print), None, Some(80), None),
+            Settings {
+                quality: 80,
+                ..print
+            }
+        );
+        assert_eq!(
+            resolve_settings(
+                Some(Preset::Archive),
+                Some(600),
+                Some(90),
+                Some(PageFormat::Jpg)
+            ),
+            Settings {
+                dpi: Some(600),
+                quality: 90,
+                format: PageFormat::Jpg,
+            }
+        );
+        assert_eq!(Preset::Archive.settings().format, PageFormat::Png);
+        assert_eq!(Preset::Screen.settings().dpi, Some(96));
+    }
+
+    #[test]
+    fn test_preset_flags() {
+        let args = Args::parse_from(["pdf2jpg", "doc.pdf", "--preset", "screen", "-q", "60"]);
+        let settings = args.settings();
+        assert_eq!((settings.dpi, settings.quality), (Some(96), 60));
+        assert!(settings_label(&args).contains("DPI: 96"));
+
+        let args = Args::parse_from(["pdf2jpg", "doc.pdf", "--preset", "archive"]);
+        let label = settings_label(&args);
+        assert!(label.contains("Quality: lossless"), "{}", label);
+        assert!(label.contains("Format: png"), "{}", label);
+        assert_eq!(page_name(None, 1, 3, args.settings().format), "001.png");
+
+        // A preset's DPI caps a size target
+        let args = Args::parse_from([
+            "pdf2jpg",
+            "doc.pdf",
+            "--preset",
+            "print",
+            "--max-width",
+            "800",
+        ]);
+        assert_eq!(dpi_label(&args), "fit 800px wide (max 300)");
+        assert_eq!(conversion_options(&args, 1).dpi, Some(300));
+
+        assert!(
+            Args::try_parse_from(["pdf2jpg", "doc.pdf", "--thumbnail", "--format", "png"]).is_err()
+        );
+    }
+
+    #[test]
+    fn test_split_sequence() {
+        // 3, 0 (unreadable), 2 and 4 pages: numbers 1-3, 4-5 and 6-9
+        let counts = [3, 0, 2, 4];
+        let all: Vec<u32> = (1..=9).collect();
+        let parts = split_sequence(&all, &counts);
+        let offsets: Vec<u32> = parts.iter().map(|part| part.offset).collect();
+        assert_eq!(offsets, [0, 3, 3, 5]);
+        assert_eq!(parts[0].pages, [1, 2, 3]);
+        assert!(parts[1].pages.is_empty());
+        assert_eq!(parts[2].pages, [1, 2]);
+        assert_eq!(parts[3].pages, [1, 2, 3, 4]);
+
+        // Numbers already converted (--skip-existing) stay with their PDF
+        let parts = split_sequence(&[2, 4, 9], &counts);
+        assert_eq!(parts[0].pages, [2]);
+        assert_eq!(parts[2].pages, [1]);
+        assert_eq!(parts[3].pages, [4]);
+    }
+
+    #[test]
+    fn test_failed_pdf_stops_sequence() {
+        let convert = |ran: &std::cell::RefCell<Vec<u32>>, pdf: u32| {
+            ran.borrow_mut().push(pdf);
+            if pdf == 2 {
+                anyhow::bail!("corrupt")
+            }
+            Ok(pdf)
+        };
+
+        let ran = std::cell::RefCell::new(Vec::new());
+        let results = run_in_order(1..=4, false, |pdf| convert(&ran, pdf));
+        assert_eq!(*ran.borrow(), [1, 2]);
+        assert!(results[0].is_ok());
+        assert_eq!(results[1].as_ref().unwrap_err().to_string(), "corrupt");
+        assert!(results[3]
+            .as_ref()
+            .unwrap_err()
+            .to_string()
+            .contains("earlier PDF failed"));
+
+        // --keep-going: every PDF is tried
+        let ran = std::cell::RefCell::new(Vec::new());
+        let results = run_in_order(1..=4, true, |pdf| convert(&ran, pdf));
+        assert_eq!(*ran.borrow(), [1, 2, 3, 4]);
+        assert_eq!(results.iter().filter(|r| r.is_ok()).count(), 3);
+    }
+
+    #[test]
+    fn test_sequence_flags() {
+        let args = Args::parse_from([
+            "pdf2jpg",
+            "ch1.pdf",
+            "ch2.pdf",
+            "--continue-numbering",
+            "--prefix",
+            "book",
+        ]);
+        let plan = RenderPlan {
+            page_count: 90,
+            pages: Vec::new(),
+            digits: 3,
+        };
+        assert_eq!(
+            sequence_label(&args, &plan, 45, 45),
+            "book_046.jpg … book_090.jpg"
+        );
+        assert_eq!(sequence_label(&args, &plan, 45, 1), "book_046.jpg");
+        assert_eq!(sequence_label(&args, &plan, 45, 0), "-");
+
+        assert!(Args::try_parse_from(["pdf2jpg", "a.pdf", "--keep-going"]).is_err());
+        assert!(
+            Args::try_parse_from(["pdf2jpg", "a.pdf", "--continue-numbering", "--thumbnail"])
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_describe_page() {
+        let page = ConvertedPage {
+            page: 2,
+            path: PathBuf::from("out/002.jpg"),
+            width: 40,
+            height: 30,
+            bytes: 1234,
+        };
+        let entry = describe_page(Path::new("doc.pdf"), &page);
+        assert_eq!(entry.pdf, "doc.pdf");
+        assert_eq!(entry.page, 2);
+        assert_eq!((entry.width, entry.height, entry.bytes), (40, 30, 1234));
+        assert_eq!(entry.file, page.path.display().to_string());
+        assert_eq!(entry.text, None);
+        assert_eq!(file_sizes(&[page]), [("002.jpg".to_string(), 1234)]);
+    }
+
+    #[test]
+    fn test_page_text() {
+        assert_eq!(
+            text_file_path(Path::new("out/doc_001.jpg")),
+            Path::new("out/doc_001.txt")
+        );
+        assert_eq!(
+            clean_page_text("\nTitle\n\n  body\n\u{c}"),
+            "Title\n\n  body"
+        );
+        assert_eq!(clean_page_text("\u{c}"), "");
+    }
+
+    /// Pages 1.. of the given sizes, as JPEGs in `dir`
+    fn page_images(dir: &Path, sizes: &[(u32, u32)]) -> Vec<(u32, PathBuf)> {
+        sizes
+            .iter()
+            .enumerate()
+            .map(|(i, &(width, height))| {
+                let page = i as u32 + 1;
+                let path = dir.join(page_name(None, page, 3, PageFormat::Jpg));
+                image::RgbImage::from_pixel(width, height, image::Rgb([200, 0, 0]))
+                    .save(&path)
+                    .unwrap();
+                (page, path)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_sheet_layout() {
+        let sizes = [(40, 60), (60, 40), (40, 60), (40, 60), (40, 60)];
+        let vertical = SheetLayout::new(&sizes, 1, false);
+        assert_eq!(vertical.width(), 60 + 2 * SHEET_MARGIN);
+        assert_eq!(vertical.height(), 60 + 40 + 3 * 60 + 6 * SHEET_MARGIN);
+
+        let grid = SheetLayout::new(&sizes, 2, false);
+        assert_eq!(grid.row_heights, [60, 60, 60]);
+        assert_eq!(grid.width(), 2 * 60 + 3 * SHEET_MARGIN);
+        assert_eq!(grid.height(), 3 * 60 + 4 * SHEET_MARGIN);
+
+        // More columns than pages: one row, no empty cells
+        assert_eq!(SheetLayout::new(&sizes[..2], 4, false).columns, 2);
+
+        let labelled = SheetLayout::new(&sizes, 2, true);
+        assert_eq!(labelled.label_height, (GLYPH_HEIGHT + 2) * 2);
+        assert_eq!(labelled.height(), grid.height() + 3 * labelled.label_height);
+    }
+
+    #[test]
+    fn test_write_sheet_dimensions() {
+        let dir = tempfile::tempdir().unwrap();
+        let pages = page_images(dir.path(), &[(20, 30), (10, 10), (20, 30)]);
+        let sizes = [(20, 30), (10, 10), (20, 30)];
+
+        for (columns, format) in [(1, CombineFormat::Png), (2, CombineFormat::Jpg)] {
+            let sheet = SheetLayout::new(&sizes, columns, true);
+            let path = dir.path().join(combined_name(Some("doc"), format));
+            write_sheet(&path, &sheet, &pages, format, 85).unwrap();
+            assert_eq!(
+                image::image_dimensions(&path).unwrap(),
+                (sheet.width(), sheet.height()),
+                "{} columns",
+                columns
+            );
+        }
+        assert!(dir.path().join("doc_combined.png").exists());
+        assert!(dir.path().join("doc_combined.jpg").exists());
+
+        // Pages are drawn, centered in their cell, on white
+        let sheet = SheetLayout::new(&sizes, 1, false);
+        let path = dir.path().join(combined_name(None, CombineFormat::Png));
+        write_sheet(&path, &sheet, &pages, CombineFormat::Png, 85).unwrap();
+        let combined = image::open(&path).unwrap().into_rgb8();
+        assert_eq!(combined.get_pixel(0, 0).0, [255, 255, 255]);
+        assert_eq!(
+            combined.get_pixel(SHEET_MARGIN, SHEET_MARGIN).0,
+            [200, 0, 0]
+        );
+        let second_top = 2 * SHEET_MARGIN + 30;
+        assert_eq!(
+            combined.get_pixel(SHEET_MARGIN, second_top).0,
+            [255, 255, 255]
+        );
+        assert_eq!(
+            combined.get_pixel(SHEET_MARGIN + 5, second_top).0,
+            [200, 0, 0]
+        );
+    }
+
+    #[test]
+    fn test_jpeg_sheet_size_limit() {
+        let dir = tempfile::tempdir().unwrap();
+        let sheet = SheetLayout {
+            columns: 1,
+            cell_width: 100,
+            row_heights: vec![40_000, 40_000],
+            label_height: 0,
+        };
+        let err = write_sheet(
+            &dir.path().join("combined.jpg"),
+            &sheet,
+            &[],
+            CombineFormat::Jpg,
+            85,
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("--combine-format png"), "{}", err);
+    }
+
+    #[test]
+    fn test_thumbnail_dpi() {
+        // Letter, portrait or landscape: 400px across 11in
+        assert_eq!(thumbnail_dpi(612.0, 792.0, 400), 37);
+        assert_eq!(thumbnail_dpi(792.0, 612.0, 400), 37);
+        let pixels = |dpi: u16| 792.0 * dpi as f64 / POINTS_PER_INCH;
+        assert!(pixels(37) >= 400.0);
+        assert!(pixels(36) < 400.0);
+        assert_eq!(thumbnail_dpi(0.0, 0.0, 400), DEFAULT_DPI);
+    }
+
+    #[test]
+    fn test_thumbnail_dimensions() {
+        assert_eq!(thumbnail_dimensions(408, 528, 400), (309, 400));
+        assert_eq!(thumbnail_dimensions(1000, 500, 400), (400, 200));
+        assert_eq!(thumbnail_dimensions(500, 500, 400), (400, 400));
+        // Scaled up when the render came out smaller
+        assert_eq!(thumbnail_dimensions(100, 50, 400), (400, 200));
+        assert_eq!(thumbnail_dimensions(3, 1000, 400), (1, 400));
+    }
+
+    #[test]
+    fn test_thumbnail_paths() {
+        let root = Path::new("covers");
+        let paths = thumbnail_paths(
+            &[
+                PathBuf::from("scans/Jan report.pdf"),
+                PathBuf::from("feb.PDF"),
+            ],
+            root,
+        )
+        .unwrap();
+        assert_eq!(
+            paths,
+            [
+                root.join("Jan report_thumb.jpg"),
+                root.join("feb_thumb.jpg")
+            ]
+        );
+
+        let err = thumbnail_paths(
+            &[PathBuf::from("a/jan.pdf"), PathBuf::from("b/jan.pdf")],
+            root,
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("both be thumbnailed into"));
+    }
+
+    #[test]
+    fn test_existing_thumbnail_is_kept() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("doc_thumb.jpg");
+        fs::write(&target, b"cover").unwrap();
+        let args = Args::parse_from(["pdf2jpg", "doc.pdf", "--thumbnail"]);
+        assert_eq!(args.thumbnail, Some(400));
+
+        // Kept without touching the (missing) PDF
+        let result = make_thumbnail(Path::new("missing.pdf"), &target, 400, &args);
+        assert!(matches!(result, Ok(Thumbnail::Kept)));
+        assert_eq!(fs::read(&target).unwrap(), b"cover");
+
+        // --overwrite renders again, and reports the broken PDF
+        let args = Args::parse_from(["pdf2jpg", "doc.pdf", "--thumbnail", "200", "--overwrite"]);
+        assert_eq!(args.thumbnail, Some(200));
+        assert!(make_thumbnail(Path::new("missing.pdf"), &target, 200, &args).is_err());
+    }
+
+    #[test]
+    fn test_disk_full_is_explained() {
+        let out = Path::new("out");
+        let full = anyhow::Error::from(io::Error::from(io::ErrorKind::StorageFull))
+            .context("Failed to write manifest: out/pages.json");
+        let err = explain_disk_full(full, out);
+        assert!(err.to_string().contains("disk holding out is full"));
+
+        let pdftoppm = anyhow::anyhow!("pdftoppm failed on pages 1-4: No space left on device");
+        assert!(is_disk_full(&pdftoppm));
+
+        let other = anyhow::Error::from(io::Error::from(io::ErrorKind::PermissionDenied));
+        assert!(!explain_disk_full(other, out).to_string().contains("full"));
+    }
+
+    #[test]
+    fn test_parse_rotation() {
+        assert_eq!(parse_rotation("0"), Ok(0));
+        assert_eq!(parse_rotation("270"), Ok(270));
+        assert!(parse_rotation("45").is_err());
+        assert!(parse_rotation("-90").is_err());
+    }
+
+    #[test]
+    fn test_collect_pdfs_expands_directories() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in ["b.pdf", "a.PDF", "notes.txt"] {
+            fs::write(dir.path().join(name), b"").unwrap();
+        }
+        fs::create_dir(dir.path().join("nested.pdf")).unwrap();
+        let single = dir.path().join("single.pdf");
+        fs::write(&single, b"").unwrap();
+
+        let pdfs = collect_pdfs(&[dir.path().to_path_buf()]).unwrap();
+        assert_eq!(
+            pdfs,
+            [
+                dir.path().join("a.PDF"),
+                dir.path().join("b.pdf"),
+                dir.path().join("single.pdf")
+            ]
+        );
+
+        let err = collect_pdfs(&[single, dir.path().join("notes.txt")]).unwrap_err();
+        assert!(err.to_string().contains("does not appear to be a PDF"));
+        let err = collect_pdfs(&[dir.path().join("missing.pdf")]).unwrap_err();
+        assert!(err.to_string().contains("PDF file not found"));
+    }
+
+    #[test]
+    fn test_batch_output_dirs_are_named_after_stems() {
+        let root = Path::new("out");
+        let dirs = batch_output_dirs(
+            &[PathBuf::from("scans/jan.pdf"), PathBuf::from("feb.pdf")],
+            root,
+        )
+        .unwrap();
+        assert_eq!(dirs, [root.join("jan"), root.join("feb")]);
+
+        // Same stem from two directories would mix their pages
+        let err = batch_output_dirs(
+            &[PathBuf::from("a/jan.pdf"), PathBuf::from("b/jan.pdf")],
+            root,
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("both be converted into"));
+    }
 }
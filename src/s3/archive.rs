@@ -0,0 +1,312 @@
+use anyhow::{Context, Result};
+use async_compression::tokio::write::GzipEncoder;
+use aws_sdk_s3::Client;
+use indicatif::ProgressBar;
+use std::collections::HashMap;
+use std::io;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context as TaskContext, Poll};
+use tokio::io::{AsyncRead, AsyncWriteExt, DuplexStream, ReadBuf};
+use tracing::{debug, info};
+
+use super::budget::PartBudget;
+use super::multipart::upload_multipart_from_reader;
+
+/// Metadata key holding the number of files in the archive
+pub const META_FILE_COUNT: &str = "file-count";
+/// Metadata key holding the total uncompressed size of all members
+pub const META_ORIGINAL_SIZE: &str = "original-size";
+/// Metadata key holding the hash of the member list
+pub const META_MEMBERS_HASH: &str = "members-hash";
+
+// Size of the in-memory pipe between the tar.gz producer and the part reader
+const PIPE_CAPACITY: usize = 256 * 1024;
+
+/// A local file to be stored in the archive
+#[derive(Debug, Clone)]
+pub struct ArchiveMember {
+    /// Path on the local filesystem
+    pub path: PathBuf,
+    /// Name inside the archive (relative path)
+    pub name: String,
+    /// File size in bytes
+    pub size: u64,
+    /// Modification time in seconds since the epoch
+    pub modified: u64,
+}
+
+impl ArchiveMember {
+    /// Build a member from a local path, reading size and mtime from disk
+    pub async fn from_path(path: PathBuf, name: String) -> Result<Self> {
+        let metadata = tokio::fs::metadata(&path)
+            .await
+            .with_context(|| format!("Failed to access file: {}", path.display()))?;
+        let modified = metadata
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+
+        Ok(Self {
+            path,
+            name,
+            size: metadata.len(),
+            modified,
+        })
+    }
+}
+
+/// Hash the member list (names, sizes and mtimes) independent of input order
+///
+/// Used instead of a content comparison: the archive is compressed on the fly,
+/// so its ETag can never be predicted locally.
+pub fn members_hash(members: &[ArchiveMember]) -> String {
+    let mut entries: Vec<_> = members.iter().collect();
+    entries.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let mut hasher = blake3::Hasher::new();
+    for member in entries {
+        hasher.update(member.name.as_bytes());
+        hasher.update(b"\0");
+        hasher.update(&member.size.to_le_bytes());
+        hasher.update(&member.modified.to_le_bytes());
+    }
+    hasher.finalize().to_hex().to_string()
+}
+
+/// User metadata stored on the archive object
+pub fn archive_metadata(members: &[ArchiveMember]) -> HashMap<String, String> {
+    let original_size: u64 = members.iter().map(|m| m.size).sum();
+
+    HashMap::from([
+        (META_FILE_COUNT.to_string(), members.len().to_string()),
+        (META_ORIGINAL_SIZE.to_string(), original_size.to_string()),
+        (META_MEMBERS_HASH.to_string(), members_hash(members)),
+    ])
+}
+
+/// Check whether the remote archive was built from the same member list
+pub async fn is_archive_unchanged(
+    client: &Client,
+    bucket: &str,
+    s3_key: &str,
+    members: &[ArchiveMember],
+) -> bool {
+    let Ok(head) = client.head_object().bucket(bucket).key(s3_key).send().await else {
+        return false;
+    };
+
+    let remote_hash = head.metadata().and_then(|m| m.get(META_MEMBERS_HASH));
+    debug!("Remote archive members hash: {:?}", remote_hash);
+
+    remote_hash.is_some_and(|hash| *hash == members_hash(members))
+}
+
+/// Stream a tar.gz of the members straight into a multipart upload
+///
+/// The archive is produced by a background task writing into an in-memory
+/// pipe; the upload side reads fixed-size parts from the other end, so no
+/// temporary archive ever touches the disk.
+///
+/// # Returns
+///
+/// Number of compressed bytes uploaded
+pub async fn upload_archive(
+    client: &Client,
+    bucket: &str,
+    s3_key: &str,
+    members: &[ArchiveMember],
+    budget: &PartBudget,
+    pb: Option<&ProgressBar>,
+) -> Result<u64> {
+    let (mut writer, reader) = tokio::io::duplex(PIPE_CAPACITY);
+    let producer_error = Arc::new(Mutex::new(None));
+
+    let producer = {
+        let members = members.to_vec();
+        let producer_error = Arc::clone(&producer_error);
+
+        tokio::spawn(async move {
+            let result = write_archive(&mut writer, &members).await;
+            if let Err(e) = &result {
+                // Record the failure before the writer is dropped so the reader
+                // reports an error instead of a clean (truncated) EOF
+                *producer_error.lock().unwrap() = Some(format!("{:#}", e));
+            }
+            drop(writer);
+            result
+        })
+    };
+
+    let mut reader = GuardedReader {
+        inner: reader,
+        producer_error,
+    };
+
+    let metadata = archive_metadata(members);
+    let uploaded =
+        upload_multipart_from_reader(client, bucket, s3_key, &mut reader, &metadata, budget, pb)
+            .await;
+
+    // Dropping the reader unblocks the producer if the upload bailed early
+    drop(reader);
+    let produced = producer.await.context("Archive producer task panicked")?;
+
+    let uploaded = uploaded?;
+    produced?;
+
+    info!(
+        "Uploaded archive of {} files ({} compressed bytes) -> s3://{}/{}",
+        members.len(),
+        uploaded,
+        bucket,
+        s3_key
+    );
+
+    Ok(uploaded)
+}
+
+/// Write all members as a gzip-compressed tarball into the writer
+async fn write_archive(writer: &mut DuplexStream, members: &[ArchiveMember]) -> Result<()> {
+    // Terminated explicitly via finish(), which lets the builder borrow the pipe
+    let mut builder = tokio_tar::Builder::new_non_terminated(GzipEncoder::new(writer));
+
+    for member in members {
+        builder
+            .append_path_with_name(&member.path, &member.name)
+            .await
+            .with_context(|| format!("Failed to add {} to archive", member.path.display()))?;
+    }
+
+    builder
+        .finish()
+        .await
+        .context("Failed to finish tar stream")?;
+    builder
+        .get_mut()
+        .shutdown()
+        .await
+        .context("Failed to finish gzip stream")?;
+
+    Ok(())
+}
+
+/// Pipe reader that turns a failed producer into a read error at EOF
+struct GuardedReader {
+    inner: DuplexStream,
+    producer_error: Arc<Mutex<Option<String>>>,
+}
+
+impl AsyncRead for GuardedReader {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let before = buf.filled().len();
+        match Pin::new(&mut self.inner).poll_read(cx, buf) {
+            Poll::Ready(Ok(())) if buf.filled().len() == before => {
+                match self.producer_error.lock().unwrap().take() {
+                    Some(message) => Poll::Ready(Err(io::Error::other(message))),
+                    None => Poll::Ready(Ok(())),
+                }
+            }
+            other => other,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+    use std::io::Write;
+    use tokio::io::AsyncReadExt;
+
+    fn member(name: &str, size: u64, modified: u64) -> ArchiveMember {
+        ArchiveMember {
+            path: PathBuf::from(name),
+            name: name.to_string(),
+            size,
+            modified,
+        }
+    }
+
+    #[test]
+    fn test_members_hash_is_order_independent() {
+        let a = vec![member("a.mp4", 1, 10), member("b.mp4", 2, 20)];
+        let b = vec![member("b.mp4", 2, 20), member("a.mp4", 1, 10)];
+        assert_eq!(members_hash(&a), members_hash(&b));
+
+        let changed = vec![member("a.mp4", 1, 11), member("b.mp4", 2, 20)];
+        assert_ne!(members_hash(&a), members_hash(&changed));
+    }
+
+    #[test]
+    fn test_archive_metadata() {
+        let members = vec![member("a.mp4", 100, 1), member("dir/b.mp4", 23, 2)];
+        let metadata = archive_metadata(&members);
+
+        assert_eq!(metadata.get(META_FILE_COUNT), Some(&"2".to_string()));
+        assert_eq!(metadata.get(META_ORIGINAL_SIZE), Some(&"123".to_string()));
+        assert_eq!(
+            metadata.get(META_MEMBERS_HASH),
+            Some(&members_hash(&members))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_write_archive_produces_gzip() {
+        let mut temp_file = tempfile::NamedTempFile::new().unwrap();
+        write!(temp_file, "hello archive").unwrap();
+        temp_file.flush().unwrap();
+
+        let members =
+            vec![
+                ArchiveMember::from_path(temp_file.path().to_path_buf(), "hello.txt".to_string())
+                    .await
+                    .unwrap(),
+            ];
+
+        let (mut writer, mut reader) = tokio::io::duplex(PIPE_CAPACITY);
+        let producer = tokio::spawn(async move { write_archive(&mut writer, &members).await });
+
+        let mut compressed = Vec::new();
+        reader.read_to_end(&mut compressed).await.unwrap();
+        producer.await.unwrap().unwrap();
+
+        // gzip magic bytes
+        assert_eq!(&compressed[..2], &[0x1f, 0x8b]);
+
+        // Round-trip through a decoder and check the member survived
+        let decoder = async_compression::tokio::bufread::GzipDecoder::new(compressed.as_slice());
+        let mut archive = tokio_tar::Archive::new(decoder);
+        let mut entries = archive.entries().unwrap();
+        let mut entry = entries.next().await.unwrap().unwrap();
+        assert_eq!(entry.path().unwrap().to_string_lossy(), "hello.txt");
+
+        let mut content = String::new();
+        entry.read_to_string(&mut content).await.unwrap();
+        assert_eq!(content, "hello archive");
+        assert!(entries.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_guarded_reader_reports_producer_failure() {
+        let (writer, reader) = tokio::io::duplex(64);
+        let producer_error = Arc::new(Mutex::new(Some("disk on fire".to_string())));
+        drop(writer);
+
+        let mut reader = GuardedReader {
+            inner: reader,
+            producer_error,
+        };
+
+        let mut buf = Vec::new();
+        let err = reader.read_to_end(&mut buf).await.unwrap_err();
+        assert!(err.to_string().contains("disk on fire"));
+    }
+}
@@ -0,0 +1,207 @@
+use anyhow::{Context, Result};
+use std::sync::Arc;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use super::multipart::MIN_PART_SIZE;
+
+/// Global memory budget for in-flight multipart part buffers
+///
+/// The budget is expressed in part units: a budget of 256MB with 10MB parts
+/// allows 25 buffers to be allocated at once across all workers. Workers
+/// acquire a permit before reading a part into memory and release it once the
+/// part has been uploaded, so concurrency throttles instead of over-allocating.
+#[derive(Debug, Clone)]
+pub struct PartBudget {
+    semaphore: Arc<Semaphore>,
+    part_size: usize,
+    max_parts: usize,
+}
+
+impl PartBudget {
+    /// Create a budget for the given number of bytes and part size
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the part size is below the S3 minimum or the
+    /// budget cannot hold even a single part
+    pub fn new(budget_bytes: u64, part_size: usize) -> Result<Self> {
+        if part_size < MIN_PART_SIZE {
+            anyhow::bail!(
+                "Part size must be at least {} MB (got {} bytes)",
+                MIN_PART_SIZE / 1024 / 1024,
+                part_size
+            );
+        }
+
+        let max_parts = (budget_bytes / part_size as u64) as usize;
+        if max_parts == 0 {
+            anyhow::bail!(
+                "Memory budget of {} MB cannot fit a single {} MB part. \
+                 Increase --memory-budget-mb or lower --part-size-mb",
+                budget_bytes / 1024 / 1024,
+                part_size / 1024 / 1024
+            );
+        }
+
+        Ok(Self {
+            semaphore: Arc::new(Semaphore::new(max_parts)),
+            part_size,
+            max_parts,
+        })
+    }
+
+    /// Size of each multipart part in bytes
+    pub fn part_size(&self) -> usize {
+        self.part_size
+    }
+
+    /// Maximum number of part buffers allowed in flight
+    pub fn max_parts(&self) -> usize {
+        self.max_parts
+    }
+
+    /// Wait until a part buffer fits in the budget
+    ///
+    /// The returned permit must be held for as long as the buffer is alive.
+    pub async fn acquire(&self) -> Result<OwnedSemaphorePermit> {
+        Arc::clone(&self.semaphore)
+            .acquire_owned()
+            .await
+            .context("Part buffer budget was closed")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::s3::multipart::upload_multipart_from_reader;
+    use aws_sdk_s3::config::{BehaviorVersion, Credentials, Region};
+    use aws_sdk_s3::Client;
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+    use std::time::{Duration, Instant};
+    use wiremock::matchers::{method, query_param, query_param_is_missing};
+    use wiremock::{Mock, MockServer, Request, ResponseTemplate};
+
+    const MB: usize = 1024 * 1024;
+
+    #[test]
+    fn test_budget_in_part_units() {
+        let budget = PartBudget::new(256 * MB as u64, 10 * MB).unwrap();
+        assert_eq!(budget.max_parts(), 25);
+        assert_eq!(budget.part_size(), 10 * MB);
+    }
+
+    #[test]
+    fn test_budget_too_small_for_one_part() {
+        let err = PartBudget::new(64 * MB as u64, 100 * MB).unwrap_err();
+        assert!(err.to_string().contains("cannot fit a single 100 MB part"));
+    }
+
+    #[test]
+    fn test_part_size_below_minimum() {
+        assert!(PartBudget::new(256 * MB as u64, MB).is_err());
+    }
+
+    /// Stub S3 for multipart uploads; records when each part arrives and
+    /// answers it after `part_delay`
+    async fn multipart_server(
+        part_delay: Duration,
+        arrivals: Arc<Mutex<Vec<Instant>>>,
+    ) -> MockServer {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(query_param("uploads", ""))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                "<InitiateMultipartUploadResult><Bucket>media</Bucket><Key>k</Key>\
+                 <UploadId>upload-1</UploadId></InitiateMultipartUploadResult>",
+            ))
+            .mount(&server)
+            .await;
+        Mock::given(method("PUT"))
+            .and(query_param("uploadId", "upload-1"))
+            .respond_with(move |_: &Request| {
+                arrivals.lock().unwrap().push(Instant::now());
+                ResponseTemplate::new(200)
+                    .insert_header("ETag", "\"part\"")
+                    .set_delay(part_delay)
+            })
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(query_param_is_missing("uploads"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                "<CompleteMultipartUploadResult><Bucket>media</Bucket><Key>k</Key>\
+                 <ETag>\"done-2\"</ETag></CompleteMultipartUploadResult>",
+            ))
+            .mount(&server)
+            .await;
+        server
+    }
+
+    fn test_client(endpoint: &str) -> Client {
+        let config = aws_sdk_s3::Config::builder()
+            .behavior_version(BehaviorVersion::latest())
+            .region(Region::new("us-west-2"))
+            .endpoint_url(endpoint)
+            .force_path_style(true)
+            .credentials_provider(Credentials::new("AKIA", "secret", None, None, "test"))
+            .build();
+        Client::from_conf(config)
+    }
+
+    /// Most parts the server was handling at once
+    ///
+    /// A part's buffer lives at least from its arrival until the delayed
+    /// response, so this is a lower bound of the buffers held at that time.
+    fn peak_overlap(arrivals: &[Instant], part_delay: Duration) -> usize {
+        arrivals
+            .iter()
+            .map(|&start| {
+                arrivals
+                    .iter()
+                    .filter(|&&other| other <= start && start < other + part_delay)
+                    .count()
+            })
+            .max()
+            .unwrap_or(0)
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_uploads_respect_budget() {
+        let budget = PartBudget::new(3 * MIN_PART_SIZE as u64, MIN_PART_SIZE).unwrap();
+        assert_eq!(budget.max_parts(), 3);
+        let part_delay = Duration::from_millis(100);
+        let arrivals = Arc::new(Mutex::new(Vec::new()));
+        let server = multipart_server(part_delay, Arc::clone(&arrivals)).await;
+        let client = test_client(&server.uri());
+
+        // 6 uploads of 2 parts each, all competing for 3 buffers
+        let data = Arc::new(vec![7u8; MIN_PART_SIZE + 1]);
+        let uploads = (0..6).map(|i| {
+            let (client, budget, data) = (client.clone(), budget.clone(), Arc::clone(&data));
+            tokio::spawn(async move {
+                upload_multipart_from_reader(
+                    &client,
+                    "media",
+                    &format!("video-{}.mp4", i),
+                    &mut data.as_slice(),
+                    &HashMap::new(),
+                    &budget,
+                    None,
+                )
+                .await
+            })
+        });
+        for upload in futures::future::join_all(uploads).await {
+            assert_eq!(upload.unwrap().unwrap(), MIN_PART_SIZE as u64 + 1);
+        }
+
+        let arrivals = arrivals.lock().unwrap();
+        assert_eq!(arrivals.len(), 12);
+        let peak = peak_overlap(&arrivals, part_delay);
+        assert!(peak <= budget.max_parts(), "{} parts in flight", peak);
+        // The uploads did contend for the budget
+        assert!(peak >= 2, "{} parts in flight", peak);
+    }
+}
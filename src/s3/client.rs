@@ -1,28 +1,51 @@
 use anyhow::Result;
 use aws_config::BehaviorVersion;
+use aws_sdk_s3::config::IdentityCache;
 use aws_sdk_s3::Client;
+use std::future::Future;
+use tracing::warn;
 
+use super::credentials::{DefaultCredentialsSource, RefreshableCredentials};
+use super::provider::Provider;
+use super::upload::is_expired_credentials;
 use crate::config::Config;
 
 #[derive(Clone)]
 pub struct S3Client {
     client: Client,
+    credentials: RefreshableCredentials,
     pub config: Config,
 }
 
 impl S3Client {
     pub async fn new(config: Config) -> Result<Self> {
+        let credentials = RefreshableCredentials::new(DefaultCredentialsSource::new(&config));
+
+        // RefreshableCredentials caches on its own; the SDK cache would keep
+        // serving expired credentials after a reload
         let mut aws_config = aws_config::defaults(BehaviorVersion::latest())
-            .region(aws_config::Region::new(config.region.clone()));
+            .region(aws_config::Region::new(config.region.clone()))
+            .credentials_provider(credentials.clone())
+            .identity_cache(IdentityCache::no_cache());
 
         if let Some(profile) = &config.profile {
             aws_config = aws_config.profile_name(profile);
         }
+        if let Some(endpoint_url) = &config.endpoint_url {
+            aws_config = aws_config.endpoint_url(endpoint_url);
+        }
 
         let sdk_config = aws_config.load().await;
-        let client = Client::new(&sdk_config);
+        let s3_config = aws_sdk_s3::config::Builder::from(&sdk_config)
+            .force_path_style(config.provider().quirks().path_style)
+            .build();
+        let client = Client::from_conf(s3_config);
 
-        Ok(Self { client, config })
+        Ok(Self {
+            client,
+            credentials,
+            config,
+        })
     }
 
     pub fn client(&self) -> &Client {
@@ -32,4 +55,158 @@ impl S3Client {
     pub fn bucket(&self) -> &str {
         &self.config.bucket
     }
+
+    /// The provider this client adapts to
+    pub fn provider(&self) -> Provider {
+        self.config.provider()
+    }
+
+    /// Run an S3 operation, reloading credentials and running it once more if
+    /// they expired
+    ///
+    /// `on_refresh` is called before the retry when this call reloaded the
+    /// credentials, so a batch of workers hitting the same expiry reports it
+    /// once. If the credentials cannot be reloaded the original error is
+    /// returned with the reason attached.
+    pub async fn with_credential_refresh<T, F, Fut>(
+        &self,
+        operation: F,
+        on_refresh: impl FnOnce(),
+    ) -> Result<T>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let generation = self.credentials.generation();
+        match operation().await {
+            Err(e) if is_expired_credentials(&e) => {
+                warn!("AWS credentials expired: {:#}", e);
+                match self.credentials.refresh(generation).await {
+                    Ok(true) => on_refresh(),
+                    Ok(false) => {}
+                    Err(refresh_err) => {
+                        return Err(e.context(format!(
+                            "AWS credentials expired and could not be reloaded: {:#}",
+                            refresh_err
+                        )));
+                    }
+                }
+                operation().await
+            }
+            result => result,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::credentials::tests::RotatingProvider;
+    use super::super::upload::upload_file;
+    use super::*;
+    use aws_sdk_s3::config::{Credentials, Region};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use wiremock::matchers::{header, method};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    const EXPIRED_TOKEN: &str = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\
+        <Error><Code>ExpiredToken</Code>\
+        <Message>The provided token has expired.</Message></Error>";
+
+    fn test_client(endpoint: &str, credentials: RefreshableCredentials) -> S3Client {
+        let s3_config = aws_sdk_s3::Config::builder()
+            .behavior_version(BehaviorVersion::latest())
+            .region(Region::new("us-west-2"))
+            .endpoint_url(endpoint)
+            .force_path_style(true)
+            .credentials_provider(credentials.clone())
+            .identity_cache(IdentityCache::no_cache())
+            .build();
+        S3Client {
+            client: Client::from_conf(s3_config),
+            credentials,
+            config: Config {
+                region: "us-west-2".to_string(),
+                profile: None,
+                role_arn: None,
+                bucket: "bucket".to_string(),
+                target_path: String::new(),
+                endpoint_url: None,
+                provider: None,
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn test_upload_refreshes_expired_credentials() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("video.mp4");
+        std::fs::write(&file, b"frames").unwrap();
+
+        // The first session expires mid-run; the reloaded one is accepted
+        let server = MockServer::start().await;
+        Mock::given(method("PUT"))
+            .and(header("x-amz-security-token", "token-1"))
+            .respond_with(ResponseTemplate::new(400).set_body_string(EXPIRED_TOKEN))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("PUT"))
+            .and(header("x-amz-security-token", "token-2"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let provider = RotatingProvider::default();
+        let loads = Arc::clone(&provider.loads);
+        let s3 = test_client(&server.uri(), RefreshableCredentials::new(provider));
+
+        let refreshed = AtomicUsize::new(0);
+        s3.with_credential_refresh(
+            || upload_file(s3.client(), s3.bucket(), "uploads/video.mp4", &file, None),
+            || {
+                refreshed.fetch_add(1, Ordering::SeqCst);
+            },
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(refreshed.load(Ordering::SeqCst), 1);
+        assert_eq!(loads.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_static_credentials_report_expiry() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("video.mp4");
+        std::fs::write(&file, b"frames").unwrap();
+
+        let server = MockServer::start().await;
+        Mock::given(method("PUT"))
+            .respond_with(ResponseTemplate::new(400).set_body_string(EXPIRED_TOKEN))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let credentials = RefreshableCredentials::new(Credentials::new(
+            "AKIASTATIC",
+            "secret",
+            Some("token".to_string()),
+            None,
+            "env",
+        ));
+        let s3 = test_client(&server.uri(), credentials);
+
+        let err = s3
+            .with_credential_refresh(
+                || upload_file(s3.client(), s3.bucket(), "uploads/video.mp4", &file, None),
+                || panic!("static credentials cannot be refreshed"),
+            )
+            .await
+            .unwrap_err();
+        let message = format!("{:#}", err);
+        assert!(message.contains("could not be reloaded"), "{}", message);
+        assert!(is_expired_credentials(&err));
+    }
 }
@@ -1,10 +1,18 @@
 use anyhow::Result;
 use aws_sdk_s3::Client;
 use md5::{Digest, Md5};
+use sha2::Sha256;
+use std::collections::HashMap;
 use std::path::Path;
 use tokio::io::AsyncReadExt;
 use tracing::{debug, trace};
 
+use super::provider::{Provider, Quirks};
+
+/// Metadata key holding the hex sha256 of the content (sent as
+/// `x-amz-meta-sha256`) on providers whose ETags can't be relied on
+pub const META_SHA256: &str = "sha256";
+
 #[derive(Debug, PartialEq)]
 pub enum FileComparison {
     /// File doesn't exist on S3
@@ -40,6 +48,21 @@ pub async fn compare_file(
     bucket: &str,
     s3_key: &str,
     local_path: &Path,
+) -> Result<FileComparison> {
+    compare_file_with_quirks(client, bucket, s3_key, local_path, &Provider::Aws.quirks()).await
+}
+
+/// Compare local file with remote S3 object, adapted to the provider
+///
+/// With `prefer_sha256` a `sha256` in the object's metadata decides before
+/// the ETag is looked at. Without `trust_multipart_etag` a multipart ETag
+/// counts as different, so the file is uploaded again and gets a sha256.
+pub async fn compare_file_with_quirks(
+    client: &Client,
+    bucket: &str,
+    s3_key: &str,
+    local_path: &Path,
+    quirks: &Quirks,
 ) -> Result<FileComparison> {
     trace!(
         "Comparing local file {} with s3://{}/{}",
@@ -73,6 +96,22 @@ pub async fn compare_file(
                 local_size
             );
 
+            if quirks.prefer_sha256
+                && let Some(remote_sha256) = head.metadata().and_then(|m| m.get(META_SHA256))
+            {
+                let local_sha256 = compute_file_sha256(local_path).await?;
+                return if local_sha256.eq_ignore_ascii_case(remote_sha256) {
+                    debug!("File content matches (sha256: {})", local_sha256);
+                    Ok(FileComparison::Identical)
+                } else {
+                    debug!(
+                        "File content differs: local sha256={}, remote sha256={}",
+                        local_sha256, remote_sha256
+                    );
+                    Ok(FileComparison::Different)
+                };
+            }
+
             // Size matches - now compare content hash
             // For S3 simple uploads (non-multipart), ETag is MD5
             // For multipart, it's complex (MD5 of MD5s with part count suffix like "abc-2")
@@ -81,6 +120,13 @@ pub async fn compare_file(
 
                 // Check if it's a multipart upload (contains '-')
                 if etag_clean.contains('-') {
+                    if !quirks.trust_multipart_etag {
+                        debug!(
+                            "Multipart ETag {} can't be verified on this provider, treating as different",
+                            etag_clean
+                        );
+                        return Ok(FileComparison::Different);
+                    }
                     debug!(
                         "Remote file uses multipart upload (ETag: {}), using size-only comparison",
                         etag_clean
@@ -146,11 +192,45 @@ async fn compute_file_md5(path: &Path) -> Result<String> {
     Ok(format!("{:x}", hasher.finalize()))
 }
 
+/// Compute the hex sha256 of a local file, read in chunks
+pub async fn compute_file_sha256(path: &Path) -> Result<String> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; 64 * 1024];
+
+    loop {
+        let n = file.read(&mut buffer).await?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buffer[..n]);
+    }
+
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// User metadata recording a file's sha256, for providers that compare it
+pub async fn sha256_metadata(path: &Path, quirks: &Quirks) -> Result<HashMap<String, String>> {
+    if !quirks.prefer_sha256 {
+        return Ok(HashMap::new());
+    }
+    Ok(HashMap::from([(
+        META_SHA256.to_string(),
+        compute_file_sha256(path).await?,
+    )]))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use aws_sdk_s3::config::{BehaviorVersion, Credentials, Region};
     use std::io::Write;
     use tempfile::NamedTempFile;
+    use wiremock::matchers::method;
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    const HELLO_MD5: &str = "5eb63bbbe01eeed093cb22bb8f5acdc3";
+    const HELLO_SHA256: &str = "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9";
 
     #[tokio::test]
     async fn test_compute_file_md5() {
@@ -174,6 +254,116 @@ mod tests {
         assert_eq!(hash, "d41d8cd98f00b204e9800998ecf8427e");
     }
 
+    fn test_client(endpoint: &str) -> Client {
+        let config = aws_sdk_s3::Config::builder()
+            .behavior_version(BehaviorVersion::latest())
+            .region(Region::new("us-west-2"))
+            .endpoint_url(endpoint)
+            .force_path_style(true)
+            .credentials_provider(Credentials::new("AKIA", "secret", None, None, "test"))
+            .build();
+        Client::from_conf(config)
+    }
+
+    /// HEAD response for an 11-byte object
+    fn head(etag: &str, sha256: Option<&str>) -> ResponseTemplate {
+        let mut response = ResponseTemplate::new(200)
+            .insert_header("Content-Length", "11")
+            .insert_header("ETag", etag);
+        if let Some(sha256) = sha256 {
+            response = response.insert_header("x-amz-meta-sha256", sha256);
+        }
+        response
+    }
+
+    async fn compare_with(response: ResponseTemplate, provider: Provider) -> FileComparison {
+        let mut temp_file = NamedTempFile::new().unwrap();
+        write!(temp_file, "hello world").unwrap();
+        temp_file.flush().unwrap();
+
+        let server = MockServer::start().await;
+        Mock::given(method("HEAD"))
+            .respond_with(response)
+            .mount(&server)
+            .await;
+
+        let client = test_client(&server.uri());
+        compare_file_with_quirks(
+            &client,
+            "bucket",
+            "hello.txt",
+            temp_file.path(),
+            &provider.quirks(),
+        )
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_compute_file_sha256() {
+        let mut temp_file = NamedTempFile::new().unwrap();
+        write!(temp_file, "hello world").unwrap();
+        temp_file.flush().unwrap();
+
+        let hash = compute_file_sha256(temp_file.path()).await.unwrap();
+        assert_eq!(hash, HELLO_SHA256);
+
+        let r2 = sha256_metadata(temp_file.path(), &Provider::R2.quirks())
+            .await
+            .unwrap();
+        assert_eq!(r2.get(META_SHA256).map(String::as_str), Some(HELLO_SHA256));
+        let aws = sha256_metadata(temp_file.path(), &Provider::Aws.quirks())
+            .await
+            .unwrap();
+        assert!(aws.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_multipart_etag_depends_on_provider() {
+        let multipart = || head("\"0123456789abcdef0123456789abcdef-3\"", None);
+
+        assert_eq!(
+            compare_with(multipart(), Provider::Aws).await,
+            FileComparison::Identical
+        );
+        assert_eq!(
+            compare_with(multipart(), Provider::Minio).await,
+            FileComparison::Identical
+        );
+        assert_eq!(
+            compare_with(multipart(), Provider::R2).await,
+            FileComparison::Different
+        );
+        assert_eq!(
+            compare_with(multipart(), Provider::Generic).await,
+            FileComparison::Different
+        );
+    }
+
+    #[tokio::test]
+    async fn test_sha256_metadata_decides_on_r2() {
+        // An ETag that is neither the MD5 nor a multipart ETag, as R2 can return
+        let etag = "\"ffffffffffffffffffffffffffffffff\"";
+
+        assert_eq!(
+            compare_with(head(etag, Some(HELLO_SHA256)), Provider::R2).await,
+            FileComparison::Identical
+        );
+        assert_eq!(
+            compare_with(head(etag, Some(&"0".repeat(64))), Provider::R2).await,
+            FileComparison::Different
+        );
+        // AWS ignores the metadata and goes by the ETag
+        assert_eq!(
+            compare_with(head(etag, Some(HELLO_SHA256)), Provider::Aws).await,
+            FileComparison::Different
+        );
+        assert_eq!(
+            compare_with(head(&format!("\"{}\"", HELLO_MD5), None), Provider::R2).await,
+            FileComparison::Identical
+        );
+    }
+
     #[tokio::test]
     async fn test_compute_file_md5_large() {
         let mut temp_file = NamedTempFile::new().unwrap();
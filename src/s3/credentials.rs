@@ -0,0 +1,270 @@
+use anyhow::{Context, Result};
+use aws_config::default_provider::credentials::DefaultCredentialsChain;
+use aws_config::sts::AssumeRoleProvider;
+use aws_config::{BehaviorVersion, Region};
+use aws_credential_types::provider::{self, future, ProvideCredentials, SharedCredentialsProvider};
+use aws_credential_types::Credentials;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::sync::Mutex;
+use tracing::{debug, info};
+
+use crate::config::Config;
+
+/// Credentials are reloaded this long before they expire
+const EXPIRY_BUFFER: Duration = Duration::from_secs(5 * 60);
+
+/// Session name used when assuming `--role-arn`
+const ROLE_SESSION_NAME: &str = "swiss-knife-s3upload";
+
+/// Credentials provider that can be forced to reload in the middle of a run
+///
+/// The SDK's own identity cache only refreshes credentials that carry an
+/// expiry, so static credentials injected through the environment (for
+/// example an exported SSO session) are used until S3 rejects them. This
+/// provider caches the credentials itself, reloads them shortly before a
+/// known expiry, and can be told to reload them when S3 reports
+/// `ExpiredToken`. Clients using it must disable the SDK identity cache so a
+/// reload takes effect on the next request.
+#[derive(Debug, Clone)]
+pub struct RefreshableCredentials {
+    source: SharedCredentialsProvider,
+    cached: Arc<Mutex<Option<Credentials>>>,
+    generation: Arc<AtomicU64>,
+}
+
+impl RefreshableCredentials {
+    /// Wrap a provider that yields fresh credentials each time it is asked
+    pub fn new(source: impl ProvideCredentials + 'static) -> Self {
+        Self {
+            source: SharedCredentialsProvider::new(source),
+            cached: Arc::new(Mutex::new(None)),
+            generation: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Number of forced reloads so far
+    ///
+    /// Read it before an operation and pass it to [`refresh`](Self::refresh)
+    /// if the operation fails with expired credentials, so that concurrent
+    /// workers hitting the same expiry reload only once.
+    pub fn generation(&self) -> u64 {
+        self.generation.load(Ordering::Acquire)
+    }
+
+    /// Reload credentials from the source
+    ///
+    /// Returns `Ok(false)` without reloading when another caller already did
+    /// since `seen_generation`. Fails if the source cannot provide credentials
+    /// or hands back the same ones again, as static credentials do.
+    pub async fn refresh(&self, seen_generation: u64) -> Result<bool> {
+        let mut cached = self.cached.lock().await;
+        if self.generation() != seen_generation {
+            return Ok(false);
+        }
+
+        let fresh = self
+            .source
+            .provide_credentials()
+            .await
+            .context("Failed to reload AWS credentials")?;
+        if let Some(stale) = cached.as_ref()
+            && same_keys(stale, &fresh)
+        {
+            anyhow::bail!("Reloading AWS credentials returned the same expired credentials");
+        }
+
+        info!("Reloaded AWS credentials ({})", fresh.access_key_id());
+        *cached = Some(fresh);
+        self.generation.fetch_add(1, Ordering::AcqRel);
+        Ok(true)
+    }
+
+    /// Cached credentials, loaded from the source when missing or expiring
+    async fn load(&self) -> provider::Result {
+        let mut cached = self.cached.lock().await;
+        if let Some(credentials) = cached.as_ref()
+            && !expires_soon(credentials, SystemTime::now())
+        {
+            return Ok(credentials.clone());
+        }
+
+        debug!("Loading AWS credentials");
+        let credentials = self.source.provide_credentials().await?;
+        *cached = Some(credentials.clone());
+        Ok(credentials)
+    }
+}
+
+impl ProvideCredentials for RefreshableCredentials {
+    fn provide_credentials<'a>(&'a self) -> future::ProvideCredentials<'a>
+    where
+        Self: 'a,
+    {
+        future::ProvideCredentials::new(self.load())
+    }
+}
+
+/// Source that rebuilds the default credential chain on every load
+///
+/// Rebuilding re-reads the environment, profile files and the SSO token
+/// cache, so a session renewed in another terminal is picked up. With a
+/// role ARN the chain's credentials are used to assume that role again.
+#[derive(Debug)]
+pub struct DefaultCredentialsSource {
+    region: String,
+    profile: Option<String>,
+    role_arn: Option<String>,
+}
+
+impl DefaultCredentialsSource {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            region: config.region.clone(),
+            profile: config.profile.clone(),
+            role_arn: config.role_arn.clone(),
+        }
+    }
+
+    async fn load(&self) -> provider::Result {
+        let region = Region::new(self.region.clone());
+        let mut chain = DefaultCredentialsChain::builder().region(region.clone());
+        if let Some(profile) = &self.profile {
+            chain = chain.profile_name(profile);
+        }
+        let chain = chain.build().await;
+
+        match &self.role_arn {
+            Some(role_arn) => {
+                let sdk_config = aws_config::defaults(BehaviorVersion::latest())
+                    .region(region.clone())
+                    .load()
+                    .await;
+                AssumeRoleProvider::builder(role_arn)
+                    .session_name(ROLE_SESSION_NAME)
+                    .region(region)
+                    .configure(&sdk_config)
+                    .build_from_provider(chain)
+                    .await
+                    .provide_credentials()
+                    .await
+            }
+            None => chain.provide_credentials().await,
+        }
+    }
+}
+
+impl ProvideCredentials for DefaultCredentialsSource {
+    fn provide_credentials<'a>(&'a self) -> future::ProvideCredentials<'a>
+    where
+        Self: 'a,
+    {
+        future::ProvideCredentials::new(self.load())
+    }
+}
+
+fn expires_soon(credentials: &Credentials, now: SystemTime) -> bool {
+    credentials
+        .expiry()
+        .is_some_and(|expiry| expiry <= now + EXPIRY_BUFFER)
+}
+
+fn same_keys(a: &Credentials, b: &Credentials) -> bool {
+    a.access_key_id() == b.access_key_id()
+        && a.secret_access_key() == b.secret_access_key()
+        && a.session_token() == b.session_token()
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+
+    /// Provider handing out a new session each time, like a renewed SSO login
+    #[derive(Debug, Default)]
+    pub(crate) struct RotatingProvider {
+        pub(crate) loads: Arc<AtomicUsize>,
+    }
+
+    impl ProvideCredentials for RotatingProvider {
+        fn provide_credentials<'a>(&'a self) -> future::ProvideCredentials<'a>
+        where
+            Self: 'a,
+        {
+            let n = self.loads.fetch_add(1, Ordering::SeqCst) + 1;
+            future::ProvideCredentials::ready(Ok(Credentials::new(
+                format!("AKIA{}", n),
+                "secret",
+                Some(format!("token-{}", n)),
+                None,
+                "test",
+            )))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_credentials_are_cached_until_refresh() {
+        let provider = RotatingProvider::default();
+        let loads = Arc::clone(&provider.loads);
+        let credentials = RefreshableCredentials::new(provider);
+
+        let first = credentials.provide_credentials().await.unwrap();
+        let again = credentials.provide_credentials().await.unwrap();
+        assert_eq!(first.access_key_id(), "AKIA1");
+        assert_eq!(again.access_key_id(), "AKIA1");
+        assert_eq!(loads.load(Ordering::SeqCst), 1);
+
+        // S3 reports the session expired halfway through the run
+        let seen = credentials.generation();
+        assert!(credentials.refresh(seen).await.unwrap());
+        let refreshed = credentials.provide_credentials().await.unwrap();
+        assert_eq!(refreshed.access_key_id(), "AKIA2");
+        assert_eq!(refreshed.session_token(), Some("token-2"));
+
+        // A second worker that saw the same expiry reuses the reload
+        assert!(!credentials.refresh(seen).await.unwrap());
+        assert_eq!(loads.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_refresh_fails_for_static_credentials() {
+        let credentials = RefreshableCredentials::new(Credentials::new(
+            "AKIASTATIC",
+            "secret",
+            Some("token".to_string()),
+            None,
+            "env",
+        ));
+        credentials.provide_credentials().await.unwrap();
+
+        let err = credentials.refresh(0).await.unwrap_err();
+        assert!(err.to_string().contains("same expired credentials"));
+        assert_eq!(credentials.generation(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_expiring_credentials_reload_on_their_own() {
+        let expiring = Credentials::new(
+            "AKIAOLD",
+            "secret",
+            None,
+            Some(SystemTime::now() + Duration::from_secs(60)),
+            "test",
+        );
+        assert!(expires_soon(&expiring, SystemTime::now()));
+        assert!(!expires_soon(
+            &Credentials::new("AKIA", "secret", None, None, "env"),
+            SystemTime::now()
+        ));
+
+        let provider = RotatingProvider::default();
+        let loads = Arc::clone(&provider.loads);
+        let credentials = RefreshableCredentials::new(provider);
+        *credentials.cached.lock().await = Some(expiring);
+
+        let loaded = credentials.provide_credentials().await.unwrap();
+        assert_eq!(loaded.access_key_id(), "AKIA1");
+        assert_eq!(loads.load(Ordering::SeqCst), 1);
+    }
+}
@@ -0,0 +1,200 @@
+use anyhow::{Context, Result};
+use aws_sdk_s3::{types::MetadataDirective, Client};
+use std::collections::HashMap;
+use std::path::Path;
+use tokio::io::AsyncReadExt;
+use tracing::{debug, info};
+
+/// Largest object a single CopyObject request can copy (5 GiB)
+pub const MAX_COPY_SIZE: u64 = 5 * 1024 * 1024 * 1024;
+
+/// Hex blake3 hash of a file's content, read in chunks
+pub async fn hash_file(path: &Path) -> Result<String> {
+    let mut file = tokio::fs::File::open(path)
+        .await
+        .with_context(|| format!("Failed to open file: {}", path.display()))?;
+    let mut hasher = blake3::Hasher::new();
+    let mut buffer = vec![0u8; 64 * 1024];
+
+    loop {
+        let n = file
+            .read(&mut buffer)
+            .await
+            .with_context(|| format!("Failed to read file: {}", path.display()))?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buffer[..n]);
+    }
+
+    Ok(hasher.finalize().to_hex().to_string())
+}
+
+/// For each file, the index of the first earlier file with the same size and
+/// hash, if any
+///
+/// Empty files and files too large for a single server-side copy are never
+/// matched; uploading them is as cheap or the only option.
+pub fn find_duplicates(files: &[(u64, &str)]) -> Vec<Option<usize>> {
+    let mut first_seen: HashMap<(u64, &str), usize> = HashMap::new();
+
+    files
+        .iter()
+        .enumerate()
+        .map(|(i, &(size, hash))| {
+            if size == 0 || size > MAX_COPY_SIZE {
+                return None;
+            }
+            match first_seen.get(&(size, hash)) {
+                Some(&source) => Some(source),
+                None => {
+                    first_seen.insert((size, hash), i);
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+/// Create `target_key` as a server-side copy of `source_key` in the same bucket
+///
+/// The copy gets `metadata` instead of the source's, so per-file metadata such
+/// as the modification time describes the duplicate rather than the original.
+pub async fn copy_object(
+    client: &Client,
+    bucket: &str,
+    source_key: &str,
+    target_key: &str,
+    metadata: HashMap<String, String>,
+) -> Result<()> {
+    debug!(
+        "Copying s3://{}/{} -> s3://{}/{}",
+        bucket, source_key, bucket, target_key
+    );
+
+    client
+        .copy_object()
+        .bucket(bucket)
+        .key(target_key)
+        .copy_source(copy_source(bucket, source_key))
+        .metadata_directive(MetadataDirective::Replace)
+        .set_metadata(Some(metadata).filter(|m| !m.is_empty()))
+        .send()
+        .await
+        .with_context(|| {
+            format!(
+                "Failed to copy s3://{}/{} to {}",
+                bucket, source_key, target_key
+            )
+        })?;
+
+    info!(
+        "Successfully copied: s3://{}/{} -> s3://{}/{}",
+        bucket, source_key, bucket, target_key
+    );
+
+    Ok(())
+}
+
+/// `bucket/key` for the x-amz-copy-source header, with the key URL-encoded
+fn copy_source(bucket: &str, key: &str) -> String {
+    let mut encoded = String::with_capacity(bucket.len() + key.len() + 1);
+    encoded.push_str(bucket);
+    encoded.push('/');
+    for byte in key.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aws_sdk_s3::config::{BehaviorVersion, Credentials, Region};
+    use wiremock::matchers::{header, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[tokio::test]
+    async fn test_hash_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let a = dir.path().join("a.mp4");
+        let b = dir.path().join("b.mp4");
+        let c = dir.path().join("c.mp4");
+        std::fs::write(&a, b"intro clip").unwrap();
+        std::fs::write(&b, b"intro clip").unwrap();
+        std::fs::write(&c, b"outro clip").unwrap();
+
+        let hash = hash_file(&a).await.unwrap();
+        assert_eq!(hash, blake3::hash(b"intro clip").to_hex().to_string());
+        assert_eq!(hash_file(&b).await.unwrap(), hash);
+        assert_ne!(hash_file(&c).await.unwrap(), hash);
+    }
+
+    #[test]
+    fn test_find_duplicates() {
+        let files = [
+            (10, "aaa"),
+            (10, "bbb"),
+            (10, "aaa"),
+            // Same hash with a different size is not treated as a duplicate
+            (11, "aaa"),
+            (10, "bbb"),
+            (0, "empty"),
+            (0, "empty"),
+            (MAX_COPY_SIZE + 1, "huge"),
+            (MAX_COPY_SIZE + 1, "huge"),
+        ];
+        assert_eq!(
+            find_duplicates(&files),
+            vec![None, None, Some(0), None, Some(1), None, None, None, None]
+        );
+    }
+
+    #[test]
+    fn test_copy_source() {
+        assert_eq!(
+            copy_source("bucket", "uploads/a/intro.mp4"),
+            "bucket/uploads/a/intro.mp4"
+        );
+        assert_eq!(
+            copy_source("bucket", "uploads/my clip+1.mp4"),
+            "bucket/uploads/my%20clip%2B1.mp4"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_copy_object_request() {
+        let server = MockServer::start().await;
+        Mock::given(method("PUT"))
+            .and(path("/bucket/b/intro.mp4"))
+            .and(header("x-amz-copy-source", "bucket/a/intro.mp4"))
+            .and(header("x-amz-metadata-directive", "REPLACE"))
+            .and(header("x-amz-meta-mtime", "1700000000.000"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_string("<CopyObjectResult><ETag>\"abc\"</ETag></CopyObjectResult>"),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let config = aws_sdk_s3::Config::builder()
+            .behavior_version(BehaviorVersion::latest())
+            .region(Region::new("us-west-2"))
+            .endpoint_url(server.uri())
+            .force_path_style(true)
+            .credentials_provider(Credentials::new("AKIA", "secret", None, None, "test"))
+            .build();
+        let client = Client::from_conf(config);
+
+        let metadata = HashMap::from([("mtime".to_string(), "1700000000.000".to_string())]);
+        copy_object(&client, "bucket", "a/intro.mp4", "b/intro.mp4", metadata)
+            .await
+            .unwrap();
+    }
+}
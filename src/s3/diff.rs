@@ -0,0 +1,377 @@
+use anyhow::{Context, Result};
+use aws_sdk_s3::Client;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use tracing::debug;
+
+use super::compare::{compare_file_with_quirks, FileComparison};
+use super::provider::Quirks;
+
+/// A single object that only exists on one side of the diff
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DiffEntry {
+    pub key: String,
+    pub size: u64,
+}
+
+/// An object that exists on both sides but differs in size or content
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ChangedEntry {
+    pub key: String,
+    pub local_size: u64,
+    pub remote_size: u64,
+}
+
+/// One section of the diff report with its aggregate numbers
+#[derive(Debug, Clone, Serialize)]
+pub struct DiffSection<T> {
+    pub count: usize,
+    pub total_bytes: u64,
+    pub entries: Vec<T>,
+}
+
+impl<T> Default for DiffSection<T> {
+    fn default() -> Self {
+        Self {
+            count: 0,
+            total_bytes: 0,
+            entries: Vec::new(),
+        }
+    }
+}
+
+/// Read-only comparison between a local tree and a remote S3 prefix
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct DiffReport {
+    pub bucket: String,
+    pub prefix: String,
+    pub local_only: DiffSection<DiffEntry>,
+    pub remote_only: DiffSection<DiffEntry>,
+    pub changed: DiffSection<ChangedEntry>,
+    pub identical: usize,
+}
+
+impl DiffSection<DiffEntry> {
+    fn push(&mut self, entry: DiffEntry) {
+        self.count += 1;
+        self.total_bytes += entry.size;
+        self.entries.push(entry);
+    }
+}
+
+impl DiffSection<ChangedEntry> {
+    fn push(&mut self, entry: ChangedEntry) {
+        self.count += 1;
+        self.total_bytes += entry.local_size;
+        self.entries.push(entry);
+    }
+}
+
+impl DiffReport {
+    /// True when local and remote sides are in sync
+    pub fn is_clean(&self) -> bool {
+        self.local_only.count == 0 && self.remote_only.count == 0 && self.changed.count == 0
+    }
+}
+
+/// Normalize a key prefix for listing
+///
+/// Returns an empty string for the bucket root, otherwise the prefix with
+/// exactly one trailing slash so that `uploads` doesn't match `uploads2/`.
+pub fn normalize_prefix(prefix: &str) -> String {
+    let trimmed = prefix.trim_matches('/');
+    if trimmed.is_empty() {
+        String::new()
+    } else {
+        format!("{}/", trimmed)
+    }
+}
+
+/// List every object under a prefix, following continuation tokens
+///
+/// # Returns
+///
+/// Map of full S3 key to object size, sorted by key
+pub async fn list_remote_objects(
+    client: &Client,
+    bucket: &str,
+    prefix: &str,
+) -> Result<BTreeMap<String, u64>> {
+    let prefix = normalize_prefix(prefix);
+    let mut objects = BTreeMap::new();
+
+    let mut request = client.list_objects_v2().bucket(bucket);
+    if !prefix.is_empty() {
+        request = request.prefix(&prefix);
+    }
+
+    let mut pages = request.into_paginator().send();
+    while let Some(page) = pages.next().await {
+        let page = page.with_context(|| format!("Failed to list s3://{}/{}", bucket, prefix))?;
+
+        for object in page.contents() {
+            if let Some(key) = object.key() {
+                // Skip "directory" placeholder objects created by some tools
+                if key.ends_with('/') {
+                    continue;
+                }
+                objects.insert(key.to_string(), object.size().unwrap_or(0) as u64);
+            }
+        }
+    }
+
+    debug!(
+        "Listed {} remote objects under s3://{}/{}",
+        objects.len(),
+        bucket,
+        prefix
+    );
+
+    Ok(objects)
+}
+
+/// Build a diff report between local files and a remote prefix
+///
+/// # Arguments
+///
+/// * `client` - AWS S3 client
+/// * `bucket` - S3 bucket name
+/// * `prefix` - Remote prefix used for listing (may be empty)
+/// * `local_files` - Pairs of (S3 key, local path) after filtering
+/// * `quirks` - How the provider's ETags and metadata can be compared
+///
+/// Files present on both sides are classified with `compare_file_with_quirks`,
+/// so the changed section uses the same size/ETag rules as the upload path.
+pub async fn diff_tree(
+    client: &Client,
+    bucket: &str,
+    prefix: &str,
+    local_files: &[(String, PathBuf)],
+    quirks: &Quirks,
+) -> Result<DiffReport> {
+    let mut remote = list_remote_objects(client, bucket, prefix).await?;
+
+    let mut report = DiffReport {
+        bucket: bucket.to_string(),
+        prefix: normalize_prefix(prefix),
+        ..Default::default()
+    };
+
+    for (key, path) in local_files {
+        let local_size = tokio::fs::metadata(path)
+            .await
+            .with_context(|| format!("Failed to access file: {}", path.display()))?
+            .len();
+
+        let Some(remote_size) = remote.remove(key) else {
+            report.local_only.push(DiffEntry {
+                key: key.clone(),
+                size: local_size,
+            });
+            continue;
+        };
+
+        match compare_file_with_quirks(client, bucket, key, path, quirks).await? {
+            FileComparison::Identical => report.identical += 1,
+            // Listed a moment ago, so a missing object here means it was deleted concurrently
+            FileComparison::Different | FileComparison::NotFound => {
+                report.changed.push(ChangedEntry {
+                    key: key.clone(),
+                    local_size,
+                    remote_size,
+                });
+            }
+        }
+    }
+
+    for (key, size) in remote {
+        report.remote_only.push(DiffEntry { key, size });
+    }
+
+    report.local_only.entries.sort_by(|a, b| a.key.cmp(&b.key));
+    report.changed.entries.sort_by(|a, b| a.key.cmp(&b.key));
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::s3::Provider;
+    use aws_sdk_s3::config::{BehaviorVersion, Credentials, Region};
+    use wiremock::matchers::{method, path, query_param};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    /// MD5 of "hello world", the ETag of a simple upload of it
+    const HELLO_ETAG: &str = "\"5eb63bbbe01eeed093cb22bb8f5acdc3\"";
+
+    fn test_client(endpoint: &str) -> Client {
+        let config = aws_sdk_s3::Config::builder()
+            .behavior_version(BehaviorVersion::latest())
+            .region(Region::new("us-west-2"))
+            .endpoint_url(endpoint)
+            .force_path_style(true)
+            .credentials_provider(Credentials::new("AKIA", "secret", None, None, "test"))
+            .build();
+        Client::from_conf(config)
+    }
+
+    fn listing(objects: &[(&str, u64)]) -> String {
+        let contents: String = objects
+            .iter()
+            .map(|(key, size)| {
+                format!(
+                    "<Contents><Key>{}</Key><Size>{}</Size></Contents>",
+                    key, size
+                )
+            })
+            .collect();
+        format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\
+             <ListBucketResult xmlns=\"http://s3.amazonaws.com/doc/2006-03-01/\">\
+             <Name>media</Name><Prefix>uploads/</Prefix><KeyCount>{}</KeyCount>\
+             <MaxKeys>1000</MaxKeys><IsTruncated>false</IsTruncated>{}</ListBucketResult>",
+            objects.len(),
+            contents
+        )
+    }
+
+    async fn head(server: &MockServer, key: &str, etag: &str) {
+        Mock::given(method("HEAD"))
+            .and(path(format!("/media/{}", key)))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("Content-Length", "11")
+                    .insert_header("ETag", etag),
+            )
+            .mount(server)
+            .await;
+    }
+
+    #[tokio::test]
+    async fn test_diff_tree_classifies_files() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in ["new.mp4", "same.mp4", "edited.mp4", "resized.mp4"] {
+            std::fs::write(dir.path().join(name), "hello world").unwrap();
+        }
+
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/media/"))
+            .and(query_param("list-type", "2"))
+            .and(query_param("prefix", "uploads/"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(listing(&[
+                ("uploads/", 0),
+                ("uploads/edited.mp4", 11),
+                ("uploads/old.mp4", 7),
+                ("uploads/resized.mp4", 11),
+                ("uploads/same.mp4", 11),
+            ])))
+            .mount(&server)
+            .await;
+        head(&server, "uploads/same.mp4", HELLO_ETAG).await;
+        head(
+            &server,
+            "uploads/edited.mp4",
+            "\"00000000000000000000000000000000\"",
+        )
+        .await;
+        Mock::given(method("HEAD"))
+            .and(path("/media/uploads/resized.mp4"))
+            .respond_with(ResponseTemplate::new(200).insert_header("Content-Length", "5"))
+            .mount(&server)
+            .await;
+
+        // A leading slash on the target path (only `Config::new` rejects one)
+        // must not split the two sides apart
+        let config = Config {
+            target_path: "/uploads".to_string(),
+            ..Config::new("us-west-2", None, "media", "uploads").unwrap()
+        };
+        let local_files: Vec<_> = ["new.mp4", "same.mp4", "edited.mp4", "resized.mp4"]
+            .iter()
+            .map(|name| (config.build_s3_key(name), dir.path().join(name)))
+            .collect();
+
+        let report = diff_tree(
+            &test_client(&server.uri()),
+            "media",
+            &config.target_path,
+            &local_files,
+            &Provider::Aws.quirks(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(report.prefix, "uploads/");
+        assert_eq!(
+            report.local_only.entries,
+            vec![DiffEntry {
+                key: "uploads/new.mp4".to_string(),
+                size: 11
+            }]
+        );
+        assert_eq!(
+            report.changed.entries,
+            vec![
+                ChangedEntry {
+                    key: "uploads/edited.mp4".to_string(),
+                    local_size: 11,
+                    remote_size: 11
+                },
+                ChangedEntry {
+                    key: "uploads/resized.mp4".to_string(),
+                    local_size: 11,
+                    remote_size: 11
+                },
+            ]
+        );
+        assert_eq!(report.identical, 1);
+        assert_eq!(
+            report.remote_only.entries,
+            vec![DiffEntry {
+                key: "uploads/old.mp4".to_string(),
+                size: 7
+            }]
+        );
+        assert!(!report.is_clean());
+    }
+
+    #[test]
+    fn test_normalize_prefix() {
+        assert_eq!(normalize_prefix(""), "");
+        assert_eq!(normalize_prefix("/"), "");
+        assert_eq!(normalize_prefix("uploads"), "uploads/");
+        assert_eq!(normalize_prefix("uploads/"), "uploads/");
+        assert_eq!(normalize_prefix("uploads/videos/"), "uploads/videos/");
+    }
+
+    #[test]
+    fn test_diff_section_totals() {
+        let mut section = DiffSection::<DiffEntry>::default();
+        section.push(DiffEntry {
+            key: "a.mp4".to_string(),
+            size: 10,
+        });
+        section.push(DiffEntry {
+            key: "b.mp4".to_string(),
+            size: 32,
+        });
+
+        assert_eq!(section.count, 2);
+        assert_eq!(section.total_bytes, 42);
+    }
+
+    #[test]
+    fn test_empty_report_is_clean() {
+        let report = DiffReport::default();
+        assert!(report.is_clean());
+
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["local_only"]["count"], 0);
+        assert_eq!(json["remote_only"]["total_bytes"], 0);
+        assert!(json["changed"]["entries"].as_array().unwrap().is_empty());
+    }
+}
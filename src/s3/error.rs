@@ -20,6 +20,10 @@ pub enum S3UploadError {
     #[error("S3 access denied for bucket '{bucket}': {message}")]
     S3AccessDenied { bucket: String, message: String },
 
+    /// AWS credentials expired or were revoked mid-run
+    #[error("AWS credentials expired: {message}")]
+    ExpiredCredentials { message: String },
+
     /// File size exceeds maximum allowed
     #[error("File too large: {size} bytes (max: {max} bytes)")]
     FileTooLarge { size: u64, max: u64 },
@@ -46,9 +50,13 @@ impl S3UploadError {
     #[allow(dead_code)] // Will be used when we integrate structured errors
     pub fn from_aws_error<E: std::fmt::Display>(bucket: &str, error: E) -> Self {
         let error_str = error.to_string();
-        if error_str.to_lowercase().contains("access denied")
-            || error_str.to_lowercase().contains("forbidden")
+        let lower = error_str.to_lowercase();
+        if lower.contains("expiredtoken")
+            || lower.contains("invalidtoken")
+            || lower.contains("token has expired")
         {
+            Self::ExpiredCredentials { message: error_str }
+        } else if lower.contains("access denied") || lower.contains("forbidden") {
             Self::S3AccessDenied {
                 bucket: bucket.to_string(),
                 message: error_str,
@@ -102,6 +110,15 @@ impl S3UploadError {
                     bucket, message, bucket
                 )
             }
+            Self::ExpiredCredentials { message } => {
+                format!(
+                    "AWS credentials expired: {}\n\nPossible solutions:\n  \
+                     1. Renew your SSO session: aws sso login\n  \
+                     2. Use a profile instead of exported keys so they can be reloaded\n  \
+                     3. Pass --role-arn to assume a role that is re-assumed on expiry",
+                    message
+                )
+            }
             Self::NetworkError { message } => {
                 format!(
                     "Network error: {}\n\nPossible solutions:\n  \
@@ -81,6 +81,7 @@ pub fn detect_content_type(path: &Path) -> String {
 /// # Examples
 ///
 /// ```
+/// # use swiss_knife::s3::parse_metadata;
 /// let metadata = parse_metadata("author=John,project=Demo");
 /// assert_eq!(metadata.get("author"), Some(&"John".to_string()));
 /// ```
@@ -137,6 +138,49 @@ pub fn parse_tags(tags_str: &str) -> HashMap<String, String> {
         .collect()
 }
 
+/// Match a path against a simple glob pattern
+///
+/// Supports `*` (any run of characters, including `/`) and `?` (any single
+/// character). Matching is case-sensitive.
+pub fn matches_glob(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+
+    let (mut p, mut t) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = star {
+            // Backtrack: let the last '*' swallow one more character
+            p = star_p + 1;
+            t = star_t + 1;
+            star = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Check whether a relative path is excluded by any of the patterns
+///
+/// Each pattern is matched against both the full relative path and the
+/// file name, so `*.tmp` and `cache/*` both work as expected.
+pub fn is_excluded(relative_path: &str, patterns: &[String]) -> bool {
+    let file_name = relative_path.rsplit('/').next().unwrap_or(relative_path);
+    patterns
+        .iter()
+        .filter(|p| !p.is_empty())
+        .any(|p| matches_glob(p, relative_path) || matches_glob(p, file_name))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -228,4 +272,28 @@ mod tests {
         let tags = parse_tags(&long_value);
         assert_eq!(tags.len(), 0);
     }
+
+    #[test]
+    fn test_matches_glob() {
+        assert!(matches_glob("*.mp4", "video.mp4"));
+        assert!(matches_glob("*.mp4", "dir/video.mp4"));
+        assert!(matches_glob("cache/*", "cache/a/b.mp4"));
+        assert!(matches_glob("clip?.mov", "clip1.mov"));
+        assert!(matches_glob("*", ""));
+
+        assert!(!matches_glob("*.mp4", "video.mov"));
+        assert!(!matches_glob("clip?.mov", "clip10.mov"));
+        assert!(!matches_glob("cache/*", "other/cache.mp4"));
+    }
+
+    #[test]
+    fn test_is_excluded() {
+        let patterns = vec!["*.tmp".to_string(), "drafts/*".to_string()];
+
+        assert!(is_excluded("a.tmp", &patterns));
+        assert!(is_excluded("nested/b.tmp", &patterns));
+        assert!(is_excluded("drafts/cut.mp4", &patterns));
+        assert!(!is_excluded("final/cut.mp4", &patterns));
+        assert!(!is_excluded("cut.mp4", &[]));
+    }
 }
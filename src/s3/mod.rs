@@ -1,17 +1,34 @@
+pub mod archive;
+pub mod budget;
 pub mod client;
 pub mod compare;
+pub mod credentials;
+pub mod dedupe;
+pub mod diff;
 pub mod error;
 pub mod helpers;
+pub mod mtime;
 pub mod multipart;
 pub mod presign;
+pub mod provider;
+pub mod qr;
+pub mod tagging;
+pub mod target;
 pub mod upload;
 
+pub use archive::{is_archive_unchanged, upload_archive, ArchiveMember};
+pub use budget::PartBudget;
 pub use client::S3Client;
 pub use compare::FileComparison;
-pub use helpers::{detect_content_type, parse_metadata, parse_tags};
-pub use multipart::{upload_multipart, MULTIPART_THRESHOLD};
+pub use credentials::RefreshableCredentials;
+pub use diff::{diff_tree, DiffReport};
+pub use helpers::{detect_content_type, is_excluded, parse_metadata, parse_tags};
+pub use mtime::{restore_mtime, META_MTIME};
+pub use multipart::{upload_multipart, upload_multipart_with_metadata, MULTIPART_THRESHOLD};
 pub use presign::{generate_presigned_url, generate_presigned_url_with_expiry};
-pub use upload::{upload_file, UploadResult};
+pub use provider::{Provider, Quirks, Support};
+pub use target::{resolve_targets, TargetDefaults, UploadTarget};
+pub use upload::{is_expired_credentials, upload_file, upload_file_with_metadata, UploadResult};
 
 // Re-export error types for potential future use
 #[allow(unused_imports)]
@@ -0,0 +1,217 @@
+use anyhow::{Context, Result};
+use filetime::FileTime;
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::debug;
+
+/// Metadata key holding the local modification time (sent as `x-amz-meta-mtime`)
+pub const META_MTIME: &str = "mtime";
+
+/// Format a modification time as epoch seconds with milliseconds, e.g. `1760529600.123`
+pub fn format_mtime(time: SystemTime) -> Option<String> {
+    let millis = time.duration_since(UNIX_EPOCH).ok()?.as_millis();
+    Some(format!("{}.{:03}", millis / 1000, millis % 1000))
+}
+
+/// Parse a value written by [`format_mtime`]; whole seconds are accepted too
+pub fn parse_mtime(value: &str) -> Option<SystemTime> {
+    let (secs, fraction) = value.trim().split_once('.').unwrap_or((value.trim(), ""));
+    if fraction.len() > 3 || !fraction.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let secs: u64 = secs.parse().ok()?;
+    let millis: u64 = format!("{:0<3}", fraction).parse().ok()?;
+    Some(UNIX_EPOCH + Duration::from_secs(secs) + Duration::from_millis(millis))
+}
+
+/// User metadata recording a local file's modification time
+///
+/// Empty when the platform doesn't report an mtime, so uploads carry the key
+/// only when it means something.
+pub fn mtime_metadata(metadata: &std::fs::Metadata) -> HashMap<String, String> {
+    metadata
+        .modified()
+        .ok()
+        .and_then(format_mtime)
+        .map(|mtime| HashMap::from([(META_MTIME.to_string(), mtime)]))
+        .unwrap_or_default()
+}
+
+/// Set a downloaded file's modification time from the object's user metadata
+///
+/// Returns `Ok(false)` and leaves the file alone when the object has no
+/// usable `mtime` (uploaded by another tool or before it was recorded), so the
+/// file keeps its download time.
+pub fn restore_mtime(path: &Path, metadata: Option<&HashMap<String, String>>) -> Result<bool> {
+    let Some(value) = metadata.and_then(|m| m.get(META_MTIME)) else {
+        return Ok(false);
+    };
+    let Some(mtime) = parse_mtime(value) else {
+        debug!("Ignoring invalid mtime metadata '{}'", value);
+        return Ok(false);
+    };
+
+    filetime::set_file_mtime(path, FileTime::from_system_time(mtime))
+        .with_context(|| format!("Failed to set modification time: {}", path.display()))?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::budget::PartBudget;
+    use super::super::multipart::upload_multipart;
+    use super::super::upload::{upload_file, upload_file_with_metadata};
+    use super::*;
+    use aws_sdk_s3::config::{BehaviorVersion, Credentials, Region};
+    use aws_sdk_s3::Client;
+    use wiremock::matchers::{header, method, query_param};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn test_client(endpoint: &str) -> Client {
+        let config = aws_sdk_s3::Config::builder()
+            .behavior_version(BehaviorVersion::latest())
+            .region(Region::new("us-west-2"))
+            .endpoint_url(endpoint)
+            .force_path_style(true)
+            .credentials_provider(Credentials::new("AKIA", "secret", None, None, "test"))
+            .build();
+        Client::from_conf(config)
+    }
+
+    /// A file whose mtime is a known value with milliseconds
+    fn file_with_mtime(dir: &Path) -> (std::path::PathBuf, SystemTime) {
+        let path = dir.join("clip.mp4");
+        std::fs::write(&path, b"frames").unwrap();
+        let mtime = UNIX_EPOCH + Duration::from_millis(1_700_000_000_250);
+        filetime::set_file_mtime(&path, FileTime::from_system_time(mtime)).unwrap();
+        (path, mtime)
+    }
+
+    #[test]
+    fn test_format_and_parse_mtime() {
+        let time = UNIX_EPOCH + Duration::from_millis(1_760_529_600_007);
+        assert_eq!(format_mtime(time).unwrap(), "1760529600.007");
+        assert_eq!(parse_mtime("1760529600.007"), Some(time));
+
+        assert_eq!(
+            parse_mtime("1760529600"),
+            Some(UNIX_EPOCH + Duration::from_secs(1_760_529_600))
+        );
+        assert_eq!(
+            parse_mtime("1760529600.5"),
+            Some(UNIX_EPOCH + Duration::from_millis(1_760_529_600_500))
+        );
+        assert_eq!(parse_mtime("yesterday"), None);
+        assert_eq!(parse_mtime("-5.000"), None);
+        assert_eq!(parse_mtime("1.2345"), None);
+    }
+
+    #[test]
+    fn test_mtime_metadata_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let (path, mtime) = file_with_mtime(dir.path());
+
+        let metadata = mtime_metadata(&std::fs::metadata(&path).unwrap());
+        assert_eq!(metadata[META_MTIME], "1700000000.250");
+
+        // Download: the file is rewritten, then its mtime restored
+        std::fs::write(&path, b"frames").unwrap();
+        assert!(restore_mtime(&path, Some(&metadata)).unwrap());
+        assert_eq!(std::fs::metadata(&path).unwrap().modified().unwrap(), mtime);
+    }
+
+    #[test]
+    fn test_restore_mtime_without_metadata() {
+        let dir = tempfile::tempdir().unwrap();
+        let (path, mtime) = file_with_mtime(dir.path());
+
+        let other = HashMap::from([("author".to_string(), "tyr".to_string())]);
+        let invalid = HashMap::from([(META_MTIME.to_string(), "soon".to_string())]);
+        assert!(!restore_mtime(&path, None).unwrap());
+        assert!(!restore_mtime(&path, Some(&other)).unwrap());
+        assert!(!restore_mtime(&path, Some(&invalid)).unwrap());
+        assert_eq!(std::fs::metadata(&path).unwrap().modified().unwrap(), mtime);
+    }
+
+    #[tokio::test]
+    async fn test_single_put_stores_mtime() {
+        let dir = tempfile::tempdir().unwrap();
+        let (path, _) = file_with_mtime(dir.path());
+
+        let server = MockServer::start().await;
+        Mock::given(method("PUT"))
+            .and(header("x-amz-meta-mtime", "1700000000.250"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = test_client(&server.uri());
+        upload_file(&client, "bucket", "clip.mp4", &path, None)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_single_put_adds_extra_metadata() {
+        let dir = tempfile::tempdir().unwrap();
+        let (path, _) = file_with_mtime(dir.path());
+
+        let server = MockServer::start().await;
+        Mock::given(method("PUT"))
+            .and(header("x-amz-meta-mtime", "1700000000.250"))
+            .and(header("x-amz-meta-sha256", "abc123"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = test_client(&server.uri());
+        let extra = HashMap::from([("sha256".to_string(), "abc123".to_string())]);
+        upload_file_with_metadata(&client, "bucket", "clip.mp4", &path, &extra, None)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_multipart_stores_mtime() {
+        let dir = tempfile::tempdir().unwrap();
+        let (path, _) = file_with_mtime(dir.path());
+
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(query_param("uploads", ""))
+            .and(header("x-amz-meta-mtime", "1700000000.250"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                "<InitiateMultipartUploadResult><Bucket>bucket</Bucket>\
+                 <Key>clip.mp4</Key><UploadId>upload-1</UploadId>\
+                 </InitiateMultipartUploadResult>",
+            ))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("PUT"))
+            .and(query_param("uploadId", "upload-1"))
+            .respond_with(ResponseTemplate::new(200).insert_header("ETag", "\"part-1\""))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(query_param("uploadId", "upload-1"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                "<CompleteMultipartUploadResult><Bucket>bucket</Bucket>\
+                 <Key>clip.mp4</Key><ETag>\"done-1\"</ETag>\
+                 </CompleteMultipartUploadResult>",
+            ))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = test_client(&server.uri());
+        let budget = PartBudget::new(64 * 1024 * 1024, 5 * 1024 * 1024).unwrap();
+        upload_multipart(&client, "bucket", "clip.mp4", &path, &budget, None)
+            .await
+            .unwrap();
+    }
+}
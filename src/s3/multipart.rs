@@ -1,21 +1,28 @@
 use anyhow::{Context, Result};
 use aws_sdk_s3::{primitives::ByteStream, types::CompletedPart, Client};
 use indicatif::ProgressBar;
+use std::collections::HashMap;
 use std::path::Path;
-use tokio::io::AsyncReadExt;
-use tracing::{debug, info};
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tracing::{debug, info, warn};
+
+use super::budget::PartBudget;
+use super::upload::object_metadata;
 
 // Threshold for using multipart upload (100MB)
 // Only use multipart for files significantly larger than the part size
 // to ensure we have multiple meaningful parts
 pub const MULTIPART_THRESHOLD: u64 = 100 * 1024 * 1024;
 
-// Size of each part (10MB) - AWS minimum is 5MB for all parts except the last
-const PART_SIZE: usize = 10 * 1024 * 1024;
+// AWS minimum part size (5MB), applies to all parts except the last.
+// The actual part size is configured via PartBudget (default 10MB)
+pub const MIN_PART_SIZE: usize = 5 * 1024 * 1024;
 
 /// Upload a large file using S3 multipart upload
 ///
 /// Multipart upload is used for files larger than MULTIPART_THRESHOLD.
+/// The local modification time is stored as `x-amz-meta-mtime`, as for
+/// single-put uploads.
 /// Benefits:
 /// - Can upload files > 5GB (AWS single PUT limit)
 /// - Better resilience (can retry individual parts)
@@ -27,33 +34,126 @@ const PART_SIZE: usize = 10 * 1024 * 1024;
 /// * `bucket` - S3 bucket name
 /// * `s3_key` - S3 object key (path)
 /// * `local_path` - Path to local file
+/// * `budget` - Shared memory budget for part buffers (also defines the part size)
 /// * `pb` - Optional progress bar
 ///
 /// # Returns
 ///
-/// Ok(()) on successful upload
+/// Ok(()) on successful upload. On failure the multipart upload is aborted so
+/// no orphaned parts are left behind on S3.
 pub async fn upload_multipart(
     client: &Client,
     bucket: &str,
     s3_key: &str,
     local_path: &Path,
+    budget: &PartBudget,
+    pb: Option<&ProgressBar>,
+) -> Result<()> {
+    upload_multipart_with_metadata(
+        client,
+        bucket,
+        s3_key,
+        local_path,
+        &HashMap::new(),
+        budget,
+        pb,
+    )
+    .await
+}
+
+/// Upload a large file like [`upload_multipart`], adding `extra_metadata` to the
+/// object's user metadata (next to `mtime`)
+pub async fn upload_multipart_with_metadata(
+    client: &Client,
+    bucket: &str,
+    s3_key: &str,
+    local_path: &Path,
+    extra_metadata: &HashMap<String, String>,
+    budget: &PartBudget,
     pb: Option<&ProgressBar>,
 ) -> Result<()> {
     let metadata = tokio::fs::metadata(local_path).await?;
     let file_size = metadata.len();
+    let part_size = budget.part_size();
 
     info!(
         "Starting multipart upload for {} ({} bytes, {} parts)",
         local_path.display(),
         file_size,
-        (file_size as usize).div_ceil(PART_SIZE)
+        (file_size as usize).div_ceil(part_size)
     );
 
+    if let Some(pb) = pb {
+        pb.set_length(file_size);
+        pb.set_position(0);
+        pb.set_message(format!(
+            "Multipart upload {}",
+            local_path.file_name().unwrap().to_string_lossy()
+        ));
+    }
+
+    let mut file = tokio::fs::File::open(local_path).await?;
+    upload_multipart_from_reader(
+        client,
+        bucket,
+        s3_key,
+        &mut file,
+        &object_metadata(&metadata, extra_metadata),
+        budget,
+        pb,
+    )
+    .await?;
+
+    if let Some(pb) = pb {
+        pb.finish_with_message(format!(
+            "✓ {}",
+            local_path.file_name().unwrap().to_string_lossy()
+        ));
+    }
+
+    info!(
+        "Successfully completed multipart upload: {} -> s3://{}/{}",
+        local_path.display(),
+        bucket,
+        s3_key
+    );
+
+    Ok(())
+}
+
+/// Upload everything produced by a reader as a single multipart object
+///
+/// The total size doesn't need to be known up front, which makes this suitable
+/// for streamed content such as archives compressed on the fly.
+///
+/// # Arguments
+///
+/// * `client` - AWS S3 client
+/// * `bucket` - S3 bucket name
+/// * `s3_key` - S3 object key (path)
+/// * `reader` - Source of the object bytes
+/// * `metadata` - User metadata stored on the object
+/// * `budget` - Shared memory budget for part buffers (also defines the part size)
+/// * `pb` - Optional progress bar, advanced by bytes uploaded
+///
+/// # Returns
+///
+/// Total number of bytes uploaded
+pub async fn upload_multipart_from_reader<R: AsyncRead + Unpin>(
+    client: &Client,
+    bucket: &str,
+    s3_key: &str,
+    reader: &mut R,
+    metadata: &HashMap<String, String>,
+    budget: &PartBudget,
+    pb: Option<&ProgressBar>,
+) -> Result<u64> {
     // Initiate multipart upload
     let multipart = client
         .create_multipart_upload()
         .bucket(bucket)
         .key(s3_key)
+        .set_metadata((!metadata.is_empty()).then(|| metadata.clone()))
         .send()
         .await
         .context("Failed to initiate multipart upload")?;
@@ -64,29 +164,72 @@ pub async fn upload_multipart(
 
     debug!("Multipart upload initiated with ID: {}", upload_id);
 
-    if let Some(pb) = pb {
-        pb.set_length(file_size);
-        pb.set_position(0);
-        pb.set_message(format!(
-            "Multipart upload {}",
-            local_path.file_name().unwrap().to_string_lossy()
-        ));
-    }
+    let (parts, parts_bytes) =
+        match upload_parts(client, bucket, s3_key, upload_id, reader, budget, pb).await {
+            Ok(result) => result,
+            Err(e) => {
+                if let Err(abort_err) =
+                    abort_multipart_upload(client, bucket, s3_key, upload_id).await
+                {
+                    warn!(
+                        "Failed to abort multipart upload {}: {:#}",
+                        upload_id, abort_err
+                    );
+                }
+                return Err(e);
+            }
+        };
+
+    debug!(
+        "All {} parts uploaded, completing multipart upload",
+        parts.len()
+    );
+
+    // Complete multipart upload
+    let completed_multipart = aws_sdk_s3::types::CompletedMultipartUpload::builder()
+        .set_parts(Some(parts))
+        .build();
+
+    client
+        .complete_multipart_upload()
+        .bucket(bucket)
+        .key(s3_key)
+        .upload_id(upload_id)
+        .multipart_upload(completed_multipart)
+        .send()
+        .await
+        .context("Failed to complete multipart upload")?;
+
+    Ok(parts_bytes)
+}
+
+/// Read and upload every part of the stream, holding a budget permit per buffer
+async fn upload_parts<R: AsyncRead + Unpin>(
+    client: &Client,
+    bucket: &str,
+    s3_key: &str,
+    upload_id: &str,
+    reader: &mut R,
+    budget: &PartBudget,
+    pb: Option<&ProgressBar>,
+) -> Result<(Vec<CompletedPart>, u64)> {
+    let part_size = budget.part_size();
 
     // Upload parts
-    let mut file = tokio::fs::File::open(local_path).await?;
     let mut parts = Vec::new();
     let mut part_number = 1i32;
     let mut uploaded_bytes = 0u64;
 
     loop {
-        let mut buffer = vec![0u8; PART_SIZE];
+        // Wait for room in the memory budget before allocating the buffer
+        let permit = budget.acquire().await?;
+        let mut buffer = vec![0u8; part_size];
         let mut total_read = 0;
 
         // Keep reading until we fill the buffer or hit EOF
         // This is necessary because AsyncReadExt::read() doesn't guarantee filling the buffer
-        while total_read < PART_SIZE {
-            let bytes_read = file.read(&mut buffer[total_read..]).await?;
+        while total_read < part_size {
+            let bytes_read = reader.read(&mut buffer[total_read..]).await?;
 
             if bytes_read == 0 {
                 // EOF reached
@@ -127,6 +270,7 @@ pub async fn upload_multipart(
             .build();
 
         parts.push(completed_part);
+        drop(permit);
 
         uploaded_bytes += total_read as u64;
         if let Some(pb) = pb {
@@ -136,48 +280,13 @@ pub async fn upload_multipart(
         part_number += 1;
     }
 
-    debug!(
-        "All {} parts uploaded, completing multipart upload",
-        parts.len()
-    );
-
-    // Complete multipart upload
-    let completed_multipart = aws_sdk_s3::types::CompletedMultipartUpload::builder()
-        .set_parts(Some(parts))
-        .build();
-
-    client
-        .complete_multipart_upload()
-        .bucket(bucket)
-        .key(s3_key)
-        .upload_id(upload_id)
-        .multipart_upload(completed_multipart)
-        .send()
-        .await
-        .context("Failed to complete multipart upload")?;
-
-    if let Some(pb) = pb {
-        pb.finish_with_message(format!(
-            "✓ {}",
-            local_path.file_name().unwrap().to_string_lossy()
-        ));
-    }
-
-    info!(
-        "Successfully completed multipart upload: {} -> s3://{}/{}",
-        local_path.display(),
-        bucket,
-        s3_key
-    );
-
-    Ok(())
+    Ok((parts, uploaded_bytes))
 }
 
 /// Abort a multipart upload (for cleanup on error)
 ///
 /// This should be called if an error occurs during multipart upload
 /// to clean up any partial uploads on S3.
-#[allow(dead_code)]
 pub async fn abort_multipart_upload(
     client: &Client,
     bucket: &str,
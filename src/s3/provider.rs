@@ -0,0 +1,197 @@
+use clap::ValueEnum;
+use serde::Deserialize;
+use std::fmt;
+
+/// S3-compatible services whose behavior differs from AWS in ways the
+/// uploader has to account for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Provider {
+    /// Amazon S3
+    Aws,
+    /// Cloudflare R2
+    R2,
+    /// MinIO
+    Minio,
+    /// Any other S3-compatible service
+    Generic,
+}
+
+/// Whether a provider implements an optional S3 API
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Support {
+    /// Implemented; errors are real failures
+    Yes,
+    /// Depends on the deployment; a "not implemented" error turns it off for the run
+    Probe,
+    /// Not implemented; never called
+    No,
+}
+
+/// How the uploader adapts to a provider
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quirks {
+    /// Multipart ETags are derived from the parts (`<md5>-<count>`) as on AWS,
+    /// so an object with one can be matched on size alone
+    pub trust_multipart_etag: bool,
+    /// Record a sha256 of the content as `x-amz-meta-sha256` on upload and
+    /// compare it before looking at the ETag
+    pub prefer_sha256: bool,
+    /// Object tagging (`--tags`)
+    pub tagging: Support,
+    /// Address the bucket in the path instead of the host name
+    pub path_style: bool,
+}
+
+impl Provider {
+    /// The quirks of this provider
+    pub fn quirks(self) -> Quirks {
+        match self {
+            Self::Aws => Quirks {
+                trust_multipart_etag: true,
+                prefer_sha256: false,
+                tagging: Support::Yes,
+                path_style: false,
+            },
+            Self::R2 => Quirks {
+                trust_multipart_etag: false,
+                prefer_sha256: true,
+                tagging: Support::No,
+                path_style: false,
+            },
+            Self::Minio => Quirks {
+                trust_multipart_etag: true,
+                prefer_sha256: false,
+                tagging: Support::Probe,
+                path_style: true,
+            },
+            Self::Generic => Quirks {
+                trust_multipart_etag: false,
+                prefer_sha256: true,
+                tagging: Support::Probe,
+                path_style: true,
+            },
+        }
+    }
+
+    /// Pick the provider from an endpoint URL; no endpoint means AWS
+    ///
+    /// R2 and AWS are recognized by their host names, MinIO by a host name
+    /// containing `minio` or its default port 9000. Anything else is generic.
+    pub fn detect(endpoint_url: Option<&str>) -> Self {
+        let Some(url) = endpoint_url else {
+            return Self::Aws;
+        };
+        let authority = url
+            .split_once("://")
+            .map_or(url, |(_, rest)| rest)
+            .split(['/', '?'])
+            .next()
+            .unwrap_or_default();
+        let authority = authority.rsplit('@').next().unwrap_or_default();
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) if port.bytes().all(|b| b.is_ascii_digit()) => (host, Some(port)),
+            _ => (authority, None),
+        };
+        let host = host.to_ascii_lowercase();
+
+        if host.ends_with(".r2.cloudflarestorage.com") {
+            Self::R2
+        } else if host == "amazonaws.com"
+            || host.ends_with(".amazonaws.com")
+            || host.ends_with(".amazonaws.com.cn")
+        {
+            Self::Aws
+        } else if host.contains("minio") || port == Some("9000") {
+            Self::Minio
+        } else {
+            Self::Generic
+        }
+    }
+}
+
+impl fmt::Display for Provider {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Aws => "aws",
+            Self::R2 => "r2",
+            Self::Minio => "minio",
+            Self::Generic => "generic",
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quirk_table() {
+        let table: Vec<_> = [
+            Provider::Aws,
+            Provider::R2,
+            Provider::Minio,
+            Provider::Generic,
+        ]
+        .into_iter()
+        .map(|p| {
+            let q = p.quirks();
+            (
+                p.to_string(),
+                q.trust_multipart_etag,
+                q.prefer_sha256,
+                q.tagging,
+                q.path_style,
+            )
+        })
+        .collect();
+
+        assert_eq!(
+            table,
+            vec![
+                ("aws".to_string(), true, false, Support::Yes, false),
+                ("r2".to_string(), false, true, Support::No, false),
+                ("minio".to_string(), true, false, Support::Probe, true),
+                ("generic".to_string(), false, true, Support::Probe, true),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_detect_provider() {
+        let cases = [
+            (None, Provider::Aws),
+            (Some("https://s3.us-west-2.amazonaws.com"), Provider::Aws),
+            (
+                Some("https://s3.cn-north-1.amazonaws.com.cn"),
+                Provider::Aws,
+            ),
+            (
+                Some("https://0123456789abcdef.r2.cloudflarestorage.com"),
+                Provider::R2,
+            ),
+            (
+                Some("https://0123456789abcdef.eu.R2.cloudflarestorage.com/"),
+                Provider::R2,
+            ),
+            (Some("http://localhost:9000"), Provider::Minio),
+            (Some("https://minio.internal.example.com"), Provider::Minio),
+            (Some("http://user:pw@127.0.0.1:9000/path"), Provider::Minio),
+            (Some("https://s3.wasabisys.com"), Provider::Generic),
+            (
+                Some("https://fra1.digitaloceanspaces.com"),
+                Provider::Generic,
+            ),
+            (Some("localhost:8333"), Provider::Generic),
+            // A look-alike host is not R2
+            (
+                Some("https://r2.cloudflarestorage.com.evil.io"),
+                Provider::Generic,
+            ),
+        ];
+
+        for (endpoint, expected) in cases {
+            assert_eq!(Provider::detect(endpoint), expected, "{:?}", endpoint);
+        }
+    }
+}
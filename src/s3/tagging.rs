@@ -0,0 +1,119 @@
+use anyhow::{Context, Result};
+use aws_sdk_s3::{
+    types::{Tag, Tagging},
+    Client,
+};
+use std::collections::{BTreeMap, HashMap};
+use tracing::debug;
+
+/// Replace an object's tags (PutObjectTagging)
+pub async fn put_object_tags(
+    client: &Client,
+    bucket: &str,
+    s3_key: &str,
+    tags: &HashMap<String, String>,
+) -> Result<()> {
+    // Sorted so requests are repeatable
+    let mut tag_set = Vec::with_capacity(tags.len());
+    for (key, value) in tags.iter().collect::<BTreeMap<_, _>>() {
+        tag_set.push(Tag::builder().key(key).value(value).build()?);
+    }
+    let tagging = Tagging::builder().set_tag_set(Some(tag_set)).build()?;
+
+    debug!(
+        "Tagging s3://{}/{} with {} tag(s)",
+        bucket,
+        s3_key,
+        tags.len()
+    );
+    client
+        .put_object_tagging()
+        .bucket(bucket)
+        .key(s3_key)
+        .tagging(tagging)
+        .send()
+        .await
+        .with_context(|| format!("Failed to tag s3://{}/{}", bucket, s3_key))?;
+
+    Ok(())
+}
+
+/// Check if an error says the service doesn't implement the request
+/// (`NotImplemented`, HTTP 501), e.g. object tagging on some MinIO setups
+pub fn is_not_implemented(error: &anyhow::Error) -> bool {
+    let error_str = format!("{:#}", error).to_lowercase();
+
+    error_str.contains("notimplemented")
+        || error_str.contains("not implemented")
+        || error_str.contains("status code: 501")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aws_sdk_s3::config::{BehaviorVersion, Credentials, Region};
+    use wiremock::matchers::{body_string_contains, method, query_param};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    const NOT_IMPLEMENTED: &str = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\
+        <Error><Code>NotImplemented</Code>\
+        <Message>A header you provided implies functionality that is not implemented</Message>\
+        </Error>";
+
+    fn test_client(endpoint: &str) -> Client {
+        let config = aws_sdk_s3::Config::builder()
+            .behavior_version(BehaviorVersion::latest())
+            .region(Region::new("us-west-2"))
+            .endpoint_url(endpoint)
+            .force_path_style(true)
+            .credentials_provider(Credentials::new("AKIA", "secret", None, None, "test"))
+            .build();
+        Client::from_conf(config)
+    }
+
+    fn tags() -> HashMap<String, String> {
+        HashMap::from([
+            ("env".to_string(), "prod".to_string()),
+            ("type".to_string(), "video".to_string()),
+        ])
+    }
+
+    #[tokio::test]
+    async fn test_put_object_tags() {
+        let server = MockServer::start().await;
+        Mock::given(method("PUT"))
+            .and(query_param("tagging", ""))
+            .and(body_string_contains(
+                "<Tag><Key>env</Key><Value>prod</Value></Tag><Tag><Key>type</Key><Value>video</Value></Tag>",
+            ))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = test_client(&server.uri());
+        put_object_tags(&client, "bucket", "clip.mp4", &tags())
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_not_implemented_is_detected() {
+        let server = MockServer::start().await;
+        Mock::given(method("PUT"))
+            .and(query_param("tagging", ""))
+            .respond_with(ResponseTemplate::new(501).set_body_string(NOT_IMPLEMENTED))
+            .mount(&server)
+            .await;
+
+        let client = test_client(&server.uri());
+        let err = put_object_tags(&client, "bucket", "clip.mp4", &tags())
+            .await
+            .unwrap_err();
+        assert!(is_not_implemented(&err), "{:#}", err);
+
+        assert!(!is_not_implemented(&anyhow::anyhow!(
+            "AccessDenied: Access Denied"
+        )));
+    }
+}
@@ -0,0 +1,359 @@
+use super::provider::Provider;
+use crate::config::Config;
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+/// One destination of a fan-out upload (s3upload `--target`)
+#[derive(Debug, Clone)]
+pub struct UploadTarget {
+    /// Short name shown in result lines and the summary table
+    pub label: String,
+    pub config: Config,
+}
+
+impl UploadTarget {
+    /// The target as an `s3://bucket/prefix` URL
+    pub fn url(&self) -> String {
+        format!("s3://{}/{}", self.config.bucket, self.config.target_path)
+    }
+}
+
+/// Settings a target falls back to when it doesn't set its own
+#[derive(Debug, Clone, Default)]
+pub struct TargetDefaults {
+    /// `AWS_REGION`
+    pub region: Option<String>,
+    /// `AWS_PROFILE`
+    pub profile: Option<String>,
+    /// `--role-arn`
+    pub role_arn: Option<String>,
+    /// `S3_ENDPOINT_URL`
+    pub endpoint_url: Option<String>,
+    /// `--provider`
+    pub provider: Option<Provider>,
+}
+
+/// A `[targets.<name>]` table of a targets file
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TargetEntry {
+    pub bucket: String,
+    #[serde(default)]
+    pub prefix: String,
+    pub region: Option<String>,
+    pub profile: Option<String>,
+    pub role_arn: Option<String>,
+    pub endpoint_url: Option<String>,
+    pub provider: Option<Provider>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TargetsFile {
+    #[serde(default)]
+    targets: BTreeMap<String, TargetEntry>,
+}
+
+/// Parse a targets file:
+///
+/// ```toml
+/// [targets.primary]
+/// bucket = "media-us"
+/// prefix = "videos"
+///
+/// [targets.dr]
+/// bucket = "media-eu"
+/// prefix = "videos"
+/// region = "eu-west-1"
+/// profile = "dr"
+///
+/// [targets.r2]
+/// bucket = "media"
+/// region = "auto"
+/// endpoint_url = "https://<account>.r2.cloudflarestorage.com"
+/// ```
+pub fn parse_targets_file(text: &str) -> Result<BTreeMap<String, TargetEntry>> {
+    let file: TargetsFile = toml::from_str(text).context("Failed to parse targets file")?;
+    if file.targets.is_empty() {
+        anyhow::bail!("Targets file defines no [targets.<name>] tables");
+    }
+    Ok(file.targets)
+}
+
+/// Parse `s3://bucket/prefix`, optionally with `?region=eu-west-1`
+pub fn parse_target_url(spec: &str, defaults: &TargetDefaults) -> Result<UploadTarget> {
+    let rest = spec
+        .strip_prefix("s3://")
+        .with_context(|| format!("Target '{}' is not an s3://bucket/prefix URL", spec))?;
+    let (location, region) = match rest.split_once('?') {
+        Some((location, query)) => {
+            let region = query
+                .strip_prefix("region=")
+                .filter(|r| !r.contains('&'))
+                .with_context(|| {
+                    format!(
+                        "Target '{}' has an unsupported query (only ?region=<region>)",
+                        spec
+                    )
+                })?;
+            (location, Some(region.to_string()))
+        }
+        None => (rest, None),
+    };
+    let (bucket, prefix) = location.split_once('/').unwrap_or((location, ""));
+    let prefix = prefix.trim_matches('/');
+    let label = if prefix.is_empty() {
+        bucket.to_string()
+    } else {
+        format!("{}/{}", bucket, prefix)
+    };
+
+    let entry = TargetEntry {
+        bucket: bucket.to_string(),
+        prefix: prefix.to_string(),
+        region,
+        profile: None,
+        role_arn: None,
+        endpoint_url: None,
+        provider: None,
+    };
+    build_target(label, &entry, defaults)
+}
+
+/// Resolve `--target` values into upload targets
+///
+/// Each value is either an `s3://` URL or the name of a table in the targets
+/// file. Without any values every target of the file is used, in name order.
+/// Two targets pointing at the same bucket and prefix are rejected.
+pub fn resolve_targets(
+    specs: &[String],
+    targets_file: Option<&BTreeMap<String, TargetEntry>>,
+    defaults: &TargetDefaults,
+) -> Result<Vec<UploadTarget>> {
+    let mut targets = Vec::new();
+    if specs.is_empty() {
+        for (name, entry) in targets_file.into_iter().flatten() {
+            targets.push(build_target(name.clone(), entry, defaults)?);
+        }
+    }
+    for spec in specs {
+        let target = if spec.starts_with("s3://") {
+            parse_target_url(spec, defaults)?
+        } else {
+            let file = targets_file.with_context(|| {
+                format!(
+                    "Target '{}' is not an s3:// URL; named targets need --targets-file",
+                    spec
+                )
+            })?;
+            let entry = file.get(spec).with_context(|| {
+                let names: Vec<_> = file.keys().map(String::as_str).collect();
+                format!(
+                    "Unknown target '{}' (targets file has: {})",
+                    spec,
+                    names.join(", ")
+                )
+            })?;
+            build_target(spec.clone(), entry, defaults)?
+        };
+        targets.push(target);
+    }
+
+    for (i, target) in targets.iter().enumerate() {
+        if let Some(earlier) = targets[..i].iter().find(|t| {
+            t.config.bucket == target.config.bucket
+                && t.config.target_path == target.config.target_path
+        }) {
+            anyhow::bail!(
+                "Targets '{}' and '{}' both point at {}",
+                earlier.label,
+                target.label,
+                target.url()
+            );
+        }
+    }
+    Ok(targets)
+}
+
+/// Validate a target, filling in what it leaves out from `defaults`
+fn build_target(
+    label: String,
+    entry: &TargetEntry,
+    defaults: &TargetDefaults,
+) -> Result<UploadTarget> {
+    let region = entry
+        .region
+        .as_ref()
+        .or(defaults.region.as_ref())
+        .with_context(|| {
+            format!(
+                "Target '{}' has no region; set one for it or AWS_REGION",
+                label
+            )
+        })?;
+    let profile = entry.profile.clone().or_else(|| defaults.profile.clone());
+    let mut config = Config::new(
+        region,
+        profile,
+        &entry.bucket,
+        entry.prefix.trim_end_matches('/'),
+    )
+    .with_context(|| format!("Invalid target '{}'", label))?;
+    config.role_arn = entry.role_arn.clone().or_else(|| defaults.role_arn.clone());
+    config.endpoint_url = entry
+        .endpoint_url
+        .clone()
+        .or_else(|| defaults.endpoint_url.clone());
+    config.provider = entry.provider.or(defaults.provider);
+
+    Ok(UploadTarget { label, config })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FILE: &str = r#"
+[targets.primary]
+bucket = "media-us"
+prefix = "videos"
+
+[targets.dr]
+bucket = "media-eu"
+prefix = "videos/"
+region = "eu-west-1"
+profile = "dr"
+role_arn = "arn:aws:iam::123456789012:role/dr-uploader"
+
+[targets.r2]
+bucket = "media"
+region = "auto"
+endpoint_url = "https://0123456789abcdef.r2.cloudflarestorage.com"
+"#;
+
+    fn defaults() -> TargetDefaults {
+        TargetDefaults {
+            region: Some("us-west-2".to_string()),
+            profile: Some("default".to_string()),
+            role_arn: None,
+            endpoint_url: None,
+            provider: None,
+        }
+    }
+
+    fn specs(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse_target_url() {
+        let target = parse_target_url("s3://media-us/videos/2025/", &defaults()).unwrap();
+        assert_eq!(target.label, "media-us/videos/2025");
+        assert_eq!(target.config.bucket, "media-us");
+        assert_eq!(target.config.target_path, "videos/2025");
+        assert_eq!(target.config.region, "us-west-2");
+        assert_eq!(target.config.profile.as_deref(), Some("default"));
+
+        let target = parse_target_url("s3://media-eu?region=eu-west-1", &defaults()).unwrap();
+        assert_eq!(target.label, "media-eu");
+        assert_eq!(target.config.target_path, "");
+        assert_eq!(target.config.region, "eu-west-1");
+        assert_eq!(target.url(), "s3://media-eu/");
+
+        assert!(parse_target_url("media-us/videos", &defaults()).is_err());
+        assert!(parse_target_url("s3://Media_US", &defaults()).is_err());
+        assert!(parse_target_url("s3://media-us?acl=private", &defaults()).is_err());
+        assert!(parse_target_url("s3://media-us", &TargetDefaults::default()).is_err());
+
+        let minio = TargetDefaults {
+            endpoint_url: Some("http://localhost:9000".to_string()),
+            ..defaults()
+        };
+        let target = parse_target_url("s3://media-us", &minio).unwrap();
+        assert_eq!(
+            target.config.endpoint_url.as_deref(),
+            Some("http://localhost:9000")
+        );
+        assert_eq!(target.config.provider(), Provider::Minio);
+        let generic = TargetDefaults {
+            provider: Some(Provider::Generic),
+            ..minio
+        };
+        let target = parse_target_url("s3://media-us", &generic).unwrap();
+        assert_eq!(target.config.provider(), Provider::Generic);
+    }
+
+    #[test]
+    fn test_parse_targets_file() {
+        let file = parse_targets_file(FILE).unwrap();
+        assert_eq!(file.len(), 3);
+        assert_eq!(file["primary"].bucket, "media-us");
+        assert_eq!(file["primary"].region, None);
+        assert_eq!(file["dr"].profile.as_deref(), Some("dr"));
+
+        assert!(parse_targets_file("").is_err());
+        assert!(parse_targets_file("[targets.a]\nbucket = \"b-1\"\nacl = \"x\"\n").is_err());
+    }
+
+    #[test]
+    fn test_resolve_named_targets() {
+        let file = parse_targets_file(FILE).unwrap();
+
+        let targets =
+            resolve_targets(&specs(&["primary", "dr"]), Some(&file), &defaults()).unwrap();
+        let labels: Vec<_> = targets.iter().map(|t| t.label.as_str()).collect();
+        assert_eq!(labels, vec!["primary", "dr"]);
+        assert_eq!(targets[0].config.region, "us-west-2");
+        assert_eq!(targets[1].config.region, "eu-west-1");
+        assert_eq!(targets[1].config.target_path, "videos");
+        assert_eq!(targets[1].config.profile.as_deref(), Some("dr"));
+        assert!(targets[1].config.role_arn.is_some());
+
+        // No --target: the whole file, in name order
+        let targets = resolve_targets(&[], Some(&file), &defaults()).unwrap();
+        let labels: Vec<_> = targets.iter().map(|t| t.label.as_str()).collect();
+        assert_eq!(labels, vec!["dr", "primary", "r2"]);
+        assert_eq!(targets[0].config.provider(), Provider::Aws);
+        assert_eq!(targets[2].config.provider(), Provider::R2);
+        assert_eq!(targets[2].config.region, "auto");
+
+        // Names and URLs mix
+        let targets = resolve_targets(
+            &specs(&["primary", "s3://media-archive/videos"]),
+            Some(&file),
+            &defaults(),
+        )
+        .unwrap();
+        assert_eq!(targets[1].label, "media-archive/videos");
+
+        assert!(resolve_targets(&specs(&["backup"]), Some(&file), &defaults()).is_err());
+        assert!(resolve_targets(&specs(&["primary"]), None, &defaults()).is_err());
+    }
+
+    #[test]
+    fn test_resolve_rejects_duplicate_targets() {
+        let file = parse_targets_file(FILE).unwrap();
+
+        let err = resolve_targets(
+            &specs(&["primary", "s3://media-us/videos"]),
+            Some(&file),
+            &defaults(),
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("'primary' and 'media-us/videos'"));
+
+        assert!(resolve_targets(
+            &specs(&["s3://media-us/a", "s3://media-us/a/"]),
+            None,
+            &defaults()
+        )
+        .is_err());
+        assert!(resolve_targets(
+            &specs(&["s3://media-us/a", "s3://media-us/b"]),
+            None,
+            &defaults()
+        )
+        .is_ok());
+    }
+}
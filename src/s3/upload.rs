@@ -1,11 +1,14 @@
 use anyhow::{Context, Result};
 use aws_sdk_s3::{primitives::ByteStream, Client};
 use indicatif::ProgressBar;
+use std::collections::HashMap;
 use std::path::Path;
 use std::time::Duration;
 use tokio::time::sleep;
 use tracing::{debug, info, warn};
 
+use super::mtime::mtime_metadata;
+
 const MAX_RETRIES: u32 = 3;
 const INITIAL_RETRY_DELAY: Duration = Duration::from_secs(1);
 
@@ -22,6 +25,7 @@ pub enum UploadResult {
 /// - Streams the file in chunks to avoid loading entire file in memory
 /// - Updates progress bar in real-time as bytes are uploaded
 /// - Retries on transient failures with exponential backoff
+/// - Stores the local modification time as `x-amz-meta-mtime`
 ///
 /// # Arguments
 ///
@@ -48,7 +52,20 @@ pub async fn upload_file(
     local_path: &Path,
     pb: Option<&ProgressBar>,
 ) -> Result<UploadResult> {
-    upload_file_with_retry(client, bucket, s3_key, local_path, pb).await
+    upload_file_with_metadata(client, bucket, s3_key, local_path, &HashMap::new(), pb).await
+}
+
+/// Upload a file like [`upload_file`], adding `extra_metadata` to the object's user
+/// metadata (next to `mtime`)
+pub async fn upload_file_with_metadata(
+    client: &Client,
+    bucket: &str,
+    s3_key: &str,
+    local_path: &Path,
+    extra_metadata: &HashMap<String, String>,
+    pb: Option<&ProgressBar>,
+) -> Result<UploadResult> {
+    upload_file_with_retry(client, bucket, s3_key, local_path, extra_metadata, pb).await
 }
 
 /// Upload file with retry logic
@@ -57,13 +74,14 @@ async fn upload_file_with_retry(
     bucket: &str,
     s3_key: &str,
     local_path: &Path,
+    extra_metadata: &HashMap<String, String>,
     pb: Option<&ProgressBar>,
 ) -> Result<UploadResult> {
     let mut attempts = 0;
     let mut delay = INITIAL_RETRY_DELAY;
 
     loop {
-        match upload_file_inner(client, bucket, s3_key, local_path, pb).await {
+        match upload_file_inner(client, bucket, s3_key, local_path, extra_metadata, pb).await {
             Ok(result) => {
                 if attempts > 0 {
                     info!(
@@ -118,6 +136,7 @@ async fn upload_file_inner(
     bucket: &str,
     s3_key: &str,
     local_path: &Path,
+    extra_metadata: &HashMap<String, String>,
     pb: Option<&ProgressBar>,
 ) -> Result<UploadResult> {
     // Get file metadata first
@@ -160,6 +179,7 @@ async fn upload_file_inner(
         .key(s3_key)
         .body(body)
         .content_length(file_size as i64)
+        .set_metadata(Some(object_metadata(&metadata, extra_metadata)).filter(|m| !m.is_empty()))
         .send()
         .await
         .with_context(|| format!("Failed to upload to s3://{}/{}", bucket, s3_key))?;
@@ -183,8 +203,25 @@ async fn upload_file_inner(
     Ok(UploadResult::Uploaded)
 }
 
+/// User metadata of an upload: the modification time plus `extra`
+pub(crate) fn object_metadata(
+    metadata: &std::fs::Metadata,
+    extra: &HashMap<String, String>,
+) -> HashMap<String, String> {
+    let mut object_metadata = mtime_metadata(metadata);
+    object_metadata.extend(extra.iter().map(|(k, v)| (k.clone(), v.clone())));
+    object_metadata
+}
+
 /// Check if an error is retryable (transient network errors, throttling, etc.)
+///
+/// Expired credentials are not: retrying would only send them again, so the
+/// caller reloads them first (see [`is_expired_credentials`]).
 fn is_retryable(error: &anyhow::Error) -> bool {
+    if is_expired_credentials(error) {
+        return false;
+    }
+
     let error_str = error.to_string().to_lowercase();
 
     // Check for common retryable error patterns
@@ -201,6 +238,19 @@ fn is_retryable(error: &anyhow::Error) -> bool {
         || error_str.contains("connection reset")
 }
 
+/// Check if an error means the AWS credentials expired or were revoked
+///
+/// The error code (e.g. `ExpiredToken`) is only in the SDK error's source, so
+/// the whole chain is searched.
+pub fn is_expired_credentials(error: &anyhow::Error) -> bool {
+    let error_str = format!("{:#}", error).to_lowercase();
+
+    error_str.contains("expiredtoken")
+        || error_str.contains("invalidtoken")
+        || error_str.contains("token has expired")
+        || error_str.contains("token included in the request is expired")
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -218,5 +268,27 @@ mod tests {
         assert!(!is_retryable(&anyhow::anyhow!("Access Denied")));
         assert!(!is_retryable(&anyhow::anyhow!("Invalid credentials")));
         assert!(!is_retryable(&anyhow::anyhow!("404 Not Found")));
+        assert!(!is_retryable(&anyhow::anyhow!(
+            "ExpiredToken: The provided token has expired (temporary credentials)"
+        )));
+    }
+
+    #[test]
+    fn test_is_expired_credentials() {
+        let sdk_error = anyhow::anyhow!("unhandled error (ExpiredToken)")
+            .context("service error")
+            .context("Failed to upload to s3://bucket/key");
+        assert!(is_expired_credentials(&sdk_error));
+        assert!(is_expired_credentials(&anyhow::anyhow!(
+            "unhandled error (InvalidToken)"
+        )));
+        assert!(is_expired_credentials(&anyhow::anyhow!(
+            "The security token included in the request is expired"
+        )));
+
+        assert!(!is_expired_credentials(&anyhow::anyhow!("Access Denied")));
+        assert!(!is_expired_credentials(&anyhow::anyhow!(
+            "Invalid credentials"
+        )));
     }
 }
@@ -1,26 +1,36 @@
-mod config;
-mod s3;
-
 use anyhow::{Context, Result};
-use clap::Parser;
+use clap::{Parser, ValueEnum};
 use console::style;
-use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
-use std::path::{Path, PathBuf};
-use std::sync::atomic::{AtomicUsize, Ordering};
+use indicatif::{ProgressBar, ProgressStyle};
+use std::collections::{BTreeMap, HashMap};
+use std::io::BufRead;
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::Arc;
-use tokio::sync::{mpsc, Mutex};
+use tokio::sync::{mpsc, watch, Mutex};
 use walkdir::WalkDir;
 
-use config::Config;
 use s3::{
-    compare::compare_file, generate_presigned_url, upload_file, upload_multipart, S3Client,
-    UploadResult, MULTIPART_THRESHOLD,
+    compare::{compare_file_with_quirks, sha256_metadata},
+    dedupe, diff_tree, generate_presigned_url, is_archive_unchanged, is_excluded, parse_tags,
+    upload_archive, upload_file_with_metadata, upload_multipart_with_metadata, ArchiveMember,
+    DiffReport, PartBudget, Provider, S3Client, Support, TargetDefaults, UploadResult,
+    UploadTarget, MULTIPART_THRESHOLD,
+};
+use s3::{
+    diff::normalize_prefix,
+    resolve_targets,
+    tagging::{is_not_implemented, put_object_tags},
+    target::parse_targets_file,
 };
+use swiss_knife::config::Config;
+use swiss_knife::s3;
+use swiss_knife::{status, ui};
 use tracing::{error, info};
 
 // Future use - keeping imports for Phase 5 integration
 #[allow(unused_imports)]
-use s3::{detect_content_type, generate_presigned_url_with_expiry, parse_metadata, parse_tags};
+use s3::{detect_content_type, generate_presigned_url_with_expiry, parse_metadata};
 
 #[derive(Parser, Debug)]
 #[command(
@@ -34,29 +44,72 @@ use s3::{detect_content_type, generate_presigned_url_with_expiry, parse_metadata
                   s3upload ./video.mp4                    # Upload single file\n  \
                   s3upload .                              # Upload all mp4/mov files in current directory\n  \
                   s3upload ./videos -e mp4,mov,avi        # Upload with custom extensions\n  \
-                  s3upload ./video.mp4 --url-only         # Generate pre-signed URL only\n\n\
+                  s3upload ./mixed --all                  # Upload every file type\n  \
+                  s3upload --from-file list.txt --base ./renders  # Upload paths listed in a file\n  \
+                  s3upload ./video.mp4 --url-only         # Generate pre-signed URL only\n  \
+                  s3upload ./videos --diff                # Compare local tree with S3 (read-only)\n  \
+                  s3upload ./videos --diff --output json  # Diff report as JSON\n  \
+                  s3upload ./project --archive backup.tar.gz  # Upload directory as one tar.gz\n  \
+                  s3upload ./videos --role-arn arn:aws:iam::123456789012:role/uploader  # Upload as a role\n  \
+                  s3upload ./video.mp4 --url-only --qr    # Show a QR code to open the URL on a phone\n  \
+                  s3upload ./videos --qr --qr-png ./qr    # Save QR codes as ./qr/<key>.png\n  \
+                  s3upload ./projects --dedupe --all      # Upload identical files once, copy the rest on S3\n  \
+                  s3upload ./videos --order size-asc --max-bytes 20G  # Small files first, stop queueing at 20 GiB\n  \
+                  s3upload ./videos --target s3://media-us/v --target 's3://media-eu/v?region=eu-west-1'  # Upload to two buckets\n  \
+                  s3upload ./videos --targets-file targets.toml --target primary --target dr  # Named targets\n\n\
+                  Set NO_COLOR=1 to turn off colors and pass --no-emoji for plain-text markers.\n\n\
+                  Credentials are reloaded when S3 reports them expired, and the file is retried once; \
+                  renew an SSO session (aws sso login) in another terminal to keep a long run going.\n\n\
                   Configuration (.env):\n  \
                   AWS_REGION=us-west-2\n  \
                   S3_BUCKET=my-bucket\n  \
-                  S3_TARGET_PATH=uploads\n\n\
+                  S3_TARGET_PATH=uploads\n  \
+                  S3_ENDPOINT_URL=https://<account>.r2.cloudflarestorage.com  # S3-compatible service (optional)\n\n\
+                  The provider (aws, r2, minio, generic) is detected from the endpoint host or set with --provider; \
+                  on r2 and generic services unchanged files are matched by an x-amz-meta-sha256 recorded on upload.\n\n\
                   For more information: https://github.com/tyrchen/swiss-knife"
 )]
 struct Cli {
     /// File or directory to upload
-    path: PathBuf,
+    #[arg(required_unless_present = "from_file")]
+    path: Option<PathBuf>,
+
+    /// Read newline-separated file paths from a manifest ("-" for stdin)
+    #[arg(long, value_name = "FILE", conflicts_with = "path")]
+    from_file: Option<String>,
+
+    /// Base directory for computing S3 keys of manifest entries
+    #[arg(long, value_name = "DIR", requires = "from_file")]
+    base: Option<PathBuf>,
 
     /// Only generate pre-signed URLs, don't upload
     #[arg(long)]
     url_only: bool,
 
-    /// Allowed file extensions (comma-separated, e.g., "mp4,mov,avi")
+    /// Allowed file extensions (comma-separated, e.g., "mp4,mov,avi"; "*" for all, "" for none)
     #[arg(long, short = 'e', default_value = "mp4,mov", value_delimiter = ',')]
     extensions: Vec<String>,
 
+    /// Upload every file type (disables extension filtering, same as -e '*')
+    #[arg(long, visible_alias = "all-extensions", conflicts_with = "extensions")]
+    all: bool,
+
+    /// Exclude files matching glob patterns (comma-separated, e.g., "*.tmp,drafts/*")
+    #[arg(long, short = 'x', value_delimiter = ',')]
+    exclude: Vec<String>,
+
     /// Maximum number of concurrent uploads
     #[arg(long, short = 'c', default_value = "4")]
     max_concurrent: usize,
 
+    /// Size of each multipart part in MB (minimum 5)
+    #[arg(long, default_value = "10", value_parser = clap::value_parser!(u64).range(5..))]
+    part_size_mb: u64,
+
+    /// Memory budget in MB for in-flight multipart part buffers across all workers
+    #[arg(long, default_value = "256")]
+    memory_budget_mb: u64,
+
     /// Perform a dry run (show what would be uploaded without uploading)
     #[arg(long)]
     dry_run: bool,
@@ -69,7 +122,7 @@ struct Cli {
     #[arg(long)]
     metadata: Option<String>,
 
-    /// Tags (key=value pairs, comma-separated)
+    /// Tag uploaded objects (key=value pairs, comma-separated)
     #[arg(long)]
     tags: Option<String>,
 
@@ -92,6 +145,95 @@ struct Cli {
     /// Interactive mode: prompt for conflicts
     #[arg(long, short = 'i')]
     interactive: bool,
+
+    /// Diff mode: report local-only, remote-only and changed files without uploading
+    #[arg(long, conflicts_with_all = ["url_only", "dry_run", "sync"])]
+    diff: bool,
+
+    /// Upload the collected files as a single tar.gz object with this name
+    #[arg(long, value_name = "NAME.tar.gz", conflicts_with_all = ["url_only", "diff"])]
+    archive: Option<String>,
+
+    /// Output format for reports
+    #[arg(long, value_enum, default_value = "text")]
+    output: OutputFormat,
+
+    /// Assume this IAM role with the loaded credentials (re-assumed when it expires)
+    #[arg(long, value_name = "ARN")]
+    role_arn: Option<String>,
+
+    /// S3-compatible provider to adapt comparisons and API calls to
+    /// [default: detected from the S3_ENDPOINT_URL host, aws without one]
+    #[arg(long, value_enum)]
+    provider: Option<Provider>,
+
+    /// Upload to this target instead of S3_BUCKET: an s3://bucket/prefix URL (add
+    /// ?region=<region> for another region) or a name from --targets-file; repeat to
+    /// upload every file to each target
+    #[arg(long, value_name = "URL|NAME", conflicts_with_all = ["diff", "archive", "dedupe", "prefix"])]
+    target: Vec<String>,
+
+    /// TOML file of named targets ([targets.<name>] with bucket, prefix, region, profile,
+    /// role_arn); without --target every target in it is used
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["diff", "archive", "dedupe", "prefix"])]
+    targets_file: Option<PathBuf>,
+
+    /// Order in which files are queued [default: name for directories, listed order for --from-file]
+    #[arg(long, value_enum)]
+    order: Option<FileOrder>,
+
+    /// Stop queueing files once their local sizes reach this budget (e.g. 500M, 2G);
+    /// the rest are reported as deferred
+    #[arg(long, value_name = "SIZE", value_parser = parse_byte_size, conflicts_with_all = ["diff", "archive"])]
+    max_bytes: Option<u64>,
+
+    /// Upload identical files once and create the other keys as server-side copies
+    #[arg(long, conflicts_with_all = ["url_only", "diff", "archive"])]
+    dedupe: bool,
+
+    /// Show a QR code for each pre-signed URL (skipped with --quiet or when not a terminal)
+    #[arg(long, conflicts_with = "diff")]
+    qr: bool,
+
+    /// Write the QR codes as PNG files named after the S3 key instead
+    #[arg(long, value_name = "DIR", requires = "qr")]
+    qr_png: Option<PathBuf>,
+
+    /// Plain output for CI logs: no progress bars, colors or emoji
+    /// (also the default when stdout is not a terminal)
+    #[arg(short, long)]
+    quiet: bool,
+
+    /// Replace emoji with plain text (also the default with --quiet, when stdout is
+    /// not a terminal or when the locale is not UTF-8)
+    #[arg(long)]
+    no_emoji: bool,
+}
+
+/// Order in which files are fed to the workers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum FileOrder {
+    /// By relative path
+    Name,
+    /// Smallest first, so their URLs are ready early
+    SizeAsc,
+    /// Largest first
+    SizeDesc,
+    /// Oldest modification time first
+    Mtime,
+}
+
+/// Where `--qr` puts the QR code of each pre-signed URL
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum QrOutput {
+    Terminal,
+    Png(PathBuf),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+    Text,
+    Json,
 }
 
 #[derive(Debug)]
@@ -101,7 +243,10 @@ struct Stats {
     failed: AtomicUsize,
     urls_generated: AtomicUsize,
     not_found: AtomicUsize,
+    deferred: AtomicUsize,
+    copied: AtomicUsize,
     total_bytes_uploaded: std::sync::atomic::AtomicU64,
+    bytes_saved: std::sync::atomic::AtomicU64,
     start_time: std::time::Instant,
 }
 
@@ -113,7 +258,10 @@ impl Default for Stats {
             failed: AtomicUsize::new(0),
             urls_generated: AtomicUsize::new(0),
             not_found: AtomicUsize::new(0),
+            deferred: AtomicUsize::new(0),
+            copied: AtomicUsize::new(0),
             total_bytes_uploaded: std::sync::atomic::AtomicU64::new(0),
+            bytes_saved: std::sync::atomic::AtomicU64::new(0),
             start_time: std::time::Instant::now(),
         }
     }
@@ -131,6 +279,13 @@ enum ProcessResult {
         size: String,
         url: String,
     },
+    /// Created by a server-side copy of an identical file (--dedupe)
+    Copied {
+        filename: String,
+        size: String,
+        url: String,
+        source_key: String,
+    },
     Failed {
         filename: String,
         error: String,
@@ -144,6 +299,46 @@ enum ProcessResult {
     },
 }
 
+/// A file queued for processing together with its key-relative path
+#[derive(Debug, Clone)]
+struct WorkItem {
+    path: PathBuf,
+    relative_path: String,
+}
+
+/// An upload target with its own client and counters
+struct Destination {
+    target: UploadTarget,
+    client: S3Client,
+    /// `[label] ` before result lines when uploading to several targets, else empty
+    tag: String,
+    /// `--tags`, unless the provider can't store them
+    tags: Option<ObjectTags>,
+    stats: Arc<Stats>,
+}
+
+/// `--tags` for one target
+#[derive(Debug)]
+struct ObjectTags {
+    tags: HashMap<String, String>,
+    support: Support,
+    /// Set once the provider answered that it doesn't implement tagging
+    unsupported: AtomicBool,
+}
+
+impl Destination {
+    /// Key a QR code PNG is named after; with several targets the bucket keeps
+    /// the names apart
+    fn qr_key(&self, relative_path: &str) -> String {
+        let key = self.target.config.build_s3_key(relative_path);
+        if self.tag.is_empty() {
+            key
+        } else {
+            format!("{}/{}", self.target.config.bucket, key)
+        }
+    }
+}
+
 impl Stats {
     fn print_upload_summary(&self) {
         let duration = self.start_time.elapsed();
@@ -153,19 +348,29 @@ impl Stats {
         let uploaded_count = self.uploaded.load(Ordering::Relaxed);
         let skipped_count = self.skipped.load(Ordering::Relaxed);
         let failed_count = self.failed.load(Ordering::Relaxed);
-
-        println!("\n{}", style("═".repeat(70)).dim());
-        println!(
-            "{}",
-            style(format!(
-                "Summary: {} uploaded, {} skipped, {} failed",
-                uploaded_count, skipped_count, failed_count
-            ))
-            .bold()
+        let missing_count = self.not_found.load(Ordering::Relaxed);
+        let deferred_count = self.deferred.load(Ordering::Relaxed);
+        let copied_count = self.copied.load(Ordering::Relaxed);
+        let bytes_saved = self.bytes_saved.load(Ordering::Relaxed);
+
+        status!("\n{}", style("═".repeat(70)).dim());
+        let mut summary = format!(
+            "Summary: {} uploaded, {} skipped, {} failed",
+            uploaded_count, skipped_count, failed_count
         );
+        if copied_count > 0 {
+            summary.push_str(&format!(", {} copied", copied_count));
+        }
+        if missing_count > 0 {
+            summary.push_str(&format!(", {} missing", missing_count));
+        }
+        if deferred_count > 0 {
+            summary.push_str(&format!(", {} deferred", deferred_count));
+        }
+        status!("{}", style(summary).bold());
 
         if total_bytes > 0 {
-            println!(
+            status!(
                 "{}",
                 style(format!(
                     "Total uploaded: {} ({} bytes)",
@@ -176,9 +381,21 @@ impl Stats {
             );
         }
 
+        if bytes_saved > 0 {
+            status!(
+                "{}",
+                style(format!(
+                    "Saved by --dedupe: {} ({} bytes) copied server-side",
+                    format_size(bytes_saved),
+                    bytes_saved
+                ))
+                .dim()
+            );
+        }
+
         if duration.as_secs() > 0 {
             let speed = total_bytes as f64 / duration.as_secs_f64() / 1024.0 / 1024.0;
-            println!(
+            status!(
                 "{}",
                 style(format!(
                     "Time: {:.2}s, Average speed: {:.2} MB/s",
@@ -191,15 +408,16 @@ impl Stats {
     }
 
     fn print_url_summary(&self) {
-        println!(
-            "{}",
-            style(format!(
-                "Summary: {} URL(s) generated, {} not found",
-                self.urls_generated.load(Ordering::Relaxed),
-                self.not_found.load(Ordering::Relaxed)
-            ))
-            .bold()
+        let mut summary = format!(
+            "Summary: {} URL(s) generated, {} not found",
+            self.urls_generated.load(Ordering::Relaxed),
+            self.not_found.load(Ordering::Relaxed)
         );
+        let deferred_count = self.deferred.load(Ordering::Relaxed);
+        if deferred_count > 0 {
+            summary.push_str(&format!(", {} deferred", deferred_count));
+        }
+        status!("{}", style(summary).bold());
     }
 }
 
@@ -207,129 +425,298 @@ impl Stats {
 async fn main() -> Result<()> {
     // Load .env file early to get LOG_LEVEL
     dotenv::dotenv().ok();
+    let cli = Cli::parse();
+    ui::init(cli.quiet, cli.no_emoji);
 
     // Initialize tracing/logging with support for LOG_LEVEL from .env
-    let log_level = std::env::var("LOG_LEVEL")
-        .ok()
-        .or_else(|| std::env::var("RUST_LOG").ok())
-        .unwrap_or_else(|| "info".to_string());
-
-    tracing_subscriber::fmt()
-        .with_env_filter(
-            tracing_subscriber::EnvFilter::try_from_default_env()
-                .or_else(|_| tracing_subscriber::EnvFilter::try_new(&log_level))
-                .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("info")),
-        )
-        .with_target(false)
-        .with_level(true)
-        .init();
-
-    let cli = Cli::parse();
+    ui::init_tracing("info");
 
     info!("S3 Upload Tool v{}", env!("CARGO_PKG_VERSION"));
     info!("Concurrent workers: {}", cli.max_concurrent);
 
-    let config = Config::from_env()?;
+    // Size the part buffer budget first so a bad combination fails before any work
+    let budget = PartBudget::new(
+        cli.memory_budget_mb * 1024 * 1024,
+        (cli.part_size_mb * 1024 * 1024) as usize,
+    )?;
+    info!(
+        "Multipart: {} MB parts, up to {} buffers in flight",
+        cli.part_size_mb,
+        budget.max_parts()
+    );
+
+    // One client per target, since targets may differ in region, profile or role
+    let targets = load_targets(&cli)?;
+    let fan_out = targets.len() > 1;
+    let tags = cli.tags.as_deref().map(parse_tags).unwrap_or_default();
+    let mut destinations = Vec::with_capacity(targets.len());
+    for target in targets {
+        let client = S3Client::new(target.config.clone()).await?;
+        let tag = if fan_out {
+            format!("[{}] ", target.label)
+        } else {
+            String::new()
+        };
+        let support = client.provider().quirks().tagging;
+        let tags = (!tags.is_empty() && support != Support::No).then(|| ObjectTags {
+            tags: tags.clone(),
+            support,
+            unsupported: AtomicBool::new(false),
+        });
+        destinations.push(Destination {
+            target,
+            client,
+            tag,
+            tags,
+            stats: Arc::new(Stats::default()),
+        });
+    }
+    let destinations = Arc::new(destinations);
 
-    // Initialize S3 client
-    let s3_client = S3Client::new(config.clone()).await?;
+    // --diff and --archive can't be combined with --target, so they use the only one
+    let config = destinations[0].target.config.clone();
+    let s3_client = destinations[0].client.clone();
 
     // Collect files to process
-    let files = collect_files(&cli.path, &cli.extensions)?;
+    let extensions = if cli.all {
+        vec!["*".to_string()]
+    } else {
+        cli.extensions.clone()
+    };
+    let (items, filtered_out) = if let Some(ref manifest) = cli.from_file {
+        let paths = if manifest == "-" {
+            read_manifest(std::io::stdin().lock())?
+        } else {
+            let file = std::fs::File::open(manifest)
+                .with_context(|| format!("Failed to open manifest: {}", manifest))?;
+            read_manifest(std::io::BufReader::new(file))?
+        };
+        collect_manifest_items(paths, cli.base.as_deref(), &extensions, &cli.exclude)?
+    } else {
+        let path = cli.path.as_deref().context("No path given")?;
+        let CollectedFiles {
+            files,
+            filtered_out,
+        } = collect_files(path, &extensions, &cli.exclude)?;
+
+        let mut items = Vec::with_capacity(files.len());
+        for file in files {
+            let relative_path = get_relative_path(path, &file, cli.flatten)?;
+            items.push(WorkItem {
+                path: file,
+                relative_path,
+            });
+        }
+        // Directory walks are reported in name order
+        items.sort_by(|a, b| a.relative_path.cmp(&b.relative_path));
+        (items, filtered_out)
+    };
+
+    let (items, deferred) = schedule_items(items, cli.order, cli.max_bytes);
+
+    // Results are printed in the order files were queued
+    let order: Arc<HashMap<PathBuf, usize>> = Arc::new(
+        items
+            .iter()
+            .enumerate()
+            .map(|(i, item)| (item.path.clone(), i))
+            .collect(),
+    );
+
+    // Handle diff mode (read-only, an empty local side is still meaningful)
+    if cli.diff {
+        let prefix = cli
+            .prefix
+            .clone()
+            .unwrap_or_else(|| config.target_path.clone());
+
+        let local_files: Vec<_> = items
+            .iter()
+            .filter(|item| item.path.exists())
+            .map(|item| {
+                let s3_key = resolve_s3_key(&config, cli.prefix.as_deref(), &item.relative_path);
+                (s3_key, item.path.clone())
+            })
+            .collect();
+
+        let report = diff_tree(
+            s3_client.client(),
+            s3_client.bucket(),
+            &prefix,
+            &local_files,
+            &s3_client.provider().quirks(),
+        )
+        .await?;
+
+        match cli.output {
+            OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&report)?),
+            OutputFormat::Text => print_diff_report(&report),
+        }
+
+        return Ok(());
+    }
+
+    if items.is_empty() && deferred.is_empty() {
+        print_no_files_found(&extensions, &filtered_out);
+        return Ok(());
+    }
 
-    if files.is_empty() {
-        println!(
+    if fan_out {
+        status!("{}", style("📦 Targets:").cyan().bold());
+        for dest in destinations.iter() {
+            status!(
+                "  {} {} ({})",
+                style(&dest.target.label).cyan().bold(),
+                dest.target.url(),
+                style(format!(
+                    "{}, {}",
+                    dest.target.config.region,
+                    dest.client.provider()
+                ))
+                .dim()
+            );
+        }
+    } else {
+        status!(
+            "{}",
+            style(format!("📦 Target: {}", destinations[0].target.url()))
+                .cyan()
+                .bold()
+        );
+        status!(
             "{}",
             style(format!(
-                "No files found with extensions: {}",
-                cli.extensions.join(", ")
+                "☁️  Provider: {} ({})",
+                s3_client.provider(),
+                provider_source(&config)
             ))
-            .yellow()
+            .dim()
         );
-        return Ok(());
+    }
+    if !tags.is_empty() {
+        for dest in destinations.iter().filter(|d| d.tags.is_none()) {
+            status!(
+                "{}",
+                style(format!(
+                    "⚠️  {}{} doesn't support object tagging; uploading without --tags",
+                    dest.tag,
+                    dest.client.provider()
+                ))
+                .yellow()
+            );
+        }
     }
 
-    println!(
-        "{}",
-        style(format!(
-            "📦 Target: s3://{}/{}",
-            s3_client.bucket(),
-            config.target_path
-        ))
-        .cyan()
-        .bold()
-    );
+    let qr = qr_output(&cli)?;
+
+    if let Some(ref archive_name) = cli.archive {
+        return process_archive(
+            &cli,
+            &config,
+            &s3_client,
+            &items,
+            archive_name,
+            &budget,
+            qr.as_ref(),
+        )
+        .await;
+    }
 
-    let multi = Arc::new(MultiProgress::new());
-    let stats = Arc::new(Stats::default());
+    let multi = Arc::new(ui::multi_progress());
+    for dest in destinations.iter() {
+        dest.stats.deferred.store(deferred.len(), Ordering::Relaxed);
+    }
 
     // Handle dry-run mode
     if cli.dry_run {
-        println!(
+        status!(
             "{}",
             style("🔍 DRY RUN MODE - No files will be uploaded")
                 .yellow()
                 .bold()
         );
-        println!();
+        status!();
 
-        for file in &files {
-            let relative_path = get_relative_path(&cli.path, file, cli.flatten)?;
-            let s3_key = if let Some(ref prefix) = cli.prefix {
-                format!(
-                    "{}/{}",
-                    prefix.trim_end_matches('/'),
-                    relative_path.trim_start_matches("./")
-                )
-            } else {
-                config.build_s3_key(&relative_path)
-            };
+        for WorkItem {
+            path: file,
+            relative_path,
+        } in &items
+        {
+            if !file.exists() {
+                status!(
+                    "  {} {} (not found locally)",
+                    style("MISSING").red().bold(),
+                    relative_path
+                );
+                continue;
+            }
 
             let metadata = tokio::fs::metadata(file).await?;
             let size = format_size(metadata.len());
 
-            // Check if file exists on S3
-            let comparison =
-                compare_file(s3_client.client(), s3_client.bucket(), &s3_key, file).await?;
-
-            match comparison {
-                s3::FileComparison::NotFound => {
-                    println!(
-                        "  {} {} → s3://{}/{} ({})",
-                        style("WOULD UPLOAD").green().bold(),
-                        relative_path,
-                        s3_client.bucket(),
-                        s3_key,
-                        size
-                    );
-                }
-                s3::FileComparison::Different => {
-                    println!(
-                        "  {} {} → s3://{}/{} ({})",
-                        style("WOULD UPDATE").yellow().bold(),
-                        relative_path,
-                        s3_client.bucket(),
-                        s3_key,
-                        size
-                    );
-                }
-                s3::FileComparison::Identical => {
-                    println!(
-                        "  {} {} ({})",
-                        style("WOULD SKIP").dim(),
-                        relative_path,
-                        size
-                    );
+            // Check if file exists on each target
+            for dest in destinations.iter() {
+                let s3_key =
+                    resolve_s3_key(&dest.target.config, cli.prefix.as_deref(), relative_path);
+                let comparison = compare_file_with_quirks(
+                    dest.client.client(),
+                    dest.client.bucket(),
+                    &s3_key,
+                    file,
+                    &dest.client.provider().quirks(),
+                )
+                .await?;
+
+                match comparison {
+                    s3::FileComparison::NotFound => {
+                        status!(
+                            "  {} {}{} → s3://{}/{} ({})",
+                            style("WOULD UPLOAD").green().bold(),
+                            dest.tag,
+                            relative_path,
+                            dest.client.bucket(),
+                            s3_key,
+                            size
+                        );
+                    }
+                    s3::FileComparison::Different => {
+                        status!(
+                            "  {} {}{} → s3://{}/{} ({})",
+                            style("WOULD UPDATE").yellow().bold(),
+                            dest.tag,
+                            relative_path,
+                            dest.client.bucket(),
+                            s3_key,
+                            size
+                        );
+                    }
+                    s3::FileComparison::Identical => {
+                        status!(
+                            "  {} {}{} ({})",
+                            style("WOULD SKIP").dim(),
+                            dest.tag,
+                            relative_path,
+                            size
+                        );
+                    }
                 }
             }
         }
+        for (item, info) in &deferred {
+            status!(
+                "  {} {} ({})",
+                style("DEFERRED").yellow().bold(),
+                item.relative_path,
+                format_size(info.size)
+            );
+        }
 
         return Ok(());
     }
 
     if cli.url_only {
         // URL-only mode - concurrent URL generation using mpsc
-        println!(
+        status!(
             "{}",
             style(format!(
                 "🔗 Generating pre-signed URLs ({} workers)...",
@@ -339,36 +726,37 @@ async fn main() -> Result<()> {
         );
 
         // Create work channel and results channel
-        let (work_tx, work_rx) = mpsc::channel::<PathBuf>(100);
-        let (result_tx, mut result_rx) = mpsc::channel::<ProcessResult>(100);
+        let (work_tx, work_rx) = mpsc::channel::<(WorkItem, usize)>(100);
+        let (result_tx, mut result_rx) = mpsc::channel::<(PathBuf, usize, ProcessResult)>(100);
         let work_rx = Arc::new(Mutex::new(work_rx));
 
         // Spawn worker tasks
         let mut workers = Vec::new();
         for _ in 0..cli.max_concurrent {
             let work_rx = Arc::clone(&work_rx);
-            let s3_client = s3_client.clone();
-            let config = config.clone();
-            let stats = Arc::clone(&stats);
-            let base_path = cli.path.clone();
+            let destinations = Arc::clone(&destinations);
             let result_tx = result_tx.clone();
 
             workers.push(tokio::spawn(async move {
                 loop {
-                    let file_path = {
+                    let job = {
                         let mut rx_guard = work_rx.lock().await;
                         rx_guard.recv().await
                     };
 
-                    match file_path {
-                        Some(path) => {
+                    match job {
+                        Some((item, target)) => {
+                            let dest = &destinations[target];
                             let result = process_url_only_with_result(
-                                &s3_client, &config, &path, &base_path, &stats,
+                                &dest.client,
+                                &dest.target.config,
+                                &item.relative_path,
+                                &dest.stats,
                             )
                             .await;
 
                             if let Ok(r) = result {
-                                let _ = result_tx.send(r).await;
+                                let _ = result_tx.send((item.path, target, r)).await;
                             }
                         }
                         None => break, // Channel closed
@@ -387,9 +775,11 @@ async fn main() -> Result<()> {
             results
         });
 
-        // Producer: Send files to channel
-        for file_path in files {
-            work_tx.send(file_path).await.unwrap();
+        // Producer: Send each file to every target
+        for item in &items {
+            for target in 0..destinations.len() {
+                work_tx.send((item.clone(), target)).await.unwrap();
+            }
         }
         drop(work_tx); // Close channel to signal workers to exit
 
@@ -402,32 +792,28 @@ async fn main() -> Result<()> {
 
         // Collect and sort results
         let mut results = collector_handle.await.unwrap();
-        results.sort_by(|a, b| {
-            let a_name = match a {
-                ProcessResult::UrlGenerated { filename, .. } => filename,
-                ProcessResult::NotFound { filename } => filename,
-                _ => "",
-            };
-            let b_name = match b {
-                ProcessResult::UrlGenerated { filename, .. } => filename,
-                ProcessResult::NotFound { filename } => filename,
-                _ => "",
-            };
-            a_name.cmp(b_name)
-        });
+        sort_results(&mut results, &order);
 
         // Print results
-        println!();
-        for result in results {
+        status!();
+        for (_, target, result) in results {
+            let dest = &destinations[target];
             match result {
                 ProcessResult::UrlGenerated { filename, url } => {
-                    println!("{} {}", style("✓").green(), style(&filename).green());
-                    println!("  {} {}", style("🔗").blue(), style(&url).dim());
+                    status!(
+                        "{} {}{}",
+                        style("✓").green(),
+                        style(&dest.tag).cyan(),
+                        style(&filename).green()
+                    );
+                    status!("  {} {}", style("🔗").blue(), style(&url).dim());
+                    print_qr(qr.as_ref(), &dest.qr_key(&filename), &url);
                 }
                 ProcessResult::NotFound { filename } => {
-                    println!(
-                        "{} {} {}",
+                    status!(
+                        "{} {}{} {}",
                         style("⚠").yellow(),
+                        style(&dest.tag).cyan(),
                         style(&filename).yellow(),
                         style("(not found on S3)").dim()
                     );
@@ -436,12 +822,24 @@ async fn main() -> Result<()> {
             }
         }
 
+        print_deferred(&deferred);
+
         // Print summary
-        println!();
-        stats.print_url_summary();
+        status!();
+        if fan_out {
+            print_target_summary(&destinations, true);
+        } else {
+            destinations[0].stats.print_url_summary();
+        }
     } else {
         // Upload mode - concurrent uploads using mpsc
-        println!(
+        let dedupe = if cli.dedupe {
+            Some(Arc::new(plan_dedupe(&items, cli.max_concurrent).await?))
+        } else {
+            None
+        };
+
+        status!(
             "{}",
             style(format!(
                 "⚡ Uploading with {} workers...",
@@ -451,56 +849,84 @@ async fn main() -> Result<()> {
         );
 
         // Create work channel and results channel
-        let (work_tx, work_rx) = mpsc::channel::<PathBuf>(100);
-        let (result_tx, mut result_rx) = mpsc::channel::<ProcessResult>(100);
+        let (work_tx, work_rx) = mpsc::channel::<(WorkItem, usize)>(100);
+        let (result_tx, mut result_rx) = mpsc::channel::<(PathBuf, usize, ProcessResult)>(100);
         let work_rx = Arc::new(Mutex::new(work_rx));
 
         // Spawn worker tasks
         let mut workers = Vec::new();
         for _ in 0..cli.max_concurrent {
             let work_rx = Arc::clone(&work_rx);
-            let s3_client = s3_client.clone();
-            let config = config.clone();
-            let stats = Arc::clone(&stats);
+            let destinations = Arc::clone(&destinations);
             let multi = Arc::clone(&multi);
-            let base_path = cli.path.clone();
+            let budget = budget.clone();
+            let dedupe = dedupe.clone();
             let result_tx = result_tx.clone();
 
             workers.push(tokio::spawn(async move {
                 loop {
-                    let file_path = {
+                    let job = {
                         let mut rx_guard = work_rx.lock().await;
                         rx_guard.recv().await
                     };
 
-                    match file_path {
-                        Some(path) => {
+                    match job {
+                        Some((item, target)) => {
+                            let dest = &destinations[target];
                             let pb = multi.add(ProgressBar::new(0));
-                            pb.set_style(
-                                ProgressStyle::default_bar()
-                                    .template(
-                                        "{spinner:.green} [{bar:40.cyan/blue}] {bytes}/{total_bytes} {msg}",
+                            pb.set_style(ui::upload_bar_style());
+
+                            let source = dedupe
+                                .as_ref()
+                                .and_then(|d| d.source_of(&item.relative_path));
+                            let result = match (&dedupe, source) {
+                                (Some(dedupe), Some(source)) => {
+                                    process_copy_with_result(
+                                        &dest.client,
+                                        &dest.target.config,
+                                        &item,
+                                        source,
+                                        dedupe,
+                                        &budget,
+                                        dest.tags.as_ref(),
+                                        &pb,
+                                        &dest.stats,
                                     )
-                                    .unwrap()
-                                    .progress_chars("#>-"),
-                            );
-
-                            let result = process_upload_with_result(
-                                &s3_client,
-                                &config,
-                                &path,
-                                &base_path,
-                                &pb,
-                                &stats,
-                            )
-                            .await;
+                                    .await
+                                }
+                                _ => {
+                                    process_upload_with_result(
+                                        &dest.client,
+                                        &dest.target.config,
+                                        &item.path,
+                                        &item.relative_path,
+                                        &budget,
+                                        dest.tags.as_ref(),
+                                        &pb,
+                                        &dest.stats,
+                                    )
+                                    .await
+                                }
+                            };
 
                             pb.finish_and_clear();
-
-                            // Send result to results channel
-                            if let Ok(r) = result {
-                                let _ = result_tx.send(r).await;
+                            if let Some(dedupe) = &dedupe {
+                                dedupe.finish(&item.relative_path, &result);
                             }
+
+                            // An error only fails the file on this target
+                            let result = result.unwrap_or_else(|e| {
+                                error!(
+                                    "{} failed for {}: {:#}",
+                                    dest.target.label, item.relative_path, e
+                                );
+                                dest.stats.failed.fetch_add(1, Ordering::Relaxed);
+                                ProcessResult::Failed {
+                                    filename: item.relative_path.clone(),
+                                    error: format!("{:#}", e),
+                                }
+                            });
+                            let _ = result_tx.send((item.path, target, result)).await;
                         }
                         None => break, // Channel closed
                     }
@@ -518,9 +944,11 @@ async fn main() -> Result<()> {
             results
         });
 
-        // Producer: Send files to channel
-        for file_path in files {
-            work_tx.send(file_path).await.unwrap();
+        // Producer: Send each file to every target
+        for item in &items {
+            for target in 0..destinations.len() {
+                work_tx.send((item.clone(), target)).await.unwrap();
+            }
         }
         drop(work_tx); // Close channel to signal workers to exit
 
@@ -533,90 +961,362 @@ async fn main() -> Result<()> {
 
         // Collect and sort results
         let mut results = collector_handle.await.unwrap();
-        results.sort_by(|a, b| {
-            let a_name = match a {
-                ProcessResult::Uploaded { filename, .. } => filename,
-                ProcessResult::Skipped { filename, .. } => filename,
-                ProcessResult::Failed { filename, .. } => filename,
-                _ => "",
-            };
-            let b_name = match b {
-                ProcessResult::Uploaded { filename, .. } => filename,
-                ProcessResult::Skipped { filename, .. } => filename,
-                ProcessResult::Failed { filename, .. } => filename,
-                _ => "",
-            };
-            a_name.cmp(b_name)
-        });
+        sort_results(&mut results, &order);
 
         // Print results
-        println!();
-        for result in results {
+        status!();
+        for (_, target, result) in results {
+            let dest = &destinations[target];
             match result {
                 ProcessResult::Uploaded {
                     filename,
                     size,
                     url,
                 } => {
-                    println!(
-                        "{} {} ({})",
+                    status!(
+                        "{} {}{} ({})",
                         style("✓").green(),
+                        style(&dest.tag).cyan(),
                         style(&filename).green(),
                         style(size).dim()
                     );
-                    println!("  {} {}", style("🔗").blue(), style(&url).dim());
+                    status!("  {} {}", style("🔗").blue(), style(&url).dim());
+                    print_qr(qr.as_ref(), &dest.qr_key(&filename), &url);
                 }
                 ProcessResult::Skipped {
                     filename,
                     size,
                     url,
                 } => {
-                    println!(
-                        "{} {} ({})",
+                    status!(
+                        "{} {}{} ({})",
                         style("↻").yellow(),
+                        style(&dest.tag).cyan(),
                         style(&filename).dim(),
                         style(format!("skipped - identical, {}", size)).dim()
                     );
-                    println!("  {} {}", style("🔗").blue(), style(&url).dim());
+                    status!("  {} {}", style("🔗").blue(), style(&url).dim());
+                    print_qr(qr.as_ref(), &dest.qr_key(&filename), &url);
+                }
+                ProcessResult::Copied {
+                    filename,
+                    size,
+                    url,
+                    source_key,
+                } => {
+                    status!(
+                        "{} {}{} ({})",
+                        style("⧉").green(),
+                        style(&dest.tag).cyan(),
+                        style(&filename).green(),
+                        style(format!("copied from {}, {}", source_key, size)).dim()
+                    );
+                    status!("  {} {}", style("🔗").blue(), style(&url).dim());
+                    print_qr(qr.as_ref(), &dest.qr_key(&filename), &url);
                 }
                 ProcessResult::Failed { filename, error } => {
-                    println!(
-                        "{} {} - {}",
+                    status!(
+                        "{} {}{} - {}",
                         style("✗").red(),
+                        style(&dest.tag).cyan(),
                         style(&filename).red(),
                         style(error).red()
                     );
                 }
+                ProcessResult::NotFound { filename } => {
+                    status!(
+                        "{} {}{} {}",
+                        style("⚠").yellow(),
+                        style(&dest.tag).cyan(),
+                        style(&filename).yellow(),
+                        style("(not found locally)").dim()
+                    );
+                }
                 _ => {}
             }
         }
 
+        print_deferred(&deferred);
+
         // Print summary
-        println!();
-        stats.print_upload_summary();
+        status!();
+        if fan_out {
+            print_target_summary(&destinations, false);
+        } else {
+            destinations[0].stats.print_upload_summary();
+        }
     }
 
     Ok(())
 }
 
-/// Collect all files to process from the given path, filtered by extensions
-fn collect_files(path: &Path, allowed_extensions: &[String]) -> Result<Vec<PathBuf>> {
-    let mut files = Vec::new();
+/// Size and modification time of a collected file, for `--order` and `--max-bytes`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct FileInfo {
+    size: u64,
+    modified: Option<std::time::SystemTime>,
+}
+
+impl FileInfo {
+    /// Read from disk; missing files count as empty and are reported later
+    fn of(path: &Path) -> Self {
+        std::fs::metadata(path)
+            .map(|m| Self {
+                size: m.len(),
+                modified: m.modified().ok(),
+            })
+            .unwrap_or_default()
+    }
+}
+
+/// Collected files with their size and modification time
+type SizedFiles = Vec<(WorkItem, FileInfo)>;
+
+/// Apply `--order` and `--max-bytes` to the collected files
+///
+/// Returns the files to queue and the deferred ones, both in queue order.
+fn schedule_items(
+    items: Vec<WorkItem>,
+    order: Option<FileOrder>,
+    max_bytes: Option<u64>,
+) -> (Vec<WorkItem>, SizedFiles) {
+    if order.is_none() && max_bytes.is_none() {
+        return (items, Vec::new());
+    }
+
+    let mut files: Vec<_> = items
+        .into_iter()
+        .map(|item| {
+            let info = FileInfo::of(&item.path);
+            (item, info)
+        })
+        .collect();
+    if let Some(order) = order {
+        order_files(&mut files, order);
+    }
+    let (queued, deferred) = match max_bytes {
+        Some(max_bytes) => split_byte_budget(files, max_bytes),
+        None => (files, Vec::new()),
+    };
+    (queued.into_iter().map(|(item, _)| item).collect(), deferred)
+}
+
+/// Sort files for `--order`; ties keep name order so runs are repeatable
+fn order_files(files: &mut [(WorkItem, FileInfo)], order: FileOrder) {
+    files.sort_by(|(a, a_info), (b, b_info)| {
+        let by_key = match order {
+            FileOrder::Name => std::cmp::Ordering::Equal,
+            FileOrder::SizeAsc => a_info.size.cmp(&b_info.size),
+            FileOrder::SizeDesc => b_info.size.cmp(&a_info.size),
+            FileOrder::Mtime => a_info.modified.cmp(&b_info.modified),
+        };
+        by_key.then_with(|| a.relative_path.cmp(&b.relative_path))
+    });
+}
+
+/// Queue files in order until the next one would take the total past
+/// `max_bytes`; that file and everything after it is deferred
+fn split_byte_budget(files: SizedFiles, max_bytes: u64) -> (SizedFiles, SizedFiles) {
+    let mut total = 0u64;
+    let fits = files
+        .iter()
+        .take_while(|(_, info)| {
+            total += info.size;
+            total <= max_bytes
+        })
+        .count();
+
+    let mut queued = files;
+    let deferred = queued.split_off(fits);
+    (queued, deferred)
+}
+
+/// Parse a byte size such as `1048576`, `500M`, `1.5G` or `2GB` (binary units)
+fn parse_byte_size(value: &str) -> Result<u64, String> {
+    let trimmed = value.trim();
+    let upper = trimmed.to_ascii_uppercase();
+    let number = upper.trim_end_matches('B').trim_end_matches('I');
+    let (number, unit) = match number.char_indices().last() {
+        Some((i, c)) if c.is_ascii_alphabetic() => (&number[..i], c),
+        _ => (number, ' '),
+    };
+    let multiplier: u64 = match unit {
+        ' ' => 1,
+        'K' => 1024,
+        'M' => 1024 * 1024,
+        'G' => 1024 * 1024 * 1024,
+        'T' => 1024 * 1024 * 1024 * 1024,
+        _ => {
+            return Err(format!(
+                "'{}' has an unknown unit (use K, M, G or T)",
+                value
+            ))
+        }
+    };
+    match number.trim().parse::<f64>() {
+        Ok(n) if n.is_finite() && n >= 0.0 => Ok((n * multiplier as f64).round() as u64),
+        _ => Err(format!("'{}' is not a size like 500M or 2G", value)),
+    }
+}
+
+/// List the files `--max-bytes` left out of this run
+fn print_deferred(deferred: &[(WorkItem, FileInfo)]) {
+    for (item, info) in deferred {
+        status!(
+            "{} {} ({})",
+            style("⏸").yellow(),
+            style(&item.relative_path).yellow(),
+            style(format!(
+                "deferred by --max-bytes, {}",
+                format_size(info.size)
+            ))
+            .dim()
+        );
+    }
+}
+
+/// Per-target counts as table rows, the header first
+fn target_summary_lines(rows: &[(&str, &Stats)], url_only: bool) -> Vec<String> {
+    let width = rows
+        .iter()
+        .map(|(label, _)| label.chars().count())
+        .max()
+        .unwrap_or(0)
+        .max("Target".len());
+    let mut lines = Vec::with_capacity(rows.len() + 1);
+    if url_only {
+        lines.push(format!(
+            "{:<width$}  {:>5}  {:>9}",
+            "Target", "URLs", "Not found"
+        ));
+        for (label, stats) in rows {
+            lines.push(format!(
+                "{:<width$}  {:>5}  {:>9}",
+                label,
+                stats.urls_generated.load(Ordering::Relaxed),
+                stats.not_found.load(Ordering::Relaxed)
+            ));
+        }
+    } else {
+        lines.push(format!(
+            "{:<width$}  {:>8}  {:>7}  {:>6}  {:>7}  {:>10}",
+            "Target", "Uploaded", "Skipped", "Failed", "Missing", "Size"
+        ));
+        for (label, stats) in rows {
+            lines.push(format!(
+                "{:<width$}  {:>8}  {:>7}  {:>6}  {:>7}  {:>10}",
+                label,
+                stats.uploaded.load(Ordering::Relaxed),
+                stats.skipped.load(Ordering::Relaxed),
+                stats.failed.load(Ordering::Relaxed),
+                stats.not_found.load(Ordering::Relaxed),
+                format_size(stats.total_bytes_uploaded.load(Ordering::Relaxed))
+            ));
+        }
+    }
+    lines
+}
+
+/// Summary for several targets: one table row each
+fn print_target_summary(destinations: &[Destination], url_only: bool) {
+    if !url_only {
+        status!("\n{}", style("═".repeat(70)).dim());
+    }
+    let rows: Vec<_> = destinations
+        .iter()
+        .map(|dest| (dest.target.label.as_str(), dest.stats.as_ref()))
+        .collect();
+    for (i, line) in target_summary_lines(&rows, url_only)
+        .into_iter()
+        .enumerate()
+    {
+        if i == 0 {
+            status!("{}", style(line).bold());
+        } else {
+            status!("{}", line);
+        }
+    }
+
+    let stats = &destinations[0].stats;
+    let deferred_count = stats.deferred.load(Ordering::Relaxed);
+    if deferred_count > 0 {
+        status!(
+            "{}",
+            style(format!("{} deferred by --max-bytes", deferred_count)).dim()
+        );
+    }
+    if !url_only {
+        status!(
+            "{}",
+            style(format!(
+                "Time: {:.2}s",
+                stats.start_time.elapsed().as_secs_f64()
+            ))
+            .dim()
+        );
+    }
+}
+
+/// Files selected for processing, plus what the extension filter dropped
+#[derive(Debug, Default)]
+struct CollectedFiles {
+    files: Vec<PathBuf>,
+    /// Number of files skipped per extension ("" for extension-less files)
+    filtered_out: BTreeMap<String, usize>,
+}
+
+/// Check whether a file passes the extension filter
+///
+/// `*` matches everything and an empty entry matches files without an extension.
+fn matches_extension(path: &Path, extensions: &[String]) -> bool {
+    if extensions.iter().any(|ext| ext == "*") {
+        return true;
+    }
+
+    let file_ext = path
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    extensions.contains(&file_ext)
+}
+
+/// Collect all files to process from the given path, filtered by extensions and exclude patterns
+fn collect_files(
+    path: &Path,
+    allowed_extensions: &[String],
+    exclude: &[String],
+) -> Result<CollectedFiles> {
+    let mut collected = CollectedFiles::default();
 
     // Normalize extensions to lowercase for case-insensitive matching
     let extensions: Vec<String> = allowed_extensions
         .iter()
-        .map(|ext| ext.trim_start_matches('.').to_lowercase())
+        .map(|ext| ext.trim().trim_start_matches('.').to_lowercase())
         .collect();
 
+    let mut consider = |file: &Path, relative: &str| {
+        if is_excluded(relative, exclude) {
+            return;
+        }
+
+        if matches_extension(file, &extensions) {
+            collected.files.push(file.to_path_buf());
+        } else {
+            let ext = file
+                .extension()
+                .map(|ext| ext.to_string_lossy().to_lowercase())
+                .unwrap_or_default();
+            *collected.filtered_out.entry(ext).or_default() += 1;
+        }
+    };
+
     if path.is_file() {
         // Check if single file matches allowed extensions
-        if let Some(ext) = path.extension() {
-            let file_ext = ext.to_string_lossy().to_lowercase();
-            if extensions.contains(&file_ext) {
-                files.push(path.to_path_buf());
-            }
-        }
+        let file_name = path
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default();
+        consider(path, &file_name);
     } else if path.is_dir() {
         for entry in WalkDir::new(path)
             .into_iter()
@@ -624,55 +1324,531 @@ fn collect_files(path: &Path, allowed_extensions: &[String]) -> Result<Vec<PathB
             .filter(|e| e.file_type().is_file())
         {
             let entry_path = entry.path();
-            if let Some(ext) = entry_path.extension() {
-                let file_ext = ext.to_string_lossy().to_lowercase();
-                if extensions.contains(&file_ext) {
-                    files.push(entry_path.to_path_buf());
-                }
-            }
+            let relative = entry_path
+                .strip_prefix(path)
+                .unwrap_or(entry_path)
+                .to_string_lossy()
+                .replace('\\', "/");
+            consider(entry_path, &relative);
         }
     } else {
         anyhow::bail!("Path does not exist: {}", path.display());
     }
 
-    Ok(files)
+    Ok(collected)
 }
 
-/// Get relative path for S3 key construction
+/// Parse a manifest of newline-separated paths
 ///
-/// # Arguments
+/// Blank lines and lines starting with `#` are ignored; order is preserved.
+fn read_manifest<R: BufRead>(reader: R) -> Result<Vec<PathBuf>> {
+    let mut paths = Vec::new();
+    for line in reader.lines() {
+        let line = line.context("Failed to read manifest")?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        paths.push(PathBuf::from(line));
+    }
+    Ok(paths)
+}
+
+/// Compute the key-relative path of a manifest entry
 ///
-/// * `base` - Base path (file or directory)
-/// * `file` - File to get relative path for
-/// * `flatten` - If true, ignore directory structure
-fn get_relative_path(base: &Path, file: &Path, flatten: bool) -> Result<String> {
-    if flatten {
-        // Just use filename, ignore directory structure
-        Ok(file
-            .file_name()
-            .context("Failed to get filename")?
-            .to_string_lossy()
-            .to_string())
-    } else if base.is_file() {
-        // For single file, just use the filename
-        Ok(file
+/// Without a base directory only the file name is used (like a single-file
+/// upload). With a base, the path must live inside it.
+fn manifest_relative_path(file: &Path, base: Option<&Path>) -> Result<String> {
+    let Some(base) = base else {
+        return Ok(file
             .file_name()
             .context("Failed to get filename")?
             .to_string_lossy()
-            .to_string())
-    } else {
-        // For directories, use relative path from base
-        let rel_path = file
-            .strip_prefix(base)
-            .context("Failed to strip prefix")?
-            .to_string_lossy()
-            .to_string();
-        Ok(rel_path)
+            .to_string());
+    };
+
+    // `absolute` keeps `..`, so `<base>/../x` has to be resolved before the check
+    let absolute_file = normalize_lexically(&std::path::absolute(file)?);
+    let absolute_base = normalize_lexically(&std::path::absolute(base)?);
+    let outside = || {
+        anyhow::anyhow!(
+            "Manifest entry {} is outside the base directory {}",
+            file.display(),
+            base.display()
+        )
+    };
+    let relative = absolute_file
+        .strip_prefix(&absolute_base)
+        .map_err(|_| outside())?;
+    if relative
+        .components()
+        .any(|c| !matches!(c, Component::Normal(_)))
+    {
+        return Err(outside());
     }
+
+    Ok(relative.to_string_lossy().replace('\\', "/"))
 }
 
-/// Format file size for display
-fn format_size(bytes: u64) -> String {
+/// Resolve `.` and `..` without touching the file system
+///
+/// `..` at the root stays at the root, as the OS would resolve it.
+fn normalize_lexically(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if matches!(
+                    normalized.components().next_back(),
+                    Some(Component::Normal(_))
+                ) {
+                    normalized.pop();
+                }
+            }
+            other => normalized.push(other),
+        }
+    }
+    normalized
+}
+
+/// Turn manifest paths into work items, applying the extension and exclude filters
+fn collect_manifest_items(
+    paths: Vec<PathBuf>,
+    base: Option<&Path>,
+    allowed_extensions: &[String],
+    exclude: &[String],
+) -> Result<(Vec<WorkItem>, BTreeMap<String, usize>)> {
+    let extensions: Vec<String> = allowed_extensions
+        .iter()
+        .map(|ext| ext.trim().trim_start_matches('.').to_lowercase())
+        .collect();
+
+    let mut items: Vec<WorkItem> = Vec::with_capacity(paths.len());
+    let mut filtered_out = BTreeMap::new();
+    // Relative path -> index in `items`; two entries with one key would overwrite each other
+    let mut keys: HashMap<String, usize> = HashMap::new();
+
+    for path in paths {
+        let relative_path = manifest_relative_path(&path, base)?;
+        if is_excluded(&relative_path, exclude) {
+            continue;
+        }
+
+        if !matches_extension(&path, &extensions) {
+            let ext = path
+                .extension()
+                .map(|ext| ext.to_string_lossy().to_lowercase())
+                .unwrap_or_default();
+            *filtered_out.entry(ext).or_default() += 1;
+            continue;
+        }
+
+        if let Some(&earlier) = keys.get(&relative_path) {
+            anyhow::bail!(
+                "Manifest entries {} and {} would both upload as {}{}",
+                items[earlier].path.display(),
+                path.display(),
+                relative_path,
+                if base.is_none() {
+                    "; pass --base to keep their directories in the key"
+                } else {
+                    ""
+                }
+            );
+        }
+        keys.insert(relative_path.clone(), items.len());
+        items.push(WorkItem {
+            path,
+            relative_path,
+        });
+    }
+
+    Ok((items, filtered_out))
+}
+
+/// Sort results (source file, target, result) into the order their files were
+/// queued, then by target
+fn sort_results(results: &mut [(PathBuf, usize, ProcessResult)], order: &HashMap<PathBuf, usize>) {
+    results
+        .sort_by_key(|(path, target, _)| (order.get(path).copied().unwrap_or(usize::MAX), *target));
+}
+
+/// Read `--target`/`--targets-file`, or fall back to the `.env` bucket
+fn load_targets(cli: &Cli) -> Result<Vec<UploadTarget>> {
+    if cli.target.is_empty() && cli.targets_file.is_none() {
+        let mut config = Config::from_env()?;
+        config.role_arn = cli.role_arn.clone();
+        config.provider = cli.provider;
+        return Ok(vec![UploadTarget {
+            label: config.bucket.clone(),
+            config,
+        }]);
+    }
+
+    let file = match &cli.targets_file {
+        Some(path) => {
+            let text = std::fs::read_to_string(path)
+                .with_context(|| format!("Failed to read targets file: {}", path.display()))?;
+            Some(parse_targets_file(&text).with_context(|| path.display().to_string())?)
+        }
+        None => None,
+    };
+    let defaults = TargetDefaults {
+        region: std::env::var("AWS_REGION").ok(),
+        profile: std::env::var("AWS_PROFILE").ok(),
+        role_arn: cli.role_arn.clone(),
+        endpoint_url: Config::endpoint_url_from_env(),
+        provider: cli.provider,
+    };
+    resolve_targets(&cli.target, file.as_ref(), &defaults)
+}
+
+/// Print why no files were selected, suggesting --all when the filter dropped some
+fn print_no_files_found(extensions: &[String], filtered_out: &BTreeMap<String, usize>) {
+    status!(
+        "{}",
+        style(format!(
+            "No files found with extensions: {}",
+            extensions.join(", ")
+        ))
+        .yellow()
+    );
+
+    if filtered_out.is_empty() {
+        return;
+    }
+
+    let total: usize = filtered_out.values().sum();
+    status!(
+        "{}",
+        style(format!(
+            "{} file(s) were excluded by the extension filter:",
+            total
+        ))
+        .dim()
+    );
+    for (ext, count) in filtered_out {
+        let label = if ext.is_empty() {
+            "(no extension)".to_string()
+        } else {
+            format!(".{}", ext)
+        };
+        status!("  {} {}", style(label).dim(), count);
+    }
+    status!(
+        "{}",
+        style("Hint: use --all (or -e '*') to upload every file type").cyan()
+    );
+}
+
+/// Upload all collected files as one streamed tar.gz archive
+async fn process_archive(
+    cli: &Cli,
+    config: &Config,
+    s3_client: &S3Client,
+    items: &[WorkItem],
+    archive_name: &str,
+    budget: &PartBudget,
+    qr: Option<&QrOutput>,
+) -> Result<()> {
+    if !archive_name.ends_with(".tar.gz") && !archive_name.ends_with(".tgz") {
+        anyhow::bail!(
+            "Archive name '{}' must end with .tar.gz or .tgz",
+            archive_name
+        );
+    }
+
+    let mut members = Vec::with_capacity(items.len());
+    for item in items {
+        if !item.path.exists() {
+            status!(
+                "{} {} {}",
+                style("⚠").yellow(),
+                style(&item.relative_path).yellow(),
+                style("(not found locally, left out of archive)").dim()
+            );
+            continue;
+        }
+        members
+            .push(ArchiveMember::from_path(item.path.clone(), item.relative_path.clone()).await?);
+    }
+
+    let s3_key = resolve_s3_key(config, cli.prefix.as_deref(), archive_name);
+    let original_size: u64 = members.iter().map(|m| m.size).sum();
+
+    if cli.dry_run {
+        status!(
+            "  {} {} files ({}) → s3://{}/{}",
+            style("WOULD ARCHIVE").green().bold(),
+            members.len(),
+            format_size(original_size),
+            s3_client.bucket(),
+            s3_key
+        );
+        return Ok(());
+    }
+
+    if is_archive_unchanged(s3_client.client(), s3_client.bucket(), &s3_key, &members).await {
+        let url = generate_presigned_url(s3_client.client(), s3_client.bucket(), &s3_key).await?;
+        status!(
+            "{} {} ({})",
+            style("↻").yellow(),
+            style(&s3_key).dim(),
+            style(format!(
+                "skipped - same {} files, {}",
+                members.len(),
+                format_size(original_size)
+            ))
+            .dim()
+        );
+        status!("  {} {}", style("🔗").blue(), style(&url).dim());
+        print_qr(qr, &s3_key, &url);
+        return Ok(());
+    }
+
+    status!(
+        "{}",
+        style(format!(
+            "🗜  Archiving {} files ({}) into {}...",
+            members.len(),
+            format_size(original_size),
+            archive_name
+        ))
+        .cyan()
+    );
+
+    // Compressed size is unknown up front, so show a byte counter instead of a bar
+    let pb = ui::new_spinner();
+    pb.set_style(
+        ProgressStyle::default_spinner()
+            .template("{spinner:.green} {bytes} uploaded ({bytes_per_sec}) {msg}")
+            .unwrap(),
+    );
+    pb.set_message(archive_name.to_string());
+    pb.enable_steady_tick(std::time::Duration::from_millis(100));
+
+    let start = std::time::Instant::now();
+    let result = s3_client
+        .with_credential_refresh(
+            || {
+                upload_archive(
+                    s3_client.client(),
+                    s3_client.bucket(),
+                    &s3_key,
+                    &members,
+                    budget,
+                    Some(&pb),
+                )
+            },
+            || pb.suspend(|| print_credentials_refreshed(archive_name)),
+        )
+        .await;
+    pb.finish_and_clear();
+
+    let compressed = result?;
+    let url = generate_presigned_url(s3_client.client(), s3_client.bucket(), &s3_key).await?;
+
+    status!(
+        "{} {} ({} files, {} → {})",
+        style("✓").green(),
+        style(&s3_key).green(),
+        members.len(),
+        style(format_size(original_size)).dim(),
+        style(format_size(compressed)).dim()
+    );
+    status!("  {} {}", style("🔗").blue(), style(&url).dim());
+    print_qr(qr, &s3_key, &url);
+    status!(
+        "{}",
+        style(format!("Time: {:.2}s", start.elapsed().as_secs_f64())).dim()
+    );
+
+    Ok(())
+}
+
+/// Resolve `--qr`/`--qr-png`, creating the PNG directory up front
+fn qr_output(cli: &Cli) -> Result<Option<QrOutput>> {
+    if !cli.qr {
+        return Ok(None);
+    }
+    match &cli.qr_png {
+        Some(dir) => {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("Failed to create QR directory: {}", dir.display()))?;
+            Ok(Some(QrOutput::Png(dir.clone())))
+        }
+        None => Ok(Some(QrOutput::Terminal)),
+    }
+}
+
+/// Show or save the QR code of a pre-signed URL; failures are reported, not fatal
+fn print_qr(qr: Option<&QrOutput>, s3_key: &str, url: &str) {
+    let result = match qr {
+        None => return,
+        // Block characters are noise in CI logs and pipes
+        Some(QrOutput::Terminal) if ui::is_plain() => return,
+        Some(QrOutput::Terminal) => s3::qr::render_terminal(url).map(|code| {
+            for line in code.lines() {
+                println!("  {}", line);
+            }
+        }),
+        Some(QrOutput::Png(dir)) => s3::qr::save_png(url, dir, s3_key).map(|path| {
+            status!("  {} {}", style("📱").blue(), style(path.display()).dim());
+        }),
+    };
+    if let Err(e) = result {
+        status!(
+            "  {} {}",
+            style("⚠").yellow(),
+            style(format!("{:#}", e)).yellow()
+        );
+    }
+}
+
+/// Tell the user a file is being retried with reloaded credentials
+fn print_credentials_refreshed(filename: &str) {
+    status!(
+        "{}",
+        style(format!(
+            "🔑 AWS credentials expired; reloaded them and retrying {}",
+            filename
+        ))
+        .yellow()
+    );
+}
+
+/// Build the S3 key for a relative path, honoring a `--prefix` override
+fn resolve_s3_key(config: &Config, prefix: Option<&str>, relative_path: &str) -> String {
+    match prefix {
+        Some(prefix) => format!(
+            "{}{}",
+            normalize_prefix(prefix),
+            relative_path.trim_start_matches("./")
+        ),
+        None => config.build_s3_key(relative_path),
+    }
+}
+
+/// Print a diff report as three sections with counts and sizes
+fn print_diff_report(report: &DiffReport) {
+    status!(
+        "{}",
+        style(format!(
+            "🔍 Diff: local vs s3://{}/{}",
+            report.bucket, report.prefix
+        ))
+        .cyan()
+        .bold()
+    );
+
+    status!();
+    status!(
+        "{} ({} files, {})",
+        style("Local only").green().bold(),
+        report.local_only.count,
+        format_size(report.local_only.total_bytes)
+    );
+    for entry in &report.local_only.entries {
+        status!(
+            "  {} {} ({})",
+            style("+").green(),
+            entry.key,
+            format_size(entry.size)
+        );
+    }
+
+    status!();
+    status!(
+        "{} ({} files, {})",
+        style("Remote only").red().bold(),
+        report.remote_only.count,
+        format_size(report.remote_only.total_bytes)
+    );
+    for entry in &report.remote_only.entries {
+        status!(
+            "  {} {} ({})",
+            style("-").red(),
+            entry.key,
+            format_size(entry.size)
+        );
+    }
+
+    status!();
+    status!(
+        "{} ({} files, {})",
+        style("Changed").yellow().bold(),
+        report.changed.count,
+        format_size(report.changed.total_bytes)
+    );
+    for entry in &report.changed.entries {
+        status!(
+            "  {} {} (local {}, remote {})",
+            style("~").yellow(),
+            entry.key,
+            format_size(entry.local_size),
+            format_size(entry.remote_size)
+        );
+    }
+
+    status!("\n{}", style("═".repeat(70)).dim());
+    if report.is_clean() {
+        status!(
+            "{}",
+            style(format!("In sync: {} identical file(s)", report.identical))
+                .green()
+                .bold()
+        );
+    } else {
+        status!(
+            "{}",
+            style(format!(
+                "Summary: {} local only, {} remote only, {} changed, {} identical",
+                report.local_only.count,
+                report.remote_only.count,
+                report.changed.count,
+                report.identical
+            ))
+            .bold()
+        );
+    }
+}
+
+/// Get relative path for S3 key construction
+///
+/// # Arguments
+///
+/// * `base` - Base path (file or directory)
+/// * `file` - File to get relative path for
+/// * `flatten` - If true, ignore directory structure
+fn get_relative_path(base: &Path, file: &Path, flatten: bool) -> Result<String> {
+    if flatten {
+        // Just use filename, ignore directory structure
+        Ok(file
+            .file_name()
+            .context("Failed to get filename")?
+            .to_string_lossy()
+            .to_string())
+    } else if base.is_file() {
+        // For single file, just use the filename
+        Ok(file
+            .file_name()
+            .context("Failed to get filename")?
+            .to_string_lossy()
+            .to_string())
+    } else {
+        // For directories, use relative path from base
+        let rel_path = file
+            .strip_prefix(base)
+            .context("Failed to strip prefix")?
+            .to_string_lossy()
+            .to_string();
+        Ok(rel_path)
+    }
+}
+
+/// Format file size for display
+fn format_size(bytes: u64) -> String {
     const KB: u64 = 1024;
     const MB: u64 = KB * 1024;
     const GB: u64 = MB * 1024;
@@ -689,25 +1865,43 @@ fn format_size(bytes: u64) -> String {
 }
 
 /// Process a file in upload mode and return result (for clean output)
+#[allow(clippy::too_many_arguments)]
 async fn process_upload_with_result(
     s3_client: &S3Client,
     config: &Config,
     file_path: &Path,
-    base_path: &Path,
+    relative_path: &str,
+    budget: &PartBudget,
+    tags: Option<&ObjectTags>,
     pb: &ProgressBar,
     stats: &Arc<Stats>,
 ) -> Result<ProcessResult> {
-    let relative_path = get_relative_path(base_path, file_path, false)?;
+    let relative_path = relative_path.to_string();
     let s3_key = config.build_s3_key(&relative_path);
 
+    // Manifest entries may point at files that don't exist
+    if !file_path.exists() {
+        stats.not_found.fetch_add(1, Ordering::Relaxed);
+        return Ok(ProcessResult::NotFound {
+            filename: relative_path,
+        });
+    }
+
     // Get file size for display
     let metadata = tokio::fs::metadata(file_path).await?;
     let file_size = metadata.len();
     let size_str = format_size(file_size);
 
     // Compare with remote
-    let comparison =
-        compare_file(s3_client.client(), s3_client.bucket(), &s3_key, file_path).await?;
+    let quirks = s3_client.provider().quirks();
+    let comparison = compare_file_with_quirks(
+        s3_client.client(),
+        s3_client.bucket(),
+        &s3_key,
+        file_path,
+        &quirks,
+    )
+    .await?;
 
     match comparison {
         s3::FileComparison::Identical => {
@@ -725,33 +1919,57 @@ async fn process_upload_with_result(
         }
         s3::FileComparison::NotFound | s3::FileComparison::Different => {
             // Choose upload strategy based on file size
-            let upload_result = if file_size >= MULTIPART_THRESHOLD {
+            if file_size >= MULTIPART_THRESHOLD {
                 info!(
                     "Using multipart upload for large file: {} ({} bytes)",
                     relative_path, file_size
                 );
-                upload_multipart(
-                    s3_client.client(),
-                    s3_client.bucket(),
-                    &s3_key,
-                    file_path,
-                    Some(pb),
-                )
-                .await
-                .map(|_| UploadResult::Uploaded)
-            } else {
-                upload_file(
-                    s3_client.client(),
-                    s3_client.bucket(),
-                    &s3_key,
-                    file_path,
-                    Some(pb),
+            }
+            let extra_metadata = sha256_metadata(file_path, &quirks).await?;
+            let upload_result = s3_client
+                .with_credential_refresh(
+                    || async {
+                        if file_size >= MULTIPART_THRESHOLD {
+                            upload_multipart_with_metadata(
+                                s3_client.client(),
+                                s3_client.bucket(),
+                                &s3_key,
+                                file_path,
+                                &extra_metadata,
+                                budget,
+                                Some(pb),
+                            )
+                            .await
+                            .map(|_| UploadResult::Uploaded)
+                        } else {
+                            upload_file_with_metadata(
+                                s3_client.client(),
+                                s3_client.bucket(),
+                                &s3_key,
+                                file_path,
+                                &extra_metadata,
+                                Some(pb),
+                            )
+                            .await
+                        }
+                    },
+                    || pb.suspend(|| print_credentials_refreshed(&relative_path)),
                 )
-                .await
-            };
+                .await;
 
             match upload_result {
                 Ok(UploadResult::Uploaded) => {
+                    if let Some(tags) = tags
+                        && let Err(e) = apply_tags(s3_client, &s3_key, tags, pb).await
+                    {
+                        error!("Tagging failed for {}: {:#}", relative_path, e);
+                        stats.failed.fetch_add(1, Ordering::Relaxed);
+                        return Ok(ProcessResult::Failed {
+                            filename: relative_path,
+                            error: format!("uploaded, but tagging failed: {:#}", e),
+                        });
+                    }
+
                     // Generate pre-signed URL
                     let url =
                         generate_presigned_url(s3_client.client(), s3_client.bucket(), &s3_key)
@@ -792,15 +2010,276 @@ async fn process_upload_with_result(
     }
 }
 
+/// Tag an uploaded object with `--tags`
+///
+/// Where tagging depends on the deployment, a "not implemented" answer is
+/// reported once and tagging is skipped for the rest of the run.
+async fn apply_tags(
+    s3_client: &S3Client,
+    s3_key: &str,
+    tags: &ObjectTags,
+    pb: &ProgressBar,
+) -> Result<()> {
+    if tags.unsupported.load(Ordering::Relaxed) {
+        return Ok(());
+    }
+    match put_object_tags(s3_client.client(), s3_client.bucket(), s3_key, &tags.tags).await {
+        Err(e) if tags.support == Support::Probe && is_not_implemented(&e) => {
+            if !tags.unsupported.swap(true, Ordering::Relaxed) {
+                pb.suspend(|| {
+                    status!(
+                        "{}",
+                        style(format!(
+                            "⚠️  {} at {} doesn't implement object tagging; uploading the rest without --tags",
+                            s3_client.provider(),
+                            s3_client.bucket()
+                        ))
+                        .yellow()
+                    )
+                });
+            }
+            Ok(())
+        }
+        result => result,
+    }
+}
+
+/// Where the provider of a target came from, for the startup line
+fn provider_source(config: &Config) -> String {
+    match (&config.provider, &config.endpoint_url) {
+        (Some(_), _) => "configured".to_string(),
+        (None, Some(endpoint_url)) => format!("detected from {}", endpoint_url),
+        (None, None) => "no S3_ENDPOINT_URL".to_string(),
+    }
+}
+
+/// Server-side copies planned by `--dedupe`
+#[derive(Debug, Default)]
+struct Dedupe {
+    /// Relative path of each duplicate -> relative path of its first occurrence
+    copies: HashMap<String, String>,
+    /// Whether each first occurrence reached S3, published when its worker is done
+    sources: HashMap<String, watch::Sender<Option<bool>>>,
+}
+
+impl Dedupe {
+    /// Build from queued relative paths and `dedupe::find_duplicates` output
+    fn new(relative_paths: &[&str], duplicates: &[Option<usize>]) -> Self {
+        let mut plan = Self::default();
+        for (path, source) in relative_paths.iter().zip(duplicates) {
+            if let Some(source) = source {
+                let source = relative_paths[*source];
+                plan.copies.insert(path.to_string(), source.to_string());
+                plan.sources
+                    .entry(source.to_string())
+                    .or_insert_with(|| watch::channel(None).0);
+            }
+        }
+        plan
+    }
+
+    fn source_of(&self, relative_path: &str) -> Option<&str> {
+        self.copies.get(relative_path).map(String::as_str)
+    }
+
+    /// Record whether a file is on S3 now, releasing copies waiting for it
+    fn finish(&self, relative_path: &str, result: &Result<ProcessResult>) {
+        if let Some(tx) = self.sources.get(relative_path) {
+            let on_s3 = matches!(
+                result,
+                Ok(ProcessResult::Uploaded { .. }
+                    | ProcessResult::Skipped { .. }
+                    | ProcessResult::Copied { .. })
+            );
+            tx.send_replace(Some(on_s3));
+        }
+    }
+
+    /// Wait until the source's worker is done; whether the source is on S3
+    ///
+    /// Sources are queued before their copies, so a worker is always already
+    /// processing the source when a copy waits for it.
+    async fn wait_for(&self, source: &str) -> bool {
+        let Some(tx) = self.sources.get(source) else {
+            return false;
+        };
+        let mut rx = tx.subscribe();
+        rx.wait_for(Option::is_some)
+            .await
+            .map(|on_s3| on_s3.unwrap_or(false))
+            .unwrap_or(false)
+    }
+}
+
+/// Hash the files that share a size with another file and plan copies for
+/// the duplicates
+async fn plan_dedupe(items: &[WorkItem], concurrency: usize) -> Result<Dedupe> {
+    use futures::stream::{self, StreamExt, TryStreamExt};
+
+    // Missing files are reported by the upload itself
+    let sizes: Vec<Option<u64>> = items
+        .iter()
+        .map(|item| std::fs::metadata(&item.path).ok().map(|m| m.len()))
+        .collect();
+    let mut size_counts: HashMap<u64, usize> = HashMap::new();
+    for size in sizes.iter().flatten() {
+        *size_counts.entry(*size).or_default() += 1;
+    }
+    let candidates: Vec<usize> = (0..items.len())
+        .filter(|&i| sizes[i].is_some_and(|size| size > 0 && size_counts[&size] > 1))
+        .collect();
+
+    if !candidates.is_empty() {
+        status!(
+            "{}",
+            style(format!(
+                "🔍 Hashing {} files with matching sizes for --dedupe...",
+                candidates.len()
+            ))
+            .cyan()
+        );
+    }
+    let hashes: Vec<(usize, String)> =
+        stream::iter(candidates)
+            .map(|i| async move {
+                Ok::<_, anyhow::Error>((i, dedupe::hash_file(&items[i].path).await?))
+            })
+            .buffered(concurrency.max(1))
+            .try_collect()
+            .await?;
+
+    let files: Vec<(u64, &str)> = hashes
+        .iter()
+        .map(|(i, hash)| (sizes[*i].unwrap_or(0), hash.as_str()))
+        .collect();
+    let paths: Vec<&str> = hashes
+        .iter()
+        .map(|(i, _)| items[*i].relative_path.as_str())
+        .collect();
+    let plan = Dedupe::new(&paths, &dedupe::find_duplicates(&files));
+
+    if !plan.copies.is_empty() {
+        status!(
+            "{}",
+            style(format!(
+                "⧉ {} duplicate files will be copied server-side instead of uploaded",
+                plan.copies.len()
+            ))
+            .cyan()
+        );
+    }
+    Ok(plan)
+}
+
+/// Process a duplicate in `--dedupe` mode: copy it from `source` on S3
+///
+/// Falls back to a normal upload when the source didn't make it to S3.
+#[allow(clippy::too_many_arguments)]
+async fn process_copy_with_result(
+    s3_client: &S3Client,
+    config: &Config,
+    item: &WorkItem,
+    source: &str,
+    dedupe: &Dedupe,
+    budget: &PartBudget,
+    tags: Option<&ObjectTags>,
+    pb: &ProgressBar,
+    stats: &Arc<Stats>,
+) -> Result<ProcessResult> {
+    pb.set_message(format!("Waiting for {}", source));
+    if !dedupe.wait_for(source).await {
+        return process_upload_with_result(
+            s3_client,
+            config,
+            &item.path,
+            &item.relative_path,
+            budget,
+            tags,
+            pb,
+            stats,
+        )
+        .await;
+    }
+
+    let relative_path = item.relative_path.clone();
+    let s3_key = config.build_s3_key(&relative_path);
+    let source_key = config.build_s3_key(source);
+    let metadata = tokio::fs::metadata(&item.path).await?;
+    let file_size = metadata.len();
+    let size_str = format_size(file_size);
+
+    let quirks = s3_client.provider().quirks();
+    let comparison = compare_file_with_quirks(
+        s3_client.client(),
+        s3_client.bucket(),
+        &s3_key,
+        &item.path,
+        &quirks,
+    )
+    .await?;
+    if comparison == s3::FileComparison::Identical {
+        let url = generate_presigned_url(s3_client.client(), s3_client.bucket(), &s3_key).await?;
+        stats.skipped.fetch_add(1, Ordering::Relaxed);
+        return Ok(ProcessResult::Skipped {
+            filename: relative_path,
+            size: size_str,
+            url,
+        });
+    }
+
+    let mut copy_metadata = s3::mtime::mtime_metadata(&metadata);
+    copy_metadata.extend(sha256_metadata(&item.path, &quirks).await?);
+
+    pb.set_message(format!("Copying {} from {}", relative_path, source_key));
+    let copied = s3_client
+        .with_credential_refresh(
+            || {
+                dedupe::copy_object(
+                    s3_client.client(),
+                    s3_client.bucket(),
+                    &source_key,
+                    &s3_key,
+                    copy_metadata.clone(),
+                )
+            },
+            || pb.suspend(|| print_credentials_refreshed(&relative_path)),
+        )
+        .await;
+
+    match copied {
+        Ok(()) => {
+            let url =
+                generate_presigned_url(s3_client.client(), s3_client.bucket(), &s3_key).await?;
+            stats.copied.fetch_add(1, Ordering::Relaxed);
+            stats.bytes_saved.fetch_add(file_size, Ordering::Relaxed);
+
+            Ok(ProcessResult::Copied {
+                filename: relative_path,
+                size: size_str,
+                url,
+                source_key,
+            })
+        }
+        Err(e) => {
+            error!("Copy failed for {}: {:#}", relative_path, e);
+            stats.failed.fetch_add(1, Ordering::Relaxed);
+
+            Ok(ProcessResult::Failed {
+                filename: relative_path,
+                error: format!("{:#}", e),
+            })
+        }
+    }
+}
+
 /// Process a file in URL-only mode and return result (for clean output)
 async fn process_url_only_with_result(
     s3_client: &S3Client,
     config: &Config,
-    file_path: &Path,
-    base_path: &Path,
+    relative_path: &str,
     stats: &Arc<Stats>,
 ) -> Result<ProcessResult> {
-    let relative_path = get_relative_path(base_path, file_path, false)?;
+    let relative_path = relative_path.to_string();
     let s3_key = config.build_s3_key(&relative_path);
 
     // Check if file exists on S3
@@ -834,3 +2313,508 @@ async fn process_url_only_with_result(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn exts(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    fn setup_tree() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("sub")).unwrap();
+        for name in [
+            "a.mp4",
+            "b.MOV",
+            "notes.txt",
+            "sub/c.mp4",
+            "sub/README",
+            "Makefile",
+        ] {
+            fs::write(dir.path().join(name), b"x").unwrap();
+        }
+        dir
+    }
+
+    fn names(collected: &CollectedFiles, base: &Path) -> Vec<String> {
+        let mut names: Vec<String> = collected
+            .files
+            .iter()
+            .map(|f| {
+                f.strip_prefix(base)
+                    .unwrap()
+                    .to_string_lossy()
+                    .replace('\\', "/")
+            })
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn test_collect_files_default_extensions() {
+        let dir = setup_tree();
+        let collected = collect_files(dir.path(), &exts(&["mp4", "mov"]), &[]).unwrap();
+
+        assert_eq!(
+            names(&collected, dir.path()),
+            vec!["a.mp4", "b.MOV", "sub/c.mp4"]
+        );
+        assert_eq!(collected.filtered_out.get("txt"), Some(&1));
+        assert_eq!(collected.filtered_out.get(""), Some(&2));
+    }
+
+    #[test]
+    fn test_collect_files_wildcard() {
+        let dir = setup_tree();
+        let collected = collect_files(dir.path(), &exts(&["*"]), &[]).unwrap();
+
+        assert_eq!(collected.files.len(), 6);
+        assert!(collected.filtered_out.is_empty());
+    }
+
+    #[test]
+    fn test_collect_files_extension_less() {
+        let dir = setup_tree();
+
+        let collected = collect_files(dir.path(), &exts(&[""]), &[]).unwrap();
+        assert_eq!(
+            names(&collected, dir.path()),
+            vec!["Makefile", "sub/README"]
+        );
+
+        let collected = collect_files(dir.path(), &exts(&["txt", ""]), &[]).unwrap();
+        assert_eq!(
+            names(&collected, dir.path()),
+            vec!["Makefile", "notes.txt", "sub/README"]
+        );
+    }
+
+    #[test]
+    fn test_collect_files_exclude() {
+        let dir = setup_tree();
+        let collected =
+            collect_files(dir.path(), &exts(&["*"]), &exts(&["sub/*", "*.txt"])).unwrap();
+
+        assert_eq!(
+            names(&collected, dir.path()),
+            vec!["Makefile", "a.mp4", "b.MOV"]
+        );
+    }
+
+    #[test]
+    fn test_read_manifest() {
+        let manifest = "# render farm output\n\nout/a.mp4\n  out/b.mov  \n# done\nout/c.mp4\n";
+        let paths = read_manifest(manifest.as_bytes()).unwrap();
+
+        assert_eq!(
+            paths,
+            vec![
+                PathBuf::from("out/a.mp4"),
+                PathBuf::from("out/b.mov"),
+                PathBuf::from("out/c.mp4")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_manifest_relative_path() {
+        let base = Path::new("/renders");
+
+        assert_eq!(
+            manifest_relative_path(Path::new("/renders/shot1/a.mp4"), Some(base)).unwrap(),
+            "shot1/a.mp4"
+        );
+        assert_eq!(
+            manifest_relative_path(Path::new("/renders/shot1/a.mp4"), None).unwrap(),
+            "a.mp4"
+        );
+
+        let err = manifest_relative_path(Path::new("/elsewhere/a.mp4"), Some(base)).unwrap_err();
+        assert!(err.to_string().contains("outside the base directory"));
+
+        // `..` is resolved before the check, so it can't climb out of the base
+        for escape in [
+            "/renders/../etc/passwd",
+            "/renders/shot1/../../etc/passwd",
+            "/renders/..",
+        ] {
+            let err = manifest_relative_path(Path::new(escape), Some(base)).unwrap_err();
+            assert!(
+                err.to_string().contains("outside the base directory"),
+                "{}",
+                escape
+            );
+        }
+        assert_eq!(
+            manifest_relative_path(Path::new("/renders/shot1/../shot2/./b.mp4"), Some(base))
+                .unwrap(),
+            "shot2/b.mp4"
+        );
+        assert_eq!(
+            manifest_relative_path(
+                Path::new("/renders/a.mp4"),
+                Some(Path::new("/x/../renders/"))
+            )
+            .unwrap(),
+            "a.mp4"
+        );
+    }
+
+    #[test]
+    fn test_collect_manifest_items_rejects_duplicate_keys() {
+        let paths = vec![
+            PathBuf::from("/r/day1/a.mp4"),
+            PathBuf::from("/r/day2/a.mp4"),
+        ];
+
+        let err = collect_manifest_items(paths.clone(), None, &exts(&["mp4"]), &[]).unwrap_err();
+        let message = err.to_string();
+        assert!(message.contains("/r/day1/a.mp4 and /r/day2/a.mp4 would both upload as a.mp4"));
+        assert!(message.contains("--base"));
+
+        // With a base the directories keep the keys apart
+        let (items, _) =
+            collect_manifest_items(paths, Some(Path::new("/r")), &exts(&["mp4"]), &[]).unwrap();
+        let names: Vec<_> = items.iter().map(|i| i.relative_path.as_str()).collect();
+        assert_eq!(names, vec!["day1/a.mp4", "day2/a.mp4"]);
+
+        // The same file listed twice is a duplicate too
+        let paths = vec![PathBuf::from("/r/a.mp4"), PathBuf::from("/r/./a.mp4")];
+        assert!(
+            collect_manifest_items(paths, Some(Path::new("/r")), &exts(&["mp4"]), &[]).is_err()
+        );
+    }
+
+    #[test]
+    fn test_collect_manifest_items_filters_and_keeps_order() {
+        let paths = vec![
+            PathBuf::from("/r/z.mp4"),
+            PathBuf::from("/r/notes.txt"),
+            PathBuf::from("/r/tmp/a.mp4"),
+            PathBuf::from("/r/missing.mov"),
+        ];
+        let (items, filtered_out) = collect_manifest_items(
+            paths,
+            Some(Path::new("/r")),
+            &exts(&["mp4", "mov"]),
+            &exts(&["tmp/*"]),
+        )
+        .unwrap();
+
+        let names: Vec<_> = items.iter().map(|i| i.relative_path.as_str()).collect();
+        assert_eq!(names, vec!["z.mp4", "missing.mov"]);
+        assert_eq!(filtered_out.get("txt"), Some(&1));
+    }
+
+    #[test]
+    fn test_sort_results_by_queue_order() {
+        let order: HashMap<PathBuf, usize> = [("/r/z.mp4", 0), ("/r/a.mp4", 1), ("/s/a.mp4", 2)]
+            .iter()
+            .map(|(k, v)| (PathBuf::from(k), *v))
+            .collect();
+        let result = |path: &str, target: usize| {
+            (
+                PathBuf::from(path),
+                target,
+                ProcessResult::Failed {
+                    filename: Path::new(path)
+                        .strip_prefix("/r")
+                        .unwrap_or(Path::new(path))
+                        .display()
+                        .to_string(),
+                    error: "boom".to_string(),
+                },
+            )
+        };
+        let mut results = vec![
+            result("/s/a.mp4", 0),
+            result("/r/a.mp4", 0),
+            result("/r/z.mp4", 1),
+            result("/r/z.mp4", 0),
+        ];
+
+        sort_results(&mut results, &order);
+        let sorted: Vec<_> = results
+            .iter()
+            .map(|(path, t, _)| (path.to_str().unwrap(), *t))
+            .collect();
+        assert_eq!(
+            sorted,
+            vec![
+                ("/r/z.mp4", 0),
+                ("/r/z.mp4", 1),
+                ("/r/a.mp4", 0),
+                ("/s/a.mp4", 0)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_target_summary_lines() {
+        let primary = Stats::default();
+        primary.uploaded.store(3, Ordering::Relaxed);
+        primary.skipped.store(1, Ordering::Relaxed);
+        primary
+            .total_bytes_uploaded
+            .store(3 * 1024 * 1024, Ordering::Relaxed);
+        let dr = Stats::default();
+        dr.uploaded.store(2, Ordering::Relaxed);
+        dr.skipped.store(1, Ordering::Relaxed);
+        dr.failed.store(1, Ordering::Relaxed);
+        dr.total_bytes_uploaded.store(2048, Ordering::Relaxed);
+
+        let rows = [("primary", &primary), ("media-eu/videos", &dr)];
+        assert_eq!(
+            target_summary_lines(&rows, false),
+            vec![
+                "Target           Uploaded  Skipped  Failed  Missing        Size",
+                "primary                 3        1       0        0     3.00 MB",
+                "media-eu/videos         2        1       1        0     2.00 KB",
+            ]
+        );
+
+        dr.urls_generated.store(4, Ordering::Relaxed);
+        dr.not_found.store(1, Ordering::Relaxed);
+        assert_eq!(
+            target_summary_lines(&rows[1..], true),
+            vec![
+                "Target            URLs  Not found",
+                "media-eu/videos      4          1",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_target_flags() {
+        let parse =
+            |args: &[&str]| Cli::try_parse_from([&["s3upload", "video.mp4"], args].concat());
+
+        let cli = parse(&["--target", "s3://media-us/videos", "--target", "dr"]).unwrap();
+        assert_eq!(cli.target, vec!["s3://media-us/videos", "dr"]);
+        assert!(parse(&["--targets-file", "targets.toml"]).is_ok());
+
+        for other in [
+            &["--diff"][..],
+            &["--archive", "all.tar.gz"],
+            &["--dedupe"],
+            &["--prefix", "x"],
+        ] {
+            assert!(parse(&[&["--target", "s3://media-us"][..], other].concat()).is_err());
+            assert!(parse(&[&["--targets-file", "t.toml"][..], other].concat()).is_err());
+        }
+    }
+
+    #[test]
+    fn test_collect_files_single_file() {
+        let dir = setup_tree();
+        let file = dir.path().join("notes.txt");
+
+        assert!(collect_files(&file, &exts(&["mp4"]), &[])
+            .unwrap()
+            .files
+            .is_empty());
+        assert_eq!(
+            collect_files(&file, &exts(&["*"]), &[]).unwrap().files,
+            vec![file]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_plan_dedupe() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut items = Vec::new();
+        for (name, content) in [
+            ("a/intro.mp4", "intro clip"),
+            ("b/intro.mp4", "intro clip"),
+            ("b/outro.mp4", "outro clip"),
+            ("c/intro.mp4", "intro clip"),
+            ("c/empty.mp4", ""),
+            ("d/empty.mp4", ""),
+        ] {
+            let path = dir.path().join(name);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(&path, content).unwrap();
+            items.push(WorkItem {
+                path,
+                relative_path: name.to_string(),
+            });
+        }
+        items.push(WorkItem {
+            path: dir.path().join("missing.mp4"),
+            relative_path: "missing.mp4".to_string(),
+        });
+
+        let plan = plan_dedupe(&items, 2).await.unwrap();
+        assert_eq!(plan.source_of("b/intro.mp4"), Some("a/intro.mp4"));
+        assert_eq!(plan.source_of("c/intro.mp4"), Some("a/intro.mp4"));
+        assert_eq!(plan.copies.len(), 2);
+        assert_eq!(plan.sources.len(), 1);
+        assert_eq!(plan.source_of("a/intro.mp4"), None);
+        // Same size, different content
+        assert_eq!(plan.source_of("b/outro.mp4"), None);
+        assert_eq!(plan.source_of("d/empty.mp4"), None);
+    }
+
+    #[tokio::test]
+    async fn test_dedupe_copies_wait_for_source() {
+        let plan = Arc::new(Dedupe::new(
+            &["a.mp4", "b.mp4", "c.mp4"],
+            &[None, Some(0), Some(0)],
+        ));
+
+        let waiting = {
+            let plan = Arc::clone(&plan);
+            tokio::spawn(async move { plan.wait_for("a.mp4").await })
+        };
+        tokio::task::yield_now().await;
+        assert!(!waiting.is_finished());
+
+        let uploaded = ProcessResult::Uploaded {
+            filename: "a.mp4".to_string(),
+            size: "10 B".to_string(),
+            url: "https://example.com/a.mp4".to_string(),
+        };
+        plan.finish("a.mp4", &Ok(uploaded));
+        assert!(waiting.await.unwrap());
+        // Copies picked up after the source finished don't wait
+        assert!(plan.wait_for("a.mp4").await);
+
+        // A failed source sends its duplicates back to a normal upload
+        let failed = Dedupe::new(&["a.mp4", "b.mp4"], &[None, Some(0)]);
+        failed.finish("a.mp4", &Err(anyhow::anyhow!("boom")));
+        assert!(!failed.wait_for("a.mp4").await);
+    }
+
+    fn sized(name: &str, size: u64, modified_secs: u64) -> (WorkItem, FileInfo) {
+        (
+            WorkItem {
+                path: PathBuf::from(name),
+                relative_path: name.to_string(),
+            },
+            FileInfo {
+                size,
+                modified: Some(
+                    std::time::UNIX_EPOCH + std::time::Duration::from_secs(modified_secs),
+                ),
+            },
+        )
+    }
+
+    fn queued_names(files: &[(WorkItem, FileInfo)]) -> Vec<&str> {
+        files
+            .iter()
+            .map(|(i, _)| i.relative_path.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn test_order_files() {
+        let files = vec![
+            sized("c.mp4", 300, 1),
+            sized("a.mp4", 200, 3),
+            sized("b.mp4", 100, 2),
+            sized("d.mp4", 200, 2),
+        ];
+        let ordered = |order| {
+            let mut files = files.clone();
+            order_files(&mut files, order);
+            queued_names(&files).join(",")
+        };
+
+        assert_eq!(ordered(FileOrder::Name), "a.mp4,b.mp4,c.mp4,d.mp4");
+        assert_eq!(ordered(FileOrder::SizeAsc), "b.mp4,a.mp4,d.mp4,c.mp4");
+        assert_eq!(ordered(FileOrder::SizeDesc), "c.mp4,a.mp4,d.mp4,b.mp4");
+        assert_eq!(ordered(FileOrder::Mtime), "c.mp4,b.mp4,d.mp4,a.mp4");
+    }
+
+    #[test]
+    fn test_split_byte_budget() {
+        let files = vec![
+            sized("a.mp4", 100, 0),
+            sized("b.mp4", 200, 0),
+            sized("c.mp4", 50, 0),
+        ];
+
+        let (queued, deferred) = split_byte_budget(files.clone(), 300);
+        assert_eq!(queued_names(&queued), vec!["a.mp4", "b.mp4"]);
+        assert_eq!(queued_names(&deferred), vec!["c.mp4"]);
+
+        // Queueing stops at the first file over budget, even if a later one fits
+        let (queued, deferred) = split_byte_budget(files.clone(), 250);
+        assert_eq!(queued_names(&queued), vec!["a.mp4"]);
+        assert_eq!(queued_names(&deferred), vec!["b.mp4", "c.mp4"]);
+
+        let (queued, deferred) = split_byte_budget(files.clone(), 50);
+        assert!(queued.is_empty());
+        assert_eq!(deferred.len(), 3);
+
+        let (queued, deferred) = split_byte_budget(files, u64::MAX);
+        assert_eq!(queued.len(), 3);
+        assert!(deferred.is_empty());
+    }
+
+    #[test]
+    fn test_schedule_items() {
+        let dir = setup_tree();
+        let item = |name: &str| WorkItem {
+            path: dir.path().join(name),
+            relative_path: name.to_string(),
+        };
+        std::fs::write(dir.path().join("big.mp4"), vec![0u8; 1000]).unwrap();
+        std::fs::write(dir.path().join("small.mp4"), vec![0u8; 10]).unwrap();
+        let items = vec![item("big.mp4"), item("small.mp4")];
+
+        // No flags: collection order is kept
+        let (queued, deferred) = schedule_items(items.clone(), None, None);
+        assert_eq!(queued[0].relative_path, "big.mp4");
+        assert!(deferred.is_empty());
+
+        let (queued, deferred) = schedule_items(items, Some(FileOrder::SizeAsc), Some(500));
+        assert_eq!(queued.len(), 1);
+        assert_eq!(queued[0].relative_path, "small.mp4");
+        assert_eq!(queued_names(&deferred), vec!["big.mp4"]);
+        assert_eq!(deferred[0].1.size, 1000);
+    }
+
+    #[test]
+    fn test_parse_byte_size() {
+        assert_eq!(parse_byte_size("1048576"), Ok(1024 * 1024));
+        assert_eq!(parse_byte_size("500M"), Ok(500 * 1024 * 1024));
+        assert_eq!(parse_byte_size("500mb"), Ok(500 * 1024 * 1024));
+        assert_eq!(parse_byte_size("2GiB"), Ok(2 * 1024 * 1024 * 1024));
+        assert_eq!(parse_byte_size("1.5K"), Ok(1536));
+        assert_eq!(parse_byte_size("3T"), Ok(3 * 1024u64.pow(4)));
+        assert!(parse_byte_size("").is_err());
+        assert!(parse_byte_size("lots").is_err());
+        assert!(parse_byte_size("5X").is_err());
+        assert!(parse_byte_size("-1G").is_err());
+    }
+
+    #[test]
+    fn test_qr_flags() {
+        let parse =
+            |args: &[&str]| Cli::try_parse_from([&["s3upload", "video.mp4"], args].concat());
+
+        assert_eq!(qr_output(&parse(&[]).unwrap()).unwrap(), None);
+        assert_eq!(
+            qr_output(&parse(&["--qr"]).unwrap()).unwrap(),
+            Some(QrOutput::Terminal)
+        );
+
+        let dir = tempfile::tempdir().unwrap();
+        let png_dir = dir.path().join("qr");
+        let png = png_dir.to_str().unwrap();
+        assert_eq!(
+            qr_output(&parse(&["--qr", "--qr-png", png]).unwrap()).unwrap(),
+            Some(QrOutput::Png(png_dir.clone()))
+        );
+        assert!(png_dir.is_dir());
+
+        assert!(parse(&["--qr-png", png]).is_err());
+        assert!(parse(&["--qr", "--diff"]).is_err());
+    }
+}
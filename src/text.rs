@@ -0,0 +1,212 @@
+/// A slice of a longer text sent to the model in one request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextWindow {
+    pub text: String,
+    /// Whether the window ends at a line break in the original text
+    pub ends_paragraph: bool,
+}
+
+/// Rough token count: ~4 ASCII characters per token, one token per other character
+///
+/// Good enough to keep requests under a budget without a tokenizer; CJK text
+/// is close to one token per character.
+pub fn estimate_tokens(text: &str) -> usize {
+    let (ascii, other) = text.chars().fold((0usize, 0usize), |(ascii, other), c| {
+        if c.is_ascii() {
+            (ascii + 1, other)
+        } else {
+            (ascii, other + 1)
+        }
+    });
+    ascii.div_ceil(4) + other
+}
+
+/// Split text into windows of at most `max_tokens` estimated tokens
+///
+/// Windows break at line boundaries when possible, then at sentence ends, and
+/// only split inside a sentence when a single sentence exceeds the budget.
+/// [`merge_windows`] restores the original paragraph structure.
+pub fn split_into_windows(text: &str, max_tokens: usize) -> Vec<TextWindow> {
+    let max_tokens = max_tokens.max(1);
+    let mut windows = Vec::new();
+    let mut current = String::new();
+
+    let mut flush = |current: &mut String, ends_paragraph: bool| {
+        if !current.trim().is_empty() {
+            windows.push(TextWindow {
+                text: std::mem::take(current).trim().to_string(),
+                ends_paragraph,
+            });
+        }
+        current.clear();
+    };
+
+    let paragraphs: Vec<&str> = text.lines().filter(|l| !l.trim().is_empty()).collect();
+    for paragraph in paragraphs {
+        if !current.is_empty()
+            && estimate_tokens(&current) + estimate_tokens(paragraph) > max_tokens
+        {
+            flush(&mut current, true);
+        }
+
+        if estimate_tokens(paragraph) <= max_tokens {
+            if !current.is_empty() {
+                current.push('\n');
+            }
+            current.push_str(paragraph);
+            continue;
+        }
+
+        // Paragraph too large on its own: pack sentences, hard-splitting long ones
+        flush(&mut current, true);
+        for piece in split_sentences(paragraph)
+            .into_iter()
+            .flat_map(|s| hard_split(s, max_tokens))
+        {
+            if !current.is_empty()
+                && estimate_tokens(&current) + estimate_tokens(piece) > max_tokens
+            {
+                flush(&mut current, false);
+            }
+            current.push_str(piece);
+        }
+        flush(&mut current, true);
+    }
+    flush(&mut current, true);
+
+    windows
+}
+
+/// Join processed windows, using line breaks only where the original had them
+pub fn merge_windows<S: AsRef<str>>(windows: &[TextWindow], outputs: &[S]) -> String {
+    let mut merged = String::new();
+    for (i, (window, output)) in windows.iter().zip(outputs).enumerate() {
+        merged.push_str(output.as_ref().trim());
+        if i + 1 < windows.len() {
+            merged.push(if window.ends_paragraph { '\n' } else { ' ' });
+        }
+    }
+    merged
+}
+
+/// Split after sentence-ending punctuation, keeping the punctuation
+fn split_sentences(text: &str) -> Vec<&str> {
+    let mut sentences = Vec::new();
+    let mut start = 0;
+    for (i, c) in text.char_indices() {
+        if matches!(c, '.' | '!' | '?' | '。' | '！' | '？') {
+            let end = i + c.len_utf8();
+            sentences.push(&text[start..end]);
+            start = end;
+        }
+    }
+    if start < text.len() {
+        sentences.push(&text[start..]);
+    }
+    sentences
+}
+
+/// Split a sentence that is larger than the budget at character boundaries
+fn hard_split(text: &str, max_tokens: usize) -> Vec<&str> {
+    let mut pieces = Vec::new();
+    let mut start = 0;
+    let mut tokens = 0;
+    for (i, c) in text.char_indices() {
+        let cost = if c.is_ascii() { 1 } else { 4 };
+        // Budget tracked in quarter tokens to match estimate_tokens
+        if tokens + cost > max_tokens * 4 && i > start {
+            pieces.push(&text[start..i]);
+            start = i;
+            tokens = 0;
+        }
+        tokens += cost;
+    }
+    pieces.push(&text[start..]);
+    pieces
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_estimate_tokens() {
+        assert_eq!(estimate_tokens(""), 0);
+        assert_eq!(estimate_tokens("abcdefgh"), 2);
+        assert_eq!(estimate_tokens("你好"), 2);
+        assert_eq!(estimate_tokens("hi 你好"), 3);
+    }
+
+    #[test]
+    fn test_split_keeps_paragraphs_together() {
+        let text = "first paragraph\nsecond paragraph\nthird paragraph";
+        let windows = split_into_windows(text, 1000);
+
+        assert_eq!(windows.len(), 1);
+        assert_eq!(windows[0].text, text);
+    }
+
+    #[test]
+    fn test_split_respects_budget_and_round_trips() {
+        let text = "One sentence here. Another one follows! A third?\nNext paragraph.";
+        let windows = split_into_windows(text, 6);
+
+        assert!(windows.len() > 1);
+        assert!(windows.iter().all(|w| estimate_tokens(&w.text) <= 6));
+
+        let outputs: Vec<_> = windows.iter().map(|w| w.text.clone()).collect();
+        let merged = merge_windows(&windows, &outputs);
+        assert_eq!(merged.lines().count(), 2);
+        assert!(merged.ends_with("\nNext paragraph."));
+    }
+
+    #[test]
+    fn test_split_cjk_sentences() {
+        let text = "这是第一句。这是第二句。这是第三句。";
+        let windows = split_into_windows(text, 6);
+
+        assert_eq!(windows.len(), 3);
+        assert_eq!(windows[0].text, "这是第一句。");
+        assert!(!windows[0].ends_paragraph);
+        assert!(windows[2].ends_paragraph);
+    }
+
+    #[test]
+    fn test_split_long_cjk_transcript() {
+        // One paragraph, as transcription returns it, of 200 sentences
+        let sentence = "我们今天讨论异步运行时的调度器设计，";
+        let text = format!("{}。", sentence.repeat(3)).repeat(200);
+        let windows = split_into_windows(&text, 500);
+
+        assert!(windows.len() > 1);
+        for window in &windows {
+            assert!(estimate_tokens(&window.text) <= 500);
+            // Windows end at sentence boundaries, not mid-sentence
+            assert!(window.text.ends_with('。'));
+        }
+
+        let outputs: Vec<_> = windows.iter().map(|w| w.text.clone()).collect();
+        assert_eq!(merge_windows(&windows, &outputs).replace(' ', ""), text);
+    }
+
+    #[test]
+    fn test_hard_split_cjk_keeps_characters_whole() {
+        // A single sentence without punctuation must be cut between characters
+        let text = "字节跳动飞书文档协作".repeat(10);
+        let windows = split_into_windows(&text, 7);
+
+        assert!(windows.len() > 1);
+        assert!(windows.iter().all(|w| w.text.chars().count() <= 7));
+        let joined: String = windows.iter().map(|w| w.text.as_str()).collect();
+        assert_eq!(joined, text);
+    }
+
+    #[test]
+    fn test_hard_split_long_sentence() {
+        let text = "a".repeat(40);
+        let windows = split_into_windows(&text, 4);
+
+        assert_eq!(windows.len(), 3);
+        assert_eq!(windows.iter().map(|w| w.text.len()).sum::<usize>(), 40);
+    }
+}
@@ -0,0 +1,285 @@
+//! Terminal output shared by the CLI tools
+//!
+//! Output is "plain" when `--quiet` is given or stdout is not a terminal (CI
+//! logs, pipes): progress bars and spinners are hidden, colors are off and
+//! emoji are dropped from status lines. Tracing output is not affected.
+//!
+//! Colors are also off when `NO_COLOR` is set, and emoji are replaced by
+//! their [`Emoji`] fallback with `--no-emoji` or a non-UTF-8 locale.
+
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+use std::fmt;
+use std::io::IsTerminal;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tokio_util::sync::CancellationToken;
+
+static PLAIN: AtomicBool = AtomicBool::new(false);
+static EMOJI: AtomicBool = AtomicBool::new(true);
+
+/// Pick plain or interactive output for the rest of the run; returns whether
+/// output is plain
+pub fn init(quiet: bool, no_emoji: bool) -> bool {
+    let plain = quiet || !std::io::stdout().is_terminal();
+    PLAIN.store(plain, Ordering::Relaxed);
+    EMOJI.store(
+        !plain && !no_emoji && locale_is_utf8(|name| std::env::var(name).ok()),
+        Ordering::Relaxed,
+    );
+    // Otherwise console decides per stream from its TTY detection
+    if plain || no_color_requested() {
+        console::set_colors_enabled(false);
+        console::set_colors_enabled_stderr(false);
+    }
+    plain
+}
+
+/// Whether `NO_COLOR` is set to a non-empty value (https://no-color.org)
+fn no_color_requested() -> bool {
+    std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty())
+}
+
+/// Whether the locale can show emoji, from the first non-empty of `LC_ALL`,
+/// `LC_CTYPE` and `LANG`; an unset locale is assumed to be UTF-8
+fn locale_is_utf8(var: impl Fn(&str) -> Option<String>) -> bool {
+    let Some(locale) = ["LC_ALL", "LC_CTYPE", "LANG"]
+        .into_iter()
+        .filter_map(var)
+        .find(|value| !value.is_empty())
+    else {
+        return true;
+    };
+    let locale = locale.to_ascii_lowercase();
+    locale.contains("utf-8") || locale.contains("utf8")
+}
+
+/// Send tracing output to stderr, filtered by RUST_LOG, else LOG_LEVEL, else
+/// `default_level` (e.g. `debug`, or `swiss_knife=trace` for API bodies)
+pub fn init_tracing(default_level: &str) {
+    let log_level = std::env::var("LOG_LEVEL")
+        .ok()
+        .or_else(|| std::env::var("RUST_LOG").ok())
+        .unwrap_or_else(|| default_level.to_string());
+
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            tracing_subscriber::EnvFilter::try_from_default_env()
+                .or_else(|_| tracing_subscriber::EnvFilter::try_new(&log_level))
+                .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new(default_level)),
+        )
+        .with_target(false)
+        .with_level(true)
+        .with_writer(std::io::stderr)
+        .init();
+}
+
+/// A token cancelled by the first Ctrl-C, for aborting outstanding API
+/// requests; a second Ctrl-C exits at once
+///
+/// Must be called from within a Tokio runtime.
+pub fn cancel_on_ctrl_c() -> CancellationToken {
+    let token = CancellationToken::new();
+    let cancel = token.clone();
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_err() {
+            return;
+        }
+        eprintln!("Interrupted: cancelling outstanding requests (Ctrl-C again to exit now)");
+        cancel.cancel();
+        if tokio::signal::ctrl_c().await.is_ok() {
+            std::process::exit(130);
+        }
+    });
+    token
+}
+
+/// Whether [`init`] chose plain output
+pub fn is_plain() -> bool {
+    PLAIN.load(Ordering::Relaxed)
+}
+
+/// Whether [`init`] left emoji on
+pub fn emoji_enabled() -> bool {
+    EMOJI.load(Ordering::Relaxed)
+}
+
+/// An emoji and the text shown instead when emoji are off
+///
+/// Like `console::Emoji`, but follows `--no-emoji` and plain output as chosen
+/// by [`init`].
+#[derive(Debug, Clone, Copy)]
+pub struct Emoji<'a, 'b>(pub &'a str, pub &'b str);
+
+impl Emoji<'_, '_> {
+    /// The emoji, or its fallback when `emoji` is false
+    pub fn pick(&self, emoji: bool) -> &str {
+        if emoji {
+            self.0
+        } else {
+            self.1
+        }
+    }
+}
+
+impl fmt::Display for Emoji<'_, '_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.pick(emoji_enabled()))
+    }
+}
+
+/// A MultiProgress that draws nothing in plain mode
+pub fn multi_progress() -> MultiProgress {
+    if is_plain() {
+        MultiProgress::with_draw_target(ProgressDrawTarget::hidden())
+    } else {
+        MultiProgress::new()
+    }
+}
+
+/// A standalone spinner that draws nothing in plain mode
+pub fn new_spinner() -> ProgressBar {
+    let spinner = ProgressBar::new_spinner();
+    if is_plain() {
+        spinner.set_draw_target(ProgressDrawTarget::hidden());
+    }
+    spinner
+}
+
+/// A standalone progress bar that draws nothing in plain mode
+pub fn new_bar(len: u64) -> ProgressBar {
+    let bar = ProgressBar::new(len);
+    if is_plain() {
+        bar.set_draw_target(ProgressDrawTarget::hidden());
+    }
+    bar
+}
+
+/// Style of a byte-count bar for an upload; `{prefix}` goes before the bar
+pub fn upload_bar_style() -> ProgressStyle {
+    ProgressStyle::default_bar()
+        .template("{spinner:.green} {prefix}[{bar:40.cyan/blue}] {bytes}/{total_bytes} {msg}")
+        .unwrap()
+        .progress_chars("#>-")
+}
+
+/// Print a status line, dropping emoji written inline when emoji are off
+pub fn print_line(line: &str) {
+    if !emoji_enabled() {
+        println!("{}", strip_emoji(line));
+    } else {
+        println!("{}", line);
+    }
+}
+
+/// `println!` for status output: emoji are dropped when emoji are off
+#[macro_export]
+macro_rules! status {
+    () => {
+        println!()
+    };
+    ($($arg:tt)*) => {
+        $crate::ui::print_line(&format!($($arg)*))
+    };
+}
+
+/// Remove emoji, and the space following each, from a line
+///
+/// Text symbols such as ✓, ✗ and ⚠ (without the emoji variation selector)
+/// carry meaning in plain logs and are kept.
+pub fn strip_emoji(line: &str) -> String {
+    let mut out = String::with_capacity(line.len());
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        let emoji = is_emoji(c) || chars.peek() == Some(&'\u{FE0F}');
+        if !emoji {
+            out.push(c);
+            continue;
+        }
+
+        // Variation selectors and joined sequences belong to the same emoji
+        while chars
+            .peek()
+            .is_some_and(|&next| matches!(next, '\u{FE0F}' | '\u{200D}') || is_emoji(next))
+        {
+            chars.next();
+        }
+        if chars.peek() == Some(&' ') {
+            chars.next();
+        }
+    }
+    out
+}
+
+/// Pictographs and the BMP symbols that render as emoji by default
+fn is_emoji(c: char) -> bool {
+    matches!(c, '\u{1F000}'..='\u{1FAFF}')
+        || matches!(
+            c,
+            '⌚' | '⌛'
+                | '⏩'..='⏳'
+                | '☔'
+                | '☕'
+                | '⚡'
+                | '✅'
+                | '✨'
+                | '❌'
+                | '❎'
+                | '❓'..='❕'
+                | '❗'
+                | '➕'..='➗'
+                | '⬛'
+                | '⬜'
+                | '⭐'
+                | '⭕'
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_strip_emoji() {
+        assert_eq!(strip_emoji("✅ Audio extracted"), "Audio extracted");
+        assert_eq!(strip_emoji("♻️ Using cached audio"), "Using cached audio");
+        assert_eq!(strip_emoji("  🏷️ Titles: a.txt"), "  Titles: a.txt");
+        assert_eq!(strip_emoji("[a.mp4] 🎬 Batch"), "[a.mp4] Batch");
+        assert_eq!(strip_emoji("👨‍💻 dev"), "dev");
+        // Text symbols and CJK stay
+        assert_eq!(strip_emoji("✓ 标题 ✗ ⚠ ═"), "✓ 标题 ✗ ⚠ ═");
+    }
+
+    #[test]
+    fn test_locale_is_utf8() {
+        let env = |vars: &'static [(&'static str, &'static str)]| {
+            move |name: &str| {
+                vars.iter()
+                    .find(|(key, _)| *key == name)
+                    .map(|(_, value)| value.to_string())
+            }
+        };
+        assert!(locale_is_utf8(env(&[("LANG", "en_US.UTF-8")])));
+        assert!(locale_is_utf8(env(&[("LANG", "zh_CN.utf8")])));
+        assert!(!locale_is_utf8(env(&[("LANG", "C")])));
+        assert!(!locale_is_utf8(env(&[("LANG", "POSIX")])));
+        assert!(!locale_is_utf8(env(&[("LANG", "en_US.ISO-8859-1")])));
+        // LC_ALL wins over LANG, and empty values are skipped
+        assert!(!locale_is_utf8(env(&[
+            ("LC_ALL", "C"),
+            ("LANG", "en_US.UTF-8")
+        ])));
+        assert!(locale_is_utf8(env(&[
+            ("LC_ALL", ""),
+            ("LANG", "en_US.UTF-8")
+        ])));
+        assert!(locale_is_utf8(env(&[])));
+    }
+
+    #[test]
+    fn test_emoji_fallback() {
+        let check = Emoji("✅ ", "");
+        let failed = Emoji("❌ ", "✗ ");
+        assert_eq!(check.pick(true), "✅ ");
+        assert_eq!(check.pick(false), "");
+        assert_eq!(failed.pick(false), "✗ ");
+    }
+}